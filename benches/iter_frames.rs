@@ -0,0 +1,63 @@
+//! Measures how long it takes to unwind a stack of frame pointer frames with
+//! `Unwinder::iter_frames`. Run with `cargo bench --bench iter_frames`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use framehop::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+use framehop::Unwinder;
+
+const FRAME_COUNT: u64 = 200;
+const ITERATIONS: u32 = 20_000;
+const ROUNDS: u32 = 5;
+
+fn main() {
+    // Every frame is a pair of [caller's bp, return address], starting at the bottom of
+    // the stack. The last frame has a zero return address.
+    let stack_start = 0x10000u64;
+    let mut stack = Vec::new();
+    for i in 0..FRAME_COUNT {
+        let caller_bp = stack_start + (i + 1) * 16;
+        let return_address = if i + 1 == FRAME_COUNT {
+            0
+        } else {
+            0x100000 + i * 0x100
+        };
+        stack.extend_from_slice(&[caller_bp, return_address]);
+    }
+    let mut read_stack = |address: u64| {
+        let index = address.checked_sub(stack_start).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+
+    let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+    let mut cache = CacheX86_64::<_>::new();
+    let mut unwind = || {
+        let regs = UnwindRegsX86_64::new(0x100000, stack_start - 8, stack_start);
+        let mut frames = unwinder.iter_frames(0x100000, regs, &mut cache, &mut read_stack);
+        let mut frame_count = 0;
+        while let Ok(Some(frame)) = frames.next() {
+            black_box(frame);
+            frame_count += 1;
+        }
+        frame_count
+    };
+    assert_eq!(unwind(), FRAME_COUNT);
+
+    // Report the fastest round, which is the least disturbed by other processes.
+    let elapsed = (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            for _ in 0..ITERATIONS {
+                black_box(unwind());
+            }
+            start.elapsed()
+        })
+        .min()
+        .unwrap();
+    println!(
+        "iter_frames: {ITERATIONS} stacks of {FRAME_COUNT} frame pointer frames in {:?} ({:?} per frame)",
+        elapsed,
+        elapsed / ITERATIONS / FRAME_COUNT as u32
+    );
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, Vec<u64>, &[u8])| {
+    let (pc, function_starts, bytes) = input;
+    framehop::fuzz_prologue_analysis(bytes, pc, &function_starts);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, &[u8])| {
+    let (pc, bytes) = input;
+    framehop::fuzz_unwind_cui(bytes, pc);
+});
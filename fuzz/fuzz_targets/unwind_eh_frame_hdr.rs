@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, [u64; 3], &[u8])| {
+    let (pc, regs, bytes) = input;
+    framehop::fuzz_unwind_eh_frame_hdr(bytes, pc, regs);
+});
@@ -1,32 +1,75 @@
 use super::unwind_rule::*;
 use crate::cache::*;
+#[cfg(target_has_atomic = "64")]
+use crate::shared_rule_cache::SharedCacheSlot;
 
 /// The unwinder cache type for [`UnwinderAarch64`](super::UnwinderAarch64).
-pub struct CacheAarch64<P: AllocationPolicy = MayAllocateDuringUnwind>(
-    pub Cache<UnwindRuleAarch64, P>,
+pub struct CacheAarch64<'s, P: AllocationPolicy = MayAllocateDuringUnwind>(
+    pub Cache<'s, UnwindRuleAarch64, P>,
 );
 
-impl CacheAarch64<MayAllocateDuringUnwind> {
+impl<'s> CacheAarch64<'s, MayAllocateDuringUnwind> {
     /// Create a new cache.
     pub fn new() -> Self {
         Self(Cache::new())
     }
+
+    /// Create a new cache whose unwind rules are stored in `slots`. See
+    /// [`Cache::new_shared`].
+    #[cfg(target_has_atomic = "64")]
+    pub fn new_shared(slots: &'s [SharedCacheSlot], layout_id: u64) -> Self {
+        Self(Cache::new_shared(slots, layout_id))
+    }
 }
 
-impl<P: AllocationPolicy> CacheAarch64<P> {
+impl<'s, P: AllocationPolicy> CacheAarch64<'s, P> {
     /// Create a new cache.
     pub fn new_in() -> Self {
         Self(Cache::new())
     }
 
+    /// Create a new cache whose unwind rules are stored in `slots`. See
+    /// [`Cache::new_shared`].
+    #[cfg(target_has_atomic = "64")]
+    pub fn new_shared_in(slots: &'s [SharedCacheSlot], layout_id: u64) -> Self {
+        Self(Cache::new_shared(slots, layout_id))
+    }
+
+    /// Change the layout ID of a shared cache. See [`Cache::set_layout_id`].
+    pub fn set_layout_id(&mut self, layout_id: u64) {
+        self.0.set_layout_id(layout_id);
+    }
+
     /// Returns a snapshot of the cache usage statistics.
     pub fn stats(&self) -> CacheStats {
         self.0.rule_cache.stats()
     }
+
+    /// Returns a snapshot of the statistics about how unwind rules were obtained.
+    pub fn unwind_stats(&self) -> UnwindStats {
+        self.0.unwind_stats
+    }
+
+    /// Returns a snapshot of the return address predictor statistics.
+    #[cfg(feature = "return-address-predictor")]
+    pub fn predictor_stats(&self) -> crate::PredictorStats {
+        self.0.predictor.stats()
+    }
+
+    /// Returns the number of bytes held by the cache. See [`Cache::memory_usage`].
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
 }
 
-impl<P: AllocationPolicy> Default for CacheAarch64<P> {
+impl<P: AllocationPolicy> Default for CacheAarch64<'_, P> {
     fn default() -> Self {
         Self::new_in()
     }
 }
+
+impl<'s, P: AllocationPolicy> ArchCache<'s, UnwindRuleAarch64, P> for CacheAarch64<'s, P> {
+    fn cache_mut(&mut self) -> &mut Cache<'s, UnwindRuleAarch64, P> {
+        &mut self.0
+    }
+}
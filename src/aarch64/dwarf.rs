@@ -1,17 +1,103 @@
 use gimli::{
     AArch64, CfaRule, Encoding, EvaluationStorage, Reader, ReaderOffset, Register, RegisterRule,
-    UnwindContextStorage, UnwindSection, UnwindTableRow,
+    UnwindContextStorage, UnwindSection, UnwindTableRow, Vendor,
 };
 
-use super::{arch::ArchAarch64, unwind_rule::UnwindRuleAarch64, unwindregs::UnwindRegsAarch64};
+use super::{
+    arch::ArchAarch64,
+    unwind_rule::UnwindRuleAarch64,
+    unwindregs::{FullUnwindRegsAarch64, UnwindRegsAarch64},
+};
 
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::unwind_regs::UnwindRegs;
 use crate::unwind_result::UnwindResult;
+use crate::unwind_step::{Resumable, Step};
+use crate::FrameAddress;
 
 use crate::dwarf::{
-    eval_cfa_rule, eval_register_rule, ConversionError, DwarfUnwindRegs, DwarfUnwinderError,
-    DwarfUnwinding,
+    saved_register_prefetch, ConversionError, DwarfCfiSectionAddresses, DwarfUnwindRegs,
+    DwarfUnwinderError, DwarfUnwinding, FullDwarfUnwindRegs, ResolvedRegisterRule, RuleEvaluation,
+    SingleFrameUnwindError,
 };
 
+/// Unwind a single frame with the CFI in an `.eh_frame` section, without setting up an
+/// [`Unwinder`](crate::Unwinder) and a [`Module`](crate::Module). This is meant for tools
+/// which already know which section covers the frame, e.g. debuggers which step one
+/// frame at a time.
+///
+/// `address` has to be translated into the SVMA space of the binary that `eh_frame_data`
+/// comes from, and `section_addresses` are the SVMAs of its sections. The FDE for the
+/// address is found with a linear search through the section, and nothing is cached, so
+/// use an unwinder to unwind many frames. On success, `regs` contain the caller's
+/// registers, and the return address is returned, or `None` at the end of the stack.
+pub fn unwind_frame_with_eh_frame<F: MemoryReader>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut UnwindRegsAarch64,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError> {
+    crate::dwarf::unwind_frame_with_eh_frame::<ArchAarch64, F>(
+        eh_frame_data,
+        section_addresses,
+        address,
+        regs,
+        read_stack,
+    )
+}
+
+/// Like [`unwind_frame_with_eh_frame`], but recovers every register which has a rule in
+/// the CFI, not just the ones which are needed to keep unwinding. See
+/// [`FullUnwindRegsAarch64`].
+pub fn unwind_full_frame_with_eh_frame<F: MemoryReader>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut FullUnwindRegsAarch64,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError> {
+    crate::dwarf::unwind_full_frame_with_eh_frame::<ArchAarch64, _, F>(
+        eh_frame_data,
+        section_addresses,
+        address,
+        regs,
+        read_stack,
+    )
+}
+
+impl DwarfUnwindRegs for FullUnwindRegsAarch64 {
+    fn get(&self, register: Register) -> Option<u64> {
+        self.get_dwarf_reg(register.0)
+    }
+}
+
+impl FullDwarfUnwindRegs for FullUnwindRegsAarch64 {
+    const RA: Register = AArch64::X30;
+
+    fn pc(&self) -> u64 {
+        self.pc()
+    }
+
+    fn set_pc(&mut self, pc: u64) {
+        self.set_pc(pc)
+    }
+
+    fn sp(&self) -> u64 {
+        self.sp()
+    }
+
+    fn set_sp(&mut self, sp: u64) {
+        self.set_sp(sp)
+    }
+
+    fn set_unknown(&mut self, register: Register) {
+        if register.0 <= 30 {
+            self.clear_x(usize::from(register.0));
+        }
+    }
+}
+
 impl DwarfUnwindRegs for UnwindRegsAarch64 {
     fn get(&self, register: Register) -> Option<u64> {
         match register {
@@ -24,16 +110,21 @@ impl DwarfUnwindRegs for UnwindRegsAarch64 {
 }
 
 impl DwarfUnwinding for ArchAarch64 {
-    fn unwind_frame<F, R, UCS, ES>(
+    // Functions which sign their return address, with pac-ret on Linux and on arm64e
+    // on Apple platforms, use DW_CFA_AARCH64_negate_ra_state. The signed return address
+    // which is read from the stack is stripped by `UnwindRegsAarch64::set_lr`.
+    const VENDOR: Vendor = Vendor::AArch64;
+
+    type DwarfEvaluation<R: Reader, S: EvaluationStorage<R>> = DwarfEvaluationAarch64<R, S>;
+
+    fn unwind_frame<R, UCS, ES>(
         section: &impl UnwindSection<R>,
         unwind_info: &UnwindTableRow<R::Offset, UCS>,
         encoding: Encoding,
-        regs: &mut Self::UnwindRegs,
+        regs: &Self::UnwindRegs,
         is_first_frame: bool,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<Self::UnwindRule>, DwarfUnwinderError>
+    ) -> Result<UnwindResult<Self::UnwindRule, DwarfEvaluationAarch64<R, ES>>, DwarfUnwinderError>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         R: Reader,
         UCS: UnwindContextStorage<R::Offset>,
         ES: EvaluationStorage<R>,
@@ -46,50 +137,22 @@ impl DwarfUnwinding for ArchAarch64 {
             Ok(unwind_rule) => return Ok(UnwindResult::ExecRule(unwind_rule)),
             Err(_err) => {
                 // Could not translate into a cacheable unwind rule. Fall back to the generic path.
-                // eprintln!("Unwind rule translation failed: {:?}", err);
+                #[cfg(feature = "log")]
+                log::trace!("Unwind rule translation failed: {:?}", _err);
             }
         }
 
-        let cfa = eval_cfa_rule::<R, _, ES>(section, cfa_rule, encoding, regs)
-            .ok_or(DwarfUnwinderError::CouldNotRecoverCfa)?;
-
-        let lr = regs.lr();
-        let fp = regs.fp();
-        let sp = regs.sp();
-
-        let (fp, lr) = if !is_first_frame {
-            if cfa <= sp {
-                return Err(DwarfUnwinderError::StackPointerMovedBackwards);
-            }
-            let fp = eval_register_rule::<R, F, _, ES>(
-                section, fp_rule, cfa, encoding, fp, regs, read_stack,
-            )
-            .ok_or(DwarfUnwinderError::CouldNotRecoverFramePointer)?;
-            let lr = eval_register_rule::<R, F, _, ES>(
-                section, lr_rule, cfa, encoding, lr, regs, read_stack,
-            )
-            .ok_or(DwarfUnwinderError::CouldNotRecoverReturnAddress)?;
-            (fp, lr)
-        } else {
-            // For the first frame, be more lenient when encountering errors.
-            // TODO: Find evidence of what this gives us. I think on macOS the prologue often has Unknown register rules
-            // and we only encounter prologues for the first frame.
-            let fp = eval_register_rule::<R, F, _, ES>(
-                section, fp_rule, cfa, encoding, fp, regs, read_stack,
-            )
-            .unwrap_or(fp);
-            let lr = eval_register_rule::<R, F, _, ES>(
-                section, lr_rule, cfa, encoding, lr, regs, read_stack,
-            )
-            .unwrap_or(lr);
-            (fp, lr)
-        };
-
-        regs.set_fp(fp);
-        regs.set_sp(cfa);
-        regs.set_lr(lr);
-
-        Ok(UnwindResult::Uncacheable(lr))
+        Ok(UnwindResult::Uncacheable(DwarfEvaluationAarch64 {
+            encoding,
+            is_first_frame,
+            fp_rule: ResolvedRegisterRule::new(section, fp_rule),
+            lr_rule: ResolvedRegisterRule::new(section, lr_rule),
+            cfa: 0,
+            new_fp: None,
+            state: DwarfEvaluationStateAarch64::Cfa(RuleEvaluation::cfa(
+                section, cfa_rule, encoding, regs,
+            )),
+        }))
     }
 
     fn rule_if_uncovered_by_fde() -> Self::UnwindRule {
@@ -97,6 +160,114 @@ impl DwarfUnwinding for ArchAarch64 {
     }
 }
 
+/// The evaluation of a CFI row which couldn't be translated into an
+/// [`UnwindRuleAarch64`]: the CFA, then fp, then lr.
+pub struct DwarfEvaluationAarch64<R: Reader, S: EvaluationStorage<R>> {
+    encoding: Encoding,
+    is_first_frame: bool,
+    fp_rule: ResolvedRegisterRule<R>,
+    lr_rule: ResolvedRegisterRule<R>,
+    cfa: u64,
+    new_fp: Option<u64>,
+    state: DwarfEvaluationStateAarch64<R, S>,
+}
+
+enum DwarfEvaluationStateAarch64<R: Reader, S: EvaluationStorage<R>> {
+    Cfa(RuleEvaluation<R, S>),
+    Fp(RuleEvaluation<R, S>),
+    Lr(RuleEvaluation<R, S>),
+    Done(Result<u64, DwarfUnwinderError>),
+}
+
+impl<R: Reader, S: EvaluationStorage<R>> Resumable<UnwindRegsAarch64>
+    for DwarfEvaluationAarch64<R, S>
+{
+    type Output = Result<u64, DwarfUnwinderError>;
+
+    fn step(&mut self, regs: &mut UnwindRegsAarch64) -> Step<Self::Output> {
+        use DwarfEvaluationStateAarch64 as State;
+        loop {
+            let next = match &mut self.state {
+                State::Cfa(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(None) => State::Done(Err(DwarfUnwinderError::CouldNotRecoverCfa)),
+                    Step::Done(Some(cfa)) if !self.is_first_frame && cfa <= regs.sp() => {
+                        State::Done(Err(DwarfUnwinderError::StackPointerMovedBackwards))
+                    }
+                    Step::Done(Some(cfa)) => {
+                        self.cfa = cfa;
+                        let mut evaluation = RuleEvaluation::register(
+                            &self.fp_rule,
+                            cfa,
+                            self.encoding,
+                            regs.fp(),
+                            regs,
+                        );
+                        if let Some((first, last)) =
+                            saved_register_prefetch(cfa, &self.fp_rule, &self.lr_rule)
+                        {
+                            evaluation = evaluation.with_prefetch(first, last);
+                        }
+                        State::Fp(evaluation)
+                    }
+                },
+                State::Fp(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(None) if !self.is_first_frame => {
+                        State::Done(Err(DwarfUnwinderError::CouldNotRecoverFramePointer))
+                    }
+                    Step::Done(new_fp) => {
+                        self.new_fp = new_fp;
+                        State::Lr(RuleEvaluation::register(
+                            &self.lr_rule,
+                            self.cfa,
+                            self.encoding,
+                            regs.lr(),
+                            regs,
+                        ))
+                    }
+                },
+                State::Lr(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(None) if !self.is_first_frame => {
+                        State::Done(Err(DwarfUnwinderError::CouldNotRecoverReturnAddress))
+                    }
+                    Step::Done(new_lr) => {
+                        // For the first frame, be more lenient when encountering errors.
+                        // TODO: Find evidence of what this gives us. I think on macOS the prologue often has Unknown register rules
+                        // and we only encounter prologues for the first frame.
+                        let lr = new_lr.unwrap_or(regs.lr());
+                        let fp = match self.new_fp {
+                            Some(fp) => fp,
+                            None => {
+                                // The caller's fp is unknown, so x29 can't be trusted as a
+                                // frame pointer.
+                                regs.set_fp_is_valid(false);
+                                regs.fp()
+                            }
+                        };
+                        regs.set_caller_sp_and_fp(self.cfa, fp);
+                        regs.set_lr(lr);
+                        State::Done(Ok(lr))
+                    }
+                },
+                State::Done(result) => return Step::Done(*result),
+            };
+            self.state = next;
+        }
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        use DwarfEvaluationStateAarch64 as State;
+        match &mut self.state {
+            State::Cfa(evaluation) | State::Fp(evaluation) | State::Lr(evaluation) => {
+                evaluation.provide(value)
+            }
+            State::Done(_) => {}
+        }
+    }
+}
+
 fn register_rule_to_cfa_offset<RO: ReaderOffset>(
     rule: &RegisterRule<RO>,
 ) -> Result<Option<i64>, ConversionError> {
@@ -120,7 +291,17 @@ fn translate_into_unwind_rule<RO: ReaderOffset>(
                 let lr_cfa_offset = register_rule_to_cfa_offset(lr_rule)?;
                 let fp_cfa_offset = register_rule_to_cfa_offset(fp_rule)?;
                 match (lr_cfa_offset, fp_cfa_offset) {
-                    (None, Some(_)) => Err(ConversionError::RestoringFpButNotLr),
+                    (None, Some(fp_cfa_offset)) => {
+                        // Only fp was saved, e.g. with `str x29, [sp, #0x40]`, and lr is
+                        // still in its register, which is only usable in the first frame.
+                        let fp_storage_offset_from_sp_by_8 =
+                            i16::try_from((offset + fp_cfa_offset) / 8)
+                                .map_err(|_| ConversionError::FpStorageOffsetDoesNotFit)?;
+                        Ok(UnwindRuleAarch64::OffsetSpAndRestoreFp {
+                            sp_offset_by_16,
+                            fp_storage_offset_from_sp_by_8,
+                        })
+                    }
                     (None, None) => {
                         if let RegisterRule::Undefined = lr_rule {
                             // If the return address is undefined, this could have two reasons:
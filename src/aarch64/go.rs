@@ -0,0 +1,36 @@
+use super::arch::ArchAarch64;
+use super::unwind_rule::UnwindRuleAarch64;
+use crate::go::{GoFrame, GoPclntabUnwinderError, GoUnwinding};
+
+impl GoUnwinding for ArchAarch64 {
+    fn rule_for_go_frame(
+        frame: GoFrame,
+        is_first_frame: bool,
+    ) -> Result<UnwindRuleAarch64, GoPclntabUnwinderError> {
+        if frame.is_top_frame {
+            return Ok(UnwindRuleAarch64::EndOfStack);
+        }
+        let sp_delta = frame.sp_delta;
+        if sp_delta < 0 || sp_delta % 16 != 0 {
+            return Err(GoPclntabUnwinderError::UnalignedSpDelta(sp_delta));
+        }
+        let sp_offset_by_16 = u16::try_from(sp_delta / 16)
+            .map_err(|_| GoPclntabUnwinderError::UnalignedSpDelta(sp_delta))?;
+        // Go functions with a frame store lr at the new sp, and the caller's fp right
+        // below it. In the first frame, the prologue may not have saved fp yet.
+        if sp_delta == 0 {
+            return Ok(UnwindRuleAarch64::NoOp);
+        }
+        if is_first_frame {
+            return Ok(UnwindRuleAarch64::OffsetSpAndRestoreLr {
+                sp_offset_by_16,
+                lr_storage_offset_from_sp_by_8: 0,
+            });
+        }
+        Ok(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+            sp_offset_by_16,
+            fp_storage_offset_from_sp_by_8: -1,
+            lr_storage_offset_from_sp_by_8: 0,
+        })
+    }
+}
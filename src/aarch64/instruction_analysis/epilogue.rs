@@ -4,6 +4,14 @@ struct EpilogueDetectorAarch64 {
     sp_offset: i32,
     fp_offset_from_initial_sp: Option<i32>,
     lr_offset_from_initial_sp: Option<i32>,
+    /// Set if the epilogue starts by restoring the stack pointer from the frame
+    /// pointer, e.g. with `mov sp, x29` or `sub sp, x29, #0x10`. In that case the
+    /// frame pointer is still valid at the initial pc.
+    restores_sp_from_fp: bool,
+    /// Set by a load like `ldr xN, [sp, #imm]` until the stack pointer is adjusted.
+    /// Function bodies reload spilled values like this all the time, so such a load is
+    /// only part of the epilogue if the stack pointer is adjusted before the return.
+    has_sp_load_without_adjustment: bool,
 }
 
 enum EpilogueStepResult {
@@ -22,6 +30,7 @@ enum EpilogueResult {
         sp_offset: i32,
         fp_offset_from_initial_sp: Option<i32>,
         lr_offset_from_initial_sp: Option<i32>,
+        restores_sp_from_fp: bool,
     },
 }
 
@@ -30,11 +39,23 @@ enum UnexpectedInstructionType {
     LoadOfWrongSize,
     LoadReferenceRegisterNotSp,
     AddSubNotOperatingOnSp,
+    SpRestoredFromFpAfterFpWasRestored,
     AutibspNotFollowedByExpectedTailCall,
     BranchWithUnadjustedStackPointer,
+    SpLoadNotFollowedByStackAdjustment,
     Unknown,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SingleLoadAddressing {
+    /// `ldr xN, [sp], #imm`
+    PostIndexed,
+    /// `ldr xN, [sp, #imm]!`
+    PreIndexed,
+    /// `ldr xN, [sp, #imm]`
+    UnsignedOffset,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 enum EpilogueInstructionType {
     NotExpectedInEpilogue,
@@ -57,14 +78,16 @@ impl EpilogueDetectorAarch64 {
             sp_offset: 0,
             fp_offset_from_initial_sp: None,
             lr_offset_from_initial_sp: None,
+            restores_sp_from_fp: false,
+            has_sp_load_without_adjustment: false,
         }
     }
 
     pub fn analyze_slice(&mut self, function_bytes: &[u8], pc_offset: usize) -> EpilogueResult {
-        let mut bytes = &function_bytes[pc_offset..];
-        if bytes.len() < 4 {
-            return EpilogueResult::ReachedFunctionEndWithoutReturn;
-        }
+        let mut bytes = match function_bytes.get(pc_offset..) {
+            Some(bytes) if bytes.len() >= 4 => bytes,
+            _ => return EpilogueResult::ReachedFunctionEndWithoutReturn,
+        };
         let mut word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
         bytes = &bytes[4..];
         match Self::analyze_instruction(word) {
@@ -74,28 +97,28 @@ impl EpilogueDetectorAarch64 {
             EpilogueInstructionType::CouldBeTailCall {
                 offset_of_expected_autibsp,
             } => {
-                if pc_offset >= offset_of_expected_autibsp as usize {
-                    let auth_tail_call_bytes =
-                        &function_bytes[pc_offset - offset_of_expected_autibsp as usize..];
-                    if auth_tail_call_bytes[0..4] == [0xff, 0x23, 0x03, 0xd5]
-                        && Self::is_auth_tail_call(&auth_tail_call_bytes[4..])
-                    {
-                        return EpilogueResult::FoundReturnOrTailCall {
-                            sp_offset: 0,
-                            fp_offset_from_initial_sp: None,
-                            lr_offset_from_initial_sp: None,
-                        };
-                    }
+                if Self::is_autibsp_followed_by_auth_tail_call(
+                    function_bytes,
+                    pc_offset,
+                    offset_of_expected_autibsp,
+                ) {
+                    return EpilogueResult::FoundReturnOrTailCall {
+                        sp_offset: 0,
+                        fp_offset_from_initial_sp: None,
+                        lr_offset_from_initial_sp: None,
+                        restores_sp_from_fp: false,
+                    };
                 }
-                if pc_offset >= 4 {
-                    let prev_b = &function_bytes[pc_offset - 4..pc_offset];
-                    let prev_word =
-                        u32::from_le_bytes([prev_b[0], prev_b[1], prev_b[2], prev_b[3]]);
+                if let Some(&[a, b, c, d]) =
+                    function_bytes.get(pc_offset.wrapping_sub(4)..pc_offset)
+                {
+                    let prev_word = u32::from_le_bytes([a, b, c, d]);
                     if Self::instruction_adjusts_stack_pointer(prev_word) {
                         return EpilogueResult::FoundReturnOrTailCall {
                             sp_offset: 0,
                             fp_offset_from_initial_sp: None,
                             lr_offset_from_initial_sp: None,
+                            restores_sp_from_fp: false,
                         };
                     }
                 }
@@ -104,18 +127,17 @@ impl EpilogueDetectorAarch64 {
             EpilogueInstructionType::CouldBePartOfAuthTailCall {
                 offset_of_expected_autibsp,
             } => {
-                if pc_offset >= offset_of_expected_autibsp as usize {
-                    let auth_tail_call_bytes =
-                        &function_bytes[pc_offset - offset_of_expected_autibsp as usize..];
-                    if auth_tail_call_bytes[0..4] == [0xff, 0x23, 0x03, 0xd5]
-                        && Self::is_auth_tail_call(&auth_tail_call_bytes[4..])
-                    {
-                        return EpilogueResult::FoundReturnOrTailCall {
-                            sp_offset: 0,
-                            fp_offset_from_initial_sp: None,
-                            lr_offset_from_initial_sp: None,
-                        };
-                    }
+                if Self::is_autibsp_followed_by_auth_tail_call(
+                    function_bytes,
+                    pc_offset,
+                    offset_of_expected_autibsp,
+                ) {
+                    return EpilogueResult::FoundReturnOrTailCall {
+                        sp_offset: 0,
+                        fp_offset_from_initial_sp: None,
+                        lr_offset_from_initial_sp: None,
+                        restores_sp_from_fp: false,
+                    };
                 }
                 return EpilogueResult::ProbablyStillInBody(UnexpectedInstructionType::Unknown);
             }
@@ -144,10 +166,16 @@ impl EpilogueDetectorAarch64 {
                     }
                 }
             }
+            if self.has_sp_load_without_adjustment {
+                return EpilogueResult::ProbablyStillInBody(
+                    UnexpectedInstructionType::SpLoadNotFollowedByStackAdjustment,
+                );
+            }
             return EpilogueResult::FoundReturnOrTailCall {
                 sp_offset: self.sp_offset,
                 fp_offset_from_initial_sp: self.fp_offset_from_initial_sp,
                 lr_offset_from_initial_sp: self.lr_offset_from_initial_sp,
+                restores_sp_from_fp: self.restores_sp_from_fp,
             };
         }
     }
@@ -157,6 +185,12 @@ impl EpilogueDetectorAarch64 {
         if (word >> 22) & 0b1011111011 == 0b1010100011 && (word >> 5) & 0b11111 == 31 {
             return true;
         }
+        // Detect single-register load from sp with writeback, e.g. `ldr lr, [sp], #0x10`.
+        if let Some((_, _, SingleLoadAddressing::PostIndexed | SingleLoadAddressing::PreIndexed)) =
+            Self::decode_single_load_from_sp(word)
+        {
+            return true;
+        }
         // Detect sub sp, sp, 0xXXXX
         if (word >> 23) & 0b111111111 == 0b100100010
             && word & 0b11111 == 31
@@ -167,6 +201,24 @@ impl EpilogueDetectorAarch64 {
         false
     }
 
+    /// Checks for an autibsp instruction `offset_of_expected_autibsp` bytes before
+    /// `pc_offset` which starts an authenticated tail call.
+    fn is_autibsp_followed_by_auth_tail_call(
+        function_bytes: &[u8],
+        pc_offset: usize,
+        offset_of_expected_autibsp: u8,
+    ) -> bool {
+        let Some(start) = pc_offset.checked_sub(offset_of_expected_autibsp as usize) else {
+            return false;
+        };
+        match function_bytes.get(start..) {
+            Some([0xff, 0x23, 0x03, 0xd5, bytes_after_autibsp @ ..]) => {
+                Self::is_auth_tail_call(bytes_after_autibsp)
+            }
+            _ => false,
+        }
+    }
+
     fn is_auth_tail_call(bytes_after_autibsp: &[u8]) -> bool {
         // libsystem_malloc.dylib contains over a hundred of these.
         // At the end of the function, after restoring the registers from the stack,
@@ -235,9 +287,49 @@ impl EpilogueDetectorAarch64 {
         (braa_opcode & 0xff_ff_fc_00) == 0xd7_1f_08_00 && (braa_opcode & 0b11111) == 16
     }
 
+    /// Detects `add sp, fp, #imm` (which includes `mov sp, fp`) and `sub sp, fp, #imm`.
+    fn decode_sp_restore_from_fp(word: u32) -> bool {
+        // Section C3.4, Data processing - immediate, add / sub imm, size class X (8 bytes)
+        let is_add_or_sub = (word >> 23) & 0b101111111 == 0b100100010;
+        let result_reg = word & 0b11111;
+        let input_reg = (word >> 5) & 0b11111;
+        is_add_or_sub && result_reg == 31 && input_reg == 29
+    }
+
+    /// Detects 64-bit `ldr xN, [sp, ...]` and returns the loaded register, the byte
+    /// offset, and the addressing mode.
+    fn decode_single_load_from_sp(word: u32) -> Option<(u16, i32, SingleLoadAddressing)> {
+        if (word >> 5) & 0b11111 != 31 {
+            return None;
+        }
+        let reg = (word & 0b11111) as u16;
+        if word & 0xff_c0_00_00 == 0xf9_40_00_00 {
+            let imm12 = ((word >> 10) & 0b111111111111) as i32;
+            return Some((reg, imm12 * 8, SingleLoadAddressing::UnsignedOffset));
+        }
+        let imm9 = (((((word >> 12) & 0b111111111) as i16) << 7) >> 7) as i32;
+        match word & 0xff_e0_0c_00 {
+            0xf8_40_04_00 => Some((reg, imm9, SingleLoadAddressing::PostIndexed)),
+            0xf8_40_0c_00 => Some((reg, imm9, SingleLoadAddressing::PreIndexed)),
+            _ => None,
+        }
+    }
+
     pub fn analyze_instruction(word: u32) -> EpilogueInstructionType {
-        // Detect ret and retab
-        if word == 0xd65f03c0 || word == 0xd65f0fff {
+        // Detect ret, retaa and retab
+        if word == 0xd65f03c0 || word == 0xd65f0bff || word == 0xd65f0fff {
+            return EpilogueInstructionType::VeryLikelyPartOfEpilogue;
+        }
+        // Detect autiasp, which is followed by ret when using pointer authentication with the A key.
+        if word == 0xd50323bf {
+            return EpilogueInstructionType::VeryLikelyPartOfEpilogue;
+        }
+        // Detect `mov sp, fp` and `add sp, fp, #imm` / `sub sp, fp, #imm`
+        if Self::decode_sp_restore_from_fp(word) {
+            return EpilogueInstructionType::VeryLikelyPartOfEpilogue;
+        }
+        // Detect single-register 64-bit loads from sp, e.g. `ldr lr, [sp], #0x10`
+        if Self::decode_single_load_from_sp(word).is_some() {
             return EpilogueInstructionType::VeryLikelyPartOfEpilogue;
         }
         // Detect autibsp
@@ -312,10 +404,49 @@ impl EpilogueDetectorAarch64 {
     }
 
     pub fn step_instruction(&mut self, word: u32) -> EpilogueStepResult {
-        // Detect ret and retab
-        if word == 0xd65f03c0 || word == 0xd65f0fff {
+        // Detect ret, retaa and retab
+        if word == 0xd65f03c0 || word == 0xd65f0bff || word == 0xd65f0fff {
             return EpilogueStepResult::FoundReturn;
         }
+        // Detect autiasp. It authenticates lr and doesn't touch the stack.
+        if word == 0xd50323bf {
+            return EpilogueStepResult::NeedMore;
+        }
+        // Detect `mov sp, fp` and `add sp, fp, #imm` / `sub sp, fp, #imm`
+        if Self::decode_sp_restore_from_fp(word) {
+            if self.fp_offset_from_initial_sp.is_some() {
+                return EpilogueStepResult::FoundBodyInstruction(
+                    UnexpectedInstructionType::SpRestoredFromFpAfterFpWasRestored,
+                );
+            }
+            // The stack pointer now depends on the frame pointer, not on the initial sp.
+            // Register locations from here on are not relative to the initial sp.
+            self.restores_sp_from_fp = true;
+            self.sp_offset = 0;
+            self.lr_offset_from_initial_sp = None;
+            self.has_sp_load_without_adjustment = false;
+            return EpilogueStepResult::NeedMore;
+        }
+        // Detect single-register 64-bit loads from sp, e.g. `ldr lr, [sp], #0x10`
+        if let Some((reg, imm, addressing)) = Self::decode_single_load_from_sp(word) {
+            let reg_loc = match addressing {
+                SingleLoadAddressing::PostIndexed => self.sp_offset,
+                SingleLoadAddressing::PreIndexed | SingleLoadAddressing::UnsignedOffset => {
+                    self.sp_offset.saturating_add(imm)
+                }
+            };
+            if reg == 29 {
+                self.fp_offset_from_initial_sp = Some(reg_loc);
+            } else if reg == 30 {
+                self.lr_offset_from_initial_sp = Some(reg_loc);
+            }
+            if addressing == SingleLoadAddressing::UnsignedOffset {
+                self.has_sp_load_without_adjustment = true;
+            } else {
+                self.adjust_sp(imm);
+            }
+            return EpilogueStepResult::NeedMore;
+        }
         // Detect autibsp
         if word == 0xd50323ff {
             return EpilogueStepResult::CouldBeAuthTailCall;
@@ -356,7 +487,7 @@ impl EpilogueDetectorAarch64 {
             let reg_loc = if is_postindexed_writeback {
                 self.sp_offset
             } else {
-                self.sp_offset + imm7
+                self.sp_offset.saturating_add(imm7)
             };
             let pair_reg_1 = (word & 0b11111) as u16;
             if pair_reg_1 == 29 {
@@ -366,12 +497,12 @@ impl EpilogueDetectorAarch64 {
             }
             let pair_reg_2 = ((word >> 10) & 0b11111) as u16;
             if pair_reg_2 == 29 {
-                self.fp_offset_from_initial_sp = Some(reg_loc + 8);
+                self.fp_offset_from_initial_sp = Some(reg_loc.saturating_add(8));
             } else if pair_reg_2 == 30 {
-                self.lr_offset_from_initial_sp = Some(reg_loc + 8);
+                self.lr_offset_from_initial_sp = Some(reg_loc.saturating_add(8));
             }
             if is_preindexed_writeback || is_postindexed_writeback {
-                self.sp_offset += imm7;
+                self.adjust_sp(imm7);
             }
             return EpilogueStepResult::NeedMore;
         }
@@ -390,11 +521,18 @@ impl EpilogueDetectorAarch64 {
             if shift_immediate_by_12 {
                 imm12 <<= 12
             }
-            self.sp_offset += imm12;
+            self.adjust_sp(imm12);
             return EpilogueStepResult::NeedMore;
         }
         EpilogueStepResult::FoundBodyInstruction(UnexpectedInstructionType::Unknown)
     }
+
+    fn adjust_sp(&mut self, offset: i32) {
+        self.sp_offset = self.sp_offset.saturating_add(offset);
+        if offset != 0 {
+            self.has_sp_load_without_adjustment = false;
+        }
+    }
 }
 
 pub fn unwind_rule_from_detected_epilogue(
@@ -405,10 +543,15 @@ pub fn unwind_rule_from_detected_epilogue(
     match detector.analyze_slice(bytes, pc_offset) {
         EpilogueResult::ProbablyStillInBody(_)
         | EpilogueResult::ReachedFunctionEndWithoutReturn => None,
+        EpilogueResult::FoundReturnOrTailCall {
+            restores_sp_from_fp: true,
+            ..
+        } => Some(UnwindRuleAarch64::UseFramePointer),
         EpilogueResult::FoundReturnOrTailCall {
             sp_offset,
             fp_offset_from_initial_sp,
             lr_offset_from_initial_sp,
+            restores_sp_from_fp: false,
         } => {
             let sp_offset_by_16 = u16::try_from(sp_offset / 16).ok()?;
             let rule = match (fp_offset_from_initial_sp, lr_offset_from_initial_sp) {
@@ -699,4 +842,161 @@ mod test {
             Some(UnwindRuleAarch64::NoOp)
         );
     }
+
+    #[test]
+    fn test_epilogue_with_autiasp() {
+        // clang -mbranch-protection=pac-ret on Linux
+        // ...
+        // 4006f4 fd 7b c1 a8     ldp        x29, x30, [sp], #0x10
+        // 4006f8 bf 23 03 d5     autiasp
+        // 4006fc c0 03 5f d6     ret
+        let bytes = &[
+            0xfd, 0x7b, 0xc1, 0xa8, 0xbf, 0x23, 0x03, 0xd5, 0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16: 1,
+                fp_storage_offset_from_sp_by_8: 0,
+                lr_storage_offset_from_sp_by_8: 1
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 8),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_with_retaa() {
+        // 400854 fd 7b c1 a8     ldp        x29, x30, [sp], #0x10
+        // 400858 ff 0b 5f d6     retaa
+        let bytes = &[0xfd, 0x7b, 0xc1, 0xa8, 0xff, 0x0b, 0x5f, 0xd6];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16: 1,
+                fp_storage_offset_from_sp_by_8: 0,
+                lr_storage_offset_from_sp_by_8: 1
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_with_single_lr_load() {
+        // gcc, non-leaf function without a frame record
+        // ...
+        // 400610 94 00 00 94     bl         some_function
+        // 400614 fe 07 41 f8     ldr        x30, [sp], #0x10
+        // 400618 c0 03 5f d6     ret
+        let bytes = &[
+            0x94, 0x00, 0x00, 0x94, 0xfe, 0x07, 0x41, 0xf8, 0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleAarch64::OffsetSpAndRestoreLr {
+                sp_offset_by_16: 1,
+                lr_storage_offset_from_sp_by_8: 0
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 8),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_restoring_sp_from_fp() {
+        // clang, function with dynamic stack allocation
+        // ...
+        // 100003f30 bf 43 00 d1     sub        sp, x29, #0x10
+        // 100003f34 fd 7b 41 a9     ldp        x29, x30, [sp, #0x10]
+        // 100003f38 f4 4f c2 a8     ldp        x20, x19, [sp], #0x20
+        // 100003f3c c0 03 5f d6     ret
+        let bytes = &[
+            0xbf, 0x43, 0x00, 0xd1, 0xfd, 0x7b, 0x41, 0xa9, 0xf4, 0x4f, 0xc2, 0xa8, 0xc0, 0x03,
+            0x5f, 0xd6,
+        ];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleAarch64::UseFramePointer)
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16: 2,
+                fp_storage_offset_from_sp_by_8: 2,
+                lr_storage_offset_from_sp_by_8: 3
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 8),
+            Some(UnwindRuleAarch64::OffsetSp { sp_offset_by_16: 2 })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 12),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+
+        // 100003f80 bf 03 00 91     mov        sp, x29
+        // 100003f84 fd 7b c1 a8     ldp        x29, x30, [sp], #0x10
+        // 100003f88 c0 03 5f d6     ret
+        let bytes = &[
+            0xbf, 0x03, 0x00, 0x91, 0xfd, 0x7b, 0xc1, 0xa8, 0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleAarch64::UseFramePointer)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_with_unsigned_offset_load() {
+        // 400700 f3 0b 40 f9     ldr        x19, [sp, #0x10]
+        // 400704 fd 7b c2 a8     ldp        x29, x30, [sp], #0x20
+        // 400708 c0 03 5f d6     ret
+        let bytes = &[
+            0xf3, 0x0b, 0x40, 0xf9, 0xfd, 0x7b, 0xc2, 0xa8, 0xc0, 0x03, 0x5f, 0xd6,
+        ];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16: 2,
+                fp_storage_offset_from_sp_by_8: 0,
+                lr_storage_offset_from_sp_by_8: 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_mid_function_unsigned_offset_load() {
+        // A loop in the function body, which reloads spilled values.
+        // 400800 e0 0b 40 f9     ldr        x0, [sp, #0x10]
+        // 400804 e1 0f 40 f9     ldr        x1, [sp, #0x18]
+        // 400808 fe ff ff 17     b          0x400800
+        let bytes = &[
+            0xe0, 0x0b, 0x40, 0xf9, 0xe1, 0x0f, 0x40, 0xf9, 0xfe, 0xff, 0xff, 0x17,
+        ];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 4), None);
+
+        // A reload followed by a return which doesn't pop a frame.
+        // 400900 e0 0b 40 f9     ldr        x0, [sp, #0x10]
+        // 400904 c0 03 5f d6     ret
+        let bytes = &[0xe0, 0x0b, 0x40, 0xf9, 0xc0, 0x03, 0x5f, 0xd6];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleAarch64::NoOp)
+        );
+    }
 }
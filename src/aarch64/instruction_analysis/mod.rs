@@ -1,25 +1,36 @@
 use super::arch::ArchAarch64;
-use crate::instruction_analysis::InstructionAnalysis;
+use crate::instruction_analysis::{FunctionBounds, InstructionAnalysis};
 
 mod epilogue;
 mod prologue;
 
 use epilogue::unwind_rule_from_detected_epilogue;
-use prologue::unwind_rule_from_detected_prologue;
+use prologue::{
+    guess_function_start, unwind_rule_for_function_body, unwind_rule_from_detected_prologue,
+};
 
 impl InstructionAnalysis for ArchAarch64 {
     fn rule_from_prologue_analysis(
         text_bytes: &[u8],
         pc_offset: usize,
     ) -> Option<Self::UnwindRule> {
-        let (slice_from_start, slice_to_end) = text_bytes.split_at(pc_offset);
+        let (slice_from_start, slice_to_end) = text_bytes.split_at_checked(pc_offset)?;
         unwind_rule_from_detected_prologue(slice_from_start, slice_to_end)
     }
 
     fn rule_from_epilogue_analysis(
         text_bytes: &[u8],
         pc_offset: usize,
+        _function: FunctionBounds,
     ) -> Option<Self::UnwindRule> {
         unwind_rule_from_detected_epilogue(text_bytes, pc_offset)
     }
+
+    fn rule_for_function_body(function_bytes: &[u8]) -> Option<(usize, Self::UnwindRule)> {
+        unwind_rule_for_function_body(function_bytes)
+    }
+
+    fn guess_function_start(text_bytes: &[u8], pc_offset: usize) -> Option<usize> {
+        guess_function_start(text_bytes, pc_offset)
+    }
 }
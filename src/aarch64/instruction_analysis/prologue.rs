@@ -175,7 +175,7 @@ impl PrologueDetectorAarch64 {
             let is_postindexed_writeback = writeback_bits == 0b01; // TODO: are there postindexed stores? What do they mean?
             if is_preindexed_writeback || is_postindexed_writeback {
                 let imm7 = (((((word >> 15) & 0b1111111) as i16) << 9) >> 6) as i32;
-                self.sp_offset -= imm7; // - to undo the instruction
+                self.sp_offset = self.sp_offset.saturating_sub(imm7); // - to undo the instruction
             }
             return PrologueStepResult::ValidPrologueInstruction;
         }
@@ -195,7 +195,7 @@ impl PrologueDetectorAarch64 {
             if shift_immediate_by_12 {
                 imm12 <<= 12
             }
-            self.sp_offset += imm12; // + to undo the sub instruction
+            self.sp_offset = self.sp_offset.saturating_add(imm12); // + to undo the sub instruction
             return PrologueStepResult::ValidPrologueInstruction;
         }
         PrologueStepResult::UnexpectedInstruction(UnexpectedInstructionType::Unknown)
@@ -221,6 +221,104 @@ pub fn unwind_rule_from_detected_prologue(
     }
 }
 
+/// Scans the prologue at the start of a function and returns the length of the
+/// prologue in bytes and the rule which applies to the rest of the function.
+///
+/// Walks forwards over stack pointer subtractions and register pair stores, tracking
+/// where fp and lr are stored, until the frame pointer is set up.
+pub fn unwind_rule_for_function_body(function_bytes: &[u8]) -> Option<(usize, UnwindRuleAarch64)> {
+    let mut sp_offset: i32 = 0;
+    // The offset of the stored fp/lr pair from the current sp.
+    let mut fp_lr_offset: Option<i32> = None;
+    let mut prologue_len = 0;
+    for (i, chunk) in function_bytes.chunks_exact(4).enumerate() {
+        let word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        // Detect paciasp, pacibsp and bti c.
+        if word == 0xd503233f || word == 0xd503237f || word == 0xd503245f {
+            continue;
+        }
+        // Detect `mov x29, sp` and `add x29, sp, #imm`.
+        if word & 0xff8003ff == 0x910003fd {
+            let mut imm12 = ((word >> 10) & 0xfff) as i32;
+            if (word >> 22) & 1 == 1 {
+                imm12 <<= 12;
+            }
+            // The frame pointer is only useful if it points to the stored fp/lr pair.
+            if fp_lr_offset != Some(imm12) {
+                return None;
+            }
+            return Some(((i + 1) * 4, UnwindRuleAarch64::UseFramePointer));
+        }
+        // Detect `sub sp, sp, #imm`.
+        if word & 0xff8003ff == 0xd10003ff {
+            let mut imm12 = ((word >> 10) & 0xfff) as i32;
+            if (word >> 22) & 1 == 1 {
+                imm12 <<= 12;
+            }
+            sp_offset = sp_offset.saturating_add(imm12);
+            fp_lr_offset = fp_lr_offset.map(|offset| offset.saturating_add(imm12));
+            prologue_len = (i + 1) * 4;
+            continue;
+        }
+        // Detect 64-bit register pair stores relative to sp, pre-indexed or with a
+        // signed offset.
+        let is_preindexed = word & 0xffc003e0 == 0xa98003e0;
+        if is_preindexed || word & 0xffc003e0 == 0xa90003e0 {
+            let imm7 = (((((word >> 15) & 0b1111111) as i16) << 9) >> 6) as i32;
+            let (rt, rt2) = (word & 0b11111, (word >> 10) & 0b11111);
+            if is_preindexed {
+                sp_offset = sp_offset.saturating_sub(imm7);
+                fp_lr_offset = fp_lr_offset.map(|offset| offset.saturating_sub(imm7));
+            }
+            if (rt, rt2) == (29, 30) {
+                fp_lr_offset = Some(if is_preindexed { 0 } else { imm7 });
+            }
+            prologue_len = (i + 1) * 4;
+            continue;
+        }
+        break;
+    }
+    // Without a frame pointer setup, the stored lr is needed to find the caller.
+    let fp_lr_offset = fp_lr_offset?;
+    if sp_offset % 16 != 0 || fp_lr_offset % 8 != 0 {
+        return None;
+    }
+    let rule = UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+        sp_offset_by_16: u16::try_from(sp_offset / 16).ok()?,
+        fp_storage_offset_from_sp_by_8: i16::try_from(fp_lr_offset / 8).ok()?,
+        lr_storage_offset_from_sp_by_8: i16::try_from(fp_lr_offset / 8 + 1).ok()?,
+    };
+    Some((prologue_len, rule))
+}
+
+/// The maximum distance, in bytes, that [`guess_function_start`] looks backwards.
+const MAX_FUNCTION_START_DISTANCE: usize = 0x4000;
+
+/// Guesses the start of the function containing `pc_offset`, for code without a
+/// symbol table. This looks backwards for a recognizable prologue which follows a
+/// `ret`, a `nop` or an `udf`.
+pub fn guess_function_start(text_bytes: &[u8], pc_offset: usize) -> Option<usize> {
+    if pc_offset > text_bytes.len() {
+        return None;
+    }
+    let pc_offset = pc_offset & !0b11;
+    let lowest_start = pc_offset.saturating_sub(MAX_FUNCTION_START_DISTANCE);
+    (lowest_start..=pc_offset).rev().step_by(4).find(|&start| {
+        let follows_ret_or_padding = match text_bytes.get(start.wrapping_sub(4)..start) {
+            Some(&[a, b, c, d]) => matches!(
+                u32::from_le_bytes([a, b, c, d]),
+                0xd65f03c0 | 0xd503201f | 0x00000000
+            ),
+            _ => start == 0,
+        };
+        follows_ret_or_padding
+            && text_bytes
+                .get(start..)
+                .and_then(unwind_rule_for_function_body)
+                .is_some_and(|(prologue_len, _)| start + prologue_len <= pc_offset)
+    })
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -397,4 +495,79 @@ mod test {
             None
         );
     }
+
+    #[test]
+    fn test_function_body_with_frame_pointer() {
+        // 1801245cc 7f 23 03 d5     pacibsp
+        // 1801245d0 f8 5f bc a9     stp        x24,x23,[sp, #local_40]!
+        // 1801245d4 f6 57 01 a9     stp        x22,x21,[sp, #local_30]
+        // 1801245d8 f4 4f 02 a9     stp        x20,x19,[sp, #local_20]
+        // 1801245dc fd 7b 03 a9     stp        x29,x30,[sp, #local_10]
+        // 1801245e0 fd c3 00 91     add        x29,sp,#0x30
+        // 1801245e4 f3 03 02 aa     mov        x19,x2
+
+        let bytes = &[
+            0x7f, 0x23, 0x03, 0xd5, 0xf8, 0x5f, 0xbc, 0xa9, 0xf6, 0x57, 0x01, 0xa9, 0xf4, 0x4f,
+            0x02, 0xa9, 0xfd, 0x7b, 0x03, 0xa9, 0xfd, 0xc3, 0x00, 0x91, 0xf3, 0x03, 0x02, 0xaa,
+        ];
+        assert_eq!(
+            unwind_rule_for_function_body(bytes),
+            Some((24, UnwindRuleAarch64::UseFramePointer))
+        );
+    }
+
+    #[test]
+    fn test_function_body_with_frame_pointer_not_at_fp_lr_pair() {
+        // ff 43 01 d1     sub        sp, sp, #0x50
+        // fd 7b 04 a9     stp        x29, x30, [sp, #0x40]
+        // fd 83 00 91     add        x29, sp, #0x20
+        let bytes = &[
+            0xff, 0x43, 0x01, 0xd1, 0xfd, 0x7b, 0x04, 0xa9, 0xfd, 0x83, 0x00, 0x91,
+        ];
+        assert_eq!(unwind_rule_for_function_body(bytes), None);
+        // Without any stored fp/lr pair.
+        assert_eq!(unwind_rule_for_function_body(&bytes[8..]), None);
+    }
+
+    #[test]
+    fn test_function_body_without_frame_pointer() {
+        // ff 43 01 d1     sub        sp, sp, #0x50
+        // fd 7b 04 a9     stp        x29, x30, [sp, #0x40]
+        // f4 03 04 aa     mov        x20, x4
+
+        let bytes = &[
+            0xff, 0x43, 0x01, 0xd1, 0xfd, 0x7b, 0x04, 0xa9, 0xf4, 0x03, 0x04, 0xaa,
+        ];
+        assert_eq!(
+            unwind_rule_for_function_body(bytes),
+            Some((
+                8,
+                UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                    sp_offset_by_16: 5,
+                    fp_storage_offset_from_sp_by_8: 8,
+                    lr_storage_offset_from_sp_by_8: 9,
+                }
+            ))
+        );
+        // A leaf function which doesn't store lr.
+        assert_eq!(unwind_rule_for_function_body(&bytes[8..]), None);
+    }
+
+    #[test]
+    fn test_guess_function_start() {
+        // 1801245c4 08 58 29 b8     str        w8,[x0, w9, UXTW #0x2]
+        // 1801245c8 c0 03 5f d6     ret
+        //                       _tiny_free_list_add_ptr
+        // 180126e94 7f 23 03 d5     pacibsp
+        // 180126e98 fd 7b bf a9     stp        x29,x30,[sp, #local_10]!
+        // 180126e9c fd 03 00 91     mov        x29,sp
+        // 180126ea0 68 04 00 51     sub        w8,w3,#0x1
+
+        let bytes = &[
+            0x08, 0x58, 0x29, 0xb8, 0xc0, 0x03, 0x5f, 0xd6, 0x7f, 0x23, 0x03, 0xd5, 0xfd, 0x7b,
+            0xbf, 0xa9, 0xfd, 0x03, 0x00, 0x91, 0x68, 0x04, 0x00, 0x51,
+        ];
+        assert_eq!(guess_function_start(bytes, 20), Some(8));
+        assert_eq!(guess_function_start(bytes, 12), None);
+    }
 }
@@ -27,7 +27,7 @@ impl CompactUnwindInfoUnwinding for ArchAarch64 {
                 ) {
                     // We are inside a prologue / epilogue. Ignore the opcode and use the rule from
                     // instruction analysis.
-                    return Ok(CuiUnwindResult::ExecRule(rule));
+                    return Ok(CuiUnwindResult::ExecRuleFromInstructionAnalysis(rule));
                 }
             }
         }
@@ -54,6 +54,9 @@ impl CompactUnwindInfoUnwinding for ArchAarch64 {
             }
             OpcodeArm64::Dwarf { eh_frame_fde } => CuiUnwindResult::NeedDwarf(eh_frame_fde),
             OpcodeArm64::FrameBased { .. } => {
+                // arm64e functions use the same frame layout, but the lr which is saved in
+                // the frame record is signed. It is stripped when the rule is executed, see
+                // `UnwinderAarch64::set_ptr_auth_policy`.
                 CuiUnwindResult::ExecRule(UnwindRuleAarch64::UseFramePointer)
             }
             OpcodeArm64::UnrecognizedKind(kind) => {
@@ -1,17 +1,22 @@
 mod arch;
 mod cache;
 mod dwarf;
+#[cfg(feature = "go")]
+mod go;
 mod instruction_analysis;
 #[cfg(feature = "macho")]
 mod macho;
 #[cfg(feature = "pe")]
 mod pe;
+mod shadow_call_stack;
 mod unwind_rule;
 mod unwinder;
 mod unwindregs;
 
 pub use arch::*;
 pub use cache::*;
+pub use dwarf::{unwind_frame_with_eh_frame, unwind_full_frame_with_eh_frame};
+pub use shadow_call_stack::*;
 pub use unwind_rule::*;
 pub use unwinder::*;
 pub use unwindregs::*;
@@ -0,0 +1,116 @@
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+
+use super::PtrAuthMask;
+
+/// The shadow call stack of a thread in a program which was built with
+/// `-fsanitize=shadow-call-stack`, as is common on Android and Fuchsia.
+///
+/// In such programs, every function which calls other functions stores its return
+/// address at the address in x18 in its prologue, increments x18, and loads the return
+/// address back in its epilogue. So the shadow call stack grows towards higher
+/// addresses, and its entries are exactly the return addresses of the functions which
+/// have set up their frame. Use [`UnwindIterator::with_shadow_call_stack`](crate::UnwindIterator::with_shadow_call_stack)
+/// to check the unwound return addresses against it, or to take them from it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShadowCallStack {
+    base: u64,
+    x18: u64,
+}
+
+impl ShadowCallStack {
+    /// `base` is the lowest address of the thread's shadow call stack, and `x18` is the
+    /// value of the x18 register when the stack was sampled, i.e. the address above the
+    /// most recent entry.
+    pub fn new(base: u64, x18: u64) -> Self {
+        Self { base, x18 }
+    }
+
+    /// The number of entries on the shadow call stack.
+    pub fn len(&self) -> usize {
+        (self.x18.saturating_sub(self.base) / 8) as usize
+    }
+
+    /// Whether the shadow call stack has no entries, e.g. on the thread's root function.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Read the return addresses from the shadow call stack, innermost first, and strip
+    /// their pointer authentication bits with `mask`.
+    pub fn read_return_addresses<F>(
+        &self,
+        read_stack: &mut F,
+        mask: PtrAuthMask,
+    ) -> Result<Vec<u64>, Error>
+    where
+        F: MemoryReader + ?Sized,
+    {
+        (0..self.len() as u64)
+            .map(|i| {
+                let address = self.x18 - 8 * (i + 1);
+                let return_address = read_stack
+                    .read_u64(address)
+                    .map_err(|err| Error::CouldNotReadStack(address, err))?;
+                Ok(mask.strip_ptr_auth(return_address))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aarch64::{CacheAarch64, UnwindRegsAarch64, UnwinderAarch64};
+    use crate::{FrameAddress, ShadowCallStackMode, Unwinder};
+
+    #[test]
+    fn test_shadow_call_stack() {
+        let unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        let mut cache = CacheAarch64::<_>::new();
+        // A frame pointer chain which ends after the first return address, because the
+        // next frame record has a zero frame pointer, and a shadow call stack at 0x40
+        // with three entries. With x18 at 0x50, the first function is a leaf function
+        // which hasn't pushed its return address.
+        let stack = [
+            0x0, 0x0, 0x20, 0x1234, 0x0, 0x2345, 0x0, 0x0, 0x3456, 0x2345, 0x1234,
+        ];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsAarch64::new(0x0, 0x0, 0x10);
+        let ip = FrameAddress::from_instruction_pointer(0x1000);
+        let return_address = |address| FrameAddress::from_return_address(address).unwrap();
+        let all_frames = [
+            ip,
+            return_address(0x1234),
+            return_address(0x2345),
+            return_address(0x3456),
+        ];
+
+        for (x18, mode, first_repaired_frame) in [
+            (0x58, ShadowCallStackMode::Validate, Some(2)),
+            (0x50, ShadowCallStackMode::Validate, Some(2)),
+            (0x58, ShadowCallStackMode::Trust, None),
+            (0x50, ShadowCallStackMode::Trust, None),
+        ] {
+            let shadow_call_stack = ShadowCallStack::new(0x40, x18)
+                .read_return_addresses(&mut read_stack, PtrAuthMask::new_no_strip())
+                .unwrap();
+            let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+            let mut iter = unwinder
+                .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+                .with_shadow_call_stack(&shadow_call_stack, mode);
+            let mut frames = Vec::new();
+            while let Some(frame) = iter.next().unwrap() {
+                frames.push(frame);
+            }
+            assert_eq!(frames, all_frames, "{x18:#x} {mode:?}");
+            assert_eq!(
+                iter.first_repaired_frame(),
+                first_repaired_frame,
+                "{x18:#x} {mode:?}"
+            );
+        }
+    }
+}
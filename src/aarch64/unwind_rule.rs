@@ -1,11 +1,13 @@
 use super::unwindregs::UnwindRegsAarch64;
 use crate::add_signed::checked_add_signed;
 use crate::error::Error;
-
-use crate::unwind_rule::UnwindRule;
+use crate::unwind_rule::{decode_rule, encode_rule, RulePlan, RuleReads, StackRead, UnwindRule};
+use crate::unwind_step::ReadRequest;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum UnwindRuleAarch64 {
+    /// The stack ends here; there is no caller frame.
+    EndOfStack,
     /// (sp, fp, lr) = (sp, fp, lr)
     /// Only possible for the first frame. Subsequent frames must get the
     /// return address from somewhere other than the lr register to avoid
@@ -29,6 +31,14 @@ pub enum UnwindRuleAarch64 {
         sp_offset_by_16: u16,
         lr_storage_offset_from_sp_by_8: i16,
     },
+    /// (sp, fp, lr) = (sp + 16x, *(sp + 8y), lr)
+    /// Only possible for the first frame. Subsequent frames must get the
+    /// return address from somewhere other than the lr register to avoid
+    /// infinite loops.
+    OffsetSpAndRestoreFp {
+        sp_offset_by_16: u16,
+        fp_storage_offset_from_sp_by_8: i16,
+    },
     /// (sp, fp, lr) = (sp + 16x, *(sp + 8y), *(sp + 8z))
     OffsetSpAndRestoreFpAndLr {
         sp_offset_by_16: u16,
@@ -45,8 +55,106 @@ pub enum UnwindRuleAarch64 {
     },
 }
 
+impl UnwindRuleAarch64 {
+    /// Serialize the rule into a stable binary form, e.g. to persist a rule cache or to
+    /// share rules with another process. The first byte is the kind of rule, followed by
+    /// the fields of the rule as little-endian 16-bit values. Unused bytes are zero.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        match *self {
+            UnwindRuleAarch64::EndOfStack => encode_rule(0, [0, 0, 0]),
+            UnwindRuleAarch64::NoOp => encode_rule(1, [0, 0, 0]),
+            UnwindRuleAarch64::NoOpIfFirstFrameOtherwiseFp => encode_rule(2, [0, 0, 0]),
+            UnwindRuleAarch64::OffsetSp { sp_offset_by_16 } => {
+                encode_rule(3, [sp_offset_by_16, 0, 0])
+            }
+            UnwindRuleAarch64::OffsetSpIfFirstFrameOtherwiseStackEndsHere { sp_offset_by_16 } => {
+                encode_rule(4, [sp_offset_by_16, 0, 0])
+            }
+            UnwindRuleAarch64::OffsetSpAndRestoreLr {
+                sp_offset_by_16,
+                lr_storage_offset_from_sp_by_8,
+            } => encode_rule(
+                5,
+                [sp_offset_by_16, lr_storage_offset_from_sp_by_8 as u16, 0],
+            ),
+            UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16,
+                fp_storage_offset_from_sp_by_8,
+                lr_storage_offset_from_sp_by_8,
+            } => encode_rule(
+                6,
+                [
+                    sp_offset_by_16,
+                    fp_storage_offset_from_sp_by_8 as u16,
+                    lr_storage_offset_from_sp_by_8 as u16,
+                ],
+            ),
+            UnwindRuleAarch64::UseFramePointer => encode_rule(7, [0, 0, 0]),
+            UnwindRuleAarch64::UseFramepointerWithOffsets {
+                sp_offset_from_fp_by_8,
+                fp_storage_offset_from_fp_by_8,
+                lr_storage_offset_from_fp_by_8,
+            } => encode_rule(
+                8,
+                [
+                    sp_offset_from_fp_by_8,
+                    fp_storage_offset_from_fp_by_8 as u16,
+                    lr_storage_offset_from_fp_by_8 as u16,
+                ],
+            ),
+            UnwindRuleAarch64::OffsetSpAndRestoreFp {
+                sp_offset_by_16,
+                fp_storage_offset_from_sp_by_8,
+            } => encode_rule(
+                9,
+                [sp_offset_by_16, fp_storage_offset_from_sp_by_8 as u16, 0],
+            ),
+        }
+    }
+
+    /// Deserialize a rule which was serialized with [`UnwindRuleAarch64::to_bytes`].
+    /// Returns `None` if the bytes don't describe a valid rule.
+    pub fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+        let rule = match decode_rule(bytes)? {
+            (0, [0, 0, 0]) => UnwindRuleAarch64::EndOfStack,
+            (1, [0, 0, 0]) => UnwindRuleAarch64::NoOp,
+            (2, [0, 0, 0]) => UnwindRuleAarch64::NoOpIfFirstFrameOtherwiseFp,
+            (3, [sp_offset_by_16, 0, 0]) => UnwindRuleAarch64::OffsetSp { sp_offset_by_16 },
+            (4, [sp_offset_by_16, 0, 0]) => {
+                UnwindRuleAarch64::OffsetSpIfFirstFrameOtherwiseStackEndsHere { sp_offset_by_16 }
+            }
+            (5, [sp_offset_by_16, lr_offset, 0]) => UnwindRuleAarch64::OffsetSpAndRestoreLr {
+                sp_offset_by_16,
+                lr_storage_offset_from_sp_by_8: lr_offset as i16,
+            },
+            (6, [sp_offset_by_16, fp_offset, lr_offset]) => {
+                UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                    sp_offset_by_16,
+                    fp_storage_offset_from_sp_by_8: fp_offset as i16,
+                    lr_storage_offset_from_sp_by_8: lr_offset as i16,
+                }
+            }
+            (7, [0, 0, 0]) => UnwindRuleAarch64::UseFramePointer,
+            (8, [sp_offset_from_fp_by_8, fp_offset, lr_offset]) => {
+                UnwindRuleAarch64::UseFramepointerWithOffsets {
+                    sp_offset_from_fp_by_8,
+                    fp_storage_offset_from_fp_by_8: fp_offset as i16,
+                    lr_storage_offset_from_fp_by_8: lr_offset as i16,
+                }
+            }
+            (9, [sp_offset_by_16, fp_offset, 0]) => UnwindRuleAarch64::OffsetSpAndRestoreFp {
+                sp_offset_by_16,
+                fp_storage_offset_from_sp_by_8: fp_offset as i16,
+            },
+            _ => return None,
+        };
+        Some(rule)
+    }
+}
+
 impl UnwindRule for UnwindRuleAarch64 {
     type UnwindRegs = UnwindRegsAarch64;
+    type Plan = RulePlanAarch64;
 
     fn rule_for_stub_functions() -> Self {
         UnwindRuleAarch64::NoOp
@@ -54,43 +162,90 @@ impl UnwindRule for UnwindRuleAarch64 {
     fn rule_for_function_start() -> Self {
         UnwindRuleAarch64::NoOp
     }
+    fn rule_for_end_of_stack() -> Self {
+        UnwindRuleAarch64::EndOfStack
+    }
+    fn rule_for_plt_lazy_resolver_entry() -> Self {
+        // PLT0 stored x16 and lr with `stp x16, x30, [sp, #-16]!`.
+        UnwindRuleAarch64::OffsetSpAndRestoreLr {
+            sp_offset_by_16: 1,
+            lr_storage_offset_from_sp_by_8: 1,
+        }
+    }
+    fn rule_for_dyld_stub_binder_entry() -> Self {
+        // The stub helper stored the lazy binding info offset and a pointer to the
+        // image's dyld private data, and left lr untouched.
+        UnwindRuleAarch64::OffsetSp { sp_offset_by_16: 1 }
+    }
     fn fallback_rule() -> Self {
         UnwindRuleAarch64::UseFramePointer
     }
 
-    fn exec<F>(
+    fn serialize(&self) -> [u8; 8] {
+        self.to_bytes()
+    }
+    fn deserialize(bytes: [u8; 8]) -> Option<Self> {
+        UnwindRuleAarch64::from_bytes(bytes)
+    }
+
+    #[cfg(feature = "return-address-predictor")]
+    fn rule_for_observed_frame(
+        callee: &UnwindRegsAarch64,
+        caller: &UnwindRegsAarch64,
+    ) -> Option<Self> {
+        let sp_offset = caller.sp().checked_sub(callee.sp())?;
+        if sp_offset % 16 != 0 {
+            return None;
+        }
+        let sp_offset_by_16 = u16::try_from(sp_offset / 16).ok()?;
+        // The frame record (fp, lr) is usually at the top of the frame.
+        let lr_storage_offset_from_sp_by_8 = i16::try_from(sp_offset / 8).ok()? - 1;
+        if caller.fp() == callee.fp() {
+            return Some(UnwindRuleAarch64::OffsetSpAndRestoreLr {
+                sp_offset_by_16,
+                lr_storage_offset_from_sp_by_8,
+            });
+        }
+        Some(UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+            sp_offset_by_16,
+            fp_storage_offset_from_sp_by_8: lr_storage_offset_from_sp_by_8 - 1,
+            lr_storage_offset_from_sp_by_8,
+        })
+    }
+
+    #[inline(always)]
+    fn plan_exec<S: RuleReads>(
         self,
         is_first_frame: bool,
-        regs: &mut UnwindRegsAarch64,
-        read_stack: &mut F,
-    ) -> Result<Option<u64>, Error>
-    where
-        F: FnMut(u64) -> Result<u64, ()>,
-    {
+        regs: &UnwindRegsAarch64,
+        reads: &mut S,
+    ) -> Result<Option<RulePlanAarch64>, Error> {
         let lr = regs.lr();
         let sp = regs.sp();
         let fp = regs.fp();
+        let mut plan = RulePlanAarch64 {
+            is_first_frame,
+            new_sp: sp,
+            lr: Some(lr),
+            fp: Some(fp),
+            check: CallerCheck::None,
+        };
 
-        let (new_lr, new_sp, new_fp) = match self {
+        match self {
+            UnwindRuleAarch64::EndOfStack => return Ok(None),
             UnwindRuleAarch64::NoOp => {
                 if !is_first_frame {
                     return Err(Error::DidNotAdvance);
                 }
-                (lr, sp, fp)
             }
             UnwindRuleAarch64::NoOpIfFirstFrameOtherwiseFp => {
-                if is_first_frame {
-                    (lr, sp, fp)
-                } else {
-                    let fp = regs.fp();
-                    let new_sp = fp.checked_add(16).ok_or(Error::IntegerOverflow)?;
-                    let new_lr =
-                        read_stack(fp + 8).map_err(|_| Error::CouldNotReadStack(fp + 8))?;
-                    let new_fp = read_stack(fp).map_err(|_| Error::CouldNotReadStack(fp))?;
-                    if new_sp <= sp {
-                        return Err(Error::FramepointerUnwindingMovedBackwards);
+                if !is_first_frame {
+                    if !regs.fp_is_valid() {
+                        return Err(Error::FramePointerNotValid);
                     }
-                    (new_lr, new_sp, new_fp)
+                    plan.new_sp = fp.checked_add(16).ok_or(Error::IntegerOverflow)?;
+                    plan.read_lr_and_fp(reads, fp + 8, fp)?;
+                    plan.check = CallerCheck::SpAdvances;
                 }
             }
             UnwindRuleAarch64::OffsetSpIfFirstFrameOtherwiseStackEndsHere { sp_offset_by_16 } => {
@@ -98,29 +253,41 @@ impl UnwindRule for UnwindRuleAarch64 {
                     return Ok(None);
                 }
                 let sp_offset = u64::from(sp_offset_by_16) * 16;
-                let new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
-                (lr, new_sp, fp)
+                plan.new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
             }
             UnwindRuleAarch64::OffsetSp { sp_offset_by_16 } => {
                 if !is_first_frame {
                     return Err(Error::DidNotAdvance);
                 }
                 let sp_offset = u64::from(sp_offset_by_16) * 16;
-                let new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
-                (lr, new_sp, fp)
+                plan.new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
             }
             UnwindRuleAarch64::OffsetSpAndRestoreLr {
                 sp_offset_by_16,
                 lr_storage_offset_from_sp_by_8,
             } => {
                 let sp_offset = u64::from(sp_offset_by_16) * 16;
-                let new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
+                plan.new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
                 let lr_storage_offset = i64::from(lr_storage_offset_from_sp_by_8) * 8;
                 let lr_location =
                     checked_add_signed(sp, lr_storage_offset).ok_or(Error::IntegerOverflow)?;
-                let new_lr =
-                    read_stack(lr_location).map_err(|_| Error::CouldNotReadStack(lr_location))?;
-                (new_lr, new_sp, fp)
+                reads.read(StackRead::new(ReadRequest::u64(lr_location)))?;
+                plan.lr = None;
+            }
+            UnwindRuleAarch64::OffsetSpAndRestoreFp {
+                sp_offset_by_16,
+                fp_storage_offset_from_sp_by_8,
+            } => {
+                if !is_first_frame {
+                    return Err(Error::DidNotAdvance);
+                }
+                let sp_offset = u64::from(sp_offset_by_16) * 16;
+                plan.new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
+                let fp_storage_offset = i64::from(fp_storage_offset_from_sp_by_8) * 8;
+                let fp_location =
+                    checked_add_signed(sp, fp_storage_offset).ok_or(Error::IntegerOverflow)?;
+                reads.read(StackRead::new(ReadRequest::u64(fp_location)))?;
+                plan.fp = None;
             }
             UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
                 sp_offset_by_16,
@@ -128,18 +295,14 @@ impl UnwindRule for UnwindRuleAarch64 {
                 lr_storage_offset_from_sp_by_8,
             } => {
                 let sp_offset = u64::from(sp_offset_by_16) * 16;
-                let new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
+                plan.new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
                 let lr_storage_offset = i64::from(lr_storage_offset_from_sp_by_8) * 8;
                 let lr_location =
                     checked_add_signed(sp, lr_storage_offset).ok_or(Error::IntegerOverflow)?;
-                let new_lr =
-                    read_stack(lr_location).map_err(|_| Error::CouldNotReadStack(lr_location))?;
                 let fp_storage_offset = i64::from(fp_storage_offset_from_sp_by_8) * 8;
                 let fp_location =
                     checked_add_signed(sp, fp_storage_offset).ok_or(Error::IntegerOverflow)?;
-                let new_fp =
-                    read_stack(fp_location).map_err(|_| Error::CouldNotReadStack(fp_location))?;
-                (new_lr, new_sp, new_fp)
+                plan.read_lr_and_fp(reads, lr_location, fp_location)?;
             }
             UnwindRuleAarch64::UseFramePointer => {
                 // Do a frame pointer stack walk. Frame-based aarch64 functions store the caller's fp and lr
@@ -180,57 +343,118 @@ impl UnwindRule for UnwindRuleAarch64 {
                 //  ^ sp                    ^ fp
                 //
                 // So: *fp is the caller's frame pointer, and *(fp + 8) is the return address.
-                let fp = regs.fp();
-                let new_sp = fp.checked_add(16).ok_or(Error::IntegerOverflow)?;
-                let new_lr = read_stack(fp + 8).map_err(|_| Error::CouldNotReadStack(fp + 8))?;
-                let new_fp = read_stack(fp).map_err(|_| Error::CouldNotReadStack(fp))?;
-                if new_fp == 0 {
-                    return Ok(None);
-                }
-                if new_fp <= fp || new_sp <= sp {
-                    return Err(Error::FramepointerUnwindingMovedBackwards);
+                if !regs.fp_is_valid() {
+                    return Err(Error::FramePointerNotValid);
                 }
-                (new_lr, new_sp, new_fp)
+                plan.new_sp = fp.checked_add(16).ok_or(Error::IntegerOverflow)?;
+                plan.read_lr_and_fp(reads, fp + 8, fp)?;
+                plan.check = CallerCheck::FramePointerChain;
             }
             UnwindRuleAarch64::UseFramepointerWithOffsets {
                 sp_offset_from_fp_by_8,
                 fp_storage_offset_from_fp_by_8,
                 lr_storage_offset_from_fp_by_8,
             } => {
+                if !regs.fp_is_valid() {
+                    return Err(Error::FramePointerNotValid);
+                }
                 let sp_offset_from_fp = u64::from(sp_offset_from_fp_by_8) * 8;
-                let new_sp = fp
+                plan.new_sp = fp
                     .checked_add(sp_offset_from_fp)
                     .ok_or(Error::IntegerOverflow)?;
                 let lr_storage_offset = i64::from(lr_storage_offset_from_fp_by_8) * 8;
                 let lr_location =
                     checked_add_signed(fp, lr_storage_offset).ok_or(Error::IntegerOverflow)?;
-                let new_lr =
-                    read_stack(lr_location).map_err(|_| Error::CouldNotReadStack(lr_location))?;
                 let fp_storage_offset = i64::from(fp_storage_offset_from_fp_by_8) * 8;
                 let fp_location =
                     checked_add_signed(fp, fp_storage_offset).ok_or(Error::IntegerOverflow)?;
-                let new_fp =
-                    read_stack(fp_location).map_err(|_| Error::CouldNotReadStack(fp_location))?;
+                plan.read_lr_and_fp(reads, lr_location, fp_location)?;
+                plan.check = CallerCheck::FramePointerChain;
+            }
+        }
+        Ok(Some(plan))
+    }
+}
+
+/// What an aarch64 unwind rule does with the values it reads.
+#[derive(Debug, Clone)]
+pub struct RulePlanAarch64 {
+    is_first_frame: bool,
+    new_sp: u64,
+    /// The caller's lr, or `None` if it is the first read value.
+    lr: Option<u64>,
+    /// The caller's fp, or `None` if it is the last read value.
+    fp: Option<u64>,
+    check: CallerCheck,
+}
+
+/// The check of the caller's registers, once they have been read.
+#[derive(Debug, Clone, Copy)]
+enum CallerCheck {
+    None,
+    /// The stack pointer must move towards the caller.
+    SpAdvances,
+    /// Both the stack pointer and the frame pointer must move towards the caller, and
+    /// a zero frame pointer ends the stack.
+    FramePointerChain,
+}
+
+impl RulePlanAarch64 {
+    /// Read lr and fp, fetching them together.
+    #[inline(always)]
+    fn read_lr_and_fp<S: RuleReads>(
+        &mut self,
+        reads: &mut S,
+        lr_location: u64,
+        fp_location: u64,
+    ) -> Result<(), Error> {
+        let request = ReadRequest::u64(lr_location).with_prefetch(lr_location, fp_location);
+        reads.read(StackRead::new(request))?;
+        reads.read(StackRead::new(ReadRequest::u64(fp_location)))?;
+        self.lr = None;
+        self.fp = None;
+        Ok(())
+    }
+}
+
+impl RulePlan for RulePlanAarch64 {
+    type UnwindRegs = UnwindRegsAarch64;
 
+    #[inline(always)]
+    fn finish(self, regs: &mut UnwindRegsAarch64, values: &[u64]) -> Result<Option<u64>, Error> {
+        let sp = regs.sp();
+        let fp = regs.fp();
+        let (new_lr, values) = match self.lr {
+            Some(lr) => (lr, values),
+            None => (values[0], &values[1..]),
+        };
+        let new_fp = self.fp.unwrap_or_else(|| values[0]);
+        let new_sp = self.new_sp;
+        match self.check {
+            CallerCheck::None => {}
+            CallerCheck::SpAdvances => {
+                if new_sp <= sp {
+                    return Err(Error::FramepointerUnwindingMovedBackwards);
+                }
+            }
+            CallerCheck::FramePointerChain => {
                 if new_fp == 0 {
                     return Ok(None);
                 }
                 if new_fp <= fp || new_sp <= sp {
                     return Err(Error::FramepointerUnwindingMovedBackwards);
                 }
-                (new_lr, new_sp, new_fp)
             }
-        };
+        }
         let return_address = regs.lr_mask().strip_ptr_auth(new_lr);
         if return_address == 0 {
             return Ok(None);
         }
-        if !is_first_frame && new_sp == sp {
+        if !self.is_first_frame && new_sp == sp {
             return Err(Error::DidNotAdvance);
         }
         regs.set_lr(new_lr);
-        regs.set_sp(new_sp);
-        regs.set_fp(new_fp);
+        regs.set_caller_sp_and_fp(new_sp, new_fp);
 
         Ok(Some(return_address))
     }
@@ -245,7 +469,7 @@ mod test {
         let stack = [
             1, 2, 3, 4, 0x40, 0x100200, 5, 6, 0x70, 0x100100, 7, 8, 9, 10, 0x0, 0x0,
         ];
-        let mut read_stack = |addr| Ok(stack[(addr / 8) as usize]);
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
         let mut regs = UnwindRegsAarch64::new(0x100300, 0x10, 0x20);
         let res = UnwindRuleAarch64::NoOp.exec(true, &mut regs, &mut read_stack);
         assert_eq!(res, Ok(Some(0x100300)));
@@ -261,4 +485,87 @@ mod test {
         let res = UnwindRuleAarch64::UseFramePointer.exec(false, &mut regs, &mut read_stack);
         assert_eq!(res, Ok(None));
     }
+
+    #[test]
+    fn test_fp_validity() {
+        let stack = [1, 2, 3, 4, 0x40, 0x100200, 5, 6, 0x70, 0x100100];
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
+        // fp is below the caller's sp, so it can't be the caller's frame pointer.
+        let mut regs = UnwindRegsAarch64::new(0x100300, 0x10, 0x8);
+        let res = UnwindRuleAarch64::OffsetSp { sp_offset_by_16: 1 }.exec(
+            true,
+            &mut regs,
+            &mut read_stack,
+        );
+        assert_eq!(res, Ok(Some(0x100300)));
+        assert!(!regs.fp_is_valid());
+        let res = UnwindRuleAarch64::UseFramePointer.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Err(Error::FramePointerNotValid));
+
+        // Restoring fp from the stack makes it valid again.
+        let res = UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+            sp_offset_by_16: 1,
+            fp_storage_offset_from_sp_by_8: 0,
+            lr_storage_offset_from_sp_by_8: 1,
+        }
+        .exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Ok(Some(0x100200)));
+        assert_eq!((regs.sp(), regs.fp()), (0x30, 0x40));
+        assert!(regs.fp_is_valid());
+        let res = UnwindRuleAarch64::UseFramePointer.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Ok(Some(0x100100)));
+    }
+
+    #[test]
+    fn test_restore_fp_only() {
+        // fp was saved 8 bytes above sp, and lr is still in its register.
+        let rule = UnwindRuleAarch64::OffsetSpAndRestoreFp {
+            sp_offset_by_16: 2,
+            fp_storage_offset_from_sp_by_8: 1,
+        };
+        let stack = [1, 0x90, 3, 4, 5, 6];
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
+        let mut regs = UnwindRegsAarch64::new(0x100200, 0x0, 0x20);
+        let res = rule.exec(true, &mut regs, &mut read_stack);
+        assert_eq!(res, Ok(Some(0x100200)));
+        assert_eq!(regs.sp(), 0x20);
+        assert_eq!(regs.fp(), 0x90);
+
+        let mut regs = UnwindRegsAarch64::new(0x100200, 0x0, 0x20);
+        let res = rule.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Err(Error::DidNotAdvance));
+    }
+
+    #[test]
+    fn test_serialization() {
+        let rules = [
+            UnwindRuleAarch64::NoOp,
+            UnwindRuleAarch64::OffsetSpIfFirstFrameOtherwiseStackEndsHere { sp_offset_by_16: 2 },
+            UnwindRuleAarch64::OffsetSpAndRestoreFpAndLr {
+                sp_offset_by_16: 4,
+                fp_storage_offset_from_sp_by_8: -2,
+                lr_storage_offset_from_sp_by_8: -1,
+            },
+            UnwindRuleAarch64::OffsetSpAndRestoreFp {
+                sp_offset_by_16: 3,
+                fp_storage_offset_from_sp_by_8: 4,
+            },
+            UnwindRuleAarch64::UseFramepointerWithOffsets {
+                sp_offset_from_fp_by_8: 2,
+                fp_storage_offset_from_fp_by_8: 0,
+                lr_storage_offset_from_fp_by_8: 1,
+            },
+        ];
+        for rule in rules {
+            assert_eq!(UnwindRuleAarch64::from_bytes(rule.to_bytes()), Some(rule));
+        }
+        assert_eq!(
+            UnwindRuleAarch64::from_bytes([1, 1, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+        assert_eq!(
+            UnwindRuleAarch64::from_bytes([10, 0, 0, 0, 0, 0, 0, 0]),
+            None
+        );
+    }
 }
@@ -1,11 +1,43 @@
-use core::ops::Deref;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::num::NonZeroU64;
+use core::ops::{Deref, Range};
 
+use crate::foreign_unwinder::ForeignUnwinder;
+use crate::memory_reader::MemoryReadError;
+use crate::trace::Tracer;
+#[cfg(feature = "trace")]
+use crate::trace::UnwindTrace;
+use crate::unwind_step::{FrameStep, FrameUnwindStepper, FrameUnwindTask};
 use crate::{
-    unwinder::UnwinderInternal, AllocationPolicy, Error, FrameAddress, MayAllocateDuringUnwind,
-    Module, Unwinder,
+    unwinder::UnwinderInternal, AddModuleOutcome, AllocationPolicy, CacheStats, CodeId, Diagnostic,
+    Error, FallbackPolicy, FrameAddress, FrameUnwindInfo, InstructionPointerAdjustment, JitRange,
+    MayAllocateDuringUnwind, MemoryReader, Module, ModuleDescriptor, StackLink, SyncModulesOutcome,
+    Unwinder,
 };
 
-use super::{ArchAarch64, CacheAarch64, UnwindRegsAarch64};
+use super::{ArchAarch64, CacheAarch64, PtrAuthMask, UnwindRegsAarch64, UnwindRuleAarch64};
+
+/// The [`UnwindTrace`] type for the Aarch64 CPU architecture.
+#[cfg(feature = "trace")]
+pub type UnwindTraceAarch64 = UnwindTrace<UnwindRuleAarch64, UnwindRegsAarch64>;
+
+/// How the Aarch64 unwinder strips pointer authentication bits from return addresses.
+/// See [`UnwinderAarch64::set_ptr_auth_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum PtrAuthPolicy {
+    /// Use the [`PtrAuthMask`] of the [`UnwindRegsAarch64`] which are passed to the
+    /// unwinder. This is the default.
+    #[default]
+    FromUnwindRegs,
+    /// Use the given mask.
+    Mask(PtrAuthMask),
+    /// Deduce the mask from the highest known code address, using
+    /// [`PtrAuthMask::from_max_known_address`]. The mask follows the modules as they
+    /// are added and removed.
+    FromMaxKnownAddress,
+}
 
 /// The unwinder for the Aarch64 CPU architecture. Use the [`Unwinder`] trait for unwinding.
 ///
@@ -13,7 +45,10 @@ use super::{ArchAarch64, CacheAarch64, UnwindRegsAarch64};
 ///
 ///  - `D`: The type for unwind section data in the modules. See [`Module`].
 /// -  `P`: The [`AllocationPolicy`].
-pub struct UnwinderAarch64<D, P = MayAllocateDuringUnwind>(UnwinderInternal<D, ArchAarch64, P>);
+pub struct UnwinderAarch64<D, P = MayAllocateDuringUnwind>(
+    UnwinderInternal<D, ArchAarch64, P>,
+    PtrAuthPolicy,
+);
 
 impl<D, P> Default for UnwinderAarch64<D, P> {
     fn default() -> Self {
@@ -23,44 +58,656 @@ impl<D, P> Default for UnwinderAarch64<D, P> {
 
 impl<D, P> Clone for UnwinderAarch64<D, P> {
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1)
     }
 }
 
 impl<D, P> UnwinderAarch64<D, P> {
     /// Create an unwinder for a process.
     pub fn new() -> Self {
-        Self(UnwinderInternal::new())
+        Self(UnwinderInternal::new(), PtrAuthPolicy::default())
+    }
+
+    /// Set how pointer authentication bits are stripped from return addresses.
+    ///
+    /// With any policy other than [`PtrAuthPolicy::FromUnwindRegs`], the mask of the
+    /// unwind registers is replaced with the policy's mask during unwinding.
+    ///
+    /// Independently of the policy, no bits are stripped when unwinding frames in
+    /// modules which are known not to sign return addresses, see
+    /// [`ModuleSectionInfo::signs_return_addresses`](crate::ModuleSectionInfo::signs_return_addresses).
+    /// This allows mixing arm64e and arm64 modules in the same process. Conversely, in
+    /// modules which are known to sign return addresses, e.g. arm64e system libraries,
+    /// the mask is deduced from the highest known code address if the policy's mask
+    /// doesn't strip anything.
+    pub fn set_ptr_auth_policy(&mut self, policy: PtrAuthPolicy) {
+        self.1 = policy;
+    }
+
+    /// The current [`PtrAuthPolicy`].
+    pub fn ptr_auth_policy(&self) -> PtrAuthPolicy {
+        self.1
+    }
+
+    /// Set a callback which receives [`Diagnostic`]s about unusual situations
+    /// encountered by this unwinder, for example unwind information which could
+    /// not be used. Replaces any previously-set callback.
+    pub fn set_diagnostics_callback<C>(&mut self, callback: C)
+    where
+        C: Fn(&Diagnostic) + Send + Sync + 'static,
+    {
+        self.0.set_diagnostics_callback(Some(Arc::new(callback)));
+    }
+
+    /// Remove the diagnostics callback.
+    pub fn clear_diagnostics_callback(&mut self) {
+        self.0.set_diagnostics_callback(None);
+    }
+
+    /// Register a stack-switch trampoline, so that unwinding continues on the parent
+    /// stack when it reaches a frame inside the trampoline. See [`StackLink`].
+    ///
+    /// Stack links are checked before the unwind information of the modules. If the
+    /// address ranges of several stack links overlap, the one which was added first is
+    /// used.
+    pub fn add_stack_link(&mut self, stack_link: StackLink) {
+        self.0.add_stack_link(stack_link);
+    }
+
+    /// Remove all stack links which were added with `add_stack_link`.
+    pub fn clear_stack_links(&mut self) {
+        self.0.clear_stack_links();
+    }
+
+    /// Set the rule which is used for addresses outside of any module, and for addresses
+    /// whose unwind information couldn't be used. The default is
+    /// [`UnwindRuleAarch64::UseFramePointer`]. For example, use
+    /// [`UnwindRuleAarch64::EndOfStack`] to stop unwinding instead of guessing, or
+    /// [`UnwindRuleAarch64::NoOpIfFirstFrameOtherwiseFp`] if the first frame is often in
+    /// a leaf function which keeps the return address in the link register.
+    ///
+    /// Modules with [`ModuleBuilder::frame_pointers_guaranteed`](crate::ModuleBuilder::frame_pointers_guaranteed)
+    /// still use the frame pointer.
+    pub fn set_fallback_rule(&mut self, rule: UnwindRuleAarch64) {
+        self.0.set_fallback_rule(rule);
+    }
+
+    /// Set what happens to frames which would be unwound with the fallback rule. The
+    /// default is [`FallbackPolicy::UseFallback`]. Crash reporters which need reliable
+    /// stacks can use [`FallbackPolicy::StopStack`] to end the stack at the first such
+    /// frame, or [`FallbackPolicy::ReturnError`] to also tell this apart from the root of
+    /// the stack.
+    pub fn set_fallback_policy(&mut self, policy: FallbackPolicy) {
+        self.0.set_fallback_policy(policy);
+    }
+
+    /// Unwind every frame with the frame pointer, i.e. with
+    /// [`UnwindRuleAarch64::UseFramePointer`], without looking up the unwind information of
+    /// the modules. This is the cheapest way to unwind if all code is compiled with frame
+    /// pointers. Stack links, foreign unwinders, JIT ranges, root address ranges and
+    /// stack end sentinels still apply, and the rule's checks still end the stack when
+    /// the frame pointer doesn't move up the stack. Rules are not cached in this mode.
+    ///
+    /// The first frame is unwound with the frame pointer as well, so its caller is
+    /// skipped if the instruction pointer is in a function which hasn't set up its
+    /// frame yet.
+    pub fn set_frame_pointer_only(&mut self, frame_pointer_only: bool) {
+        self.0.set_frame_pointer_only(frame_pointer_only);
+    }
+
+    /// In frame-pointer-only mode, also unwind every `interval`-th frame with the unwind
+    /// information of its module, and count in the [`UnwindStats`](crate::UnwindStats)
+    /// of the cache how often the frame pointer produced a different caller. This
+    /// measures how reliable the frame pointers of a workload are, at a fraction of the
+    /// cost of always using the unwind information. Frames which the unwind information
+    /// doesn't cover aren't verified. `None`, the default, disables the verification.
+    pub fn set_frame_pointer_verification_interval(&mut self, interval: Option<NonZeroU64>) {
+        self.0.set_frame_pointer_verification_interval(interval);
+    }
+
+    /// Set how instruction pointers are adjusted before their unwind information is
+    /// looked up. See [`InstructionPointerAdjustment`].
+    pub fn set_instruction_pointer_adjustment(&mut self, adjustment: InstructionPointerAdjustment) {
+        self.0.set_instruction_pointer_adjustment(adjustment);
+    }
+
+    /// Declare that the function at `avma_range` is the root of the stack, e.g. the
+    /// entry point of the process or of its threads. Unwinding a frame in this range
+    /// ends the stack with `Ok(None)`, even if the function's unwind information doesn't
+    /// say so and the frame pointer isn't zero.
+    pub fn add_root_address_range(&mut self, avma_range: Range<u64>) {
+        self.0.add_root_address_range(avma_range);
+    }
+
+    /// Remove all ranges which were added with `add_root_address_range`.
+    pub fn clear_root_address_ranges(&mut self) {
+        self.0.clear_root_address_ranges();
+    }
+
+    /// Tag the code at `avma_range`, e.g. a signal trampoline, a JIT entry stub or a
+    /// syscall wrapper, so that iterators created with
+    /// [`UnwindIterator::with_frame_markers`](crate::UnwindIterator::with_frame_markers)
+    /// or [`UnwindIterator::with_frame_hook`](crate::UnwindIterator::with_frame_hook)
+    /// yield a [`MixedFrame::Marker`](crate::MixedFrame::Marker) with `tag` before
+    /// every frame in this range. The meaning of the tag is up to the caller. Marker
+    /// ranges don't change how frames are unwound.
+    pub fn add_marker_range(&mut self, avma_range: Range<u64>, tag: u32) {
+        self.0.add_marker_range(avma_range, tag);
+    }
+
+    /// Remove all ranges which were added with `add_marker_range`.
+    pub fn clear_marker_ranges(&mut self) {
+        self.0.clear_marker_ranges();
+    }
+
+    /// Set the stack pointer values at which the stack ends, e.g. the known tops of the
+    /// threads' stacks. If unwinding a frame restores one of these values as the stack
+    /// pointer, unwinding ends with `Ok(None)` instead of reading the caller's frame
+    /// from outside the stack.
+    pub fn set_stack_end_sentinels(&mut self, sentinels: Vec<u64>) {
+        self.0.set_stack_end_sentinels(sentinels);
+    }
+
+    /// Replace the ranges of JIT code outside of any module, e.g. after reading the
+    /// JIT's perf map file with [`JitRange::from_perf_map`]. Frames in these ranges are
+    /// unwound according to the hint of their range instead of with the fallback rule.
+    /// Replacing the ranges is cheap and doesn't invalidate the rule cache.
+    pub fn set_jit_ranges(&mut self, ranges: Vec<JitRange>) {
+        self.0.set_jit_ranges(ranges);
+    }
+
+    /// Add a range of JIT code, e.g. for a function which the JIT just compiled. Ranges
+    /// which overlap the new range are removed.
+    pub fn add_jit_range(&mut self, range: JitRange) {
+        self.0.add_jit_range(range);
+    }
+
+    /// Remove all JIT ranges.
+    pub fn clear_jit_ranges(&mut self) {
+        self.0.clear_jit_ranges();
+    }
+
+    /// Register a callback which unwinds the frames in `avma_range`, for code that
+    /// framehop can't unwind on its own, e.g. the interpreter and the compiled code of
+    /// a JVM, whose frames a mixed-mode profiler walks with the JVM's own stack walker.
+    ///
+    /// The callback sets the registers to the values of the caller frame and returns
+    /// the return address, or `None` if the stack ends. Unwinding then continues
+    /// natively in the caller frame. The callback's results are not cached.
+    ///
+    /// Foreign unwinders are checked after stack links and before JIT ranges and the
+    /// modules. If the address ranges of several foreign unwinders overlap, the one
+    /// which was added first is used.
+    pub fn add_foreign_unwinder<C>(&mut self, avma_range: Range<u64>, callback: C)
+    where
+        C: Fn(
+                FrameAddress,
+                &mut UnwindRegsAarch64,
+                &mut dyn MemoryReader,
+            ) -> Result<Option<u64>, Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.0.add_foreign_unwinder(ForeignUnwinder {
+            avma_range,
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// Remove all callbacks which were added with `add_foreign_unwinder`.
+    pub fn clear_foreign_unwinders(&mut self) {
+        self.0.clear_foreign_unwinders();
+    }
+}
+
+impl<D: Deref<Target = [u8]>, P: AllocationPolicy> UnwinderAarch64<D, P> {
+    /// Returns the number of bytes held by all modules. See [`Module::memory_usage`].
+    /// Data which modules share with their clones is counted once.
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    /// Limit the number of bytes held by the modules. If the modules hold more than
+    /// `budget`, the text bytes of some modules are dropped, which makes unwinding in
+    /// function prologues and epilogues less reliable. Unwind sections and indexes are
+    /// never dropped, and neither is data which a module shares with its clones, so the
+    /// usage can stay above the budget. This is reported with
+    /// [`Diagnostic::MemoryBudgetExceeded`](crate::Diagnostic::MemoryBudgetExceeded).
+    ///
+    /// The budget is checked now and whenever a module is added. Modules created with
+    /// [`Module::new_lazy`] are only counted once they are loaded.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.0.set_memory_budget(budget);
+    }
+
+    /// Load the unwind information of the modules created with
+    /// [`Module::new_lazy`] which contain any of `addresses`, so that their loaders
+    /// don't run during unwinding, e.g. in a signal handler. See [`Module::load`].
+    /// Returns the number of modules which weren't loaded before.
+    #[cfg(feature = "std")]
+    pub fn load_modules_for(&mut self, addresses: impl IntoIterator<Item = u64>) -> usize {
+        self.0.load_modules_for(addresses)
+    }
+}
+
+impl<D: Deref<Target = [u8]>, P: AllocationPolicy> UnwinderAarch64<D, P> {
+    /// Applies the pointer authentication policy to `regs` for unwinding the frame at
+    /// `address`, calls `f`, and then restores the policy's mask.
+    fn with_ptr_auth_mask<T>(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsAarch64,
+        f: impl FnOnce(&mut UnwindRegsAarch64) -> T,
+    ) -> T {
+        let (mask, frame_mask) = self.ptr_auth_masks(address, regs);
+        regs.set_lr_mask(frame_mask);
+        // In the first frame, lr may still hold a signed return address.
+        regs.set_lr(regs.lr());
+        let result = f(regs);
+        regs.set_lr_mask(mask);
+        result
+    }
+
+    /// The mask of the pointer authentication policy, and the mask for unwinding the
+    /// frame at `address`.
+    fn ptr_auth_masks(
+        &self,
+        address: FrameAddress,
+        regs: &UnwindRegsAarch64,
+    ) -> (PtrAuthMask, PtrAuthMask) {
+        let mask = match self.1 {
+            PtrAuthPolicy::FromUnwindRegs => regs.lr_mask(),
+            PtrAuthPolicy::Mask(mask) => mask,
+            PtrAuthPolicy::FromMaxKnownAddress => match self.0.max_known_code_address() {
+                0 => PtrAuthMask::new_no_strip(),
+                max_known_address => PtrAuthMask::from_max_known_address(max_known_address),
+            },
+        };
+        let signs_return_addresses = self
+            .0
+            .module_for_address(address.address_for_lookup())
+            .and_then(|module| module.signs_return_addresses());
+        let frame_mask = match signs_return_addresses {
+            Some(false) => PtrAuthMask::new_no_strip(),
+            // The return addresses of arm64e code are signed even if the caller didn't
+            // set up a mask, and they'd be unusable without stripping.
+            Some(true) if mask == PtrAuthMask::new_no_strip() => {
+                match self.0.max_known_code_address() {
+                    0 => mask,
+                    max_known_address => PtrAuthMask::from_max_known_address(max_known_address),
+                }
+            }
+            _ => mask,
+        };
+        (mask, frame_mask)
+    }
+}
+
+/// A frame unwind for a [`FrameUnwindStepper`] which applies the pointer authentication
+/// policy like [`UnwinderAarch64::with_ptr_auth_mask`].
+struct PtrAuthFrameUnwind<T> {
+    task: T,
+    mask: PtrAuthMask,
+    frame_mask: PtrAuthMask,
+    started: bool,
+}
+
+impl<'u, C, T> FrameUnwindTask<'u, UnwindRegsAarch64, C> for PtrAuthFrameUnwind<T>
+where
+    T: FrameUnwindTask<'u, UnwindRegsAarch64, C>,
+{
+    fn step(
+        &mut self,
+        regs: &mut UnwindRegsAarch64,
+        cache: &mut C,
+        info: &mut FrameUnwindInfo,
+    ) -> FrameStep<'u, UnwindRegsAarch64> {
+        if !self.started {
+            self.started = true;
+            regs.set_lr_mask(self.frame_mask);
+            // In the first frame, lr may still hold a signed return address.
+            regs.set_lr(regs.lr());
+        }
+        let step = self.task.step(regs, cache, info);
+        if let FrameStep::Done(_) = step {
+            regs.set_lr_mask(self.mask);
+        }
+        step
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        self.task.provide(value);
+    }
+
+    fn provide_foreign_result(&mut self, result: Result<Option<u64>, Error>) {
+        self.task.provide_foreign_result(result);
     }
 }
 
 impl<D: Deref<Target = [u8]>, P: AllocationPolicy> Unwinder for UnwinderAarch64<D, P> {
     type UnwindRegs = UnwindRegsAarch64;
-    type Cache = CacheAarch64<P>;
+    type Cache<'s> = CacheAarch64<'s, P>;
     type Module = Module<D>;
+    #[cfg(feature = "trace")]
+    type UnwindTrace = UnwindTraceAarch64;
 
-    fn add_module(&mut self, module: Module<D>) {
-        self.0.add_module(module);
+    fn add_module(&mut self, module: Module<D>) -> AddModuleOutcome {
+        self.0.add_module(module)
     }
 
     fn remove_module(&mut self, module_address_range_start: u64) {
         self.0.remove_module(module_address_range_start);
     }
 
+    fn set_modules(&mut self, modules: Vec<Self::Module>) {
+        self.0.set_modules(modules);
+    }
+
+    fn sync_modules<I, L>(&mut self, current: I, load: L) -> SyncModulesOutcome
+    where
+        I: IntoIterator<Item = ModuleDescriptor>,
+        L: FnMut(&ModuleDescriptor) -> Option<Self::Module>,
+    {
+        self.0.sync_modules(current, load)
+    }
+
     fn max_known_code_address(&self) -> u64 {
         self.0.max_known_code_address()
     }
 
+    fn modules_generation(&self) -> u16 {
+        self.0.modules_generation()
+    }
+
+    fn module_relative_address(&self, address: FrameAddress) -> Option<(Option<&CodeId>, u32)> {
+        self.0.module_relative_address(address)
+    }
+
+    fn module_for_address(&self, address: u64) -> Option<&Self::Module> {
+        self.0.module_for_address(address)
+    }
+
+    fn modules(&self) -> &[Self::Module] {
+        self.0.modules()
+    }
+
+    fn marker_for_address(&self, address: u64) -> Option<u32> {
+        self.0.marker_for_address(address)
+    }
+
+    fn cache_stats(&self, cache: &Self::Cache<'_>) -> CacheStats {
+        cache.stats()
+    }
+
     fn unwind_frame<F>(
         &self,
         address: FrameAddress,
         regs: &mut UnwindRegsAarch64,
-        cache: &mut CacheAarch64<P>,
+        cache: &mut CacheAarch64<'_, P>,
         read_stack: &mut F,
     ) -> Result<Option<u64>, Error>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
+        F: MemoryReader,
     {
-        self.0.unwind_frame(address, regs, &mut cache.0, read_stack)
+        self.with_ptr_auth_mask(address, regs, |regs| {
+            self.0.unwind_frame(
+                address,
+                regs,
+                &mut cache.0,
+                read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        })
+    }
+
+    fn unwind_frame_with_info<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsAarch64,
+        cache: &mut CacheAarch64<'_, P>,
+        read_stack: &mut F,
+        info: &mut FrameUnwindInfo,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.with_ptr_auth_mask(address, regs, |regs| {
+            self.0.unwind_frame(
+                address,
+                regs,
+                &mut cache.0,
+                read_stack,
+                Some(info),
+                &mut Tracer::disabled(),
+            )
+        })
+    }
+
+    fn frame_unwind_stepper<'s>(
+        &self,
+        address: FrameAddress,
+        regs: UnwindRegsAarch64,
+    ) -> FrameUnwindStepper<'_, 's, Self> {
+        let (mask, frame_mask) = self.ptr_auth_masks(address, &regs);
+        let task = PtrAuthFrameUnwind {
+            task: self.0.frame_unwind_task(address),
+            mask,
+            frame_mask,
+            started: false,
+        };
+        FrameUnwindStepper::new(address, regs, Box::new(task))
+    }
+
+    fn precompute_rules<I>(&self, cache: &mut CacheAarch64<'_, P>, addresses: I) -> usize
+    where
+        I: IntoIterator<Item = FrameAddress>,
+    {
+        self.0
+            .precompute_rules(&mut cache.0, addresses, &UnwindRegsAarch64::new(0, 0, 0))
+    }
+
+    #[cfg(feature = "trace")]
+    fn unwind_frame_traced<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsAarch64,
+        cache: &mut CacheAarch64<'_, P>,
+        read_stack: &mut F,
+        trace: &mut UnwindTraceAarch64,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.with_ptr_auth_mask(address, regs, |regs| {
+            self.0.unwind_frame(
+                address,
+                regs,
+                &mut cache.0,
+                read_stack,
+                None,
+                &mut Tracer::new(trace),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ExplicitModuleSectionInfo;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_ptr_auth_policy() {
+        let module = |signs_return_addresses| {
+            Module::new(
+                String::from("test"),
+                0x1000..0x2000,
+                0x1000,
+                ExplicitModuleSectionInfo {
+                    signs_return_addresses,
+                    ..Default::default()
+                },
+            )
+        };
+        let signed_return_address = 0x002a_0000_0000_1234;
+        let stack = [0x0, 0x0, 0x0, 0x0, 0x40, signed_return_address];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let address = FrameAddress::from_return_address(0x1800).unwrap();
+
+        for (policy, signs_return_addresses, expected_return_address) in [
+            (PtrAuthPolicy::FromUnwindRegs, None, signed_return_address),
+            (PtrAuthPolicy::FromUnwindRegs, Some(true), 0x1234),
+            (
+                PtrAuthPolicy::FromUnwindRegs,
+                Some(false),
+                signed_return_address,
+            ),
+            (PtrAuthPolicy::FromMaxKnownAddress, None, 0x1234),
+            (PtrAuthPolicy::FromMaxKnownAddress, Some(true), 0x1234),
+            (
+                PtrAuthPolicy::FromMaxKnownAddress,
+                Some(false),
+                signed_return_address,
+            ),
+            (
+                PtrAuthPolicy::Mask(PtrAuthMask::from_virtual_address_bits(48)),
+                None,
+                0x1234,
+            ),
+        ] {
+            let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+            unwinder.set_ptr_auth_policy(policy);
+            unwinder.add_module(module(signs_return_addresses));
+            let mut cache = CacheAarch64::<_>::new();
+            let mut regs = UnwindRegsAarch64::new(0x0, 0x0, 0x20);
+            let res = unwinder.unwind_frame(address, &mut regs, &mut cache, &mut read_stack);
+            assert_eq!(res, Ok(Some(expected_return_address)));
+        }
+    }
+
+    #[test]
+    fn test_negate_ra_state() {
+        #[rustfmt::skip]
+        let debug_frame = vec![
+            // CIE: version 1, code alignment 4, data alignment -8, return address in x30
+            0x0c, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x78, 0x1e,
+            // DW_CFA_def_cfa: sp + 0
+            0x0c, 0x1f, 0x00,
+            // FDE for 0x800..0x810
+            0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // 0x800: paciasp
+            0x2d,       // DW_CFA_AARCH64_negate_ra_state
+            0x41,       // DW_CFA_advance_loc: 4
+            // 0x804: stp x29, x30, [sp, #-0x10]!
+            0x0e, 0x10, // DW_CFA_def_cfa_offset: 16
+            0x9e, 0x01, // DW_CFA_offset: x30 at cfa - 8
+            0x9d, 0x02, // DW_CFA_offset: x29 at cfa - 16
+        ];
+        let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        unwinder.set_ptr_auth_policy(PtrAuthPolicy::FromMaxKnownAddress);
+        unwinder.add_module(Module::new(
+            String::from("test"),
+            0x1000..0x2000,
+            0x1000,
+            ExplicitModuleSectionInfo {
+                debug_frame: Some(debug_frame),
+                ..Default::default()
+            },
+        ));
+        let signed_return_address = 0x002a_0000_0000_1234;
+        let stack = [0x0, 0x0, 0x40, signed_return_address];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut cache = CacheAarch64::<_>::new();
+        let mut regs = UnwindRegsAarch64::new(0xdead, 0x10, 0x10);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x1808),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert_eq!(regs.sp(), 0x20);
+        assert_eq!(regs.fp(), 0x40);
+    }
+
+    #[cfg(feature = "macho")]
+    #[test]
+    fn test_arm64e_compact_unwind_info() {
+        // The __unwind_info of an arm64e binary with a frame-based function at 0x1000
+        // and a frameless leaf function at 0x1800, covering 0x1000..0x2000.
+        #[rustfmt::skip]
+        let unwind_info: Vec<u8> = [
+            // Header: version 1, no common encodings, no personalities, two index entries
+            // at 0x1c.
+            1, 0x1c, 0, 0, 0, 0x1c, 0, 0, 0, 0x1c, 2, 0x1c, 0, 2,
+            // Index: the page at 0x34 starts at 0x1000, the sentinel at 0x2000. No LSDAs.
+            0x1000, 0x34, 0x34, 0x2000, 0, 0x34,
+            // Regular page with two entries at offset 8.
+            2, 0x0002_0008,
+            // Frame-based function.
+            0x1000, 0x0400_0000,
+            // Frameless function without stack allocation.
+            0x1800, 0x0200_0000,
+        ]
+        .iter()
+        .flat_map(|word: &u32| word.to_le_bytes())
+        .collect();
+        let module = |signs_return_addresses| {
+            Module::new(
+                String::from("arm64e"),
+                0x1000..0x2000,
+                0,
+                ExplicitModuleSectionInfo {
+                    text_svma: Some(0x1000..0x2000),
+                    unwind_info: Some(unwind_info.clone()),
+                    signs_return_addresses,
+                    ..Default::default()
+                },
+            )
+        };
+        let signed_return_address = 0x002a_0000_0000_1234;
+        let stack = [0x0, 0x0, 0x0, 0x0, 0x40, signed_return_address];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+
+        // The frame-based function: the signed lr is read from the frame record, and
+        // stripped even though the unwind registers don't have a mask.
+        for (signs_return_addresses, expected_return_address) in
+            [(Some(true), 0x1234), (None, signed_return_address)]
+        {
+            let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+            unwinder.add_module(module(signs_return_addresses));
+            let mut cache = CacheAarch64::<_>::new();
+            let mut regs = UnwindRegsAarch64::new(0x0, 0x10, 0x20);
+            let res = unwinder.unwind_frame(
+                FrameAddress::from_return_address(0x1100).unwrap(),
+                &mut regs,
+                &mut cache,
+                &mut read_stack,
+            );
+            assert_eq!(res, Ok(Some(expected_return_address)));
+            assert_eq!(regs.sp(), 0x30);
+            assert_eq!(regs.fp(), 0x40);
+        }
+
+        // The frameless function: lr is still in its register.
+        let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        unwinder.add_module(module(Some(true)));
+        let mut cache = CacheAarch64::<_>::new();
+        let mut regs = UnwindRegsAarch64::new(signed_return_address, 0x10, 0x20);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x1804),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert_eq!(regs.sp(), 0x10);
     }
 }
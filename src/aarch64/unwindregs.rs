@@ -1,6 +1,9 @@
 use core::fmt::Debug;
 
 use crate::display_utils::HexNum;
+use crate::partial_regs::PartialUnwindRegs;
+use crate::stack_link::StackLinkRegs;
+use crate::unwind_regs::UnwindRegs;
 
 /// The registers used for unwinding on Aarch64. We only need lr (x30), sp (x31),
 /// and fp (x29).
@@ -14,6 +17,7 @@ pub struct UnwindRegsAarch64 {
     lr: u64,
     sp: u64,
     fp: u64,
+    fp_is_valid: bool,
 }
 
 /// Aarch64 CPUs support special instructions which interpret pointers as pair
@@ -58,6 +62,20 @@ impl PtrAuthMask {
         Self(u64::MAX >> address.leading_zeros())
     }
 
+    /// Create a mask from the number of virtual address bits used by the process.
+    /// All higher bits are reserved for the hash.
+    ///
+    /// On Linux, the number of virtual address bits is 64 minus the `T0SZ` field of the
+    /// `TCR_EL1` register. On macOS, it is available from the
+    /// `machdep.virtual_address_size` sysctl.
+    pub fn from_virtual_address_bits(bits: u32) -> Self {
+        Self(
+            u64::MAX
+                .checked_shr(64u32.saturating_sub(bits))
+                .unwrap_or(0),
+        )
+    }
+
     /// Apply the mask to the given pointer.
     #[inline(always)]
     pub fn strip_ptr_auth(&self, ptr: u64) -> u64 {
@@ -74,6 +92,7 @@ impl UnwindRegsAarch64 {
             lr,
             sp,
             fp,
+            fp_is_valid: true,
         }
     }
 
@@ -90,6 +109,7 @@ impl UnwindRegsAarch64 {
             lr: code_ptr_auth_mask.strip_ptr_auth(lr),
             sp,
             fp,
+            fp_is_valid: true,
         }
     }
 
@@ -99,6 +119,13 @@ impl UnwindRegsAarch64 {
         self.lr_mask
     }
 
+    /// Set the [`PtrAuthMask`] which we apply to `lr` values from now on. This does not
+    /// change the current `lr` value.
+    #[inline(always)]
+    pub fn set_lr_mask(&mut self, lr_mask: PtrAuthMask) {
+        self.lr_mask = lr_mask;
+    }
+
     /// Get the stack pointer value.
     #[inline(always)]
     pub fn sp(&self) -> u64 {
@@ -123,6 +150,40 @@ impl UnwindRegsAarch64 {
         self.fp = fp
     }
 
+    /// Whether fp is believed to be a real frame pointer, rather than x29 used as a
+    /// scratch register by code which doesn't maintain the frame pointer chain.
+    ///
+    /// This starts out as `true`. After each unwound frame, it describes the fp value of
+    /// the caller: an fp which was restored from the stack by the frame's unwind rule is
+    /// valid if it points at or above the new sp, and an fp which the rule left
+    /// unchanged keeps its validity, unless it's now below sp. Frame-pointer-based rules,
+    /// including the fallback rule, refuse to follow an invalid fp.
+    #[inline(always)]
+    pub fn fp_is_valid(&self) -> bool {
+        self.fp_is_valid
+    }
+
+    /// Set whether fp is a real frame pointer, e.g. if the sampled code is known to
+    /// use x29 as a scratch register. See [`UnwindRegsAarch64::fp_is_valid`].
+    #[inline(always)]
+    pub fn set_fp_is_valid(&mut self, fp_is_valid: bool) {
+        self.fp_is_valid = fp_is_valid
+    }
+
+    /// Set sp and fp to the values of the caller, and update the validity of fp.
+    #[inline(always)]
+    pub(crate) fn set_caller_sp_and_fp(&mut self, sp: u64, fp: u64) {
+        // An unchanged fp keeps its validity, a restored fp is checked from scratch.
+        let fp_is_valid = if fp == self.fp {
+            self.fp_is_valid
+        } else {
+            true
+        };
+        self.fp_is_valid = fp_is_valid && (fp == 0 || fp >= sp);
+        self.sp = sp;
+        self.fp = fp;
+    }
+
     /// Get the lr register value.
     #[inline(always)]
     pub fn lr(&self) -> u64 {
@@ -136,19 +197,285 @@ impl UnwindRegsAarch64 {
     }
 }
 
+/// The registers are x29 (fp), x30 (lr) and x31 (sp). Setting lr strips the pointer
+/// authentication bits, like [`UnwindRegsAarch64::set_lr`].
+impl UnwindRegs for UnwindRegsAarch64 {
+    fn get_dwarf_reg(&self, register: u16) -> Option<u64> {
+        match register {
+            29 => Some(self.fp()),
+            30 => Some(self.lr()),
+            31 => Some(self.sp()),
+            _ => None,
+        }
+    }
+
+    fn set_dwarf_reg(&mut self, register: u16, value: u64) -> bool {
+        match register {
+            29 => self.set_fp(value),
+            30 => self.set_lr(value),
+            31 => self.set_sp(value),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl StackLinkRegs for UnwindRegsAarch64 {
+    fn sp(&self) -> u64 {
+        self.sp()
+    }
+
+    fn fp(&self) -> u64 {
+        self.fp()
+    }
+
+    fn switch_to_parent_stack(&mut self, sp: u64, fp: u64, return_address: u64) -> u64 {
+        self.set_sp(sp);
+        self.set_fp(fp);
+        self.set_lr(return_address);
+        self.lr()
+    }
+}
+
+/// The registers of Aarch64 which are needed for unwinding, when lr or fp might be
+/// unknown. See
+/// [`Unwinder::unwind_frame_with_partial_regs`](crate::Unwinder::unwind_frame_with_partial_regs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialUnwindRegsAarch64 {
+    pub lr_mask: PtrAuthMask,
+    pub lr: Option<u64>,
+    pub sp: u64,
+    pub fp: Option<u64>,
+}
+
+impl PartialUnwindRegsAarch64 {
+    /// Create a set of partial register values and do not apply any pointer
+    /// authentication stripping.
+    pub fn new(lr: Option<u64>, sp: u64, fp: Option<u64>) -> Self {
+        Self {
+            lr_mask: PtrAuthMask::new_no_strip(),
+            lr,
+            sp,
+            fp,
+        }
+    }
+}
+
+impl PartialUnwindRegs for PartialUnwindRegsAarch64 {
+    type Regs = UnwindRegsAarch64;
+
+    fn is_complete(&self) -> bool {
+        self.lr.is_some() && self.fp.is_some()
+    }
+
+    fn with_placeholder(&self, placeholder: u64) -> UnwindRegsAarch64 {
+        UnwindRegsAarch64::new_with_ptr_auth_mask(
+            self.lr_mask,
+            self.lr.unwrap_or(placeholder),
+            self.sp,
+            self.fp.unwrap_or(placeholder),
+        )
+    }
+
+    fn set_from_unwound(&mut self, first: &UnwindRegsAarch64, second: &UnwindRegsAarch64) {
+        self.lr = Some(first.lr()).filter(|lr| *lr == second.lr());
+        self.sp = first.sp();
+        self.fp = Some(first.fp()).filter(|fp| *fp == second.fp());
+    }
+}
+
+/// All general-purpose registers of Aarch64, for consumers which need the complete
+/// register file of the caller, e.g. debuggers which evaluate variable locations.
+///
+/// [`UnwindRegsAarch64`] only recovers the registers which are needed to keep unwinding.
+/// When a frame is unwound with
+/// [`unwind_full_frame_with_eh_frame`](crate::aarch64::unwind_full_frame_with_eh_frame),
+/// every register which has a rule in the CFI is recovered, and pc is set to the
+/// recovered lr. Registers without a rule keep their values, and registers whose rule
+/// could not be evaluated become unknown.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FullUnwindRegsAarch64 {
+    lr_mask: PtrAuthMask,
+    pc: u64,
+    sp: u64,
+    x: [u64; 31],
+    /// Bit `n` is set if x`n` is known.
+    known: u32,
+}
+
+impl FullUnwindRegsAarch64 {
+    /// Create a register file in which all registers are known, and do not apply any
+    /// pointer authentication stripping.
+    pub fn new(pc: u64, sp: u64, x: [u64; 31]) -> Self {
+        Self {
+            lr_mask: PtrAuthMask::new_no_strip(),
+            pc,
+            sp,
+            x,
+            known: u32::MAX >> 1,
+        }
+    }
+
+    /// Get the [`PtrAuthMask`] which we apply to `lr` values.
+    #[inline(always)]
+    pub fn lr_mask(&self) -> PtrAuthMask {
+        self.lr_mask
+    }
+
+    /// Set the [`PtrAuthMask`] which we apply to `lr` values from now on. This does not
+    /// change the current `lr` value.
+    #[inline(always)]
+    pub fn set_lr_mask(&mut self, lr_mask: PtrAuthMask) {
+        self.lr_mask = lr_mask;
+    }
+
+    /// The value of x`n`, or `None` if it could not be recovered or if `n` is not in
+    /// `0..=30`.
+    #[inline(always)]
+    pub fn x(&self, n: usize) -> Option<u64> {
+        if n < 31 && self.known & (1 << n) != 0 {
+            Some(self.x[n])
+        } else {
+            None
+        }
+    }
+
+    /// Set x`n`. Setting x30 (lr) strips the pointer authentication bits.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is not in `0..=30`.
+    #[inline(always)]
+    pub fn set_x(&mut self, n: usize, value: u64) {
+        self.x[n] = if n == 30 {
+            self.lr_mask.strip_ptr_auth(value)
+        } else {
+            value
+        };
+        self.known |= 1 << n;
+    }
+
+    /// Mark x`n` as unknown.
+    #[inline(always)]
+    pub fn clear_x(&mut self, n: usize) {
+        self.known &= !(1 << n);
+    }
+
+    /// Get the program counter value.
+    #[inline(always)]
+    pub fn pc(&self) -> u64 {
+        self.pc
+    }
+
+    /// Set the program counter value.
+    #[inline(always)]
+    pub fn set_pc(&mut self, pc: u64) {
+        self.pc = pc
+    }
+
+    /// Get the stack pointer value, which is always known, because unwinding sets it to
+    /// the CFA.
+    #[inline(always)]
+    pub fn sp(&self) -> u64 {
+        self.sp
+    }
+
+    /// Set the stack pointer value.
+    #[inline(always)]
+    pub fn set_sp(&mut self, sp: u64) {
+        self.sp = sp
+    }
+
+    /// The registers which are needed to continue unwinding with an
+    /// [`UnwinderAarch64`](crate::aarch64::UnwinderAarch64). An unknown fp becomes zero
+    /// and is marked as invalid, and an unknown lr becomes zero.
+    pub fn to_unwind_regs(&self) -> UnwindRegsAarch64 {
+        let mut regs = UnwindRegsAarch64::new_with_ptr_auth_mask(
+            self.lr_mask,
+            self.x(30).unwrap_or(0),
+            self.sp,
+            self.x(29).unwrap_or(0),
+        );
+        regs.set_fp_is_valid(self.x(29).is_some());
+        regs
+    }
+}
+
+/// The registers are x0 to x30, x31 (sp) and 32 (pc). Unknown registers are `None`.
+impl UnwindRegs for FullUnwindRegsAarch64 {
+    fn get_dwarf_reg(&self, register: u16) -> Option<u64> {
+        match register {
+            0..=30 => self.x(usize::from(register)),
+            31 => Some(self.sp()),
+            32 => Some(self.pc()),
+            _ => None,
+        }
+    }
+
+    fn set_dwarf_reg(&mut self, register: u16, value: u64) -> bool {
+        match register {
+            0..=30 => self.set_x(usize::from(register), value),
+            31 => self.set_sp(value),
+            32 => self.set_pc(value),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl Debug for FullUnwindRegsAarch64 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("FullUnwindRegsAarch64");
+        s.field("pc", &HexNum(self.pc));
+        s.field("sp", &HexNum(self.sp));
+        for (n, name) in X_NAMES.iter().enumerate() {
+            match self.x(n) {
+                Some(value) => s.field(name, &HexNum(value)),
+                None => s.field(name, &format_args!("<unknown>")),
+            };
+        }
+        s.finish()
+    }
+}
+
+const X_NAMES: [&str; 31] = [
+    "x0", "x1", "x2", "x3", "x4", "x5", "x6", "x7", "x8", "x9", "x10", "x11", "x12", "x13", "x14",
+    "x15", "x16", "x17", "x18", "x19", "x20", "x21", "x22", "x23", "x24", "x25", "x26", "x27",
+    "x28", "x29", "x30",
+];
+
 impl Debug for UnwindRegsAarch64 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("UnwindRegsAarch64")
             .field("lr", &HexNum(self.lr))
             .field("sp", &HexNum(self.sp))
             .field("fp", &HexNum(self.fp))
+            .field("fp_is_valid", &self.fp_is_valid)
             .finish()
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::aarch64::PtrAuthMask;
+    use crate::aarch64::{PtrAuthMask, UnwindRegsAarch64};
+    use crate::UnwindRegs;
+
+    #[test]
+    fn test_dwarf_regs() {
+        let mut regs = UnwindRegsAarch64::new_with_ptr_auth_mask(
+            PtrAuthMask::new_24_40(),
+            0x1000,
+            0x2000,
+            0x3000,
+        );
+        assert_eq!(regs.get_dwarf_reg(29), Some(0x3000));
+        assert_eq!(regs.get_dwarf_reg(30), Some(0x1000));
+        assert_eq!(regs.get_dwarf_reg(31), Some(0x2000));
+        assert_eq!(regs.get_dwarf_reg(0), None);
+        assert!(regs.set_dwarf_reg(30, 0xff00_0000_0000_4000));
+        assert_eq!(regs.lr(), 0x4000);
+        assert!(!regs.set_dwarf_reg(28, 0x5000));
+    }
 
     #[test]
     fn test() {
@@ -178,5 +505,14 @@ mod test {
             PtrAuthMask::from_max_known_address(0x000000022a3ccff7).0,
             0x00000003ffffffff
         );
+        assert_eq!(
+            PtrAuthMask::from_virtual_address_bits(48).0,
+            0x0000ffffffffffff
+        );
+        assert_eq!(
+            PtrAuthMask::from_virtual_address_bits(39).0,
+            0x0000007fffffffff
+        );
+        assert_eq!(PtrAuthMask::from_virtual_address_bits(64).0, u64::MAX);
     }
 }
@@ -0,0 +1,476 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::unwinder::ModuleDescriptor;
+
+/// The memory and the modules of an unwound process.
+///
+/// Most integrations need three things from the unwound process: a way to read its
+/// stack, its list of modules, and ideally its executable memory ranges to reject bogus
+/// return addresses. This trait bundles them, so that one value can be passed to
+/// [`Unwinder::sync_address_space`](crate::Unwinder::sync_address_space) to update the
+/// modules and then to [`Unwinder::iter_frames`](crate::Unwinder::iter_frames) to unwind.
+/// Memory reads and the executable check come from the [`MemoryReader`] supertrait.
+///
+/// [`ProcessAddressSpace`] reads a live process on Linux, and [`SnapshotAddressSpace`]
+/// reads a captured stack. Closures remain available as the low-level `read_stack`
+/// argument.
+pub trait AddressSpace: MemoryReader {
+    /// The module type of the unwinder, see [`Unwinder::Module`](crate::Unwinder::Module).
+    type Module;
+
+    /// The modules which are loaded in the process right now.
+    fn module_descriptors(&mut self) -> Vec<ModuleDescriptor>;
+
+    /// Create the module for `descriptor`, or `None` to skip it.
+    fn load_module(&mut self, descriptor: &ModuleDescriptor) -> Option<Self::Module>;
+}
+
+/// Returns the `len` bytes at `address` from `bytes`, which are mapped at `start`.
+fn bytes_at(start: u64, bytes: &[u8], address: u64, len: usize) -> Option<&[u8]> {
+    let offset = usize::try_from(address.checked_sub(start)?).ok()?;
+    bytes.get(offset..offset.checked_add(len)?)
+}
+
+/// An [`AddressSpace`] for a captured stack, e.g. from a perf sample or a minidump: the
+/// stack bytes, plus the modules and optionally their mapped images.
+///
+/// Reads are served from the stack bytes or, for addresses outside the stack, from the
+/// module images, which is needed for unwind rules that read memory outside the stack.
+/// All other reads fail with [`MemoryReadError::OutsideSnapshot`].
+pub struct SnapshotAddressSpace<'a, M> {
+    stack_avma: u64,
+    stack: &'a [u8],
+    modules: Vec<(ModuleDescriptor, Option<&'a [u8]>, M)>,
+    executable_ranges: Option<Vec<Range<u64>>>,
+}
+
+impl<'a, M: Clone> SnapshotAddressSpace<'a, M> {
+    /// Create an address space for the stack bytes `stack`, which were captured at the
+    /// address `stack_avma`. If the stack bytes would extend past the end of the address
+    /// space, they are ignored and the address space has no stack bytes.
+    pub fn new(stack_avma: u64, stack: &'a [u8]) -> Self {
+        let stack = match stack_avma.checked_add(stack.len() as u64) {
+            Some(_) => stack,
+            None => &[],
+        };
+        Self {
+            stack_avma,
+            stack,
+            modules: Vec::new(),
+            executable_ranges: None,
+        }
+    }
+
+    /// Add a module. `image` is the module's memory as mapped at
+    /// `descriptor.avma_range.start`, if it was captured.
+    pub fn add_module(&mut self, descriptor: ModuleDescriptor, image: Option<&'a [u8]>, module: M) {
+        self.modules.push((descriptor, image, module));
+    }
+
+    /// Set the executable memory ranges of the process at the time of the capture, so
+    /// that return addresses outside of them stop unwinding. Without them, return
+    /// addresses aren't checked.
+    pub fn set_executable_ranges(&mut self, ranges: Vec<Range<u64>>) {
+        self.executable_ranges = Some(ranges);
+    }
+
+    fn bytes_at(&self, address: u64, len: usize) -> Result<&'a [u8], MemoryReadError> {
+        if let Some(bytes) = bytes_at(self.stack_avma, self.stack, address, len) {
+            return Ok(bytes);
+        }
+        self.modules
+            .iter()
+            .filter_map(|(descriptor, image, _)| {
+                bytes_at(descriptor.avma_range.start, (*image)?, address, len)
+            })
+            .next()
+            .ok_or(MemoryReadError::OutsideSnapshot)
+    }
+}
+
+impl<M: Clone> MemoryReader for SnapshotAddressSpace<'_, M> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        let bytes = self.bytes_at(address, 8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        let bytes = self.bytes_at(address, 4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        buf.copy_from_slice(self.bytes_at(address, buf.len())?);
+        Ok(())
+    }
+
+    fn is_executable(&mut self, address: u64) -> Option<bool> {
+        let ranges = self.executable_ranges.as_ref()?;
+        Some(ranges.iter().any(|range| range.contains(&address)))
+    }
+
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        Some(self.stack_avma..self.stack_avma + self.stack.len() as u64)
+    }
+}
+
+impl<M: Clone> AddressSpace for SnapshotAddressSpace<'_, M> {
+    type Module = M;
+
+    fn module_descriptors(&mut self) -> Vec<ModuleDescriptor> {
+        self.modules
+            .iter()
+            .map(|(descriptor, _, _)| descriptor.clone())
+            .collect()
+    }
+
+    fn load_module(&mut self, descriptor: &ModuleDescriptor) -> Option<M> {
+        self.modules
+            .iter()
+            .find(|(d, _, _)| d == descriptor)
+            .map(|(_, _, module)| module.clone())
+    }
+}
+
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+pub use process::ProcessAddressSpace;
+
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+mod process {
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::ops::Range;
+    use std::fs::File;
+    use std::os::unix::fs::FileExt;
+
+    use super::AddressSpace;
+    use crate::memory_reader::{MemoryReadError, MemoryReader};
+    use crate::unwinder::ModuleDescriptor;
+
+    /// An [`AddressSpace`] for a live process on Linux or Android, either another process or the
+    /// current one. Memory is read from `/proc/<pid>/mem`, and the modules and executable
+    /// ranges come from `/proc/<pid>/maps`.
+    ///
+    /// Reading the memory of another process requires ptrace access to it. Every read
+    /// is a syscall, so this is best suited for tools like debuggers and for stopped
+    /// threads; a sampling profiler should copy the stack once and use a
+    /// [`SnapshotAddressSpace`](super::SnapshotAddressSpace).
+    pub struct ProcessAddressSpace<M, L> {
+        pid: Option<u32>,
+        mem: File,
+        executable_ranges: Option<Vec<Range<u64>>>,
+        load: L,
+        _module: core::marker::PhantomData<fn() -> M>,
+    }
+
+    impl<M, L> ProcessAddressSpace<M, L>
+    where
+        L: FnMut(&ModuleDescriptor) -> Option<M>,
+    {
+        /// Open the address space of the process `pid`. `load` creates the modules, e.g.
+        /// by parsing the file at the descriptor's path.
+        pub fn new(pid: u32, load: L) -> std::io::Result<Self> {
+            Self::open(Some(pid), load)
+        }
+
+        /// Open the address space of the current process.
+        pub fn current(load: L) -> std::io::Result<Self> {
+            Self::open(None, load)
+        }
+
+        fn open(pid: Option<u32>, load: L) -> std::io::Result<Self> {
+            let mem = File::open(Self::proc_path(pid, "mem"))?;
+            Ok(Self {
+                pid,
+                mem,
+                executable_ranges: None,
+                load,
+                _module: core::marker::PhantomData,
+            })
+        }
+
+        fn proc_path(pid: Option<u32>, file: &str) -> String {
+            let pid = match pid {
+                Some(pid) => pid.to_string(),
+                None => "self".to_string(),
+            };
+            alloc::format!("/proc/{pid}/{file}")
+        }
+    }
+
+    impl<M, L> MemoryReader for ProcessAddressSpace<M, L> {
+        fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            let mut buf = [0; 8];
+            self.read_block(address, &mut buf)?;
+            Ok(u64::from_le_bytes(buf))
+        }
+
+        fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+            let mut buf = [0; 4];
+            self.read_block(address, &mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        }
+
+        fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+            self.mem.read_exact_at(buf, address).map_err(|e| {
+                match e.raw_os_error() {
+                    // The kernel reports unmapped addresses in /proc/<pid>/mem as EIO.
+                    Some(5) => MemoryReadError::Unmapped,
+                    Some(13) => MemoryReadError::PermissionDenied,
+                    Some(code) => MemoryReadError::Other(code as u64),
+                    None => MemoryReadError::Unknown,
+                }
+            })
+        }
+
+        fn is_executable(&mut self, address: u64) -> Option<bool> {
+            let ranges = self.executable_ranges.as_ref()?;
+            Some(ranges.iter().any(|range| range.contains(&address)))
+        }
+    }
+
+    impl<M, L> AddressSpace for ProcessAddressSpace<M, L>
+    where
+        L: FnMut(&ModuleDescriptor) -> Option<M>,
+    {
+        type Module = M;
+
+        /// Re-reads `/proc/<pid>/maps`, which also updates the executable ranges. If
+        /// the file can't be read, no modules are returned.
+        fn module_descriptors(&mut self) -> Vec<ModuleDescriptor> {
+            let Ok(maps) = std::fs::read_to_string(Self::proc_path(self.pid, "maps")) else {
+                return Vec::new();
+            };
+            let (mapped_files, executable_ranges) = super::parse_proc_maps(&maps, false);
+            self.executable_ranges = Some(executable_ranges);
+            mapped_files
+                .into_iter()
+                .map(|mapped_file| mapped_file.descriptor)
+                .collect()
+        }
+
+        fn load_module(&mut self, descriptor: &ModuleDescriptor) -> Option<M> {
+            (self.load)(descriptor)
+        }
+    }
+}
+
+/// A mapped file in `/proc/<pid>/maps`, see [`parse_proc_maps`].
+#[cfg(any(
+    feature = "android",
+    all(feature = "std", any(target_os = "linux", target_os = "android"))
+))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MappedFile {
+    pub descriptor: ModuleDescriptor,
+    /// The file offset of the first mapping.
+    pub file_offset: u64,
+}
+
+/// Parse the contents of `/proc/<pid>/maps` into one entry per mapped file with
+/// executable code, covering all consecutive mappings of the file, and the executable
+/// ranges, including anonymous ones like JIT code. Consecutive mappings of a file are
+/// merged even if unmapped pages separate them, like the segments of a main executable
+/// which the kernel maps without filling the gaps between them.
+///
+/// If `split_at_offset_jumps` is true, consecutive mappings of a file are only merged if
+/// they are adjacent and their file offsets advance like their addresses. This keeps
+/// images apart which are mapped from different offsets of the same file, like the
+/// libraries in an Android APK.
+#[cfg(any(
+    feature = "android",
+    all(feature = "std", any(target_os = "linux", target_os = "android"))
+))]
+pub(crate) fn parse_proc_maps(
+    maps: &str,
+    split_at_offset_jumps: bool,
+) -> (Vec<MappedFile>, Vec<Range<u64>>) {
+    let mut mapped_files: Vec<(MappedFile, bool)> = Vec::new();
+    let mut executable_ranges = Vec::new();
+    for line in maps.lines() {
+        let mut fields = line.split_whitespace();
+        let (Some(range), Some(perms), Some(offset)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let Some((start, end)) = range.split_once('-') else {
+            continue;
+        };
+        let (Ok(start), Ok(end), Ok(offset)) = (
+            u64::from_str_radix(start, 16),
+            u64::from_str_radix(end, 16),
+            u64::from_str_radix(offset, 16),
+        ) else {
+            continue;
+        };
+        let is_executable = perms.as_bytes().get(2) == Some(&b'x');
+        if is_executable {
+            executable_ranges.push(start..end);
+        }
+        // Skip device and inode. The path may contain spaces.
+        let path = fields.skip(2).collect::<Vec<_>>().join(" ");
+        if !path.starts_with('/') && path != "[vdso]" {
+            continue;
+        }
+        match mapped_files.last_mut() {
+            Some((last, has_code))
+                if last.descriptor.name == path
+                    && if split_at_offset_jumps {
+                        last.descriptor.avma_range.end == start
+                            && offset.wrapping_sub(last.file_offset)
+                                == start - last.descriptor.avma_range.start
+                    } else {
+                        last.descriptor.avma_range.end <= start
+                    } =>
+            {
+                last.descriptor.avma_range.end = end;
+                *has_code |= is_executable;
+            }
+            _ => mapped_files.push((
+                MappedFile {
+                    descriptor: ModuleDescriptor {
+                        name: path,
+                        avma_range: start..end,
+                        code_id: None,
+                    },
+                    file_offset: offset,
+                },
+                is_executable,
+            )),
+        }
+    }
+    let mapped_files = mapped_files
+        .into_iter()
+        .filter(|(_, has_code)| *has_code)
+        .map(|(mapped_file, _)| mapped_file)
+        .collect();
+    (mapped_files, executable_ranges)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::{Error, FrameAddress, Unwinder};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_snapshot_address_space() {
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        // Frame pointer chain: [0x10] = caller bp, [0x18] = return address.
+        let stack: Vec<u8> = [0x0u64, 0x20, 0x2000, 0x30, 0x9000, 0x0, 0x2100]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        let mut address_space = SnapshotAddressSpace::<crate::Module<Vec<u8>>>::new(0x8, &stack);
+        assert_eq!(
+            unwinder
+                .sync_address_space(&mut address_space)
+                .added_module_count,
+            0
+        );
+
+        let regs = UnwindRegsX86_64::new(0x1000, 0x10, 0x10);
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut address_space);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x2000).unwrap()))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x9000).unwrap()))
+        );
+
+        // 0x9000 is not executable, so the stack is known to be wrong at that point.
+        address_space.set_executable_ranges(vec![0x1000..0x1800, 0x2000..0x3000]);
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut address_space);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x2000).unwrap()))
+        );
+        assert_eq!(iter.next(), Err(Error::ReturnAddressNotExecutable(0x9000)));
+        // The iterator doesn't unwind the frame at 0x2000 again with the registers of
+        // the frame at 0x9000.
+        assert_eq!(iter.next(), Ok(None));
+        assert_eq!(
+            address_space.read_u64(0x40),
+            Err(MemoryReadError::OutsideSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_address_space_at_end_of_address_space() {
+        let stack = [0u8; 16];
+        let mut address_space = SnapshotAddressSpace::<()>::new(u64::MAX - 7, &stack);
+        assert_eq!(
+            address_space.snapshot_range(),
+            Some(u64::MAX - 7..u64::MAX - 7)
+        );
+        assert_eq!(
+            address_space.read_u64(u64::MAX - 7),
+            Err(MemoryReadError::OutsideSnapshot)
+        );
+    }
+
+    #[cfg(any(
+        feature = "android",
+        all(feature = "std", any(target_os = "linux", target_os = "android"))
+    ))]
+    #[test]
+    fn test_parse_proc_maps() {
+        use alloc::string::ToString;
+
+        let maps = "\
+555555554000-555555556000 r--p 00000000 fd:01 1234 /usr/bin/my app
+555555557000-55555555a000 r-xp 00002000 fd:01 1234 /usr/bin/my app
+55555555a000-55555555c000 rw-p 00005000 fd:01 1234 /usr/bin/my app
+7f0000000000-7f0000001000 rwxp 00000000 00:00 0
+7f0000002000-7f0000003000 r--p 00000000 fd:01 99 /usr/share/locale/data
+7ffff7fc1000-7ffff7fc3000 r-xp 00000000 00:00 0 [vdso]
+7ffffffde000-7ffffffff000 rw-p 00000000 00:00 0 [stack]
+";
+        let (mapped_files, executable_ranges) = parse_proc_maps(maps, false);
+        assert_eq!(
+            mapped_files,
+            vec![
+                MappedFile {
+                    descriptor: ModuleDescriptor {
+                        name: "/usr/bin/my app".to_string(),
+                        avma_range: 0x555555554000..0x55555555c000,
+                        code_id: None,
+                    },
+                    file_offset: 0,
+                },
+                MappedFile {
+                    descriptor: ModuleDescriptor {
+                        name: "[vdso]".to_string(),
+                        avma_range: 0x7ffff7fc1000..0x7ffff7fc3000,
+                        code_id: None,
+                    },
+                    file_offset: 0,
+                },
+            ]
+        );
+        assert_eq!(
+            executable_ranges,
+            vec![
+                0x555555557000..0x55555555a000,
+                0x7f0000000000..0x7f0000001000,
+                0x7ffff7fc1000..0x7ffff7fc3000
+            ]
+        );
+    }
+}
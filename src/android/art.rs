@@ -0,0 +1,543 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::aarch64::UnwindRegsAarch64;
+use crate::error::Error;
+use crate::frame_info::FallbackReason;
+use crate::memory_reader::MemoryReader;
+use crate::x86_64::UnwindRegsX86_64;
+use crate::FrameAddress;
+
+use super::MappedModule;
+
+/// The kind of code which the Android runtime (ART) compiled into a mapping, see
+/// [`MappedModule::art_code_kind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ArtCodeKind {
+    /// An oat file with code which dex2oat compiled ahead of time, e.g. `base.odex` or
+    /// `boot.oat`. Oat files are ELF files, but they usually have no unwind information.
+    AheadOfTime,
+    /// The code cache of ART's JIT compiler.
+    Jit,
+}
+
+impl MappedModule {
+    /// The kind of ART code in this mapping, if it contains code which ART compiled.
+    /// The frames of this code can be unwound with an [`ArtQuickFrameUnwinder`].
+    pub fn art_code_kind(&self) -> Option<ArtCodeKind> {
+        let name = self.descriptor.name.as_str();
+        if name.ends_with(".oat") || name.ends_with(".odex") {
+            Some(ArtCodeKind::AheadOfTime)
+        } else if name.starts_with("/memfd:jit-cache") || name.contains("jit-code-cache") {
+            Some(ArtCodeKind::Jit)
+        } else {
+            None
+        }
+    }
+}
+
+/// The header of the `CodeInfo` of a method which ART compiled, in the layout of
+/// Android 12 and later. ART's compilers don't emit unwind information for managed
+/// code; the frame layout of each method is described by this header instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ArtCodeInfoHeader {
+    pub flags: u32,
+    /// The size of the method's code in bytes.
+    pub code_size: u32,
+    /// The size of the method's frame in bytes, including the return address and the
+    /// spilled callee-saved registers.
+    pub frame_size: u32,
+    /// The callee-saved core registers which the method spills, as a bit mask of ART's
+    /// register numbers. The return address is included as a register, `lr` on aarch64
+    /// and a fake register 16 on x86_64.
+    pub core_spill_mask: u32,
+    /// The callee-saved floating point registers which the method spills.
+    pub fp_spill_mask: u32,
+    pub number_of_dex_registers: u32,
+}
+
+impl ArtCodeInfoHeader {
+    /// The maximum number of bytes which a header occupies.
+    pub const MAX_SIZE: usize = 32;
+
+    /// The number of fields in the header.
+    const FIELD_COUNT: usize = 7;
+    /// Varints in `CodeInfo` start with a 4-bit value. Values up to 11 are stored
+    /// inline, and larger values say how many bytes, plus 11, hold the value.
+    const VARINT_BITS: usize = 4;
+    const VARINT_MAX: u64 = 11;
+    /// The unit of the packed frame size.
+    const STACK_ALIGNMENT: u32 = 16;
+
+    /// Parse the header at the start of `data`.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut reader = BitReader {
+            data,
+            bit_offset: 0,
+        };
+        let mut fields = [0; Self::FIELD_COUNT];
+        for field in &mut fields {
+            *field = reader.read_bits(Self::VARINT_BITS)?;
+        }
+        for field in &mut fields {
+            if *field > Self::VARINT_MAX {
+                let byte_count = (*field - Self::VARINT_MAX) as usize;
+                if byte_count > 4 {
+                    return None;
+                }
+                *field = reader.read_bits(byte_count * 8)?;
+            }
+        }
+        let [flags, code_size, packed_frame_size, core_spill_mask, fp_spill_mask, number_of_dex_registers, _bit_table_flags] =
+            fields.map(|field| field as u32);
+        Some(Self {
+            flags,
+            code_size,
+            frame_size: packed_frame_size.checked_mul(Self::STACK_ALIGNMENT)?,
+            core_spill_mask,
+            fp_spill_mask,
+            number_of_dex_registers,
+        })
+    }
+
+    /// The offset of the spill slot of the core register `register` from the stack
+    /// pointer of the method's frame, if the method spills it. Registers are spilled in
+    /// ascending order at the top of the frame.
+    fn core_spill_offset(&self, register: u32) -> Option<u64> {
+        if self.core_spill_mask & (1 << register) == 0 {
+            return None;
+        }
+        let spilled_register_count = u64::from(self.core_spill_mask.count_ones());
+        let lower_spilled_register_count =
+            u64::from((self.core_spill_mask & ((1 << register) - 1)).count_ones());
+        let spill_area_start =
+            u64::from(self.frame_size).checked_sub(8 * spilled_register_count)?;
+        Some(spill_area_start + 8 * lower_spilled_register_count)
+    }
+}
+
+/// Reads bits from the least significant bit of the first byte onwards, like ART's
+/// `BitMemoryReader`.
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit_offset: usize,
+}
+
+impl BitReader<'_> {
+    fn read_bits(&mut self, bit_count: usize) -> Option<u64> {
+        let mut value = 0;
+        for i in 0..bit_count {
+            let bit_offset = self.bit_offset + i;
+            let byte = self.data.get(bit_offset / 8)?;
+            value |= u64::from((byte >> (bit_offset % 8)) & 1) << i;
+        }
+        self.bit_offset += bit_count;
+        Some(value)
+    }
+}
+
+/// Unwinds the frames of methods which ART compiled, ahead of time or with its JIT,
+/// based on the [`ArtCodeInfoHeader`] of each method. This crosses managed frames
+/// reliably, even though ART's managed code maintains no frame pointer chain and has no
+/// unwind information.
+///
+/// The unwinder needs the code ranges of the compiled methods, e.g. from the symbols
+/// which `oatdump` or a profiler's symbolizer extracts from the oat files, or from the
+/// perf map file which ART writes for JIT code, see
+/// [`JitRange::parse_perf_map_line`](crate::JitRange::parse_perf_map_line). It reads
+/// the method headers and code infos from memory, so the memory reader needs to be
+/// able to read the oat files and the JIT code cache.
+///
+/// Register it with `add_foreign_unwinder` for the executable ranges of the oat files
+/// and of the JIT code cache, e.g. with
+/// [`UnwinderAarch64::add_foreign_unwinder`](crate::aarch64::UnwinderAarch64::add_foreign_unwinder)
+/// and [`ArtQuickFrameUnwinder::unwind_frame_aarch64`]. Frames in these ranges which
+/// aren't in a known method fail with [`Error::UnreliableFrame`].
+///
+/// The first frame is only unwound correctly if the instruction pointer is at the
+/// start of its method or past the method's prologue.
+#[derive(Debug, Clone, Default)]
+pub struct ArtQuickFrameUnwinder {
+    /// The code ranges of the compiled methods, sorted and without overlaps.
+    methods: Vec<Range<u64>>,
+}
+
+/// The caller's frame, as described by the `CodeInfo` of a method.
+struct ArtCallerFrame {
+    return_address: u64,
+    sp: u64,
+    /// The value of the frame pointer register if the method spilled it, otherwise
+    /// the method didn't change it.
+    fp: Option<u64>,
+}
+
+impl ArtQuickFrameUnwinder {
+    /// The ART register number of x29 on aarch64.
+    const AARCH64_FP: u32 = 29;
+    /// The ART register number of lr on aarch64.
+    const AARCH64_LR: u32 = 30;
+    /// The ART register number of rbp on x86_64.
+    const X86_64_RBP: u32 = 5;
+    /// The fake register number which stands for the return address on x86_64.
+    const X86_64_RETURN_ADDRESS: u32 = 16;
+    /// The bits of the method header which hold the offset of the `CodeInfo` from the
+    /// start of the code, if the method has one.
+    const CODE_INFO_OFFSET_MASK: u32 = 0x3fff_ffff;
+    /// The bit of the method header which says whether the method has a `CodeInfo`.
+    const IS_CODE_INFO_BIT: u32 = 0x4000_0000;
+
+    /// Create an unwinder for the methods with the given code ranges. If ranges
+    /// overlap, the later one wins.
+    pub fn new(method_ranges: Vec<Range<u64>>) -> Self {
+        // Sort the ranges once, and remember their order to resolve overlaps.
+        let mut ranges: Vec<(usize, Range<u64>)> = method_ranges
+            .into_iter()
+            .enumerate()
+            .filter(|(_, range)| !range.is_empty())
+            .collect();
+        ranges.sort_unstable_by_key(|(_, range)| range.start);
+        let mut methods: Vec<(usize, Range<u64>)> = Vec::with_capacity(ranges.len());
+        'ranges: for (order, range) in ranges {
+            // The kept methods are sorted and don't overlap, so only the last ones can
+            // overlap this range.
+            while let Some((last_order, last)) = methods.last() {
+                if last.end <= range.start {
+                    break;
+                }
+                if *last_order > order {
+                    continue 'ranges;
+                }
+                methods.pop();
+            }
+            methods.push((order, range));
+        }
+        Self {
+            methods: methods.into_iter().map(|(_, range)| range).collect(),
+        }
+    }
+
+    /// Add the code range of a compiled method, e.g. after the JIT compiled it. Ranges
+    /// which overlap the new range are removed.
+    pub fn add_method(&mut self, range: Range<u64>) {
+        if range.is_empty() {
+            return;
+        }
+        // The methods don't overlap, so they're sorted by both start and end address.
+        let overlap_start = self
+            .methods
+            .partition_point(|method| method.end <= range.start);
+        let overlap_end = self
+            .methods
+            .partition_point(|method| method.start < range.end)
+            .max(overlap_start);
+        self.methods
+            .splice(overlap_start..overlap_end, core::iter::once(range));
+    }
+
+    /// The number of known methods.
+    pub fn method_count(&self) -> usize {
+        self.methods.len()
+    }
+
+    /// The code range of the method which contains `address`.
+    pub fn method_for_address(&self, address: u64) -> Option<Range<u64>> {
+        let index = self
+            .methods
+            .partition_point(|method| method.start <= address)
+            .checked_sub(1)?;
+        let method = &self.methods[index];
+        method.contains(&address).then(|| method.clone())
+    }
+
+    /// Read the `CodeInfo` header of the method whose code starts at `code_start`.
+    pub fn read_code_info_header(
+        code_start: u64,
+        memory: &mut dyn MemoryReader,
+    ) -> Result<ArtCodeInfoHeader, Error> {
+        // The method header is a 32-bit word just before the code.
+        let header_address = code_start.checked_sub(4).ok_or(Error::IntegerOverflow)?;
+        let method_header = memory
+            .read_u32(header_address)
+            .map_err(|err| Error::CouldNotReadStack(header_address, err))?;
+        if method_header & Self::IS_CODE_INFO_BIT == 0 {
+            return Err(Error::UnreliableFrame(FallbackReason::UnwindInfoError));
+        }
+        let code_info_address = code_start
+            .checked_sub(u64::from(method_header & Self::CODE_INFO_OFFSET_MASK))
+            .ok_or(Error::IntegerOverflow)?;
+        let mut data = [0; ArtCodeInfoHeader::MAX_SIZE];
+        memory
+            .read_block(code_info_address, &mut data)
+            .map_err(|err| Error::CouldNotReadStack(code_info_address, err))?;
+        ArtCodeInfoHeader::parse(&data)
+            .ok_or(Error::UnreliableFrame(FallbackReason::UnwindInfoError))
+    }
+
+    /// Unwind a frame in ART code on aarch64. This has the signature of a foreign
+    /// unwinder callback.
+    pub fn unwind_frame_aarch64(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsAarch64,
+        memory: &mut dyn MemoryReader,
+    ) -> Result<Option<u64>, Error> {
+        let method = self.method(address)?;
+        if address == FrameAddress::InstructionPointer(method.start) {
+            // The method hasn't set up its frame yet.
+            let return_address = regs.lr();
+            return Ok(Some(return_address));
+        }
+        let header = Self::read_code_info_header(method.start, memory)?;
+        let caller = Self::caller_frame(
+            &header,
+            regs.sp(),
+            Self::AARCH64_LR,
+            Self::AARCH64_FP,
+            memory,
+        )?;
+        regs.set_sp(caller.sp);
+        if let Some(fp) = caller.fp {
+            regs.set_fp(fp);
+        }
+        regs.set_lr(caller.return_address);
+        Ok(Some(caller.return_address))
+    }
+
+    /// Unwind a frame in ART code on x86_64. This has the signature of a foreign
+    /// unwinder callback.
+    pub fn unwind_frame_x86_64(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsX86_64,
+        memory: &mut dyn MemoryReader,
+    ) -> Result<Option<u64>, Error> {
+        let method = self.method(address)?;
+        if address == FrameAddress::InstructionPointer(method.start) {
+            // The method hasn't set up its frame yet; the return address is on top of
+            // the stack.
+            let sp = regs.sp();
+            let return_address = memory
+                .read_u64(sp)
+                .map_err(|err| Error::CouldNotReadStack(sp, err))?;
+            regs.set_sp(sp.checked_add(8).ok_or(Error::IntegerOverflow)?);
+            return Ok(Some(return_address));
+        }
+        let header = Self::read_code_info_header(method.start, memory)?;
+        let caller = Self::caller_frame(
+            &header,
+            regs.sp(),
+            Self::X86_64_RETURN_ADDRESS,
+            Self::X86_64_RBP,
+            memory,
+        )?;
+        regs.set_sp(caller.sp);
+        if let Some(bp) = caller.fp {
+            regs.set_bp(bp);
+        }
+        Ok(Some(caller.return_address))
+    }
+
+    fn method(&self, address: FrameAddress) -> Result<Range<u64>, Error> {
+        self.method_for_address(address.address_for_lookup())
+            .ok_or(Error::UnreliableFrame(FallbackReason::NoUnwindData))
+    }
+
+    fn caller_frame(
+        header: &ArtCodeInfoHeader,
+        sp: u64,
+        return_address_register: u32,
+        fp_register: u32,
+        memory: &mut dyn MemoryReader,
+    ) -> Result<ArtCallerFrame, Error> {
+        let mut read_spilled_register = |register| -> Result<Option<u64>, Error> {
+            let Some(offset) = header.core_spill_offset(register) else {
+                return Ok(None);
+            };
+            let address = sp.checked_add(offset).ok_or(Error::IntegerOverflow)?;
+            let value = memory
+                .read_u64(address)
+                .map_err(|err| Error::CouldNotReadStack(address, err))?;
+            Ok(Some(value))
+        };
+        let return_address = read_spilled_register(return_address_register)?
+            .ok_or(Error::UnreliableFrame(FallbackReason::UnwindInfoError))?;
+        let fp = read_spilled_register(fp_register)?;
+        let sp = sp
+            .checked_add(u64::from(header.frame_size))
+            .ok_or(Error::IntegerOverflow)?;
+        Ok(ArtCallerFrame {
+            return_address,
+            sp,
+            fp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MemoryReadError;
+    use alloc::vec;
+
+    /// Encode a `CodeInfo` header like ART's `BitMemoryWriter::WriteInterleavedVarints`.
+    fn encode_header(fields: [u32; 7]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        let mut push_bits = |value: u64, bit_count: usize| {
+            bits.extend((0..bit_count).map(|i| (value >> i) & 1 == 1));
+        };
+        let byte_count = |value: u32| (32 - value.leading_zeros()).div_ceil(8) as u64;
+        for field in fields {
+            match field {
+                0..=11 => push_bits(u64::from(field), 4),
+                _ => push_bits(11 + byte_count(field), 4),
+            }
+        }
+        for field in fields {
+            if field > 11 {
+                push_bits(u64::from(field), 8 * byte_count(field) as usize);
+            }
+        }
+        bits.chunks(8)
+            .map(|byte| {
+                byte.iter()
+                    .enumerate()
+                    .fold(0, |acc, (i, &bit)| acc | (u8::from(bit) << i))
+            })
+            .collect()
+    }
+
+    struct TestMemory(Vec<(u64, Vec<u8>)>);
+
+    impl MemoryReader for TestMemory {
+        fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            let mut buf = [0; 8];
+            self.read_block(address, &mut buf)?;
+            Ok(u64::from_le_bytes(buf))
+        }
+
+        fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+            let mut buf = [0; 4];
+            self.read_block(address, &mut buf)?;
+            Ok(u32::from_le_bytes(buf))
+        }
+
+        fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+            for (start, bytes) in &self.0 {
+                let Some(offset) = address.checked_sub(*start) else {
+                    continue;
+                };
+                if let Some(data) = bytes.get(offset as usize..offset as usize + buf.len()) {
+                    buf.copy_from_slice(data);
+                    return Ok(());
+                }
+            }
+            Err(MemoryReadError::Unmapped)
+        }
+    }
+
+    #[test]
+    fn test_parse_code_info_header() {
+        let data = encode_header([0, 0x1234, 5, 0x6000_0000, 0, 3, 0x7f]);
+        assert_eq!(
+            ArtCodeInfoHeader::parse(&data),
+            Some(ArtCodeInfoHeader {
+                flags: 0,
+                code_size: 0x1234,
+                frame_size: 0x50,
+                core_spill_mask: 0x6000_0000,
+                fp_spill_mask: 0,
+                number_of_dex_registers: 3,
+            })
+        );
+        assert_eq!(ArtCodeInfoHeader::parse(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_art_method_ranges() {
+        let mut unwinder = ArtQuickFrameUnwinder::new(vec![
+            0x300..0x400,
+            0x100..0x200,
+            0x180..0x280,
+            0x500..0x500,
+            0x3c0..0x440,
+            0x600..0x700,
+        ]);
+        assert_eq!(
+            unwinder.methods,
+            vec![0x180..0x280, 0x3c0..0x440, 0x600..0x700]
+        );
+
+        // A new method replaces the methods it overlaps, and only those.
+        unwinder.add_method(0x200..0x3d0);
+        unwinder.add_method(0x700..0x710);
+        assert_eq!(
+            unwinder.methods,
+            vec![0x200..0x3d0, 0x600..0x700, 0x700..0x710]
+        );
+    }
+
+    #[test]
+    fn test_art_quick_frames() {
+        const METHOD: u64 = 0x7000_1000;
+        let mut unwinder = ArtQuickFrameUnwinder::default();
+        unwinder.add_method(METHOD..METHOD + 0x100);
+        unwinder.add_method(METHOD + 0x100..METHOD + 0x180);
+        assert_eq!(unwinder.method_count(), 2);
+        assert_eq!(
+            unwinder.method_for_address(METHOD + 0x120),
+            Some(METHOD + 0x100..METHOD + 0x180)
+        );
+
+        for is_aarch64 in [true, false] {
+            // The method spills x29 and lr on aarch64, or rbx, rbp and the return
+            // address on x86_64, at the top of its 0x40 byte frame.
+            let core_spill_mask = match is_aarch64 {
+                true => 0x6000_0000,
+                false => 0x1_0028,
+            };
+            let code_info = encode_header([0, 0x100, 4, core_spill_mask, 0, 0, 0]);
+            let method_header = 0x4000_0000u32 | 0x20;
+            // The code info is 0x20 bytes before the code, and the method header is
+            // right before the code.
+            let mut image = code_info;
+            image.resize(0x1c, 0);
+            image.extend_from_slice(&method_header.to_le_bytes());
+            let stack: Vec<u8> = [0, 0, 0, 0, 0x3333, 0, 0x1111, 0x2222]
+                .iter()
+                .flat_map(|value: &u64| value.to_le_bytes())
+                .collect();
+            let mut memory = TestMemory(vec![(METHOD - 0x20, image), (0x8000, stack)]);
+            let address = FrameAddress::from_return_address(METHOD + 0x80).unwrap();
+            if is_aarch64 {
+                let mut regs = UnwindRegsAarch64::new(0x0, 0x8000, 0x0);
+                let result = unwinder.unwind_frame_aarch64(address, &mut regs, &mut memory);
+                assert_eq!(result, Ok(Some(0x2222)));
+                assert_eq!((regs.sp(), regs.fp()), (0x8040, 0x1111));
+            } else {
+                let mut regs = UnwindRegsX86_64::new(0x0, 0x8000, 0x0);
+                let result = unwinder.unwind_frame_x86_64(address, &mut regs, &mut memory);
+                assert_eq!(result, Ok(Some(0x2222)));
+                assert_eq!((regs.sp(), regs.bp()), (0x8040, 0x1111));
+            }
+        }
+
+        let mut regs = UnwindRegsAarch64::new(0x4444, 0x8000, 0x0);
+        let mut memory = TestMemory(vec![]);
+        assert_eq!(
+            unwinder.unwind_frame_aarch64(
+                FrameAddress::from_instruction_pointer(METHOD),
+                &mut regs,
+                &mut memory
+            ),
+            Ok(Some(0x4444))
+        );
+        assert_eq!(
+            unwinder.unwind_frame_aarch64(
+                FrameAddress::from_instruction_pointer(METHOD + 0x200),
+                &mut regs,
+                &mut memory
+            ),
+            Err(Error::UnreliableFrame(FallbackReason::NoUnwindData))
+        );
+    }
+}
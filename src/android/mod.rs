@@ -0,0 +1,247 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Deref, Range};
+
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use crate::module_builder::ModuleBuilder;
+use crate::unwinder::{Module, ModuleDescriptor};
+
+mod art;
+
+pub use art::*;
+
+/// A module in the `/proc/<pid>/maps` file of an Android process.
+///
+/// Apps can load their native libraries directly from their APK, if the libraries are
+/// stored uncompressed and page-aligned. Such libraries show up in the maps file with the
+/// path of the APK and the offset of the library in it, and several libraries can be
+/// mapped from the same APK.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MappedModule {
+    /// The descriptor for [`Unwinder::sync_modules`](crate::Unwinder::sync_modules). Its
+    /// name is the path in the maps file.
+    pub descriptor: ModuleDescriptor,
+    /// The offset of the start of the ELF image in the mapped file. This is zero unless
+    /// the library is embedded in an APK.
+    pub file_offset: u64,
+}
+
+impl MappedModule {
+    /// Whether the module is a library which was loaded from an APK or another zip
+    /// file, rather than from a file of its own.
+    pub fn is_embedded(&self) -> bool {
+        self.file_offset != 0
+    }
+
+    /// Create the module from the data of the mapped file, i.e. the data of the APK for
+    /// embedded libraries. The ELF image is parsed at [`MappedModule::file_offset`], and
+    /// the well-known trampolines of bionic and ART are recognized in its symbol tables,
+    /// see [`ModuleBuilder::recognize_trampolines`].
+    ///
+    /// Embedded libraries are named `<apk path>!/<entry name>`, like in Android's
+    /// tombstones, if the zip entry can be found with [`apk_entry_name`].
+    pub fn module_from_file_data<'data, D>(&self, file_data: &'data [u8]) -> Option<Module<D>>
+    where
+        D: Deref<Target = [u8]> + From<&'data [u8]>,
+    {
+        let image = file_data.get(usize::try_from(self.file_offset).ok()?..)?;
+        let file = object::File::parse(image).ok()?;
+        // The first mapping starts with the first loadable segment.
+        let first_segment = file.segments().next()?;
+        let base_avma = self
+            .descriptor
+            .avma_range
+            .start
+            .wrapping_add(first_segment.file_range().0)
+            .wrapping_sub(first_segment.address());
+        let name = match apk_entry_name(file_data, self.file_offset) {
+            Some(entry_name) if self.is_embedded() => {
+                format!("{}!/{entry_name}", self.descriptor.name)
+            }
+            _ => self.descriptor.name.clone(),
+        };
+        let symbols: Vec<(&str, Range<u64>)> = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.size() != 0)
+            .filter_map(|symbol| {
+                let start = symbol.address();
+                Some((symbol.name().ok()?, start..start + symbol.size()))
+            })
+            .collect();
+        let module = ModuleBuilder::new(name, self.descriptor.avma_range.clone(), base_avma, file)
+            .recognize_trampolines(symbols)
+            .build();
+        Some(module)
+    }
+}
+
+/// Read `/proc/<pid>/maps` of the process `pid`, or of the current process if `pid` is
+/// `None`, and return its modules. See [`parse_proc_maps`].
+#[cfg(feature = "std")]
+pub fn read_proc_maps(pid: Option<u32>) -> std::io::Result<Vec<MappedModule>> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{pid}/maps"),
+        None => String::from("/proc/self/maps"),
+    };
+    Ok(parse_proc_maps(&std::fs::read_to_string(path)?))
+}
+
+/// Parse the contents of `/proc/<pid>/maps` of an Android process into one module per
+/// mapped ELF image with executable code.
+///
+/// Consecutive mappings belong to the same image if they map the same file, and if
+/// their file offsets advance like their addresses. Unlike on desktop Linux, this keeps
+/// the libraries which are loaded from the same APK apart, even if they're mapped next
+/// to each other.
+pub fn parse_proc_maps(maps: &str) -> Vec<MappedModule> {
+    let (mapped_files, _) = crate::address_space::parse_proc_maps(maps, true);
+    mapped_files
+        .into_iter()
+        .map(|mapped_file| MappedModule {
+            descriptor: mapped_file.descriptor,
+            file_offset: mapped_file.file_offset,
+        })
+        .collect()
+}
+
+/// The name of the file in the zip archive `zip_data`, e.g. an APK, whose data starts
+/// at `file_offset`, e.g. `lib/arm64-v8a/libfoo.so`. Only uncompressed entries are
+/// found, because compressed libraries can't be mapped from the APK. Zip64 archives
+/// aren't supported.
+pub fn apk_entry_name(zip_data: &[u8], file_offset: u64) -> Option<&str> {
+    const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+    const CENTRAL_DIRECTORY_ENTRY: u32 = 0x0201_4b50;
+    const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+    let u16_at = |offset: usize| -> Option<u16> {
+        Some(u16::from_le_bytes(
+            zip_data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            zip_data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+
+    // The end of central directory record is at least 22 bytes long, and followed by
+    // a comment of at most 64KiB.
+    let search_start = zip_data.len().saturating_sub(22 + 0xffff);
+    let eocd = (search_start..zip_data.len().saturating_sub(21))
+        .rev()
+        .find(|&offset| u32_at(offset) == Some(END_OF_CENTRAL_DIRECTORY))?;
+    let entry_count = u16_at(eocd + 10)?;
+    let mut entry = usize::try_from(u32_at(eocd + 16)?).ok()?;
+    for _ in 0..entry_count {
+        if u32_at(entry)? != CENTRAL_DIRECTORY_ENTRY {
+            return None;
+        }
+        let compression_method = u16_at(entry + 10)?;
+        let name_len = usize::from(u16_at(entry + 28)?);
+        let extra_len = usize::from(u16_at(entry + 30)?);
+        let comment_len = usize::from(u16_at(entry + 32)?);
+        let local_header = usize::try_from(u32_at(entry + 42)?).ok()?;
+        let name = zip_data.get(entry + 46..entry + 46 + name_len)?;
+        entry += 46 + name_len + extra_len + comment_len;
+        if compression_method != 0 || u32_at(local_header)? != LOCAL_FILE_HEADER {
+            continue;
+        }
+        // The extra field of the local header can differ from the one in the central
+        // directory, e.g. because of alignment padding.
+        let data_start = local_header
+            + 30
+            + usize::from(u16_at(local_header + 26)?)
+            + usize::from(u16_at(local_header + 28)?);
+        if data_start as u64 == file_offset {
+            return core::str::from_utf8(name).ok();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_parse_proc_maps() {
+        let maps = "\
+5c8a400000-5c8a402000 r--p 00000000 fd:05 812 /system/bin/app_process64
+5c8a402000-5c8a404000 r-xp 00002000 fd:05 812 /system/bin/app_process64
+7a10000000-7a10010000 r--p 00004000 fd:0e 77 /data/app/~~x==/com.example-y==/base.apk
+7a10010000-7a10030000 r-xp 00014000 fd:0e 77 /data/app/~~x==/com.example-y==/base.apk
+7a10030000-7a10040000 r--p 00101000 fd:0e 77 /data/app/~~x==/com.example-y==/base.apk
+7a10040000-7a10050000 r-xp 00111000 fd:0e 77 /data/app/~~x==/com.example-y==/base.apk
+7a10050000-7a10051000 r--p 00000000 00:00 0 [anon:dalvik-classes.dex extracted in memory]
+7a20000000-7a20001000 r-xp 00000000 00:00 0 [vdso]
+";
+        let module = |name: &str, avma_range, file_offset| MappedModule {
+            descriptor: ModuleDescriptor {
+                name: name.to_string(),
+                avma_range,
+                code_id: None,
+            },
+            file_offset,
+        };
+        let apk = "/data/app/~~x==/com.example-y==/base.apk";
+        assert_eq!(
+            parse_proc_maps(maps),
+            vec![
+                module("/system/bin/app_process64", 0x5c8a400000..0x5c8a404000, 0),
+                module(apk, 0x7a10000000..0x7a10030000, 0x4000),
+                module(apk, 0x7a10030000..0x7a10050000, 0x101000),
+                module("[vdso]", 0x7a20000000..0x7a20001000, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apk_entry_name() {
+        // A zip file with a compressed entry and an uncompressed entry.
+        let mut zip = Vec::new();
+        let mut central_directory = Vec::new();
+        let mut data_starts = Vec::new();
+        for (name, compression_method) in [("classes.dex", 8u16), ("lib/arm64-v8a/libfoo.so", 0)] {
+            let local_header = zip.len() as u32;
+            zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+            zip.extend_from_slice(&[0; 4]);
+            zip.extend_from_slice(&compression_method.to_le_bytes());
+            zip.extend_from_slice(&[0; 16]);
+            zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            // Alignment padding in the extra field.
+            zip.extend_from_slice(&3u16.to_le_bytes());
+            zip.extend_from_slice(name.as_bytes());
+            zip.extend_from_slice(&[0; 3]);
+            data_starts.push(zip.len() as u64);
+            zip.extend_from_slice(b"data");
+
+            central_directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            central_directory.extend_from_slice(&[0; 6]);
+            central_directory.extend_from_slice(&compression_method.to_le_bytes());
+            central_directory.extend_from_slice(&[0; 16]);
+            central_directory.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central_directory.extend_from_slice(&[0; 12]);
+            central_directory.extend_from_slice(&local_header.to_le_bytes());
+            central_directory.extend_from_slice(name.as_bytes());
+        }
+        let central_directory_offset = zip.len() as u32;
+        zip.extend_from_slice(&central_directory);
+        zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        zip.extend_from_slice(&[0; 6]);
+        zip.extend_from_slice(&2u16.to_le_bytes());
+        zip.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+        zip.extend_from_slice(&central_directory_offset.to_le_bytes());
+        zip.extend_from_slice(&[0; 2]);
+
+        assert_eq!(apk_entry_name(&zip, data_starts[0]), None);
+        assert_eq!(
+            apk_entry_name(&zip, data_starts[1]),
+            Some("lib/arm64-v8a/libfoo.so")
+        );
+        assert_eq!(apk_entry_name(&zip, data_starts[1] + 1), None);
+    }
+}
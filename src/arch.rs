@@ -1,6 +1,7 @@
+use crate::stack_link::StackLinkRegs;
 use crate::unwind_rule::UnwindRule;
 
 pub trait Arch {
-    type UnwindRegs;
+    type UnwindRegs: Clone + StackLinkRegs;
     type UnwindRule: UnwindRule<UnwindRegs = Self::UnwindRegs>;
 }
@@ -0,0 +1,291 @@
+use core::future::Future;
+use core::ops::Range;
+
+use crate::code_address::FrameAddress;
+use crate::error::Error;
+use crate::frame_info::FrameUnwindInfo;
+use crate::memory_reader::MemoryReadError;
+use crate::unwind_step::{MemoryRequest, UnwindStep};
+use crate::unwinder::Unwinder;
+
+/// Like [`MemoryReader`](crate::MemoryReader), but the reads return futures, e.g. because they are requests
+/// to a gdb remote stub or to a crash reporting service.
+pub trait AsyncMemoryReader {
+    /// Read the 8-byte value at `address`.
+    fn read_u64(&mut self, address: u64) -> impl Future<Output = Result<u64, MemoryReadError>>;
+
+    /// Read the 4-byte value at `address`.
+    ///
+    /// The default implementation reads 8 bytes at `address` and keeps the low four
+    /// bytes, like [`MemoryReader::read_u32`](crate::MemoryReader::read_u32).
+    fn read_u32(&mut self, address: u64) -> impl Future<Output = Result<u32, MemoryReadError>> {
+        async move { self.read_u64(address).await.map(|value| value as u32) }
+    }
+
+    /// Read the 2-byte value at `address`.
+    ///
+    /// The default implementation reads 8 bytes at `address` and keeps the low two
+    /// bytes.
+    fn read_u16(&mut self, address: u64) -> impl Future<Output = Result<u16, MemoryReadError>> {
+        async move { self.read_u64(address).await.map(|value| value as u16) }
+    }
+
+    /// Read the byte at `address`.
+    ///
+    /// The default implementation reads 8 bytes at `address` and keeps the low byte.
+    fn read_u8(&mut self, address: u64) -> impl Future<Output = Result<u8, MemoryReadError>> {
+        async move { self.read_u64(address).await.map(|value| value as u8) }
+    }
+
+    /// Whether `address` is in executable memory of the unwound process, or `None` if
+    /// the reader doesn't know the memory mappings. See
+    /// [`MemoryReader::is_executable`](crate::MemoryReader::is_executable).
+    fn is_executable(&mut self, address: u64) -> Option<bool> {
+        let _ = address;
+        None
+    }
+
+    /// The range of stack addresses which the reader has captured, if it reads from a
+    /// copy of the stack. See
+    /// [`MemoryReader::snapshot_range`](crate::MemoryReader::snapshot_range).
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        None
+    }
+}
+
+/// The implementation of [`Unwinder::unwind_frame_async`].
+pub(crate) async fn unwind_frame<U, F>(
+    unwinder: &U,
+    address: FrameAddress,
+    regs: &mut U::UnwindRegs,
+    cache: &mut U::Cache<'_>,
+    read_stack: &mut F,
+    info: &mut FrameUnwindInfo,
+) -> Result<Option<u64>, Error>
+where
+    U: Unwinder,
+    U::UnwindRegs: Clone,
+    F: AsyncMemoryReader + ?Sized,
+{
+    let mut stepper = unwinder.frame_unwind_stepper(address, regs.clone());
+    if let Some(snapshot_range) = read_stack.snapshot_range() {
+        stepper = stepper.with_snapshot_range(snapshot_range);
+    }
+    let result = loop {
+        match stepper.step(cache) {
+            UnwindStep::NeedsMemory(MemoryRequest::ReadU64(address)) => {
+                stepper.provide(read_stack.read_u64(address).await);
+            }
+            UnwindStep::NeedsMemory(MemoryRequest::ReadU32(address)) => {
+                stepper.provide(read_stack.read_u32(address).await.map(u64::from));
+            }
+            UnwindStep::NeedsMemory(MemoryRequest::ReadU16(address)) => {
+                stepper.provide(read_stack.read_u16(address).await.map(u64::from));
+            }
+            UnwindStep::NeedsMemory(MemoryRequest::ReadU8(address)) => {
+                stepper.provide(read_stack.read_u8(address).await.map(u64::from));
+            }
+            UnwindStep::Done(result) => break result,
+        }
+    };
+    *info = stepper.frame_info().clone();
+    *regs = stepper.into_regs();
+    result
+}
+
+/// An iterator for unwinding the entire stack with an [`AsyncMemoryReader`], like
+/// [`UnwindIterator`](crate::UnwindIterator). Create it with
+/// [`Unwinder::iter_frames_async`].
+pub struct AsyncUnwindIterator<'u, 'c, 's, 'r, U: Unwinder, F: AsyncMemoryReader + ?Sized> {
+    unwinder: &'u U,
+    state: AsyncUnwindIteratorState,
+    regs: U::UnwindRegs,
+    cache: &'c mut U::Cache<'s>,
+    read_stack: &'r mut F,
+    frame_info: FrameUnwindInfo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AsyncUnwindIteratorState {
+    Initial(u64),
+    Unwinding(FrameAddress),
+    Done,
+}
+
+impl<'u, 'c, 's, 'r, U, F> AsyncUnwindIterator<'u, 'c, 's, 'r, U, F>
+where
+    U: Unwinder,
+    U::UnwindRegs: Clone,
+    F: AsyncMemoryReader + ?Sized,
+{
+    /// Create a new iterator. You'd usually use [`Unwinder::iter_frames_async`] instead.
+    pub fn new(
+        unwinder: &'u U,
+        pc: u64,
+        regs: U::UnwindRegs,
+        cache: &'c mut U::Cache<'s>,
+        read_stack: &'r mut F,
+    ) -> Self {
+        Self {
+            unwinder,
+            state: AsyncUnwindIteratorState::Initial(pc),
+            regs,
+            cache,
+            read_stack,
+            frame_info: FrameUnwindInfo::default(),
+        }
+    }
+
+    /// Yield the next frame in the stack, like [`UnwindIterator::next`](crate::UnwindIterator::next).
+    pub async fn next(&mut self) -> Result<Option<FrameAddress>, Error> {
+        let address = match self.state {
+            AsyncUnwindIteratorState::Initial(pc) => {
+                let address = FrameAddress::InstructionPointer(pc);
+                self.state = AsyncUnwindIteratorState::Unwinding(address);
+                return Ok(Some(address));
+            }
+            AsyncUnwindIteratorState::Unwinding(address) => address,
+            AsyncUnwindIteratorState::Done => return Ok(None),
+        };
+        let next = unwind_frame(
+            self.unwinder,
+            address,
+            &mut self.regs,
+            self.cache,
+            self.read_stack,
+            &mut self.frame_info,
+        )
+        .await?;
+        let Some(return_address) = next else {
+            self.state = AsyncUnwindIteratorState::Done;
+            return Ok(None);
+        };
+        if self.read_stack.is_executable(return_address) == Some(false) {
+            // The registers are already the caller's, so unwinding can't continue.
+            self.state = AsyncUnwindIteratorState::Done;
+            return Err(Error::ReturnAddressNotExecutable(return_address));
+        }
+        let return_address =
+            FrameAddress::from_return_address(return_address).ok_or(Error::ReturnAddressIsNull)?;
+        self.state = AsyncUnwindIteratorState::Unwinding(return_address);
+        Ok(Some(return_address))
+    }
+
+    /// Information about how the most recent frame was unwound.
+    pub fn last_frame_info(&self) -> &FrameUnwindInfo {
+        &self.frame_info
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    /// Poll `future` until it completes. Our futures never return `Poll::Pending`
+    /// without being ready on the next poll, so this doesn't need a real waker.
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        let mut future = pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(value) = future.as_mut().poll(&mut context) {
+                return value;
+            }
+        }
+    }
+
+    /// A reader whose every read yields once before it completes, like a request over
+    /// the network, and which counts the reads.
+    struct YieldingReader<'a> {
+        stack: &'a [u64],
+        read_count: usize,
+        /// An address which the reader reports as not executable.
+        non_executable_address: Option<u64>,
+    }
+
+    impl AsyncMemoryReader for YieldingReader<'_> {
+        async fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            self.read_count += 1;
+            let mut yielded = false;
+            core::future::poll_fn(|context| match yielded {
+                true => Poll::Ready(()),
+                false => {
+                    yielded = true;
+                    context.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+            self.stack
+                .get((address / 8) as usize)
+                .copied()
+                .ok_or(MemoryReadError::Unmapped)
+        }
+
+        fn is_executable(&mut self, address: u64) -> Option<bool> {
+            self.non_executable_address
+                .map(|non_executable_address| address != non_executable_address)
+        }
+    }
+
+    #[test]
+    fn test_iter_frames_async() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        // Frame pointer chain: [0x10] = caller bp, [0x18] = return address.
+        let stack = [0, 0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let regs = UnwindRegsX86_64::new(0x1000, 0x10, 0x10);
+
+        let mut cache = CacheX86_64::<_>::new();
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        let mut expected_frames = Vec::new();
+        while let Some(frame) = iter.next().unwrap() {
+            expected_frames.push(frame);
+        }
+        assert_eq!(expected_frames.len(), 4);
+
+        let mut cache = CacheX86_64::<_>::new();
+        let mut reader = YieldingReader {
+            stack: &stack,
+            read_count: 0,
+            non_executable_address: None,
+        };
+        let frames = block_on(async {
+            let mut iter = unwinder.iter_frames_async(0x1000, regs, &mut cache, &mut reader);
+            let mut frames = Vec::new();
+            while let Some(frame) = iter.next().await.unwrap() {
+                frames.push(frame);
+            }
+            frames
+        });
+        assert_eq!(frames, expected_frames);
+        // Every read is only sent once.
+        assert_eq!(reader.read_count, 6);
+    }
+
+    #[test]
+    fn test_iter_frames_async_not_executable() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let stack = [0, 0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let regs = UnwindRegsX86_64::new(0x1000, 0x10, 0x10);
+        let mut cache = CacheX86_64::<_>::new();
+        let mut reader = YieldingReader {
+            stack: &stack,
+            read_count: 0,
+            non_executable_address: Some(0x2345),
+        };
+        block_on(async {
+            let mut iter = unwinder.iter_frames_async(0x1000, regs, &mut cache, &mut reader);
+            assert!(iter.next().await.unwrap().is_some());
+            assert!(iter.next().await.unwrap().is_some());
+            assert_eq!(
+                iter.next().await,
+                Err(Error::ReturnAddressNotExecutable(0x2345))
+            );
+            // The frame at 0x1234 isn't unwound again with the caller's registers.
+            assert_eq!(iter.next().await, Ok(None));
+        });
+    }
+}
@@ -0,0 +1,118 @@
+use core::ffi::c_void;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::stack_link::StackLinkRegs;
+use crate::unwinder::Unwinder;
+use crate::FrameAddress;
+
+/// A stack frame with the same accessors as `backtrace::Frame` from the `backtrace`
+/// crate, so that code written around that crate can switch to framehop with few changes.
+/// See [`trace`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BacktraceFrame {
+    address: FrameAddress,
+    sp: u64,
+}
+
+impl BacktraceFrame {
+    /// The instruction pointer of the first frame, and the return address of all other
+    /// frames.
+    pub fn ip(&self) -> *mut c_void {
+        self.address.address() as usize as *mut c_void
+    }
+
+    /// The stack pointer of the frame.
+    pub fn sp(&self) -> *mut c_void {
+        self.sp as usize as *mut c_void
+    }
+
+    /// Always null, because the unwinder doesn't know where functions start. Resolve
+    /// [`BacktraceFrame::ip`] with a symbolicator instead.
+    pub fn symbol_address(&self) -> *mut c_void {
+        core::ptr::null_mut()
+    }
+
+    /// The frame address, which says whether [`BacktraceFrame::ip`] is a return address.
+    pub fn frame_address(&self) -> FrameAddress {
+        self.address
+    }
+}
+
+/// Unwind the stack and call `callback` for every frame, starting with the frame of the
+/// instruction pointer `pc`, like `backtrace::trace` does. Unwinding stops when
+/// `callback` returns `false` or when the root of the stack is reached.
+///
+/// Unlike `backtrace::trace`, this returns the error which stopped unwinding early.
+pub fn trace<U, F, C>(
+    unwinder: &U,
+    pc: u64,
+    mut regs: U::UnwindRegs,
+    cache: &mut U::Cache<'_>,
+    read_stack: &mut F,
+    mut callback: C,
+) -> Result<(), Error>
+where
+    U: Unwinder,
+    U::UnwindRegs: StackLinkRegs,
+    F: MemoryReader,
+    C: FnMut(&BacktraceFrame) -> bool,
+{
+    let mut address = FrameAddress::from_instruction_pointer(pc);
+    loop {
+        let frame = BacktraceFrame {
+            address,
+            sp: regs.sp(),
+        };
+        if !callback(&frame) {
+            return Ok(());
+        }
+        match unwinder.unwind_frame(address, &mut regs, cache, read_stack)? {
+            Some(return_address) => {
+                address = FrameAddress::from_return_address(return_address)
+                    .ok_or(Error::ReturnAddressIsNull)?;
+            }
+            None => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_trace() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x0, 0x5678];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x8, 0x10);
+
+        let mut frames = Vec::new();
+        let result = trace(
+            &unwinder,
+            0x1000,
+            regs,
+            &mut cache,
+            &mut read_stack,
+            |frame| {
+                frames.push((frame.ip() as u64, frame.sp() as u64));
+                true
+            },
+        );
+        assert_eq!(result, Ok(()));
+        assert_eq!(frames, [(0x1000, 0x8), (0x1234, 0x20), (0x5678, 0x30)]);
+
+        // Returning false from the callback stops unwinding.
+        let mut frame_count = 0;
+        let result = trace(&unwinder, 0x1000, regs, &mut cache, &mut read_stack, |_| {
+            frame_count += 1;
+            false
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(frame_count, 1);
+    }
+}
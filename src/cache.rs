@@ -1,8 +1,14 @@
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 
+#[cfg(target_has_atomic = "64")]
+use crate::shared_rule_cache::SharedCacheSlot;
 use crate::{rule_cache::RuleCache, unwind_rule::UnwindRule};
 
+#[cfg(feature = "return-address-predictor")]
+use crate::return_address_predictor::ReturnAddressPredictor;
 pub use crate::rule_cache::CacheStats;
+pub use crate::unwind_stats::UnwindStats;
 
 /// A trait which lets you opt into allocation-free unwinding. The two implementations of
 /// this trait are [`MustNotAllocateDuringUnwind`] and [`MayAllocateDuringUnwind`].
@@ -17,28 +23,76 @@ pub trait AllocationPolicy {
 /// Using this means that the unwinder cache takes up more memory, because it preallocates
 /// space for DWARF CFI unwind table row evaluation and for DWARF CFI expression evaluation.
 /// And because those preallocations are of a fixed size, it is possible that this fixed
-/// size is not large enough for certain DWARF unwinding tasks.
-pub struct MustNotAllocateDuringUnwind;
+/// size is not large enough for certain DWARF unwinding tasks. Frames whose CFI needs
+/// more space are unwound with the fallback rule.
+///
+/// The sizes can be tuned for the binaries which are unwound:
+///
+///  - `REGISTER_RULES`: The number of registers which an unwind table row can have
+///    rules for.
+///  - `ROW_STACK_DEPTH`: The number of unwind table rows which can be stored at once.
+///    This includes the rows which are saved by `DW_CFA_remember_state`, and one or two
+///    rows which are always stored. FDEs which nest `DW_CFA_remember_state` deeply need
+///    a larger stack.
+///  - `EXPRESSION_STACK_SIZE`: The number of values on the stack of a DWARF expression.
+///
+/// For example, `MustNotAllocateDuringUnwind<192, 16>` allows up to 14 nested
+/// `DW_CFA_remember_state` instructions.
+pub struct MustNotAllocateDuringUnwind<
+    const REGISTER_RULES: usize = 192,
+    const ROW_STACK_DEPTH: usize = 4,
+    const EXPRESSION_STACK_SIZE: usize = 64,
+>;
 
 /// This is only used in the implementation of [MustNotAllocateDuringUnwind] and
 /// is not intended to be used by the outside world.
 #[doc(hidden)]
-pub struct StoreOnStack;
+pub struct StoreOnStack<
+    const REGISTER_RULES: usize,
+    const ROW_STACK_DEPTH: usize,
+    const EXPRESSION_STACK_SIZE: usize,
+>;
 
-impl<RO: gimli::ReaderOffset> gimli::UnwindContextStorage<RO> for StoreOnStack {
-    type Rules = [(gimli::Register, gimli::RegisterRule<RO>); 192];
-    type Stack = [gimli::UnwindTableRow<RO, Self>; 4];
+impl<
+        RO,
+        const REGISTER_RULES: usize,
+        const ROW_STACK_DEPTH: usize,
+        const EXPRESSION_STACK_SIZE: usize,
+    > gimli::UnwindContextStorage<RO>
+    for StoreOnStack<REGISTER_RULES, ROW_STACK_DEPTH, EXPRESSION_STACK_SIZE>
+where
+    RO: gimli::ReaderOffset,
+{
+    type Rules = [(gimli::Register, gimli::RegisterRule<RO>); REGISTER_RULES];
+    type Stack = [gimli::UnwindTableRow<RO, Self>; ROW_STACK_DEPTH];
 }
 
-impl<R: gimli::Reader> gimli::EvaluationStorage<R> for StoreOnStack {
-    type Stack = [gimli::Value; 64];
+impl<
+        R,
+        const REGISTER_RULES: usize,
+        const ROW_STACK_DEPTH: usize,
+        const EXPRESSION_STACK_SIZE: usize,
+    > gimli::EvaluationStorage<R>
+    for StoreOnStack<REGISTER_RULES, ROW_STACK_DEPTH, EXPRESSION_STACK_SIZE>
+where
+    R: gimli::Reader,
+{
+    type Stack = [gimli::Value; EXPRESSION_STACK_SIZE];
     type ExpressionStack = [(R, R); 4];
     type Result = [gimli::Piece<R>; 1];
 }
 
-impl AllocationPolicy for MustNotAllocateDuringUnwind {
-    type GimliUnwindContextStorage<R: gimli::ReaderOffset> = StoreOnStack;
-    type GimliEvaluationStorage<R: gimli::Reader> = StoreOnStack;
+impl<
+        const REGISTER_RULES: usize,
+        const ROW_STACK_DEPTH: usize,
+        const EXPRESSION_STACK_SIZE: usize,
+    > AllocationPolicy
+    for MustNotAllocateDuringUnwind<REGISTER_RULES, ROW_STACK_DEPTH, EXPRESSION_STACK_SIZE>
+{
+    type GimliUnwindContextStorage<R: gimli::ReaderOffset> =
+        StoreOnStack<REGISTER_RULES, ROW_STACK_DEPTH, EXPRESSION_STACK_SIZE>;
+    type GimliEvaluationStorage<R: gimli::Reader> =
+        StoreOnStack<REGISTER_RULES, ROW_STACK_DEPTH, EXPRESSION_STACK_SIZE>;
 }
 
 /// Allow allocation during unwinding. This is one of the two [`AllocationPolicy`]
@@ -47,9 +101,27 @@ impl AllocationPolicy for MustNotAllocateDuringUnwind {
 /// This is the preferred policy because it saves memory and places no limitations on
 /// DWARF CFI evaluation.
 pub struct MayAllocateDuringUnwind;
+
+/// This is only used in the implementation of [MayAllocateDuringUnwind] and is not
+/// intended to be used by the outside world. Unlike [`gimli::StoreOnHeap`], the stack of
+/// unwind table rows grows as needed, so `DW_CFA_remember_state` can be nested deeply.
+#[doc(hidden)]
+pub struct StoreOnHeap;
+
+impl<RO: gimli::ReaderOffset> gimli::UnwindContextStorage<RO> for StoreOnHeap {
+    type Rules = [(gimli::Register, gimli::RegisterRule<RO>); 192];
+    type Stack = Vec<gimli::UnwindTableRow<RO, Self>>;
+}
+
+impl<R: gimli::Reader> gimli::EvaluationStorage<R> for StoreOnHeap {
+    type Stack = Vec<gimli::Value>;
+    type ExpressionStack = Vec<(R, R)>;
+    type Result = Vec<gimli::Piece<R>>;
+}
+
 impl AllocationPolicy for MayAllocateDuringUnwind {
-    type GimliUnwindContextStorage<R: gimli::ReaderOffset> = gimli::StoreOnHeap;
-    type GimliEvaluationStorage<R: gimli::Reader> = gimli::StoreOnHeap;
+    type GimliUnwindContextStorage<R: gimli::ReaderOffset> = StoreOnHeap;
+    type GimliEvaluationStorage<R: gimli::Reader> = StoreOnHeap;
 }
 
 /// The unwinder cache. This needs to be created upfront before unwinding. During
@@ -58,24 +130,90 @@ impl AllocationPolicy for MayAllocateDuringUnwind {
 /// A single unwinder cache can be used with multiple unwinders alternatingly.
 ///
 /// The cache stores unwind rules for addresses it has seen before, and it stores the
-/// unwind context which gimli needs for DWARF CFI evaluation.
-pub struct Cache<R: UnwindRule, P: AllocationPolicy = MayAllocateDuringUnwind> {
+/// unwind context which gimli needs for DWARF CFI evaluation. It also keeps
+/// [`UnwindStats`] about the unwinds it was used for.
+///
+/// `'s` is the lifetime of the slots of a cache created with [`Cache::new_shared`].
+pub struct Cache<'s, R: UnwindRule, P: AllocationPolicy = MayAllocateDuringUnwind> {
     pub(crate) gimli_unwind_context:
         Box<gimli::UnwindContext<usize, P::GimliUnwindContextStorage<usize>>>,
-    pub(crate) rule_cache: RuleCache<R>,
+    pub(crate) rule_cache: RuleCache<'s, R>,
+    pub(crate) unwind_stats: UnwindStats,
+    /// The number of frames which were unwound in frame-pointer-only mode. Every n-th
+    /// of them is verified, see `frame_pointer_verification_interval`.
+    pub(crate) frame_pointer_only_frame_count: u64,
+    #[cfg(feature = "return-address-predictor")]
+    pub(crate) predictor: ReturnAddressPredictor<R>,
 }
 
-impl<R: UnwindRule, P: AllocationPolicy> Cache<R, P> {
+impl<'s, R: UnwindRule, P: AllocationPolicy> Cache<'s, R, P> {
     pub fn new() -> Self {
         Self {
             gimli_unwind_context: Box::new(gimli::UnwindContext::new_in()),
             rule_cache: RuleCache::new(),
+            unwind_stats: UnwindStats::new(),
+            frame_pointer_only_frame_count: 0,
+            #[cfg(feature = "return-address-predictor")]
+            predictor: ReturnAddressPredictor::new(),
+        }
+    }
+
+    /// Create a cache whose unwind rules are stored in `slots` instead of on the heap.
+    /// The slots can be in a shared memory region, so that processes which unwind the
+    /// same modules, e.g. a sampler in the target process and an analyzer process, can
+    /// reuse each other's rules. Slots are accessed without locks.
+    ///
+    /// Rules are keyed by address and `layout_id`. All users of the slots have to use
+    /// the same layout ID for the same set of modules, and a new layout ID whenever the
+    /// modules change, see [`Cache::set_layout_id`]. With empty `slots`, every lookup is
+    /// a miss.
+    ///
+    /// This is only available on targets with 64-bit atomics.
+    #[cfg(target_has_atomic = "64")]
+    pub fn new_shared(slots: &'s [SharedCacheSlot], layout_id: u64) -> Self {
+        Self {
+            gimli_unwind_context: Box::new(gimli::UnwindContext::new_in()),
+            rule_cache: RuleCache::new_shared(slots, layout_id),
+            unwind_stats: UnwindStats::new(),
+            frame_pointer_only_frame_count: 0,
+            #[cfg(feature = "return-address-predictor")]
+            predictor: ReturnAddressPredictor::new(),
         }
     }
+
+    /// Change the layout ID of a cache created with [`Cache::new_shared`], e.g. after
+    /// the modules changed. Does nothing for other caches.
+    pub fn set_layout_id(&mut self, layout_id: u64) {
+        self.rule_cache.set_layout_id(layout_id);
+    }
+
+    /// The number of bytes held by the cache. This doesn't include the memory which gimli
+    /// allocates on the heap while evaluating DWARF CFI with [`MayAllocateDuringUnwind`].
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + core::mem::size_of_val(&*self.gimli_unwind_context)
+            + self.rule_cache.memory_usage()
+            + self.predictor_memory_usage()
+    }
+
+    #[cfg(feature = "return-address-predictor")]
+    fn predictor_memory_usage(&self) -> usize {
+        self.predictor.memory_usage()
+    }
+
+    #[cfg(not(feature = "return-address-predictor"))]
+    fn predictor_memory_usage(&self) -> usize {
+        0
+    }
 }
 
-impl<R: UnwindRule, P: AllocationPolicy> Default for Cache<R, P> {
+impl<R: UnwindRule, P: AllocationPolicy> Default for Cache<'_, R, P> {
     fn default() -> Self {
         Self::new()
     }
 }
+
+/// The cache type of an architecture's unwinder, which wraps a [`Cache`].
+pub(crate) trait ArchCache<'s, R: UnwindRule, P: AllocationPolicy> {
+    fn cache_mut(&mut self) -> &mut Cache<'s, R, P>;
+}
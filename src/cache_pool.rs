@@ -0,0 +1,132 @@
+use alloc::boxed::Box;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+
+use crate::rule_cache::CacheStats;
+use crate::unwinder::Unwinder;
+
+/// A fixed number of caches for a fixed number of worker threads, e.g. in a batch
+/// processor which unwinds samples on a thread pool.
+///
+/// A worker checks out a cache with [`CachePool::checkout`] and returns it by dropping
+/// the guard. Caches are only created when they're checked out for the first time, and
+/// they're kept until the pool is dropped, so their rules and statistics survive between
+/// checkouts. Unlike [`SyncCache`](crate::SyncCache), which creates a new cache whenever
+/// all caches are in use, the pool never holds more caches than it has slots, and
+/// [`CachePool::checkout`] waits if all of them are checked out.
+pub struct CachePool<C> {
+    slots: Box<[Mutex<Option<C>>]>,
+    /// The slot at which the next checkout starts looking for a free cache, so that
+    /// workers don't all contend for the first slot.
+    next_slot: AtomicUsize,
+}
+
+impl<C> CachePool<C> {
+    /// Create a pool with `size` caches, or with one cache if `size` is zero.
+    pub fn new(size: usize) -> Self {
+        Self {
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+            next_slot: AtomicUsize::new(0),
+        }
+    }
+
+    /// Create a pool with one cache per thread which the system can run in parallel.
+    pub fn for_available_parallelism() -> Self {
+        Self::new(std::thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// The number of caches in the pool, including the ones which weren't created yet.
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check out a cache, creating it if it's the first checkout of its slot. If all
+    /// caches are checked out, this waits until one is returned.
+    pub fn checkout(&self) -> CachePoolGuard<'_, C>
+    where
+        C: Default,
+    {
+        let start = self.next_slot.fetch_add(1, Ordering::Relaxed);
+        let slot_count = self.slots.len();
+        let mut guard = (0..slot_count)
+            .find_map(|i| self.slots[(start + i) % slot_count].try_lock().ok())
+            .unwrap_or_else(|| lock(&self.slots[start % slot_count]));
+        guard.get_or_insert_with(C::default);
+        CachePoolGuard { guard }
+    }
+
+    /// The merged usage statistics of all caches which were created, see
+    /// [`Unwinder::cache_stats`]. This waits for checked-out caches to be returned.
+    pub fn cache_stats<'s, U>(&self, unwinder: &U) -> CacheStats
+    where
+        U: Unwinder<Cache<'s> = C>,
+    {
+        let mut stats = CacheStats::new();
+        for slot in self.slots.iter() {
+            if let Some(cache) = &*lock(slot) {
+                stats.merge(&unwinder.cache_stats(cache));
+            }
+        }
+        stats
+    }
+}
+
+fn lock<C>(slot: &Mutex<Option<C>>) -> MutexGuard<'_, Option<C>> {
+    // A cache is still usable if a worker panicked while it was checked out.
+    slot.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A cache which was checked out of a [`CachePool`]. Dropping it returns the cache to
+/// the pool.
+pub struct CachePoolGuard<'a, C> {
+    /// Always `Some`; the cache is created before the guard.
+    guard: MutexGuard<'a, Option<C>>,
+}
+
+impl<C> Deref for CachePoolGuard<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<C> DerefMut for CachePoolGuard<'_, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::FrameAddress;
+
+    #[test]
+    fn test_cache_pool() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let pool = CachePool::<CacheX86_64>::new(2);
+        assert_eq!(pool.size(), 2);
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let mut read_stack =
+                        |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+                    let mut regs = UnwindRegsX86_64::new(0x1800, 0x0, 0x10);
+                    let res = unwinder.unwind_frame(
+                        FrameAddress::from_instruction_pointer(0x1800),
+                        &mut regs,
+                        &mut pool.checkout(),
+                        &mut read_stack,
+                    );
+                    assert_eq!(res, Ok(Some(0x1234)));
+                });
+            }
+        });
+        let stats = pool.cache_stats(&unwinder);
+        assert_eq!(stats.total(), 4);
+    }
+}
@@ -0,0 +1,360 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Deref, Range};
+
+use crate::dwarf::DwarfCfiIndex;
+use crate::module_id::{CodeId, DebugId};
+use crate::unwinder::{Module, ModuleSectionInfo, Unwinder};
+
+/// The magic bytes and the format version at the start of a serialized checkpoint.
+const MAGIC: &[u8; 4] = b"FHCP";
+const VERSION: u32 = 1;
+
+/// An error from [`UnwinderCheckpoint::from_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointError {
+    /// The data doesn't start with the checkpoint magic bytes.
+    NotACheckpoint,
+    /// The checkpoint was written in a format version which this version of framehop
+    /// doesn't understand.
+    UnsupportedVersion(u32),
+    /// The data is truncated or malformed.
+    InvalidData,
+}
+
+impl core::fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::NotACheckpoint => write!(f, "The data is not an unwinder checkpoint"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported checkpoint format version {version}")
+            }
+            Self::InvalidData => write!(f, "The checkpoint data is truncated or malformed"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CheckpointError {}
+
+/// What an [`UnwinderCheckpoint`] knows about a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleCheckpoint {
+    /// See [`Module::name`].
+    pub name: String,
+    /// See [`Module::avma_range`].
+    pub avma_range: Range<u64>,
+    /// See [`Module::base_avma`].
+    pub base_avma: u64,
+    /// See [`Module::base_svma`].
+    pub base_svma: u64,
+    /// See [`Module::code_id`]. Used to find the binary again when the checkpoint is
+    /// restored, and to check that it hasn't changed.
+    pub code_id: Option<CodeId>,
+    /// See [`Module::frame_pointers_guaranteed`].
+    pub frame_pointers_guaranteed: bool,
+    /// The index which was built for the module's `.eh_frame` or `.debug_frame`, if the
+    /// module uses one and its unwind information was loaded.
+    pub dwarf_cfi_index: Option<DwarfCfiIndex>,
+}
+
+impl ModuleCheckpoint {
+    /// Record the state of `module`.
+    pub fn new<D: Deref<Target = [u8]>>(module: &Module<D>) -> Self {
+        Self {
+            name: module.name().into(),
+            avma_range: module.avma_range(),
+            base_avma: module.base_avma(),
+            base_svma: module.base_svma(),
+            code_id: module.code_id().cloned(),
+            frame_pointers_guaranteed: module.frame_pointers_guaranteed(),
+            dwarf_cfi_index: module.dwarf_cfi_index().cloned(),
+        }
+    }
+
+    /// Create the module again, with the section info of its binary. The saved DWARF
+    /// CFI index is used instead of building a new one only if `section_info` has the
+    /// same code ID as the checkpoint. If either code ID is missing, there is no way to
+    /// tell whether the binary has changed, so the index is built again.
+    pub fn restore<D, S>(&self, mut section_info: S) -> Module<D>
+    where
+        D: Deref<Target = [u8]>,
+        S: ModuleSectionInfo<D>,
+    {
+        let code_id = section_info.code_id();
+        let dwarf_cfi_index = match (&code_id, &self.code_id) {
+            (Some(current), Some(saved)) if current == saved => self.dwarf_cfi_index.clone(),
+            _ => None,
+        };
+        let section_info = RestoredSectionInfo {
+            inner: section_info,
+            code_id,
+            dwarf_cfi_index,
+        };
+        let mut module = Module::new(
+            self.name.clone(),
+            self.avma_range.clone(),
+            self.base_avma,
+            section_info,
+        );
+        module.set_frame_pointers_guaranteed(self.frame_pointers_guaranteed);
+        module
+    }
+}
+
+/// The modules of an unwinder, including the indexes which were built for them, in a
+/// form that can be saved to disk. This lets a long-running analysis service restart
+/// without building the indexes of all its binaries again.
+///
+/// The checkpoint doesn't contain the unwind data itself, only the module metadata and
+/// the indexes. When the checkpoint is restored, the caller loads the unwind sections
+/// of every module again, e.g. by mapping the binary with the saved code ID. Unwinder
+/// settings, unwind hints and trampolines are not saved, and have to be set up again.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnwinderCheckpoint {
+    /// The modules, sorted by address.
+    pub modules: Vec<ModuleCheckpoint>,
+}
+
+impl UnwinderCheckpoint {
+    /// Record the modules of `unwinder`. Lazily loaded modules which haven't been used
+    /// yet are saved without an index.
+    pub fn new<D, U>(unwinder: &U) -> Self
+    where
+        D: Deref<Target = [u8]>,
+        U: Unwinder<Module = Module<D>>,
+    {
+        Self {
+            modules: unwinder
+                .modules()
+                .iter()
+                .map(ModuleCheckpoint::new)
+                .collect(),
+        }
+    }
+
+    /// Replace the modules of `unwinder` with the modules of the checkpoint. `load` is
+    /// called for every module and returns the section info of its binary, or `None` to
+    /// skip the module, e.g. if the binary can't be found anymore.
+    pub fn restore<D, U, S, L>(&self, unwinder: &mut U, mut load: L)
+    where
+        D: Deref<Target = [u8]>,
+        U: Unwinder<Module = Module<D>>,
+        S: ModuleSectionInfo<D>,
+        L: FnMut(&ModuleCheckpoint) -> Option<S>,
+    {
+        let modules = self
+            .modules
+            .iter()
+            .filter_map(|module| Some(module.restore(load(module)?)))
+            .collect();
+        unwinder.set_modules(modules);
+    }
+
+    /// Serialize the checkpoint. The format is private to framehop and versioned, so
+    /// checkpoints can only be restored by versions of framehop which understand them.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut w = Writer(Vec::new());
+        w.0.extend_from_slice(MAGIC);
+        w.u32(VERSION);
+        w.u32(self.modules.len() as u32);
+        for module in &self.modules {
+            w.bytes(module.name.as_bytes());
+            w.u64(module.avma_range.start);
+            w.u64(module.avma_range.end);
+            w.u64(module.base_avma);
+            w.u64(module.base_svma);
+            w.0.push(u8::from(module.frame_pointers_guaranteed));
+            match &module.code_id {
+                None => w.0.push(0),
+                Some(CodeId::ElfBuildId(build_id)) => {
+                    w.0.push(1);
+                    w.bytes(build_id);
+                }
+                Some(CodeId::MachoUuid(uuid)) => {
+                    w.0.push(2);
+                    w.0.extend_from_slice(uuid);
+                }
+                Some(CodeId::PeTimestampAndImageSize {
+                    timestamp,
+                    image_size,
+                }) => {
+                    w.0.push(3);
+                    w.u32(*timestamp);
+                    w.u32(*image_size);
+                }
+            }
+            match &module.dwarf_cfi_index {
+                None => w.0.push(0),
+                Some(index) => {
+                    w.0.push(1);
+                    w.bytes(&index.to_bytes());
+                }
+            }
+        }
+        w.0
+    }
+
+    /// Deserialize a checkpoint which was serialized with [`UnwinderCheckpoint::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, CheckpointError> {
+        let mut r = Reader(bytes);
+        if r.take(4).ok() != Some(&MAGIC[..]) {
+            return Err(CheckpointError::NotACheckpoint);
+        }
+        let version = r.u32()?;
+        if version != VERSION {
+            return Err(CheckpointError::UnsupportedVersion(version));
+        }
+        let module_count = r.u32()?;
+        let mut modules = Vec::new();
+        for _ in 0..module_count {
+            let name =
+                String::from_utf8(r.bytes()?.to_vec()).map_err(|_| CheckpointError::InvalidData)?;
+            let avma_range = r.u64()?..r.u64()?;
+            let base_avma = r.u64()?;
+            let base_svma = r.u64()?;
+            let frame_pointers_guaranteed = r.u8()? != 0;
+            let code_id = match r.u8()? {
+                0 => None,
+                1 => Some(CodeId::ElfBuildId(r.bytes()?.to_vec())),
+                2 => Some(CodeId::MachoUuid(r.take(16)?.try_into().unwrap())),
+                3 => Some(CodeId::PeTimestampAndImageSize {
+                    timestamp: r.u32()?,
+                    image_size: r.u32()?,
+                }),
+                _ => return Err(CheckpointError::InvalidData),
+            };
+            let dwarf_cfi_index = match r.u8()? {
+                0 => None,
+                1 => Some(
+                    DwarfCfiIndex::from_bytes(r.bytes()?)
+                        .map_err(|_| CheckpointError::InvalidData)?,
+                ),
+                _ => return Err(CheckpointError::InvalidData),
+            };
+            modules.push(ModuleCheckpoint {
+                name,
+                avma_range,
+                base_avma,
+                base_svma,
+                code_id,
+                frame_pointers_guaranteed,
+                dwarf_cfi_index,
+            });
+        }
+        if !r.0.is_empty() {
+            return Err(CheckpointError::InvalidData);
+        }
+        Ok(Self { modules })
+    }
+}
+
+/// The section info of a restored module: the caller's section info, plus the saved
+/// index.
+struct RestoredSectionInfo<S> {
+    inner: S,
+    code_id: Option<CodeId>,
+    dwarf_cfi_index: Option<DwarfCfiIndex>,
+}
+
+impl<D, S: ModuleSectionInfo<D>> ModuleSectionInfo<D> for RestoredSectionInfo<S> {
+    fn base_svma(&self) -> u64 {
+        self.inner.base_svma()
+    }
+
+    fn section_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        self.inner.section_svma_range(name)
+    }
+
+    fn section_data(&mut self, name: &[u8]) -> Option<D> {
+        self.inner.section_data(name)
+    }
+
+    fn section_avma(&mut self, name: &[u8]) -> Option<u64> {
+        self.inner.section_avma(name)
+    }
+
+    fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        self.inner.segment_svma_range(name)
+    }
+
+    fn segment_data(&mut self, name: &[u8]) -> Option<D> {
+        self.inner.segment_data(name)
+    }
+
+    fn instruction_analysis_for_dwarf_cfi_gaps(&self) -> bool {
+        self.inner.instruction_analysis_for_dwarf_cfi_gaps()
+    }
+
+    fn synthesize_rules_from_prologues(&self) -> bool {
+        self.inner.synthesize_rules_from_prologues()
+    }
+
+    fn function_starts(&mut self) -> Option<Vec<u64>> {
+        self.inner.function_starts()
+    }
+
+    fn signs_return_addresses(&self) -> Option<bool> {
+        self.inner.signs_return_addresses()
+    }
+
+    fn code_id(&mut self) -> Option<CodeId> {
+        self.code_id.clone()
+    }
+
+    fn debug_id(&mut self) -> Option<DebugId> {
+        self.inner.debug_id()
+    }
+
+    fn dwarf_cfi_index(&mut self) -> Option<DwarfCfiIndex> {
+        self.dwarf_cfi_index.take()
+    }
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    /// Write `bytes` with a `u32` length prefix.
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CheckpointError> {
+        if self.0.len() < len {
+            return Err(CheckpointError::InvalidData);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, CheckpointError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, CheckpointError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, CheckpointError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], CheckpointError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
@@ -1,5 +1,7 @@
 use core::num::NonZeroU64;
 
+use crate::module_id::CodeId;
+
 /// An absolute code address for a stack frame. Can either be taken directly from the
 /// instruction pointer ("program counter"), or from a return address.
 ///
@@ -72,4 +74,107 @@ impl FrameAddress {
             FrameAddress::ReturnAddress(_) => true,
         }
     }
+
+    /// The address (AVMA) that should be used to look up the function name for this
+    /// frame, e.g. in a symbol table.
+    ///
+    /// This is the same as [`FrameAddress::address_for_lookup`]: return addresses are
+    /// adjusted by one byte so that they point inside the call instruction, and
+    /// instruction pointers are used as is. One byte is enough on every architecture,
+    /// because a symbol covers all bytes of its instructions.
+    pub fn address_for_symbolication(self) -> u64 {
+        self.address_for_lookup()
+    }
+
+    /// The address (AVMA) that should be used to look up the source location and the
+    /// inlined frames for this frame, e.g. in a DWARF line table.
+    ///
+    /// For return addresses, this is the start of the call instruction if `arch` has
+    /// fixed-size instructions, i.e. the return address minus four bytes on aarch64.
+    /// On x86_64, instructions have a variable length, so the call instruction can't be
+    /// found from the return address, and the return address minus one byte is used
+    /// instead. Instruction pointers are used as is.
+    pub fn address_for_line_lookup(self, arch: CodeArch) -> u64 {
+        match (self, arch) {
+            (FrameAddress::InstructionPointer(address), _) => address,
+            (FrameAddress::ReturnAddress(address), CodeArch::Aarch64) => {
+                u64::from(address).saturating_sub(4)
+            }
+            (FrameAddress::ReturnAddress(address), CodeArch::X86_64) => u64::from(address) - 1,
+        }
+    }
+}
+
+/// The CPU architecture of a [`FrameAddress`], for the address adjustments which depend
+/// on the instruction encoding. See [`FrameAddress::address_for_line_lookup`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CodeArch {
+    /// x86_64, with variable-length instructions.
+    X86_64,
+    /// Aarch64, with four-byte instructions.
+    Aarch64,
+}
+
+/// A stack frame, identified by its module and its address relative to the module. This
+/// doesn't depend on where the module was loaded, so the same code has the same
+/// relative frame in all processes. See [`UnwindIterator::next_relative`](crate::UnwindIterator::next_relative).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelativeFrame<'a> {
+    /// The frame is in a known module.
+    Module {
+        /// The identifier of the module, e.g. the ELF build ID, if known.
+        code_id: Option<&'a CodeId>,
+        /// The address relative to the module's base address, i.e. the AVMA minus the
+        /// module's `base_avma`. This is the relative address which symbol files use.
+        relative_address: u32,
+        /// Whether the address is a return address. Symbolicators should look up
+        /// `relative_address - 1` for return addresses.
+        is_return_address: bool,
+    },
+    /// The frame is outside of all known modules, e.g. in JIT-compiled code.
+    Unknown(FrameAddress),
+}
+
+/// How the unwinder looks up the unwind information for a
+/// [`FrameAddress::InstructionPointer`]. Return addresses are always looked up at the
+/// address minus one, see [`FrameAddress::address_for_lookup`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum InstructionPointerAdjustment {
+    /// Look up the instruction pointer as is. This is correct if the instruction pointer
+    /// is the address of the next instruction to execute, e.g. if it comes from the
+    /// register context of an interrupted thread.
+    #[default]
+    None,
+    /// Look up the instruction pointer minus one, like a return address. Use this if
+    /// the sampler reports a return address as the instruction pointer of the first
+    /// frame, e.g. because it samples in a callee which it doesn't report. Otherwise, if
+    /// the call is the last instruction of a function, the address points to the start
+    /// of the next function, and its unwind information is used by mistake.
+    SubtractOne,
+}
+
+impl InstructionPointerAdjustment {
+    /// The address (AVMA) that should be used for looking up `address`.
+    pub(crate) fn lookup_address(self, address: FrameAddress) -> u64 {
+        match (self, address) {
+            (Self::SubtractOne, FrameAddress::InstructionPointer(ip)) => ip.wrapping_sub(1),
+            _ => address.address_for_lookup(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_adjusted_addresses() {
+        let ip = FrameAddress::from_instruction_pointer(0x1000);
+        let ra = FrameAddress::from_return_address(0x1000).unwrap();
+        assert_eq!(ip.address_for_symbolication(), 0x1000);
+        assert_eq!(ra.address_for_symbolication(), 0xfff);
+        assert_eq!(ip.address_for_line_lookup(CodeArch::Aarch64), 0x1000);
+        assert_eq!(ra.address_for_line_lookup(CodeArch::Aarch64), 0xffc);
+        assert_eq!(ra.address_for_line_lookup(CodeArch::X86_64), 0xfff);
+    }
 }
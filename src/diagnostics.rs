@@ -0,0 +1,114 @@
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use crate::error::UnwinderError;
+use crate::FrameAddress;
+
+/// An event that the unwinder reports to the diagnostics callback.
+///
+/// Framehop never prints anything. Instead, unusual situations which don't cause
+/// unwinding to fail, but which might explain bad stacks, are reported as
+/// diagnostics. You can receive them by setting a callback on the unwinder, e.g. with
+/// [`UnwinderX86_64::set_diagnostics_callback`](crate::x86_64::UnwinderX86_64::set_diagnostics_callback).
+/// If the `log` feature is enabled, diagnostics are also emitted as `log` records.
+#[derive(Debug, Clone)]
+pub enum Diagnostic<'a> {
+    /// A module was added whose address range overlapped with the address ranges of
+    /// existing modules. The existing modules were removed.
+    OverlappingModulesReplaced {
+        /// The name of the newly-added module.
+        module_name: &'a str,
+        /// The address range of the newly-added module.
+        avma_range: Range<u64>,
+        /// The number of modules which were removed.
+        removed_module_count: usize,
+    },
+    /// The unwind information of a module could not be used to unwind a frame, so
+    /// the fallback rule was used instead.
+    UsedFallbackRuleAfterError {
+        /// The name of the module containing the address.
+        module_name: &'a str,
+        /// The address of the frame which was unwound.
+        address: FrameAddress,
+        /// The error which was encountered.
+        error: UnwinderError,
+    },
+    /// A frame's address isn't in any module, so the fallback rule was used. This often
+    /// means that a module wasn't added to the unwinder.
+    UsedFallbackRuleOutsideModules {
+        /// The address of the frame which was unwound.
+        address: FrameAddress,
+    },
+    /// The modules hold more bytes than the memory budget, even after the optional
+    /// data which could be dropped was dropped. Unwind sections and indexes are never
+    /// dropped, and neither is data which a module shares with its clones.
+    MemoryBudgetExceeded {
+        /// The number of bytes which the modules hold.
+        memory_usage: usize,
+        /// The memory budget.
+        budget: usize,
+    },
+}
+
+impl core::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::OverlappingModulesReplaced {
+                module_name,
+                avma_range,
+                removed_module_count,
+            } => write!(
+                f,
+                "Module {module_name} at 0x{:x}..0x{:x} replaced {removed_module_count} overlapping module(s)",
+                avma_range.start, avma_range.end
+            ),
+            Self::UsedFallbackRuleAfterError {
+                module_name,
+                address,
+                error,
+            } => write!(
+                f,
+                "Using fallback rule for 0x{:x} in module {module_name}: {error}",
+                address.address()
+            ),
+            Self::UsedFallbackRuleOutsideModules { address } => write!(
+                f,
+                "Using fallback rule for 0x{:x}, which is not in any module",
+                address.address()
+            ),
+            Self::MemoryBudgetExceeded {
+                memory_usage,
+                budget,
+            } => write!(
+                f,
+                "Modules hold {memory_usage} bytes, more than the memory budget of {budget} bytes"
+            ),
+        }
+    }
+}
+
+/// The type of the callback which receives diagnostics.
+pub type DiagnosticsCallback = dyn Fn(&Diagnostic) + Send + Sync;
+
+/// Holds the optional diagnostics callback of an unwinder.
+#[derive(Clone, Default)]
+pub struct DiagnosticsSink(Option<Arc<DiagnosticsCallback>>);
+
+impl DiagnosticsSink {
+    pub fn set_callback(&mut self, callback: Option<Arc<DiagnosticsCallback>>) {
+        self.0 = callback;
+    }
+
+    pub fn emit(&self, diagnostic: Diagnostic) {
+        #[cfg(feature = "log")]
+        match &diagnostic {
+            Diagnostic::OverlappingModulesReplaced { .. }
+            | Diagnostic::MemoryBudgetExceeded { .. } => log::warn!("{diagnostic}"),
+            Diagnostic::UsedFallbackRuleAfterError { .. }
+            | Diagnostic::UsedFallbackRuleOutsideModules { .. } => log::debug!("{diagnostic}"),
+        }
+        if let Some(callback) = &self.0 {
+            callback(&diagnostic);
+        }
+    }
+}
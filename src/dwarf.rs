@@ -1,16 +1,27 @@
+use core::cmp::Reverse;
 use core::marker::PhantomData;
+use core::ops::Range;
 
+use alloc::collections::BinaryHeap;
 use alloc::vec::Vec;
 use gimli::{
-    CfaRule, CieOrFde, DebugFrame, EhFrame, EhFrameHdr, Encoding, EndianSlice, Evaluation,
-    EvaluationResult, EvaluationStorage, Expression, LittleEndian, Location, ParsedEhFrameHdr,
-    Reader, ReaderOffset, Register, RegisterRule, UnwindContext, UnwindContextStorage,
-    UnwindOffset, UnwindSection, UnwindTableRow, Value,
+    CfaRule, CommonInformationEntry, DebugFrame, EhFrame, EhFrameHdr, EhHdrTableIter, Encoding,
+    EndianSlice, Evaluation, EvaluationResult, EvaluationStorage, Expression, Format,
+    FrameDescriptionEntry, LittleEndian, Location, ParsedEhFrameHdr, Reader, ReaderOffset,
+    Register, RegisterRule, Section, SectionId, UnwindContext, UnwindContextStorage, UnwindOffset,
+    UnwindSection, UnwindTableRow, Value, Vendor,
 };
 
 pub(crate) use gimli::BaseAddresses;
 
-use crate::{arch::Arch, unwind_result::UnwindResult, ModuleSectionInfo};
+use crate::add_signed::checked_add_signed;
+use crate::cache::StoreOnHeap;
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::trace::{trace_event, Tracer};
+use crate::unwind_regs::UnwindRegs;
+use crate::unwind_rule::UnwindRule;
+use crate::unwind_step::{ReadRequest, Resumable, Step, SyncReads};
+use crate::{arch::Arch, unwind_result::UnwindResult, Error, FrameAddress, ModuleSectionInfo};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DwarfUnwinderError {
@@ -53,6 +64,48 @@ impl std::error::Error for DwarfUnwinderError {
     }
 }
 
+/// The error type of the functions which unwind a single frame with an `.eh_frame`
+/// section, e.g. [`x86_64::unwind_frame_with_eh_frame`](crate::x86_64::unwind_frame_with_eh_frame).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SingleFrameUnwindError {
+    /// No FDE covers the address, or its CFI couldn't be evaluated.
+    Dwarf(DwarfUnwinderError),
+    /// The CFI was evaluated, but unwinding with it failed, e.g. because the stack
+    /// couldn't be read.
+    Unwind(Error),
+}
+
+impl core::fmt::Display for SingleFrameUnwindError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Dwarf(err) => write!(f, "DWARF unwinding failed: {err}"),
+            Self::Unwind(err) => write!(f, "Unwinding failed: {err}"),
+        }
+    }
+}
+
+impl From<DwarfUnwinderError> for SingleFrameUnwindError {
+    fn from(e: DwarfUnwinderError) -> Self {
+        Self::Dwarf(e)
+    }
+}
+
+impl From<Error> for SingleFrameUnwindError {
+    fn from(e: Error) -> Self {
+        Self::Unwind(e)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SingleFrameUnwindError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Dwarf(e) => Some(e),
+            Self::Unwind(e) => Some(e),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum ConversionError {
     CfaIsExpression,
@@ -61,7 +114,6 @@ pub enum ConversionError {
     ReturnAddressRuleWasWeird,
     SpOffsetDoesNotFit,
     RegisterNotStoredRelativeToCfa,
-    RestoringFpButNotLr,
     LrStorageOffsetDoesNotFit,
     FpStorageOffsetDoesNotFit,
     SpOffsetFromFpDoesNotFit,
@@ -72,16 +124,24 @@ pub enum ConversionError {
 }
 
 pub trait DwarfUnwinding: Arch {
-    fn unwind_frame<F, R, UCS, ES>(
+    /// The vendor extensions which are used when parsing call frame instructions.
+    const VENDOR: Vendor = Vendor::Default;
+
+    /// The evaluation of a CFI row which couldn't be translated into an unwind rule. It
+    /// sets the caller's registers in its last step and results in the return address.
+    type DwarfEvaluation<R: Reader, S: EvaluationStorage<R>>: Resumable<
+        Self::UnwindRegs,
+        Output = Result<u64, DwarfUnwinderError>,
+    >;
+
+    fn unwind_frame<R, UCS, ES>(
         section: &impl UnwindSection<R>,
         unwind_info: &UnwindTableRow<R::Offset, UCS>,
         encoding: Encoding,
-        regs: &mut Self::UnwindRegs,
+        regs: &Self::UnwindRegs,
         is_first_frame: bool,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<Self::UnwindRule>, DwarfUnwinderError>
+    ) -> DwarfUnwindResult<Self, R, ES>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         R: Reader,
         UCS: UnwindContextStorage<R::Offset>,
         ES: EvaluationStorage<R>;
@@ -89,6 +149,12 @@ pub trait DwarfUnwinding: Arch {
     fn rule_if_uncovered_by_fde() -> Self::UnwindRule;
 }
 
+/// The result of [`DwarfUnwinding::unwind_frame`].
+pub type DwarfUnwindResult<A, R, S> = Result<
+    UnwindResult<<A as Arch>::UnwindRule, <A as DwarfUnwinding>::DwarfEvaluation<R, S>>,
+    DwarfUnwinderError,
+>;
+
 pub enum UnwindSectionType {
     EhFrame,
     DebugFrame,
@@ -106,6 +172,8 @@ where
     unwind_context: &'a mut UnwindContext<R::Offset, UCS>,
     base_svma: u64,
     bases: BaseAddresses,
+    /// The replacement CIEs of the section, and a reader for their data.
+    cie_fixups: Option<(R, &'a CieFixups)>,
     _arch: PhantomData<A>,
 }
 
@@ -140,12 +208,25 @@ where
             unwind_context,
             bases,
             base_svma,
+            cie_fixups: None,
             _arch: PhantomData,
         }
     }
 
+    /// Use the replacements in `cie_fixups` for the CIEs which gimli can't parse.
+    pub(crate) fn with_cie_fixups<'f: 'a>(mut self, cie_fixups: &'f CieFixups) -> Self
+    where
+        R: From<EndianSlice<'f, LittleEndian>>,
+    {
+        if !cie_fixups.is_empty() {
+            let data = R::from(EndianSlice::new(&cie_fixups.data, LittleEndian));
+            self.cie_fixups = Some((data, cie_fixups));
+        }
+        self
+    }
+
     pub fn get_fde_offset_for_relative_address(&self, rel_lookup_address: u32) -> Option<u32> {
-        let lookup_svma = self.base_svma + rel_lookup_address as u64;
+        let lookup_svma = self.base_svma.wrapping_add(rel_lookup_address as u64);
         let eh_frame_hdr = self.eh_frame_hdr.as_ref()?;
         let table = eh_frame_hdr.table()?;
         let fde_ptr = table.lookup(lookup_svma, &self.bases).ok()?;
@@ -153,53 +234,232 @@ where
         fde_offset.0.into_u64().try_into().ok()
     }
 
-    pub fn unwind_frame_with_fde<F, ES>(
+    /// The start address of the first FDE in the eh_frame_hdr table which starts after
+    /// `rel_lookup_address`, relative to the base address.
+    pub(crate) fn get_next_fde_start_for_relative_address(
+        &self,
+        rel_lookup_address: u32,
+    ) -> Option<u32> {
+        let lookup_svma = self.base_svma.wrapping_add(rel_lookup_address as u64);
+        let table = self.eh_frame_hdr.as_ref()?.table()?;
+        // The entries of the table have a fixed size, so `EhHdrTableIter::nth` decodes
+        // the entry at its offset, without walking the entries before it. Each probe of
+        // the search below is constant time.
+        let entry_start = |index: usize| -> Option<u64> {
+            let (initial_location, _) =
+                EhHdrTableIter::nth(&mut table.iter(&self.bases), index).ok()??;
+            initial_location.direct().ok()
+        };
+        let starts_at_or_before_lookup =
+            |index: usize| entry_start(index).is_some_and(|start| start <= lookup_svma);
+        // The table doesn't tell us its length, so we double the end of the search range
+        // until it includes an entry past the lookup address, or the end of the table.
+        let mut low = 0;
+        let mut high = 1;
+        while starts_at_or_before_lookup(high - 1) {
+            low = high;
+            high = high.checked_mul(2)?;
+        }
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if starts_at_or_before_lookup(mid) {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let start = entry_start(low)?;
+        start.checked_sub(self.base_svma)?.try_into().ok()
+    }
+
+    /// The end address of the FDE at `fde_offset`, relative to the base address.
+    pub(crate) fn get_fde_end_for_offset(&self, fde_offset: u32) -> Option<u32> {
+        let unwind_section_data = self.unwind_section_data.clone();
+        let end_svma = match self.unwind_section_type {
+            UnwindSectionType::EhFrame => {
+                let mut eh_frame = EhFrame::from(unwind_section_data);
+                eh_frame.set_address_size(8);
+                eh_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = EhFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                self.fde_end_address(&eh_frame, fixups, fde_offset)?
+            }
+            UnwindSectionType::DebugFrame => {
+                let mut debug_frame = DebugFrame::from(unwind_section_data);
+                debug_frame.set_address_size(8);
+                debug_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = DebugFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                self.fde_end_address(&debug_frame, fixups, fde_offset)?
+            }
+        };
+        end_svma.checked_sub(self.base_svma)?.try_into().ok()
+    }
+
+    fn fde_end_address<US: UnwindSection<R>>(
+        &self,
+        unwind_section: &US,
+        fixups: Option<(&US, &CieFixups)>,
+        fde_offset: u32,
+    ) -> Option<u64> {
+        let fde = unwind_section
+            .fde_from_offset(
+                &self.bases,
+                US::Offset::from(R::Offset::from_u32(fde_offset)),
+                |unwind_section, bases, cie_offset| {
+                    cie_from_offset_with_fixups(unwind_section, bases, cie_offset, fixups)
+                },
+            )
+            .ok()?;
+        Some(fde.end_address())
+    }
+
+    /// Find the FDE for `rel_lookup_address` with a linear search through the section.
+    /// This is for the rare lookups which can't use an index, e.g. when the compact unwind
+    /// info entry of a function is malformed, so it doesn't say where its FDE is.
+    #[cfg(feature = "macho")]
+    pub(crate) fn find_fde_offset_with_linear_search(
+        &self,
+        rel_lookup_address: u32,
+    ) -> Option<u32> {
+        let lookup_svma = self.base_svma.wrapping_add(rel_lookup_address as u64);
+        let unwind_section_data = self.unwind_section_data.clone();
+        let fde_offset = match self.unwind_section_type {
+            UnwindSectionType::EhFrame => {
+                let mut eh_frame = EhFrame::from(unwind_section_data);
+                eh_frame.set_address_size(8);
+                eh_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = EhFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                self.fde_offset_for_svma(&eh_frame, fixups, lookup_svma)?
+            }
+            UnwindSectionType::DebugFrame => {
+                let mut debug_frame = DebugFrame::from(unwind_section_data);
+                debug_frame.set_address_size(8);
+                debug_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = DebugFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                self.fde_offset_for_svma(&debug_frame, fixups, lookup_svma)?
+            }
+        };
+        fde_offset.into_u64().try_into().ok()
+    }
+
+    #[cfg(feature = "macho")]
+    fn fde_offset_for_svma<US: UnwindSection<R>>(
+        &self,
+        unwind_section: &US,
+        fixups: Option<(&US, &CieFixups)>,
+        lookup_svma: u64,
+    ) -> Option<R::Offset> {
+        let fde = unwind_section
+            .fde_for_address(
+                &self.bases,
+                lookup_svma,
+                |unwind_section, bases, cie_offset| {
+                    cie_from_offset_with_fixups(unwind_section, bases, cie_offset, fixups)
+                },
+            )
+            .ok()?;
+        Some(fde.offset())
+    }
+
+    /// If the FDE doesn't cover the lookup address, this returns
+    /// [`DwarfUnwinderError::UnwindInfoForAddressFailed`] with
+    /// [`gimli::Error::NoUnwindInfoForAddress`] and the caller decides which rule to use
+    /// instead.
+    pub fn unwind_frame_with_fde<ES>(
         &mut self,
-        regs: &mut A::UnwindRegs,
+        regs: &A::UnwindRegs,
         is_first_frame: bool,
         rel_lookup_address: u32,
         fde_offset: u32,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<A::UnwindRule>, DwarfUnwinderError>
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> DwarfUnwindResult<A, R, ES>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         ES: EvaluationStorage<R>,
     {
-        let lookup_svma = self.base_svma + rel_lookup_address as u64;
+        let lookup_svma = self.base_svma.wrapping_add(rel_lookup_address as u64);
         let unwind_section_data = self.unwind_section_data.clone();
         match self.unwind_section_type {
             UnwindSectionType::EhFrame => {
                 let mut eh_frame = EhFrame::from(unwind_section_data);
                 eh_frame.set_address_size(8);
-                let unwind_info = self.unwind_info_for_fde(&eh_frame, lookup_svma, fde_offset);
-                if let Err(DwarfUnwinderError::UnwindInfoForAddressFailed(_)) = unwind_info {
-                    return Ok(UnwindResult::ExecRule(A::rule_if_uncovered_by_fde()));
-                }
-                let (unwind_info, encoding) = unwind_info?;
-                A::unwind_frame::<F, R, UCS, ES>(
+                eh_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = EhFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                let (unwind_info, encoding) =
+                    self.unwind_info_for_fde(&eh_frame, fixups, lookup_svma, fde_offset)?;
+                trace_event!(
+                    tracer,
+                    DwarfRow {
+                        fde_offset,
+                        start_address: unwind_info.start_address(),
+                        end_address: unwind_info.end_address(),
+                        row: alloc::format!("{unwind_info:?}"),
+                    }
+                );
+                A::unwind_frame::<R, UCS, ES>(
                     &eh_frame,
                     unwind_info,
                     encoding,
                     regs,
                     is_first_frame,
-                    read_stack,
                 )
             }
             UnwindSectionType::DebugFrame => {
                 let mut debug_frame = DebugFrame::from(unwind_section_data);
                 debug_frame.set_address_size(8);
-                let unwind_info = self.unwind_info_for_fde(&debug_frame, lookup_svma, fde_offset);
-                if let Err(DwarfUnwinderError::UnwindInfoForAddressFailed(_)) = unwind_info {
-                    return Ok(UnwindResult::ExecRule(A::rule_if_uncovered_by_fde()));
-                }
-                let (unwind_info, encoding) = unwind_info?;
-                A::unwind_frame::<F, R, UCS, ES>(
+                debug_frame.set_vendor(A::VENDOR);
+                let fixups = self.cie_fixups.as_ref().map(|(data, fixups)| {
+                    let mut fixup_section = DebugFrame::from(data.clone());
+                    fixup_section.set_address_size(8);
+                    fixup_section.set_vendor(A::VENDOR);
+                    (fixup_section, *fixups)
+                });
+                let fixups = fixups.as_ref().map(|(section, fixups)| (section, *fixups));
+                let (unwind_info, encoding) =
+                    self.unwind_info_for_fde(&debug_frame, fixups, lookup_svma, fde_offset)?;
+                trace_event!(
+                    tracer,
+                    DwarfRow {
+                        fde_offset,
+                        start_address: unwind_info.start_address(),
+                        end_address: unwind_info.end_address(),
+                        row: alloc::format!("{unwind_info:?}"),
+                    }
+                );
+                A::unwind_frame::<R, UCS, ES>(
                     &debug_frame,
                     unwind_info,
                     encoding,
                     regs,
                     is_first_frame,
-                    read_stack,
                 )
             }
         }
@@ -208,13 +468,16 @@ where
     fn unwind_info_for_fde<US: UnwindSection<R>>(
         &mut self,
         unwind_section: &US,
+        fixups: Option<(&US, &CieFixups)>,
         lookup_svma: u64,
         fde_offset: u32,
     ) -> Result<(&UnwindTableRow<R::Offset, UCS>, Encoding), DwarfUnwinderError> {
         let fde = unwind_section.fde_from_offset(
             &self.bases,
             US::Offset::from(R::Offset::from_u32(fde_offset)),
-            US::cie_from_offset,
+            |unwind_section, bases, cie_offset| {
+                cie_from_offset_with_fixups(unwind_section, bases, cie_offset, fixups)
+            },
         );
         let fde = fde.map_err(DwarfUnwinderError::FdeFromOffsetFailed)?;
         let encoding = fde.cie().encoding();
@@ -230,21 +493,442 @@ where
     }
 }
 
+/// Unwind a single frame with the CFI in `eh_frame_data`, without an unwinder or a
+/// module. `address` is in the SVMA space of the binary, and the FDE which covers it is
+/// found with a linear search through the section.
+pub(crate) fn unwind_frame_with_eh_frame<A, F>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut A::UnwindRegs,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError>
+where
+    A: DwarfUnwinding,
+    F: MemoryReader,
+{
+    let is_first_frame = !address.is_return_address();
+    with_eh_frame_row(
+        eh_frame_data,
+        section_addresses,
+        address,
+        A::VENDOR,
+        |eh_frame, unwind_info, encoding| {
+            let result = A::unwind_frame::<_, _, StoreOnHeap>(
+                eh_frame,
+                unwind_info,
+                encoding,
+                regs,
+                is_first_frame,
+            )?;
+            match result {
+                UnwindResult::ExecRule(rule) => Ok(rule.exec(is_first_frame, regs, read_stack)?),
+                UnwindResult::Uncacheable(mut evaluation) => {
+                    let return_address = SyncReads::new(read_stack).run(&mut evaluation, regs)?;
+                    Ok(Some(return_address))
+                }
+            }
+        },
+    )
+}
+
+/// Like [`unwind_frame_with_eh_frame`], but for a register file which holds all
+/// general-purpose registers. Every register with a rule in the CFI row is updated.
+pub(crate) fn unwind_full_frame_with_eh_frame<A, UR, F>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut UR,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError>
+where
+    A: DwarfUnwinding,
+    UR: FullDwarfUnwindRegs,
+    F: MemoryReader,
+{
+    let is_first_frame = !address.is_return_address();
+    with_eh_frame_row(
+        eh_frame_data,
+        section_addresses,
+        address,
+        A::VENDOR,
+        |eh_frame, unwind_info, encoding| {
+            Ok(unwind_full_frame::<_, F, UR, _, StoreOnHeap>(
+                eh_frame,
+                unwind_info,
+                encoding,
+                regs,
+                is_first_frame,
+                read_stack,
+            )?)
+        },
+    )
+}
+
+/// Find the CFI row for `address` in `eh_frame_data` and call `f` with it.
+fn with_eh_frame_row<'a, T>(
+    eh_frame_data: &'a [u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    vendor: Vendor,
+    f: impl FnOnce(
+        &EhFrame<EndianSlice<'a, LittleEndian>>,
+        &UnwindTableRow<usize, StoreOnHeap>,
+        Encoding,
+    ) -> Result<T, SingleFrameUnwindError>,
+) -> Result<T, SingleFrameUnwindError> {
+    let mut eh_frame = EhFrame::new(eh_frame_data, LittleEndian);
+    eh_frame.set_address_size(8);
+    eh_frame.set_vendor(vendor);
+    let bases = section_addresses.bases();
+    let lookup_svma = address.address_for_lookup();
+    let fde = eh_frame
+        .fde_for_address(&bases, lookup_svma, EhFrame::cie_from_offset)
+        .map_err(DwarfUnwinderError::UnwindInfoForAddressFailed)?;
+    let mut unwind_context = UnwindContext::<usize, StoreOnHeap>::new_in();
+    let unwind_info = fde
+        .unwind_info_for_address(&eh_frame, &bases, &mut unwind_context, lookup_svma)
+        .map_err(DwarfUnwinderError::UnwindInfoForAddressFailed)?;
+    f(&eh_frame, unwind_info, fde.cie().encoding())
+}
+
+/// Collects the addresses which pointers in DWARF CFI can be relative to. Bases of
+/// sections which the module doesn't have are left undefined, so that pointers which are
+/// relative to them fail to parse instead of resolving to a wrong address.
+///
+/// Data-relative pointers are relative to the GOT, or to the `.data` section in binaries
+/// without a GOT.
+///
+/// Pc-relative pointers are relative to the address of the bytes which contain them. If
+/// the unwind section data was read at an AVMA which doesn't match its SVMA (see
+/// [`ModuleSectionInfo::section_avma`]), that AVMA is translated to the SVMA space of the
+/// module with the module's bias, using `base_avma`.
 pub(crate) fn base_addresses_for_sections<D>(
     section_info: &mut impl ModuleSectionInfo<D>,
+    base_avma: u64,
 ) -> BaseAddresses {
-    let mut start_addr = |names: &[&[u8]]| -> u64 {
+    let bias = base_avma.wrapping_sub(section_info.base_svma());
+    let mut data_addr = |names: &[&[u8]]| -> Option<u64> {
+        names
+            .iter()
+            .find_map(|name| match section_info.section_avma(name) {
+                Some(avma) => Some(avma.wrapping_sub(bias)),
+                None => section_info.section_svma_range(name).map(|r| r.start),
+            })
+    };
+    let eh_frame = data_addr(&[b"__eh_frame", b".eh_frame"]);
+    let eh_frame_hdr = data_addr(&[b"__eh_frame_hdr", b".eh_frame_hdr"]);
+    let mut start_addr = |names: &[&[u8]]| -> Option<u64> {
         names
             .iter()
             .find_map(|name| section_info.section_svma_range(name))
             .map(|r| r.start)
-            .unwrap_or_default()
     };
-    BaseAddresses::default()
-        .set_eh_frame(start_addr(&[b"__eh_frame", b".eh_frame"]))
-        .set_eh_frame_hdr(start_addr(&[b"__eh_frame_hdr", b".eh_frame_hdr"]))
-        .set_text(start_addr(&[b"__text", b".text"]))
-        .set_got(start_addr(&[b"__got", b".got"]))
+    let text = start_addr(&[b"__text", b".text"]);
+    let data = start_addr(&[b"__got", b".got"]).or_else(|| start_addr(&[b"__data", b".data"]));
+    bases_from_addresses(eh_frame, eh_frame_hdr, text, data)
+}
+
+fn bases_from_addresses(
+    eh_frame: Option<u64>,
+    eh_frame_hdr: Option<u64>,
+    text: Option<u64>,
+    data: Option<u64>,
+) -> BaseAddresses {
+    let mut bases = BaseAddresses::default();
+    if let Some(eh_frame) = eh_frame {
+        bases = bases.set_eh_frame(eh_frame);
+    }
+    if let Some(eh_frame_hdr) = eh_frame_hdr {
+        bases = bases.set_eh_frame_hdr(eh_frame_hdr);
+    }
+    if let Some(text) = text {
+        bases = bases.set_text(text);
+    }
+    if let Some(data) = data {
+        bases = bases.set_got(data);
+    }
+    bases
+}
+
+/// An entry of an eh_frame or debug_frame section, see [`for_each_cfi_entry`].
+struct CfiEntry<R: Reader> {
+    /// The offset of the entry in the section.
+    offset: R::Offset,
+    format: Format,
+    /// Whether the entry is a CIE, otherwise it's an FDE.
+    is_cie: bool,
+    /// The bytes of the entry after its length and its CIE ID or CIE pointer.
+    rest: R,
+}
+
+/// Calls `f` for every CIE and FDE in `data`, the contents of an eh_frame section if
+/// `is_eh_frame` is true and of a debug_frame section otherwise.
+///
+/// Unlike [`UnwindSection::entries`], this doesn't parse the CIEs, so it continues
+/// after CIEs which gimli can't parse.
+fn for_each_cfi_entry<R: Reader>(
+    data: &R,
+    is_eh_frame: bool,
+    mut f: impl FnMut(CfiEntry<R>) -> Result<(), DwarfCfiIndexError>,
+) -> Result<(), DwarfCfiIndexError> {
+    let mut input = data.clone();
+    while !input.is_empty() {
+        let offset = input.offset_from(data);
+        let (length, format) = input.read_initial_length()?;
+        if length == R::Offset::from_u8(0) {
+            if is_eh_frame {
+                // The terminator of the section.
+                break;
+            }
+            // An empty entry, which some assemblers emit in debug_frame.
+            continue;
+        }
+        let mut rest = input.split(length)?;
+        let is_cie = if is_eh_frame {
+            rest.read_u32()? == 0
+        } else {
+            match format {
+                Format::Dwarf32 => rest.read_u32()? == 0xffff_ffff,
+                Format::Dwarf64 => rest.read_u64()? == 0xffff_ffff_ffff_ffff,
+            }
+        };
+        f(CfiEntry {
+            offset,
+            format,
+            is_cie,
+            rest,
+        })?;
+    }
+    Ok(())
+}
+
+/// Replacements for the CIEs of an unwind section whose augmentation gimli can't parse,
+/// so that the FDEs which use these CIEs can still be used.
+///
+/// gimli rejects augmentation strings with characters it doesn't know, e.g. the `B`
+/// (pointer authentication with the B key) and `G` (memory tagging) characters which
+/// AArch64 toolchains emit, or the `armcc+` augmentation of the ARM compiler. The
+/// replacement of such a CIE is a copy with an augmentation string which gimli can
+/// parse: `B` and `G` are dropped because they have no augmentation data, and
+/// unknown characters are dropped along with all characters after them, whose data
+/// can't be located anymore. The augmentation data stays the same, so the data of the
+/// remaining characters is still found, and the length which the `z` character
+/// prefixes it with skips the rest. Augmentation strings which don't start with `z`,
+/// other than `armcc+`, are left alone, because the layout of the rest of the CIE
+/// depends on them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct CieFixups {
+    /// `(offset of a CIE in the section, offset of its replacement in data)`, sorted by
+    /// the offset in the section.
+    offsets: Vec<(u64, u64)>,
+    /// The replacement CIEs, one after another, in the format of the section.
+    data: Vec<u8>,
+}
+
+impl CieFixups {
+    pub(crate) fn new(section_data: &[u8], section_type: &UnwindSectionType) -> Self {
+        let mut fixups = Self::default();
+        let is_eh_frame = matches!(section_type, UnwindSectionType::EhFrame);
+        let data = EndianSlice::new(section_data, LittleEndian);
+        // A section which can't be walked fails later, when its FDEs are used.
+        let _ = for_each_cfi_entry(&data, is_eh_frame, |entry| {
+            if !entry.is_cie {
+                return Ok(());
+            }
+            let mut rest = entry.rest;
+            let version = rest.read_u8()?;
+            let augmentation = rest.read_null_terminated_slice()?;
+            let Some(augmentation) = parseable_augmentation(augmentation.slice()) else {
+                return Ok(());
+            };
+            let id_size = match (is_eh_frame, entry.format) {
+                (false, Format::Dwarf64) => 8,
+                _ => 4,
+            };
+            let length = id_size + 1 + augmentation.len() + 1 + rest.len();
+            fixups
+                .offsets
+                .push((entry.offset as u64, fixups.data.len() as u64));
+            match entry.format {
+                Format::Dwarf32 => {
+                    fixups
+                        .data
+                        .extend_from_slice(&(length as u32).to_le_bytes());
+                }
+                Format::Dwarf64 => {
+                    fixups.data.extend_from_slice(&[0xff; 4]);
+                    fixups
+                        .data
+                        .extend_from_slice(&(length as u64).to_le_bytes());
+                }
+            }
+            let cie_id: &[u8] = match (is_eh_frame, id_size) {
+                (true, _) => &[0; 4],
+                (false, 4) => &[0xff; 4],
+                (false, _) => &[0xff; 8],
+            };
+            fixups.data.extend_from_slice(cie_id);
+            fixups.data.push(version);
+            fixups.data.extend_from_slice(&augmentation);
+            fixups.data.push(0);
+            fixups.data.extend_from_slice(rest.slice());
+            Ok(())
+        });
+        fixups
+    }
+
+    /// The offset of the replacement of the CIE at `offset` in the section, if it has
+    /// one.
+    fn replacement_offset(&self, offset: u64) -> Option<u64> {
+        let i = self
+            .offsets
+            .binary_search_by_key(&offset, |&(offset, _)| offset)
+            .ok()?;
+        Some(self.offsets[i].1)
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.offsets.capacity() * core::mem::size_of::<(u64, u64)>() + self.data.capacity()
+    }
+
+    /// The replacement CIEs of an eh_frame section of a 64-bit binary.
+    fn eh_frame(&self, vendor: Vendor) -> EhFrame<EndianSlice<'_, LittleEndian>> {
+        let mut eh_frame = EhFrame::from(EndianSlice::new(&self.data, LittleEndian));
+        eh_frame.set_address_size(8);
+        eh_frame.set_vendor(vendor);
+        eh_frame
+    }
+
+    /// The replacement CIEs of a debug_frame section of a 64-bit binary.
+    fn debug_frame(&self, vendor: Vendor) -> DebugFrame<EndianSlice<'_, LittleEndian>> {
+        let mut debug_frame = DebugFrame::from(EndianSlice::new(&self.data, LittleEndian));
+        debug_frame.set_address_size(8);
+        debug_frame.set_vendor(vendor);
+        debug_frame
+    }
+}
+
+/// The augmentation string to use instead of `augmentation`, if gimli can't parse it.
+/// See [`CieFixups`].
+fn parseable_augmentation(augmentation: &[u8]) -> Option<Vec<u8>> {
+    const PARSEABLE: &[u8] = b"LPRS";
+    const WITHOUT_DATA: &[u8] = b"BG";
+    if augmentation == b"armcc+" {
+        return Some(Vec::new());
+    }
+    let [b'z', rest @ ..] = augmentation else {
+        return None;
+    };
+    if rest.iter().all(|ch| PARSEABLE.contains(ch)) {
+        return None;
+    }
+    let mut parseable = alloc::vec![b'z'];
+    for ch in rest {
+        if PARSEABLE.contains(ch) {
+            parseable.push(*ch);
+        } else if !WITHOUT_DATA.contains(ch) {
+            break;
+        }
+    }
+    Some(parseable)
+}
+
+/// Parses the CIE at `offset` in `unwind_section`, or its replacement in `fixups`.
+fn cie_from_offset_with_fixups<R, US>(
+    unwind_section: &US,
+    bases: &BaseAddresses,
+    offset: US::Offset,
+    fixups: Option<(&US, &CieFixups)>,
+) -> gimli::Result<CommonInformationEntry<R>>
+where
+    R: Reader,
+    US: UnwindSection<R>,
+{
+    if let Some((fixup_section, fixups)) = fixups {
+        let section_offset = <US::Offset as UnwindOffset<R::Offset>>::into(offset).into_u64();
+        if let Some(replacement_offset) = fixups.replacement_offset(section_offset) {
+            let replacement_offset = US::Offset::from(R::Offset::from_u64(replacement_offset)?);
+            return fixup_section.cie_from_offset(bases, replacement_offset);
+        }
+    }
+    unwind_section.cie_from_offset(bases, offset)
+}
+
+/// Calls `f` for every FDE in `unwind_section`, in the order of the section. CIEs with a
+/// replacement in `fixups` are parsed from the replacement.
+fn for_each_fde<R, US>(
+    unwind_section: &US,
+    bases: &BaseAddresses,
+    fixups: Option<(&US, &CieFixups)>,
+    mut f: impl FnMut(&FrameDescriptionEntry<R>) -> Result<(), DwarfCfiIndexError>,
+) -> Result<(), DwarfCfiIndexError>
+where
+    R: Reader,
+    US: UnwindSection<R> + Section<R>,
+{
+    let mut cur_cie: Option<CommonInformationEntry<R>> = None;
+    let is_eh_frame = US::id() == SectionId::EhFrame;
+    for_each_cfi_entry(unwind_section.reader(), is_eh_frame, |entry| {
+        if entry.is_cie {
+            return Ok(());
+        }
+        let fde = unwind_section.fde_from_offset(
+            bases,
+            US::Offset::from(entry.offset),
+            |unwind_section, bases, cie_offset| {
+                if let Some(cie) = &cur_cie {
+                    if cie.offset() == <US::Offset as UnwindOffset<R::Offset>>::into(cie_offset) {
+                        return Ok(cie.clone());
+                    }
+                }
+                let cie = cie_from_offset_with_fixups(unwind_section, bases, cie_offset, fixups);
+                if let Ok(cie) = &cie {
+                    cur_cie = Some(cie.clone());
+                }
+                cie
+            },
+        )?;
+        f(&fde)
+    })
+}
+
+/// The address ranges (SVMAs) of the FDEs in the eh_frame or debug_frame section of a
+/// 64-bit binary, in the order of the section.
+pub(crate) fn fde_svma_ranges(
+    section_data: &[u8],
+    section_type: UnwindSectionType,
+    bases: &BaseAddresses,
+    cie_fixups: &CieFixups,
+) -> Result<Vec<Range<u64>>, DwarfCfiIndexError> {
+    let mut ranges = Vec::new();
+    let mut push_range = |fde: &FrameDescriptionEntry<_>| {
+        let start = fde.initial_address();
+        ranges.push(start..start.saturating_add(fde.len()));
+        Ok(())
+    };
+    let data = EndianSlice::new(section_data, LittleEndian);
+    match section_type {
+        UnwindSectionType::EhFrame => {
+            let mut eh_frame = EhFrame::from(data);
+            eh_frame.set_address_size(8);
+            let fixup_section = cie_fixups.eh_frame(Vendor::Default);
+            let fixups = Some((&fixup_section, cie_fixups));
+            for_each_fde(&eh_frame, bases, fixups, &mut push_range)?;
+        }
+        UnwindSectionType::DebugFrame => {
+            let mut debug_frame = DebugFrame::from(data);
+            debug_frame.set_address_size(8);
+            let fixup_section = cie_fixups.debug_frame(Vendor::Default);
+            let fixups = Some((&fixup_section, cie_fixups));
+            for_each_fde(&debug_frame, bases, fixups, &mut push_range)?;
+        }
+    }
+    Ok(ranges)
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -253,6 +937,14 @@ pub enum DwarfCfiIndexError {
     CouldNotSubtractBaseAddress,
     RelativeAddressTooBig,
     FdeOffsetTooBig,
+    InvalidSerializedIndex,
+    /// A pointer is relative to its own section, but the section's address is unknown.
+    SectionBaseAddressMissing,
+    /// A pointer is relative to the `.text` section, but its address is unknown.
+    TextBaseAddressMissing,
+    /// A pointer is relative to the data base, but neither the address of the `.got`
+    /// section nor of the `.data` section is known.
+    DataBaseAddressMissing,
 }
 
 impl core::fmt::Display for DwarfCfiIndexError {
@@ -264,13 +956,33 @@ impl core::fmt::Display for DwarfCfiIndexError {
             }
             Self::RelativeAddressTooBig => write!(f, "Relative address did not fit into u32"),
             Self::FdeOffsetTooBig => write!(f, "FDE offset did not fit into u32"),
+            Self::InvalidSerializedIndex => write!(f, "The serialized index is malformed"),
+            Self::SectionBaseAddressMissing => write!(
+                f,
+                "Found a pointer relative to the unwind section, but the section's address is unknown"
+            ),
+            Self::TextBaseAddressMissing => write!(
+                f,
+                "Found a pointer relative to `.text`, but the address of `.text` is unknown"
+            ),
+            Self::DataBaseAddressMissing => write!(
+                f,
+                "Found a data-relative pointer, but the addresses of `.got` and `.data` are unknown"
+            ),
         }
     }
 }
 
 impl From<gimli::Error> for DwarfCfiIndexError {
     fn from(e: gimli::Error) -> Self {
-        Self::Gimli(e)
+        match e {
+            gimli::Error::PcRelativePointerButSectionBaseIsUndefined => {
+                Self::SectionBaseAddressMissing
+            }
+            gimli::Error::TextRelativePointerButTextBaseIsUndefined => Self::TextBaseAddressMissing,
+            gimli::Error::DataRelativePointerButDataBaseIsUndefined => Self::DataBaseAddressMissing,
+            e => Self::Gimli(e),
+        }
     }
 }
 
@@ -284,8 +996,42 @@ impl std::error::Error for DwarfCfiIndexError {
     }
 }
 
-/// A binary search table for eh_frame FDEs. We generate this whenever a module
-/// without eh_frame_hdr is added.
+/// The addresses of the sections which pointers in DWARF CFI can be relative to, as
+/// stated in the binary (SVMAs). Addresses of sections which the binary doesn't have should
+/// be left at zero; pointers which are relative to them are reported as errors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DwarfCfiSectionAddresses {
+    /// The address of the `.eh_frame` / `__eh_frame` section.
+    pub eh_frame: u64,
+    /// The address of the `.eh_frame_hdr` / `__eh_frame_hdr` section.
+    pub eh_frame_hdr: u64,
+    /// The address of the `.text` / `__text` section.
+    pub text: u64,
+    /// The address of the `.got` / `__got` section.
+    pub got: u64,
+    /// The address of the `.data` / `__data` section. Data-relative pointers are relative
+    /// to this section if the binary has no GOT.
+    pub data: u64,
+}
+
+impl DwarfCfiSectionAddresses {
+    pub(crate) fn bases(&self) -> BaseAddresses {
+        let known = |address: u64| Some(address).filter(|&address| address != 0);
+        bases_from_addresses(
+            known(self.eh_frame),
+            known(self.eh_frame_hdr),
+            known(self.text),
+            known(self.got).or(known(self.data)),
+        )
+    }
+}
+
+/// A binary search table for the FDEs in an eh_frame or debug_frame section. The unwinder
+/// generates this whenever a module without eh_frame_hdr is added.
+///
+/// The index can also be built separately, for example by a symbol server, and shipped
+/// in the format of [`DwarfCfiIndex::to_bytes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DwarfCfiIndex {
     /// Contains the initial address for every FDE, relative to the base address.
     /// This vector is sorted so that it can be used for binary search.
@@ -297,6 +1043,8 @@ pub struct DwarfCfiIndex {
 }
 
 impl DwarfCfiIndex {
+    /// Build the index from a parsed gimli section. `base_svma` is the address which
+    /// the relative addresses in the index are relative to.
     pub fn try_new<R, US>(
         unwind_section: US,
         bases: BaseAddresses,
@@ -305,35 +1053,25 @@ impl DwarfCfiIndex {
     where
         R: Reader,
         R::Offset: TryInto<u32>,
-        US: UnwindSection<R>,
+        US: UnwindSection<R> + Section<R>,
     {
-        let mut fde_pc_and_offset = Vec::new();
-
-        let mut cur_cie = None;
-        let mut entries_iter = unwind_section.entries(&bases);
-        while let Some(entry) = entries_iter.next()? {
-            let fde = match entry {
-                CieOrFde::Cie(cie) => {
-                    cur_cie = Some(cie);
-                    continue;
-                }
-                CieOrFde::Fde(partial_fde) => {
-                    partial_fde.parse(|unwind_section, bases, cie_offset| {
-                        if let Some(cie) = &cur_cie {
-                            if cie.offset()
-                                == <US::Offset as UnwindOffset<R::Offset>>::into(cie_offset)
-                            {
-                                return Ok(cie.clone());
-                            }
-                        }
-                        let cie = unwind_section.cie_from_offset(bases, cie_offset);
-                        if let Ok(cie) = &cie {
-                            cur_cie = Some(cie.clone());
-                        }
-                        cie
-                    })?
-                }
-            };
+        Self::try_new_with_fixups(&unwind_section, bases, base_svma, None)
+    }
+
+    /// Build the index, with replacements for the CIEs which gimli can't parse.
+    fn try_new_with_fixups<R, US>(
+        unwind_section: &US,
+        bases: BaseAddresses,
+        base_svma: u64,
+        fixups: Option<(&US, &CieFixups)>,
+    ) -> Result<Self, DwarfCfiIndexError>
+    where
+        R: Reader,
+        R::Offset: TryInto<u32>,
+        US: UnwindSection<R> + Section<R>,
+    {
+        let mut fdes = Vec::new();
+        for_each_fde(unwind_section, &bases, fixups, |fde| {
             let pc = fde.initial_address();
             let relative_pc = pc
                 .checked_sub(base_svma)
@@ -342,9 +1080,13 @@ impl DwarfCfiIndex {
                 .map_err(|_| DwarfCfiIndexError::RelativeAddressTooBig)?;
             let fde_offset = <R::Offset as TryInto<u32>>::try_into(fde.offset())
                 .map_err(|_| DwarfCfiIndexError::FdeOffsetTooBig)?;
-            fde_pc_and_offset.push((relative_pc, fde_offset));
-        }
-        fde_pc_and_offset.sort_by_key(|(pc, _)| *pc);
+            if fde.len() != 0 {
+                let relative_end = u64::from(relative_pc).saturating_add(fde.len());
+                fdes.push((relative_pc, relative_end, fde_offset));
+            }
+            Ok(())
+        })?;
+        let fde_pc_and_offset = Self::non_overlapping_entries(fdes);
         let sorted_fde_pc_starts = fde_pc_and_offset.iter().map(|(pc, _)| *pc).collect();
         let fde_offsets = fde_pc_and_offset.into_iter().map(|(_, fde)| fde).collect();
         Ok(Self {
@@ -353,29 +1095,164 @@ impl DwarfCfiIndex {
         })
     }
 
-    pub fn try_new_eh_frame<D>(
+    /// Turn the `(relative start, relative end, FDE offset)` triples of the FDEs, in any
+    /// order, into sorted `(relative start, FDE offset)` entries for binary search.
+    ///
+    /// Some linkers and post-processing tools emit FDEs out of order, or FDEs whose
+    /// ranges overlap. Looking up the entry with the closest start address would miss an
+    /// FDE which encloses a smaller FDE for the addresses after the smaller one, so
+    /// overlapping ranges are split at every start and end address, and every piece gets
+    /// the smallest FDE which covers it. Among FDEs with the same range, the first one in
+    /// the section is used.
+    fn non_overlapping_entries(mut fdes: Vec<(u32, u64, u32)>) -> Vec<(u32, u32)> {
+        // The stable sort keeps the section order of FDEs with the same range.
+        fdes.sort_by_key(|&(start, end, _)| (start, end));
+        let has_overlaps = fdes.windows(2).any(|pair| u64::from(pair[1].0) < pair[0].1);
+        if !has_overlaps {
+            return fdes
+                .into_iter()
+                .map(|(start, _, fde_offset)| (start, fde_offset))
+                .collect();
+        }
+
+        let mut boundaries: Vec<u64> = fdes
+            .iter()
+            .flat_map(|&(start, end, _)| [u64::from(start), end])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        // The FDEs which cover the current boundary, smallest first. FDEs which ended
+        // are only removed once they're the smallest.
+        let mut active = BinaryHeap::new();
+        let mut next_fde = 0;
+        let mut entries: Vec<(u32, u32)> = Vec::with_capacity(fdes.len());
+        for boundary in boundaries {
+            while let Some(&(start, end, fde_offset)) = fdes.get(next_fde) {
+                if u64::from(start) != boundary {
+                    break;
+                }
+                active.push(Reverse((end - u64::from(start), next_fde, end, fde_offset)));
+                next_fde += 1;
+            }
+            while matches!(active.peek(), Some(Reverse((_, _, end, _))) if *end <= boundary) {
+                active.pop();
+            }
+            let Some(&Reverse((_, _, _, fde_offset))) = active.peek() else {
+                continue;
+            };
+            // The end of the last FDE can be beyond the u32 range, but then nothing starts
+            // there.
+            let Ok(boundary) = u32::try_from(boundary) else {
+                break;
+            };
+            if entries.last().map(|&(_, offset)| offset) != Some(fde_offset) {
+                entries.push((boundary, fde_offset));
+            }
+        }
+        entries
+    }
+
+    pub(crate) fn try_new_eh_frame<D>(
         eh_frame_data: &[u8],
+        cie_fixups: &CieFixups,
         section_info: &mut impl ModuleSectionInfo<D>,
+        base_avma: u64,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let bases = base_addresses_for_sections(section_info, base_avma);
+        Self::try_new_eh_frame_with_bases(
+            eh_frame_data,
+            cie_fixups,
+            bases,
+            section_info.base_svma(),
+        )
+    }
+
+    pub(crate) fn try_new_debug_frame<D>(
+        debug_frame_data: &[u8],
+        cie_fixups: &CieFixups,
+        section_info: &mut impl ModuleSectionInfo<D>,
+        base_avma: u64,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let bases = base_addresses_for_sections(section_info, base_avma);
+        Self::try_new_debug_frame_with_bases(
+            debug_frame_data,
+            cie_fixups,
+            bases,
+            section_info.base_svma(),
+        )
+    }
+
+    fn try_new_eh_frame_with_bases(
+        eh_frame_data: &[u8],
+        cie_fixups: &CieFixups,
+        bases: BaseAddresses,
+        base_svma: u64,
     ) -> Result<Self, DwarfCfiIndexError> {
-        let bases = base_addresses_for_sections(section_info);
         let mut eh_frame = EhFrame::from(EndianSlice::new(eh_frame_data, LittleEndian));
         eh_frame.set_address_size(8);
+        let fixup_section = cie_fixups.eh_frame(Vendor::Default);
 
-        Self::try_new(eh_frame, bases, section_info.base_svma())
+        Self::try_new_with_fixups(
+            &eh_frame,
+            bases,
+            base_svma,
+            Some((&fixup_section, cie_fixups)),
+        )
     }
 
-    pub fn try_new_debug_frame<D>(
+    fn try_new_debug_frame_with_bases(
         debug_frame_data: &[u8],
-        section_info: &mut impl ModuleSectionInfo<D>,
+        cie_fixups: &CieFixups,
+        bases: BaseAddresses,
+        base_svma: u64,
     ) -> Result<Self, DwarfCfiIndexError> {
-        let bases = base_addresses_for_sections(section_info);
         let mut debug_frame = DebugFrame::from(EndianSlice::new(debug_frame_data, LittleEndian));
         debug_frame.set_address_size(8);
+        let fixup_section = cie_fixups.debug_frame(Vendor::Default);
+
+        Self::try_new_with_fixups(
+            &debug_frame,
+            bases,
+            base_svma,
+            Some((&fixup_section, cie_fixups)),
+        )
+    }
+
+    /// Build the index from the raw bytes of an eh_frame section of a 64-bit binary.
+    pub fn try_from_eh_frame_data(
+        eh_frame_data: &[u8],
+        section_addresses: &DwarfCfiSectionAddresses,
+        base_svma: u64,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let cie_fixups = CieFixups::new(eh_frame_data, &UnwindSectionType::EhFrame);
+        Self::try_new_eh_frame_with_bases(
+            eh_frame_data,
+            &cie_fixups,
+            section_addresses.bases(),
+            base_svma,
+        )
+    }
 
-        Self::try_new(debug_frame, bases, section_info.base_svma())
+    /// Build the index from the raw bytes of a debug_frame section of a 64-bit binary.
+    pub fn try_from_debug_frame_data(
+        debug_frame_data: &[u8],
+        section_addresses: &DwarfCfiSectionAddresses,
+        base_svma: u64,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let cie_fixups = CieFixups::new(debug_frame_data, &UnwindSectionType::DebugFrame);
+        Self::try_new_debug_frame_with_bases(
+            debug_frame_data,
+            &cie_fixups,
+            section_addresses.bases(),
+            base_svma,
+        )
     }
 
-    pub fn fde_offset_for_relative_address(&self, rel_lookup_address: u32) -> Option<u32> {
+    /// Find the offset of the FDE which covers `rel_lookup_address`, i.e. the FDE with the
+    /// closest start address at or below it. The FDE itself needs to be checked for
+    /// whether its PC range actually includes the address.
+    pub fn lookup(&self, rel_lookup_address: u32) -> Option<u32> {
         let i = match self.sorted_fde_pc_starts.binary_search(&rel_lookup_address) {
             Err(0) => return None,
             Ok(i) => i,
@@ -383,55 +1260,503 @@ impl DwarfCfiIndex {
         };
         Some(self.fde_offsets[i])
     }
+
+    /// The start address of the first FDE which starts after `rel_lookup_address`.
+    pub(crate) fn next_fde_start(&self, rel_lookup_address: u32) -> Option<u32> {
+        let i = self
+            .sorted_fde_pc_starts
+            .partition_point(|start| *start <= rel_lookup_address);
+        self.sorted_fde_pc_starts.get(i).copied()
+    }
+
+    /// The number of FDEs in the index.
+    pub fn len(&self) -> usize {
+        self.fde_offsets.len()
+    }
+
+    /// Whether the index contains no FDEs.
+    pub fn is_empty(&self) -> bool {
+        self.fde_offsets.is_empty()
+    }
+
+    /// The number of bytes which the index holds on the heap.
+    pub(crate) fn memory_usage(&self) -> usize {
+        (self.sorted_fde_pc_starts.capacity() + self.fde_offsets.capacity())
+            * core::mem::size_of::<u32>()
+    }
+
+    /// Iterate over the `(relative start address, FDE offset)` pairs of the index, sorted
+    /// by start address.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.sorted_fde_pc_starts
+            .iter()
+            .copied()
+            .zip(self.fde_offsets.iter().copied())
+    }
+
+    /// Serialize the index. The format is the number of FDEs, followed by the sorted start
+    /// addresses and then by the FDE offsets, all as little-endian `u32` values.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(4 + self.len() * 8);
+        bytes.extend_from_slice(&(self.len() as u32).to_le_bytes());
+        for value in self.sorted_fde_pc_starts.iter().chain(&self.fde_offsets) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize an index which was serialized with [`DwarfCfiIndex::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DwarfCfiIndexError> {
+        let mut values = bytes
+            .chunks(4)
+            .map(|chunk| <[u8; 4]>::try_from(chunk).map(u32::from_le_bytes));
+        let len = match values.next() {
+            Some(Ok(len)) => len as usize,
+            _ => return Err(DwarfCfiIndexError::InvalidSerializedIndex),
+        };
+        if len.checked_mul(8).and_then(|size| size.checked_add(4)) != Some(bytes.len()) {
+            return Err(DwarfCfiIndexError::InvalidSerializedIndex);
+        }
+        let values: Vec<u32> = values
+            .collect::<Result<_, _>>()
+            .map_err(|_| DwarfCfiIndexError::InvalidSerializedIndex)?;
+        let (sorted_fde_pc_starts, fde_offsets) = values.split_at(len);
+        if sorted_fde_pc_starts.windows(2).any(|w| w[0] > w[1]) {
+            return Err(DwarfCfiIndexError::InvalidSerializedIndex);
+        }
+        Ok(Self {
+            sorted_fde_pc_starts: sorted_fde_pc_starts.to_vec(),
+            fde_offsets: fde_offsets.to_vec(),
+        })
+    }
 }
 
 pub trait DwarfUnwindRegs {
     fn get(&self, register: Register) -> Option<u64>;
 }
 
-pub fn eval_cfa_rule<R: Reader, UR: DwarfUnwindRegs, S: EvaluationStorage<R>>(
+/// A register file which holds all general-purpose registers of an architecture, and
+/// remembers which of them are unknown, see e.g.
+/// [`FullUnwindRegsX86_64`](crate::x86_64::FullUnwindRegsX86_64).
+pub trait FullDwarfUnwindRegs: UnwindRegs + DwarfUnwindRegs + Clone {
+    /// The return address column of the CFI.
+    const RA: Register;
+
+    fn pc(&self) -> u64;
+    fn set_pc(&mut self, pc: u64);
+    fn sp(&self) -> u64;
+    fn set_sp(&mut self, sp: u64);
+
+    /// Mark a register as unknown, because its rule could not be evaluated. Does
+    /// nothing for registers which the register file doesn't hold.
+    fn set_unknown(&mut self, register: Register);
+}
+
+/// Unwind a frame by evaluating the rules of all registers in the CFI row, rather than
+/// just the ones which are needed to continue unwinding. Registers without a rule keep
+/// their values. Returns `None` if the return address is undefined, which marks the end
+/// of the stack.
+pub fn unwind_full_frame<R, F, UR, UCS, ES>(
     section: &impl UnwindSection<R>,
-    rule: &CfaRule<R::Offset>,
+    unwind_info: &UnwindTableRow<R::Offset, UCS>,
     encoding: Encoding,
-    regs: &UR,
-) -> Option<u64> {
-    match rule {
-        CfaRule::RegisterAndOffset { register, offset } => {
-            let val = regs.get(*register)?;
-            u64::try_from(i64::try_from(val).ok()?.checked_add(*offset)?).ok()
+    regs: &mut UR,
+    is_first_frame: bool,
+    read_stack: &mut F,
+) -> Result<Option<u64>, DwarfUnwinderError>
+where
+    R: Reader,
+    F: MemoryReader,
+    UR: FullDwarfUnwindRegs,
+    UCS: UnwindContextStorage<R::Offset>,
+    ES: EvaluationStorage<R>,
+{
+    let ra_rule = unwind_info.register(UR::RA);
+    if ra_rule == RegisterRule::Undefined {
+        return Ok(None);
+    }
+    let cfa = eval_cfa_rule::<R, F, _, ES>(section, unwind_info.cfa(), encoding, regs, read_stack)
+        .ok_or(DwarfUnwinderError::CouldNotRecoverCfa)?;
+    if !is_first_frame && cfa < regs.sp() {
+        return Err(DwarfUnwinderError::StackPointerMovedBackwards);
+    }
+
+    // The rules are evaluated with the callee's registers, so collect the caller's
+    // registers in a copy.
+    let mut eval = |register: Register, rule: &RegisterRule<R::Offset>| match rule {
+        RegisterRule::SameValue => regs.get(register),
+        _ => eval_register_rule::<R, _, _, ES>(
+            section,
+            rule.clone(),
+            cfa,
+            encoding,
+            0,
+            regs,
+            read_stack,
+        ),
+    };
+    let return_address =
+        eval(UR::RA, &ra_rule).ok_or(DwarfUnwinderError::CouldNotRecoverReturnAddress)?;
+    let mut caller = regs.clone();
+    for (register, rule) in unwind_info.registers() {
+        if *register == UR::RA {
+            continue;
         }
-        CfaRule::Expression(expr) => {
-            let expr = expr.get(section).ok()?;
-            eval_expr::<R, UR, S>(expr, encoding, regs)
+        match eval(*register, rule) {
+            Some(value) => {
+                caller.set_dwarf_reg(register.0, value);
+            }
+            None => caller.set_unknown(*register),
         }
     }
+    caller.set_dwarf_reg(UR::RA.0, return_address);
+    // Take the value back from the register file, which may strip pointer
+    // authentication bits.
+    let return_address = caller.get(UR::RA).unwrap_or(return_address);
+    caller.set_pc(return_address);
+    caller.set_sp(cfa);
+
+    if cfa == regs.sp() && return_address == regs.pc() {
+        return Err(DwarfUnwinderError::DidNotAdvance);
+    }
+    *regs = caller;
+    Ok(Some(return_address))
+}
+
+pub fn eval_cfa_rule<R, F, UR, S>(
+    section: &impl UnwindSection<R>,
+    rule: &CfaRule<R::Offset>,
+    encoding: Encoding,
+    regs: &UR,
+    read_stack: &mut F,
+) -> Option<u64>
+where
+    R: Reader,
+    F: MemoryReader,
+    UR: DwarfUnwindRegs,
+    S: EvaluationStorage<R>,
+{
+    RuleEvaluation::<R, S>::cfa(section, rule, encoding, regs).run(regs, read_stack)
 }
 
-fn eval_expr<R: Reader, UR: DwarfUnwindRegs, S: EvaluationStorage<R>>(
+/// The maximum number of memory reads during the evaluation of a single DWARF
+/// expression. Expressions can contain loops, and unwinding may happen in a signal
+/// handler, so evaluation has to be bounded.
+const MAX_EXPRESSION_MEMORY_READS: usize = 16;
+
+/// The maximum number of operations which are executed during the evaluation of a single
+/// DWARF expression.
+const MAX_EXPRESSION_ITERATIONS: u32 = 1000;
+
+/// Evaluate a DWARF expression and return the value at the top of the stack. If `cfa` is
+/// given, it is pushed on the stack before evaluation, as is done for the expressions of
+/// register rules.
+#[cfg(test)]
+fn eval_expr<R, F, UR, S>(
     expr: Expression<R>,
     encoding: Encoding,
+    cfa: Option<u64>,
+    regs: &UR,
+    read_stack: &mut F,
+) -> Option<u64>
+where
+    R: Reader,
+    F: MemoryReader,
+    UR: DwarfUnwindRegs,
+    S: EvaluationStorage<R>,
+{
+    RuleEvaluation::<R, S>::expression(expr, encoding, cfa, false).run(regs, read_stack)
+}
+
+/// A register rule whose expression, if it has one, has been read from the section.
+#[derive(Clone, Debug)]
+pub struct ResolvedRegisterRule<R: Reader> {
+    rule: RegisterRule<R::Offset>,
+    expression: Option<Expression<R>>,
+}
+
+impl<R: Reader> ResolvedRegisterRule<R> {
+    pub fn new(section: &impl UnwindSection<R>, rule: RegisterRule<R::Offset>) -> Self {
+        let expression = match &rule {
+            RegisterRule::Expression(expr) | RegisterRule::ValExpression(expr) => {
+                expr.get(section).ok()
+            }
+            _ => None,
+        };
+        Self { rule, expression }
+    }
+
+    /// The stack location of the saved register, if the rule restores it from an offset
+    /// from the CFA.
+    pub fn location(&self, cfa: u64) -> Option<u64> {
+        match self.rule {
+            RegisterRule::Offset(offset) => checked_add_signed(cfa, offset),
+            _ => None,
+        }
+    }
+}
+
+/// The evaluation of a CFA rule or a register rule. It returns the memory reads it needs,
+/// one at a time, like [`Resumable`], and results in `None` if the rule can't be
+/// evaluated.
+pub struct RuleEvaluation<R: Reader, S: EvaluationStorage<R>> {
+    state: RuleEvaluationState<R, S>,
+}
+
+enum RuleEvaluationState<R: Reader, S: EvaluationStorage<R>> {
+    Done(Option<u64>),
+    /// The result is the value at this location. A failed read results in `None`.
+    Load {
+        request: ReadRequest,
+        provided: Option<Result<u64, MemoryReadError>>,
+    },
+    /// The result is the result of the expression, or the value at the location which
+    /// it computes if `deref` is set.
+    Expression {
+        eval: Evaluation<R, S>,
+        cfa: Option<u64>,
+        deref: bool,
+        memory_read_count: usize,
+        state: ExpressionState,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ExpressionState {
+    Start,
+    Read(ReadRequest),
+    Provided(Result<u64, MemoryReadError>),
+}
+
+impl<R: Reader, S: EvaluationStorage<R>> RuleEvaluation<R, S> {
+    fn done(value: Option<u64>) -> Self {
+        Self {
+            state: RuleEvaluationState::Done(value),
+        }
+    }
+
+    fn load(location: Option<u64>) -> Self {
+        match location {
+            Some(address) => Self {
+                state: RuleEvaluationState::Load {
+                    request: ReadRequest::u64(address),
+                    provided: None,
+                },
+            },
+            None => Self::done(None),
+        }
+    }
+
+    fn expression(expr: Expression<R>, encoding: Encoding, cfa: Option<u64>, deref: bool) -> Self {
+        let mut eval = Evaluation::<R, S>::new_in(expr.0, encoding);
+        eval.set_max_iterations(MAX_EXPRESSION_ITERATIONS);
+        if let Some(cfa) = cfa {
+            eval.set_initial_value(cfa);
+        }
+        Self {
+            state: RuleEvaluationState::Expression {
+                eval,
+                cfa,
+                deref,
+                memory_read_count: 0,
+                state: ExpressionState::Start,
+            },
+        }
+    }
+
+    /// Start evaluating a CFA rule.
+    pub fn cfa<UR: DwarfUnwindRegs>(
+        section: &impl UnwindSection<R>,
+        rule: &CfaRule<R::Offset>,
+        encoding: Encoding,
+        regs: &UR,
+    ) -> Self {
+        match rule {
+            CfaRule::RegisterAndOffset { register, offset } => Self::done(
+                regs.get(*register)
+                    .and_then(|val| i64::try_from(val).ok()?.checked_add(*offset))
+                    .and_then(|cfa| u64::try_from(cfa).ok()),
+            ),
+            CfaRule::Expression(expr) => match expr.get(section) {
+                Ok(expr) => Self::expression(expr, encoding, None, false),
+                Err(_) => Self::done(None),
+            },
+        }
+    }
+
+    /// Start evaluating a register rule. `val` is the value of the register in the
+    /// callee.
+    pub fn register<UR: DwarfUnwindRegs>(
+        rule: &ResolvedRegisterRule<R>,
+        cfa: u64,
+        encoding: Encoding,
+        val: u64,
+        regs: &UR,
+    ) -> Self {
+        let cfa_plus =
+            |offset: i64| u64::try_from(i64::try_from(cfa).ok()?.checked_add(offset)?).ok();
+        match (&rule.rule, &rule.expression) {
+            (RegisterRule::Undefined, _) => Self::done(None),
+            (RegisterRule::SameValue, _) => Self::done(Some(val)),
+            (RegisterRule::Offset(offset), _) => Self::load(cfa_plus(*offset)),
+            (RegisterRule::ValOffset(offset), _) => Self::done(cfa_plus(*offset)),
+            (RegisterRule::Register(register), _) => Self::done(regs.get(*register)),
+            (RegisterRule::Expression(_), Some(expr)) => {
+                Self::expression(expr.clone(), encoding, Some(cfa), true)
+            }
+            (RegisterRule::ValExpression(_), Some(expr)) => {
+                Self::expression(expr.clone(), encoding, Some(cfa), false)
+            }
+            // TODO: Find out what the architectural rules for x86_64 and for aarch64 are, if any.
+            _ => Self::done(None),
+        }
+    }
+
+    /// Fetch the 8-byte values from `first` to `last` with the read of a rule which
+    /// restores the register from the stack.
+    pub fn with_prefetch(mut self, first: u64, last: u64) -> Self {
+        if let RuleEvaluationState::Load { request, .. } = &mut self.state {
+            *request = request.with_prefetch(first, last);
+        }
+        self
+    }
+
+    /// Don't read the saved register if it is in the uncaptured red zone below `sp`.
+    pub fn with_red_zone(mut self, sp: u64) -> Self {
+        if let RuleEvaluationState::Load { request, .. } = &mut self.state {
+            *request = request.with_red_zone(sp);
+        }
+        self
+    }
+
+    /// Continue the evaluation, like [`Resumable::step`]. The rule is evaluated with the
+    /// registers `regs` of the callee.
+    pub fn step<UR: DwarfUnwindRegs>(&mut self, regs: &UR) -> Step<Option<u64>> {
+        loop {
+            let next = match &mut self.state {
+                RuleEvaluationState::Done(value) => return Step::Done(*value),
+                RuleEvaluationState::Load { request, provided } => match provided.take() {
+                    Some(value) => RuleEvaluationState::Done(value.ok()),
+                    None => return Step::Read(*request),
+                },
+                RuleEvaluationState::Expression {
+                    eval,
+                    cfa,
+                    deref,
+                    memory_read_count,
+                    state,
+                } => match step_expression(eval, *cfa, memory_read_count, state, regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(Some(address)) if *deref => RuleEvaluationState::Load {
+                        request: ReadRequest::u64(address),
+                        provided: None,
+                    },
+                    Step::Done(value) => RuleEvaluationState::Done(value),
+                },
+            };
+            self.state = next;
+        }
+    }
+
+    /// Provide the result of the read which the last step asked for.
+    pub fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        match &mut self.state {
+            RuleEvaluationState::Load { provided, .. } => *provided = Some(value),
+            RuleEvaluationState::Expression { state, .. } => {
+                if let ExpressionState::Read(_) = state {
+                    *state = ExpressionState::Provided(value);
+                }
+            }
+            RuleEvaluationState::Done(_) => {}
+        }
+    }
+
+    /// Run the evaluation to the end with a [`MemoryReader`].
+    fn run<UR: DwarfUnwindRegs, F: MemoryReader>(
+        mut self,
+        regs: &UR,
+        read_stack: &mut F,
+    ) -> Option<u64> {
+        let mut reads = SyncReads::new(read_stack);
+        loop {
+            match self.step(regs) {
+                Step::Read(request) => {
+                    let value = reads.read(&request);
+                    self.provide(value);
+                }
+                Step::Done(value) => return value,
+            }
+        }
+    }
+}
+
+/// Continue evaluating a DWARF expression until it needs a memory read or until it's
+/// done. The result is the address at the top of the stack.
+fn step_expression<R, S, UR>(
+    eval: &mut Evaluation<R, S>,
+    cfa: Option<u64>,
+    memory_read_count: &mut usize,
+    state: &mut ExpressionState,
     regs: &UR,
-) -> Option<u64> {
-    let mut eval = Evaluation::<R, S>::new_in(expr.0, encoding);
-    let mut result = eval.evaluate().ok()?;
+) -> Step<Option<u64>>
+where
+    R: Reader,
+    S: EvaluationStorage<R>,
+    UR: DwarfUnwindRegs,
+{
+    let mut result = match *state {
+        ExpressionState::Start => eval.evaluate(),
+        ExpressionState::Read(request) => return Step::Read(request),
+        ExpressionState::Provided(Ok(value)) => eval.resume_with_memory(Value::Generic(value)),
+        ExpressionState::Provided(Err(_)) => return Step::Done(None),
+    };
     loop {
-        match result {
+        let Ok(requirement) = result else {
+            return Step::Done(None);
+        };
+        result = match requirement {
             EvaluationResult::Complete => break,
-            EvaluationResult::RequiresRegister { register, .. } => {
-                let value = regs.get(register)?;
-                result = eval.resume_with_register(Value::Generic(value as _)).ok()?;
+            EvaluationResult::RequiresRegister { register, .. } => match regs.get(register) {
+                Some(value) => eval.resume_with_register(Value::Generic(value as _)),
+                None => return Step::Done(None),
+            },
+            EvaluationResult::RequiresCallFrameCfa => match cfa {
+                Some(cfa) => eval.resume_with_call_frame_cfa(cfa),
+                None => return Step::Done(None),
+            },
+            EvaluationResult::RequiresMemory {
+                address,
+                size: size @ (1 | 2 | 4 | 8),
+                space: None,
+                ..
+            } => {
+                *memory_read_count += 1;
+                if *memory_read_count > MAX_EXPRESSION_MEMORY_READS {
+                    return Step::Done(None);
+                }
+                let request = ReadRequest::sized(address, size);
+                *state = ExpressionState::Read(request);
+                return Step::Read(request);
             }
-            _ => return None,
-        }
+            _ => return Step::Done(None),
+        };
     }
-    let x = &eval.as_result().last()?.location;
-    if let Location::Address { address } = x {
-        Some(*address)
-    } else {
-        None
+    match eval.as_result().last().map(|piece| &piece.location) {
+        Some(Location::Address { address }) => Step::Done(Some(*address)),
+        _ => Step::Done(None),
     }
 }
 
+/// The locations of two registers which are saved at offsets from the CFA, so that they
+/// can be fetched with a single read.
+pub fn saved_register_prefetch<R: Reader>(
+    cfa: u64,
+    rule1: &ResolvedRegisterRule<R>,
+    rule2: &ResolvedRegisterRule<R>,
+) -> Option<(u64, u64)> {
+    Some((rule1.location(cfa)?, rule2.location(cfa)?))
+}
+
 pub fn eval_register_rule<R, F, UR, S>(
     section: &impl UnwindSection<R>,
     rule: RegisterRule<R::Offset>,
@@ -443,36 +1768,172 @@ pub fn eval_register_rule<R, F, UR, S>(
 ) -> Option<u64>
 where
     R: Reader,
-    F: FnMut(u64) -> Result<u64, ()>,
+    F: MemoryReader,
     UR: DwarfUnwindRegs,
     S: EvaluationStorage<R>,
 {
-    match rule {
-        RegisterRule::Undefined => None,
-        RegisterRule::SameValue => Some(val),
-        RegisterRule::Offset(offset) => {
-            let cfa_plus_offset =
-                u64::try_from(i64::try_from(cfa).ok()?.checked_add(offset)?).ok()?;
-            read_stack(cfa_plus_offset).ok()
-        }
-        RegisterRule::ValOffset(offset) => {
-            u64::try_from(i64::try_from(cfa).ok()?.checked_add(offset)?).ok()
-        }
-        RegisterRule::Register(register) => regs.get(register),
-        RegisterRule::Expression(expr) => {
-            let expr = expr.get(section).ok()?;
-            let val = eval_expr::<R, UR, S>(expr, encoding, regs)?;
-            read_stack(val).ok()
-        }
-        RegisterRule::ValExpression(expr) => {
-            let expr = expr.get(section).ok()?;
-            eval_expr::<R, UR, S>(expr, encoding, regs)
-        }
-        RegisterRule::Architectural => {
-            // Unimplemented
-            // TODO: Find out what the architectural rules for x86_64 and for aarch64 are, if any.
-            None
+    let rule = ResolvedRegisterRule::new(section, rule);
+    RuleEvaluation::<R, S>::register(&rule, cfa, encoding, val, regs).run(regs, read_stack)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use gimli::StoreOnHeap;
+
+    struct TestRegs;
+
+    impl DwarfUnwindRegs for TestRegs {
+        fn get(&self, register: Register) -> Option<u64> {
+            match register.0 {
+                7 => Some(0x10),
+                _ => None,
+            }
         }
-        _ => None,
+    }
+
+    fn eval(expr: &[u8], cfa: Option<u64>, stack: &[u64]) -> Option<u64> {
+        let encoding = Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 4,
+            address_size: 8,
+        };
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        eval_expr::<_, _, _, StoreOnHeap>(
+            Expression(EndianSlice::new(expr, LittleEndian)),
+            encoding,
+            cfa,
+            &TestRegs,
+            &mut read_stack,
+        )
+    }
+
+    #[test]
+    fn test_eval_expr_memory_reads() {
+        let stack = [0x0, 0x0, 0x0, 0x1234];
+        // DW_OP_breg7 (rsp) 8; DW_OP_deref
+        assert_eq!(eval(&[0x77, 0x08, 0x06], None, &stack), Some(0x1234));
+        // DW_OP_breg7 (rsp) 8; DW_OP_deref_size 2
+        assert_eq!(eval(&[0x77, 0x08, 0x94, 0x02], None, &stack), Some(0x1234));
+        // DW_OP_breg7 (rsp) 8; DW_OP_deref_size 1
+        assert_eq!(eval(&[0x77, 0x08, 0x94, 0x01], None, &stack), Some(0x34));
+        // The CFA is on the stack for register rules: DW_OP_lit8; DW_OP_plus; DW_OP_deref
+        assert_eq!(eval(&[0x38, 0x22, 0x06], Some(0x10), &stack), Some(0x1234));
+        // DW_OP_call_frame_cfa; DW_OP_lit8; DW_OP_plus
+        assert_eq!(eval(&[0x9c, 0x38, 0x22], Some(0x10), &stack), Some(0x18));
+        assert_eq!(eval(&[0x9c, 0x38, 0x22], None, &stack), None);
+        // An endless loop of reads: DW_OP_lit0; DW_OP_deref; DW_OP_skip -4
+        assert_eq!(eval(&[0x30, 0x06, 0x2f, 0xfc, 0xff], None, &stack), None);
+    }
+
+    #[rustfmt::skip]
+    fn cfi_index_test_debug_frame() -> Vec<u8> {
+        vec![
+            // CIE: version 1, code alignment 4, data alignment -8, return address in x30
+            0x0c, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x04, 0x78, 0x1e,
+            // DW_CFA_def_cfa: sp + 0
+            0x0c, 0x1f, 0x00,
+            // FDE for 0x900..0x920
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // FDE for 0x800..0x810
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ]
+    }
+
+    #[test]
+    fn test_cfi_index() {
+        let debug_frame = cfi_index_test_debug_frame();
+        let index = DwarfCfiIndex::try_from_debug_frame_data(
+            &debug_frame,
+            &DwarfCfiSectionAddresses::default(),
+            0x100,
+        )
+        .unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(
+            index.iter().collect::<Vec<_>>(),
+            vec![(0x700, 0x28), (0x800, 0x10)]
+        );
+        assert_eq!(index.lookup(0x6ff), None);
+        assert_eq!(index.lookup(0x704), Some(0x28));
+        assert_eq!(index.lookup(0x800), Some(0x10));
+
+        let bytes = index.to_bytes();
+        assert_eq!(DwarfCfiIndex::from_bytes(&bytes), Ok(index));
+        assert_eq!(
+            DwarfCfiIndex::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(DwarfCfiIndexError::InvalidSerializedIndex)
+        );
+    }
+
+    #[cfg(feature = "macho")]
+    #[test]
+    fn test_fde_linear_search() {
+        let debug_frame = cfi_index_test_debug_frame();
+
+        // Without an index, the FDEs are found with a linear search.
+        let mut unwind_context = UnwindContext::<usize, StoreOnHeap>::new_in();
+        let unwinder = DwarfUnwinder::<_, crate::aarch64::ArchAarch64, _>::new(
+            EndianSlice::new(&debug_frame, LittleEndian),
+            UnwindSectionType::DebugFrame,
+            None,
+            &mut unwind_context,
+            BaseAddresses::default(),
+            0x100,
+        );
+        assert_eq!(unwinder.find_fde_offset_with_linear_search(0x6ff), None);
+        assert_eq!(
+            unwinder.find_fde_offset_with_linear_search(0x704),
+            Some(0x28)
+        );
+        assert_eq!(
+            unwinder.find_fde_offset_with_linear_search(0x800),
+            Some(0x10)
+        );
+    }
+
+    #[test]
+    fn test_data_relative_fde_addresses() {
+        #[rustfmt::skip]
+        let eh_frame = vec![
+            // CIE: augmentation "zR", code alignment 1, data alignment -8, return address
+            // in r16, FDE pointer encoding DW_EH_PE_datarel | DW_EH_PE_sdata4
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, b'z', b'R', 0x00,
+            0x01, 0x78, 0x10, 0x01, 0x3b,
+            // DW_CFA_def_cfa: rsp + 8
+            0x0c, 0x07, 0x08,
+            // FDE for data base + 0x100..0x120
+            0x10, 0x00, 0x00, 0x00, 0x18, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let index_with = |section_addresses| {
+            DwarfCfiIndex::try_from_eh_frame_data(&eh_frame, &section_addresses, 0)
+                .map(|index| index.iter().collect::<Vec<_>>())
+        };
+        assert_eq!(
+            index_with(DwarfCfiSectionAddresses {
+                got: 0x2000,
+                data: 0x3000,
+                ..Default::default()
+            }),
+            Ok(vec![(0x2100, 0x14)])
+        );
+        assert_eq!(
+            index_with(DwarfCfiSectionAddresses {
+                data: 0x3000,
+                ..Default::default()
+            }),
+            Ok(vec![(0x3100, 0x14)])
+        );
+        assert_eq!(
+            index_with(DwarfCfiSectionAddresses::default()),
+            Err(DwarfCfiIndexError::DataBaseAddressMissing)
+        );
     }
 }
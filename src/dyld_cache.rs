@@ -0,0 +1,196 @@
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::{Deref, Range};
+
+use object::read::macho::DyldCache;
+use object::{Endianness, Object, ObjectSection, ObjectSegment};
+
+use crate::{CodeId, MappedRange, Module, ModuleSectionInfo};
+
+/// The [`ModuleSectionInfo`] for an image in the dyld shared cache.
+///
+/// The segments of an image in the dyld shared cache are not contiguous: the `__TEXT`
+/// segments of all images are grouped together, and so are the `__DATA_CONST` and
+/// `__LINKEDIT` segments, possibly in different subcache files. Section addresses are
+/// reported as they are stated in the cache, and section data is read from whichever
+/// cache file contains it.
+pub struct DyldCacheImageSectionInfo<'data> {
+    file: object::File<'data>,
+    base_svma: u64,
+}
+
+impl<'data> DyldCacheImageSectionInfo<'data> {
+    fn segment(&self, name: &[u8]) -> Option<object::Segment<'data, '_>> {
+        self.file
+            .segments()
+            .find(|segment| segment.name_bytes() == Ok(Some(name)))
+    }
+
+    fn section_bytes(&self, name: &[u8]) -> Option<&'data [u8]> {
+        self.file.section_by_name_bytes(name)?.data().ok()
+    }
+
+    fn segment_bytes(&self, name: &[u8]) -> Option<&'data [u8]> {
+        self.segment(name)?.data().ok()
+    }
+}
+
+impl<'data, D: From<&'data [u8]>> ModuleSectionInfo<D> for DyldCacheImageSectionInfo<'data> {
+    fn base_svma(&self) -> u64 {
+        self.base_svma
+    }
+
+    fn section_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        let section = self.file.section_by_name_bytes(name)?;
+        Some(section.address()..section.address() + section.size())
+    }
+
+    fn section_data(&mut self, name: &[u8]) -> Option<D> {
+        self.section_bytes(name).map(D::from)
+    }
+
+    fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        let segment = self.segment(name)?;
+        Some(segment.address()..segment.address() + segment.size())
+    }
+
+    fn segment_data(&mut self, name: &[u8]) -> Option<D> {
+        self.segment_bytes(name).map(D::from)
+    }
+
+    fn code_id(&mut self) -> Option<CodeId> {
+        self.file.mach_uuid().ok()?.map(CodeId::MachoUuid)
+    }
+}
+
+/// Creates a [`Module`] for each image in a dyld shared cache, so that the images can be
+/// added to an unwinder without extracting them from the cache first.
+///
+/// `main_cache_data` is the contents of the main cache file, e.g.
+/// `dyld_shared_cache_arm64e`, and `subcache_data` the contents of its subcache files
+/// (`.01`, `.02`, ...) in order. `slide` is the amount by which the cache was shifted
+/// when it was mapped into the process, i.e. the difference between the actual addresses
+/// and the addresses stated in the cache.
+///
+/// The address range of each module only covers its `__TEXT` segment. The other segments
+/// of an image are far away from its `__TEXT` segment, and between them lie the segments
+/// of other images, so they can't be part of the module's address range. This is fine for
+/// unwinding, which only needs to find the module for code addresses.
+///
+/// Images without a `__TEXT` segment are skipped.
+pub fn dyld_cache_modules<'data, D>(
+    main_cache_data: &'data [u8],
+    subcache_data: &[&'data [u8]],
+    slide: u64,
+) -> Result<Vec<Module<D>>, object::Error>
+where
+    D: From<&'data [u8]> + Deref<Target = [u8]>,
+{
+    dyld_cache_modules_impl(main_cache_data, subcache_data, slide, |section_info| {
+        section_info
+    })
+}
+
+/// Like [`dyld_cache_modules`], but for cache files which the caller mapped into memory.
+/// The section data of the modules refers to the mappings instead of being copied.
+///
+/// `main_cache` is the mapping of the main cache file and `subcaches` the mappings of
+/// its subcache files, in order.
+pub fn dyld_cache_modules_mapped<M>(
+    main_cache: &Arc<M>,
+    subcaches: &[Arc<M>],
+    slide: u64,
+) -> Result<Vec<Module<MappedRange<M>>>, object::Error>
+where
+    M: Deref<Target = [u8]>,
+{
+    let subcache_data: Vec<&[u8]> = subcaches.iter().map(|subcache| &***subcache).collect();
+    dyld_cache_modules_impl(main_cache, &subcache_data, slide, |inner| {
+        MappedSectionInfo {
+            inner,
+            main_cache,
+            subcaches,
+        }
+    })
+}
+
+fn dyld_cache_modules_impl<'data, D, S>(
+    main_cache_data: &'data [u8],
+    subcache_data: &[&'data [u8]],
+    slide: u64,
+    section_info: impl Fn(DyldCacheImageSectionInfo<'data>) -> S,
+) -> Result<Vec<Module<D>>, object::Error>
+where
+    D: Deref<Target = [u8]>,
+    S: ModuleSectionInfo<D>,
+{
+    let cache = DyldCache::<Endianness>::parse(main_cache_data, subcache_data)?;
+    let mut modules = Vec::new();
+    for image in cache.images() {
+        let path = image.path()?;
+        let file = image.parse_object()?;
+        let Some(text_segment_svma) = file
+            .segments()
+            .find(|segment| segment.name_bytes() == Ok(Some(b"__TEXT")))
+            .map(|segment| segment.address()..segment.address() + segment.size())
+        else {
+            continue;
+        };
+        // Relative addresses in mach-O images are relative to the mach header, which is
+        // at the start of the __TEXT segment.
+        let base_svma = text_segment_svma.start;
+        let avma_range =
+            text_segment_svma.start.wrapping_add(slide)..text_segment_svma.end.wrapping_add(slide);
+        modules.push(Module::new(
+            path.to_string(),
+            avma_range,
+            base_svma.wrapping_add(slide),
+            section_info(DyldCacheImageSectionInfo { file, base_svma }),
+        ));
+    }
+    Ok(modules)
+}
+
+/// Wraps the section info of an image and returns its section data as ranges of the
+/// mapped cache files.
+struct MappedSectionInfo<'data, M> {
+    inner: DyldCacheImageSectionInfo<'data>,
+    main_cache: &'data Arc<M>,
+    subcaches: &'data [Arc<M>],
+}
+
+impl<M: Deref<Target = [u8]>> MappedSectionInfo<'_, M> {
+    /// Find the cache file which contains `data`.
+    fn mapped_range(&self, data: &[u8]) -> Option<MappedRange<M>> {
+        core::iter::once(self.main_cache)
+            .chain(self.subcaches)
+            .find_map(|mapping| MappedRange::from_subslice(mapping, data))
+    }
+}
+
+impl<M: Deref<Target = [u8]>> ModuleSectionInfo<MappedRange<M>> for MappedSectionInfo<'_, M> {
+    fn base_svma(&self) -> u64 {
+        self.inner.base_svma
+    }
+
+    fn section_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        ModuleSectionInfo::<&[u8]>::section_svma_range(&mut self.inner, name)
+    }
+
+    fn section_data(&mut self, name: &[u8]) -> Option<MappedRange<M>> {
+        self.mapped_range(self.inner.section_bytes(name)?)
+    }
+
+    fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        ModuleSectionInfo::<&[u8]>::segment_svma_range(&mut self.inner, name)
+    }
+
+    fn segment_data(&mut self, name: &[u8]) -> Option<MappedRange<M>> {
+        self.mapped_range(self.inner.segment_bytes(name)?)
+    }
+
+    fn code_id(&mut self) -> Option<CodeId> {
+        ModuleSectionInfo::<&[u8]>::code_id(&mut self.inner)
+    }
+}
@@ -1,23 +1,45 @@
 use crate::dwarf::DwarfUnwinderError;
+use crate::frame_info::FallbackReason;
+#[cfg(feature = "go")]
+use crate::go::GoPclntabUnwinderError;
 #[cfg(feature = "macho")]
 use crate::macho::CompactUnwindInfoUnwinderError;
+use crate::memory_reader::MemoryReadError;
 #[cfg(feature = "pe")]
 use crate::pe::PeUnwinderError;
 
 /// The error type used in this crate.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Error {
-    CouldNotReadStack(u64),
+    CouldNotReadStack(u64, MemoryReadError),
     FramepointerUnwindingMovedBackwards,
     DidNotAdvance,
     IntegerOverflow,
     ReturnAddressIsNull,
+    /// The frame's unwind rule reads the frame record at the frame pointer, but the
+    /// aarch64 frame pointer isn't known to hold a frame record. See
+    /// [`UnwindRegsAarch64::fp_is_valid`](crate::aarch64::UnwindRegsAarch64::fp_is_valid).
+    FramePointerNotValid,
+    /// The memory reader reported that the return address is not in executable memory.
+    /// See [`MemoryReader::is_executable`](crate::MemoryReader::is_executable).
+    ReturnAddressNotExecutable(u64),
+    /// The budget of the iterator was exhausted. See
+    /// [`UnwindIterator::with_budget`](crate::UnwindIterator::with_budget).
+    Timeout,
+    /// The frame would have been unwound with the fallback rule, and the fallback
+    /// policy is [`FallbackPolicy::ReturnError`](crate::FallbackPolicy::ReturnError).
+    UnreliableFrame(FallbackReason),
+    /// The frame's unwind rule needs a register whose value isn't known. See
+    /// [`Unwinder::unwind_frame_with_partial_regs`](crate::Unwinder::unwind_frame_with_partial_regs).
+    MissingRegisterValue,
 }
 
 impl core::fmt::Display for Error {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
-            Self::CouldNotReadStack(addr) => write!(f, "Could not read stack memory at 0x{addr:x}"),
+            Self::CouldNotReadStack(addr, err) => {
+                write!(f, "Could not read stack memory at 0x{addr:x}: {err}")
+            }
             Self::FramepointerUnwindingMovedBackwards => {
                 write!(f, "Frame pointer unwinding moved backwards")
             }
@@ -27,6 +49,34 @@ impl core::fmt::Display for Error {
             ),
             Self::IntegerOverflow => write!(f, "Unwinding caused integer overflow"),
             Self::ReturnAddressIsNull => write!(f, "Return address is null"),
+            Self::FramePointerNotValid => write!(
+                f,
+                "The frame pointer was not recovered reliably and may be a scratch register"
+            ),
+            Self::ReturnAddressNotExecutable(address) => write!(
+                f,
+                "Return address 0x{address:x} is not in executable memory"
+            ),
+            Self::Timeout => write!(f, "The unwinding budget was exhausted"),
+            Self::UnreliableFrame(reason) => {
+                write!(
+                    f,
+                    "The frame could only be unwound with the fallback rule: "
+                )?;
+                match reason {
+                    FallbackReason::NoModule => write!(f, "the address is not in any module"),
+                    FallbackReason::NoUnwindData => {
+                        write!(f, "the module has no unwind information")
+                    }
+                    FallbackReason::UnwindInfoError => {
+                        write!(f, "the module's unwind information could not be used")
+                    }
+                }
+            }
+            Self::MissingRegisterValue => write!(
+                f,
+                "The unwind rule needs a register whose value is not known"
+            ),
         }
     }
 }
@@ -34,6 +84,9 @@ impl core::fmt::Display for Error {
 #[cfg(feature = "std")]
 impl std::error::Error for Error {}
 
+/// An error which occurred while looking up or interpreting a module's unwind
+/// information. These errors don't end unwinding; they cause the fallback rule to be
+/// used instead.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UnwinderError {
     #[cfg(feature = "macho")]
@@ -41,11 +94,14 @@ pub enum UnwinderError {
     Dwarf(DwarfUnwinderError),
     #[cfg(feature = "pe")]
     Pe(PeUnwinderError),
+    #[cfg(feature = "go")]
+    Go(GoPclntabUnwinderError),
     #[cfg(feature = "macho")]
     NoDwarfData,
     NoModuleUnwindData,
     EhFrameHdrCouldNotFindAddress,
     DwarfCfiIndexCouldNotFindAddress,
+    NoPrologueFound,
 }
 
 impl core::fmt::Display for UnwinderError {
@@ -58,6 +114,8 @@ impl core::fmt::Display for UnwinderError {
             Self::Dwarf(err) => write!(f, "DWARF unwinding failed: {err}"),
             #[cfg(feature = "pe")]
             Self::Pe(err) => write!(f, "PE unwinding failed: {err}"),
+            #[cfg(feature = "go")]
+            Self::Go(err) => write!(f, "Go pclntab unwinding failed: {err}"),
             #[cfg(feature = "macho")]
             Self::NoDwarfData => write!(
                 f,
@@ -74,6 +132,10 @@ impl core::fmt::Display for UnwinderError {
                 f,
                 "Failed to look up the address in the DwarfCfiIndex search table"
             ),
+            Self::NoPrologueFound => write!(
+                f,
+                "Could not find a function prologue to synthesize an unwind rule from"
+            ),
         }
     }
 }
@@ -91,6 +153,13 @@ impl From<PeUnwinderError> for UnwinderError {
     }
 }
 
+#[cfg(feature = "go")]
+impl From<GoPclntabUnwinderError> for UnwinderError {
+    fn from(e: GoPclntabUnwinderError) -> Self {
+        Self::Go(e)
+    }
+}
+
 #[cfg(feature = "macho")]
 impl From<CompactUnwindInfoUnwinderError> for UnwinderError {
     fn from(e: CompactUnwindInfoUnwinderError) -> Self {
@@ -110,6 +179,8 @@ impl std::error::Error for UnwinderError {
             Self::Dwarf(e) => Some(e),
             #[cfg(feature = "pe")]
             Self::Pe(e) => Some(e),
+            #[cfg(feature = "go")]
+            Self::Go(e) => Some(e),
             _ => None,
         }
     }
@@ -0,0 +1,33 @@
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::FrameAddress;
+
+/// The type of the callback which unwinds frames in the address range of a foreign
+/// unwinder, e.g. `add_foreign_unwinder` on
+/// [`UnwinderX86_64`](crate::x86_64::UnwinderX86_64::add_foreign_unwinder).
+///
+/// The callback receives the address of the frame, the registers of the frame and the
+/// stack memory. It sets the registers to the values of the caller frame and returns the
+/// return address, or `None` if the stack ends in this frame.
+pub type ForeignUnwindCallback<Regs> = dyn Fn(FrameAddress, &mut Regs, &mut dyn MemoryReader) -> Result<Option<u64>, Error>
+    + Send
+    + Sync;
+
+/// A callback which unwinds the frames in an address range whose code framehop can't
+/// unwind on its own, e.g. the interpreter and the compiled code of a JVM.
+pub struct ForeignUnwinder<Regs> {
+    pub avma_range: Range<u64>,
+    pub callback: Arc<ForeignUnwindCallback<Regs>>,
+}
+
+impl<Regs> Clone for ForeignUnwinder<Regs> {
+    fn clone(&self) -> Self {
+        Self {
+            avma_range: self.avma_range.clone(),
+            callback: self.callback.clone(),
+        }
+    }
+}
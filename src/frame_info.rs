@@ -0,0 +1,104 @@
+use alloc::sync::Arc;
+use core::ops::Range;
+
+use crate::error::UnwinderError;
+use crate::unwinder::UnwindDataKind;
+
+/// Information about how a single frame was unwound. This is filled in by
+/// [`Unwinder::unwind_frame_with_info`](crate::Unwinder::unwind_frame_with_info).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameUnwindInfo {
+    /// Whether the unwind rule for this frame was found in the cache. If it was,
+    /// the other information about how the rule was computed is not available.
+    pub from_cache: bool,
+    /// If the module's unwind information could not be used for this frame and the
+    /// fallback rule was used instead, this contains details about what went wrong.
+    pub error_details: Option<UnwindErrorDetails>,
+    /// If the fallback rule was used for this frame, this says why, e.g. to tell
+    /// "the module wasn't registered" apart from "the module is stripped".
+    pub fallback_reason: Option<FallbackReason>,
+    /// How much the caller's return address can be trusted, based on how it was found.
+    pub confidence: FrameConfidence,
+    /// Whether unwinding stopped because the frame is in a module of a translator such
+    /// as Rosetta 2, see [`Module::is_rosetta`](crate::Module::is_rosetta). In that case
+    /// the stack is incomplete, even though no caller was returned.
+    pub reached_translation_boundary: bool,
+    /// Whether unwinding stopped because the frame is the WOW64 transition frame of a
+    /// 32-bit thread, see [`Module::is_wow64_transition`](crate::Module::is_wow64_transition).
+    /// In that case the stack continues in 32-bit code, even though no caller was returned.
+    pub reached_wow64_transition: bool,
+    /// The number of stack bytes which the frame uses, i.e. the caller's stack pointer
+    /// minus the stack pointer of this frame, e.g. for memory profilers which attribute
+    /// stack usage to functions. This is `None` if no caller was found, if the stack
+    /// pointer moved backwards, or if the caller is on a different stack.
+    pub frame_size: Option<u64>,
+}
+
+/// How much a frame can be trusted, based on the strategy which was used to find it and
+/// on the checks it passed. Crash analysis can use this to weight frames.
+///
+/// The variants are ordered from the most to the least confident, so the maximum of two
+/// confidences is the lower one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FrameConfidence {
+    /// The frame was found with the module's unwind information, or it's the
+    /// instruction pointer.
+    #[default]
+    Certain,
+    /// The frame was found without the module's unwind information, and the return
+    /// address is in a known module or JIT range. This is the case for the fallback
+    /// rule, which usually follows the frame pointer, for the rules of JIT ranges and
+    /// of the frame-pointer-only mode, for predicted rules and for foreign unwinders.
+    Likely,
+    /// The frame was found like a [`FrameConfidence::Likely`] frame, but the return
+    /// address isn't in any known module, or it was unwound from such a frame.
+    Guessed,
+}
+
+/// Why the fallback rule was used to unwind a frame, see
+/// [`FrameUnwindInfo::fallback_reason`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FallbackReason {
+    /// The address isn't in any module, e.g. because the module which contains it
+    /// wasn't added to the unwinder, or because the code was generated at runtime.
+    NoModule,
+    /// The address is in a module which has no unwind information, e.g. because it
+    /// is stripped.
+    NoUnwindData,
+    /// The module's unwind information could not be used, see
+    /// [`FrameUnwindInfo::error_details`].
+    UnwindInfoError,
+}
+
+/// What the unwinder does when a frame would be unwound with the fallback rule, see
+/// e.g. [`UnwinderX86_64::set_fallback_policy`](crate::x86_64::UnwinderX86_64::set_fallback_policy).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FallbackPolicy {
+    /// Unwind the frame with the fallback rule, usually by following the frame pointer.
+    #[default]
+    UseFallback,
+    /// End the stack at the frame, as if it was the root of the stack.
+    StopStack,
+    /// Fail with [`Error::UnreliableFrame`](crate::Error::UnreliableFrame).
+    ReturnError,
+}
+
+/// Details about an error which occurred while using a module's unwind information,
+/// and which caused the fallback rule to be used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnwindErrorDetails {
+    /// The name of the module containing the lookup address.
+    pub module_name: Arc<str>,
+    /// The address range of the module containing the lookup address.
+    pub module_avma_range: Range<u64>,
+    /// The kind of unwind information which the module has.
+    pub unwind_data_kind: UnwindDataKind,
+    /// The address which was looked up (AVMA). For return addresses, this is the
+    /// return address minus one.
+    pub lookup_address: u64,
+    /// The lookup address, relative to the module's base address.
+    pub relative_lookup_address: u32,
+    /// The error.
+    pub error: UnwinderError,
+}
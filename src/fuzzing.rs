@@ -0,0 +1,237 @@
+//! Entry points for fuzzing the unwind data parsers with untrusted input, e.g. from the
+//! unwind sections of a module in a minidump. They must never panic, and they only
+//! depend on their arguments.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::aarch64::{CacheAarch64, UnwindRegsAarch64, UnwinderAarch64};
+use crate::unwinder::{ExplicitModuleSectionInfo, Module, Unwinder};
+use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+/// The stated addresses of the fake module which holds the fuzzed sections.
+const TEXT_SVMA: Range<u64> = 0x1000..0x20_0000;
+const EH_FRAME_SVMA: u64 = 0x20_0000;
+const EH_FRAME_HDR_SVMA: u64 = 0x30_0000;
+const MODULE_AVMA: Range<u64> = 0..0x40_0000;
+
+/// The maximum number of frames to unwind, so that a looping stack terminates.
+const MAX_FRAMES: usize = 16;
+
+/// Returns a deterministic, address-dependent value for every stack read.
+fn read_stack(address: u64) -> Result<u64, ()> {
+    match address {
+        0 => Err(()),
+        _ => Ok(address.wrapping_mul(0x9e37_79b9_7f4a_7c15).rotate_left(17)),
+    }
+}
+
+/// Unwind up to [`MAX_FRAMES`] frames from `pc` with both unwinders and a module with
+/// the given sections.
+fn unwind_module(section_info: ExplicitModuleSectionInfo<Vec<u8>>, pc: u64, regs: [u64; 3]) {
+    let [sp, fp, lr] = regs;
+
+    let mut unwinder = UnwinderX86_64::new();
+    unwinder.add_module(Module::new(
+        String::from("fuzz"),
+        MODULE_AVMA,
+        0,
+        section_info.clone(),
+    ));
+    let mut cache = CacheX86_64::<_>::new();
+    let regs = UnwindRegsX86_64::new(pc, sp, fp);
+    unwinder
+        .iter_frames(pc, regs, &mut cache, &mut read_stack)
+        .collect_stack(MAX_FRAMES);
+
+    let mut unwinder = UnwinderAarch64::new();
+    unwinder.add_module(Module::new(
+        String::from("fuzz"),
+        MODULE_AVMA,
+        0,
+        section_info,
+    ));
+    let mut cache = CacheAarch64::<_>::new();
+    let regs = UnwindRegsAarch64::new(lr, sp, fp);
+    unwinder
+        .iter_frames(pc, regs, &mut cache, &mut read_stack)
+        .collect_stack(MAX_FRAMES);
+}
+
+/// Unwind from `pc` in a module whose `__unwind_info` section is `bytes`.
+#[cfg(feature = "macho")]
+pub fn fuzz_unwind_cui(bytes: &[u8], pc: u64) {
+    let section_info = ExplicitModuleSectionInfo {
+        text_svma: Some(TEXT_SVMA),
+        unwind_info: Some(bytes.to_vec()),
+        ..Default::default()
+    };
+    unwind_module(section_info, pc, [0x8000, 0x8010, 0x1234]);
+}
+
+/// Unwind from `pc` with the stack pointer, frame pointer and link register in `regs`,
+/// in a module whose `.eh_frame` section is `bytes`. The unwinder builds its own index
+/// of the FDEs.
+pub fn fuzz_unwind_eh_frame(bytes: &[u8], pc: u64, regs: [u64; 3]) {
+    let section_info = ExplicitModuleSectionInfo {
+        text_svma: Some(TEXT_SVMA),
+        eh_frame_svma: Some(EH_FRAME_SVMA..EH_FRAME_SVMA + bytes.len() as u64),
+        eh_frame: Some(bytes.to_vec()),
+        ..Default::default()
+    };
+    unwind_module(section_info, pc, regs);
+}
+
+/// Like [`fuzz_unwind_eh_frame`], but with an `.eh_frame_hdr` section. The first two
+/// bytes of `bytes` are the little-endian length of the `.eh_frame_hdr` data, which
+/// follows them; the rest is the `.eh_frame` data.
+pub fn fuzz_unwind_eh_frame_hdr(bytes: &[u8], pc: u64, regs: [u64; 3]) {
+    let Some((len, rest)) = bytes.split_first_chunk::<2>() else {
+        return;
+    };
+    let hdr_len = usize::from(u16::from_le_bytes(*len)).min(rest.len());
+    let (eh_frame_hdr, eh_frame) = rest.split_at(hdr_len);
+    let section_info = ExplicitModuleSectionInfo {
+        text_svma: Some(TEXT_SVMA),
+        eh_frame_svma: Some(EH_FRAME_SVMA..EH_FRAME_SVMA + eh_frame.len() as u64),
+        eh_frame: Some(eh_frame.to_vec()),
+        eh_frame_hdr_svma: Some(EH_FRAME_HDR_SVMA..EH_FRAME_HDR_SVMA + eh_frame_hdr.len() as u64),
+        eh_frame_hdr: Some(eh_frame_hdr.to_vec()),
+        ..Default::default()
+    };
+    unwind_module(section_info, pc, regs);
+}
+
+/// Unwind from `pc` in a module without unwind information, whose `.text` section is
+/// `bytes`, so that the rules are synthesized from the instructions around `pc`. The
+/// stated text range is larger than `bytes`. If `function_starts` is empty, the
+/// function starts are guessed.
+pub fn fuzz_prologue_analysis(bytes: &[u8], pc: u64, function_starts: &[u64]) {
+    let section_info = ExplicitModuleSectionInfo {
+        text_svma: Some(TEXT_SVMA),
+        text: Some(bytes.to_vec()),
+        synthesize_rules_from_prologues: true,
+        function_starts: (!function_starts.is_empty()).then(|| function_starts.to_vec()),
+        ..Default::default()
+    };
+    unwind_module(section_info, pc, [0x8000, 0x8010, 0x1234]);
+}
+
+/// Unwind from `pc` with the stack pointer, frame pointer and link register in `regs`,
+/// in a module whose `.gopclntab` section is `bytes`.
+#[cfg(feature = "go")]
+pub fn fuzz_go_pclntab(bytes: &[u8], pc: u64, regs: [u64; 3]) {
+    let section_info = ExplicitModuleSectionInfo {
+        text_svma: Some(TEXT_SVMA),
+        gopclntab: Some(bytes.to_vec()),
+        ..Default::default()
+    };
+    unwind_module(section_info, pc, regs);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fuzz_entry_points_handle_garbage() {
+        let inputs: [&[u8]; 4] = [
+            &[],
+            &[0xff; 64],
+            // A truncated CIE.
+            &[0x14, 0, 0, 0, 0, 0, 0, 0, 1],
+            // An eh_frame_hdr with a huge FDE count and no table.
+            &[4, 0, 1, 0x1b, 0x03, 0x3b, 0xff, 0xff, 0xff, 0x7f],
+        ];
+        for bytes in inputs {
+            for pc in [0, 0x1000, 0x1_0000, u64::MAX] {
+                #[cfg(feature = "macho")]
+                fuzz_unwind_cui(bytes, pc);
+                fuzz_unwind_eh_frame(bytes, pc, [0x8000, 0x8010, 0x1234]);
+                fuzz_unwind_eh_frame_hdr(bytes, pc, [u64::MAX, 0, 0]);
+                fuzz_prologue_analysis(bytes, pc, &[]);
+                fuzz_prologue_analysis(bytes, pc, &[0x1000, 0x1100, u64::MAX]);
+                #[cfg(feature = "go")]
+                fuzz_go_pclntab(bytes, pc, [0x8000, 0x8010, 0x1234]);
+            }
+        }
+    }
+
+    #[cfg(feature = "go")]
+    #[test]
+    fn test_fuzz_go_pclntab_overflowing_header() {
+        // A Go 1.20 header with 64-bit pointers, one function and a text start close to
+        // the end of the address space, followed by a function table at offset 0x48.
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0xffff_fff1_u32.to_le_bytes());
+        bytes.extend_from_slice(&[0, 0, 1, 8]);
+        for field in [1, 0, u64::MAX - 0x10, 0, 0, 0, 0x48, 0x48] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[0, 0, 0, 0, 0x10, 0, 0, 0, 0x40, 0, 0, 0, 0, 0, 0, 0]);
+        for pc in [TEXT_SVMA.start, TEXT_SVMA.start + 0x20] {
+            fuzz_go_pclntab(&bytes, pc, [0x8000, 0x8010, 0x1234]);
+        }
+    }
+
+    #[test]
+    fn test_fuzz_entry_points_never_panic() {
+        // A deterministic xorshift generator, so that failures are reproducible.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        // Bytes which are common in prologues and epilogues, so that the instruction
+        // analysis gets past the first instruction.
+        const INTERESTING: [u8; 12] = [
+            0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3, 0x41, 0x83, 0xc4, 0xfd, 0x7b, 0xa9,
+        ];
+        for _ in 0..500 {
+            let len = (next() % 96) as usize;
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| match next() % 3 {
+                    0 => INTERESTING[(next() % 12) as usize],
+                    _ => next() as u8,
+                })
+                .collect();
+            let pc = TEXT_SVMA.start + next() % 0x200;
+            let regs = [next(), next() % 0x10000, next()];
+            #[cfg(feature = "macho")]
+            fuzz_unwind_cui(&bytes, pc);
+            fuzz_unwind_eh_frame(&bytes, pc, regs);
+            fuzz_unwind_eh_frame_hdr(&bytes, pc, regs);
+            fuzz_prologue_analysis(&bytes, pc, &[]);
+            fuzz_prologue_analysis(&bytes, pc, &[TEXT_SVMA.start + next() % 0x100]);
+            #[cfg(feature = "go")]
+            fuzz_go_pclntab(&random_pclntab_header(&mut next, &bytes), pc, regs);
+        }
+    }
+
+    /// Prepend a pclntab header with random fields to `bytes`, so that the Go unwinder
+    /// gets past the header checks. The offsets are mostly small, so that they point
+    /// into `bytes`, but some fields are huge.
+    #[cfg(feature = "go")]
+    fn random_pclntab_header(next: &mut impl FnMut() -> u64, bytes: &[u8]) -> Vec<u8> {
+        let magic = [0xffff_fffa_u32, 0xffff_fff0, 0xffff_fff1][(next() % 3) as usize];
+        let ptr_size = [4, 8][(next() % 2) as usize];
+        let mut data = magic.to_le_bytes().to_vec();
+        data.extend_from_slice(&[0, 0, 1 << (next() % 3), ptr_size]);
+        for index in 0..8 {
+            let field = match (index, next() % 4) {
+                (_, 0) => next(),
+                // The function count.
+                (0, _) => next() % 4,
+                // The text start.
+                (2, _) => TEXT_SVMA.start,
+                _ => 8 + 8 * u64::from(ptr_size) + next() % 0x40,
+            };
+            data.extend_from_slice(&field.to_le_bytes()[..usize::from(ptr_size)]);
+        }
+        data.extend_from_slice(bytes);
+        data
+    }
+}
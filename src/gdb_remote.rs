@@ -0,0 +1,528 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::aarch64::UnwindRegsAarch64;
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::x86_64::UnwindRegsX86_64;
+
+/// The size and alignment of the memory blocks which [`GdbRemoteClient`] reads and
+/// caches. A frame's reads are usually close to each other, so one `m` packet often
+/// serves several frames.
+const CACHE_BLOCK_SIZE: u64 = 256;
+
+/// A client for the GDB remote serial protocol, for unwinding the stopped threads of a
+/// gdbserver, of QEMU's gdbstub, or of an embedded debug probe.
+///
+/// The client implements [`MemoryReader`] with `m` packets, so it can be passed to
+/// [`Unwinder::iter_frames`](crate::Unwinder::iter_frames) directly. The memory is read
+/// in aligned blocks which are cached until [`GdbRemoteClient::clear_memory_cache`] is
+/// called, which has to happen whenever the target ran.
+///
+/// The register values come from the `g` packet of the current thread, see
+/// [`GdbRemoteClient::regs_x86_64`] and [`GdbRemoteClient::regs_aarch64`], and the
+/// loaded libraries from the `qXfer:libraries:read` packet, see
+/// [`GdbRemoteClient::libraries`].
+pub struct GdbRemoteClient<S> {
+    stream: S,
+    memory_cache: BTreeMap<u64, Result<Vec<u8>, MemoryReadError>>,
+}
+
+impl GdbRemoteClient<TcpStream> {
+    /// Connect to a stub which listens on a TCP socket, e.g. `gdbserver :1234 ...` or
+    /// `qemu -gdb tcp::1234`.
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S: Read + Write> GdbRemoteClient<S> {
+    /// Use a connection to a stub, e.g. a serial port.
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            memory_cache: BTreeMap::new(),
+        }
+    }
+
+    /// Forget the cached memory contents. Call this after the target has run.
+    pub fn clear_memory_cache(&mut self) {
+        self.memory_cache.clear();
+    }
+
+    /// The raw contents of the `g` packet of the current thread, in the target's byte
+    /// order. Registers which the stub doesn't have are zero.
+    pub fn read_registers(&mut self) -> io::Result<Vec<u8>> {
+        let reply = self.request(b"g")?;
+        parse_hex_bytes(&reply)
+    }
+
+    /// The registers of the current thread of an x86_64 target, and its instruction
+    /// pointer. In the `g` packet, `rbp`, `rsp` and `rip` are the registers 6, 7 and 16.
+    pub fn regs_x86_64(&mut self) -> io::Result<(u64, UnwindRegsX86_64)> {
+        let registers = self.read_registers()?;
+        let reg = |index| register_u64(&registers, index);
+        let ip = reg(16)?;
+        Ok((ip, UnwindRegsX86_64::new(ip, reg(7)?, reg(6)?)))
+    }
+
+    /// The registers of the current thread of an aarch64 target, and its program
+    /// counter. In the `g` packet, `x29`, `x30`, `sp` and `pc` are the registers 29 to 32.
+    pub fn regs_aarch64(&mut self) -> io::Result<(u64, UnwindRegsAarch64)> {
+        let registers = self.read_registers()?;
+        let reg = |index| register_u64(&registers, index);
+        Ok((
+            reg(32)?,
+            UnwindRegsAarch64::new(reg(30)?, reg(31)?, reg(29)?),
+        ))
+    }
+
+    /// Read `len` bytes of memory at `address` with an `m` packet. The stub can return
+    /// fewer bytes than requested if the end of the range isn't readable.
+    pub fn read_memory(&mut self, address: u64, len: usize) -> io::Result<Vec<u8>> {
+        let reply = self.request(format!("m{address:x},{len:x}").as_bytes())?;
+        parse_hex_bytes(&reply)
+    }
+
+    /// The libraries which are loaded in the target, from the `qXfer:libraries:read`
+    /// packet.
+    pub fn libraries(&mut self) -> io::Result<Vec<GdbLibrary>> {
+        let mut document = Vec::new();
+        loop {
+            let command = format!("qXfer:libraries:read::{:x},fff", document.len());
+            let reply = self.request(command.as_bytes())?;
+            let (kind, data) = reply
+                .split_first()
+                .ok_or_else(|| invalid_data("empty reply"))?;
+            document.extend_from_slice(data);
+            match kind {
+                b'm' => continue,
+                b'l' => break,
+                _ => return Err(invalid_data("unexpected qXfer reply")),
+            }
+        }
+        let document = String::from_utf8(document).map_err(|_| invalid_data("invalid UTF-8"))?;
+        Ok(parse_library_list(&document))
+    }
+
+    /// Send a packet and return the payload of the reply. Error replies `Enn` become
+    /// errors of kind [`io::ErrorKind::Other`], and empty replies, which mean that the
+    /// stub doesn't support the packet, become errors of kind
+    /// [`io::ErrorKind::Unsupported`].
+    pub fn request(&mut self, command: &[u8]) -> io::Result<Vec<u8>> {
+        self.send_packet(command)?;
+        let reply = self.receive_packet()?;
+        if reply.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "unsupported packet",
+            ));
+        }
+        if let Some(code) = error_reply_code(&reply) {
+            return Err(io::Error::other(format!("error reply E{code:02x}")));
+        }
+        Ok(reply)
+    }
+
+    fn send_packet(&mut self, command: &[u8]) -> io::Result<()> {
+        let checksum = command.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        let mut packet = Vec::with_capacity(command.len() + 4);
+        packet.push(b'$');
+        packet.extend_from_slice(command);
+        packet.extend_from_slice(format!("#{checksum:02x}").as_bytes());
+        loop {
+            self.stream.write_all(&packet)?;
+            self.stream.flush()?;
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                _ => return Err(invalid_data("expected an acknowledgement")),
+            }
+        }
+    }
+
+    fn receive_packet(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            while self.read_byte()? != b'$' {}
+            let mut payload = Vec::new();
+            let mut checksum = 0u8;
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    b => {
+                        checksum = checksum.wrapping_add(b);
+                        payload.push(b);
+                    }
+                }
+            }
+            let mut expected = [0; 2];
+            self.stream.read_exact(&mut expected)?;
+            if parse_hex_u64(&expected) == Some(u64::from(checksum)) {
+                self.stream.write_all(b"+")?;
+                return decode_payload(&payload);
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    fn read_cached(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        let block_start = address - address % CACHE_BLOCK_SIZE;
+        let offset = (address - block_start) as usize;
+        if offset + buf.len() > CACHE_BLOCK_SIZE as usize {
+            // The read crosses a block boundary, so read it on its own.
+            let bytes = self
+                .read_memory(address, buf.len())
+                .map_err(|err| memory_read_error(&err))?;
+            return copy_bytes(&bytes, 0, buf);
+        }
+        if !self.memory_cache.contains_key(&block_start) {
+            let block = self
+                .read_memory(block_start, CACHE_BLOCK_SIZE as usize)
+                .map_err(|err| memory_read_error(&err));
+            self.memory_cache.insert(block_start, block);
+        }
+        match &self.memory_cache[&block_start] {
+            Ok(block) if offset + buf.len() <= block.len() => copy_bytes(block, offset, buf),
+            // The start of the block isn't readable, or the stub returned a shorter
+            // block, so ask for exactly these bytes.
+            _ => {
+                let bytes = self
+                    .read_memory(address, buf.len())
+                    .map_err(|err| memory_read_error(&err))?;
+                copy_bytes(&bytes, 0, buf)
+            }
+        }
+    }
+}
+
+impl<S: Read + Write> MemoryReader for GdbRemoteClient<S> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        let mut buf = [0; 8];
+        self.read_cached(address, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        let mut buf = [0; 4];
+        self.read_cached(address, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        self.read_cached(address, buf)
+    }
+}
+
+/// A library in the `qXfer:libraries:read` document of a GDB remote stub.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct GdbLibrary {
+    /// The path of the library.
+    pub name: String,
+    /// The addresses of the library's loaded segments, if the stub describes the library
+    /// by its segments. The first one is the address of the first loadable segment.
+    pub segments: Vec<u64>,
+    /// The addresses of the library's sections, if the stub describes the library by its
+    /// sections, e.g. `.text`.
+    pub sections: Vec<u64>,
+}
+
+/// Parse a `<library-list>` document.
+pub fn parse_library_list(document: &str) -> Vec<GdbLibrary> {
+    let mut libraries = Vec::new();
+    for element in document.split('<').skip(1) {
+        let (tag, attributes) = element
+            .split_once(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+            .unwrap_or((element, ""));
+        match tag {
+            "library" => libraries.push(GdbLibrary {
+                name: xml_attribute(attributes, "name").unwrap_or_default(),
+                ..Default::default()
+            }),
+            "segment" | "section" => {
+                let Some(library) = libraries.last_mut() else {
+                    continue;
+                };
+                let Some(address) =
+                    xml_attribute(attributes, "address").and_then(|address| parse_number(&address))
+                else {
+                    continue;
+                };
+                match tag {
+                    "segment" => library.segments.push(address),
+                    _ => library.sections.push(address),
+                }
+            }
+            _ => {}
+        }
+    }
+    libraries
+}
+
+fn xml_attribute(attributes: &str, name: &str) -> Option<String> {
+    let mut rest = attributes;
+    loop {
+        let (key, value) = rest.split_once('=')?;
+        let value = value.trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let (value, remainder) = value[1..].split_once(quote)?;
+        if key.trim() == name {
+            return Some(
+                value
+                    .replace("&lt;", "<")
+                    .replace("&gt;", ">")
+                    .replace("&quot;", "\"")
+                    .replace("&apos;", "'")
+                    .replace("&amp;", "&"),
+            );
+        }
+        rest = remainder;
+    }
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Undo the run-length encoding and the escaping of a packet payload.
+fn decode_payload(payload: &[u8]) -> io::Result<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(payload.len());
+    let mut bytes = payload.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => {
+                let escaped = bytes.next().ok_or_else(|| invalid_data("bad escape"))?;
+                decoded.push(escaped ^ 0x20);
+            }
+            b'*' => {
+                let last = *decoded.last().ok_or_else(|| invalid_data("bad repeat"))?;
+                let count = bytes.next().ok_or_else(|| invalid_data("bad repeat"))?;
+                let count = count
+                    .checked_sub(29)
+                    .ok_or_else(|| invalid_data("bad repeat"))?;
+                decoded.extend(core::iter::repeat_n(last, count as usize));
+            }
+            _ => decoded.push(b),
+        }
+    }
+    Ok(decoded)
+}
+
+fn error_reply_code(reply: &[u8]) -> Option<u8> {
+    match reply {
+        [b'E', digits @ ..] if digits.len() == 2 => Some(parse_hex_u64(digits)? as u8),
+        _ => None,
+    }
+}
+
+fn parse_hex_u64(digits: &[u8]) -> Option<u64> {
+    u64::from_str_radix(core::str::from_utf8(digits).ok()?, 16).ok()
+}
+
+/// Parse the hex pairs of a `g` or `m` reply. The pair `xx` stands for a byte which the
+/// stub doesn't know, and becomes zero.
+fn parse_hex_bytes(hex: &[u8]) -> io::Result<Vec<u8>> {
+    hex.chunks(2)
+        .map(|pair| match pair {
+            b"xx" => Ok(0),
+            _ => parse_hex_u64(pair)
+                .filter(|_| pair.len() == 2)
+                .map(|byte| byte as u8)
+                .ok_or_else(|| invalid_data("invalid hex data")),
+        })
+        .collect()
+}
+
+fn register_u64(registers: &[u8], index: usize) -> io::Result<u64> {
+    let bytes = registers
+        .get(index * 8..index * 8 + 8)
+        .ok_or_else(|| invalid_data("g reply too short"))?;
+    Ok(u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+fn copy_bytes(bytes: &[u8], offset: usize, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+    let bytes = bytes
+        .get(offset..offset + buf.len())
+        .ok_or(MemoryReadError::Unmapped)?;
+    buf.copy_from_slice(bytes);
+    Ok(())
+}
+
+fn memory_read_error(err: &io::Error) -> MemoryReadError {
+    match err.kind() {
+        // Stubs answer reads of unmapped memory with an error reply, usually E14 (EFAULT).
+        io::ErrorKind::Other => MemoryReadError::Unmapped,
+        _ => MemoryReadError::Unknown,
+    }
+}
+
+fn invalid_data(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwinderX86_64};
+    use crate::{FrameAddress, Unwinder};
+    use alloc::collections::VecDeque;
+
+    /// A stub which answers `g`, `m` and `qXfer:libraries:read` packets.
+    struct FakeStub {
+        registers: Vec<u64>,
+        memory: (u64, Vec<u8>),
+        libraries: &'static str,
+        incoming: Vec<u8>,
+        outgoing: VecDeque<u8>,
+        memory_packet_count: usize,
+    }
+
+    impl FakeStub {
+        fn answer(&mut self, command: &str) -> String {
+            let hex = |bytes: &[u8]| bytes.iter().map(|b| format!("{b:02x}")).collect();
+            if command == "g" {
+                let bytes: Vec<u8> = self
+                    .registers
+                    .iter()
+                    .flat_map(|r| r.to_le_bytes())
+                    .collect();
+                return hex(&bytes);
+            }
+            if let Some(args) = command.strip_prefix('m') {
+                self.memory_packet_count += 1;
+                let (address, len) = args.split_once(',').unwrap();
+                let address = u64::from_str_radix(address, 16).unwrap();
+                let len = usize::from_str_radix(len, 16).unwrap();
+                let (start, bytes) = &self.memory;
+                let Some(offset) = address.checked_sub(*start) else {
+                    return "E14".into();
+                };
+                let offset = offset as usize;
+                if offset >= bytes.len() {
+                    return "E14".into();
+                }
+                return hex(&bytes[offset..(offset + len).min(bytes.len())]);
+            }
+            if let Some(args) = command.strip_prefix("qXfer:libraries:read::") {
+                // Send the document in two parts.
+                let offset = usize::from_str_radix(args.split_once(',').unwrap().0, 16).unwrap();
+                let split = self.libraries.len() / 2;
+                return match offset {
+                    0 => format!("m{}", &self.libraries[..split]),
+                    _ => format!("l{}", &self.libraries[offset..]),
+                };
+            }
+            String::new()
+        }
+    }
+
+    impl Read for FakeStub {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.outgoing.read(buf)
+        }
+    }
+
+    impl Write for FakeStub {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.incoming.extend_from_slice(buf);
+            while let Some(start) = self.incoming.iter().position(|&b| b == b'$') {
+                let Some(end) = self.incoming.iter().position(|&b| b == b'#') else {
+                    break;
+                };
+                if self.incoming.len() < end + 3 {
+                    break;
+                }
+                let command = String::from_utf8(self.incoming[start + 1..end].to_vec()).unwrap();
+                self.incoming.drain(..end + 3);
+                let reply = self.answer(&command);
+                let checksum = reply.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+                self.outgoing.push_back(b'+');
+                self.outgoing
+                    .extend(format!("${reply}#{checksum:02x}").into_bytes());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_decode_payload() {
+        assert_eq!(decode_payload(b"0* ").unwrap(), b"0000");
+        assert_eq!(decode_payload(b"a}]b").unwrap(), b"a}b");
+        assert!(decode_payload(b"*").is_err());
+    }
+
+    #[test]
+    fn test_gdb_remote_backtrace() {
+        let mut registers = vec![0; 17];
+        registers[6] = 0x1010; // rbp
+        registers[7] = 0x1000; // rsp
+        registers[16] = 0x5000; // rip
+                                // Frame pointer chain: [0x1010] = caller bp, [0x1018] = return address.
+        let stack: Vec<u8> = [0, 0, 0x1020, 0x5100, 0, 0x5200]
+            .iter()
+            .flat_map(|value: &u64| value.to_le_bytes())
+            .collect();
+        let stub = FakeStub {
+            registers,
+            memory: (0x1000, stack),
+            libraries: r#"<library-list><library name="/lib/a&amp;b.so"><segment address="0x5000"/></library><library name='/lib/c.so'><section address="0x7000"/></library></library-list>"#,
+            incoming: Vec::new(),
+            outgoing: VecDeque::new(),
+            memory_packet_count: 0,
+        };
+        let mut client = GdbRemoteClient::new(stub);
+
+        assert_eq!(
+            client.libraries().unwrap(),
+            vec![
+                GdbLibrary {
+                    name: "/lib/a&b.so".into(),
+                    segments: vec![0x5000],
+                    sections: vec![],
+                },
+                GdbLibrary {
+                    name: "/lib/c.so".into(),
+                    segments: vec![],
+                    sections: vec![0x7000],
+                },
+            ]
+        );
+
+        let (ip, regs) = client.regs_x86_64().unwrap();
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let mut iter = unwinder.iter_frames(ip, regs, &mut cache, &mut client);
+        let mut frames = Vec::new();
+        while let Some(frame) = iter.next().unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            [
+                FrameAddress::from_instruction_pointer(0x5000),
+                FrameAddress::from_return_address(0x5100).unwrap(),
+                FrameAddress::from_return_address(0x5200).unwrap(),
+            ]
+        );
+        // The stack is in a single cached block.
+        assert_eq!(client.stream.memory_packet_count, 1);
+        assert_eq!(client.read_u64(0x900), Err(MemoryReadError::Unmapped));
+    }
+}
@@ -0,0 +1,403 @@
+use crate::arch::Arch;
+use core::ops::Deref;
+
+/// The magic number of the pclntab of Go 1.16 and 1.17.
+const MAGIC_GO_1_16: u32 = 0xffff_fffa;
+/// The magic number of the pclntab of Go 1.18 and 1.19.
+const MAGIC_GO_1_18: u32 = 0xffff_fff0;
+/// The magic number of the pclntab of Go 1.20 and later.
+const MAGIC_GO_1_20: u32 = 0xffff_fff1;
+
+/// The `funcFlag_TOPFRAME` bit of the function flags: the function is the outermost
+/// frame of a goroutine, e.g. `runtime.goexit`.
+const FUNC_FLAG_TOPFRAME: u8 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GoPclntabUnwinderError {
+    UnsupportedVersion(u32),
+    BadHeader,
+    BadFunctionTable,
+    BadFunction(u64),
+    MissingSpDeltaTable(u64),
+    BadSpDeltaTable(u64),
+    UnalignedSpDelta(i32),
+}
+
+impl core::fmt::Display for GoPclntabUnwinderError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::UnsupportedVersion(magic) => {
+                write!(f, "unsupported gopclntab magic number {magic:#x}")
+            }
+            Self::BadHeader => write!(f, "failed to parse the gopclntab header"),
+            Self::BadFunctionTable => write!(f, "failed to read the gopclntab function table"),
+            Self::BadFunction(entry) => {
+                write!(f, "failed to read the Go function at {entry:#x}")
+            }
+            Self::MissingSpDeltaTable(entry) => {
+                write!(f, "the Go function at {entry:#x} has no pcsp table")
+            }
+            Self::BadSpDeltaTable(entry) => {
+                write!(
+                    f,
+                    "failed to decode the pcsp table of the Go function at {entry:#x}"
+                )
+            }
+            Self::UnalignedSpDelta(sp_delta) => {
+                write!(f, "the Go stack frame size {sp_delta} is not aligned")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for GoPclntabUnwinderError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GoVersion {
+    Go1_16,
+    Go1_18,
+    Go1_20,
+}
+
+/// The frame of a Go function at an address, as described by the pclntab.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GoFrame {
+    /// How far the stack pointer has moved since the entry of the function, in bytes.
+    /// The return address is stored at this offset from the stack pointer on x86_64.
+    pub sp_delta: i32,
+    /// Whether the function is the outermost frame of a goroutine.
+    pub is_top_frame: bool,
+}
+
+/// The `.gopclntab` section of a Go binary (`__gopclntab` in mach-O), which the Go
+/// runtime uses to unwind its own stacks. It maps every function to a table of stack
+/// pointer deltas, which is accurate for every instruction, including in prologues and
+/// epilogues. Go doesn't emit DWARF CFI into `.eh_frame`, so the `.eh_frame` of a Go
+/// binary only covers cgo and other non-Go code.
+///
+/// Go 1.16 and later are supported.
+///
+/// Type arguments:
+///  - `D`: The type for unwind section data. This allows carrying owned data on the
+///    module, e.g. `Vec<u8>`. But it could also be a wrapper around mapped memory from
+///    a file or a different process, for example. It just needs to provide a slice of
+///    bytes via its `Deref` implementation.
+pub struct GoPclntab<D> {
+    data: D,
+    version: GoVersion,
+    /// The instruction size quantum; pc deltas in pc-value tables are multiplied by it.
+    pc_quantum: u8,
+    ptr_size: u8,
+    function_count: usize,
+    /// The address (SVMA) which the function entries are relative to, in Go 1.18+.
+    text_start: u64,
+    pctab_offset: usize,
+    /// The offset of the function table, which the function offsets are relative to.
+    pcln_offset: usize,
+}
+
+impl<D: Deref<Target = [u8]>> GoPclntab<D> {
+    /// Parse the header of the pclntab. `text_svma` is the start address of the text
+    /// section, which is used if the header doesn't state it, e.g. in position-independent
+    /// executables whose header field is only filled in by a relocation.
+    pub fn parse(data: D, text_svma: Option<u64>) -> Result<Self, GoPclntabUnwinderError> {
+        let magic = read_u32(&data, 0).ok_or(GoPclntabUnwinderError::BadHeader)?;
+        let version = match magic {
+            MAGIC_GO_1_16 => GoVersion::Go1_16,
+            MAGIC_GO_1_18 => GoVersion::Go1_18,
+            MAGIC_GO_1_20 => GoVersion::Go1_20,
+            _ => return Err(GoPclntabUnwinderError::UnsupportedVersion(magic)),
+        };
+        let (Some(&pc_quantum), Some(&ptr_size)) = (data.get(6), data.get(7)) else {
+            return Err(GoPclntabUnwinderError::BadHeader);
+        };
+        if pc_quantum == 0 || (ptr_size != 4 && ptr_size != 8) {
+            return Err(GoPclntabUnwinderError::BadHeader);
+        }
+        let header_field = |index: usize| {
+            read_ptr(&data, 8 + index * usize::from(ptr_size), ptr_size)
+                .ok_or(GoPclntabUnwinderError::BadHeader)
+        };
+        let to_usize =
+            |value: u64| usize::try_from(value).map_err(|_| GoPclntabUnwinderError::BadHeader);
+        // Go 1.18 added textStart after nfunc and nfiles.
+        let (text_start, pctab_index) = match version {
+            GoVersion::Go1_16 => (0, 5),
+            GoVersion::Go1_18 | GoVersion::Go1_20 => match header_field(2)? {
+                0 => (text_svma.ok_or(GoPclntabUnwinderError::BadHeader)?, 6),
+                text_start => (text_start, 6),
+            },
+        };
+        let function_count = to_usize(header_field(0)?)?;
+        let pcln_offset = to_usize(header_field(pctab_index + 1)?)?;
+        // The function table has an extra entry for the end of the last function, and
+        // it has to fit into the section.
+        let entry_size = Self::function_table_entry_size(version, ptr_size);
+        function_count
+            .checked_add(1)
+            .and_then(|count| count.checked_mul(entry_size))
+            .and_then(|size| size.checked_add(pcln_offset))
+            .filter(|&table_end| table_end <= data.len())
+            .ok_or(GoPclntabUnwinderError::BadFunctionTable)?;
+        Ok(Self {
+            function_count,
+            text_start,
+            pctab_offset: to_usize(header_field(pctab_index)?)?,
+            pcln_offset,
+            data,
+            version,
+            pc_quantum,
+            ptr_size,
+        })
+    }
+
+    /// The size of an entry of the function table.
+    fn function_table_entry_size(version: GoVersion, ptr_size: u8) -> usize {
+        match version {
+            GoVersion::Go1_16 => 2 * usize::from(ptr_size),
+            GoVersion::Go1_18 | GoVersion::Go1_20 => 8,
+        }
+    }
+
+    /// The section data.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The address (SVMA) which the function entries are relative to.
+    #[cfg(feature = "std")]
+    pub fn text_start(&self) -> u64 {
+        self.text_start
+    }
+
+    /// Look up the frame of the Go function containing `svma`. Returns `Ok(None)` if
+    /// the address is not in a Go function.
+    pub fn frame_for_svma(&self, svma: u64) -> Result<Option<GoFrame>, GoPclntabUnwinderError> {
+        let Some((entry, func_offset)) = self.function_for_svma(svma)? else {
+            return Ok(None);
+        };
+        let func = self
+            .pcln_offset
+            .checked_add(func_offset)
+            .ok_or(GoPclntabUnwinderError::BadFunction(entry))?;
+        let (pcsp_field, flag_field) = match self.version {
+            GoVersion::Go1_16 => (usize::from(self.ptr_size) + 12, None),
+            GoVersion::Go1_18 => (16, Some(37)),
+            GoVersion::Go1_20 => (16, Some(41)),
+        };
+        let pcsp = func
+            .checked_add(pcsp_field)
+            .and_then(|offset| read_u32(&self.data, offset))
+            .ok_or(GoPclntabUnwinderError::BadFunction(entry))?;
+        if pcsp == 0 {
+            return Err(GoPclntabUnwinderError::MissingSpDeltaTable(entry));
+        }
+        let is_top_frame = match flag_field {
+            Some(flag_field) => {
+                let flag = func
+                    .checked_add(flag_field)
+                    .and_then(|offset| self.data.get(offset))
+                    .ok_or(GoPclntabUnwinderError::BadFunction(entry))?;
+                flag & FUNC_FLAG_TOPFRAME != 0
+            }
+            None => false,
+        };
+        let sp_delta = self
+            .pctab_offset
+            .checked_add(pcsp as usize)
+            .and_then(|offset| self.pc_value(offset, entry, svma))
+            .ok_or(GoPclntabUnwinderError::BadSpDeltaTable(entry))?;
+        Ok(Some(GoFrame {
+            sp_delta,
+            is_top_frame,
+        }))
+    }
+
+    /// Binary search the function table for the function containing `svma`, and return
+    /// its entry address and the offset of its `_func` structure.
+    fn function_for_svma(&self, svma: u64) -> Result<Option<(u64, usize)>, GoPclntabUnwinderError> {
+        // Every entry is (entry address, function offset). The table has an extra entry
+        // whose address is the end of the last function.
+        let entry = |index: usize| -> Result<(u64, usize), GoPclntabUnwinderError> {
+            let entry_size = Self::function_table_entry_size(self.version, self.ptr_size);
+            let bytes = index
+                .checked_mul(entry_size)
+                .and_then(|offset| offset.checked_add(self.pcln_offset))
+                .and_then(|offset| self.data.get(offset..))
+                .ok_or(GoPclntabUnwinderError::BadFunctionTable)?;
+            let (address, func_offset) = match self.version {
+                GoVersion::Go1_16 => (
+                    read_ptr(bytes, 0, self.ptr_size),
+                    read_ptr(bytes, entry_size / 2, self.ptr_size),
+                ),
+                GoVersion::Go1_18 | GoVersion::Go1_20 => (
+                    read_u32(bytes, 0).and_then(|off| self.text_start.checked_add(u64::from(off))),
+                    read_u32(bytes, 4).map(u64::from),
+                ),
+            };
+            match (address, func_offset) {
+                (Some(address), Some(func_offset)) => Ok((address, func_offset as usize)),
+                _ => Err(GoPclntabUnwinderError::BadFunctionTable),
+            }
+        };
+        if self.function_count == 0 {
+            return Ok(None);
+        }
+        let (first, _) = entry(0)?;
+        let (end, _) = entry(self.function_count)?;
+        if svma < first || svma >= end {
+            return Ok(None);
+        }
+        // Find the last function whose entry is <= svma.
+        let (mut low, mut high) = (0, self.function_count);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if entry(mid)?.0 <= svma {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        entry(low).map(Some)
+    }
+
+    /// Decode the pc-value table at `offset` for the function starting at `entry`, and
+    /// return the value at `target`.
+    fn pc_value(&self, mut offset: usize, entry: u64, target: u64) -> Option<i32> {
+        let mut pc = entry;
+        let mut value: i32 = -1;
+        loop {
+            let is_first = pc == entry;
+            let value_delta = read_uvarint(&self.data, &mut offset)?;
+            // A zero delta ends the table, except for the first entry.
+            if value_delta == 0 && !is_first {
+                return None;
+            }
+            let value_delta = (-((value_delta & 1) as i32)) ^ ((value_delta >> 1) as i32);
+            value = value.wrapping_add(value_delta);
+            let pc_delta = read_uvarint(&self.data, &mut offset)?;
+            pc = pc.checked_add(u64::from(pc_delta) * u64::from(self.pc_quantum))?;
+            if target < pc {
+                return Some(value);
+            }
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn read_ptr(data: &[u8], offset: usize, ptr_size: u8) -> Option<u64> {
+    if ptr_size == 4 {
+        return read_u32(data, offset).map(u64::from);
+    }
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Read an unsigned LEB128 value of at most 32 bits.
+fn read_uvarint(data: &[u8], offset: &mut usize) -> Option<u32> {
+    let mut result = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*offset)?;
+        *offset += 1;
+        result |= u32::from(byte & 0x7f).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(result);
+        }
+    }
+    None
+}
+
+pub trait GoUnwinding: Arch {
+    /// The rule for unwinding a frame of a Go function, given its stack pointer delta.
+    fn rule_for_go_frame(
+        frame: GoFrame,
+        is_first_frame: bool,
+    ) -> Result<Self::UnwindRule, GoPclntabUnwinderError>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// Build a Go 1.20 pclntab with 64-bit pointers and two functions at 0x1000 and
+    /// 0x1040, which end at 0x1080. The second function has the TOPFRAME flag.
+    fn build_pclntab() -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&MAGIC_GO_1_20.to_le_bytes());
+        data.extend_from_slice(&[0, 0, 1, 8]);
+        let pctab_offset = 8 + 8 * 8;
+        // pcsp table of the first function: -1 + 1 = 0 until 0x1004, +8 = 8 until
+        // 0x1030, -8 = 0 until 0x1040.
+        let pcsp = [0x02, 0x04, 0x10, 0x2c, 0x0f, 0x10, 0x00];
+        let pcln_offset = pctab_offset + 1 + pcsp.len();
+        for field in [2, 0, 0x1000, 0, 0, 0, pctab_offset, pcln_offset] {
+            data.extend_from_slice(&(field as u64).to_le_bytes());
+        }
+        // The pcsp offsets are relative to the pctab; zero means "no table".
+        data.push(0);
+        data.extend_from_slice(&pcsp);
+        assert_eq!(data.len(), pcln_offset);
+        let func_offset = |index: usize| (3 * 8 + index * 44) as u32;
+        for (entry_offset, func_offset) in
+            [(0x0u32, func_offset(0)), (0x40, func_offset(1)), (0x80, 0)]
+        {
+            data.extend_from_slice(&entry_offset.to_le_bytes());
+            data.extend_from_slice(&func_offset.to_le_bytes());
+        }
+        for (entry_offset, flag) in [(0x0u32, 0u8), (0x40, FUNC_FLAG_TOPFRAME)] {
+            let mut func = [0u8; 44];
+            func[0..4].copy_from_slice(&entry_offset.to_le_bytes());
+            func[16..20].copy_from_slice(&1u32.to_le_bytes());
+            func[41] = flag;
+            data.extend_from_slice(&func);
+        }
+        data
+    }
+
+    #[test]
+    fn test_pclntab() {
+        let pclntab = GoPclntab::parse(build_pclntab(), None).unwrap();
+        let frame = |svma| pclntab.frame_for_svma(svma).unwrap();
+        let sp_delta = |svma| frame(svma).unwrap().sp_delta;
+        assert_eq!(frame(0xfff), None);
+        assert_eq!(frame(0x1080), None);
+        assert_eq!(sp_delta(0x1000), 0);
+        assert_eq!(sp_delta(0x1003), 0);
+        assert_eq!(sp_delta(0x1004), 8);
+        assert_eq!(sp_delta(0x102f), 8);
+        assert_eq!(sp_delta(0x1030), 0);
+        assert!(!frame(0x1010).unwrap().is_top_frame);
+        assert!(frame(0x1050).unwrap().is_top_frame);
+
+        // A text start close to the end of the address space makes the function
+        // addresses overflow.
+        let mut overflowing_text_start = build_pclntab();
+        overflowing_text_start[24..32].copy_from_slice(&(u64::MAX - 0x10).to_le_bytes());
+        let pclntab = GoPclntab::parse(overflowing_text_start, None).unwrap();
+        assert_eq!(
+            pclntab.frame_for_svma(u64::MAX - 0x8),
+            Err(GoPclntabUnwinderError::BadFunctionTable)
+        );
+
+        // The function table of a huge function count doesn't fit into the section.
+        let mut huge_function_count = build_pclntab();
+        huge_function_count[8..16].copy_from_slice(&(u64::MAX / 4).to_le_bytes());
+        assert_eq!(
+            GoPclntab::parse(huge_function_count, None).err(),
+            Some(GoPclntabUnwinderError::BadFunctionTable)
+        );
+
+        let mut bad_magic = build_pclntab();
+        bad_magic[0] = 0xfb;
+        assert_eq!(
+            GoPclntab::parse(bad_magic, None).err(),
+            Some(GoPclntabUnwinderError::UnsupportedVersion(0xffff_fffb))
+        );
+    }
+}
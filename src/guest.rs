@@ -0,0 +1,342 @@
+use alloc::collections::BTreeMap;
+
+use crate::error::Error;
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::unwinder::{AddModuleOutcome, Unwinder};
+use crate::FrameAddress;
+
+/// Where a guest-virtual address of a virtual machine is mapped in guest-physical
+/// memory, see [`GuestPageTranslator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GuestMapping {
+    /// The guest-physical address of the translated address.
+    pub physical_address: u64,
+    /// The size of the page which contains the translated address, e.g. 0x1000, or
+    /// 0x20_0000 for a 2 MiB huge page. Must be a power of two.
+    pub page_size: u64,
+}
+
+/// Translates the guest-virtual addresses of a virtual machine to guest-physical
+/// addresses, usually by walking the guest's page tables from the page table root of
+/// the unwound thread, e.g. its CR3 on x86_64 or its TTBR on aarch64.
+///
+/// This is implemented for closures which take the guest-virtual address.
+pub trait GuestPageTranslator {
+    /// Translate `guest_virtual`, or return `None` if it isn't mapped.
+    fn translate(&mut self, guest_virtual: u64) -> Option<GuestMapping>;
+}
+
+impl<F> GuestPageTranslator for F
+where
+    F: FnMut(u64) -> Option<GuestMapping>,
+{
+    fn translate(&mut self, guest_virtual: u64) -> Option<GuestMapping> {
+        self(guest_virtual)
+    }
+}
+
+/// The number of translated pages which [`GuestMemoryReader`] remembers.
+const TRANSLATION_CACHE_SIZE: usize = 8;
+
+/// A [`MemoryReader`] for the guest-virtual memory of a virtual machine, e.g. of a
+/// QEMU/KVM guest, on top of a reader for its guest-physical memory, e.g. a VM snapshot
+/// or the mapped memory of a live VM.
+///
+/// Every read is translated with the caller's [`GuestPageTranslator`], and reads which
+/// cross a page boundary are split, because consecutive guest-virtual pages are rarely
+/// consecutive in guest-physical memory. The last few translated pages are cached until
+/// [`GuestMemoryReader::clear_translation_cache`] is called, which has to happen when
+/// the guest's page tables change, e.g. when the guest ran.
+pub struct GuestMemoryReader<T, P> {
+    translator: T,
+    physical_memory: P,
+    /// The guest-virtual page start, guest-physical page start and page size of recent
+    /// translations, most recent first.
+    translations: [Option<(u64, u64, u64)>; TRANSLATION_CACHE_SIZE],
+}
+
+impl<T: GuestPageTranslator, P: MemoryReader> GuestMemoryReader<T, P> {
+    /// Create a reader which translates with `translator` and reads the guest-physical
+    /// memory from `physical_memory`.
+    pub fn new(translator: T, physical_memory: P) -> Self {
+        Self {
+            translator,
+            physical_memory,
+            translations: [None; TRANSLATION_CACHE_SIZE],
+        }
+    }
+
+    /// Forget the cached translations.
+    pub fn clear_translation_cache(&mut self) {
+        self.translations = [None; TRANSLATION_CACHE_SIZE];
+    }
+
+    /// The guest-physical address of `guest_virtual`, and the number of bytes from there
+    /// to the end of its page.
+    fn translate(&mut self, guest_virtual: u64) -> Result<(u64, u64), MemoryReadError> {
+        let cached = self
+            .translations
+            .iter()
+            .flatten()
+            .find(|(start, _, size)| guest_virtual.wrapping_sub(*start) < *size);
+        let (virtual_start, physical_start, size) = match cached {
+            Some(&translation) => translation,
+            None => {
+                let mapping = self
+                    .translator
+                    .translate(guest_virtual)
+                    .ok_or(MemoryReadError::Unmapped)?;
+                let offset = guest_virtual & (mapping.page_size - 1);
+                let translation = (
+                    guest_virtual - offset,
+                    mapping.physical_address - offset,
+                    mapping.page_size,
+                );
+                self.translations.rotate_right(1);
+                self.translations[0] = Some(translation);
+                translation
+            }
+        };
+        let offset = guest_virtual - virtual_start;
+        Ok((physical_start + offset, size - offset))
+    }
+}
+
+impl<T: GuestPageTranslator, P: MemoryReader> MemoryReader for GuestMemoryReader<T, P> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        let mut buf = [0; 8];
+        self.read_block(address, &mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        let mut buf = [0; 4];
+        self.read_block(address, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        let mut address = address;
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let (physical_address, bytes_in_page) = self.translate(address)?;
+            let len = buf
+                .len()
+                .min(usize::try_from(bytes_in_page).unwrap_or(usize::MAX));
+            let (chunk, rest) = buf.split_at_mut(len);
+            self.physical_memory.read_block(physical_address, chunk)?;
+            address = address.wrapping_add(len as u64);
+            buf = rest;
+        }
+        Ok(())
+    }
+}
+
+/// Manages the modules of a virtual machine's guest: the kernel and its modules, which
+/// are mapped in every address space, and the user-space modules of every address
+/// space, which is identified by its page table root, e.g. its CR3 on x86_64.
+///
+/// Every address space gets its own unwinder with the kernel modules and its user
+/// modules, so that stacks which cross from the kernel into user space can be unwound
+/// with a single unwinder.
+pub struct GuestUnwinder<U: Unwinder> {
+    /// The template with the kernel modules. Cloned for every new address space, and
+    /// used for address spaces without user modules.
+    kernel: U,
+    address_spaces: BTreeMap<u64, U>,
+}
+
+impl<U: Unwinder> GuestUnwinder<U>
+where
+    U::Module: Clone,
+{
+    /// Create a manager. `template` is cloned for every address space, so that settings
+    /// like the fallback rule apply to all of them.
+    pub fn new(template: U) -> Self {
+        Self {
+            kernel: template,
+            address_spaces: BTreeMap::new(),
+        }
+    }
+
+    /// Add a module which is mapped in every address space, like the guest kernel or a
+    /// kernel module.
+    pub fn add_kernel_module(&mut self, module: U::Module) -> AddModuleOutcome {
+        for unwinder in self.address_spaces.values_mut() {
+            unwinder.add_module(module.clone());
+        }
+        self.kernel.add_module(module)
+    }
+
+    /// Remove a kernel module from every address space, see [`Unwinder::remove_module`].
+    pub fn remove_kernel_module(&mut self, module_avma_range_start: u64) {
+        for unwinder in self.address_spaces.values_mut() {
+            unwinder.remove_module(module_avma_range_start);
+        }
+        self.kernel.remove_module(module_avma_range_start);
+    }
+
+    /// Add a user-space module to the address space with the page table root
+    /// `page_table_root`.
+    pub fn add_user_module(&mut self, page_table_root: u64, module: U::Module) -> AddModuleOutcome {
+        self.address_spaces
+            .entry(page_table_root)
+            .or_insert_with(|| self.kernel.clone())
+            .add_module(module)
+    }
+
+    /// Remove a user-space module from the address space with the page table root
+    /// `page_table_root`.
+    pub fn remove_user_module(&mut self, page_table_root: u64, module_avma_range_start: u64) {
+        if let Some(unwinder) = self.address_spaces.get_mut(&page_table_root) {
+            unwinder.remove_module(module_avma_range_start);
+        }
+    }
+
+    /// Forget an address space and its user modules, e.g. when the guest process
+    /// exited.
+    pub fn remove_address_space(&mut self, page_table_root: u64) {
+        self.address_spaces.remove(&page_table_root);
+    }
+
+    /// The unwinder for the address space with the page table root `page_table_root`.
+    /// Address spaces without user modules get the unwinder with only the kernel
+    /// modules.
+    pub fn unwinder(&self, page_table_root: u64) -> &U {
+        self.address_spaces
+            .get(&page_table_root)
+            .unwrap_or(&self.kernel)
+    }
+
+    /// Unwind a single frame in the address space with the page table root
+    /// `page_table_root`, see [`Unwinder::unwind_frame`].
+    pub fn unwind_frame<F>(
+        &self,
+        page_table_root: u64,
+        address: FrameAddress,
+        regs: &mut U::UnwindRegs,
+        cache: &mut U::Cache<'_>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.unwinder(page_table_root)
+            .unwind_frame(address, regs, cache, read_stack)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::{ExplicitModuleSectionInfo, Module};
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_guest_memory_reader() {
+        // The guest-virtual pages 0x7000 and 0x8000 are mapped to the guest-physical
+        // pages 0x2000 and 0x1000, in this order.
+        let translate = |address: u64| {
+            let physical_page = match address & !0xfff {
+                0x7000 => 0x2000,
+                0x8000 => 0x1000,
+                _ => return None,
+            };
+            Some(GuestMapping {
+                physical_address: physical_page | (address & 0xfff),
+                page_size: 0x1000,
+            })
+        };
+        let mut physical = vec![0u64; 0x3000 / 8];
+        // Frame pointer chain at the end of the page 0x7000: [0x7ff0] = caller bp,
+        // [0x7ff8] = return address, and [0x8000] = the next caller's frame record.
+        physical[0x2ff0 / 8] = 0x8000;
+        physical[0x2ff8 / 8] = 0x5100;
+        physical[0x1000 / 8] = 0;
+        physical[0x1008 / 8] = 0x5200;
+        let read_physical = |address: u64| physical.get((address / 8) as usize).copied().ok_or(());
+        let mut reader = GuestMemoryReader::new(translate, read_physical);
+
+        let mut buf = [0; 16];
+        reader.read_block(0x7ff8, &mut buf).unwrap();
+        assert_eq!(buf[..8], 0x5100u64.to_le_bytes());
+        assert_eq!(buf[8..], 0u64.to_le_bytes());
+        assert_eq!(reader.read_u64(0x9000), Err(MemoryReadError::Unmapped));
+
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let regs = UnwindRegsX86_64::new(0x5000, 0x7fe0, 0x7ff0);
+        let mut iter = unwinder.iter_frames(0x5000, regs, &mut cache, &mut reader);
+        let mut frames = Vec::new();
+        while let Some(frame) = iter.next().unwrap() {
+            frames.push(frame);
+        }
+        assert_eq!(
+            frames,
+            [
+                FrameAddress::from_instruction_pointer(0x5000),
+                FrameAddress::from_return_address(0x5100).unwrap(),
+                FrameAddress::from_return_address(0x5200).unwrap(),
+            ]
+        );
+    }
+
+    fn module(name: &str, avma_range: core::ops::Range<u64>) -> Module<Vec<u8>> {
+        let base_avma = avma_range.start;
+        Module::new(
+            String::from(name),
+            avma_range,
+            base_avma,
+            ExplicitModuleSectionInfo::default(),
+        )
+    }
+
+    #[test]
+    fn test_guest_unwinder() {
+        let mut guest = GuestUnwinder::new(UnwinderX86_64::<Vec<u8>>::new());
+        guest.add_user_module(0x1000, module("app", 0x40_0000..0x50_0000));
+        guest.add_kernel_module(module(
+            "vmlinux",
+            0xffff_8000_0000_0000..0xffff_8000_0100_0000,
+        ));
+        guest.add_user_module(0x2000, module("other", 0x40_0000..0x48_0000));
+
+        let kernel_address = 0xffff_8000_0000_1000;
+        for root in [0x1000, 0x2000, 0x3000] {
+            assert!(guest
+                .unwinder(root)
+                .module_for_address(kernel_address)
+                .is_some());
+        }
+        assert_eq!(
+            guest.unwinder(0x1000).max_known_code_address(),
+            0xffff_8000_0100_0000
+        );
+        assert!(guest
+            .unwinder(0x1000)
+            .module_for_address(0x49_0000)
+            .is_some());
+        assert!(guest
+            .unwinder(0x2000)
+            .module_for_address(0x49_0000)
+            .is_none());
+        assert!(guest
+            .unwinder(0x3000)
+            .module_for_address(0x40_0000)
+            .is_none());
+
+        guest.remove_kernel_module(0xffff_8000_0000_0000);
+        assert!(guest
+            .unwinder(0x1000)
+            .module_for_address(kernel_address)
+            .is_none());
+        guest.remove_address_space(0x1000);
+        assert!(guest
+            .unwinder(0x1000)
+            .module_for_address(0x40_0000)
+            .is_none());
+    }
+}
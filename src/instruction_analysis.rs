@@ -1,20 +1,95 @@
 use crate::arch::Arch;
 
+/// Where the analyzed bytes are in their function, if they are only a part of it, e.g.
+/// a window around the instruction pointer. Jumps to targets outside the function are
+/// tail calls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionBounds {
+    /// The offset of the first analyzed byte from the start of the function.
+    pub offset: usize,
+    /// The length of the function in bytes.
+    pub len: usize,
+}
+
+impl FunctionBounds {
+    /// The bounds of a function whose bytes are all analyzed.
+    pub fn of(function_bytes: &[u8]) -> Self {
+        Self {
+            offset: 0,
+            len: function_bytes.len(),
+        }
+    }
+}
+
 pub trait InstructionAnalysis: Arch {
-    /// Caller guarantees pc_offset <= text_bytes.len()
+    /// Returns `None` if `pc_offset` is out of bounds of `text_bytes`.
     fn rule_from_prologue_analysis(text_bytes: &[u8], pc_offset: usize)
         -> Option<Self::UnwindRule>;
 
-    /// Caller guarantees pc_offset <= text_bytes.len()
-    fn rule_from_epilogue_analysis(text_bytes: &[u8], pc_offset: usize)
-        -> Option<Self::UnwindRule>;
+    /// Returns `None` if `pc_offset` is out of bounds of `text_bytes`.
+    fn rule_from_epilogue_analysis(
+        text_bytes: &[u8],
+        pc_offset: usize,
+        function: FunctionBounds,
+    ) -> Option<Self::UnwindRule>;
 
-    /// Caller guarantees pc_offset <= text_bytes.len()
+    /// Returns `None` if `pc_offset` is out of bounds of `text_bytes`. `text_bytes` are
+    /// the bytes of the whole function.
     fn rule_from_instruction_analysis(
         text_bytes: &[u8],
         pc_offset: usize,
+    ) -> Option<Self::UnwindRule> {
+        Self::rule_from_instruction_analysis_in_function(
+            text_bytes,
+            pc_offset,
+            FunctionBounds::of(text_bytes),
+        )
+    }
+
+    /// Like [`InstructionAnalysis::rule_from_instruction_analysis`], for `text_bytes`
+    /// which are the part of the function described by `function`.
+    fn rule_from_instruction_analysis_in_function(
+        text_bytes: &[u8],
+        pc_offset: usize,
+        function: FunctionBounds,
     ) -> Option<Self::UnwindRule> {
         Self::rule_from_prologue_analysis(text_bytes, pc_offset)
-            .or_else(|| Self::rule_from_epilogue_analysis(text_bytes, pc_offset))
+            .or_else(|| Self::rule_from_epilogue_analysis(text_bytes, pc_offset, function))
+    }
+
+    /// Scans the prologue at the start of a function and returns the length of the
+    /// prologue in bytes and the rule which applies to the rest of the function.
+    /// `function_bytes` starts at the function's first instruction.
+    fn rule_for_function_body(function_bytes: &[u8]) -> Option<(usize, Self::UnwindRule)>;
+
+    /// Guesses the start of the function containing `pc_offset`, for code without a
+    /// symbol table.
+    ///
+    /// Returns `None` if `pc_offset` is out of bounds of `text_bytes`.
+    fn guess_function_start(text_bytes: &[u8], pc_offset: usize) -> Option<usize>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aarch64::ArchAarch64;
+    use crate::x86_64::ArchX86_64;
+
+    fn check_out_of_bounds<A: InstructionAnalysis>(text_bytes: &[u8]) {
+        for pc_offset in [text_bytes.len() + 1, text_bytes.len() + 0x100, usize::MAX] {
+            assert!(A::rule_from_instruction_analysis(text_bytes, pc_offset).is_none());
+            assert_eq!(A::guess_function_start(text_bytes, pc_offset), None);
+        }
+    }
+
+    #[test]
+    fn test_pc_offset_out_of_bounds() {
+        // push rbp; mov rbp, rsp; pop rbp; ret
+        check_out_of_bounds::<ArchX86_64>(&[0x55, 0x48, 0x89, 0xe5, 0x5d, 0xc3]);
+        // stp x29, x30, [sp, #-0x10]!; mov x29, sp; ldp x29, x30, [sp], #0x10; ret
+        check_out_of_bounds::<ArchAarch64>(&[
+            0xfd, 0x7b, 0xbf, 0xa9, 0xfd, 0x03, 0x00, 0x91, 0xfd, 0x7b, 0xc1, 0xa8, 0xc0, 0x03,
+            0x5f, 0xd6,
+        ]);
     }
 }
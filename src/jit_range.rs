@@ -0,0 +1,157 @@
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::unwind_rule::UnwindHint;
+
+/// A range of JIT-compiled code outside of any module, together with how its frames are
+/// unwound. JIT code has no unwind information, so without a range its frames are
+/// unwound with the unwinder's fallback rule, which is only right by accident.
+///
+/// Register ranges with `set_jit_ranges` or `add_jit_range` on the unwinder, e.g.
+/// [`UnwinderX86_64::set_jit_ranges`](crate::x86_64::UnwinderX86_64::set_jit_ranges).
+/// Rules for these ranges are not stored in the rule cache, so the ranges can be
+/// replaced whenever the JIT recompiles code without invalidating the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitRange {
+    /// The address range of the JIT code.
+    pub avma_range: Range<u64>,
+    /// How frames in this range are unwound: [`UnwindHint::FramePointer`] if the JIT
+    /// maintains the frame pointer chain, [`UnwindHint::NoFrame`] for leaf code which
+    /// doesn't set up a frame, or [`UnwindHint::EndOfStack`] for entry trampolines.
+    pub hint: UnwindHint,
+}
+
+impl JitRange {
+    /// Parse a line of a perf map file, `/tmp/perf-<pid>.map`, as written by V8 with
+    /// `--perf-basic-prof` and by other JITs. A line is `START SIZE name`, with
+    /// hexadecimal `START` and `SIZE`. Returns the range and the symbol name, or `None`
+    /// if the line is malformed or the size is zero.
+    pub fn parse_perf_map_line(line: &str) -> Option<(Range<u64>, &str)> {
+        let mut parts = line.trim().splitn(3, ' ');
+        let parse_hex = |s: &str| {
+            let s = s.strip_prefix("0x").unwrap_or(s);
+            u64::from_str_radix(s, 16).ok()
+        };
+        let start = parse_hex(parts.next()?)?;
+        let size = parse_hex(parts.next()?)?;
+        let name = parts.next().unwrap_or_default();
+        if size == 0 {
+            return None;
+        }
+        Some((start..start.checked_add(size)?, name))
+    }
+
+    /// Parse the contents of a perf map file, see [`JitRange::parse_perf_map_line`].
+    /// `hint_for_symbol` decides the hint for each range based on its symbol name, or
+    /// returns `None` to skip the range. Malformed lines are skipped.
+    pub fn from_perf_map(
+        contents: &str,
+        mut hint_for_symbol: impl FnMut(&str) -> Option<UnwindHint>,
+    ) -> Vec<JitRange> {
+        contents
+            .lines()
+            .filter_map(|line| {
+                let (avma_range, name) = Self::parse_perf_map_line(line)?;
+                let hint = hint_for_symbol(name)?;
+                Some(JitRange { avma_range, hint })
+            })
+            .collect()
+    }
+}
+
+/// The JIT ranges of an unwinder, sorted by start address and without overlaps.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct JitRanges {
+    ranges: Vec<JitRange>,
+}
+
+impl JitRanges {
+    /// Replace all ranges. If ranges overlap, the later one wins.
+    pub fn set(&mut self, ranges: Vec<JitRange>) {
+        self.ranges.clear();
+        for range in ranges {
+            self.add(range);
+        }
+    }
+
+    /// Add a range, replacing the ranges it overlaps, e.g. code which the JIT discarded
+    /// before reusing its memory.
+    pub fn add(&mut self, range: JitRange) {
+        if range.avma_range.is_empty() {
+            return;
+        }
+        self.ranges.retain(|existing| {
+            existing.avma_range.end <= range.avma_range.start
+                || range.avma_range.end <= existing.avma_range.start
+        });
+        let index = self
+            .ranges
+            .partition_point(|existing| existing.avma_range.start < range.avma_range.start);
+        self.ranges.insert(index, range);
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+    }
+
+    pub fn hint_for_address(&self, address: u64) -> Option<UnwindHint> {
+        let index = self
+            .ranges
+            .partition_point(|range| range.avma_range.start <= address);
+        let range = &self.ranges[index.checked_sub(1)?];
+        range.avma_range.contains(&address).then_some(range.hint)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_perf_map() {
+        let contents = "3a4b0000 40 LazyCompile:~main /app.js:1\n\
+                        0x3a4b0100 0x20 Stub:CEntry\n\
+                        garbage\n\
+                        3a4b0200 0 Empty\n";
+        let ranges = JitRange::from_perf_map(contents, |name| {
+            Some(if name.starts_with("Stub:") {
+                UnwindHint::NoFrame
+            } else {
+                UnwindHint::FramePointer
+            })
+        });
+        assert_eq!(
+            ranges,
+            vec![
+                JitRange {
+                    avma_range: 0x3a4b0000..0x3a4b0040,
+                    hint: UnwindHint::FramePointer,
+                },
+                JitRange {
+                    avma_range: 0x3a4b0100..0x3a4b0120,
+                    hint: UnwindHint::NoFrame,
+                },
+            ]
+        );
+
+        let mut jit_ranges = JitRanges::default();
+        jit_ranges.set(ranges);
+        assert_eq!(
+            jit_ranges.hint_for_address(0x3a4b0110),
+            Some(UnwindHint::NoFrame)
+        );
+        assert_eq!(jit_ranges.hint_for_address(0x3a4b0040), None);
+        // Recompiled code replaces the ranges it overlaps.
+        jit_ranges.add(JitRange {
+            avma_range: 0x3a4b0020..0x3a4b0110,
+            hint: UnwindHint::EndOfStack,
+        });
+        assert_eq!(jit_ranges.hint_for_address(0x3a4b0000), None);
+        assert_eq!(
+            jit_ranges.hint_for_address(0x3a4b0100),
+            Some(UnwindHint::EndOfStack)
+        );
+        assert_eq!(jit_ranges.hint_for_address(0x3a4b0110), None);
+    }
+}
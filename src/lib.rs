@@ -10,7 +10,7 @@
 //!
 //!  - Live unwinding of a remote process. This is how [`samply`](https://github.com/mstange/samply/) uses it.
 //!  - Offline unwinding from saved registers and stack bytes, even on a different machine, a different OS, or a different CPU architecture.
-//!  - Live unwinding inside the same process. This is currently unproven, but should work as long as you can do heap allocation before sampling, in order to allocate a cache and to update the list of modules. The actual unwinding does not require any heap allocation and should work even inside a signal handler, as long as you use `MustNotAllocateDuringUnwind`.
+//!  - Live unwinding inside the same process. This is currently unproven, but should work as long as you can do heap allocation before sampling, in order to allocate a cache and to update the list of modules. The actual unwinding does not require any heap allocation and should work even inside a signal handler, as long as you use `MustNotAllocateDuringUnwind` and load any modules created with `Module::new_lazy` beforehand.
 //!
 //! As a user of framehop, your responsibilities are the following:
 //!
@@ -116,38 +116,166 @@
 extern crate alloc;
 
 mod add_signed;
+mod address_space;
 mod arch;
+#[cfg(feature = "async")]
+mod async_unwind;
 mod cache;
+#[cfg(feature = "std")]
+mod cache_pool;
+mod checkpoint;
 mod code_address;
+mod diagnostics;
 mod display_utils;
 mod dwarf;
+#[cfg(feature = "object")]
+mod dyld_cache;
 mod error;
+mod foreign_unwinder;
+mod frame_info;
+#[cfg(feature = "fuzzing")]
+mod fuzzing;
+#[cfg(feature = "go")]
+mod go;
+mod guest;
 mod instruction_analysis;
+mod jit_range;
 #[cfg(feature = "macho")]
 mod macho;
+mod mapped_range;
+mod memory_reader;
+mod mixed_stack;
+mod module_builder;
+mod module_id;
+#[cfg(feature = "object")]
+mod object_file;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod partial_regs;
 #[cfg(feature = "pe")]
 mod pe;
+#[cfg(feature = "std")]
+mod process_group;
+#[cfg(feature = "return-address-predictor")]
+mod return_address_predictor;
+mod rosetta;
 mod rule_cache;
+mod shadow_stack;
+#[cfg(target_has_atomic = "64")]
+mod shared_rule_cache;
+mod stack_fingerprint;
+mod stack_link;
+mod stack_stitcher;
+#[cfg(feature = "std")]
+mod sync_cache;
+mod trace;
+mod trampoline;
+#[cfg(feature = "std")]
+mod unwind_data_store;
+mod unwind_regs;
 mod unwind_result;
 mod unwind_rule;
+mod unwind_stats;
+mod unwind_step;
 mod unwinder;
+mod versioned_module_store;
+mod wow64;
 
 /// Types for unwinding on the aarch64 CPU architecture.
 pub mod aarch64;
+/// Helpers for the modules of Android processes, including libraries which are loaded
+/// directly from an APK.
+#[cfg(feature = "android")]
+pub mod android;
+/// An adapter with the iteration model of the `backtrace` crate.
+#[cfg(feature = "backtrace-compat")]
+pub mod backtrace_compat;
+/// A client for the GDB remote serial protocol, which provides the registers, the memory
+/// and the libraries of a stopped target for unwinding.
+#[cfg(feature = "gdb-remote")]
+pub mod gdb_remote;
 /// Types for unwinding on the x86_64 CPU architecture.
 pub mod x86_64;
 
+#[cfg(all(feature = "std", any(target_os = "linux", target_os = "android")))]
+pub use address_space::ProcessAddressSpace;
+pub use address_space::{AddressSpace, SnapshotAddressSpace};
+#[cfg(feature = "async")]
+pub use async_unwind::{AsyncMemoryReader, AsyncUnwindIterator};
 pub use cache::{AllocationPolicy, MayAllocateDuringUnwind, MustNotAllocateDuringUnwind};
-pub use code_address::FrameAddress;
-pub use error::Error;
+#[cfg(feature = "std")]
+pub use cache_pool::{CachePool, CachePoolGuard};
+pub use checkpoint::{CheckpointError, ModuleCheckpoint, UnwinderCheckpoint};
+pub use code_address::{CodeArch, FrameAddress, InstructionPointerAdjustment, RelativeFrame};
+pub use diagnostics::Diagnostic;
+pub use dwarf::{
+    DwarfCfiIndex, DwarfCfiIndexError, DwarfCfiSectionAddresses, DwarfUnwinderError,
+    SingleFrameUnwindError,
+};
+#[cfg(feature = "object")]
+pub use dyld_cache::{dyld_cache_modules, dyld_cache_modules_mapped, DyldCacheImageSectionInfo};
+pub use error::{Error, UnwinderError};
+pub use foreign_unwinder::ForeignUnwindCallback;
+pub use frame_info::{
+    FallbackPolicy, FallbackReason, FrameConfidence, FrameUnwindInfo, UnwindErrorDetails,
+};
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use fuzzing::*;
+#[cfg(feature = "go")]
+pub use go::GoPclntabUnwinderError;
+pub use guest::{GuestMapping, GuestMemoryReader, GuestPageTranslator, GuestUnwinder};
+pub use jit_range::JitRange;
+#[cfg(feature = "macho")]
+pub use macho::{
+    CompactUnwindInfoContents, CompactUnwindInfoEntry, CompactUnwindInfoPage,
+    CompactUnwindInfoPageKind, CompactUnwindInfoUnwinderError,
+};
+pub use mapped_range::MappedRange;
+pub use memory_reader::{MemoryReadError, MemoryReader};
+pub use mixed_stack::{FrameHookContext, FrameHookOutput, MixedFrame, MixedStackUnwindIterator};
+pub use module_builder::ModuleBuilder;
+pub use module_id::{CodeId, DebugId};
+#[cfg(feature = "rayon")]
+pub use parallel::{unwind_batch_parallel, BatchUnwindResult};
+pub use partial_regs::PartialUnwindRegs;
+#[cfg(feature = "pe")]
+pub use pe::PeUnwinderError;
+#[cfg(feature = "std")]
+pub use process_group::ProcessGroupUnwinder;
+#[cfg(feature = "return-address-predictor")]
+pub use return_address_predictor::PredictorStats;
 pub use rule_cache::CacheStats;
+pub use shadow_stack::{ShadowCallStackMode, ShadowStackUnwindIterator};
+#[cfg(target_has_atomic = "64")]
+pub use shared_rule_cache::SharedCacheSlot;
+pub use stack_fingerprint::StackFingerprint;
+pub use stack_link::{StackLink, StackLinkBase, StackLinkRule};
+pub use stack_stitcher::{
+    FrameOrigin, StackStitcher, StitchBoundary, StitchedFrame, StitchedStack,
+};
+#[cfg(feature = "std")]
+pub use sync_cache::SyncCache;
+#[cfg(feature = "trace")]
+pub use trace::{UnwindTrace, UnwindTraceEvent};
+pub use trampoline::Trampoline;
+#[cfg(feature = "std")]
+pub use unwind_data_store::{UnwindDataKey, UnwindDataStore};
+pub use unwind_regs::UnwindRegs;
+pub use unwind_rule::UnwindHint;
+pub use unwind_stats::UnwindStats;
+pub use unwind_step::{FrameUnwindStepper, MemoryRequest, UnwindStep};
 pub use unwinder::{
-    ExplicitModuleSectionInfo, Module, ModuleSectionInfo, UnwindIterator, Unwinder,
+    AddModuleOutcome, DepthLimit, ExplicitModuleSectionInfo, Module, ModuleDescriptor,
+    ModuleMemoryUsage, ModuleSectionInfo, StackEndState, StackResult, SyncModulesOutcome,
+    TruncationSummary, UnwindBudget, UnwindCoverage, UnwindDataKind, UnwindIterator,
+    UnwindIteratorCheckpoint, Unwinder,
 };
+pub use versioned_module_store::VersionedModuleStore;
 
 /// The unwinder cache for the native CPU architecture.
 #[cfg(target_arch = "aarch64")]
-pub type CacheNative<P> = aarch64::CacheAarch64<P>;
+pub type CacheNative<'s, P> = aarch64::CacheAarch64<'s, P>;
 /// The unwind registers type for the native CPU architecture.
 #[cfg(target_arch = "aarch64")]
 pub type UnwindRegsNative = aarch64::UnwindRegsAarch64;
@@ -157,7 +285,7 @@ pub type UnwinderNative<D, P> = aarch64::UnwinderAarch64<D, P>;
 
 /// The unwinder cache for the native CPU architecture.
 #[cfg(target_arch = "x86_64")]
-pub type CacheNative<P> = x86_64::CacheX86_64<P>;
+pub type CacheNative<'s, P> = x86_64::CacheX86_64<'s, P>;
 /// The unwind registers type for the native CPU architecture.
 #[cfg(target_arch = "x86_64")]
 pub type UnwindRegsNative = x86_64::UnwindRegsX86_64;
@@ -1,7 +1,12 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
 use core::marker::PhantomData;
+use core::ops::Range;
 
 use crate::dwarf::DwarfUnwinderError;
+use crate::trace::{trace_event, Tracer};
 use crate::{arch::Arch, unwind_rule::UnwindRule};
+use macho_unwind_info::raw::{consts, CompactUnwindInfoHeader, CompressedPage, RegularPage};
 use macho_unwind_info::UnwindInfo;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -47,6 +52,12 @@ impl From<macho_unwind_info::Error> for CompactUnwindInfoUnwinderError {
     }
 }
 
+impl From<macho_unwind_info::ReadError> for CompactUnwindInfoUnwinderError {
+    fn from(e: macho_unwind_info::ReadError) -> Self {
+        Self::BadFormat(e.into())
+    }
+}
+
 impl From<DwarfUnwinderError> for CompactUnwindInfoUnwinderError {
     fn from(e: DwarfUnwinderError) -> Self {
         Self::BadDwarfUnwinding(e)
@@ -67,6 +78,9 @@ impl std::error::Error for CompactUnwindInfoUnwinderError {
 #[derive(Clone, Debug)]
 pub enum CuiUnwindResult<R: UnwindRule> {
     ExecRule(R),
+    /// The rule was obtained by instruction analysis because the address is inside
+    /// a prologue or epilogue, which compact unwind info doesn't describe.
+    ExecRuleFromInstructionAnalysis(R),
     NeedDwarf(u32),
 }
 
@@ -138,6 +152,7 @@ impl<'a, A: CompactUnwindInfoUnwinding> CompactUnwindInfoUnwinder<'a, A> {
         &mut self,
         rel_lookup_address: u32,
         is_first_frame: bool,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
     ) -> Result<CuiUnwindResult<A::UnwindRule>, CompactUnwindInfoUnwinderError> {
         // Exclude __stubs and __stub_helper sections. The __unwind_info does not describe those
         // sections. These sections need to be manually excluded because the addresses in
@@ -176,13 +191,24 @@ impl<'a, A: CompactUnwindInfoUnwinding> CompactUnwindInfoUnwinder<'a, A> {
             }
             Err(err) => return Err(err),
         };
+        trace_event!(
+            tracer,
+            CompactUnwindInfoFunction {
+                start_address: function.start_address,
+                end_address: function.end_address,
+                opcode: function.opcode,
+            }
+        );
         if is_first_frame && rel_lookup_address == function.start_address {
             return Ok(CuiUnwindResult::ExecRule(
                 A::UnwindRule::rule_for_function_start(),
             ));
         }
-        let address_offset_within_function =
-            usize::try_from(rel_lookup_address - function.start_address).unwrap();
+        let address_offset_within_function = rel_lookup_address
+            .checked_sub(function.start_address)
+            .ok_or(CompactUnwindInfoUnwinderError::AddressOutsideRange(
+                rel_lookup_address,
+            ))? as usize;
         let function_bytes = self.text_bytes.and_then(|text_bytes| {
             let TextBytes {
                 offset_from_base_address,
@@ -204,3 +230,131 @@ impl<'a, A: CompactUnwindInfoUnwinding> CompactUnwindInfoUnwinder<'a, A> {
         )
     }
 }
+
+/// The kind of a second-level page in a `__unwind_info` section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompactUnwindInfoPageKind {
+    /// Each entry stores its full function address and opcode.
+    Regular,
+    /// Entries store their function address relative to the page's first
+    /// address, and index into the global opcodes or into the page's local
+    /// opcodes.
+    Compressed,
+}
+
+/// A second-level page in a `__unwind_info` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactUnwindInfoPage {
+    pub kind: CompactUnwindInfoPageKind,
+    /// The range of module-relative addresses covered by this page.
+    pub address_range: Range<u32>,
+    /// The number of function entries in this page.
+    pub entry_count: usize,
+    /// The number of opcodes stored in this page. Always zero for regular pages.
+    pub local_opcode_count: usize,
+}
+
+/// A function entry in a `__unwind_info` section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactUnwindInfoEntry {
+    /// The range of module-relative addresses covered by this entry.
+    pub address_range: Range<u32>,
+    /// The raw compact unwind opcode.
+    pub opcode: u32,
+}
+
+impl CompactUnwindInfoEntry {
+    /// The opcode kind, i.e. bits 24 to 27 of the opcode.
+    ///
+    /// The meaning of each kind depends on the architecture: for example,
+    /// 1 is "rbp frame" and 4 is "DWARF" on x86_64, whereas 3 is "DWARF" and
+    /// 4 is "frame" on arm64. Kind 0 means that the function has no unwind info.
+    pub fn opcode_kind(&self) -> u8 {
+        ((self.opcode >> 24) & 0xf) as u8
+    }
+}
+
+/// A read-only view of the pages and entries of a `__unwind_info` section.
+///
+/// This is meant for diagnostic tooling which wants to audit what the linker
+/// produced for a binary, for example when investigating broken stacks. It
+/// is not used for unwinding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactUnwindInfoContents {
+    /// The range of module-relative addresses covered by the section.
+    pub address_range: Range<u32>,
+    /// The number of opcodes shared by all compressed pages.
+    pub global_opcode_count: usize,
+    /// The second-level pages, excluding the final sentinel page.
+    pub pages: Vec<CompactUnwindInfoPage>,
+    /// All function entries, ordered by address.
+    pub entries: Vec<CompactUnwindInfoEntry>,
+}
+
+impl CompactUnwindInfoContents {
+    /// Parse the contents of a `__unwind_info` section.
+    pub fn parse(data: &[u8]) -> Result<Self, CompactUnwindInfoUnwinderError> {
+        let header = CompactUnwindInfoHeader::parse(data)?;
+        let global_opcode_count = header.global_opcodes(data)?.len();
+        let page_entries = header.pages(data)?;
+        let mut pages = Vec::with_capacity(page_entries.len().saturating_sub(1));
+        for pair in page_entries.windows(2) {
+            let (page_entry, next_page_entry) = (&pair[0], &pair[1]);
+            let page_offset = page_entry.page_offset();
+            let address_range = page_entry.first_address()..next_page_entry.first_address();
+            let page = match page_entry.page_kind(data)? {
+                consts::PAGE_KIND_REGULAR => {
+                    let page = RegularPage::parse(data, page_offset.into())?;
+                    CompactUnwindInfoPage {
+                        kind: CompactUnwindInfoPageKind::Regular,
+                        address_range,
+                        entry_count: page.functions_len().into(),
+                        local_opcode_count: 0,
+                    }
+                }
+                consts::PAGE_KIND_COMPRESSED => {
+                    let page = CompressedPage::parse(data, page_offset.into())?;
+                    CompactUnwindInfoPage {
+                        kind: CompactUnwindInfoPageKind::Compressed,
+                        address_range,
+                        entry_count: page.functions_len().into(),
+                        local_opcode_count: page.local_opcodes_len().into(),
+                    }
+                }
+                consts::PAGE_KIND_SENTINEL => {
+                    return Err(macho_unwind_info::Error::UnexpectedSentinelPage.into())
+                }
+                _ => return Err(macho_unwind_info::Error::InvalidPageKind.into()),
+            };
+            pages.push(page);
+        }
+
+        let unwind_info = UnwindInfo::parse(data)?;
+        let address_range = unwind_info.address_range();
+        let mut entries = Vec::new();
+        let mut functions = unwind_info.functions();
+        while let Some(function) = functions.next()? {
+            entries.push(CompactUnwindInfoEntry {
+                address_range: function.start_address..function.end_address,
+                opcode: function.opcode,
+            });
+        }
+
+        Ok(Self {
+            address_range,
+            global_opcode_count,
+            pages,
+            entries,
+        })
+    }
+
+    /// The number of entries for each opcode kind, see
+    /// [`CompactUnwindInfoEntry::opcode_kind`].
+    pub fn opcode_kind_counts(&self) -> BTreeMap<u8, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in &self.entries {
+            *counts.entry(entry.opcode_kind()).or_default() += 1;
+        }
+        counts
+    }
+}
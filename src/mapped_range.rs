@@ -0,0 +1,89 @@
+use alloc::sync::Arc;
+use core::ops::{Deref, Range};
+
+/// A range of bytes inside a shared mapping, e.g. a section inside a memory-mapped file.
+///
+/// Use this as the data type `D` of a [`Module`](crate::Module) to refer to the section
+/// data inside the mapped file instead of copying every section into its own `Vec<u8>`.
+/// All ranges of a file share the same mapping, which is unmapped when the last range is
+/// dropped.
+///
+/// `M` is the type of the mapping, for example `memmap2::Mmap` or a `Vec<u8>` with the
+/// file contents.
+pub struct MappedRange<M> {
+    mapping: Arc<M>,
+    range: Range<usize>,
+}
+
+impl<M: Deref<Target = [u8]>> MappedRange<M> {
+    /// Create a range of the mapping. Returns `None` if the range is out of bounds.
+    pub fn new(mapping: Arc<M>, range: Range<usize>) -> Option<Self> {
+        mapping.get(range.clone())?;
+        Some(Self { mapping, range })
+    }
+
+    /// Create the range which `data` covers. `data` has to be a sub-slice of the mapping,
+    /// e.g. the section data which a parser returned for the mapped file. Returns `None`
+    /// if `data` lies outside the mapping.
+    pub fn from_subslice(mapping: &Arc<M>, data: &[u8]) -> Option<Self> {
+        let mapping_start = mapping.as_ptr() as usize;
+        let start = (data.as_ptr() as usize).checked_sub(mapping_start)?;
+        let range = start..start.checked_add(data.len())?;
+        Self::new(mapping.clone(), range)
+    }
+
+    /// The mapping which this range is part of.
+    pub fn mapping(&self) -> &Arc<M> {
+        &self.mapping
+    }
+
+    /// The offset range inside the mapping.
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+}
+
+impl<M: Deref<Target = [u8]>> Deref for MappedRange<M> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.mapping[self.range.clone()]
+    }
+}
+
+impl<M> Clone for MappedRange<M> {
+    fn clone(&self) -> Self {
+        Self {
+            mapping: self.mapping.clone(),
+            range: self.range.clone(),
+        }
+    }
+}
+
+impl<M> core::fmt::Debug for MappedRange<M> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MappedRange")
+            .field("range", &self.range)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_from_subslice() {
+        let mapping = Arc::new((0..16).collect::<Vec<u8>>());
+        let range = MappedRange::from_subslice(&mapping, &mapping[4..8]).unwrap();
+        assert_eq!(range.range(), 4..8);
+        assert_eq!(&*range, &[4, 5, 6, 7]);
+        assert!(Arc::ptr_eq(range.mapping(), &mapping));
+
+        let other = vec![0u8; 4];
+        assert!(MappedRange::from_subslice(&mapping, &other).is_none());
+        assert!(MappedRange::new(mapping, 8..20).is_none());
+    }
+}
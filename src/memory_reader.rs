@@ -0,0 +1,307 @@
+use core::ops::Range;
+
+/// Reads values from the stack memory of the unwound thread.
+///
+/// This is implemented for closures of the shape `FnMut(u64) -> Result<u64, E>`, which
+/// read the 8-byte little-endian value at the given address, where `E` is either `()` or
+/// a [`MemoryReadError`]. Implement it for your own type if reads are expensive and you
+/// can provide a faster [`MemoryReader::read_block`].
+pub trait MemoryReader {
+    /// Read the 8-byte value at `address`.
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError>;
+
+    /// Read the 4-byte value at `address`.
+    ///
+    /// The default implementation reads the four bytes with [`MemoryReader::read_block`].
+    /// If that isn't implemented either, e.g. for closures, this reads 8 bytes at
+    /// `address` and keeps the low four bytes, which fails if only the first four bytes
+    /// are readable, e.g. at the end of a stack snapshot.
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        let mut bytes = [0; 4];
+        self.read_block(address, &mut bytes)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Read `buf.len()` bytes starting at `address` into `buf`.
+    ///
+    /// The unwinder uses this to fetch the saved registers of a frame with a single call,
+    /// which helps if every read is expensive, e.g. a syscall or a round-trip to another
+    /// process. If this fails, the unwinder falls back to reading the values one by one.
+    ///
+    /// The default implementation calls [`MemoryReader::read_u64`] for every 8 bytes.
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        let mut address = address;
+        for chunk in buf.chunks_mut(8) {
+            let value = self.read_u64(address)?;
+            chunk.copy_from_slice(&value.to_le_bytes()[..chunk.len()]);
+            address = address.wrapping_add(8);
+        }
+        Ok(())
+    }
+
+    /// Whether `address` is in executable memory of the unwound process, or `None` if
+    /// the reader doesn't know the memory mappings.
+    ///
+    /// [`UnwindIterator`](crate::UnwindIterator) checks every return address with this
+    /// and stops with [`Error::ReturnAddressNotExecutable`](crate::Error::ReturnAddressNotExecutable)
+    /// if it returns `Some(false)`, because such a return address means that the stack
+    /// was unwound incorrectly. The default implementation returns `None`.
+    fn is_executable(&mut self, address: u64) -> Option<bool> {
+        let _ = address;
+        None
+    }
+
+    /// The range of stack addresses which the reader has captured, if it reads from a
+    /// copy of the stack rather than from the unwound process, e.g. the stack bytes of a
+    /// perf sample, which are copied starting at the stack pointer.
+    ///
+    /// On x86_64, the first frame can restore registers from the red zone below the
+    /// stack pointer, which such a copy usually doesn't include. If the reader returns
+    /// a range which doesn't cover such a location, the unwinder keeps the register's
+    /// current value instead of reading it. The default implementation returns `None`,
+    /// which means that all locations are read.
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        None
+    }
+}
+
+impl<F, E> MemoryReader for F
+where
+    F: FnMut(u64) -> Result<u64, E>,
+    E: Into<MemoryReadError>,
+{
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        self(address).map_err(Into::into)
+    }
+}
+
+/// Whether `location`, which holds a saved register of the first frame, is below the
+/// stack pointer `sp` and outside of `snapshot_range`, see
+/// [`MemoryReader::snapshot_range`].
+///
+/// Leaf functions can save registers in the red zone, the 128 bytes below the stack
+/// pointer, and epilogues can leave rules for registers which were already popped. A
+/// copy of the stack which starts at the stack pointer doesn't have these values, so the
+/// register keeps its current value, which is correct for popped registers and the best
+/// guess otherwise.
+pub(crate) fn is_uncaptured_red_zone_location(
+    snapshot_range: Option<&Range<u64>>,
+    sp: u64,
+    location: u64,
+) -> bool {
+    if location >= sp {
+        return false;
+    }
+    match snapshot_range {
+        Some(range) => !(range.contains(&location) && location.saturating_add(8) <= range.end),
+        None => false,
+    }
+}
+
+/// The maximum number of bytes that [`PrefetchingReader`] fetches at once.
+const PREFETCH_SIZE: usize = 64;
+
+/// A [`MemoryReader`] which fetches a small range of memory with a single
+/// [`MemoryReader::read_block`] call and serves the reads inside this range from its
+/// buffer. Reads outside of the range, and all reads if the block read failed, go to the
+/// wrapped reader.
+pub(crate) struct PrefetchingReader<'a, F: MemoryReader> {
+    inner: &'a mut F,
+    start: u64,
+    len: usize,
+    buf: [u8; PREFETCH_SIZE],
+}
+
+impl<'a, F: MemoryReader> PrefetchingReader<'a, F> {
+    pub fn new(inner: &'a mut F) -> Self {
+        Self {
+            inner,
+            start: 0,
+            len: 0,
+            buf: [0; PREFETCH_SIZE],
+        }
+    }
+
+    /// Fetch the 8-byte values at `first` and `last` and everything in between, if they
+    /// are close enough to each other.
+    pub fn prefetch(&mut self, first: u64, last: u64) {
+        self.len = 0;
+        let start = first.min(last);
+        let Some(end) = first.max(last).checked_add(8) else {
+            return;
+        };
+        if end - start > PREFETCH_SIZE as u64 {
+            return;
+        }
+        let len = (end - start) as usize;
+        if self.inner.read_block(start, &mut self.buf[..len]).is_ok() {
+            self.start = start;
+            self.len = len;
+        }
+    }
+
+    /// The wrapped reader.
+    pub fn inner(&mut self) -> &mut F {
+        self.inner
+    }
+
+    fn prefetched<const N: usize>(&self, address: u64) -> Option<[u8; N]> {
+        let offset = usize::try_from(address.checked_sub(self.start)?).ok()?;
+        let bytes = self.buf[..self.len].get(offset..offset.checked_add(N)?)?;
+        bytes.try_into().ok()
+    }
+}
+
+impl<F: MemoryReader> MemoryReader for PrefetchingReader<'_, F> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        match self.prefetched(address) {
+            Some(bytes) => Ok(u64::from_le_bytes(bytes)),
+            None => self.inner.read_u64(address),
+        }
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        match self.prefetched(address) {
+            Some(bytes) => Ok(u32::from_le_bytes(bytes)),
+            None => self.inner.read_u32(address),
+        }
+    }
+
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        self.inner.read_block(address, buf)
+    }
+
+    fn is_executable(&mut self, address: u64) -> Option<bool> {
+        self.inner.is_executable(address)
+    }
+
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        self.inner.snapshot_range()
+    }
+}
+
+/// The reason why stack memory could not be read. This is returned by the memory reader
+/// and embedded in [`Error::CouldNotReadStack`](crate::Error::CouldNotReadStack), so that
+/// the caller can tell why unwinding stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryReadError {
+    /// The memory reader did not say why the read failed. This is what memory readers
+    /// which return `Err(())` produce.
+    Unknown,
+    /// The address is not mapped in the unwound process.
+    Unmapped,
+    /// The address is mapped but not readable.
+    PermissionDenied,
+    /// The address is outside of the captured stack bytes, e.g. beyond the end of a stack
+    /// snapshot which was truncated to a maximum size.
+    OutsideSnapshot,
+    /// A caller-defined error code, e.g. an `errno` value from `ptrace` or
+    /// `process_vm_readv`.
+    Other(u64),
+}
+
+impl From<()> for MemoryReadError {
+    fn from(_: ()) -> Self {
+        Self::Unknown
+    }
+}
+
+impl core::fmt::Display for MemoryReadError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "unknown error"),
+            Self::Unmapped => write!(f, "address is not mapped"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::OutsideSnapshot => write!(f, "address is outside of the stack snapshot"),
+            Self::Other(code) => write!(f, "error code {code}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MemoryReadError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::{Error, FrameAddress, Unwinder};
+    use alloc::vec::Vec;
+    use fallible_iterator::FallibleIterator;
+
+    #[test]
+    fn test_read_error_is_reported() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x40, 0x1234];
+        let mut read_stack = |addr: u64| {
+            stack
+                .get((addr / 8) as usize)
+                .copied()
+                .ok_or(MemoryReadError::OutsideSnapshot)
+        };
+        let regs = UnwindRegsX86_64::new(0x1000, 0x8, 0x10);
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(
+            iter.next(),
+            Err(Error::CouldNotReadStack(
+                0x18,
+                MemoryReadError::OutsideSnapshot
+            ))
+        );
+    }
+
+    struct CountingReader<'a> {
+        stack: &'a [u64],
+        word_reads: usize,
+        block_reads: usize,
+    }
+
+    impl MemoryReader for CountingReader<'_> {
+        fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            self.word_reads += 1;
+            self.stack
+                .get((address / 8) as usize)
+                .copied()
+                .ok_or(MemoryReadError::OutsideSnapshot)
+        }
+
+        fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+            self.block_reads += 1;
+            let start = (address / 8) as usize;
+            let words = self
+                .stack
+                .get(start..start + buf.len() / 8)
+                .ok_or(MemoryReadError::OutsideSnapshot)?;
+            for (chunk, word) in buf.chunks_mut(8).zip(words) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_frame_pointer_walk_uses_block_reads() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x0, 0x5678];
+        let mut reader = CountingReader {
+            stack: &stack,
+            word_reads: 0,
+            block_reads: 0,
+        };
+        let regs = UnwindRegsX86_64::new(0x1000, 0x8, 0x10);
+        let frames: Vec<_> = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut reader)
+            .map(|frame| Ok(frame.address()))
+            .collect()
+            .unwrap();
+        assert_eq!(frames, [0x1000, 0x1234, 0x5678]);
+        assert_eq!(reader.block_reads, 2);
+        assert_eq!(reader.word_reads, 0);
+    }
+}
@@ -0,0 +1,231 @@
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::{UnwindIterator, Unwinder};
+use crate::FrameAddress;
+
+/// A frame yielded by a [`MixedStackUnwindIterator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MixedFrame {
+    /// A native frame, as yielded by [`UnwindIterator::next`].
+    Native(FrameAddress),
+    /// A frame which a frame hook injected, e.g. a Python function which was executed
+    /// by an interpreter frame. The value is opaque to the unwinder; it could be the
+    /// address of a `PyFrameObject`, or an index into a table of the hook.
+    Extension(u64),
+    /// A separator before a frame in a marker range, e.g. at the boundary of a signal
+    /// handler. The value is the tag of the range, see e.g.
+    /// [`UnwinderX86_64::add_marker_range`](crate::x86_64::UnwinderX86_64::add_marker_range).
+    Marker(u32),
+}
+
+/// The native frame which is passed to the hook of a [`MixedStackUnwindIterator`].
+pub struct FrameHookContext<'a, M, R> {
+    /// The address of the frame.
+    pub frame: FrameAddress,
+    /// The module which contains the address, if any.
+    pub module: Option<&'a M>,
+    /// The registers of the frame, i.e. the values they had while the function of the
+    /// frame was executing. For return addresses, only the registers which the unwinder
+    /// recovers are accurate, see [`UnwindRegs`](crate::UnwindRegs).
+    pub regs: &'a R,
+}
+
+/// What the hook of a [`MixedStackUnwindIterator`] wants to do with a native frame.
+#[derive(Debug, Default)]
+pub struct FrameHookOutput {
+    extension_frames: Vec<u64>,
+    hide_native_frame: bool,
+}
+
+impl FrameHookOutput {
+    /// Yield an extension frame before the native frame. Extension frames are yielded in
+    /// the order in which they are pushed, so push the innermost frame first.
+    pub fn push_extension_frame(&mut self, value: u64) {
+        self.extension_frames.push(value);
+    }
+
+    /// Don't yield the native frame, e.g. to replace an interpreter loop frame with the
+    /// frames which it was executing. Unwinding still continues from it.
+    pub fn hide_native_frame(&mut self) {
+        self.hide_native_frame = true;
+    }
+
+    fn clear(&mut self) {
+        self.extension_frames.clear();
+        self.hide_native_frame = false;
+    }
+}
+
+/// An [`UnwindIterator`] which calls a hook for every native frame, so that profilers
+/// for language runtimes can mix the frames of the runtime into the native stack.
+///
+/// The hook is called after a native frame was unwound and before it's yielded. It gets
+/// the frame's registers and module, and the `read_stack` callback of the iterator, so
+/// it can read the runtime's frame structures, e.g. walk the `PyFrameObject` chain when
+/// the frame is in `_PyEval_EvalFrameDefault`. The extension frames which it pushes are
+/// yielded before the native frame, because they were called by it.
+///
+/// If the native frame is in a marker range, a [`MixedFrame::Marker`] is yielded before
+/// the frame and its extension frames.
+///
+/// Create this with [`UnwindIterator::with_frame_hook`] or
+/// [`UnwindIterator::with_frame_markers`].
+pub struct MixedStackUnwindIterator<'u, 'c, 's, 'r, U, F, H>
+where
+    U: Unwinder,
+    F: MemoryReader,
+{
+    inner: UnwindIterator<'u, 'c, 's, 'r, U, F>,
+    hook: H,
+    output: FrameHookOutput,
+    /// The frames which are yielded next, in reverse order.
+    pending: Vec<MixedFrame>,
+}
+
+impl<'u, 'c, 's, 'r, U, F, H> MixedStackUnwindIterator<'u, 'c, 's, 'r, U, F, H>
+where
+    U: Unwinder,
+    F: MemoryReader,
+    H: FnMut(&FrameHookContext<'_, U::Module, U::UnwindRegs>, &mut F, &mut FrameHookOutput),
+{
+    pub(crate) fn new(inner: UnwindIterator<'u, 'c, 's, 'r, U, F>, hook: H) -> Self {
+        Self {
+            inner,
+            hook,
+            output: FrameHookOutput::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Yield the next frame in the stack, like [`UnwindIterator::next`].
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<MixedFrame>, Error> {
+        loop {
+            if let Some(frame) = self.pending.pop() {
+                return Ok(Some(frame));
+            }
+            let Some(frame) = self.inner.next()? else {
+                return Ok(None);
+            };
+            self.output.clear();
+            let (unwinder, regs, read_stack) = self.inner.hook_parts();
+            let marker = unwinder.marker_for_address(frame.address_for_lookup());
+            let context = FrameHookContext {
+                frame,
+                module: unwinder.module_for_address(frame.address_for_lookup()),
+                regs,
+            };
+            (self.hook)(&context, read_stack, &mut self.output);
+            if !self.output.hide_native_frame {
+                self.pending.push(MixedFrame::Native(frame));
+            }
+            self.pending.extend(
+                self.output
+                    .extension_frames
+                    .iter()
+                    .rev()
+                    .map(|&value| MixedFrame::Extension(value)),
+            );
+            if let Some(tag) = marker {
+                self.pending.push(MixedFrame::Marker(tag));
+            }
+        }
+    }
+
+    /// The native iterator, e.g. to check [`UnwindIterator::last_frame_info`].
+    pub fn native(&self) -> &UnwindIterator<'u, 'c, 's, 'r, U, F> {
+        &self.inner
+    }
+}
+
+impl<'u, 'c, 's, 'r, U, F, H> fallible_iterator::FallibleIterator
+    for MixedStackUnwindIterator<'u, 'c, 's, 'r, U, F, H>
+where
+    U: Unwinder,
+    F: MemoryReader,
+    H: FnMut(&FrameHookContext<'_, U::Module, U::UnwindRegs>, &mut F, &mut FrameHookOutput),
+{
+    type Item = MixedFrame;
+    type Error = Error;
+
+    fn next(&mut self) -> Result<Option<MixedFrame>, Error> {
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::Module;
+    use fallible_iterator::FallibleIterator;
+
+    #[test]
+    fn test_frame_hook() {
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.add_module(Module::new(
+            "interpreter".into(),
+            0x2000..0x3000,
+            0x2000,
+            crate::ExplicitModuleSectionInfo::<Vec<u8>>::default(),
+        ));
+        let mut cache = CacheX86_64::<_>::new();
+        // A frame pointer chain: 0x1234 is called by 0x2345 in the interpreter, which
+        // is called by 0x3456.
+        let stack = [0x0, 0x7000, 0x20, 0x2345, 0x30, 0x3456, 0x0, 0x0];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1234, 0x10, 0x10);
+        let frames: Vec<MixedFrame> = unwinder
+            .iter_frames(0x1234, regs, &mut cache, &mut read_stack)
+            .with_frame_hook(|context, read_stack, output| {
+                let Some(module) = context.module else {
+                    return;
+                };
+                assert_eq!(module.name(), "interpreter");
+                // The interpreter keeps its current frame object at bp - 24, and
+                // the frame object's caller is at 0x8000.
+                let bp = context.regs.bp();
+                output.push_extension_frame(read_stack.read_u64(bp - 24).unwrap());
+                output.push_extension_frame(0x8000);
+                output.hide_native_frame();
+            })
+            .collect()
+            .unwrap();
+        assert_eq!(
+            frames,
+            [
+                MixedFrame::Native(FrameAddress::from_instruction_pointer(0x1234)),
+                MixedFrame::Extension(0x7000),
+                MixedFrame::Extension(0x8000),
+                MixedFrame::Native(FrameAddress::from_return_address(0x3456).unwrap()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frame_markers() {
+        const SIGNAL_TRAMPOLINE: u32 = 1;
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.add_marker_range(0x2340..0x2350, SIGNAL_TRAMPOLINE);
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x2345, 0x30, 0x3456, 0x0, 0x0];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1234, 0x10, 0x10);
+        let frames: Vec<MixedFrame> = unwinder
+            .iter_frames(0x1234, regs, &mut cache, &mut read_stack)
+            .with_frame_markers()
+            .collect()
+            .unwrap();
+        assert_eq!(
+            frames,
+            [
+                MixedFrame::Native(FrameAddress::from_instruction_pointer(0x1234)),
+                MixedFrame::Marker(SIGNAL_TRAMPOLINE),
+                MixedFrame::Native(FrameAddress::from_return_address(0x2345).unwrap()),
+                MixedFrame::Native(FrameAddress::from_return_address(0x3456).unwrap()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::{Deref, Range};
+
+use crate::module_id::{CodeId, DebugId};
+use crate::trampoline::Trampoline;
+use crate::unwind_rule::UnwindHint;
+use crate::unwinder::{Module, ModuleSectionInfo, UnwindDataKind};
+
+/// Creates a [`Module`], with overrides on top of what the [`ModuleSectionInfo`]
+/// provides.
+///
+/// [`Module::new`] decides everything based on the sections of the module. Use this
+/// builder if some of that information is wrong or comes from somewhere else, e.g. if the
+/// code bytes are read from the process memory instead of the file on disk.
+pub struct ModuleBuilder<D, S> {
+    name: String,
+    avma_range: Range<u64>,
+    base_avma: u64,
+    section_info: S,
+    unwind_data_kind: Option<UnwindDataKind>,
+    stubs_svma: Option<Range<u64>>,
+    stub_helper_svma: Option<Range<u64>>,
+    text: Option<(Range<u64>, D)>,
+    frame_pointers_guaranteed: bool,
+    unwind_hints: Vec<(Range<u64>, UnwindHint)>,
+    trampolines: Vec<(Range<u64>, Trampoline)>,
+}
+
+impl<D, S> ModuleBuilder<D, S>
+where
+    D: Deref<Target = [u8]>,
+    S: ModuleSectionInfo<D>,
+{
+    /// Create a builder with the same arguments as [`Module::new`].
+    pub fn new(name: String, avma_range: Range<u64>, base_avma: u64, section_info: S) -> Self {
+        Self {
+            name,
+            avma_range,
+            base_avma,
+            section_info,
+            unwind_data_kind: None,
+            stubs_svma: None,
+            stub_helper_svma: None,
+            text: None,
+            frame_pointers_guaranteed: false,
+            unwind_hints: Vec::new(),
+            trampolines: Vec::new(),
+        }
+    }
+
+    /// Only use the given kind of unwind information, and ignore the sections which
+    /// belong to other kinds. For example, `UnwindDataKind::DebugFrame` ignores
+    /// `__unwind_info`, `.pdata` and `.eh_frame`.
+    ///
+    /// If the module doesn't have the sections for this kind, it ends up with
+    /// [`UnwindDataKind::None`] or with the fallback kinds of the forced kind, e.g.
+    /// `EhFrame` for `EhFrameHdrAndEhFrame` if the module has no `.eh_frame_hdr`.
+    pub fn unwind_data_kind(mut self, kind: UnwindDataKind) -> Self {
+        self.unwind_data_kind = Some(kind);
+        self
+    }
+
+    /// Set the address range (SVMA) of the mach-O `__stubs` section.
+    pub fn stubs_svma(mut self, svma_range: Range<u64>) -> Self {
+        self.stubs_svma = Some(svma_range);
+        self
+    }
+
+    /// Set the address range (SVMA) of the mach-O `__stub_helper` section.
+    pub fn stub_helper_svma(mut self, svma_range: Range<u64>) -> Self {
+        self.stub_helper_svma = Some(svma_range);
+        self
+    }
+
+    /// Use `data` as the code bytes at `svma_range`, instead of the `__text` / `.text`
+    /// section data and the mach-O `__TEXT` segment data of the module.
+    pub fn text(mut self, svma_range: Range<u64>, data: D) -> Self {
+        self.text = Some((svma_range, data));
+        self
+    }
+
+    /// Declare that all code in the module maintains the frame pointer chain. For return
+    /// addresses in this module, the unwinder then follows the frame pointer without
+    /// looking up the module's unwind information. The first frame is still unwound with
+    /// the unwind information, because the instruction pointer can be in a function
+    /// prologue or epilogue.
+    pub fn frame_pointers_guaranteed(mut self, guaranteed: bool) -> Self {
+        self.frame_pointers_guaranteed = guaranteed;
+        self
+    }
+
+    /// Unwind addresses in `svma_range` according to `hint`, instead of with the unwind
+    /// information of the module. Use this to work around functions whose unwind
+    /// information is known to be wrong, e.g. hand-written assembly in crypto libraries.
+    ///
+    /// Hints apply to first frames and to return addresses. If ranges overlap, the hint
+    /// which was added first wins.
+    pub fn unwind_hint(mut self, svma_range: Range<u64>, hint: UnwindHint) -> Self {
+        self.unwind_hints.push((svma_range, hint));
+        self
+    }
+
+    /// Declare that the function at `svma_range` is a well-known trampoline, which is
+    /// unwound with rules for its kind where its unwind information is wrong. Unwind
+    /// hints take precedence over trampolines.
+    pub fn trampoline(mut self, svma_range: Range<u64>, trampoline: Trampoline) -> Self {
+        self.trampolines.push((svma_range, trampoline));
+        self
+    }
+
+    /// Find the well-known trampolines among `symbols`, which are pairs of a symbol name
+    /// and the symbol's address range (SVMA), e.g. from the symbol table of the module.
+    /// See [`Trampoline::from_symbol_name`] for the recognized names.
+    pub fn recognize_trampolines<I, N>(mut self, symbols: I) -> Self
+    where
+        I: IntoIterator<Item = (N, Range<u64>)>,
+        N: AsRef<str>,
+    {
+        for (name, svma_range) in symbols {
+            if let Some(trampoline) = Trampoline::from_symbol_name(name.as_ref()) {
+                self.trampolines.push((svma_range, trampoline));
+            }
+        }
+        self
+    }
+
+    /// Create the module.
+    pub fn build(self) -> Module<D> {
+        let section_info = OverriddenSectionInfo {
+            inner: self.section_info,
+            unwind_data_kind: self.unwind_data_kind,
+            stubs_svma: self.stubs_svma,
+            stub_helper_svma: self.stub_helper_svma,
+            text: self.text,
+        };
+        let mut module = Module::new(self.name, self.avma_range, self.base_avma, section_info);
+        module.set_frame_pointers_guaranteed(self.frame_pointers_guaranteed);
+        module.set_unwind_hints(self.unwind_hints);
+        module.set_trampolines(self.trampolines);
+        module
+    }
+}
+
+struct OverriddenSectionInfo<D, S> {
+    inner: S,
+    unwind_data_kind: Option<UnwindDataKind>,
+    stubs_svma: Option<Range<u64>>,
+    stub_helper_svma: Option<Range<u64>>,
+    text: Option<(Range<u64>, D)>,
+}
+
+impl<D, S> OverriddenSectionInfo<D, S> {
+    /// Whether the section with unwind information is hidden because a different kind
+    /// of unwind information was forced.
+    fn is_hidden(&self, name: &[u8]) -> bool {
+        let Some(kind) = self.unwind_data_kind else {
+            return false;
+        };
+        let kinds: &[UnwindDataKind] = match name {
+            b"__unwind_info" => &[UnwindDataKind::CompactUnwindInfoAndEhFrame],
+            b".pdata" => &[UnwindDataKind::PeUnwindInfo],
+            b".eh_frame_hdr" | b"__eh_frame_hdr" => &[UnwindDataKind::EhFrameHdrAndEhFrame],
+            b".eh_frame" | b"__eh_frame" => &[
+                UnwindDataKind::CompactUnwindInfoAndEhFrame,
+                UnwindDataKind::EhFrameHdrAndEhFrame,
+                UnwindDataKind::EhFrame,
+            ],
+            b".debug_frame" | b"__debug_frame" => &[UnwindDataKind::DebugFrame],
+            _ => return false,
+        };
+        !kinds.contains(&kind)
+    }
+
+    fn has_text_override(&self, name: &[u8]) -> bool {
+        self.text.is_some() && matches!(name, b"__text" | b".text")
+    }
+}
+
+impl<D, S> ModuleSectionInfo<D> for OverriddenSectionInfo<D, S>
+where
+    S: ModuleSectionInfo<D>,
+{
+    fn base_svma(&self) -> u64 {
+        self.inner.base_svma()
+    }
+
+    fn section_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        match name {
+            b"__stubs" | b"__auth_stubs" if self.stubs_svma.is_some() => self.stubs_svma.clone(),
+            b"__stub_helper" if self.stub_helper_svma.is_some() => self.stub_helper_svma.clone(),
+            _ if self.has_text_override(name) => self.text.as_ref().map(|(r, _)| r.clone()),
+            _ => self.inner.section_svma_range(name),
+        }
+    }
+
+    fn section_data(&mut self, name: &[u8]) -> Option<D> {
+        if self.is_hidden(name) {
+            return None;
+        }
+        if self.has_text_override(name) {
+            return self.text.take().map(|(_, data)| data);
+        }
+        self.inner.section_data(name)
+    }
+
+    fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        self.inner.segment_svma_range(name)
+    }
+
+    fn segment_data(&mut self, name: &[u8]) -> Option<D> {
+        if self.text.is_some() && matches!(name, b"__TEXT" | b"__TEXT_EXEC") {
+            return None;
+        }
+        self.inner.segment_data(name)
+    }
+
+    fn instruction_analysis_for_dwarf_cfi_gaps(&self) -> bool {
+        self.inner.instruction_analysis_for_dwarf_cfi_gaps()
+    }
+
+    fn synthesize_rules_from_prologues(&self) -> bool {
+        match self.unwind_data_kind {
+            Some(UnwindDataKind::PrologueAnalysis) => true,
+            Some(UnwindDataKind::None) => false,
+            _ => self.inner.synthesize_rules_from_prologues(),
+        }
+    }
+
+    fn function_starts(&mut self) -> Option<Vec<u64>> {
+        self.inner.function_starts()
+    }
+
+    fn signs_return_addresses(&self) -> Option<bool> {
+        self.inner.signs_return_addresses()
+    }
+
+    fn code_id(&mut self) -> Option<CodeId> {
+        self.inner.code_id()
+    }
+
+    fn debug_id(&mut self) -> Option<DebugId> {
+        self.inner.debug_id()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ExplicitModuleSectionInfo;
+    use alloc::vec;
+
+    #[test]
+    fn test_forced_unwind_data_kind() {
+        let section_info = || ExplicitModuleSectionInfo::<Vec<u8>> {
+            eh_frame: Some(vec![0; 4]),
+            eh_frame_hdr: Some(vec![0; 4]),
+            ..Default::default()
+        };
+        let module = Module::new(String::from("test"), 0x1000..0x2000, 0x1000, section_info());
+        assert_eq!(
+            module.unwind_data_kind(),
+            UnwindDataKind::EhFrameHdrAndEhFrame
+        );
+
+        let module =
+            ModuleBuilder::new(String::from("test"), 0x1000..0x2000, 0x1000, section_info())
+                .unwind_data_kind(UnwindDataKind::None)
+                .frame_pointers_guaranteed(true)
+                .build();
+        assert_eq!(module.unwind_data_kind(), UnwindDataKind::None);
+        assert!(module.frame_pointers_guaranteed());
+    }
+}
@@ -0,0 +1,95 @@
+use alloc::vec::Vec;
+
+/// An identifier of a binary, as stored in the binary itself. Symbol servers use this to
+/// find the binary.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CodeId {
+    /// The contents of the `NT_GNU_BUILD_ID` note of an ELF binary.
+    ElfBuildId(Vec<u8>),
+    /// The `LC_UUID` of a mach-O binary.
+    MachoUuid([u8; 16]),
+    /// The `TimeDateStamp` and `SizeOfImage` fields of a PE binary.
+    PeTimestampAndImageSize { timestamp: u32, image_size: u32 },
+}
+
+impl CodeId {
+    /// Derive the debug ID from the code ID, following the conventions of Breakpad.
+    ///
+    /// This returns `None` for PE binaries, whose debug ID comes from the CodeView record
+    /// of the PDB file instead.
+    pub fn debug_id(&self) -> Option<DebugId> {
+        match self {
+            Self::ElfBuildId(build_id) => {
+                // The first 16 bytes of the build ID, interpreted as a little-endian GUID.
+                let mut uuid = [0; 16];
+                let len = build_id.len().min(16);
+                uuid[..len].copy_from_slice(&build_id[..len]);
+                uuid[..4].reverse();
+                uuid[4..6].reverse();
+                uuid[6..8].reverse();
+                Some(DebugId { uuid, appendix: 0 })
+            }
+            Self::MachoUuid(uuid) => Some(DebugId {
+                uuid: *uuid,
+                appendix: 0,
+            }),
+            Self::PeTimestampAndImageSize { .. } => None,
+        }
+    }
+}
+
+impl core::fmt::Display for CodeId {
+    /// Formats the code ID the way symbol servers expect it.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ElfBuildId(build_id) => build_id.iter().try_for_each(|b| write!(f, "{b:02x}")),
+            Self::MachoUuid(uuid) => uuid.iter().try_for_each(|b| write!(f, "{b:02X}")),
+            Self::PeTimestampAndImageSize {
+                timestamp,
+                image_size,
+            } => write!(f, "{timestamp:08X}{image_size:x}"),
+        }
+    }
+}
+
+/// The identifier of the debug information of a binary, i.e. a GUID and an age or
+/// appendix. This is what profilers use to match stacks to symbol files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DebugId {
+    /// The GUID, in the byte order in which it is displayed.
+    pub uuid: [u8; 16],
+    /// The PDB age for PE binaries, zero otherwise.
+    pub appendix: u32,
+}
+
+impl core::fmt::Display for DebugId {
+    /// Formats the debug ID in the Breakpad format, e.g.
+    /// `0E3D9F5D1E7A3AE8B1E56A7E1E7F0BA90`.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.uuid.iter().try_for_each(|b| write!(f, "{b:02X}"))?;
+        write!(f, "{:X}", self.appendix)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn test_elf_debug_id() {
+        let code_id = CodeId::ElfBuildId(vec![
+            0x5d, 0x9f, 0x3d, 0x0e, 0x7a, 0x1e, 0xe8, 0x3a, 0xb1, 0xe5, 0x6a, 0x7e, 0x1e, 0x7f,
+            0x0b, 0xa9, 0x12, 0x34, 0x56, 0x78,
+        ]);
+        assert_eq!(
+            code_id.to_string(),
+            "5d9f3d0e7a1ee83ab1e56a7e1e7f0ba912345678"
+        );
+        assert_eq!(
+            code_id.debug_id().unwrap().to_string(),
+            "0E3D9F5D1E7A3AE8B1E56A7E1E7F0BA90"
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use core::ops::Range;
+
+use object::{Architecture, Object, ObjectSection, ObjectSegment, SubArchitecture};
+
+use crate::{CodeId, DebugId, ModuleSectionInfo};
+
+/// Parsed ELF, mach-O and PE files from the `object` crate can be passed to
+/// [`Module::new`](crate::Module::new) directly. The section data is converted to `D`
+/// with `From<&[u8]>`.
+///
+/// Compressed sections, e.g. `.zdebug_frame`, are not supported. Implement
+/// [`ModuleSectionInfo`] yourself if you need them.
+impl<'data, O, D> ModuleSectionInfo<D> for O
+where
+    O: Object<'data>,
+    D: From<&'data [u8]>,
+{
+    fn base_svma(&self) -> u64 {
+        relative_address_base(self)
+    }
+
+    fn section_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        let section = self.section_by_name_bytes(name)?;
+        Some(section.address()..section.address() + section.size())
+    }
+
+    fn section_data(&mut self, name: &[u8]) -> Option<D> {
+        let section = self.section_by_name_bytes(name)?;
+        section.data().ok().map(D::from)
+    }
+
+    fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
+        let segment = self
+            .segments()
+            .find(|segment| segment.name_bytes() == Ok(Some(name)))?;
+        Some(segment.address()..segment.address() + segment.size())
+    }
+
+    fn segment_data(&mut self, name: &[u8]) -> Option<D> {
+        let segment = self
+            .segments()
+            .find(|segment| segment.name_bytes() == Ok(Some(name)))?;
+        segment.data().ok().map(D::from)
+    }
+
+    /// Detects arm64e mach-O binaries. Returns `None` for other binaries, whose
+    /// return addresses may or may not be signed.
+    fn signs_return_addresses(&self) -> Option<bool> {
+        if self.architecture() != Architecture::Aarch64 {
+            return None;
+        }
+        // The subtype isn't recognized if it has the pointer authentication ABI version
+        // bits set, which is the case for most arm64e binaries. Those binaries still have
+        // mach-O sections for authenticated pointers, which arm64 binaries don't have.
+        let is_arm64e = self.sub_architecture() == Some(SubArchitecture::Arm64E)
+            || [&b"__auth_stubs"[..], b"__auth_got", b"__auth_ptr"]
+                .iter()
+                .any(|name| self.section_by_name_bytes(name).is_some());
+        is_arm64e.then_some(true)
+    }
+
+    fn code_id(&mut self) -> Option<CodeId> {
+        if let Ok(Some(build_id)) = self.build_id() {
+            return Some(CodeId::ElfBuildId(build_id.into()));
+        }
+        if let Ok(Some(uuid)) = self.mach_uuid() {
+            return Some(CodeId::MachoUuid(uuid));
+        }
+        None
+    }
+
+    fn debug_id(&mut self) -> Option<DebugId> {
+        // Only PE files have a debug ID of their own. For ELF and mach-O files, the
+        // debug ID is derived from the code ID.
+        let pdb_info = self.pdb_info().ok()??;
+        // The GUID is stored with its first three fields in little-endian byte order.
+        let mut uuid = pdb_info.guid();
+        uuid[..4].reverse();
+        uuid[4..6].reverse();
+        uuid[6..8].reverse();
+        Some(DebugId {
+            uuid,
+            appendix: pdb_info.age(),
+        })
+    }
+}
+
+/// Relative addresses are u32 offsets which are relative to some "base address".
+///
+/// This function computes that base address. It is defined as follows:
+///
+///  - For Windows binaries, the base address is the "image base address".
+///  - For mach-O binaries, the base address is the vmaddr of the __TEXT segment, or
+///    of the segment which contains the mach header if there is no __TEXT segment.
+///  - For ELF binaries, the base address is zero.
+fn relative_address_base<'data>(object_file: &impl Object<'data>) -> u64 {
+    if let Some(text_segment) = object_file
+        .segments()
+        .find(|s| s.name() == Ok(Some("__TEXT")))
+    {
+        // This is a mach-O image. "Relative addresses" are relative to the
+        // vmaddr of the __TEXT segment.
+        return text_segment.address();
+    }
+
+    if object_file
+        .segments()
+        .any(|s| s.name() == Ok(Some("__TEXT_EXEC")))
+    {
+        // This mach-O image has no __TEXT segment, which can happen for images in
+        // kernel collections. Relative addresses are relative to the mach header,
+        // which is at the start of the segment with file offset zero.
+        if let Some(header_segment) = object_file.segments().find(|s| s.file_range().0 == 0) {
+            return header_segment.address();
+        }
+    }
+
+    // For PE binaries, relative_address_base() returns the image base address.
+    // Otherwise it returns zero. This gives regular ELF images a base address of zero,
+    // which is what we want.
+    object_file.relative_address_base()
+}
@@ -0,0 +1,94 @@
+use alloc::vec::Vec;
+use rayon::prelude::*;
+
+use crate::rule_cache::CacheStats;
+use crate::unwinder::Unwinder;
+
+/// The result of [`unwind_batch_parallel`].
+#[derive(Debug, Clone)]
+pub struct BatchUnwindResult<T> {
+    /// The result for every sample, in the order of the samples.
+    pub results: Vec<T>,
+    /// The merged usage statistics of the caches of all shards.
+    pub cache_stats: CacheStats,
+}
+
+/// Unwind many samples in parallel, e.g. when converting a recorded profile offline.
+///
+/// The samples are split into one shard per thread of the rayon thread pool. Every
+/// shard gets its own cache, and all shards share `unwinder`, which isn't modified, so
+/// the modules have to be added before the batch is unwound. `unwind_sample` is called
+/// for every sample with the unwinder and the cache of its shard, and usually unwinds
+/// the sample with [`Unwinder::iter_frames`].
+pub fn unwind_batch_parallel<U, S, T, F>(
+    unwinder: &U,
+    samples: &[S],
+    unwind_sample: F,
+) -> BatchUnwindResult<T>
+where
+    U: Unwinder + Sync,
+    U::Cache<'static>: Default,
+    S: Sync,
+    T: Send,
+    F: Fn(&U, &mut U::Cache<'static>, &S) -> T + Sync,
+{
+    let shard_len = samples.len().div_ceil(rayon::current_num_threads()).max(1);
+    let shards: Vec<(Vec<T>, CacheStats)> = samples
+        .par_chunks(shard_len)
+        .map(|shard| {
+            let mut cache = U::Cache::<'static>::default();
+            let results = shard
+                .iter()
+                .map(|sample| unwind_sample(unwinder, &mut cache, sample))
+                .collect();
+            (results, unwinder.cache_stats(&cache))
+        })
+        .collect();
+
+    let mut batch = BatchUnwindResult {
+        results: Vec::with_capacity(samples.len()),
+        cache_stats: CacheStats::new(),
+    };
+    for (results, cache_stats) in shards {
+        batch.results.extend(results);
+        batch.cache_stats.merge(&cache_stats);
+    }
+    batch
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{UnwindRegsX86_64, UnwinderX86_64};
+    use crate::FrameAddress;
+
+    #[test]
+    fn test_unwind_batch_parallel() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        // Frame pointer chain: [0x10] = caller bp, [0x18] = return address.
+        let stack: Vec<u64> = vec![0, 0, 0x20, 0x2000, 0, 0];
+        let samples: Vec<u64> = (0..100).map(|i| 0x1000 + i).collect();
+        let batch = unwind_batch_parallel(&unwinder, &samples, |unwinder, cache, &ip| {
+            let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+            let mut iter = unwinder.iter_frames(
+                ip,
+                UnwindRegsX86_64::new(ip, 0x10, 0x10),
+                cache,
+                &mut read_stack,
+            );
+            let mut frames = Vec::new();
+            while let Ok(Some(frame)) = iter.next() {
+                frames.push(frame);
+            }
+            frames
+        });
+        assert_eq!(batch.results.len(), 100);
+        assert_eq!(
+            batch.results[42],
+            vec![
+                FrameAddress::from_instruction_pointer(0x1000 + 42),
+                FrameAddress::from_return_address(0x2000).unwrap(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use crate::code_address::FrameAddress;
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::Unwinder;
+
+/// Register values of which some are unknown, e.g. because the sampler only captured
+/// the instruction pointer and the stack pointer. See
+/// [`Unwinder::unwind_frame_with_partial_regs`].
+///
+/// This is implemented by [`PartialUnwindRegsX86_64`](crate::x86_64::PartialUnwindRegsX86_64)
+/// and [`PartialUnwindRegsAarch64`](crate::aarch64::PartialUnwindRegsAarch64).
+pub trait PartialUnwindRegs {
+    /// The register type of the unwinder.
+    type Regs;
+
+    /// Whether all registers are known.
+    fn is_complete(&self) -> bool;
+
+    /// The registers, with every unknown register set to `placeholder`.
+    fn with_placeholder(&self, placeholder: u64) -> Self::Regs;
+
+    /// Set the registers to the registers of the caller, which were unwound twice, from
+    /// the two register sets that [`PartialUnwindRegs::with_placeholder`] returned for
+    /// two different placeholders. A register whose values differ depends on an unknown
+    /// register, and becomes unknown.
+    fn set_from_unwound(&mut self, first: &Self::Regs, second: &Self::Regs);
+}
+
+/// The values for unknown registers. They are 16-byte aligned so that alignment checks
+/// treat them alike, far apart so that no offset from one reaches the other, and differ
+/// in their low bits so that stripping pointer authentication bits keeps them apart.
+const PLACEHOLDERS: [u64; 2] = [0x5a5a_5a5a_5a5a_5a50, 0x3c3c_3c3c_3c3c_3c30];
+
+/// The implementation of [`Unwinder::unwind_frame_with_partial_regs`].
+pub(crate) fn unwind_frame_with_partial_regs<U, R, F>(
+    unwinder: &U,
+    address: FrameAddress,
+    regs: &mut R,
+    cache: &mut U::Cache<'_>,
+    read_stack: &mut F,
+) -> Result<Option<u64>, Error>
+where
+    U: Unwinder,
+    R: PartialUnwindRegs<Regs = U::UnwindRegs>,
+    F: MemoryReader,
+{
+    let mut first = regs.with_placeholder(PLACEHOLDERS[0]);
+    let first_result = unwinder.unwind_frame(address, &mut first, cache, read_stack);
+    if regs.is_complete() {
+        regs.set_from_unwound(&first, &first);
+        return first_result;
+    }
+    // Unwind again with a different value for the unknown registers. If the result
+    // changes, the frame's rule needs an unknown register.
+    let mut second = regs.with_placeholder(PLACEHOLDERS[1]);
+    let second_result = unwinder.unwind_frame(address, &mut second, cache, read_stack);
+    if first_result != second_result {
+        return Err(Error::MissingRegisterValue);
+    }
+    regs.set_from_unwound(&first, &second);
+    first_result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, PartialUnwindRegsX86_64, UnwindRuleX86_64, UnwinderX86_64};
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_partial_regs_without_frame_pointer() {
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.set_fallback_rule(UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp);
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x1234, 0x2345, 0x3456];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+
+        // The first frame returns to the address at sp, which doesn't need bp.
+        let mut regs = PartialUnwindRegsX86_64::new(0x1000, 0, None);
+        let address = FrameAddress::from_instruction_pointer(0x1000);
+        let result = unwinder.unwind_frame_with_partial_regs(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(result, Ok(Some(0x1234)));
+        assert_eq!(regs, PartialUnwindRegsX86_64::new(0x1234, 8, None));
+
+        // The caller is unwound with the frame pointer, which is unknown.
+        let address = FrameAddress::from_return_address(0x1234).unwrap();
+        let result = unwinder.unwind_frame_with_partial_regs(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(result, Err(Error::MissingRegisterValue));
+
+        // With a frame pointer, this is the same as unwinding with complete registers.
+        let mut regs = PartialUnwindRegsX86_64::new(0x1234, 8, Some(8));
+        let result = unwinder.unwind_frame_with_partial_regs(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(result, Ok(Some(0x3456)));
+        assert_eq!(regs, PartialUnwindRegsX86_64::new(0x3456, 24, Some(0x2345)));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::memory_reader::MemoryReadError;
+use crate::unwind_step::{Resumable, Step};
 use crate::{arch::Arch, unwind_result::UnwindResult};
 use core::ops::Range;
 
@@ -87,14 +89,28 @@ fn memory_at_rva<D: core::ops::Deref<Target = [u8]>>(
 }
 
 pub trait PeUnwinding: Arch {
-    fn unwind_frame<F, D>(
+    /// The evaluation of unwind codes which couldn't be translated into an unwind rule.
+    /// It sets the caller's registers and results in the return address.
+    type PeEvaluation: Resumable<Self::UnwindRegs, Output = Result<u64, PeUnwinderError>>;
+
+    fn unwind_frame<D>(
         sections: PeSections<D>,
         address: u32,
-        regs: &mut Self::UnwindRegs,
         is_first_frame: bool,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<Self::UnwindRule>, PeUnwinderError>
+    ) -> Result<UnwindResult<Self::UnwindRule, Self::PeEvaluation>, PeUnwinderError>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         D: core::ops::Deref<Target = [u8]>;
 }
+
+/// The evaluation of architectures which don't support PE unwinding.
+impl<C> Resumable<C> for core::convert::Infallible {
+    type Output = Result<u64, PeUnwinderError>;
+
+    fn step(&mut self, _context: &mut C) -> Step<Self::Output> {
+        match *self {}
+    }
+
+    fn provide(&mut self, _value: Result<u64, MemoryReadError>) {
+        match *self {}
+    }
+}
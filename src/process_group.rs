@@ -0,0 +1,156 @@
+use alloc::vec::Vec;
+use std::collections::HashMap;
+
+use core::ops::Deref;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::{AddModuleOutcome, Module, Unwinder};
+use crate::FrameAddress;
+
+/// Manages the module lists of many processes, e.g. in a system-wide profiler, and shares
+/// the unwind data of identical modules between them.
+///
+/// Libraries like libc are loaded into almost every process. With one unwinder per
+/// process, their unwind data would be stored once per process. This type compares the
+/// unwind data of every added module with the unwind data of the modules which were
+/// added before, and if it's identical, the new module uses the existing copy.
+///
+/// The rule cache can be shared between processes, but it is invalidated whenever the
+/// unwound process changes, so it's better to use one cache per process if samples from
+/// different processes are interleaved.
+pub struct ProcessGroupUnwinder<U: Unwinder> {
+    /// Cloned to create the unwinder of every new process. Also used for processes
+    /// without any modules.
+    template: U,
+    processes: HashMap<u32, U>,
+    /// Modules with distinct unwind data, keyed by a hash of their unwind data.
+    shared_modules: HashMap<u64, Vec<U::Module>>,
+}
+
+impl<D, U> ProcessGroupUnwinder<U>
+where
+    D: Deref<Target = [u8]>,
+    U: Unwinder<Module = Module<D>>,
+{
+    /// Create a manager. `template` is cloned for every new process, so that settings
+    /// like the fallback rule apply to all processes.
+    pub fn new(template: U) -> Self {
+        Self {
+            template,
+            processes: HashMap::new(),
+            shared_modules: HashMap::new(),
+        }
+    }
+
+    /// Add a module to the process `pid`, see [`Unwinder::add_module`]. If a module
+    /// with identical unwind data was added before, to any process, the unwind data of
+    /// `module` is dropped and the existing copy is used instead.
+    pub fn add_module(&mut self, pid: u32, mut module: Module<D>) -> AddModuleOutcome {
+        if let Some(hash) = module.content_hash() {
+            let candidates = self.shared_modules.entry(hash).or_default();
+            if !candidates
+                .iter()
+                .any(|candidate| module.share_sections_with(candidate))
+            {
+                candidates.push(module.clone());
+            }
+        }
+        self.processes
+            .entry(pid)
+            .or_insert_with(|| self.template.clone())
+            .add_module(module)
+    }
+
+    /// Remove a module from the process `pid`, see [`Unwinder::remove_module`].
+    pub fn remove_module(&mut self, pid: u32, module_avma_range_start: u64) {
+        if let Some(unwinder) = self.processes.get_mut(&pid) {
+            unwinder.remove_module(module_avma_range_start);
+            self.remove_unused_shared_modules();
+        }
+    }
+
+    /// Forget the process `pid` and all its modules, e.g. when the process exited.
+    pub fn remove_process(&mut self, pid: u32) {
+        if self.processes.remove(&pid).is_some() {
+            self.remove_unused_shared_modules();
+        }
+    }
+
+    fn remove_unused_shared_modules(&mut self) {
+        self.shared_modules.retain(|_, modules| {
+            modules.retain(Module::has_shared_sections);
+            !modules.is_empty()
+        });
+    }
+
+    /// The unwinder of the process `pid`, if any modules were added to it.
+    pub fn unwinder(&self, pid: u32) -> Option<&U> {
+        self.processes.get(&pid)
+    }
+
+    /// The number of distinct copies of unwind data which are held for all processes.
+    pub fn unique_module_data_count(&self) -> usize {
+        self.shared_modules.values().map(Vec::len).sum()
+    }
+
+    /// Unwind a single frame in the process `pid`, see [`Unwinder::unwind_frame`].
+    /// Processes without modules are unwound with the fallback rule.
+    pub fn unwind_frame<F>(
+        &self,
+        pid: u32,
+        address: FrameAddress,
+        regs: &mut U::UnwindRegs,
+        cache: &mut U::Cache<'_>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.processes
+            .get(&pid)
+            .unwrap_or(&self.template)
+            .unwind_frame(address, regs, cache, read_stack)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::UnwinderX86_64;
+    use crate::ExplicitModuleSectionInfo;
+    use std::string::String;
+    use std::vec;
+
+    fn libc(avma_start: u64, eh_frame: Vec<u8>) -> Module<Vec<u8>> {
+        Module::new(
+            String::from("libc.so.6"),
+            avma_start..avma_start + 0x1000,
+            avma_start,
+            ExplicitModuleSectionInfo {
+                eh_frame: Some(eh_frame),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_shared_module_data() {
+        let mut group = ProcessGroupUnwinder::new(UnwinderX86_64::<Vec<u8>>::new());
+        group.add_module(1, libc(0x10000, vec![0; 16]));
+        group.add_module(2, libc(0x20000, vec![0; 16]));
+        assert_eq!(group.unique_module_data_count(), 1);
+        group.add_module(3, libc(0x30000, vec![1; 16]));
+        assert_eq!(group.unique_module_data_count(), 2);
+
+        assert_eq!(group.unwinder(1).unwrap().max_known_code_address(), 0x11000);
+        assert_eq!(group.unwinder(2).unwrap().max_known_code_address(), 0x21000);
+        assert!(group.unwinder(4).is_none());
+
+        group.remove_process(3);
+        assert_eq!(group.unique_module_data_count(), 1);
+        group.remove_module(1, 0x10000);
+        group.remove_module(2, 0x20000);
+        assert_eq!(group.unique_module_data_count(), 0);
+    }
+}
@@ -0,0 +1,123 @@
+use alloc::boxed::Box;
+
+use crate::unwind_rule::UnwindRule;
+
+const PREDICTOR_ENTRY_COUNT: usize = 61;
+
+/// The number of times in a row that a frame has to be unwound with the same stack
+/// layout before the layout is used to predict later unwinds of that frame.
+const HOT_THRESHOLD: u8 = 2;
+
+/// Predicts how to unwind frames whose DWARF CFI couldn't be translated into a
+/// cacheable rule, e.g. the frames of interpreter loops like `PyEval_EvalFrameDefault`,
+/// which are unwound over and over at different stack pointers.
+///
+/// After such a frame was unwound with the CFI, the unwinder looks for a rule which
+/// moves the stack pointer by the same delta and restores the return address and frame
+/// pointer from the same stack slots. Once the same rule was observed twice in a row for
+/// an address, later unwinds at that address use the rule and skip FDE evaluation.
+pub struct ReturnAddressPredictor<R: UnwindRule> {
+    entries: Box<[Option<PredictorEntry<R>>; PREDICTOR_ENTRY_COUNT]>,
+    stats: PredictorStats,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PredictorEntry<R: UnwindRule> {
+    address: u64,
+    modules_generation: u16,
+    rule: R,
+    observation_count: u8,
+}
+
+impl<R: UnwindRule> ReturnAddressPredictor<R> {
+    pub fn new() -> Self {
+        Self {
+            entries: Box::new([None; PREDICTOR_ENTRY_COUNT]),
+            stats: PredictorStats::new(),
+        }
+    }
+
+    /// The rule for `address`, if its frame is hot.
+    pub fn predict(&mut self, address: u64, modules_generation: u16) -> Option<R> {
+        match &self.entries[Self::slot(address)] {
+            Some(entry)
+                if entry.address == address
+                    && entry.modules_generation == modules_generation
+                    && entry.observation_count >= HOT_THRESHOLD =>
+            {
+                self.stats.hit_count += 1;
+                Some(entry.rule)
+            }
+            _ => {
+                self.stats.miss_count += 1;
+                None
+            }
+        }
+    }
+
+    /// Record the rule which reproduces the unwind of the frame at `address` with its
+    /// CFI, or `None` if no rule does.
+    pub fn observe(&mut self, address: u64, modules_generation: u16, rule: Option<R>) {
+        let slot = &mut self.entries[Self::slot(address)];
+        let Some(rule) = rule else {
+            if matches!(slot, Some(entry) if entry.address == address) {
+                *slot = None;
+            }
+            return;
+        };
+        match slot {
+            Some(entry)
+                if entry.address == address && entry.modules_generation == modules_generation =>
+            {
+                if entry.rule == rule {
+                    entry.observation_count = entry.observation_count.saturating_add(1);
+                } else {
+                    self.stats.layout_change_count += 1;
+                    entry.rule = rule;
+                    entry.observation_count = 1;
+                }
+            }
+            _ => {
+                *slot = Some(PredictorEntry {
+                    address,
+                    modules_generation,
+                    rule,
+                    observation_count: 1,
+                });
+            }
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of_val(&*self.entries)
+    }
+
+    pub fn stats(&self) -> PredictorStats {
+        self.stats
+    }
+
+    fn slot(address: u64) -> usize {
+        (address % PREDICTOR_ENTRY_COUNT as u64) as usize
+    }
+}
+
+/// Statistics about the return address predictor, see e.g.
+/// [`CacheX86_64::predictor_stats`](crate::x86_64::CacheX86_64::predictor_stats).
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PredictorStats {
+    /// The number of frames which were unwound with a predicted rule, without
+    /// evaluating their CFI.
+    pub hit_count: u64,
+    /// The number of rule cache misses for which there was no prediction.
+    pub miss_count: u64,
+    /// The number of times a frame was unwound with a different stack layout than the
+    /// previous time, which resets its prediction.
+    pub layout_change_count: u64,
+}
+
+impl PredictorStats {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
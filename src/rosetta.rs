@@ -0,0 +1,30 @@
+/// Whether `name` is the path of a module which belongs to Rosetta 2, the translator
+/// which runs x86_64 code on Apple silicon: the runtime, which is mapped into every
+/// translated process, or an ahead-of-time translation of an x86_64 binary.
+///
+/// The frames of these modules follow the conventions of the emulated x86_64 code, not
+/// of aarch64, so a native unwinder can't continue past them.
+pub(crate) fn is_rosetta_module_name(name: &str) -> bool {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    name.ends_with("/rosetta/runtime")
+        || file_name == "libRosettaRuntime"
+        || (name.contains("/oah/") && file_name.ends_with(".aot"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_rosetta_module_name() {
+        assert!(is_rosetta_module_name("/usr/libexec/rosetta/runtime"));
+        assert!(is_rosetta_module_name(
+            "/Library/Apple/usr/libexec/oah/libRosettaRuntime"
+        ));
+        assert!(is_rosetta_module_name(
+            "/private/var/db/oah/2b6e/5f1c/libfoo.dylib.aot"
+        ));
+        assert!(!is_rosetta_module_name("/usr/lib/libSystem.B.dylib"));
+        assert!(!is_rosetta_module_name("/tmp/report.aot"));
+    }
+}
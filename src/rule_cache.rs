@@ -1,25 +1,97 @@
 use alloc::boxed::Box;
+#[cfg(not(target_has_atomic = "64"))]
+use core::marker::PhantomData;
 
+#[cfg(target_has_atomic = "64")]
+use crate::shared_rule_cache::{SharedCacheSlot, SharedRuleCache};
 use crate::unwind_rule::UnwindRule;
 
 const CACHE_ENTRY_COUNT: usize = 509;
 
-pub struct RuleCache<R: UnwindRule> {
-    entries: Box<[Option<CacheEntry<R>>; CACHE_ENTRY_COUNT]>,
+/// `'s` is the lifetime of the slots of a shared cache.
+pub struct RuleCache<'s, R: UnwindRule> {
+    storage: RuleCacheStorage<'s, R>,
     stats: CacheStats,
 }
 
-impl<R: UnwindRule> RuleCache<R> {
+enum RuleCacheStorage<'s, R: UnwindRule> {
+    Heap {
+        entries: Box<[Option<CacheEntry<R>>; CACHE_ENTRY_COUNT]>,
+        /// One bit per slot, set if the slot's rule is the fallback rule. This is kept
+        /// out of the entries so that they don't grow.
+        fallback_slots: [u64; CACHE_ENTRY_COUNT.div_ceil(64)],
+        /// Shared caches need 64-bit atomics, so without them only the lifetime is left.
+        #[cfg(not(target_has_atomic = "64"))]
+        shared_slots: PhantomData<&'s ()>,
+    },
+    #[cfg(target_has_atomic = "64")]
+    Shared(SharedRuleCache<'s>),
+}
+
+impl<'s, R: UnwindRule> RuleCache<'s, R> {
     pub fn new() -> Self {
         Self {
-            entries: Box::new([None; CACHE_ENTRY_COUNT]),
+            storage: RuleCacheStorage::Heap {
+                entries: Box::new([None; CACHE_ENTRY_COUNT]),
+                fallback_slots: [0; CACHE_ENTRY_COUNT.div_ceil(64)],
+                #[cfg(not(target_has_atomic = "64"))]
+                shared_slots: PhantomData,
+            },
+            stats: CacheStats::new(),
+        }
+    }
+
+    /// Create a cache whose entries are stored in `slots`, see [`SharedRuleCache`].
+    #[cfg(target_has_atomic = "64")]
+    pub fn new_shared(slots: &'s [SharedCacheSlot], layout_id: u64) -> Self {
+        Self {
+            storage: RuleCacheStorage::Shared(SharedRuleCache::new(slots, layout_id)),
             stats: CacheStats::new(),
         }
     }
 
+    /// Change the layout ID of a shared cache. Does nothing for other caches.
+    pub fn set_layout_id(&mut self, layout_id: u64) {
+        #[cfg(target_has_atomic = "64")]
+        if let RuleCacheStorage::Shared(shared) = &mut self.storage {
+            shared.set_layout_id(layout_id);
+        }
+        #[cfg(not(target_has_atomic = "64"))]
+        let _ = layout_id;
+    }
+
+    /// The number of bytes held by the cache entries. Shared slots aren't included,
+    /// because their memory is owned by the caller.
+    pub fn memory_usage(&self) -> usize {
+        match &self.storage {
+            RuleCacheStorage::Heap { entries, .. } => core::mem::size_of_val(&**entries),
+            #[cfg(target_has_atomic = "64")]
+            RuleCacheStorage::Shared(_) => 0,
+        }
+    }
+
     pub fn lookup(&mut self, address: u64, modules_generation: u16) -> CacheResult<R> {
-        let slot = (address % (CACHE_ENTRY_COUNT as u64)) as u16;
-        match &self.entries[slot as usize] {
+        let (entries, fallback_slots) = match &self.storage {
+            RuleCacheStorage::Heap {
+                entries,
+                fallback_slots,
+                ..
+            } => (entries, fallback_slots),
+            #[cfg(target_has_atomic = "64")]
+            RuleCacheStorage::Shared(shared) => {
+                let slot = shared.slot_for_address(address);
+                if let Some((rule, is_fallback)) = shared.lookup(slot, address, &mut self.stats) {
+                    return CacheResult::Hit { rule, is_fallback };
+                }
+                return CacheResult::Miss(CacheHandle {
+                    slot,
+                    address,
+                    modules_generation,
+                });
+            }
+        };
+        let slot = (address % (CACHE_ENTRY_COUNT as u64)) as usize;
+        match &entries[slot] {
             None => {
                 self.stats.miss_empty_slot_count += 1;
             }
@@ -27,7 +99,10 @@ impl<R: UnwindRule> RuleCache<R> {
                 if entry.modules_generation == modules_generation {
                     if entry.address == address {
                         self.stats.hit_count += 1;
-                        return CacheResult::Hit(entry.unwind_rule);
+                        return CacheResult::Hit {
+                            rule: entry.unwind_rule,
+                            is_fallback: fallback_slots[slot / 64] & (1 << (slot % 64)) != 0,
+                        };
                     } else {
                         self.stats.miss_wrong_address_count += 1;
                     }
@@ -43,17 +118,37 @@ impl<R: UnwindRule> RuleCache<R> {
         })
     }
 
-    pub fn insert(&mut self, handle: CacheHandle, unwind_rule: R) {
+    /// Store the rule for the looked up address. `is_fallback` says whether the rule is
+    /// the unwinder's fallback rule, which is returned on later hits.
+    pub fn insert(&mut self, handle: CacheHandle, unwind_rule: R, is_fallback: bool) {
         let CacheHandle {
             slot,
             address,
             modules_generation,
         } = handle;
-        self.entries[slot as usize] = Some(CacheEntry {
-            address,
-            modules_generation,
-            unwind_rule,
-        });
+        match &mut self.storage {
+            RuleCacheStorage::Heap {
+                entries,
+                fallback_slots,
+                ..
+            } => {
+                entries[slot] = Some(CacheEntry {
+                    address,
+                    modules_generation,
+                    unwind_rule,
+                });
+                let bit = 1 << (slot % 64);
+                if is_fallback {
+                    fallback_slots[slot / 64] |= bit;
+                } else {
+                    fallback_slots[slot / 64] &= !bit;
+                }
+            }
+            #[cfg(target_has_atomic = "64")]
+            RuleCacheStorage::Shared(shared) => {
+                shared.insert(slot, address, unwind_rule, is_fallback)
+            }
+        }
     }
 
     /// Returns a snapshot of the cache usage statistics.
@@ -64,20 +159,16 @@ impl<R: UnwindRule> RuleCache<R> {
 
 pub enum CacheResult<R: UnwindRule> {
     Miss(CacheHandle),
-    Hit(R),
+    Hit { rule: R, is_fallback: bool },
 }
 
+#[derive(Clone, Copy)]
 pub struct CacheHandle {
-    slot: u16,
+    slot: usize,
     address: u64,
     modules_generation: u16,
 }
 
-const _: () = assert!(
-    CACHE_ENTRY_COUNT as u64 <= u16::MAX as u64,
-    "u16 should be sufficient to store the cache slot index"
-);
-
 #[derive(Clone, Copy, Debug)]
 struct CacheEntry<R: UnwindRule> {
     address: u64,
@@ -123,6 +214,15 @@ impl CacheStats {
     pub fn misses(&self) -> u64 {
         self.miss_empty_slot_count + self.miss_wrong_modules_count + self.miss_wrong_address_count
     }
+
+    /// Add the counts of `other` to these counts, e.g. to combine the statistics of
+    /// caches which were used on different threads.
+    pub fn merge(&mut self, other: &CacheStats) {
+        self.hit_count += other.hit_count;
+        self.miss_empty_slot_count += other.miss_empty_slot_count;
+        self.miss_wrong_modules_count += other.miss_wrong_modules_count;
+        self.miss_wrong_address_count += other.miss_wrong_address_count;
+    }
 }
 
 #[cfg(test)]
@@ -143,4 +243,21 @@ mod tests {
             24 // <-- larger than we'd like
         );
     }
+
+    #[test]
+    fn test_merge_stats() {
+        let mut stats = CacheStats {
+            hit_count: 3,
+            miss_empty_slot_count: 1,
+            ..Default::default()
+        };
+        stats.merge(&CacheStats {
+            hit_count: 2,
+            miss_wrong_address_count: 4,
+            ..Default::default()
+        });
+        assert_eq!(stats.hits(), 5);
+        assert_eq!(stats.misses(), 5);
+        assert_eq!(stats.total(), 10);
+    }
 }
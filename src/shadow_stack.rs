@@ -0,0 +1,237 @@
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::{UnwindIterator, Unwinder};
+use crate::FrameAddress;
+
+/// An [`UnwindIterator`] which checks the unwound return addresses against a captured
+/// hardware shadow stack, such as the shadow stack of Intel CET.
+///
+/// A shadow stack only contains return addresses, but it contains them exactly. As long
+/// as the unwound return addresses match the shadow stack, the frames are returned
+/// unchanged. As soon as unwinding produces an address which doesn't match, fails, or
+/// stops early, the remaining frames are taken from the shadow stack instead. Register
+/// values can't be recovered from the shadow stack, so regular unwinding doesn't resume
+/// after that.
+///
+/// Create this with [`UnwindIterator::with_shadow_stack`] or
+/// [`UnwindIterator::with_shadow_call_stack`].
+pub struct ShadowStackUnwindIterator<'u, 'c, 's, 'r, 'ss, U: Unwinder, F: MemoryReader> {
+    inner: UnwindIterator<'u, 'c, 's, 'r, U, F>,
+    shadow_stack: &'ss [u64],
+    /// The index of the next expected return address in `shadow_stack`.
+    shadow_stack_index: usize,
+    state: ShadowStackState,
+    /// Whether the first return address may be missing from the shadow stack, because
+    /// functions push their return address in their prologue rather than with the call
+    /// instruction, and leaf functions don't push it at all.
+    first_return_address_may_be_missing: bool,
+    /// Whether the first return address was yielded even though it's not on the shadow
+    /// stack.
+    first_return_address_was_missing: bool,
+    mode: ShadowCallStackMode,
+}
+
+/// How an iterator created with [`UnwindIterator::with_shadow_call_stack`] uses the
+/// shadow call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ShadowCallStackMode {
+    /// Unwind every frame, and check the return addresses against the shadow call
+    /// stack, like [`UnwindIterator::with_shadow_stack`]. This is the default.
+    #[default]
+    Validate,
+    /// Only unwind the first frame, and take all other return addresses from the
+    /// shadow call stack. This is faster than unwinding, and doesn't depend on the
+    /// unwind information of the modules, but the frames after the first one have no
+    /// register values.
+    Trust,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ShadowStackState {
+    /// The unwound frames have matched the shadow stack so far.
+    Validating,
+    /// The remaining frames come from the shadow stack.
+    Repairing {
+        /// The index of the first frame which was taken from the shadow stack.
+        first_repaired_frame: usize,
+    },
+    /// The remaining frames come from the shadow stack because of
+    /// [`ShadowCallStackMode::Trust`].
+    Trusting,
+}
+
+impl<'u, 'c, 's, 'r, 'ss, U: Unwinder, F: MemoryReader>
+    ShadowStackUnwindIterator<'u, 'c, 's, 'r, 'ss, U, F>
+{
+    pub(crate) fn new(
+        inner: UnwindIterator<'u, 'c, 's, 'r, U, F>,
+        shadow_stack: &'ss [u64],
+    ) -> Self {
+        Self {
+            inner,
+            shadow_stack,
+            shadow_stack_index: 0,
+            state: ShadowStackState::Validating,
+            first_return_address_may_be_missing: false,
+            first_return_address_was_missing: false,
+            mode: ShadowCallStackMode::Validate,
+        }
+    }
+
+    pub(crate) fn new_shadow_call_stack(
+        inner: UnwindIterator<'u, 'c, 's, 'r, U, F>,
+        shadow_call_stack: &'ss [u64],
+        mode: ShadowCallStackMode,
+    ) -> Self {
+        Self {
+            first_return_address_may_be_missing: true,
+            mode,
+            ..Self::new(inner, shadow_call_stack)
+        }
+    }
+
+    /// Yield the next frame in the stack, like [`UnwindIterator::next`].
+    ///
+    /// The first frame is the instruction pointer. Return addresses are only yielded
+    /// if they match the shadow stack, or if they come from it.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<FrameAddress>, Error> {
+        if self.state != ShadowStackState::Validating {
+            return self.next_from_shadow_stack();
+        }
+        let expected = self.shadow_stack.get(self.shadow_stack_index).copied();
+        match self.inner.next() {
+            Ok(Some(FrameAddress::InstructionPointer(pc))) => {
+                Ok(Some(FrameAddress::InstructionPointer(pc)))
+            }
+            Ok(Some(FrameAddress::ReturnAddress(return_address)))
+                if Some(return_address.get()) == expected =>
+            {
+                self.shadow_stack_index += 1;
+                self.after_return_address();
+                Ok(Some(FrameAddress::ReturnAddress(return_address)))
+            }
+            Ok(Some(FrameAddress::ReturnAddress(return_address)))
+                if self.first_return_address_may_be_missing =>
+            {
+                // The first function hasn't pushed its return address yet, or it's a
+                // leaf function which doesn't push it at all.
+                self.first_return_address_was_missing = true;
+                self.after_return_address();
+                Ok(Some(FrameAddress::ReturnAddress(return_address)))
+            }
+            Ok(None) if expected.is_none() => Ok(None),
+            result => {
+                if expected.is_none() {
+                    // The shadow stack is exhausted; there is nothing to repair with.
+                    return result;
+                }
+                self.state = ShadowStackState::Repairing {
+                    first_repaired_frame: self.shadow_stack_index
+                        + 1
+                        + usize::from(self.first_return_address_was_missing),
+                };
+                self.next_from_shadow_stack()
+            }
+        }
+    }
+
+    fn after_return_address(&mut self) {
+        self.first_return_address_may_be_missing = false;
+        if self.mode == ShadowCallStackMode::Trust {
+            self.state = ShadowStackState::Trusting;
+        }
+    }
+
+    fn next_from_shadow_stack(&mut self) -> Result<Option<FrameAddress>, Error> {
+        let Some(&return_address) = self.shadow_stack.get(self.shadow_stack_index) else {
+            return Ok(None);
+        };
+        self.shadow_stack_index += 1;
+        FrameAddress::from_return_address(return_address)
+            .ok_or(Error::ReturnAddressIsNull)
+            .map(Some)
+    }
+
+    /// If the unwound frames didn't match the shadow stack, this returns the index of
+    /// the first frame which was taken from the shadow stack instead. The first frame,
+    /// i.e. the instruction pointer, has index 0.
+    pub fn first_repaired_frame(&self) -> Option<usize> {
+        match self.state {
+            ShadowStackState::Validating | ShadowStackState::Trusting => None,
+            ShadowStackState::Repairing {
+                first_repaired_frame,
+            } => Some(first_repaired_frame),
+        }
+    }
+}
+
+impl<'u, 'c, 's, 'r, 'ss, U: Unwinder, F: MemoryReader> fallible_iterator::FallibleIterator
+    for ShadowStackUnwindIterator<'u, 'c, 's, 'r, 'ss, U, F>
+{
+    type Item = FrameAddress;
+    type Error = Error;
+
+    fn next(&mut self) -> Result<Option<FrameAddress>, Error> {
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use alloc::vec::Vec;
+
+    fn collect_frames<I>(mut iter: I) -> Vec<FrameAddress>
+    where
+        I: fallible_iterator::FallibleIterator<Item = FrameAddress, Error = Error>,
+    {
+        let mut frames = Vec::new();
+        while let Ok(Some(frame)) = iter.next() {
+            frames.push(frame);
+        }
+        frames
+    }
+
+    #[test]
+    fn test_shadow_stack() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        // A frame pointer chain with two frames, the second of which has a zero frame
+        // pointer, so frame pointer unwinding stops after it.
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x0, 0x2345];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+        let return_address = |address| FrameAddress::from_return_address(address).unwrap();
+
+        let shadow_stack = [0x1234, 0x2345, 0x3456];
+        let mut iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_shadow_stack(&shadow_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(iter.next(), Ok(Some(return_address(0x1234))));
+        assert_eq!(iter.next(), Ok(Some(return_address(0x2345))));
+        assert_eq!(iter.first_repaired_frame(), None);
+        assert_eq!(iter.next(), Ok(Some(return_address(0x3456))));
+        assert_eq!(iter.first_repaired_frame(), Some(3));
+        assert_eq!(iter.next(), Ok(None));
+
+        // The second return address doesn't match.
+        let shadow_stack = [0x1234, 0x5678];
+        let iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_shadow_stack(&shadow_stack);
+        assert_eq!(
+            collect_frames(iter),
+            [
+                FrameAddress::from_instruction_pointer(0x1000),
+                return_address(0x1234),
+                return_address(0x5678),
+            ]
+        );
+    }
+}
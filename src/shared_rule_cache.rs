@@ -0,0 +1,231 @@
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+
+use crate::rule_cache::CacheStats;
+use crate::unwind_rule::UnwindRule;
+
+/// Marks a slot as filled. The last byte of a serialized rule is always zero.
+const FILLED_MARKER: u64 = 1 << 56;
+/// Marks the rule of a slot as the fallback rule.
+const FALLBACK_MARKER: u64 = 1 << 57;
+/// The bits of a serialized rule.
+const RULE_MASK: u64 = FILLED_MARKER - 1;
+
+/// A slot of a rule cache which lives in memory provided by the caller, e.g. in a shared
+/// memory region which is mapped into several processes. See
+/// [`CacheX86_64::new_shared`](crate::x86_64::CacheX86_64::new_shared).
+///
+/// A slot is four 64-bit words and all-zero bytes are an empty slot, so a freshly mapped
+/// region of zeroed memory can be used as an array of empty slots.
+///
+/// Writers don't take a lock which could be recovered: if a writer stops in the middle of
+/// an update, e.g. because its process is killed, the slot stays marked as being written
+/// and is unusable from then on. Lookups of its addresses miss and inserts into it are
+/// dropped. Such slots only become usable again if the region is zeroed while no cache
+/// uses it.
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct SharedCacheSlot {
+    /// Odd while a writer is updating the other fields, and incremented again once it's
+    /// done, so that a reader can detect a slot which was written while it read it.
+    sequence: AtomicU64,
+    address: AtomicU64,
+    layout_id: AtomicU64,
+    /// The serialized rule, with [`FILLED_MARKER`] and maybe [`FALLBACK_MARKER`].
+    rule: AtomicU64,
+}
+
+impl SharedCacheSlot {
+    /// Create an empty slot.
+    pub const fn new() -> Self {
+        Self {
+            sequence: AtomicU64::new(0),
+            address: AtomicU64::new(0),
+            layout_id: AtomicU64::new(0),
+            rule: AtomicU64::new(0),
+        }
+    }
+}
+
+/// The rule cache storage used for [`SharedCacheSlot`]s. Every slot is a sequence lock:
+/// a writer makes the slot's sequence number odd while it stores the address, the layout
+/// ID and the rule, and a reader which sees an odd or changed sequence number treats the
+/// slot as a miss. Writers don't wait for each other; an insert into a slot which is
+/// being written is dropped.
+///
+/// The modules generation of an unwinder only has a meaning within one process, so it
+/// isn't part of the key. Instead, all users of the slots agree on a layout ID, which
+/// has to change whenever the modules change.
+pub struct SharedRuleCache<'s> {
+    slots: &'s [SharedCacheSlot],
+    layout_id: u64,
+}
+
+impl<'s> SharedRuleCache<'s> {
+    /// Without slots, every lookup is a miss.
+    pub fn new(slots: &'s [SharedCacheSlot], layout_id: u64) -> Self {
+        Self { slots, layout_id }
+    }
+
+    pub fn set_layout_id(&mut self, layout_id: u64) {
+        self.layout_id = layout_id;
+    }
+
+    pub fn slot_for_address(&self, address: u64) -> usize {
+        address.checked_rem(self.slots.len() as u64).unwrap_or(0) as usize
+    }
+
+    pub fn lookup<R: UnwindRule>(
+        &self,
+        slot: usize,
+        address: u64,
+        stats: &mut CacheStats,
+    ) -> Option<(R, bool)> {
+        let Some(slot) = self.slots.get(slot) else {
+            stats.miss_empty_slot_count += 1;
+            return None;
+        };
+        let sequence = slot.sequence.load(Ordering::Acquire);
+        let slot_address = slot.address.load(Ordering::Relaxed);
+        let slot_layout_id = slot.layout_id.load(Ordering::Relaxed);
+        let rule = slot.rule.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        if sequence % 2 != 0 || slot.sequence.load(Ordering::Relaxed) != sequence {
+            // A write is in progress.
+            stats.miss_wrong_address_count += 1;
+            return None;
+        }
+        if rule == 0 {
+            stats.miss_empty_slot_count += 1;
+            return None;
+        }
+        if slot_address != address
+            || slot_layout_id != self.layout_id
+            || rule & FILLED_MARKER == 0
+            || rule & !(RULE_MASK | FILLED_MARKER | FALLBACK_MARKER) != 0
+        {
+            stats.miss_wrong_address_count += 1;
+            return None;
+        }
+        let is_fallback = rule & FALLBACK_MARKER != 0;
+        match R::deserialize((rule & RULE_MASK).to_le_bytes()) {
+            Some(rule) => {
+                stats.hit_count += 1;
+                Some((rule, is_fallback))
+            }
+            None => {
+                stats.miss_wrong_address_count += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert<R: UnwindRule>(
+        &self,
+        slot: usize,
+        address: u64,
+        unwind_rule: R,
+        is_fallback: bool,
+    ) {
+        let Some(slot) = self.slots.get(slot) else {
+            return;
+        };
+        let mut rule = u64::from_le_bytes(unwind_rule.serialize()) | FILLED_MARKER;
+        if is_fallback {
+            rule |= FALLBACK_MARKER;
+        }
+        let sequence = slot.sequence.load(Ordering::Relaxed);
+        if sequence % 2 != 0
+            || slot
+                .sequence
+                .compare_exchange(
+                    sequence,
+                    sequence.wrapping_add(1),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            // Another writer is updating the slot.
+            return;
+        }
+        // Readers must see the odd sequence number before any of the new values.
+        fence(Ordering::Release);
+        slot.address.store(address, Ordering::Relaxed);
+        slot.layout_id.store(self.layout_id, Ordering::Relaxed);
+        slot.rule.store(rule, Ordering::Relaxed);
+        slot.sequence
+            .store(sequence.wrapping_add(2), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rule_cache::{CacheResult, RuleCache};
+    use crate::x86_64::UnwindRuleX86_64;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_shared_rule_cache() {
+        let slots: Vec<_> = (0..7).map(|_| SharedCacheSlot::new()).collect();
+        let rule = UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 };
+        let mut writer = RuleCache::<UnwindRuleX86_64>::new_shared(&slots, 1);
+        let mut reader = RuleCache::<UnwindRuleX86_64>::new_shared(&slots, 1);
+        match writer.lookup(0x1234, 0) {
+            CacheResult::Miss(handle) => writer.insert(handle, rule, true),
+            CacheResult::Hit { .. } => panic!("the slots should be empty"),
+        }
+        // The module generation is process-local and isn't part of the key.
+        assert!(
+            matches!(reader.lookup(0x1234, 5), CacheResult::Hit { rule: r, is_fallback: true } if r == rule)
+        );
+        assert!(matches!(reader.lookup(0x1234 + 7, 5), CacheResult::Miss(_)));
+        reader.set_layout_id(2);
+        assert!(matches!(reader.lookup(0x1234, 5), CacheResult::Miss(_)));
+        let stats = reader.stats();
+        assert_eq!((stats.hit_count, stats.miss_wrong_address_count), (1, 2));
+    }
+
+    #[test]
+    fn test_shared_rule_cache_concurrent_write() {
+        let slots = [SharedCacheSlot::new()];
+        let cache = SharedRuleCache::new(&slots, 1);
+        let mut stats = CacheStats::new();
+        let rule = UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 };
+        cache.insert(0, 0x1234, rule, false);
+        assert_eq!(cache.lookup(0, 0x1234, &mut stats), Some((rule, false)));
+
+        // A writer in another process is in the middle of writing the slot.
+        slots[0].sequence.fetch_add(1, Ordering::Relaxed);
+        slots[0].address.store(0x5678, Ordering::Relaxed);
+        assert_eq!(
+            cache.lookup::<UnwindRuleX86_64>(0, 0x5678, &mut stats),
+            None
+        );
+        // Other writers leave the slot alone.
+        cache.insert(0, 0x1234, rule, false);
+        assert_eq!(slots[0].address.load(Ordering::Relaxed), 0x5678);
+        slots[0].sequence.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(cache.lookup(0, 0x5678, &mut stats), Some((rule, false)));
+
+        // Every field of the slot is checked.
+        slots[0].rule.fetch_or(1 << 60, Ordering::Relaxed);
+        assert_eq!(
+            cache.lookup::<UnwindRuleX86_64>(0, 0x5678, &mut stats),
+            None
+        );
+        assert_eq!((stats.hit_count, stats.miss_wrong_address_count), (2, 2));
+    }
+
+    #[test]
+    fn test_shared_rule_cache_without_slots() {
+        let cache = SharedRuleCache::new(&[], 1);
+        let mut stats = CacheStats::new();
+        let slot = cache.slot_for_address(0x1234);
+        cache.insert(slot, 0x1234, UnwindRuleX86_64::JustReturn, false);
+        assert_eq!(
+            cache.lookup::<UnwindRuleX86_64>(slot, 0x1234, &mut stats),
+            None
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use crate::FrameAddress;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// A hash of the frames of a stack, which is computed while the stack is unwound. See
+/// [`UnwindIterator::fingerprint`](crate::UnwindIterator::fingerprint).
+///
+/// Samplers can use it to deduplicate identical stacks without storing the frames of
+/// every sample first. Different stacks can have the same hash, so the frames of a
+/// stack should still be compared when the hash matches, if collisions matter.
+///
+/// The fingerprint contains the modules generation of the unwinder, see
+/// [`Unwinder::modules_generation`](crate::Unwinder::modules_generation). A stored
+/// fingerprint whose generation differs from the current one is stale: the same frame
+/// addresses may now belong to different modules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackFingerprint {
+    hash: u64,
+    frame_count: usize,
+    modules_generation: u16,
+}
+
+impl StackFingerprint {
+    /// The fingerprint of a stack without frames.
+    pub fn new(modules_generation: u16) -> Self {
+        let mut fingerprint = Self {
+            hash: FNV_OFFSET_BASIS,
+            frame_count: 0,
+            modules_generation,
+        };
+        fingerprint.mix(u64::from(modules_generation));
+        fingerprint
+    }
+
+    /// Add the next frame, going from the innermost frame to the root.
+    pub fn push(&mut self, frame: FrameAddress) {
+        // Distinguish instruction pointers from return addresses with the same value.
+        self.mix(u64::from(frame.is_return_address()));
+        self.mix(frame.address());
+        self.frame_count += 1;
+    }
+
+    fn mix(&mut self, value: u64) {
+        for byte in value.to_le_bytes() {
+            self.hash ^= u64::from(byte);
+            self.hash = self.hash.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    /// The hash of the modules generation and of all frames so far.
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /// The number of frames which were added.
+    pub fn frame_count(&self) -> usize {
+        self.frame_count
+    }
+
+    /// The modules generation of the unwinder which unwound the stack.
+    pub fn modules_generation(&self) -> u16 {
+        self.modules_generation
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stack_fingerprint() {
+        let fingerprint = |generation, frames: &[FrameAddress]| {
+            let mut fingerprint = StackFingerprint::new(generation);
+            for frame in frames {
+                fingerprint.push(*frame);
+            }
+            fingerprint
+        };
+        let ip = FrameAddress::from_instruction_pointer(0x1000);
+        let ra = FrameAddress::from_return_address(0x1000).unwrap();
+
+        assert_eq!(fingerprint(1, &[ip, ra]), fingerprint(1, &[ip, ra]));
+        assert_ne!(
+            fingerprint(1, &[ip, ra]).hash(),
+            fingerprint(2, &[ip, ra]).hash()
+        );
+        assert_ne!(
+            fingerprint(1, &[ip, ra]).hash(),
+            fingerprint(1, &[ip, ip]).hash()
+        );
+        assert_ne!(
+            fingerprint(1, &[ip]).hash(),
+            fingerprint(1, &[ip, ra]).hash()
+        );
+        assert_eq!(fingerprint(1, &[ip, ra]).frame_count(), 2);
+    }
+}
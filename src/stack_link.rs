@@ -0,0 +1,194 @@
+use core::ops::Range;
+
+use arrayvec::ArrayVec;
+
+use crate::add_signed::checked_add_signed;
+use crate::error::Error;
+use crate::memory_reader::MemoryReadError;
+use crate::unwind_step::{ReadRequest, Resumable, Step};
+
+/// A stack-switch trampoline, i.e. the function at the bottom of a stack which was
+/// switched to from another stack, for example by a coroutine runtime or a fiber library.
+///
+/// When the unwinder reaches a frame whose address is inside `avma_range`, it doesn't use
+/// the unwind information of the module. Instead, it uses `rule` to find the registers
+/// of the frame on the parent stack which switched to this stack, and continues
+/// unwinding there.
+///
+/// Add stack links with `add_stack_link` on the unwinder, e.g.
+/// [`UnwinderX86_64::add_stack_link`](crate::x86_64::UnwinderX86_64::add_stack_link).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackLink {
+    /// The address range of the trampoline code.
+    pub avma_range: Range<u64>,
+    /// Where the trampoline stores the registers of the parent stack.
+    pub rule: StackLinkRule,
+}
+
+/// Describes where a stack-switch trampoline stores the registers of the parent stack.
+/// See [`StackLink`].
+///
+/// The values are read from the current stack, at the given offsets from the value of the
+/// `base` register in the trampoline frame. If the stored return address is zero, the
+/// stack has no parent and unwinding ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StackLinkRule {
+    /// The register which the offsets are relative to.
+    pub base: StackLinkBase,
+    /// The offset of the stored stack pointer of the parent stack.
+    pub sp_offset: i64,
+    /// The offset of the stored frame pointer of the parent stack.
+    pub fp_offset: i64,
+    /// The offset of the stored return address, i.e. the address at which the parent
+    /// stack resumes when control comes back to it.
+    pub return_address_offset: i64,
+}
+
+/// The register which the offsets in a [`StackLinkRule`] are relative to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackLinkBase {
+    /// The stack pointer.
+    StackPointer,
+    /// The frame pointer.
+    FramePointer,
+}
+
+/// Access to the registers which are needed to follow a [`StackLink`].
+pub trait StackLinkRegs {
+    fn sp(&self) -> u64;
+    fn fp(&self) -> u64;
+
+    /// Set the registers to the values of the parent stack. Returns the return address,
+    /// after any adjustments such as stripping pointer authentication bits.
+    fn switch_to_parent_stack(&mut self, sp: u64, fp: u64, return_address: u64) -> u64;
+}
+
+impl StackLinkRule {
+    /// Start following the link from the trampoline frame with the registers `regs`.
+    pub(crate) fn start_exec<R: StackLinkRegs>(&self, regs: &R) -> StackLinkExecution {
+        let base = match self.base {
+            StackLinkBase::StackPointer => regs.sp(),
+            StackLinkBase::FramePointer => regs.fp(),
+        };
+        StackLinkExecution {
+            offsets: [self.return_address_offset, self.sp_offset, self.fp_offset],
+            base,
+            values: ArrayVec::new(),
+            address: 0,
+            provided: None,
+            result: None,
+        }
+    }
+}
+
+/// The execution of a [`StackLinkRule`]: it reads the return address, and then, unless
+/// it's zero, the stack pointer and the frame pointer of the parent stack.
+pub(crate) struct StackLinkExecution {
+    offsets: [i64; 3],
+    base: u64,
+    values: ArrayVec<u64, 3>,
+    /// The address of the last requested read.
+    address: u64,
+    provided: Option<Result<u64, MemoryReadError>>,
+    result: Option<Result<Option<u64>, Error>>,
+}
+
+impl<R: StackLinkRegs> Resumable<R> for StackLinkExecution {
+    type Output = Result<Option<u64>, Error>;
+
+    fn step(&mut self, regs: &mut R) -> Step<Self::Output> {
+        if let Some(result) = self.result {
+            return Step::Done(result);
+        }
+        if let Some(value) = self.provided.take() {
+            match value {
+                Ok(value) => self.values.push(value),
+                Err(err) => {
+                    let result = Err(Error::CouldNotReadStack(self.address, err));
+                    self.result = Some(result);
+                    return Step::Done(result);
+                }
+            }
+        }
+        let result = match *self.values.as_slice() {
+            [0] => Ok(None),
+            // The parent stack can be anywhere in memory, so there is no check that the
+            // stack pointer moves in the usual direction.
+            [return_address, sp, fp] => {
+                Ok(Some(regs.switch_to_parent_stack(sp, fp, return_address)))
+            }
+            _ => match checked_add_signed(self.base, self.offsets[self.values.len()]) {
+                Some(address) => {
+                    self.address = address;
+                    return Step::Read(ReadRequest::u64(address));
+                }
+                None => Err(Error::IntegerOverflow),
+            },
+        };
+        self.result = Some(result);
+        Step::Done(result)
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        self.provided = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::{FrameAddress, Unwinder};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_stack_link() {
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.add_stack_link(StackLink {
+            avma_range: 0x5000..0x5100,
+            rule: StackLinkRule {
+                base: StackLinkBase::StackPointer,
+                return_address_offset: 0,
+                sp_offset: 8,
+                fp_offset: 16,
+            },
+        });
+        let mut cache = CacheX86_64::<_>::new();
+        let mut stack = vec![0; 0x130 / 8];
+        // The coroutine stack: one frame, called from the trampoline, which stores the
+        // registers of the parent stack above its return address.
+        stack[0x10 / 8] = 0x40;
+        stack[0x18 / 8] = 0x5010;
+        stack[0x20 / 8] = 0x7777;
+        stack[0x28 / 8] = 0x100;
+        stack[0x30 / 8] = 0x110;
+        // The parent stack: one more frame, whose caller is the root.
+        stack[0x110 / 8] = 0x0;
+        stack[0x118 / 8] = 0x8888;
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x10, 0x10);
+        let return_address = |address| FrameAddress::from_return_address(address).unwrap();
+
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(iter.next(), Ok(Some(return_address(0x5010))));
+        assert_eq!(iter.next(), Ok(Some(return_address(0x7777))));
+        assert_eq!(iter.next(), Ok(Some(return_address(0x8888))));
+        assert_eq!(iter.next(), Ok(None));
+
+        // Without a parent stack, unwinding ends at the trampoline.
+        stack[0x20 / 8] = 0x0;
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(iter.next(), Ok(Some(return_address(0x5010))));
+        assert_eq!(iter.next(), Ok(None));
+    }
+}
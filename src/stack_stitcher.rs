@@ -0,0 +1,211 @@
+use alloc::vec::Vec;
+
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::{StackEndState, Unwinder};
+use crate::FrameAddress;
+
+/// The `PERF_CONTEXT_*` markers which perf inserts into callchains. All values at or
+/// above `PERF_CONTEXT_MAX` are markers.
+const PERF_CONTEXT_KERNEL: u64 = -128i64 as u64;
+const PERF_CONTEXT_USER: u64 = -512i64 as u64;
+const PERF_CONTEXT_MAX: u64 = -4095i64 as u64;
+
+/// Where a frame of a [`StitchedStack`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameOrigin {
+    /// The frame was captured by the kernel, e.g. by perf or eBPF.
+    Kernel,
+    /// The frame was unwound by framehop from the user registers and stack.
+    User,
+}
+
+/// A frame of a [`StitchedStack`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StitchedFrame {
+    /// The address of the frame.
+    pub address: FrameAddress,
+    /// Where the frame comes from.
+    pub origin: FrameOrigin,
+}
+
+/// How well the kernel frames and the user frames of a [`StitchedStack`] fit together.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StitchBoundary {
+    /// The kernel callchain contained the user pc, and it matched the pc of the user
+    /// registers.
+    Verified,
+    /// The kernel callchain didn't contain the user pc, so the boundary couldn't be
+    /// checked.
+    Unverified,
+    /// The user pc in the kernel callchain differs from the pc of the user registers,
+    /// e.g. because the two were captured at different times. The user frames are still
+    /// unwound from the registers, but the stack is probably inconsistent.
+    Mismatch {
+        /// The user pc which the kernel recorded.
+        kernel_user_pc: u64,
+    },
+}
+
+/// A stack which was stitched together from kernel frames and unwound user frames. See
+/// [`StackStitcher`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StitchedStack {
+    /// The kernel frames, innermost first, followed by the user frames.
+    pub frames: Vec<StitchedFrame>,
+    /// Whether the kernel's view of the user pc matched the user registers.
+    pub boundary: StitchBoundary,
+    /// Why unwinding the user frames stopped.
+    pub user_end_state: StackEndState,
+}
+
+/// Combines a kernel stack and a user stack which were captured separately, as is
+/// common with eBPF and perf: the kernel provides the kernel frames, and the user
+/// frames are unwound with framehop from the user registers (`pt_regs`) and a copy of
+/// the user stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackStitcher {
+    max_user_frames: usize,
+}
+
+impl StackStitcher {
+    /// Create a stitcher which unwinds at most `max_user_frames` user frames.
+    pub fn new(max_user_frames: usize) -> Self {
+        Self { max_user_frames }
+    }
+
+    /// Stitch the kernel frames in `kernel_callchain` and the user frames which are
+    /// unwound from `user_pc` and `user_regs`.
+    ///
+    /// `kernel_callchain` is innermost first, e.g. from `bpf_get_stack` or the callchain
+    /// of a perf sample. It may contain perf's `PERF_CONTEXT_*` markers. If it contains
+    /// user entries after `PERF_CONTEXT_USER`, the first one is compared with `user_pc`
+    /// to validate the boundary, and the user entries are otherwise ignored.
+    pub fn stitch<U, F>(
+        &self,
+        unwinder: &U,
+        kernel_callchain: &[u64],
+        user_pc: u64,
+        user_regs: U::UnwindRegs,
+        cache: &mut U::Cache<'_>,
+        read_stack: &mut F,
+    ) -> StitchedStack
+    where
+        U: Unwinder,
+        F: MemoryReader,
+    {
+        let mut frames = Vec::new();
+        let mut kernel_user_pc = None;
+        let mut is_user_context = false;
+        for &address in kernel_callchain {
+            if address >= PERF_CONTEXT_MAX {
+                match address {
+                    PERF_CONTEXT_KERNEL => is_user_context = false,
+                    PERF_CONTEXT_USER => is_user_context = true,
+                    _ => {}
+                }
+                continue;
+            }
+            if is_user_context {
+                kernel_user_pc.get_or_insert(address);
+                continue;
+            }
+            let address = if frames.is_empty() {
+                FrameAddress::from_instruction_pointer(address)
+            } else {
+                match FrameAddress::from_return_address(address) {
+                    Some(address) => address,
+                    None => continue,
+                }
+            };
+            frames.push(StitchedFrame {
+                address,
+                origin: FrameOrigin::Kernel,
+            });
+        }
+        let boundary = match kernel_user_pc {
+            None => StitchBoundary::Unverified,
+            Some(pc) if pc == user_pc => StitchBoundary::Verified,
+            Some(pc) => StitchBoundary::Mismatch { kernel_user_pc: pc },
+        };
+
+        let user_stack = unwinder
+            .iter_frames(user_pc, user_regs, cache, read_stack)
+            .collect_stack(self.max_user_frames);
+        frames.extend(user_stack.frames.into_iter().map(|address| StitchedFrame {
+            address,
+            origin: FrameOrigin::User,
+        }));
+        StitchedStack {
+            frames,
+            boundary,
+            user_end_state: user_stack.end_state,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+    #[test]
+    fn test_stitch() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x0, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let stitcher = StackStitcher::new(10);
+        let callchain = [
+            PERF_CONTEXT_KERNEL,
+            0xffff_8000_0000_1000,
+            0xffff_8000_0000_2000,
+            PERF_CONTEXT_USER,
+            0x1000,
+            0x1234,
+        ];
+
+        let stitched = stitcher.stitch(
+            &unwinder,
+            &callchain,
+            0x1000,
+            UnwindRegsX86_64::new(0x1000, 0x10, 0x10),
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(stitched.boundary, StitchBoundary::Verified);
+        assert_eq!(stitched.user_end_state, StackEndState::Root);
+        let origins: Vec<_> = stitched.frames.iter().map(|frame| frame.origin).collect();
+        assert_eq!(
+            origins,
+            [
+                FrameOrigin::Kernel,
+                FrameOrigin::Kernel,
+                FrameOrigin::User,
+                FrameOrigin::User
+            ]
+        );
+        assert_eq!(
+            stitched.frames[1].address,
+            FrameAddress::from_return_address(0xffff_8000_0000_2000).unwrap()
+        );
+        assert_eq!(
+            stitched.frames[3].address,
+            FrameAddress::from_return_address(0x1234).unwrap()
+        );
+
+        let stitched = stitcher.stitch(
+            &unwinder,
+            &callchain,
+            0x1001,
+            UnwindRegsX86_64::new(0x1001, 0x10, 0x10),
+            &mut cache,
+            &mut read_stack,
+        );
+        assert_eq!(
+            stitched.boundary,
+            StitchBoundary::Mismatch {
+                kernel_user_pc: 0x1000
+            }
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use alloc::vec::Vec;
+use std::sync::Mutex;
+
+/// A pool of caches which can be used through a shared reference, e.g. to offer an
+/// unwinding API which takes `&self` from a type which owns both the unwinder and the
+/// caches. See [`Unwinder::unwind_frame_with_pooled_cache`](crate::Unwinder::unwind_frame_with_pooled_cache).
+///
+/// Every caller takes a cache out of the pool for the duration of its unwind and puts it
+/// back afterwards, so concurrent callers never wait for each other while they unwind.
+/// If the pool is empty, a new cache is created, so the pool holds as many caches as
+/// there were concurrent callers at most. The mutex is only locked to take and return
+/// caches; don't use this from a signal handler. For a fixed number of worker threads,
+/// [`CachePool`](crate::CachePool) bounds the number of caches instead.
+pub struct SyncCache<C> {
+    pool: Mutex<Vec<C>>,
+}
+
+impl<C> SyncCache<C> {
+    /// Create an empty pool.
+    pub fn new() -> Self {
+        Self {
+            pool: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Call `f` with a cache from the pool, and put the cache back afterwards.
+    pub fn with_cache<T>(&self, f: impl FnOnce(&mut C) -> T) -> T
+    where
+        C: Default,
+    {
+        let mut cache = self.lock().pop().unwrap_or_default();
+        let result = f(&mut cache);
+        self.lock().push(cache);
+        result
+    }
+
+    /// The number of caches which are in the pool and not currently in use.
+    pub fn idle_cache_count(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Drop all caches which are not currently in use, e.g. to free their memory.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<C>> {
+        // The pool is consistent even if a caller panicked while it was locked.
+        self.pool.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl<C> Default for SyncCache<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use crate::{FrameAddress, Unwinder};
+
+    #[test]
+    fn test_pooled_cache() {
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let caches = SyncCache::<CacheX86_64>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    let mut read_stack =
+                        |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+                    let mut regs = UnwindRegsX86_64::new(0x1800, 0x0, 0x10);
+                    let res = unwinder.unwind_frame_with_pooled_cache(
+                        FrameAddress::from_instruction_pointer(0x1800),
+                        &mut regs,
+                        &caches,
+                        &mut read_stack,
+                    );
+                    assert_eq!(res, Ok(Some(0x1234)));
+                });
+            }
+        });
+        let idle_cache_count = caches.idle_cache_count();
+        assert!((1..=4).contains(&idle_cache_count));
+        caches.clear();
+        assert_eq!(caches.idle_cache_count(), 0);
+    }
+}
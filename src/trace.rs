@@ -0,0 +1,211 @@
+use core::marker::PhantomData;
+
+#[cfg(feature = "trace")]
+use alloc::{string::String, sync::Arc, vec::Vec};
+#[cfg(feature = "trace")]
+use core::ops::Range;
+
+#[cfg(feature = "trace")]
+use crate::error::{Error, UnwinderError};
+#[cfg(feature = "trace")]
+use crate::stack_link::StackLinkRule;
+#[cfg(feature = "trace")]
+use crate::unwind_rule::UnwindHint;
+#[cfg(feature = "trace")]
+use crate::unwinder::UnwindDataKind;
+#[cfg(feature = "trace")]
+use crate::FrameAddress;
+
+/// A record of every decision the unwinder made while unwinding a frame.
+///
+/// Pass this to [`Unwinder::unwind_frame_traced`](crate::Unwinder::unwind_frame_traced)
+/// to find out why a particular stack came out the way it did. Events from
+/// successive calls are appended, so a single trace can cover a whole stack. Call
+/// [`UnwindTrace::clear`] to reuse the buffer.
+///
+/// `R` is the unwind rule type and `Regs` is the unwind registers type of the
+/// CPU architecture, e.g. [`UnwindTraceX86_64`](crate::x86_64::UnwindTraceX86_64).
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone)]
+pub struct UnwindTrace<R, Regs> {
+    events: Vec<UnwindTraceEvent<R, Regs>>,
+}
+
+#[cfg(feature = "trace")]
+impl<R, Regs> Default for UnwindTrace<R, Regs> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "trace")]
+impl<R, Regs> UnwindTrace<R, Regs> {
+    /// Create an empty trace.
+    pub fn new() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    /// The recorded events, in the order in which they happened.
+    pub fn events(&self) -> &[UnwindTraceEvent<R, Regs>] {
+        &self.events
+    }
+
+    /// Remove all recorded events.
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+/// A single step of an unwind, as recorded in an [`UnwindTrace`].
+#[cfg(feature = "trace")]
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum UnwindTraceEvent<R, Regs> {
+    /// Unwinding of a frame started.
+    Begin {
+        /// The address of the frame.
+        address: FrameAddress,
+        /// The register values before unwinding.
+        regs: Regs,
+    },
+    /// The unwind rule was found in the cache.
+    CacheHit {
+        /// The cached rule.
+        rule: R,
+    },
+    /// The unwind rule was not found in the cache and needs to be computed.
+    CacheMiss,
+    /// No module contains the lookup address.
+    NoModule {
+        /// The address which was looked up.
+        lookup_address: u64,
+    },
+    /// The address is in a stack-switch trampoline, so unwinding continues on the parent
+    /// stack. See [`StackLink`](crate::StackLink).
+    StackLink {
+        /// The rule which describes where the parent stack's registers are stored.
+        rule: StackLinkRule,
+    },
+    /// The address is in the range of a foreign unwinder, whose callback unwound the
+    /// frame. See e.g.
+    /// [`UnwinderX86_64::add_foreign_unwinder`](crate::x86_64::UnwinderX86_64::add_foreign_unwinder).
+    ForeignUnwinder {
+        /// The address range of the foreign unwinder.
+        avma_range: Range<u64>,
+    },
+    /// The address is in a JIT range, so it is unwound according to the hint of the
+    /// range. See [`JitRange`](crate::JitRange).
+    JitRange {
+        /// The hint of the range.
+        hint: UnwindHint,
+        /// The rule for the hint, which is executed without being cached.
+        rule: R,
+    },
+    /// The module containing the lookup address was found.
+    Module {
+        /// The name of the module.
+        module_name: Arc<str>,
+        /// The lookup address, relative to the module's base address.
+        relative_lookup_address: u32,
+        /// The kind of unwind information which the module has.
+        unwind_data_kind: UnwindDataKind,
+    },
+    /// The `__unwind_info` entry for the function was found.
+    CompactUnwindInfoFunction {
+        /// The start address of the function, relative to the module's base address.
+        start_address: u32,
+        /// The end address of the function, relative to the module's base address.
+        end_address: u32,
+        /// The raw compact unwind encoding of the function.
+        opcode: u32,
+    },
+    /// The `__unwind_info` entry for the function was malformed, so its FDE was
+    /// searched in `__eh_frame` instead.
+    InvalidCompactUnwindInfoEntry {
+        /// The offset of the FDE in `__eh_frame`, or `None` if no FDE covers the
+        /// lookup address.
+        fde_offset: Option<u32>,
+    },
+    /// The DWARF CFI row for the lookup address was evaluated.
+    DwarfRow {
+        /// The offset of the FDE in the unwind section.
+        fde_offset: u32,
+        /// The first address (SVMA) covered by the row.
+        start_address: u64,
+        /// The address (SVMA) after the last address covered by the row.
+        end_address: u64,
+        /// The `Debug` representation of the row.
+        row: String,
+    },
+    /// The module's unwind information could not be used.
+    UnwindInfoError {
+        /// The error.
+        error: UnwinderError,
+    },
+    /// The return address was computed directly because the rule could not be
+    /// represented in the cache.
+    Uncacheable {
+        /// The return address.
+        return_address: u64,
+    },
+    /// This rule was computed, stored in the cache, and executed.
+    ExecRule {
+        /// The rule.
+        rule: R,
+    },
+    /// Unwinding of the frame finished.
+    End {
+        /// The result of the unwind.
+        result: Result<Option<u64>, Error>,
+        /// The register values after unwinding.
+        regs: Regs,
+    },
+}
+
+/// Records trace events if a trace buffer was supplied. Without the `trace`
+/// feature, this is an empty type and recording compiles to nothing.
+pub(crate) struct Tracer<'a, R, Regs> {
+    #[cfg(feature = "trace")]
+    trace: Option<&'a mut UnwindTrace<R, Regs>>,
+    _phantom: PhantomData<(&'a mut (), R, Regs)>,
+}
+
+impl<'a, R, Regs> Tracer<'a, R, Regs> {
+    pub fn disabled() -> Self {
+        Self {
+            #[cfg(feature = "trace")]
+            trace: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    pub fn new(trace: &'a mut UnwindTrace<R, Regs>) -> Self {
+        Self {
+            trace: Some(trace),
+            _phantom: PhantomData,
+        }
+    }
+
+    #[cfg(feature = "trace")]
+    #[inline]
+    pub fn record(&mut self, event: impl FnOnce() -> UnwindTraceEvent<R, Regs>) {
+        if let Some(trace) = &mut self.trace {
+            trace.events.push(event());
+        }
+    }
+}
+
+/// Records an [`UnwindTraceEvent`] on a [`Tracer`], e.g.
+/// `trace_event!(tracer, ExecRule { rule })`. The event is only constructed if
+/// tracing is enabled.
+macro_rules! trace_event {
+    ($tracer:expr, $($event:tt)*) => {
+        #[cfg(feature = "trace")]
+        $tracer.record(|| $crate::trace::UnwindTraceEvent::$($event)*);
+        #[cfg(not(feature = "trace"))]
+        let _ = &$tracer;
+    };
+}
+
+pub(crate) use trace_event;
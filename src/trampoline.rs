@@ -0,0 +1,101 @@
+/// A well-known function whose unwind information is missing or doesn't describe how it
+/// was entered. Modules can be told where these functions are, usually based on the
+/// symbol table, with [`ModuleBuilder::trampoline`](crate::ModuleBuilder::trampoline)
+/// or [`ModuleBuilder::recognize_trampolines`](crate::ModuleBuilder::recognize_trampolines).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trampoline {
+    /// The entry point of the process or of a thread, e.g. `_start`,
+    /// `__libc_start_main` or `thread_start`. Unwinding stops in this function, even if
+    /// its unwind information doesn't mark it as the end of the stack.
+    StackRoot,
+    /// A message dispatch function like `objc_msgSend`, which jumps to its target
+    /// without setting up a frame on its fast path. A first frame in this function is
+    /// unwound like a stub function.
+    MessageDispatch,
+    /// The lazy binding resolver of the ELF dynamic loader, e.g.
+    /// `_dl_runtime_resolve_xsavec`. It is entered from the first PLT entry, which
+    /// pushed data on top of the return address. A first frame at the start of this
+    /// function is unwound with a rule for this entry state.
+    PltLazyResolver,
+    /// The lazy binding resolver of the mach-O dynamic loader, `dyld_stub_binder`. It is
+    /// entered from `__stub_helper`, which pushed data on top of the return address. A
+    /// first frame at the start of this function is unwound with a rule for this entry
+    /// state.
+    DyldStubBinder,
+}
+
+const KNOWN_TRAMPOLINES: &[(&str, Trampoline)] = &[
+    ("_start", Trampoline::StackRoot),
+    ("start", Trampoline::StackRoot),
+    ("__libc_start_main", Trampoline::StackRoot),
+    ("thread_start", Trampoline::StackRoot),
+    ("RtlUserThreadStart", Trampoline::StackRoot),
+    ("objc_msgSend", Trampoline::MessageDispatch),
+    ("objc_msgSend_stret", Trampoline::MessageDispatch),
+    ("objc_msgSend_fpret", Trampoline::MessageDispatch),
+    ("objc_msgSendSuper", Trampoline::MessageDispatch),
+    ("objc_msgSendSuper2", Trampoline::MessageDispatch),
+    ("objc_msgSendSuper2_stret", Trampoline::MessageDispatch),
+    ("_dl_runtime_resolve", Trampoline::PltLazyResolver),
+    ("_dl_runtime_resolve_fxsave", Trampoline::PltLazyResolver),
+    ("_dl_runtime_resolve_xsave", Trampoline::PltLazyResolver),
+    ("_dl_runtime_resolve_xsavec", Trampoline::PltLazyResolver),
+    ("dyld_stub_binder", Trampoline::DyldStubBinder),
+];
+
+/// The entry points of bionic, and the trampolines of the Android runtime (ART) which
+/// jump to their target without setting up a frame.
+#[cfg(feature = "android")]
+const ANDROID_TRAMPOLINES: &[(&str, Trampoline)] = &[
+    ("__libc_init", Trampoline::StackRoot),
+    ("__start_thread", Trampoline::StackRoot),
+    (
+        "art_quick_imt_conflict_trampoline",
+        Trampoline::MessageDispatch,
+    ),
+];
+
+impl Trampoline {
+    /// Recognize a well-known trampoline by its symbol name. Mach-O symbol names with
+    /// the extra leading underscore are recognized, too. With the `android` feature, the
+    /// entry points of bionic and some trampolines of ART are recognized as well.
+    pub fn from_symbol_name(name: &str) -> Option<Self> {
+        let lookup = |name: &str| {
+            let known_trampolines = KNOWN_TRAMPOLINES.iter();
+            #[cfg(feature = "android")]
+            let known_trampolines = known_trampolines.chain(ANDROID_TRAMPOLINES);
+            known_trampolines
+                .clone()
+                .find(|(known_name, _)| *known_name == name)
+                .map(|(_, trampoline)| *trampoline)
+        };
+        lookup(name).or_else(|| lookup(name.strip_prefix('_')?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_symbol_name() {
+        assert_eq!(
+            Trampoline::from_symbol_name("_objc_msgSend"),
+            Some(Trampoline::MessageDispatch)
+        );
+        assert_eq!(
+            Trampoline::from_symbol_name("_start"),
+            Some(Trampoline::StackRoot)
+        );
+        assert_eq!(
+            Trampoline::from_symbol_name("_dl_runtime_resolve_xsavec"),
+            Some(Trampoline::PltLazyResolver)
+        );
+        assert_eq!(Trampoline::from_symbol_name("main"), None);
+        #[cfg(feature = "android")]
+        assert_eq!(
+            Trampoline::from_symbol_name("art_quick_imt_conflict_trampoline"),
+            Some(Trampoline::MessageDispatch)
+        );
+    }
+}
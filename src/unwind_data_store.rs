@@ -0,0 +1,134 @@
+use alloc::string::String;
+use core::ops::{Deref, Range};
+use std::collections::HashMap;
+
+use crate::module_id::CodeId;
+use crate::unwinder::{Module, ModuleSectionInfo};
+
+/// Identifies the file of a module, so that modules from the same file can share their
+/// unwind data. See [`UnwindDataStore`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum UnwindDataKey {
+    /// The code ID of the file, e.g. the ELF build ID. This is the most reliable key.
+    CodeId(CodeId),
+    /// The path of the file together with its size and modification time, for files
+    /// without a code ID.
+    File {
+        path: String,
+        size: u64,
+        modification_time: u64,
+    },
+}
+
+/// Shares the unwind data of modules which are loaded from the same file, e.g. a library
+/// which is loaded at different addresses in one or many processes.
+///
+/// The unwind sections of a file are only read and indexed the first time the file is
+/// seen. Later modules for the same key reference the same data, which is freed when the
+/// last of these modules is dropped.
+pub struct UnwindDataStore<D> {
+    /// A module for every key, whose unwind data is used for new modules with this key.
+    modules: HashMap<UnwindDataKey, Module<D>>,
+}
+
+impl<D: Deref<Target = [u8]>> UnwindDataStore<D> {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self {
+            modules: HashMap::new(),
+        }
+    }
+
+    /// Create a module, like [`Module::new`]. If a module with the same `key` was
+    /// created before and is still in use, its unwind data is used and `load` isn't
+    /// called. Otherwise `load` is called to get the section info.
+    pub fn get_or_load<S, L>(
+        &mut self,
+        key: UnwindDataKey,
+        name: String,
+        avma_range: Range<u64>,
+        base_avma: u64,
+        load: L,
+    ) -> Module<D>
+    where
+        S: ModuleSectionInfo<D>,
+        L: FnOnce() -> S,
+    {
+        if let Some(module) = self.modules.get(&key) {
+            if module.has_shared_sections() {
+                return module.with_address(name, avma_range, base_avma);
+            }
+        }
+        self.remove_unused();
+        let module = Module::new(name, avma_range, base_avma, load());
+        self.modules.insert(key, module.clone());
+        module
+    }
+
+    /// Forget the unwind data which is no longer used by any module.
+    pub fn remove_unused(&mut self) {
+        self.modules
+            .retain(|_, module| module.has_shared_sections());
+    }
+
+    /// The number of files whose unwind data is in the store.
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    /// Whether the store is empty.
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+}
+
+impl<D: Deref<Target = [u8]>> Default for UnwindDataStore<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ExplicitModuleSectionInfo;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_unwind_data_store() {
+        let mut store = UnwindDataStore::<Vec<u8>>::new();
+        let key = UnwindDataKey::CodeId(CodeId::ElfBuildId(vec![1, 2, 3, 4]));
+        let load_count = core::cell::Cell::new(0);
+        let load = || {
+            load_count.set(load_count.get() + 1);
+            ExplicitModuleSectionInfo {
+                eh_frame: Some(vec![0; 16]),
+                ..Default::default()
+            }
+        };
+        let module1 = store.get_or_load(
+            key.clone(),
+            String::from("libc.so.6"),
+            0x10000..0x11000,
+            0x10000,
+            load,
+        );
+        let module2 = store.get_or_load(
+            key.clone(),
+            String::from("libc.so.6"),
+            0x20000..0x21000,
+            0x20000,
+            load,
+        );
+        assert_eq!(load_count.get(), 1);
+        assert_eq!(module2.avma_range(), 0x20000..0x21000);
+        assert_eq!(module2.unwind_data_kind(), module1.unwind_data_kind());
+
+        // Once all modules are dropped, the data is loaded again.
+        drop((module1, module2));
+        store.get_or_load(key, String::from("libc.so.6"), 0x0..0x1000, 0x0, load);
+        assert_eq!(load_count.get(), 2);
+        assert_eq!(store.len(), 1);
+    }
+}
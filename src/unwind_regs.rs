@@ -0,0 +1,16 @@
+/// Access to unwind registers by their DWARF register number, so that code which is
+/// generic over the CPU architecture doesn't need the accessors of the concrete register
+/// types, e.g. [`UnwindRegsX86_64::bp`](crate::x86_64::UnwindRegsX86_64::bp).
+///
+/// Unwinding a frame recovers the registers which are needed to unwind the next frame:
+/// the instruction pointer or return address, the stack pointer and the frame pointer.
+/// Other registers which the register type holds keep the values they were set to.
+pub trait UnwindRegs {
+    /// The value of the register with the DWARF register number `register`, or `None` if
+    /// the register type doesn't hold this register.
+    fn get_dwarf_reg(&self, register: u16) -> Option<u64>;
+
+    /// Set the register with the DWARF register number `register`. Returns `false`, and
+    /// doesn't change anything, if the register type doesn't hold this register.
+    fn set_dwarf_reg(&mut self, register: u16, value: u64) -> bool;
+}
@@ -1,5 +1,16 @@
+/// The outcome of looking up the unwind information of a frame. `E` is the evaluation
+/// which unwinds the frame if the information couldn't be turned into a rule.
 #[derive(Debug, Clone)]
-pub enum UnwindResult<R> {
+pub enum UnwindResult<R, E> {
     ExecRule(R),
-    Uncacheable(u64),
+    Uncacheable(E),
+}
+
+impl<R, E> UnwindResult<R, E> {
+    pub fn map_evaluation<T>(self, f: impl FnOnce(E) -> T) -> UnwindResult<R, T> {
+        match self {
+            Self::ExecRule(rule) => UnwindResult::ExecRule(rule),
+            Self::Uncacheable(evaluation) => UnwindResult::Uncacheable(f(evaluation)),
+        }
+    }
 }
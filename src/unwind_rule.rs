@@ -1,8 +1,42 @@
+use arrayvec::ArrayVec;
+
 use crate::error::Error;
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::trampoline::Trampoline;
+use crate::unwind_step::{ReadRequest, Resumable, Step, SyncReads};
 
-pub trait UnwindRule: Copy + core::fmt::Debug {
+pub trait UnwindRule: Copy + core::fmt::Debug + PartialEq {
     type UnwindRegs;
 
+    /// What the rule does with the values it reads from the stack.
+    type Plan: RulePlan<UnwindRegs = Self::UnwindRegs>;
+
+    /// Pass the stack reads of the rule for the frame with the registers `regs` to
+    /// `reads`, in order, and return what to do with their values, or `None` if the rule
+    /// says that the stack ends here.
+    fn plan_exec<S: RuleReads>(
+        self,
+        is_first_frame: bool,
+        regs: &Self::UnwindRegs,
+        reads: &mut S,
+    ) -> Result<Option<Self::Plan>, Error>;
+
+    /// Start executing the rule for the frame with the registers `regs`, for the frame
+    /// unwind stepper. The execution asks for the stack reads one at a time, and sets the
+    /// caller's registers in its last step.
+    fn start_exec(
+        self,
+        is_first_frame: bool,
+        regs: &Self::UnwindRegs,
+    ) -> RuleExecution<Self::Plan> {
+        let mut reads = ArrayVec::new();
+        match self.plan_exec(is_first_frame, regs, &mut reads) {
+            Ok(Some(plan)) => RuleExecution::new(plan, reads),
+            Ok(None) => RuleExecution::done(Ok(None)),
+            Err(err) => RuleExecution::done(Err(err)),
+        }
+    }
+
     fn exec<F>(
         self,
         is_first_frame: bool,
@@ -10,9 +44,206 @@ pub trait UnwindRule: Copy + core::fmt::Debug {
         read_stack: &mut F,
     ) -> Result<Option<u64>, Error>
     where
-        F: FnMut(u64) -> Result<u64, ()>;
+        F: MemoryReader,
+    {
+        SyncReads::new(read_stack).exec(self, is_first_frame, regs)
+    }
 
     fn rule_for_stub_functions() -> Self;
     fn rule_for_function_start() -> Self;
+    fn rule_for_end_of_stack() -> Self;
+    fn rule_for_plt_lazy_resolver_entry() -> Self;
+    fn rule_for_dyld_stub_binder_entry() -> Self;
     fn fallback_rule() -> Self;
+
+    /// Serialize the rule, see e.g. [`UnwindRuleX86_64::to_bytes`](crate::x86_64::UnwindRuleX86_64::to_bytes).
+    fn serialize(&self) -> [u8; 8];
+    fn deserialize(bytes: [u8; 8]) -> Option<Self>;
+
+    /// A rule which moves the stack pointer by the same delta as the unwind from `callee`
+    /// to `caller`, and which restores the frame pointer and return address from the
+    /// stack slots where they're usually saved. The caller has to check the rule by
+    /// executing it. Used by the return address predictor.
+    #[cfg(feature = "return-address-predictor")]
+    fn rule_for_observed_frame(
+        callee: &Self::UnwindRegs,
+        caller: &Self::UnwindRegs,
+    ) -> Option<Self>;
+
+    fn rule_for_hint(hint: UnwindHint) -> Self {
+        match hint {
+            UnwindHint::FramePointer => Self::fallback_rule(),
+            UnwindHint::NoFrame => Self::rule_for_stub_functions(),
+            UnwindHint::EndOfStack => Self::rule_for_end_of_stack(),
+        }
+    }
+
+    /// The rule for an address in a trampoline, or `None` if the module's unwind
+    /// information should be used.
+    fn rule_for_trampoline(
+        trampoline: Trampoline,
+        is_first_frame: bool,
+        is_function_start: bool,
+    ) -> Option<Self> {
+        match trampoline {
+            Trampoline::StackRoot => Some(Self::rule_for_end_of_stack()),
+            Trampoline::MessageDispatch if is_first_frame => Some(Self::rule_for_stub_functions()),
+            Trampoline::PltLazyResolver if is_first_frame && is_function_start => {
+                Some(Self::rule_for_plt_lazy_resolver_entry())
+            }
+            Trampoline::DyldStubBinder if is_first_frame && is_function_start => {
+                Some(Self::rule_for_dyld_stub_binder_entry())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The maximum number of stack reads of a [`RuleExecution`]: up to eight popped
+/// registers and the return address.
+pub const MAX_RULE_READS: usize = 9;
+
+/// A stack read of a [`RuleExecution`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackRead {
+    pub request: ReadRequest,
+    /// The value to use if the read fails. Without one, the execution fails with
+    /// [`Error::CouldNotReadStack`].
+    pub fallback: Option<u64>,
+}
+
+impl StackRead {
+    pub fn new(request: ReadRequest) -> Self {
+        Self {
+            request,
+            fallback: None,
+        }
+    }
+}
+
+/// Where an unwind rule sends its stack reads, see [`UnwindRule::plan_exec`].
+pub trait RuleReads {
+    /// Read `read`, or remember it for later. Fails if the read is done right away and
+    /// fails without a fallback.
+    fn read(&mut self, read: StackRead) -> Result<(), Error>;
+}
+
+/// The reads are collected for a [`RuleExecution`].
+impl RuleReads for ArrayVec<StackRead, MAX_RULE_READS> {
+    fn read(&mut self, read: StackRead) -> Result<(), Error> {
+        self.push(read);
+        Ok(())
+    }
+}
+
+/// What an unwind rule does once it has read the values from the stack.
+pub trait RulePlan {
+    type UnwindRegs;
+
+    /// Set the caller's registers. `values` are the results of the rule's reads, in
+    /// order.
+    fn finish(self, regs: &mut Self::UnwindRegs, values: &[u64]) -> Result<Option<u64>, Error>;
+}
+
+/// The execution of an unwind rule: a list of stack reads, followed by the
+/// [`RulePlan`] which computes the caller's registers from the read values.
+#[derive(Debug, Clone)]
+pub struct RuleExecution<P> {
+    /// The plan, until the execution is done.
+    plan: Option<P>,
+    reads: ArrayVec<StackRead, MAX_RULE_READS>,
+    values: ArrayVec<u64, MAX_RULE_READS>,
+    provided: Option<Result<u64, MemoryReadError>>,
+    result: Result<Option<u64>, Error>,
+}
+
+impl<P: RulePlan> RuleExecution<P> {
+    /// An execution which does `reads`, and then finishes with `plan`.
+    pub fn new(plan: P, reads: ArrayVec<StackRead, MAX_RULE_READS>) -> Self {
+        Self {
+            plan: Some(plan),
+            reads,
+            values: ArrayVec::new(),
+            provided: None,
+            result: Ok(None),
+        }
+    }
+
+    /// An execution which doesn't need to read anything.
+    pub fn done(result: Result<Option<u64>, Error>) -> Self {
+        Self {
+            plan: None,
+            reads: ArrayVec::new(),
+            values: ArrayVec::new(),
+            provided: None,
+            result,
+        }
+    }
+}
+
+impl<P: RulePlan> Resumable<P::UnwindRegs> for RuleExecution<P> {
+    type Output = Result<Option<u64>, Error>;
+
+    fn step(&mut self, regs: &mut P::UnwindRegs) -> Step<Self::Output> {
+        if let Some(value) = self.provided.take() {
+            let read = self.reads[self.values.len()];
+            match value.or_else(|err| read.fallback.ok_or(err)) {
+                Ok(value) => self.values.push(value),
+                Err(err) => {
+                    self.plan = None;
+                    self.result = Err(Error::CouldNotReadStack(read.request.address, err));
+                }
+            }
+        }
+        if self.values.len() == self.reads.len() {
+            if let Some(plan) = self.plan.take() {
+                self.result = plan.finish(regs, &self.values);
+            }
+        }
+        match self.plan {
+            Some(_) => Step::Read(self.reads[self.values.len()].request),
+            None => Step::Done(self.result),
+        }
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        if self.plan.is_some() {
+            self.provided = Some(value);
+        }
+    }
+}
+
+/// Serialize an unwind rule as its kind, followed by up to three little-endian 16-bit
+/// fields. Unused fields and the last byte are zero.
+pub(crate) fn encode_rule(kind: u8, fields: [u16; 3]) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    bytes[0] = kind;
+    for (chunk, field) in bytes[1..7].chunks_exact_mut(2).zip(fields) {
+        chunk.copy_from_slice(&field.to_le_bytes());
+    }
+    bytes
+}
+
+/// The inverse of [`encode_rule`]. Returns `None` if the last byte isn't zero.
+pub(crate) fn decode_rule(bytes: [u8; 8]) -> Option<(u8, [u16; 3])> {
+    if bytes[7] != 0 {
+        return None;
+    }
+    let field = |i: usize| u16::from_le_bytes([bytes[1 + 2 * i], bytes[2 + 2 * i]]);
+    Some((bytes[0], [field(0), field(1), field(2)]))
+}
+
+/// A rule which overrides the unwind information of a module for a range of addresses,
+/// e.g. for hand-written assembly functions whose CFI is known to be wrong. See
+/// [`ModuleBuilder::unwind_hint`](crate::ModuleBuilder::unwind_hint).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindHint {
+    /// The code maintains the frame pointer chain, so the caller's frame is found by
+    /// following the frame pointer.
+    FramePointer,
+    /// The code doesn't touch the stack or the return address, like a stub function or a
+    /// leaf function without a prologue.
+    NoFrame,
+    /// The code is the root of the stack, e.g. a thread entry point. Unwinding stops here.
+    EndOfStack,
 }
@@ -0,0 +1,83 @@
+/// Statistics about how unwind rules were obtained.
+///
+/// Only frames whose unwind rule was not found in the cache are counted by the
+/// per-strategy counters; cache hits are counted in [`CacheStats`](crate::CacheStats).
+/// Comparing these numbers between versions or configurations can help quantify
+/// changes in stack quality.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindStats {
+    /// The number of rules which were computed from `__unwind_info`.
+    pub compact_unwind_info_count: u64,
+    /// The number of rules which were computed from DWARF CFI, in `.eh_frame`,
+    /// `__eh_frame` or `.debug_frame`.
+    pub dwarf_count: u64,
+    /// The number of rules which were computed from PE unwind info.
+    pub pe_count: u64,
+    /// The number of rules which were computed from the `.gopclntab` of a Go binary.
+    pub go_pclntab_count: u64,
+    /// The number of rules which were computed by analyzing the instructions around
+    /// the instruction pointer, because it was found to be inside a function prologue
+    /// or epilogue.
+    pub instruction_analysis_count: u64,
+    /// The number of times the stub function rule was used because the address was
+    /// in an ELF PLT section which the module's unwind information doesn't cover.
+    pub plt_stub_count: u64,
+    /// The number of times the frame pointer rule was used for a return address in a
+    /// module with guaranteed frame pointers, or for any frame in frame-pointer-only
+    /// mode, without looking at the unwind information.
+    pub frame_pointer_count: u64,
+    /// The number of frames in a JIT range which were unwound according to the hint
+    /// of the range. These rules are not cached.
+    pub jit_range_count: u64,
+    /// The number of frames which were unwound by a foreign unwinder callback. See
+    /// e.g. [`UnwinderX86_64::add_foreign_unwinder`](crate::x86_64::UnwinderX86_64::add_foreign_unwinder).
+    pub foreign_unwinder_count: u64,
+    /// The number of times the fallback rule was used because no module contained
+    /// the address.
+    pub fallback_count: u64,
+    /// The number of times the fallback rule was used because the module's unwind
+    /// information could not be used.
+    pub fallback_after_error_count: u64,
+    /// The number of frames whose return address was computed directly, without a
+    /// rule that could be stored in the cache. These frames are not counted by the
+    /// other counters, e.g. by [`UnwindStats::dwarf_count`].
+    pub uncacheable_count: u64,
+    /// The number of DWARF CFI rows which were evaluated, successfully or not.
+    pub dwarf_evaluation_count: u64,
+    /// The number of frames which were unwound in frame-pointer-only mode and also with
+    /// the unwind information of their module, to verify the frame pointer. See e.g.
+    /// [`UnwinderX86_64::set_frame_pointer_verification_interval`](crate::x86_64::UnwinderX86_64::set_frame_pointer_verification_interval).
+    /// The rules which were computed for the verification are counted by the other
+    /// counters as usual.
+    pub frame_pointer_verification_count: u64,
+    /// The number of verified frames for which the frame pointer produced a different
+    /// caller than the unwind information.
+    pub frame_pointer_mismatch_count: u64,
+}
+
+impl UnwindStats {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The number of unwinds which used the fallback rule, for any reason.
+    pub fn fallbacks(&self) -> u64 {
+        self.fallback_count + self.fallback_after_error_count
+    }
+
+    /// The number of unwind rules which were computed, i.e. which were not found in
+    /// the cache.
+    pub fn total(&self) -> u64 {
+        self.compact_unwind_info_count
+            + self.dwarf_count
+            + self.pe_count
+            + self.go_pclntab_count
+            + self.instruction_analysis_count
+            + self.plt_stub_count
+            + self.frame_pointer_count
+            + self.jit_range_count
+            + self.foreign_unwinder_count
+            + self.fallbacks()
+    }
+}
@@ -0,0 +1,731 @@
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use arrayvec::ArrayVec;
+
+use crate::code_address::FrameAddress;
+use crate::error::Error;
+use crate::foreign_unwinder::ForeignUnwindCallback;
+use crate::frame_info::FrameUnwindInfo;
+use crate::memory_reader::{
+    is_uncaptured_red_zone_location, MemoryReadError, MemoryReader, PrefetchingReader,
+};
+use crate::unwind_rule::{
+    RuleExecution, RulePlan, RuleReads, StackRead, UnwindRule, MAX_RULE_READS,
+};
+use crate::unwinder::Unwinder;
+
+/// A memory read which a [`FrameUnwindStepper`] needs before it can continue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryRequest {
+    /// Read the 8-byte value at this address.
+    ReadU64(u64),
+    /// Read the 4-byte value at this address.
+    ReadU32(u64),
+    /// Read the 2-byte value at this address.
+    ReadU16(u64),
+    /// Read the byte at this address.
+    ReadU8(u64),
+}
+
+impl MemoryRequest {
+    /// The address of the read.
+    pub fn address(&self) -> u64 {
+        match *self {
+            Self::ReadU64(address)
+            | Self::ReadU32(address)
+            | Self::ReadU16(address)
+            | Self::ReadU8(address) => address,
+        }
+    }
+}
+
+/// A read which an unwind step needs before it can continue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadRequest {
+    /// The address of the read.
+    pub address: u64,
+    /// The size of the read in bytes: 1, 2, 4 or 8.
+    pub size: u8,
+    /// The first and the last 8-byte value of a range which is read right after this
+    /// read. A [`MemoryReader`] fetches the whole range with a single
+    /// [`MemoryReader::read_block`] call.
+    pub prefetch: Option<(u64, u64)>,
+    /// The stack pointer of the first frame, if this reads a saved register which is
+    /// skipped if it is in an uncaptured red zone, see [`MemoryReader::snapshot_range`].
+    pub red_zone_sp: Option<u64>,
+}
+
+impl ReadRequest {
+    /// Read the 8-byte value at `address`.
+    pub fn u64(address: u64) -> Self {
+        Self::sized(address, 8)
+    }
+
+    /// Read the `size`-byte value at `address`.
+    pub fn sized(address: u64, size: u8) -> Self {
+        Self {
+            address,
+            size,
+            prefetch: None,
+            red_zone_sp: None,
+        }
+    }
+
+    /// Ask for the 8-byte values from `first` to `last` to be fetched with this read.
+    pub fn with_prefetch(self, first: u64, last: u64) -> Self {
+        Self {
+            prefetch: Some((first, last)),
+            ..self
+        }
+    }
+
+    /// Skip this read if it is in the uncaptured red zone below `sp`.
+    pub fn with_red_zone(self, sp: u64) -> Self {
+        Self {
+            red_zone_sp: Some(sp),
+            ..self
+        }
+    }
+
+    /// The request which a [`FrameUnwindStepper`] returns for this read.
+    pub fn memory_request(&self) -> MemoryRequest {
+        match self.size {
+            1 => MemoryRequest::ReadU8(self.address),
+            2 => MemoryRequest::ReadU16(self.address),
+            4 => MemoryRequest::ReadU32(self.address),
+            _ => MemoryRequest::ReadU64(self.address),
+        }
+    }
+
+    /// Keep the bytes of `value` which this read asked for.
+    pub fn truncate(&self, value: u64) -> u64 {
+        match self.size {
+            8.. => value,
+            size => value & ((1 << (u32::from(size) * 8)) - 1),
+        }
+    }
+}
+
+/// The outcome of [`Resumable::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step<T> {
+    /// The task needs the result of this read. Pass it to [`Resumable::provide`] and
+    /// call [`Resumable::step`] again.
+    Read(ReadRequest),
+    /// The task is done.
+    Done(T),
+}
+
+/// A task which reads memory by returning the reads it needs, one at a time, and which
+/// resumes where it stopped once it has the result of the read.
+///
+/// Stepping the task again without providing the read returns the same read. A task
+/// which is done must not be stepped again.
+pub trait Resumable<C: ?Sized> {
+    /// The result of the task.
+    type Output;
+
+    /// Continue the task until it needs a read or until it's done. `context` must be the
+    /// same in every step.
+    fn step(&mut self, context: &mut C) -> Step<Self::Output>;
+
+    /// Provide the result of the read which the last step asked for.
+    fn provide(&mut self, value: Result<u64, MemoryReadError>);
+}
+
+/// Runs [`Resumable`] tasks with a [`MemoryReader`], which is how the synchronous
+/// unwinding functions read memory.
+pub(crate) struct SyncReads<'a, F: MemoryReader> {
+    reader: PrefetchingReader<'a, F>,
+}
+
+impl<'a, F: MemoryReader> SyncReads<'a, F> {
+    pub fn new(read_stack: &'a mut F) -> Self {
+        Self {
+            reader: PrefetchingReader::new(read_stack),
+        }
+    }
+
+    /// The wrapped reader.
+    pub fn inner(&mut self) -> &mut F {
+        self.reader.inner()
+    }
+
+    /// Perform a single read.
+    #[inline(always)]
+    pub fn read(&mut self, request: &ReadRequest) -> Result<u64, MemoryReadError> {
+        if let Some((first, last)) = request.prefetch {
+            self.reader.prefetch(first, last);
+        }
+        if let Some(sp) = request.red_zone_sp {
+            let snapshot_range = self.reader.snapshot_range();
+            if is_uncaptured_red_zone_location(snapshot_range.as_ref(), sp, request.address) {
+                return Err(MemoryReadError::OutsideSnapshot);
+            }
+        }
+        match request.size {
+            8 => self.reader.read_u64(request.address),
+            4 => self.reader.read_u32(request.address).map(u64::from),
+            size => {
+                let mut bytes = [0; 8];
+                let len = usize::from(size).min(8);
+                self.reader.read_block(request.address, &mut bytes[..len])?;
+                Ok(u64::from_le_bytes(bytes))
+            }
+        }
+    }
+
+    /// Execute `rule`, doing its reads right away rather than through its
+    /// [`RuleExecution`](crate::unwind_rule::RuleExecution).
+    #[inline(always)]
+    pub fn exec<R: UnwindRule>(
+        &mut self,
+        rule: R,
+        is_first_frame: bool,
+        regs: &mut R::UnwindRegs,
+    ) -> Result<Option<u64>, Error> {
+        let mut reads = DirectReads {
+            reads: self,
+            values: ArrayVec::new(),
+        };
+        match rule.plan_exec(is_first_frame, regs, &mut reads)? {
+            Some(plan) => plan.finish(regs, &reads.values),
+            None => Ok(None),
+        }
+    }
+
+    /// Run `task` until it's done.
+    pub fn run<C: ?Sized, T: Resumable<C>>(&mut self, task: &mut T, context: &mut C) -> T::Output {
+        loop {
+            match task.step(context) {
+                Step::Read(request) => {
+                    let value = self.read(&request);
+                    task.provide(value);
+                }
+                Step::Done(output) => return output,
+            }
+        }
+    }
+}
+
+/// The [`RuleReads`] of [`SyncReads::exec`], which keeps the read values for the rule's
+/// [`RulePlan`].
+struct DirectReads<'r, 'a, F: MemoryReader> {
+    reads: &'r mut SyncReads<'a, F>,
+    values: ArrayVec<u64, MAX_RULE_READS>,
+}
+
+impl<F: MemoryReader> RuleReads for DirectReads<'_, '_, F> {
+    #[inline(always)]
+    fn read(&mut self, read: StackRead) -> Result<(), Error> {
+        let value = self
+            .reads
+            .read(&read.request)
+            .or_else(|err| read.fallback.ok_or(err))
+            .map_err(|err| Error::CouldNotReadStack(read.request.address, err))?;
+        self.values.push(value);
+        Ok(())
+    }
+}
+
+/// How a frame unwind executes its unwind rules.
+pub(crate) trait RuleExecutor<R: UnwindRule> {
+    /// Execute `rule` for the frame with the registers `regs`, or continue the execution
+    /// which the last call started, like [`Resumable::step`].
+    fn exec_rule(
+        &mut self,
+        rule: R,
+        is_first_frame: bool,
+        regs: &mut R::UnwindRegs,
+    ) -> Step<Result<Option<u64>, Error>>;
+
+    /// Provide the result of the read which the last call of `exec_rule` asked for.
+    fn provide(&mut self, value: Result<u64, MemoryReadError>);
+}
+
+/// The synchronous unwinder executes rules right away.
+impl<R: UnwindRule, F: MemoryReader> RuleExecutor<R> for SyncReads<'_, F> {
+    #[inline(always)]
+    fn exec_rule(
+        &mut self,
+        rule: R,
+        is_first_frame: bool,
+        regs: &mut R::UnwindRegs,
+    ) -> Step<Result<Option<u64>, Error>> {
+        Step::Done(self.exec(rule, is_first_frame, regs))
+    }
+
+    fn provide(&mut self, _value: Result<u64, MemoryReadError>) {}
+}
+
+/// The [`RuleExecutor`] of a [`FrameUnwindStepper`], which executes a rule as a
+/// [`RuleExecution`] that returns its reads one at a time.
+pub(crate) struct SteppedRules<R: UnwindRule> {
+    execution: Option<RuleExecution<R::Plan>>,
+}
+
+impl<R: UnwindRule> SteppedRules<R> {
+    pub fn new() -> Self {
+        Self { execution: None }
+    }
+}
+
+impl<R: UnwindRule> RuleExecutor<R> for SteppedRules<R> {
+    fn exec_rule(
+        &mut self,
+        rule: R,
+        is_first_frame: bool,
+        regs: &mut R::UnwindRegs,
+    ) -> Step<Result<Option<u64>, Error>> {
+        let execution = self
+            .execution
+            .get_or_insert_with(|| rule.start_exec(is_first_frame, regs));
+        let step = execution.step(regs);
+        if let Step::Done(_) = step {
+            self.execution = None;
+        }
+        step
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        if let Some(execution) = &mut self.execution {
+            execution.provide(value);
+        }
+    }
+}
+
+/// The outcome of [`FrameUnwindTask::step`].
+pub(crate) enum FrameStep<'u, Regs> {
+    /// The unwind needs the result of this read.
+    Read(ReadRequest),
+    /// The frame is unwound by this foreign unwinder. Pass its result to
+    /// [`FrameUnwindTask::provide_foreign_result`].
+    Foreign(&'u ForeignUnwindCallback<Regs>),
+    /// The frame is unwound.
+    Done(Result<Option<u64>, Error>),
+}
+
+/// The unwind of a single frame, as a resumable task. This is what the unwinders put into
+/// a [`FrameUnwindStepper`].
+pub(crate) trait FrameUnwindTask<'u, Regs, C> {
+    /// Continue unwinding the frame, like [`Resumable::step`]. Once it's done, this keeps
+    /// returning the same result.
+    fn step(
+        &mut self,
+        regs: &mut Regs,
+        cache: &mut C,
+        info: &mut FrameUnwindInfo,
+    ) -> FrameStep<'u, Regs>;
+
+    /// Provide the result of the read which the last step asked for.
+    fn provide(&mut self, value: Result<u64, MemoryReadError>);
+
+    /// Provide the result of the foreign unwinder which the last step returned.
+    fn provide_foreign_result(&mut self, result: Result<Option<u64>, Error>);
+}
+
+/// The outcome of [`FrameUnwindStepper::step`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnwindStep {
+    /// The stepper needs the result of this read. Pass it to
+    /// [`FrameUnwindStepper::provide`] and call [`FrameUnwindStepper::step`] again.
+    NeedsMemory(MemoryRequest),
+    /// The frame is unwound, with the same result as [`Unwinder::unwind_frame`]. On
+    /// success, [`FrameUnwindStepper::regs`] has the caller's registers.
+    Done(Result<Option<u64>, Error>),
+}
+
+/// Unwinds a single frame without calling a memory reader: instead of reading memory,
+/// [`FrameUnwindStepper::step`] returns the reads it needs, one at a time, and the
+/// caller resumes it with their results. Create it with
+/// [`Unwinder::frame_unwind_stepper`].
+///
+/// This is the building block for memory readers which can't be a [`MemoryReader`]
+/// callback, e.g. async reads, reads across an FFI boundary, or reads which the caller
+/// collects and sends as a batch. The async entry points of the `async` feature are a
+/// thin wrapper around it. The result is the same as the one of
+/// [`Unwinder::unwind_frame`], which reads memory directly and doesn't go through the
+/// stepper.
+///
+/// Every step continues where the previous step stopped, so the frame is only looked
+/// up, and its unwind information only evaluated, once. The exception are frames of
+/// foreign unwinders, see e.g.
+/// [`UnwinderAarch64::add_foreign_unwinder`](crate::aarch64::UnwinderAarch64::add_foreign_unwinder):
+/// their callback can't be suspended, so it runs again with the reads which have been
+/// provided so far, until it doesn't need a read that it doesn't have. A callback which
+/// makes `n` reads runs `n + 1` times, so the stepper provides at most 32 reads to it,
+/// and further reads fail with [`MemoryReadError::Unknown`].
+///
+/// If the reads are served from a copy of the stack, pass the range of the copy to
+/// [`FrameUnwindStepper::with_snapshot_range`], like a [`MemoryReader`] does with
+/// [`MemoryReader::snapshot_range`]. Otherwise the stepper asks for saved registers in
+/// the red zone of the first frame even if the copy doesn't have them.
+pub struct FrameUnwindStepper<'u, 's, U: Unwinder> {
+    address: FrameAddress,
+    task: Box<dyn FrameUnwindTask<'u, U::UnwindRegs, U::Cache<'s>> + 'u>,
+    regs: U::UnwindRegs,
+    info: FrameUnwindInfo,
+    snapshot_range: Option<Range<u64>>,
+    pending: Option<PendingRead>,
+    foreign: Option<ForeignReplay<'u, U::UnwindRegs>>,
+    result: Option<Result<Option<u64>, Error>>,
+}
+
+/// The read which the stepper returned last.
+#[derive(Debug, Clone, Copy)]
+enum PendingRead {
+    Task(ReadRequest),
+    Foreign(MemoryRequest),
+}
+
+/// The maximum number of reads which the stepper provides to a foreign unwinder. The
+/// callback is replayed for every read, so this bounds its total number of reads.
+const MAX_FOREIGN_UNWINDER_READS: usize = 32;
+
+/// A foreign unwinder callback, with the reads which it has asked for so far.
+struct ForeignReplay<'u, Regs> {
+    callback: &'u ForeignUnwindCallback<Regs>,
+    reads: Vec<(MemoryRequest, Result<u64, MemoryReadError>)>,
+}
+
+impl<'u, 's, U: Unwinder> FrameUnwindStepper<'u, 's, U>
+where
+    U::UnwindRegs: Clone,
+{
+    pub(crate) fn new(
+        address: FrameAddress,
+        regs: U::UnwindRegs,
+        task: Box<dyn FrameUnwindTask<'u, U::UnwindRegs, U::Cache<'s>> + 'u>,
+    ) -> Self {
+        Self {
+            address,
+            task,
+            regs,
+            info: FrameUnwindInfo::default(),
+            snapshot_range: None,
+            pending: None,
+            foreign: None,
+            result: None,
+        }
+    }
+
+    /// The range of stack addresses which the caller can read, if the reads are served
+    /// from a copy of the stack. See [`MemoryReader::snapshot_range`].
+    pub fn with_snapshot_range(mut self, snapshot_range: Range<u64>) -> Self {
+        self.snapshot_range = Some(snapshot_range);
+        self
+    }
+
+    /// Continue unwinding the frame, until it needs a read which hasn't been provided or
+    /// until it is done. `cache` must be the same in every step.
+    pub fn step(&mut self, cache: &mut U::Cache<'s>) -> UnwindStep {
+        if let Some(result) = self.result {
+            return UnwindStep::Done(result);
+        }
+        match self.pending {
+            Some(PendingRead::Task(request)) => {
+                return UnwindStep::NeedsMemory(request.memory_request())
+            }
+            Some(PendingRead::Foreign(request)) => return UnwindStep::NeedsMemory(request),
+            None => {}
+        }
+        loop {
+            if let Some(foreign) = &self.foreign {
+                let mut regs = self.regs.clone();
+                let mut reader = ReplayReader {
+                    reads: &foreign.reads,
+                    pending: None,
+                };
+                let result = (foreign.callback)(self.address, &mut regs, &mut reader);
+                if let Some(request) = reader.pending {
+                    if foreign.reads.len() < MAX_FOREIGN_UNWINDER_READS {
+                        self.pending = Some(PendingRead::Foreign(request));
+                        return UnwindStep::NeedsMemory(request);
+                    }
+                }
+                self.foreign = None;
+                self.regs = regs;
+                self.task.provide_foreign_result(result);
+            }
+            match self.task.step(&mut self.regs, cache, &mut self.info) {
+                FrameStep::Read(request) => {
+                    if let Some(sp) = request.red_zone_sp {
+                        let snapshot_range = self.snapshot_range.as_ref();
+                        if is_uncaptured_red_zone_location(snapshot_range, sp, request.address) {
+                            // The copy doesn't have the saved register, so the read fails
+                            // like it would with a MemoryReader.
+                            self.task.provide(Err(MemoryReadError::OutsideSnapshot));
+                            continue;
+                        }
+                    }
+                    self.pending = Some(PendingRead::Task(request));
+                    return UnwindStep::NeedsMemory(request.memory_request());
+                }
+                FrameStep::Foreign(callback) => {
+                    self.foreign = Some(ForeignReplay {
+                        callback,
+                        reads: Vec::new(),
+                    });
+                }
+                FrameStep::Done(result) => {
+                    self.result = Some(result);
+                    return UnwindStep::Done(result);
+                }
+            }
+        }
+    }
+
+    /// Provide the result of the read which the last step asked for. For reads of less
+    /// than eight bytes, e.g. [`MemoryRequest::ReadU32`], only the low bytes of `value`
+    /// which the read asked for are used.
+    ///
+    /// This does nothing if no read is outstanding.
+    pub fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        match self.pending.take() {
+            Some(PendingRead::Task(request)) => {
+                self.task
+                    .provide(value.map(|value| request.truncate(value)));
+            }
+            Some(PendingRead::Foreign(request)) => {
+                if let Some(foreign) = &mut self.foreign {
+                    foreign.reads.push((request, value));
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// The registers of the frame before it is unwound, and the caller's registers once
+    /// a step has returned [`UnwindStep::Done`].
+    pub fn regs(&self) -> &U::UnwindRegs {
+        &self.regs
+    }
+
+    /// Consume the stepper and return its registers, see [`FrameUnwindStepper::regs`].
+    pub fn into_regs(self) -> U::UnwindRegs {
+        self.regs
+    }
+
+    /// Information about how the frame was unwound, once it's done.
+    pub fn frame_info(&self) -> &FrameUnwindInfo {
+        &self.info
+    }
+}
+
+impl<U: Unwinder> core::fmt::Debug for FrameUnwindStepper<'_, '_, U>
+where
+    U::UnwindRegs: core::fmt::Debug,
+{
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("FrameUnwindStepper")
+            .field("address", &self.address)
+            .field("regs", &self.regs)
+            .field("info", &self.info)
+            .field("snapshot_range", &self.snapshot_range)
+            .field("pending", &self.pending)
+            .field("result", &self.result)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The reader which the stepper passes to a foreign unwinder. It only serves the reads
+/// which have been provided, and remembers the first read that it doesn't know.
+struct ReplayReader<'a> {
+    reads: &'a [(MemoryRequest, Result<u64, MemoryReadError>)],
+    pending: Option<MemoryRequest>,
+}
+
+impl ReplayReader<'_> {
+    fn read(&mut self, request: MemoryRequest) -> Result<u64, MemoryReadError> {
+        match self.reads.iter().find(|(known, _)| *known == request) {
+            Some((_, result)) => *result,
+            None => {
+                self.pending.get_or_insert(request);
+                Err(MemoryReadError::Unknown)
+            }
+        }
+    }
+}
+
+impl MemoryReader for ReplayReader<'_> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        self.read(MemoryRequest::ReadU64(address))
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        self.read(MemoryRequest::ReadU32(address))
+            .map(|value| value as u32)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::aarch64::{CacheAarch64, UnwindRegsAarch64, UnwinderAarch64};
+
+    #[test]
+    fn test_frame_unwind_stepper() {
+        let unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        let mut cache = CacheAarch64::<_>::new();
+        // Frame pointer chain: [0x20] = caller fp, [0x28] = return address.
+        let stack = [0, 0, 0, 0, 0x40, 0x2345, 0, 0];
+        let regs = UnwindRegsAarch64::new(0x1234, 0x10, 0x20);
+        let address = FrameAddress::from_return_address(0x1234).unwrap();
+
+        let mut stepper = unwinder.frame_unwind_stepper(address, regs);
+        let mut requests = Vec::new();
+        let result = loop {
+            match stepper.step(&mut cache) {
+                UnwindStep::NeedsMemory(request) => {
+                    // Asking again without providing the read returns the same request.
+                    assert_eq!(stepper.step(&mut cache), UnwindStep::NeedsMemory(request));
+                    requests.push(request);
+                    let value = stack.get((request.address() / 8) as usize).copied();
+                    stepper.provide(value.ok_or(MemoryReadError::Unmapped));
+                }
+                UnwindStep::Done(result) => break result,
+            }
+        };
+        assert_eq!(
+            requests,
+            [MemoryRequest::ReadU64(0x28), MemoryRequest::ReadU64(0x20)]
+        );
+        let stepper_regs = stepper.into_regs();
+
+        let mut regs = UnwindRegsAarch64::new(0x1234, 0x10, 0x20);
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let expected = unwinder.unwind_frame(address, &mut regs, &mut cache, &mut read_stack);
+        assert_eq!(result, expected);
+        assert_eq!(result, Ok(Some(0x2345)));
+        assert_eq!(stepper_regs, regs);
+    }
+
+    #[test]
+    fn test_frame_unwind_stepper_runs_lookup_once() {
+        use crate::diagnostics::Diagnostic;
+        use core::sync::atomic::{AtomicUsize, Ordering};
+
+        static DIAGNOSTIC_COUNT: AtomicUsize = AtomicUsize::new(0);
+        let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        unwinder.set_diagnostics_callback(|diagnostic: &Diagnostic| {
+            if let Diagnostic::UsedFallbackRuleOutsideModules { .. } = diagnostic {
+                DIAGNOSTIC_COUNT.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        let mut cache = CacheAarch64::<_>::new();
+        let stack = [0, 0, 0, 0, 0x40, 0x2345, 0, 0];
+        let regs = UnwindRegsAarch64::new(0x1234, 0x10, 0x20);
+        let address = FrameAddress::from_return_address(0x1234).unwrap();
+
+        let mut stepper = unwinder.frame_unwind_stepper(address, regs);
+        let mut read_count = 0;
+        let result = loop {
+            match stepper.step(&mut cache) {
+                UnwindStep::NeedsMemory(request) => {
+                    read_count += 1;
+                    let value = stack.get((request.address() / 8) as usize).copied();
+                    stepper.provide(value.ok_or(MemoryReadError::Unmapped));
+                }
+                UnwindStep::Done(result) => break result,
+            }
+        };
+        assert_eq!(result, Ok(Some(0x2345)));
+        assert_eq!(read_count, 2);
+        // The frame is outside of any module, so it's unwound with the fallback rule.
+        // The lookup, and its diagnostic, happen once for both reads.
+        assert_eq!(DIAGNOSTIC_COUNT.load(Ordering::Relaxed), 1);
+        let stats = unwinder.cache_stats(&cache);
+        assert_eq!(stats.hit_count, 0);
+        assert_eq!(stats.miss_empty_slot_count, 1);
+    }
+
+    /// A copy of `bytes` at address 0, which only implements `read_block`.
+    struct ByteSnapshot<'a>(&'a [u8]);
+
+    impl MemoryReader for ByteSnapshot<'_> {
+        fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            let mut bytes = [0; 8];
+            self.read_block(address, &mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+
+        fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+            let start = address as usize;
+            let bytes = self
+                .0
+                .get(start..start + buf.len())
+                .ok_or(MemoryReadError::OutsideSnapshot)?;
+            buf.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_frame_unwind_stepper_foreign_read_limit() {
+        let mut unwinder = UnwinderAarch64::<Vec<u8>>::new();
+        // Reads 64 values, and fails once a read fails.
+        unwinder.add_foreign_unwinder(0x7000..0x8000, |_, _, read_stack| {
+            let mut sum = 0;
+            for address in (0..64).map(|i| i * 8) {
+                sum += read_stack
+                    .read_u64(address)
+                    .map_err(|error| Error::CouldNotReadStack(address, error))?;
+            }
+            Ok(Some(sum))
+        });
+        let mut cache = CacheAarch64::<_>::new();
+        let regs = UnwindRegsAarch64::new(0x7010, 0x10, 0x20);
+        let address = FrameAddress::from_instruction_pointer(0x7010);
+
+        let mut stepper = unwinder.frame_unwind_stepper(address, regs);
+        let mut read_count = 0;
+        let result = loop {
+            match stepper.step(&mut cache) {
+                UnwindStep::NeedsMemory(_) => {
+                    read_count += 1;
+                    stepper.provide(Ok(1));
+                }
+                UnwindStep::Done(result) => break result,
+            }
+        };
+        assert_eq!(read_count, MAX_FOREIGN_UNWINDER_READS);
+        assert_eq!(
+            result,
+            Err(Error::CouldNotReadStack(
+                MAX_FOREIGN_UNWINDER_READS as u64 * 8,
+                MemoryReadError::Unknown
+            ))
+        );
+
+        // unwind_frame calls the foreign unwinder with the memory reader, so it isn't
+        // limited.
+        let mut regs = UnwindRegsAarch64::new(0x7010, 0x10, 0x20);
+        let mut read_stack = |_| Ok::<_, ()>(1);
+        let result = unwinder.unwind_frame(address, &mut regs, &mut cache, &mut read_stack);
+        assert_eq!(result, Ok(Some(64)));
+    }
+
+    #[test]
+    fn test_sync_reads_u32_at_end_of_snapshot() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let mut reader = ByteSnapshot(&bytes);
+        let mut reads = SyncReads::new(&mut reader);
+        assert_eq!(reads.read(&ReadRequest::sized(8, 4)), Ok(0x0c0b_0a09));
+        assert_eq!(
+            reads.read(&ReadRequest::u64(8)),
+            Err(MemoryReadError::OutsideSnapshot)
+        );
+    }
+
+    #[test]
+    fn test_sized_memory_requests() {
+        let request = |size| ReadRequest::sized(0x10, size).memory_request();
+        assert_eq!(request(1), MemoryRequest::ReadU8(0x10));
+        assert_eq!(request(2), MemoryRequest::ReadU16(0x10));
+        assert_eq!(request(4), MemoryRequest::ReadU32(0x10));
+        assert_eq!(request(8), MemoryRequest::ReadU64(0x10));
+        assert_eq!(ReadRequest::sized(0x10, 2).truncate(0x1234_5678), 0x5678);
+    }
+}
@@ -4,24 +4,58 @@ use alloc::vec::Vec;
 use fallible_iterator::FallibleIterator;
 use gimli::{EndianSlice, LittleEndian};
 
+use crate::address_space::AddressSpace;
 use crate::arch::Arch;
-use crate::cache::{AllocationPolicy, Cache};
-use crate::dwarf::{DwarfCfiIndex, DwarfUnwinder, DwarfUnwinding, UnwindSectionType};
+#[cfg(feature = "async")]
+use crate::async_unwind::{self, AsyncMemoryReader, AsyncUnwindIterator};
+use crate::cache::{AllocationPolicy, ArchCache, Cache};
+use crate::diagnostics::{Diagnostic, DiagnosticsCallback, DiagnosticsSink};
+use crate::dwarf::{
+    fde_svma_ranges, CieFixups, DwarfCfiIndex, DwarfUnwinder, DwarfUnwinderError, DwarfUnwinding,
+    UnwindSectionType,
+};
 use crate::error::{Error, UnwinderError};
-use crate::instruction_analysis::InstructionAnalysis;
+use crate::foreign_unwinder::{ForeignUnwindCallback, ForeignUnwinder};
+use crate::frame_info::{
+    FallbackPolicy, FallbackReason, FrameConfidence, FrameUnwindInfo, UnwindErrorDetails,
+};
+use crate::instruction_analysis::{FunctionBounds, InstructionAnalysis};
+use crate::jit_range::{JitRange, JitRanges};
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::module_id::{CodeId, DebugId};
 
+#[cfg(feature = "go")]
+use crate::go::{GoPclntab, GoUnwinding};
 #[cfg(feature = "macho")]
 use crate::macho::{
-    CompactUnwindInfoUnwinder, CompactUnwindInfoUnwinding, CuiUnwindResult, TextBytes,
+    CompactUnwindInfoUnwinder, CompactUnwindInfoUnwinderError, CompactUnwindInfoUnwinding,
+    CuiUnwindResult, TextBytes,
 };
+use crate::mixed_stack::{FrameHookContext, FrameHookOutput, MixedStackUnwindIterator};
+use crate::partial_regs::PartialUnwindRegs;
 #[cfg(feature = "pe")]
 use crate::pe::{DataAtRvaRange, PeUnwinding};
-use crate::rule_cache::CacheResult;
+use crate::rosetta::is_rosetta_module_name;
+use crate::rule_cache::{CacheHandle, CacheResult, CacheStats};
+use crate::shadow_stack::{ShadowCallStackMode, ShadowStackUnwindIterator};
+use crate::stack_fingerprint::StackFingerprint;
+use crate::stack_link::{StackLink, StackLinkExecution, StackLinkRegs, StackLinkRule};
+#[cfg(feature = "std")]
+use crate::sync_cache::SyncCache;
+use crate::trace::{trace_event, Tracer};
+use crate::trampoline::Trampoline;
 use crate::unwind_result::UnwindResult;
-use crate::unwind_rule::UnwindRule;
-use crate::FrameAddress;
+use crate::unwind_rule::{UnwindHint, UnwindRule};
+use crate::unwind_stats::UnwindStats;
+use crate::unwind_step::{
+    FrameStep, FrameUnwindStepper, FrameUnwindTask, Resumable, RuleExecutor, Step, SteppedRules,
+    SyncReads,
+};
+use crate::wow64::is_wow64_transition_module_name;
+use crate::{FrameAddress, InstructionPointerAdjustment, RelativeFrame};
 
 use core::marker::PhantomData;
+use core::num::NonZeroU64;
 use core::ops::{Deref, Range};
 use core::sync::atomic::{AtomicU16, Ordering};
 
@@ -29,29 +63,78 @@ use core::sync::atomic::{AtomicU16, Ordering};
 /// This trait's methods are what let you do the actual unwinding.
 pub trait Unwinder: Clone {
     /// The unwind registers type for the targeted CPU architecture.
-    type UnwindRegs;
+    type UnwindRegs: crate::UnwindRegs;
 
     /// The unwind cache for the targeted CPU architecture.
     /// This is an associated type because the cache stores unwind rules, whose concrete
     /// type depends on the CPU arch, and because the cache can support different allocation
-    /// policies.
-    type Cache;
+    /// policies. `'s` is the lifetime of the slots of a shared cache, see
+    /// [`CacheX86_64::new_shared`](crate::x86_64::CacheX86_64::new_shared).
+    type Cache<'s>;
 
     /// The module type. This is an associated type because the concrete type varies
     /// depending on the type you use to give the module access to the unwind section data.
     type Module;
 
+    /// The trace buffer type for the targeted CPU architecture. This is an associated
+    /// type because the trace records unwind rules and registers, whose concrete types
+    /// depend on the CPU arch.
+    #[cfg(feature = "trace")]
+    type UnwindTrace;
+
     /// Add a module that's loaded in the profiled process. This is how you provide unwind
     /// information and address ranges.
     ///
     /// This should be called whenever a new module is loaded into the process.
-    fn add_module(&mut self, module: Self::Module);
+    ///
+    /// If the new module's address range overlaps with the address ranges of modules
+    /// that were added earlier, the earlier modules are removed: a module can only be
+    /// mapped at an address if whatever was mapped there before has been unmapped. This
+    /// keeps the module list consistent even if the unload notification for the old
+    /// module was missed or arrives late. The returned [`AddModuleOutcome`] says whether
+    /// this happened.
+    fn add_module(&mut self, module: Self::Module) -> AddModuleOutcome;
 
     /// Remove a module that was added before using `add_module`, keyed by the start
     /// address of that module's address range. If no match is found, the call is ignored.
     /// This should be called whenever a module is unloaded from the process.
     fn remove_module(&mut self, module_avma_range_start: u64);
 
+    /// Replace all modules with `modules`, e.g. after the process called `exec` or after
+    /// re-reading the full module list of the process.
+    ///
+    /// This is cheaper than removing and adding the modules one by one, because the
+    /// rules in the caches are only invalidated once. The modules don't need to be sorted.
+    /// If modules overlap, later modules replace earlier ones, like with
+    /// [`Unwinder::add_module`].
+    fn set_modules(&mut self, modules: Vec<Self::Module>);
+
+    /// Update the modules to match `current`, the full list of modules which are loaded
+    /// in the process right now, e.g. from `/proc/<pid>/maps` or from dyld's image list.
+    ///
+    /// Modules which are already known are kept. A module is known if an existing module
+    /// has the same address range and, if both code IDs are known, the same code ID.
+    /// `load` is only called for the other descriptors; if it returns `None`, the module
+    /// is skipped. Existing modules which are not in `current` are removed.
+    ///
+    /// If nothing changed, the caches stay valid, so periodic re-scans are cheap.
+    fn sync_modules<I, L>(&mut self, current: I, load: L) -> SyncModulesOutcome
+    where
+        I: IntoIterator<Item = ModuleDescriptor>,
+        L: FnMut(&ModuleDescriptor) -> Option<Self::Module>;
+
+    /// Update the modules to match the modules of `address_space`, like
+    /// [`Unwinder::sync_modules`] with the descriptors and the loader of the address
+    /// space. Afterwards, pass the same address space as the `read_stack` argument of
+    /// [`Unwinder::iter_frames`].
+    fn sync_address_space<S>(&mut self, address_space: &mut S) -> SyncModulesOutcome
+    where
+        S: AddressSpace<Module = Self::Module>,
+    {
+        let current = address_space.module_descriptors();
+        self.sync_modules(current, |descriptor| address_space.load_module(descriptor))
+    }
+
     /// Returns the highest code address that is known in this process based on the module
     /// address ranges. Returns 0 if no modules have been added.
     ///
@@ -60,31 +143,191 @@ pub trait Unwinder: Clone {
     /// to make an educated guess at a pointer authentication mask for Aarch64 return addresses.
     fn max_known_code_address(&self) -> u64;
 
+    /// Returns a number which changes whenever the modules or the settings of this
+    /// unwinder change. Unwinding the same registers and stack contents can give
+    /// different frames with a different generation. Generations are shared by all
+    /// unwinders and wrap around, so they should only be compared for equality.
+    fn modules_generation(&self) -> u16;
+
+    /// Returns the code ID of the module which contains `address`, and the address
+    /// relative to the module's base address. Returns `None` if the address isn't in a
+    /// known module.
+    fn module_relative_address(&self, address: FrameAddress) -> Option<(Option<&CodeId>, u32)>;
+
+    /// Returns the module which contains `address`, if any.
+    fn module_for_address(&self, address: u64) -> Option<&Self::Module>;
+
+    /// Returns all modules, sorted by address.
+    fn modules(&self) -> &[Self::Module];
+
+    /// Returns the tag of the marker range which contains `address`, if any. See e.g.
+    /// [`UnwinderX86_64::add_marker_range`](crate::x86_64::UnwinderX86_64::add_marker_range).
+    fn marker_for_address(&self, address: u64) -> Option<u32>;
+
+    /// Returns the usage statistics of a cache which was used with this unwinder.
+    fn cache_stats(&self, cache: &Self::Cache<'_>) -> CacheStats;
+
     /// Unwind a single frame, to recover return address and caller register values.
     /// This is the main entry point for unwinding.
     fn unwind_frame<F>(
         &self,
         address: FrameAddress,
         regs: &mut Self::UnwindRegs,
-        cache: &mut Self::Cache,
+        cache: &mut Self::Cache<'_>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader;
+
+    /// Unwind a single frame, like [`Unwinder::unwind_frame`], with a cache from
+    /// `caches`. This lets several threads unwind with a shared unwinder through `&self`
+    /// without passing a cache around.
+    #[cfg(feature = "std")]
+    fn unwind_frame_with_pooled_cache<'s, F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut Self::UnwindRegs,
+        caches: &SyncCache<Self::Cache<'s>>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+        Self::Cache<'s>: Default,
+    {
+        caches.with_cache(|cache| self.unwind_frame(address, regs, cache, read_stack))
+    }
+
+    /// Unwind a single frame, like [`Unwinder::unwind_frame`], and fill `info` with
+    /// information about how the frame was unwound.
+    fn unwind_frame_with_info<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut Self::UnwindRegs,
+        cache: &mut Self::Cache<'_>,
+        read_stack: &mut F,
+        info: &mut FrameUnwindInfo,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader;
+
+    /// Unwind a single frame, like [`Unwinder::unwind_frame`], and append every step
+    /// that the unwinder takes to `trace`.
+    ///
+    /// This is much slower than [`Unwinder::unwind_frame`] and is meant for debugging
+    /// incorrect stacks.
+    #[cfg(feature = "trace")]
+    fn unwind_frame_traced<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut Self::UnwindRegs,
+        cache: &mut Self::Cache<'_>,
+        read_stack: &mut F,
+        trace: &mut Self::UnwindTrace,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader;
+
+    /// Unwind a single frame, like [`Unwinder::unwind_frame`], when only some of the
+    /// registers are known, e.g. because the sampler only captured the instruction
+    /// pointer and the stack pointer.
+    ///
+    /// Rules which don't need the unknown registers work as usual, e.g. DWARF rules
+    /// which compute the CFA from the stack pointer, and the caller's registers which
+    /// would be restored from unknown registers stay unknown. If the frame's rule needs
+    /// an unknown register, e.g. a frame pointer rule without a frame pointer, this
+    /// returns [`Error::MissingRegisterValue`] rather than a wrong return address.
+    ///
+    /// To find out which registers a rule needs, frames with unknown registers are
+    /// unwound twice, with different values for the unknown registers, so this is slower
+    /// than [`Unwinder::unwind_frame`].
+    fn unwind_frame_with_partial_regs<R, F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut R,
+        cache: &mut Self::Cache<'_>,
         read_stack: &mut F,
     ) -> Result<Option<u64>, Error>
     where
-        F: FnMut(u64) -> Result<u64, ()>;
+        R: PartialUnwindRegs<Regs = Self::UnwindRegs>,
+        F: MemoryReader,
+    {
+        crate::partial_regs::unwind_frame_with_partial_regs(self, address, regs, cache, read_stack)
+    }
+
+    /// Compute the unwind rules for `addresses` ahead of time and store them in `cache`,
+    /// so that unwinding from these addresses later only needs a cache lookup.
+    ///
+    /// This is useful to avoid latency spikes during the first samples, for example by
+    /// passing the hottest addresses from a previous profiling session before sampling
+    /// starts. Addresses whose rule depends on register values or stack contents, whose
+    /// unwind information can't be used, or where unwinding stops at a Rosetta or WOW64
+    /// boundary, are skipped. Since the cache has a fixed size, later addresses can
+    /// evict the rules of earlier addresses.
+    ///
+    /// Returns the number of rules which were stored in the cache. The lookups and
+    /// computations done here are counted in the cache statistics.
+    fn precompute_rules<I>(&self, cache: &mut Self::Cache<'_>, addresses: I) -> usize
+    where
+        I: IntoIterator<Item = FrameAddress>;
 
     /// Return an iterator that unwinds frame by frame until the end of the stack is found.
-    fn iter_frames<'u, 'c, 'r, F>(
+    fn iter_frames<'u, 'c, 's, 'r, F>(
         &'u self,
         pc: u64,
         regs: Self::UnwindRegs,
-        cache: &'c mut Self::Cache,
+        cache: &'c mut Self::Cache<'s>,
         read_stack: &'r mut F,
-    ) -> UnwindIterator<'u, 'c, 'r, Self, F>
+    ) -> UnwindIterator<'u, 'c, 's, 'r, Self, F>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
+        F: MemoryReader,
     {
         UnwindIterator::new(self, pc, regs, cache, read_stack)
     }
+
+    /// Start unwinding the frame at `address` without a memory reader: the returned
+    /// stepper asks for the reads it needs, one at a time. See [`FrameUnwindStepper`].
+    fn frame_unwind_stepper<'s>(
+        &self,
+        address: FrameAddress,
+        regs: Self::UnwindRegs,
+    ) -> FrameUnwindStepper<'_, 's, Self>;
+
+    /// Unwind a single frame, like [`Unwinder::unwind_frame`], with a memory reader
+    /// whose reads are async. This drives a [`FrameUnwindStepper`] with `read_stack`.
+    #[cfg(feature = "async")]
+    fn unwind_frame_async<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut Self::UnwindRegs,
+        cache: &mut Self::Cache<'_>,
+        read_stack: &mut F,
+    ) -> impl core::future::Future<Output = Result<Option<u64>, Error>>
+    where
+        F: AsyncMemoryReader + ?Sized,
+        Self::UnwindRegs: Clone,
+    {
+        async move {
+            let mut info = FrameUnwindInfo::default();
+            async_unwind::unwind_frame(self, address, regs, cache, read_stack, &mut info).await
+        }
+    }
+
+    /// Return an iterator that unwinds frame by frame with a memory reader whose reads
+    /// are async, like [`Unwinder::iter_frames`].
+    #[cfg(feature = "async")]
+    fn iter_frames_async<'u, 'c, 's, 'r, F>(
+        &'u self,
+        pc: u64,
+        regs: Self::UnwindRegs,
+        cache: &'c mut Self::Cache<'s>,
+        read_stack: &'r mut F,
+    ) -> AsyncUnwindIterator<'u, 'c, 's, 'r, Self, F>
+    where
+        F: AsyncMemoryReader + ?Sized,
+        Self::UnwindRegs: Clone,
+    {
+        AsyncUnwindIterator::new(self, pc, regs, cache, read_stack)
+    }
 }
 
 /// An iterator for unwinding the entire stack, starting from the initial register values.
@@ -104,30 +347,39 @@ pub trait Unwinder: Clone {
 ///
 ///  - `'u`: The lifetime of the [`Unwinder`].
 ///  - `'c`: The lifetime of the unwinder cache.
+///  - `'s`: The lifetime of the slots of a shared unwinder cache, see
+///    [`CacheX86_64::new_shared`](crate::x86_64::CacheX86_64::new_shared).
 ///  - `'r`: The lifetime of the exclusive access to the `read_stack` callback.
-pub struct UnwindIterator<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>> {
+pub struct UnwindIterator<'u, 'c, 's, 'r, U: Unwinder + ?Sized, F: MemoryReader> {
     unwinder: &'u U,
     state: UnwindIteratorState,
     regs: U::UnwindRegs,
-    cache: &'c mut U::Cache,
+    cache: &'c mut U::Cache<'s>,
     read_stack: &'r mut F,
+    frame_info: FrameUnwindInfo,
+    frames_to_skip: usize,
+    depth_limit: Option<DepthLimit>,
+    yielded_frame_count: usize,
+    truncation: Option<TruncationSummary>,
+    fingerprint: StackFingerprint,
+    confidence: FrameConfidence,
+    budget: Option<BudgetState>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum UnwindIteratorState {
     Initial(u64),
     Unwinding(FrameAddress),
     Done,
 }
 
-impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>>
-    UnwindIterator<'u, 'c, 'r, U, F>
-{
+impl<'u, 'c, 's, 'r, U: Unwinder + ?Sized, F: MemoryReader> UnwindIterator<'u, 'c, 's, 'r, U, F> {
     /// Create a new iterator. You'd usually use [`Unwinder::iter_frames`] instead.
     pub fn new(
         unwinder: &'u U,
         pc: u64,
         regs: U::UnwindRegs,
-        cache: &'c mut U::Cache,
+        cache: &'c mut U::Cache<'s>,
         read_stack: &'r mut F,
     ) -> Self {
         Self {
@@ -136,38 +388,205 @@ impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>>
             regs,
             cache,
             read_stack,
+            frame_info: FrameUnwindInfo::default(),
+            frames_to_skip: 0,
+            depth_limit: None,
+            yielded_frame_count: 0,
+            truncation: None,
+            fingerprint: StackFingerprint::new(unwinder.modules_generation()),
+            confidence: FrameConfidence::Certain,
+            budget: None,
+        }
+    }
+
+    /// Create an iterator which continues where the iterator which produced `checkpoint`
+    /// stopped. See [`UnwindIterator::checkpoint`].
+    pub fn resume(
+        unwinder: &'u U,
+        checkpoint: UnwindIteratorCheckpoint<U::UnwindRegs>,
+        cache: &'c mut U::Cache<'s>,
+        read_stack: &'r mut F,
+    ) -> Self {
+        let UnwindIteratorCheckpoint {
+            state,
+            regs,
+            frames_to_skip,
+            depth_limit,
+            yielded_frame_count,
+            fingerprint,
+            confidence,
+        } = checkpoint;
+        Self {
+            unwinder,
+            state,
+            regs,
+            cache,
+            read_stack,
+            frame_info: FrameUnwindInfo::default(),
+            frames_to_skip,
+            depth_limit,
+            yielded_frame_count,
+            truncation: None,
+            fingerprint,
+            confidence,
+            budget: None,
         }
     }
+
+    /// Don't yield the first `count` frames, including the instruction pointer frame.
+    /// The skipped frames are still unwound, so the first yielded frame is the return
+    /// address of the `count`-th frame, with the correct registers.
+    ///
+    /// This is useful when capturing a stack from inside instrumentation code, whose own
+    /// frames are always at the top of the stack.
+    pub fn skip_frames(mut self, count: usize) -> Self {
+        self.frames_to_skip = count;
+        self
+    }
+
+    /// Stop yielding frames after `limit.max_frames` frames. The iterator then completes
+    /// with `Ok(None)`, and [`UnwindIterator::truncation_summary`] says how many frames
+    /// were left out, so that a profiler can display "N more frames" instead of silently
+    /// truncating the stack.
+    ///
+    /// To find out how many frames were left out, the iterator keeps unwinding without
+    /// yielding, for at most `limit.max_counted_frames` frames. This bounds the work for
+    /// very deep or looping stacks. If the stack ends right at the limit, nothing was
+    /// left out and there is no truncation summary; the frame after the limit is always
+    /// unwound to find out.
+    pub fn with_depth_limit(mut self, limit: DepthLimit) -> Self {
+        self.depth_limit = Some(limit);
+        self
+    }
+
+    /// Bound the work which this iterator does, e.g. in a signal handler which must not
+    /// take too long even if a frame is pathological. Once the budget is exhausted,
+    /// `next()` returns [`Error::Timeout`]; [`UnwindIterator::collect_stack`] keeps the
+    /// frames which were produced until then.
+    ///
+    /// The budget is not part of a [`UnwindIteratorCheckpoint`]; set a new budget after
+    /// resuming.
+    pub fn with_budget(mut self, budget: UnwindBudget) -> Self {
+        self.budget = Some(BudgetState {
+            budget,
+            used_steps: 0,
+            exhausted: false,
+        });
+        self
+    }
 }
 
-impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>>
-    UnwindIterator<'u, 'c, 'r, U, F>
-{
+impl<'u, 'c, 's, 'r, U: Unwinder + ?Sized, F: MemoryReader> UnwindIterator<'u, 'c, 's, 'r, U, F> {
     /// Yield the next frame in the stack.
     ///
     /// The first frame is `Ok(Some(FrameAddress::InstructionPointer(...)))`.
-    /// Subsequent frames are `Ok(Some(FrameAddress::ReturnAddress(...)))`.
+    /// Subsequent frames are `Ok(Some(FrameAddress::ReturnAddress(...)))`. If frames are
+    /// skipped with [`UnwindIterator::skip_frames`], the first yielded frame is a return
+    /// address.
     ///
     /// If a root function has been reached, this iterator completes with `Ok(None)`.
     /// Otherwise it completes with `Err(...)`, usually indicating that a certain stack
     /// address could not be read.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Result<Option<FrameAddress>, Error> {
+        while self.frames_to_skip != 0 {
+            self.frames_to_skip -= 1;
+            if self.next_frame()?.is_none() {
+                return Ok(None);
+            }
+        }
+        if let Some(limit) = self.depth_limit {
+            if self.yielded_frame_count >= limit.max_frames {
+                if self.state != UnwindIteratorState::Done {
+                    self.truncation = self.count_remaining_frames(limit.max_counted_frames);
+                    self.state = UnwindIteratorState::Done;
+                }
+                return Ok(None);
+            }
+        }
+        let frame = self.next_frame()?;
+        if let Some(frame) = frame {
+            self.yielded_frame_count += 1;
+            self.fingerprint.push(frame);
+        }
+        Ok(frame)
+    }
+
+    /// Unwinds up to `max_count` frames without yielding them, and at least one frame
+    /// to find out whether the stack continues. Returns `None` if it doesn't.
+    fn count_remaining_frames(&mut self, max_count: usize) -> Option<TruncationSummary> {
+        match self.next_frame() {
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => return None,
+        }
+        let mut omitted_frame_count = 1;
+        while omitted_frame_count < max_count {
+            match self.next_frame() {
+                Ok(Some(_)) => omitted_frame_count += 1,
+                Ok(None) => {
+                    return Some(TruncationSummary {
+                        omitted_frame_count,
+                        root_reached: true,
+                    })
+                }
+                Err(_) => break,
+            }
+        }
+        Some(TruncationSummary {
+            omitted_frame_count,
+            root_reached: false,
+        })
+    }
+
+    fn next_frame(&mut self) -> Result<Option<FrameAddress>, Error> {
         let next = match self.state {
             UnwindIteratorState::Initial(pc) => {
                 self.state = UnwindIteratorState::Unwinding(FrameAddress::InstructionPointer(pc));
                 return Ok(Some(FrameAddress::InstructionPointer(pc)));
             }
-            UnwindIteratorState::Unwinding(address) => {
-                self.unwinder
-                    .unwind_frame(address, &mut self.regs, self.cache, self.read_stack)?
-            }
+            UnwindIteratorState::Unwinding(address) => match &mut self.budget {
+                None => self.unwinder.unwind_frame_with_info(
+                    address,
+                    &mut self.regs,
+                    self.cache,
+                    self.read_stack,
+                    &mut self.frame_info,
+                )?,
+                Some(budget) => {
+                    budget.step()?;
+                    let mut reader = BudgetedReader {
+                        inner: &mut *self.read_stack,
+                        budget: &mut *budget,
+                    };
+                    let result = self.unwinder.unwind_frame_with_info(
+                        address,
+                        &mut self.regs,
+                        self.cache,
+                        &mut reader,
+                        &mut self.frame_info,
+                    );
+                    // Reads fail once the budget is exhausted, which can also make the
+                    // unwinder fall back to a wrong rule, so don't trust the result.
+                    if budget.exhausted {
+                        self.state = UnwindIteratorState::Done;
+                        return Err(Error::Timeout);
+                    }
+                    result?
+                }
+            },
             UnwindIteratorState::Done => return Ok(None),
         };
         match next {
             Some(return_address) => {
+                if self.read_stack.is_executable(return_address) == Some(false) {
+                    // The registers are already the caller's, so unwinding can't continue.
+                    self.state = UnwindIteratorState::Done;
+                    return Err(Error::ReturnAddressNotExecutable(return_address));
+                }
                 let return_address = FrameAddress::from_return_address(return_address)
                     .ok_or(Error::ReturnAddressIsNull)?;
+                // A frame can't be more trustworthy than the frame it was unwound from.
+                self.confidence = self.confidence.max(self.frame_info.confidence);
                 self.state = UnwindIteratorState::Unwinding(return_address);
                 Ok(Some(return_address))
             }
@@ -177,10 +596,190 @@ impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>>
             }
         }
     }
+
+    /// Information about how the most recent frame was unwound, i.e. how the caller's
+    /// registers were recovered from the callee's registers in the last call to `next()`.
+    pub fn last_frame_info(&self) -> &FrameUnwindInfo {
+        &self.frame_info
+    }
+
+    /// If the module's unwind information could not be used when unwinding the most
+    /// recent frame, this returns details about the failure: which module was involved,
+    /// which address was looked up, and what the error was. In that case, the returned
+    /// address was obtained with the fallback rule (usually frame pointer unwinding),
+    /// which might be wrong.
+    ///
+    /// Details are only available if the unwind rule for the frame was computed during
+    /// this call, i.e. if it wasn't found in the cache.
+    pub fn last_error_details(&self) -> Option<&UnwindErrorDetails> {
+        self.frame_info.error_details.as_ref()
+    }
+
+    /// If the iterator stopped because of the limit set with
+    /// [`UnwindIterator::with_depth_limit`] and the stack has more frames, this returns
+    /// how many frames were left out.
+    pub fn truncation_summary(&self) -> Option<&TruncationSummary> {
+        self.truncation.as_ref()
+    }
+
+    /// Like [`UnwindIterator::next`], but also returns how much the frame can be
+    /// trusted. The instruction pointer is [`FrameConfidence::Certain`], and every
+    /// return address is at most as confident as the frame it was unwound from.
+    pub fn next_with_confidence(
+        &mut self,
+    ) -> Result<Option<(FrameAddress, FrameConfidence)>, Error> {
+        Ok(self.next()?.map(|frame| (frame, self.confidence)))
+    }
+
+    /// A hash of the frames which were yielded so far, which can be used to deduplicate
+    /// identical stacks. See [`StackFingerprint`].
+    pub fn fingerprint(&self) -> StackFingerprint {
+        self.fingerprint
+    }
+
+    /// Like [`UnwindIterator::next`], but yields the frames relative to their modules,
+    /// so that they can be symbolicated without looking up the modules again.
+    pub fn next_relative(&mut self) -> Result<Option<RelativeFrame<'u>>, Error> {
+        let Some(frame) = self.next()? else {
+            return Ok(None);
+        };
+        let unwinder: &'u U = self.unwinder;
+        Ok(Some(match unwinder.module_relative_address(frame) {
+            Some((code_id, relative_address)) => RelativeFrame::Module {
+                code_id,
+                relative_address,
+                is_return_address: frame.is_return_address(),
+            },
+            None => RelativeFrame::Unknown(frame),
+        }))
+    }
+
+    /// Unwind up to `max_frames` frames and return them together with the reason why
+    /// unwinding stopped.
+    ///
+    /// Unlike collecting the frames with `FallibleIterator`, this keeps the frames which
+    /// were found before an error.
+    pub fn collect_stack(&mut self, max_frames: usize) -> StackResult {
+        let mut frames = Vec::new();
+        let end_state = loop {
+            if frames.len() >= max_frames {
+                break StackEndState::MaxDepth;
+            }
+            match self.next() {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) if self.truncation.is_some() => break StackEndState::MaxDepth,
+                Ok(None) if self.frame_info.reached_translation_boundary => {
+                    break StackEndState::TranslationBoundary
+                }
+                Ok(None) if self.frame_info.reached_wow64_transition => {
+                    break StackEndState::Wow64Transition
+                }
+                Ok(None) => break StackEndState::Root,
+                Err(error) => break StackEndState::Error(error),
+            }
+        };
+        StackResult { frames, end_state }
+    }
+
+    /// Save the state of this iterator, so that unwinding can be resumed later with
+    /// [`UnwindIterator::resume`].
+    ///
+    /// The checkpoint is small and doesn't borrow anything, so it can be created in a
+    /// latency-critical context, e.g. after unwinding the first few frames in a signal
+    /// handler, and the rest of the stack can be unwound later, e.g. on a different
+    /// thread. Resuming needs an unwinder with the same modules and a `read_stack`
+    /// callback which still returns the stack contents at the time of the checkpoint,
+    /// e.g. from a copy of the stack.
+    pub fn checkpoint(&self) -> UnwindIteratorCheckpoint<U::UnwindRegs>
+    where
+        U::UnwindRegs: Clone,
+    {
+        UnwindIteratorCheckpoint {
+            state: self.state,
+            regs: self.regs.clone(),
+            frames_to_skip: self.frames_to_skip,
+            depth_limit: self.depth_limit,
+            yielded_frame_count: self.yielded_frame_count,
+            fingerprint: self.fingerprint,
+            confidence: self.confidence,
+        }
+    }
+
+    /// Check the unwound return addresses against a captured hardware shadow stack,
+    /// and use the shadow stack for the remaining frames if they don't match.
+    ///
+    /// `shadow_stack` contains the return addresses from the shadow stack, innermost
+    /// first, i.e. the first entry is the return address of the function which the
+    /// instruction pointer is in.
+    pub fn with_shadow_stack<'ss>(
+        self,
+        shadow_stack: &'ss [u64],
+    ) -> ShadowStackUnwindIterator<'u, 'c, 's, 'r, 'ss, U, F> {
+        ShadowStackUnwindIterator::new(self, shadow_stack)
+    }
+
+    /// Check the unwound return addresses against the shadow call stack of an aarch64
+    /// program built with `-fsanitize=shadow-call-stack`, or take them from it, see
+    /// [`ShadowCallStackMode`]. In both modes, frames are taken from the shadow call
+    /// stack once unwinding fails or produces an address which doesn't match it.
+    ///
+    /// `shadow_call_stack` contains the return addresses from the shadow call stack,
+    /// innermost first, as returned by
+    /// [`ShadowCallStack::read_return_addresses`](crate::aarch64::ShadowCallStack::read_return_addresses).
+    /// Unlike with [`UnwindIterator::with_shadow_stack`], the first return address may
+    /// be missing from it, because functions push their return address in their
+    /// prologue and leaf functions don't push it at all. The first frame is always
+    /// unwound to find out whether it's there.
+    pub fn with_shadow_call_stack<'ss>(
+        self,
+        shadow_call_stack: &'ss [u64],
+        mode: ShadowCallStackMode,
+    ) -> ShadowStackUnwindIterator<'u, 'c, 's, 'r, 'ss, U, F> {
+        ShadowStackUnwindIterator::new_shadow_call_stack(self, shadow_call_stack, mode)
+    }
+
+    /// Call `hook` for every native frame, and yield the extension frames which it
+    /// pushes, e.g. the frames of an interpreter. See [`MixedStackUnwindIterator`].
+    ///
+    /// The hook gets the frame's registers and module, the `read_stack` callback of this
+    /// iterator, and the [`FrameHookOutput`] to fill in.
+    pub fn with_frame_hook<H>(self, hook: H) -> MixedStackUnwindIterator<'u, 'c, 's, 'r, U, F, H>
+    where
+        H: FnMut(&FrameHookContext<'_, U::Module, U::UnwindRegs>, &mut F, &mut FrameHookOutput),
+    {
+        MixedStackUnwindIterator::new(self, hook)
+    }
+
+    /// Yield a [`MixedFrame::Marker`](crate::MixedFrame::Marker) before every frame in a marker range, e.g. to
+    /// separate the frames of a signal handler from the interrupted frames. See e.g.
+    /// [`UnwinderX86_64::add_marker_range`](crate::x86_64::UnwinderX86_64::add_marker_range).
+    ///
+    /// Markers are also yielded by iterators created with
+    /// [`UnwindIterator::with_frame_hook`].
+    #[allow(clippy::type_complexity)]
+    pub fn with_frame_markers(
+        self,
+    ) -> MixedStackUnwindIterator<
+        'u,
+        'c,
+        's,
+        'r,
+        U,
+        F,
+        fn(&FrameHookContext<'_, U::Module, U::UnwindRegs>, &mut F, &mut FrameHookOutput),
+    > {
+        MixedStackUnwindIterator::new(self, |_, _, _| {})
+    }
+
+    /// The parts of the iterator which a frame hook gets: the unwinder, the registers of
+    /// the most recently yielded frame, and the memory reader.
+    pub(crate) fn hook_parts(&mut self) -> (&'u U, &U::UnwindRegs, &mut F) {
+        (self.unwinder, &self.regs, self.read_stack)
+    }
 }
 
-impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>> FallibleIterator
-    for UnwindIterator<'u, 'c, 'r, U, F>
+impl<'u, 'c, 's, 'r, U: Unwinder + ?Sized, F: MemoryReader> FallibleIterator
+    for UnwindIterator<'u, 'c, 's, 'r, U, F>
 {
     type Item = FrameAddress;
     type Error = Error;
@@ -190,6 +789,201 @@ impl<'u, 'c, 'r, U: Unwinder + ?Sized, F: FnMut(u64) -> Result<u64, ()>> Fallibl
     }
 }
 
+/// A limit on the number of frames which an [`UnwindIterator`] yields.
+/// See [`UnwindIterator::with_depth_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DepthLimit {
+    /// The maximum number of frames to yield.
+    pub max_frames: usize,
+    /// The maximum number of frames to unwind after `max_frames`, without yielding
+    /// them, in order to count the frames which were left out. At least one frame is
+    /// unwound, to find out whether any frames were left out at all.
+    pub max_counted_frames: usize,
+}
+
+/// A limit on the work which an [`UnwindIterator`] does. See
+/// [`UnwindIterator::with_budget`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnwindBudget {
+    /// The maximum number of steps, if any. Unwinding a frame is one step, and so is
+    /// every stack memory read, including the reads of DWARF expressions.
+    pub max_steps: Option<usize>,
+    /// Called before every step; if it returns `true`, unwinding stops. This can check
+    /// a deadline against a clock, or a cancellation flag in a static `AtomicBool`. It
+    /// must be async-signal-safe if the iterator runs in a signal handler.
+    pub should_stop: Option<fn() -> bool>,
+}
+
+impl UnwindBudget {
+    /// A budget of at most `max_steps` steps.
+    pub fn with_max_steps(max_steps: usize) -> Self {
+        Self {
+            max_steps: Some(max_steps),
+            should_stop: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BudgetState {
+    budget: UnwindBudget,
+    used_steps: usize,
+    exhausted: bool,
+}
+
+impl BudgetState {
+    /// Account for one step. Fails with [`Error::Timeout`] if the budget is exhausted.
+    fn step(&mut self) -> Result<(), Error> {
+        if !self.exhausted {
+            let steps_exhausted = self
+                .budget
+                .max_steps
+                .is_some_and(|max_steps| self.used_steps >= max_steps);
+            let stopped = self
+                .budget
+                .should_stop
+                .is_some_and(|should_stop| should_stop());
+            self.exhausted = steps_exhausted || stopped;
+            self.used_steps += 1;
+        }
+        match self.exhausted {
+            true => Err(Error::Timeout),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Counts the memory reads of a frame against the budget of an [`UnwindIterator`].
+struct BudgetedReader<'a, F: MemoryReader> {
+    inner: &'a mut F,
+    budget: &'a mut BudgetState,
+}
+
+impl<F: MemoryReader> MemoryReader for BudgetedReader<'_, F> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        self.budget.step().map_err(|_| MemoryReadError::Unknown)?;
+        self.inner.read_u64(address)
+    }
+
+    fn read_u32(&mut self, address: u64) -> Result<u32, MemoryReadError> {
+        self.budget.step().map_err(|_| MemoryReadError::Unknown)?;
+        self.inner.read_u32(address)
+    }
+
+    fn read_block(&mut self, address: u64, buf: &mut [u8]) -> Result<(), MemoryReadError> {
+        self.budget.step().map_err(|_| MemoryReadError::Unknown)?;
+        self.inner.read_block(address, buf)
+    }
+
+    fn is_executable(&mut self, address: u64) -> Option<bool> {
+        self.inner.is_executable(address)
+    }
+
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        self.inner.snapshot_range()
+    }
+}
+
+/// Describes the frames which an [`UnwindIterator`] left out because of its
+/// [`DepthLimit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TruncationSummary {
+    /// The number of frames which were unwound but not yielded. If `root_reached` is
+    /// false, the stack has at least this many more frames.
+    pub omitted_frame_count: usize,
+    /// Whether unwinding reached the root function while counting the left-out frames.
+    pub root_reached: bool,
+}
+
+/// The frames of a stack, created with [`UnwindIterator::collect_stack`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackResult {
+    /// The unwound frames. The first frame is the instruction pointer, unless frames
+    /// were skipped with [`UnwindIterator::skip_frames`].
+    pub frames: Vec<FrameAddress>,
+    /// Why unwinding stopped after the last frame in `frames`.
+    pub end_state: StackEndState,
+}
+
+/// The reason why [`UnwindIterator::collect_stack`] stopped unwinding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackEndState {
+    /// The root function was reached, so the stack is complete.
+    Root,
+    /// A frame of a translator such as Rosetta 2 was reached, see
+    /// [`Module::is_rosetta`]. The rest of the stack is in translated code, which
+    /// framehop can't unwind, so the stack is incomplete.
+    TranslationBoundary,
+    /// The WOW64 transition frame of a 32-bit thread on 64-bit Windows was reached, see
+    /// [`Module::is_wow64_transition`]. The caller is 32-bit code, which framehop can't
+    /// unwind, so the stack is incomplete.
+    Wow64Transition,
+    /// Unwinding the last frame failed, so the stack is probably truncated.
+    Error(Error),
+    /// The maximum number of frames was reached, either the one passed to
+    /// `collect_stack` or the one set with [`UnwindIterator::with_depth_limit`].
+    MaxDepth,
+}
+
+/// The saved state of an [`UnwindIterator`], created with [`UnwindIterator::checkpoint`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindIteratorCheckpoint<R> {
+    state: UnwindIteratorState,
+    regs: R,
+    frames_to_skip: usize,
+    depth_limit: Option<DepthLimit>,
+    yielded_frame_count: usize,
+    fingerprint: StackFingerprint,
+    confidence: FrameConfidence,
+}
+
+impl<R> UnwindIteratorCheckpoint<R> {
+    /// The register values at the checkpoint.
+    pub fn regs(&self) -> &R {
+        &self.regs
+    }
+
+    /// Whether the iterator had already reached the end of the stack.
+    pub fn is_done(&self) -> bool {
+        self.state == UnwindIteratorState::Done
+    }
+}
+
+/// A module which is loaded in the process, as passed to [`Unwinder::sync_modules`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleDescriptor {
+    /// The name or file path of the module.
+    pub name: String,
+    /// The address range where the module is mapped into the process.
+    pub avma_range: Range<u64>,
+    /// The code ID of the module, if known, e.g. the ELF build ID.
+    pub code_id: Option<CodeId>,
+}
+
+/// The outcome of a call to [`Unwinder::sync_modules`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncModulesOutcome {
+    /// The number of modules which were loaded and added.
+    pub added_module_count: usize,
+    /// The number of modules which were removed.
+    pub removed_module_count: usize,
+    /// The number of existing modules which were kept.
+    pub kept_module_count: usize,
+}
+
+/// The outcome of a call to [`Unwinder::add_module`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddModuleOutcome {
+    /// The module was added. It did not overlap with any existing module.
+    Added,
+    /// The module was added, and it replaced one or more existing modules whose
+    /// address ranges overlapped with the new module's address range.
+    ReplacedOverlapping {
+        /// The number of modules which were removed.
+        removed_module_count: usize,
+    },
+}
+
 /// This global generation counter makes it so that the cache can be shared
 /// between multiple unwinders.
 /// This is a u16, so if you make it wrap around by adding / removing modules
@@ -202,112 +996,506 @@ fn next_global_modules_generation() -> u16 {
     GLOBAL_MODULES_GENERATION.fetch_add(1, Ordering::Relaxed)
 }
 
+/// [`GoUnwinding`] if the `go` feature is enabled. Otherwise implemented by all types.
+#[cfg(feature = "go")]
+pub trait MaybeGoUnwinding: GoUnwinding {}
+#[cfg(feature = "go")]
+impl<T: GoUnwinding> MaybeGoUnwinding for T {}
+#[cfg(not(feature = "go"))]
+pub trait MaybeGoUnwinding {}
+#[cfg(not(feature = "go"))]
+impl<T> MaybeGoUnwinding for T {}
+
 cfg_if::cfg_if! {
     if #[cfg(all(feature = "macho", feature = "pe"))] {
         pub trait Unwinding:
-            Arch + DwarfUnwinding + InstructionAnalysis + CompactUnwindInfoUnwinding + PeUnwinding {}
-        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + CompactUnwindInfoUnwinding + PeUnwinding>
+            Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + CompactUnwindInfoUnwinding + PeUnwinding {}
+        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + CompactUnwindInfoUnwinding + PeUnwinding>
             Unwinding for T {}
     } else if #[cfg(feature = "macho")] {
         pub trait Unwinding:
-            Arch + DwarfUnwinding + InstructionAnalysis + CompactUnwindInfoUnwinding {}
-        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + CompactUnwindInfoUnwinding> Unwinding for T {}
+            Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + CompactUnwindInfoUnwinding {}
+        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + CompactUnwindInfoUnwinding> Unwinding for T {}
     } else if #[cfg(feature = "pe")] {
         pub trait Unwinding:
-            Arch + DwarfUnwinding + InstructionAnalysis  + PeUnwinding {}
-        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + PeUnwinding> Unwinding for T {}
+            Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + PeUnwinding {}
+        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding + PeUnwinding> Unwinding for T {}
     } else {
-        pub trait Unwinding: Arch + DwarfUnwinding + InstructionAnalysis {}
-        impl<T: Arch + DwarfUnwinding + InstructionAnalysis> Unwinding for T {}
+        pub trait Unwinding: Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding {}
+        impl<T: Arch + DwarfUnwinding + InstructionAnalysis + MaybeGoUnwinding> Unwinding for T {}
     }
 }
 
-pub struct UnwinderInternal<D, A, P> {
+pub struct UnwinderInternal<D, A: Arch, P> {
     /// sorted by avma_range.start
     modules: Vec<Module<D>>,
     /// Incremented every time modules is changed.
     modules_generation: u16,
+    /// Receives diagnostics about unusual situations.
+    diagnostics: DiagnosticsSink,
+    /// Stack-switch trampolines, in the order in which they were added.
+    stack_links: Vec<StackLink>,
+    /// The maximum number of bytes the modules should hold, see `set_memory_budget`.
+    memory_budget: Option<usize>,
+    /// The rule for addresses outside of any module, and for addresses whose unwind
+    /// information couldn't be used.
+    fallback_rule: A::UnwindRule,
+    /// What happens to frames which would be unwound with the fallback rule.
+    fallback_policy: FallbackPolicy,
+    /// Whether frames are unwound with the frame pointer without looking up any unwind
+    /// information, see `set_frame_pointer_only`.
+    frame_pointer_only: bool,
+    /// In frame-pointer-only mode, every frame whose number is a multiple of this
+    /// interval is also unwound with the unwind information of its module, see
+    /// `set_frame_pointer_verification_interval`.
+    frame_pointer_verification_interval: Option<NonZeroU64>,
+    /// How instruction pointers are adjusted before looking up their unwind information.
+    instruction_pointer_adjustment: InstructionPointerAdjustment,
+    /// Address ranges (AVMAs) of root functions, in which unwinding stops.
+    root_address_ranges: Vec<Range<u64>>,
+    /// Stack pointer values at which unwinding stops, e.g. the top of a thread's stack.
+    stack_end_sentinels: Vec<u64>,
+    /// Tagged address ranges (AVMAs) before whose frames a marker is yielded.
+    marker_ranges: Vec<(Range<u64>, u32)>,
+    /// Address ranges (AVMAs) of JIT code, which are unwound according to their hint.
+    jit_ranges: JitRanges,
+    /// Callbacks which unwind frames in their address ranges (AVMAs), in the order in
+    /// which they were added.
+    foreign_unwinders: Vec<ForeignUnwinder<A::UnwindRegs>>,
     _arch: PhantomData<A>,
     _allocation_policy: PhantomData<P>,
 }
 
-impl<D, A, P> Default for UnwinderInternal<D, A, P> {
+impl<D, A: Arch, P> Default for UnwinderInternal<D, A, P> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl<D, A, P> Clone for UnwinderInternal<D, A, P> {
+impl<D, A: Arch, P> Clone for UnwinderInternal<D, A, P> {
     fn clone(&self) -> Self {
         Self {
             modules: self.modules.clone(),
             modules_generation: self.modules_generation,
+            diagnostics: self.diagnostics.clone(),
+            stack_links: self.stack_links.clone(),
+            memory_budget: self.memory_budget,
+            fallback_rule: self.fallback_rule,
+            fallback_policy: self.fallback_policy,
+            frame_pointer_only: self.frame_pointer_only,
+            frame_pointer_verification_interval: self.frame_pointer_verification_interval,
+            instruction_pointer_adjustment: self.instruction_pointer_adjustment,
+            root_address_ranges: self.root_address_ranges.clone(),
+            stack_end_sentinels: self.stack_end_sentinels.clone(),
+            marker_ranges: self.marker_ranges.clone(),
+            jit_ranges: self.jit_ranges.clone(),
+            foreign_unwinders: self.foreign_unwinders.clone(),
             _arch: PhantomData,
             _allocation_policy: PhantomData,
         }
     }
 }
 
-impl<D, A, P> UnwinderInternal<D, A, P> {
+impl<D, A: Arch, P> UnwinderInternal<D, A, P> {
     pub fn new() -> Self {
         Self {
             modules: Vec::new(),
             modules_generation: next_global_modules_generation(),
+            diagnostics: DiagnosticsSink::default(),
+            stack_links: Vec::new(),
+            memory_budget: None,
+            fallback_rule: A::UnwindRule::fallback_rule(),
+            fallback_policy: FallbackPolicy::UseFallback,
+            frame_pointer_only: false,
+            frame_pointer_verification_interval: None,
+            instruction_pointer_adjustment: InstructionPointerAdjustment::None,
+            root_address_ranges: Vec::new(),
+            stack_end_sentinels: Vec::new(),
+            marker_ranges: Vec::new(),
+            jit_ranges: JitRanges::default(),
+            foreign_unwinders: Vec::new(),
             _arch: PhantomData,
             _allocation_policy: PhantomData,
         }
     }
-}
 
-impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInternal<D, A, P> {
-    pub fn add_module(&mut self, module: Module<D>) {
-        let insertion_index = match self
-            .modules
-            .binary_search_by_key(&module.avma_range.start, |module| module.avma_range.start)
-        {
-            Ok(i) => {
-                #[cfg(feature = "std")]
-                eprintln!(
-                    "Now we have two modules at the same start address 0x{:x}. This can't be good.",
-                    module.avma_range.start
-                );
-                i
-            }
-            Err(i) => i,
-        };
-        self.modules.insert(insertion_index, module);
+    pub fn set_diagnostics_callback(&mut self, callback: Option<Arc<DiagnosticsCallback>>) {
+        self.diagnostics.set_callback(callback);
+    }
+
+    pub fn add_stack_link(&mut self, stack_link: StackLink) {
+        self.stack_links.push(stack_link);
+    }
+
+    pub fn clear_stack_links(&mut self) {
+        self.stack_links.clear();
+    }
+
+    pub fn set_fallback_rule(&mut self, rule: A::UnwindRule) {
+        self.fallback_rule = rule;
+        // The rule cache may contain the old fallback rule.
         self.modules_generation = next_global_modules_generation();
     }
 
-    pub fn remove_module(&mut self, module_address_range_start: u64) {
-        if let Ok(index) = self
-            .modules
-            .binary_search_by_key(&module_address_range_start, |module| {
-                module.avma_range.start
-            })
-        {
-            self.modules.remove(index);
-            self.modules_generation = next_global_modules_generation();
-        };
+    pub fn set_fallback_policy(&mut self, policy: FallbackPolicy) {
+        self.fallback_policy = policy;
     }
 
-    pub fn max_known_code_address(&self) -> u64 {
-        self.modules.last().map_or(0, |m| m.avma_range.end)
+    pub fn set_frame_pointer_only(&mut self, frame_pointer_only: bool) {
+        self.frame_pointer_only = frame_pointer_only;
     }
 
-    fn find_module_for_address(&self, address: u64) -> Option<(usize, u32)> {
-        let (module_index, module) = match self
-            .modules
-            .binary_search_by_key(&address, |m| m.avma_range.start)
-        {
-            Ok(i) => (i, &self.modules[i]),
-            Err(insertion_index) => {
-                if insertion_index == 0 {
-                    // address is before first known module
-                    return None;
-                }
-                let i = insertion_index - 1;
-                let module = &self.modules[i];
+    pub fn set_frame_pointer_verification_interval(&mut self, interval: Option<NonZeroU64>) {
+        self.frame_pointer_verification_interval = interval;
+    }
+
+    pub fn set_instruction_pointer_adjustment(&mut self, adjustment: InstructionPointerAdjustment) {
+        self.instruction_pointer_adjustment = adjustment;
+        // The rule cache may contain rules which were looked up with the old adjustment.
+        self.modules_generation = next_global_modules_generation();
+    }
+
+    pub fn add_root_address_range(&mut self, avma_range: Range<u64>) {
+        self.root_address_ranges.push(avma_range);
+    }
+
+    pub fn clear_root_address_ranges(&mut self) {
+        self.root_address_ranges.clear();
+    }
+
+    pub fn set_stack_end_sentinels(&mut self, sentinels: Vec<u64>) {
+        self.stack_end_sentinels = sentinels;
+    }
+
+    pub fn add_marker_range(&mut self, avma_range: Range<u64>, tag: u32) {
+        self.marker_ranges.push((avma_range, tag));
+    }
+
+    pub fn clear_marker_ranges(&mut self) {
+        self.marker_ranges.clear();
+    }
+
+    pub fn marker_for_address(&self, address: u64) -> Option<u32> {
+        self.marker_ranges
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+            .map(|&(_, tag)| tag)
+    }
+
+    pub fn set_jit_ranges(&mut self, ranges: Vec<JitRange>) {
+        self.jit_ranges.set(ranges);
+    }
+
+    pub fn add_jit_range(&mut self, range: JitRange) {
+        self.jit_ranges.add(range);
+    }
+
+    pub fn clear_jit_ranges(&mut self) {
+        self.jit_ranges.clear();
+    }
+
+    pub fn add_foreign_unwinder(&mut self, foreign_unwinder: ForeignUnwinder<A::UnwindRegs>) {
+        self.foreign_unwinders.push(foreign_unwinder);
+    }
+
+    pub fn clear_foreign_unwinders(&mut self) {
+        self.foreign_unwinders.clear();
+    }
+
+    fn is_root_address(&self, address: u64) -> bool {
+        self.root_address_ranges
+            .iter()
+            .any(|range| range.contains(&address))
+    }
+
+    fn lookup_address(&self, address: FrameAddress) -> u64 {
+        self.instruction_pointer_adjustment.lookup_address(address)
+    }
+
+    fn foreign_unwinder_for_address(
+        &self,
+        address: u64,
+    ) -> Option<&ForeignUnwinder<A::UnwindRegs>> {
+        self.foreign_unwinders
+            .iter()
+            .find(|foreign_unwinder| foreign_unwinder.avma_range.contains(&address))
+    }
+
+    fn stack_link_rule_for_address(&self, address: u64) -> Option<StackLinkRule> {
+        self.stack_links
+            .iter()
+            .find(|stack_link| stack_link.avma_range.contains(&address))
+            .map(|stack_link| stack_link.rule)
+    }
+}
+
+impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInternal<D, A, P> {
+    pub fn add_module(&mut self, module: Module<D>) -> AddModuleOutcome {
+        let removed_module_count = self.insert_module(module);
+        self.modules_generation = next_global_modules_generation();
+        self.enforce_memory_budget();
+        if removed_module_count == 0 {
+            AddModuleOutcome::Added
+        } else {
+            AddModuleOutcome::ReplacedOverlapping {
+                removed_module_count,
+            }
+        }
+    }
+
+    pub fn set_modules(&mut self, modules: Vec<Module<D>>) {
+        self.modules.clear();
+        for module in modules {
+            self.insert_module(module);
+        }
+        self.modules_generation = next_global_modules_generation();
+        self.enforce_memory_budget();
+    }
+
+    pub fn sync_modules<I, L>(&mut self, current: I, mut load: L) -> SyncModulesOutcome
+    where
+        I: IntoIterator<Item = ModuleDescriptor>,
+        L: FnMut(&ModuleDescriptor) -> Option<Module<D>>,
+    {
+        let old_starts: Vec<u64> = self.modules.iter().map(|m| m.avma_range.start).collect();
+        let mut old_modules: Vec<Option<Module<D>>> = core::mem::take(&mut self.modules)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut modules = Vec::new();
+        let mut outcome = SyncModulesOutcome::default();
+        for descriptor in current {
+            let existing = old_starts
+                .binary_search(&descriptor.avma_range.start)
+                .ok()
+                .and_then(|index| {
+                    let slot = &mut old_modules[index];
+                    match slot {
+                        Some(module) if module.matches_descriptor(&descriptor) => slot.take(),
+                        _ => None,
+                    }
+                });
+            if let Some(module) = existing {
+                outcome.kept_module_count += 1;
+                modules.push(module);
+            } else if let Some(module) = load(&descriptor) {
+                outcome.added_module_count += 1;
+                modules.push(module);
+            }
+        }
+        outcome.removed_module_count = old_starts.len() - outcome.kept_module_count;
+        if outcome.added_module_count == 0 && outcome.removed_module_count == 0 {
+            // All modules were kept, so they don't overlap. Keep the generation, so that
+            // the cached rules stay valid.
+            modules.sort_unstable_by_key(|module| module.avma_range.start);
+            self.modules = modules;
+        } else {
+            self.set_modules(modules);
+        }
+        outcome
+    }
+
+    /// Insert the module into the sorted list, replacing the modules which overlap with
+    /// it. Returns the number of replaced modules. The caller has to update the
+    /// generation.
+    fn insert_module(&mut self, module: Module<D>) -> usize {
+        // The modules don't overlap, so they're sorted by both start and end address.
+        // Find the range of existing modules which overlap with the new module.
+        let Range { start, end } = module.avma_range.clone();
+        let overlap_start = self.modules.partition_point(|m| m.avma_range.end <= start);
+        let overlap_end = self
+            .modules
+            .partition_point(|m| m.avma_range.start < end || m.avma_range.start == start)
+            .max(overlap_start);
+        let removed_module_count = overlap_end - overlap_start;
+        if removed_module_count != 0 {
+            self.diagnostics
+                .emit(Diagnostic::OverlappingModulesReplaced {
+                    module_name: &module.name,
+                    avma_range: module.avma_range.clone(),
+                    removed_module_count,
+                });
+        }
+        self.modules
+            .splice(overlap_start..overlap_end, core::iter::once(module));
+        removed_module_count
+    }
+
+    pub fn remove_module(&mut self, module_address_range_start: u64) {
+        if let Ok(index) = self
+            .modules
+            .binary_search_by_key(&module_address_range_start, |module| {
+                module.avma_range.start
+            })
+        {
+            self.modules.remove(index);
+            self.modules_generation = next_global_modules_generation();
+        };
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        // Modules which share their sections with clones are only counted once.
+        let mut modules: Vec<_> = self
+            .modules
+            .iter()
+            .map(|module| (Arc::as_ptr(&module.sections), module))
+            .collect();
+        modules.sort_unstable_by_key(|(sections, _)| *sections);
+        modules.dedup_by_key(|(sections, _)| *sections);
+        modules
+            .iter()
+            .map(|(_, module)| module.memory_usage().total())
+            .sum()
+    }
+
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.memory_budget = budget;
+        self.enforce_memory_budget();
+    }
+
+    /// Load the lazily loaded modules which contain any of `addresses`, see
+    /// [`Module::load`]. Returns the number of modules which weren't loaded before.
+    #[cfg(feature = "std")]
+    pub fn load_modules_for(&mut self, addresses: impl IntoIterator<Item = u64>) -> usize {
+        let mut loaded_count = 0;
+        for address in addresses {
+            match self.module_for_address(address) {
+                Some(module) if !module.is_loaded() => {
+                    module.load();
+                    loaded_count += 1;
+                }
+                _ => {}
+            }
+        }
+        // The loaded modules are counted against the budget now.
+        if loaded_count > 0 {
+            self.enforce_memory_budget();
+        }
+        loaded_count
+    }
+
+    /// Drop the text bytes of modules, in address order, until the modules hold no more
+    /// than the memory budget. The text bytes are only needed for instruction analysis,
+    /// so unwinding keeps working with slightly worse results in function prologues and
+    /// epilogues. If that isn't enough, a diagnostic is emitted.
+    fn enforce_memory_budget(&mut self) {
+        let Some(budget) = self.memory_budget else {
+            return;
+        };
+        let mut memory_usage = self.memory_usage();
+        for module in &mut self.modules {
+            if memory_usage <= budget {
+                break;
+            }
+            memory_usage -= module.drop_optional_data();
+        }
+        if memory_usage > budget {
+            self.diagnostics.emit(Diagnostic::MemoryBudgetExceeded {
+                memory_usage,
+                budget,
+            });
+        }
+    }
+
+    pub fn max_known_code_address(&self) -> u64 {
+        self.modules.last().map_or(0, |m| m.avma_range.end)
+    }
+
+    pub fn modules_generation(&self) -> u16 {
+        self.modules_generation
+    }
+
+    pub fn precompute_rules<I>(
+        &self,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        addresses: I,
+        regs: &A::UnwindRegs,
+    ) -> usize
+    where
+        I: IntoIterator<Item = FrameAddress>,
+    {
+        let mut stored_rule_count = 0;
+        for address in addresses {
+            let lookup_address = self.lookup_address(address);
+            let cache_handle = match cache
+                .rule_cache
+                .lookup(lookup_address, self.modules_generation)
+            {
+                CacheResult::Hit { .. } => continue,
+                CacheResult::Miss(handle) => handle,
+            };
+            let (unwind_rule, is_fallback) = match self.find_module_for_address(lookup_address) {
+                None => (self.fallback_rule, true),
+                Some((module_index, relative_lookup_address)) => {
+                    let module = &self.modules[module_index];
+                    if module.is_rosetta || module.is_wow64_transition {
+                        // Unwinding stops at these frames without a rule, see
+                        // lookup_rule.
+                        continue;
+                    }
+                    // Cacheable rules don't depend on the register values, so dummy values
+                    // can be used here.
+                    match Self::unwind_frame_impl(
+                        module,
+                        address,
+                        relative_lookup_address,
+                        regs,
+                        cache,
+                        &mut Tracer::disabled(),
+                    ) {
+                        Ok(UnwindResult::ExecRule(rule)) => (rule, false),
+                        Ok(UnwindResult::Uncacheable(_)) => continue,
+                        Err(_) => match Self::rule_for_plt_stub(
+                            module,
+                            address,
+                            relative_lookup_address,
+                            &mut cache.unwind_stats,
+                        ) {
+                            Some(rule) => (rule, false),
+                            None => continue,
+                        },
+                    }
+                }
+            };
+            cache
+                .rule_cache
+                .insert(cache_handle, unwind_rule, is_fallback);
+            stored_rule_count += 1;
+        }
+        stored_rule_count
+    }
+
+    pub fn module_for_address(&self, address: u64) -> Option<&Module<D>> {
+        let (module_index, _) = self.find_module_for_address(address)?;
+        Some(&self.modules[module_index])
+    }
+
+    pub fn modules(&self) -> &[Module<D>] {
+        &self.modules
+    }
+
+    pub fn module_relative_address(&self, address: FrameAddress) -> Option<(Option<&CodeId>, u32)> {
+        let module = self.module_for_address(address.address_for_lookup())?;
+        let relative_address = address.address().checked_sub(module.base_avma)?;
+        Some((
+            module.code_id.as_ref(),
+            u32::try_from(relative_address).ok()?,
+        ))
+    }
+
+    fn find_module_for_address(&self, address: u64) -> Option<(usize, u32)> {
+        let (module_index, module) = match self
+            .modules
+            .binary_search_by_key(&address, |m| m.avma_range.start)
+        {
+            Ok(i) => (i, &self.modules[i]),
+            Err(insertion_index) => {
+                if insertion_index == 0 {
+                    // address is before first known module
+                    return None;
+                }
+                let i = insertion_index - 1;
+                let module = &self.modules[i];
                 if module.avma_range.end <= address {
                     // address is after this module
                     return None;
@@ -323,90 +1511,415 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
         Some((module_index, relative_address))
     }
 
-    fn with_cache<F, G>(
-        &self,
+    /// Looks up the rule for the frame at `address` in the cache, or in the unwind
+    /// information of its module.
+    fn lookup_rule<'u>(
+        &'u self,
         address: FrameAddress,
-        regs: &mut A::UnwindRegs,
-        cache: &mut Cache<A::UnwindRule, P>,
-        read_stack: &mut F,
-        callback: G,
-    ) -> Result<Option<u64>, Error>
-    where
-        F: FnMut(u64) -> Result<u64, ()>,
-        G: FnOnce(
-            &Module<D>,
-            FrameAddress,
-            u32,
-            &mut A::UnwindRegs,
-            &mut Cache<A::UnwindRule, P>,
-            &mut F,
-        ) -> Result<UnwindResult<A::UnwindRule>, UnwinderError>,
-    {
-        let lookup_address = address.address_for_lookup();
-        let is_first_frame = !address.is_return_address();
+        regs: &A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        mut info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> RuleLookup<'u, A, P> {
+        let lookup_address = self.lookup_address(address);
+        if let Some(info) = info.as_deref_mut() {
+            *info = FrameUnwindInfo::default();
+        }
         let cache_handle = match cache
             .rule_cache
             .lookup(lookup_address, self.modules_generation)
         {
-            CacheResult::Hit(unwind_rule) => {
-                return unwind_rule.exec(is_first_frame, regs, read_stack);
+            CacheResult::Hit {
+                rule: unwind_rule,
+                is_fallback,
+            } => {
+                trace_event!(tracer, CacheHit { rule: unwind_rule });
+                if is_fallback {
+                    if let Some(result) =
+                        self.apply_fallback_policy(self.cached_fallback_reason(lookup_address))
+                    {
+                        return RuleLookup::Done(result);
+                    }
+                }
+                if let Some(info) = info {
+                    info.from_cache = true;
+                }
+                return RuleLookup::Rule {
+                    rule: unwind_rule,
+                    is_fallback,
+                };
             }
             CacheResult::Miss(handle) => handle,
         };
+        trace_event!(tracer, CacheMiss);
 
-        let unwind_rule = match self.find_module_for_address(lookup_address) {
-            None => A::UnwindRule::fallback_rule(),
-            Some((module_index, relative_lookup_address)) => {
-                let module = &self.modules[module_index];
-                match callback(
-                    module,
-                    address,
+        #[cfg(feature = "return-address-predictor")]
+        if let Some(rule) = cache
+            .predictor
+            .predict(lookup_address, self.modules_generation)
+        {
+            trace_event!(tracer, ExecRule { rule });
+            // The prediction isn't checked against the module's unwind information, so
+            // the caller is trusted like one found with the fallback rule.
+            return RuleLookup::Rule {
+                rule,
+                is_fallback: true,
+            };
+        }
+
+        let Some((module_index, relative_lookup_address)) =
+            self.find_module_for_address(lookup_address)
+        else {
+            trace_event!(tracer, NoModule { lookup_address });
+            cache.unwind_stats.fallback_count += 1;
+            self.diagnostics
+                .emit(Diagnostic::UsedFallbackRuleOutsideModules { address });
+            let rule = (self.fallback_rule, Some(FallbackReason::NoModule));
+            return self.start_new_rule(cache_handle, rule, cache, info, tracer);
+        };
+        let module = &self.modules[module_index];
+        trace_event!(
+            tracer,
+            Module {
+                module_name: module.name.clone(),
+                relative_lookup_address,
+                unwind_data_kind: module.unwind_data_kind(),
+            }
+        );
+        if module.is_rosetta {
+            // The native stack continues in translated x86_64 code, which can't be
+            // unwound with aarch64 rules. No rule is ever cached for these modules, so
+            // every stack which reaches this frame gets the marker.
+            if let Some(info) = info {
+                info.reached_translation_boundary = true;
+            }
+            return RuleLookup::Done(Ok(None));
+        }
+        if module.is_wow64_transition {
+            // The caller runs in 32-bit mode, with a different stack layout and
+            // register width. Like for Rosetta, nothing is cached.
+            if let Some(info) = info {
+                info.reached_wow64_transition = true;
+            }
+            return RuleLookup::Done(Ok(None));
+        }
+        let rule = match Self::unwind_frame_impl(
+            module,
+            address,
+            relative_lookup_address,
+            regs,
+            cache,
+            tracer,
+        ) {
+            Ok(UnwindResult::ExecRule(rule)) => (rule, None),
+            Ok(UnwindResult::Uncacheable(evaluation)) => {
+                return RuleLookup::Evaluation {
+                    evaluation,
+                    cache_handle,
+                    module_index,
                     relative_lookup_address,
-                    regs,
-                    cache,
-                    read_stack,
-                ) {
-                    Ok(UnwindResult::ExecRule(rule)) => rule,
-                    Ok(UnwindResult::Uncacheable(return_address)) => {
-                        return Ok(Some(return_address))
-                    }
-                    Err(_err) => {
-                        // eprintln!("Unwinder error: {}", err);
-                        A::UnwindRule::fallback_rule()
-                    }
-                }
+                };
+            }
+            Err(error) => self.rule_after_error(
+                module,
+                address,
+                relative_lookup_address,
+                error,
+                &mut cache.unwind_stats,
+                info.as_deref_mut(),
+                tracer,
+            ),
+        };
+        self.start_new_rule(cache_handle, rule, cache, info, tracer)
+    }
+
+    /// Store a rule which was just found for a frame in the cache, and return it for
+    /// execution. `rule` is the rule and the reason why it is the fallback rule, if it is.
+    fn start_new_rule<'u>(
+        &'u self,
+        cache_handle: CacheHandle,
+        (unwind_rule, fallback_reason): (A::UnwindRule, Option<FallbackReason>),
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> RuleLookup<'u, A, P> {
+        let is_fallback = fallback_reason.is_some();
+        cache
+            .rule_cache
+            .insert(cache_handle, unwind_rule, is_fallback);
+        if let Some(reason) = fallback_reason {
+            if let Some(info) = info {
+                info.fallback_reason = Some(reason);
             }
+            if let Some(result) = self.apply_fallback_policy(reason) {
+                return RuleLookup::Done(result);
+            }
+        }
+        trace_event!(tracer, ExecRule { rule: unwind_rule });
+        RuleLookup::Rule {
+            rule: unwind_rule,
+            is_fallback,
+        }
+    }
+
+    /// The rule for the frame at `address` if the unwind information of its module
+    /// couldn't be used, and the reason why it's the fallback rule.
+    #[allow(clippy::too_many_arguments)]
+    fn rule_after_error(
+        &self,
+        module: &Module<D>,
+        address: FrameAddress,
+        relative_lookup_address: u32,
+        error: UnwinderError,
+        stats: &mut UnwindStats,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> (A::UnwindRule, Option<FallbackReason>) {
+        if let Some(rule) = Self::rule_for_plt_stub(module, address, relative_lookup_address, stats)
+        {
+            return (rule, None);
+        }
+        trace_event!(tracer, UnwindInfoError { error });
+        stats.fallback_after_error_count += 1;
+        self.diagnostics
+            .emit(Diagnostic::UsedFallbackRuleAfterError {
+                module_name: &module.name,
+                address,
+                error,
+            });
+        let reason = match error {
+            UnwinderError::NoModuleUnwindData => FallbackReason::NoUnwindData,
+            _ => FallbackReason::UnwindInfoError,
         };
-        cache.rule_cache.insert(cache_handle, unwind_rule);
-        unwind_rule.exec(is_first_frame, regs, read_stack)
+        if let Some(info) = info {
+            info.error_details = Some(UnwindErrorDetails {
+                module_name: module.name.clone(),
+                module_avma_range: module.avma_range.clone(),
+                unwind_data_kind: module.unwind_data_kind(),
+                lookup_address: self.lookup_address(address),
+                relative_lookup_address,
+                error,
+            });
+        }
+        (self.fallback_rule, Some(reason))
+    }
+
+    /// The rule for a first frame in the PLT, whose unwind information couldn't be used.
+    fn rule_for_plt_stub(
+        module: &Module<D>,
+        address: FrameAddress,
+        rel_lookup_address: u32,
+        stats: &mut UnwindStats,
+    ) -> Option<A::UnwindRule> {
+        if address.is_return_address()
+            || !module.is_in_plt(module.base_svma.wrapping_add(rel_lookup_address as u64))
+        {
+            return None;
+        }
+        // The PLT isn't covered by the module's unwind information. PLT entries
+        // jump to the target function without touching the stack.
+        stats.plt_stub_count += 1;
+        Some(A::UnwindRule::rule_for_stub_functions())
+    }
+
+    /// The result for a frame which would be unwound with the fallback rule, or `None` if
+    /// the fallback rule should be used.
+    fn apply_fallback_policy(&self, reason: FallbackReason) -> Option<Result<Option<u64>, Error>> {
+        match self.fallback_policy {
+            FallbackPolicy::UseFallback => None,
+            FallbackPolicy::StopStack => Some(Ok(None)),
+            FallbackPolicy::ReturnError => Some(Err(Error::UnreliableFrame(reason))),
+        }
+    }
+
+    /// Why the fallback rule was cached for `lookup_address`. The cache doesn't keep the
+    /// reason, so it's derived from the module again.
+    fn cached_fallback_reason(&self, lookup_address: u64) -> FallbackReason {
+        match self.find_module_for_address(lookup_address) {
+            None => FallbackReason::NoModule,
+            Some((module_index, _)) => match self.modules[module_index].unwind_data_kind() {
+                UnwindDataKind::None => FallbackReason::NoUnwindData,
+                _ => FallbackReason::UnwindInfoError,
+            },
+        }
+    }
+
+    /// A return address which was found with the fallback rule is likely correct if
+    /// it's in a known module or JIT range, and a guess otherwise.
+    fn confidence_for_result(
+        &self,
+        is_fallback: bool,
+        result: &Result<Option<u64>, Error>,
+    ) -> FrameConfidence {
+        match result {
+            Ok(Some(return_address)) if is_fallback => {
+                let lookup_address = return_address.wrapping_sub(1);
+                if self.find_module_for_address(lookup_address).is_some()
+                    || self.jit_ranges.hint_for_address(lookup_address).is_some()
+                {
+                    FrameConfidence::Likely
+                } else {
+                    FrameConfidence::Guessed
+                }
+            }
+            _ => FrameConfidence::Certain,
+        }
     }
 
     pub fn unwind_frame<F>(
         &self,
         address: FrameAddress,
         regs: &mut A::UnwindRegs,
-        cache: &mut Cache<A::UnwindRule, P>,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
         read_stack: &mut F,
+        mut info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
     ) -> Result<Option<u64>, Error>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
+        F: MemoryReader,
     {
-        self.with_cache(address, regs, cache, read_stack, Self::unwind_frame_impl)
+        let reads = &mut SyncReads::new(read_stack);
+        let mut unwind = FrameUnwind::new(self, address);
+        if let Some(mut cached) = unwind.start(regs, cache, info.as_deref_mut(), tracer) {
+            // Most frames are in module code, so their unwind is stepped here instead of
+            // being moved into `unwind`.
+            let result = loop {
+                match cached.step(self, regs, cache, info.as_deref_mut(), tracer, reads) {
+                    Step::Read(request) => {
+                        let value = reads.read(&request);
+                        cached.provide(value, reads);
+                    }
+                    Step::Done(result) => break result,
+                }
+            };
+            return unwind.finish(result, regs, info, tracer);
+        }
+        loop {
+            match unwind.step(regs, cache, info.as_deref_mut(), tracer, reads) {
+                FrameStep::Read(request) => {
+                    let value = reads.read(&request);
+                    unwind.provide(value, reads);
+                }
+                FrameStep::Foreign(callback) => {
+                    let result = callback(address, regs, reads.inner());
+                    unwind.provide_foreign_result(result);
+                }
+                FrameStep::Done(result) => return result,
+            }
+        }
     }
 
-    fn unwind_frame_impl<F>(
-        module: &Module<D>,
+    /// Set the confidence of a frame which was unwound with a rule that isn't from the
+    /// module's unwind information, and compare the result to `verification`, which is
+    /// the return address and the stack pointer from the module's unwind information.
+    fn finish_uncached_rule(
+        &self,
+        result: &Result<Option<u64>, Error>,
+        verification: Option<(u64, u64)>,
+        regs: &A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        info: Option<&mut FrameUnwindInfo>,
+    ) {
+        // These rules aren't from the module's unwind information, so the caller is
+        // trusted like one found with the fallback rule.
+        if let Some(info) = info {
+            info.confidence = self.confidence_for_result(true, result);
+        }
+        if let Some((return_address, sp)) = verification {
+            cache.unwind_stats.frame_pointer_verification_count += 1;
+            if *result != Ok(Some(return_address)) || regs.sp() != sp {
+                cache.unwind_stats.frame_pointer_mismatch_count += 1;
+            }
+        }
+    }
+
+    /// Check the result of a frame whose stack pointer was `sp` before it was unwound,
+    /// and fill in its frame size.
+    fn finish_frame(
+        &self,
+        result: Result<Option<u64>, Error>,
+        sp: u64,
+        switched_stacks: bool,
+        regs: &A::UnwindRegs,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> Result<Option<u64>, Error> {
+        let result = match result {
+            Ok(Some(_)) if self.stack_end_sentinels.contains(&regs.sp()) => Ok(None),
+            result => result,
+        };
+        if let (Some(info), Ok(Some(_)), false) = (info, result, switched_stacks) {
+            info.frame_size = regs.sp().checked_sub(sp);
+        }
+        trace_event!(
+            tracer,
+            End {
+                result,
+                regs: regs.clone(),
+            }
+        );
+        result
+    }
+
+    /// The unwind of the frame at `address`, for a
+    /// [`FrameUnwindStepper`].
+    pub fn frame_unwind_task(&self, address: FrameAddress) -> SteppedFrameUnwind<'_, D, A, P> {
+        SteppedFrameUnwind {
+            unwind: FrameUnwind::new(self, address),
+            rules: SteppedRules::new(),
+            result: None,
+        }
+    }
+
+    fn unwind_frame_impl<'u>(
+        module: &'u Module<D>,
         address: FrameAddress,
         rel_lookup_address: u32,
-        regs: &mut A::UnwindRegs,
-        cache: &mut Cache<A::UnwindRule, P>,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<A::UnwindRule>, UnwinderError>
-    where
-        F: FnMut(u64) -> Result<u64, ()>,
-    {
+        regs: &A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> Result<UnwindResult<A::UnwindRule, ModuleEvaluation<'u, A, P>>, UnwinderError> {
+        let svma = module.base_svma.wrapping_add(rel_lookup_address as u64);
+        if let Some(hint) = module.unwind_hint_for_svma(svma) {
+            return Ok(UnwindResult::ExecRule(A::UnwindRule::rule_for_hint(hint)));
+        }
+        if let Some(rule) = module.trampoline_rule_for_svma(svma, !address.is_return_address()) {
+            return Ok(UnwindResult::ExecRule(rule));
+        }
+        if module.frame_pointers_guaranteed && address.is_return_address() {
+            cache.unwind_stats.frame_pointer_count += 1;
+            return Ok(UnwindResult::ExecRule(A::UnwindRule::fallback_rule()));
+        }
+        #[cfg(feature = "go")]
+        if let Some(go_pclntab) = &module.sections.get().go_pclntab {
+            // Addresses outside of Go functions, e.g. in cgo code, use the other unwind data.
+            if let Some(frame) = go_pclntab.frame_for_svma(svma)? {
+                let rule =
+                    <A as GoUnwinding>::rule_for_go_frame(frame, !address.is_return_address())?;
+                cache.unwind_stats.go_pclntab_count += 1;
+                return Ok(UnwindResult::ExecRule(rule));
+            }
+        }
+        Self::unwind_frame_with_module_unwind_data(
+            module,
+            address,
+            rel_lookup_address,
+            regs,
+            cache,
+            tracer,
+        )
+    }
+
+    fn unwind_frame_with_module_unwind_data<'u>(
+        module: &'u Module<D>,
+        address: FrameAddress,
+        rel_lookup_address: u32,
+        regs: &A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> Result<UnwindResult<A::UnwindRule, ModuleEvaluation<'u, A, P>>, UnwinderError> {
         let is_first_frame = !address.is_return_address();
-        let unwind_result = match &*module.unwind_data {
+        let unwind_result = match &module.sections.get().unwind_data {
             #[cfg(feature = "macho")]
             ModuleUnwindDataInternal::CompactUnwindInfoAndEhFrame {
                 unwind_info,
@@ -414,9 +1927,9 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                 stubs_svma: stubs,
                 stub_helper_svma: stub_helper,
                 base_addresses,
+                cie_fixups,
                 text_data,
             } => {
-                // eprintln!("unwinding with cui and eh_frame in module {}", module.name);
                 let text_bytes = text_data.as_ref().and_then(|data| {
                     let offset_from_base =
                         u32::try_from(data.svma_range.start.checked_sub(module.base_svma)?).ok()?;
@@ -424,16 +1937,16 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                 });
                 let stubs_range = if let Some(stubs_range) = stubs {
                     (
-                        (stubs_range.start - module.base_svma) as u32,
-                        (stubs_range.end - module.base_svma) as u32,
+                        stubs_range.start.wrapping_sub(module.base_svma) as u32,
+                        stubs_range.end.wrapping_sub(module.base_svma) as u32,
                     )
                 } else {
                     (0, 0)
                 };
                 let stub_helper_range = if let Some(stub_helper_range) = stub_helper {
                     (
-                        (stub_helper_range.start - module.base_svma) as u32,
-                        (stub_helper_range.end - module.base_svma) as u32,
+                        stub_helper_range.start.wrapping_sub(module.base_svma) as u32,
+                        stub_helper_range.end.wrapping_sub(module.base_svma) as u32,
                     )
                 } else {
                     (0, 0)
@@ -445,9 +1958,43 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                     stub_helper_range,
                 );
 
-                let unwind_result = unwinder.unwind_frame(rel_lookup_address, is_first_frame)?;
+                let unwind_result = match (
+                    unwinder.unwind_frame(rel_lookup_address, is_first_frame, tracer),
+                    eh_frame.as_deref(),
+                ) {
+                    (
+                        Err(CompactUnwindInfoUnwinderError::InvalidFrameless),
+                        Some(eh_frame_data),
+                    ) => {
+                        // The entry's opcode is malformed, so it has no FDE offset
+                        // either. The function might still have an FDE in __eh_frame.
+                        let dwarf_unwinder = DwarfUnwinder::<_, A, _>::new(
+                            EndianSlice::new(eh_frame_data, LittleEndian),
+                            UnwindSectionType::EhFrame,
+                            None,
+                            &mut cache.gimli_unwind_context,
+                            base_addresses.clone(),
+                            module.base_svma,
+                        )
+                        .with_cie_fixups(cie_fixups);
+                        let fde_offset =
+                            dwarf_unwinder.find_fde_offset_with_linear_search(rel_lookup_address);
+                        trace_event!(tracer, InvalidCompactUnwindInfoEntry { fde_offset });
+                        let fde_offset =
+                            fde_offset.ok_or(CompactUnwindInfoUnwinderError::InvalidFrameless)?;
+                        CuiUnwindResult::NeedDwarf(fde_offset)
+                    }
+                    (result, _) => result?,
+                };
                 match unwind_result {
-                    CuiUnwindResult::ExecRule(rule) => UnwindResult::ExecRule(rule),
+                    CuiUnwindResult::ExecRule(rule) => {
+                        cache.unwind_stats.compact_unwind_info_count += 1;
+                        UnwindResult::ExecRule(rule)
+                    }
+                    CuiUnwindResult::ExecRuleFromInstructionAnalysis(rule) => {
+                        cache.unwind_stats.instruction_analysis_count += 1;
+                        UnwindResult::ExecRule(rule)
+                    }
                     CuiUnwindResult::NeedDwarf(fde_offset) => {
                         let eh_frame_data =
                             eh_frame.as_deref().ok_or(UnwinderError::NoDwarfData)?;
@@ -458,14 +2005,19 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                             &mut cache.gimli_unwind_context,
                             base_addresses.clone(),
                             module.base_svma,
-                        );
-                        dwarf_unwinder.unwind_frame_with_fde::<_, P::GimliEvaluationStorage<_>>(
-                            regs,
-                            is_first_frame,
-                            rel_lookup_address,
-                            fde_offset,
-                            read_stack,
-                        )?
+                        )
+                        .with_cie_fixups(cie_fixups);
+                        cache.unwind_stats.dwarf_evaluation_count += 1;
+                        let result = dwarf_unwinder
+                            .unwind_frame_with_fde::<P::GimliEvaluationStorage<_>>(
+                                regs,
+                                is_first_frame,
+                                rel_lookup_address,
+                                fde_offset,
+                                tracer,
+                            );
+                        Self::dwarf_result_or_cfi_gap(result, || None, &mut cache.unwind_stats)?
+                            .map_evaluation(ModuleEvaluation::Dwarf)
                     }
                 }
             }
@@ -473,6 +2025,8 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                 eh_frame_hdr,
                 eh_frame,
                 base_addresses,
+                cie_fixups,
+                text_data,
             } => {
                 let eh_frame_hdr_data = &eh_frame_hdr[..];
                 let mut dwarf_unwinder = DwarfUnwinder::<_, A, _>::new(
@@ -482,22 +2036,51 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                     &mut cache.gimli_unwind_context,
                     base_addresses.clone(),
                     module.base_svma,
-                );
-                let fde_offset = dwarf_unwinder
-                    .get_fde_offset_for_relative_address(rel_lookup_address)
-                    .ok_or(UnwinderError::EhFrameHdrCouldNotFindAddress)?;
-                dwarf_unwinder.unwind_frame_with_fde::<_, P::GimliEvaluationStorage<_>>(
-                    regs,
-                    is_first_frame,
+                )
+                .with_cie_fixups(cie_fixups);
+                let cfi_gap = |gap_start, gap_end| CfiGap {
+                    text_data: text_data.as_ref(),
+                    base_svma: module.base_svma,
                     rel_lookup_address,
-                    fde_offset,
-                    read_stack,
-                )?
+                    is_first_frame,
+                    gap_start,
+                    gap_end,
+                };
+                let Some(fde_offset) =
+                    dwarf_unwinder.get_fde_offset_for_relative_address(rel_lookup_address)
+                else {
+                    let gap_end =
+                        dwarf_unwinder.get_next_fde_start_for_relative_address(rel_lookup_address);
+                    return Self::rule_for_cfi_gap(
+                        &cfi_gap(None, gap_end),
+                        &mut cache.unwind_stats,
+                    )
+                    .map(UnwindResult::ExecRule)
+                    .ok_or(UnwinderError::EhFrameHdrCouldNotFindAddress);
+                };
+                cache.unwind_stats.dwarf_evaluation_count += 1;
+                let result = dwarf_unwinder.unwind_frame_with_fde::<P::GimliEvaluationStorage<_>>(
+                    regs,
+                    is_first_frame,
+                    rel_lookup_address,
+                    fde_offset,
+                    tracer,
+                );
+                let cfi_gap = || {
+                    Some(cfi_gap(
+                        dwarf_unwinder.get_fde_end_for_offset(fde_offset),
+                        dwarf_unwinder.get_next_fde_start_for_relative_address(rel_lookup_address),
+                    ))
+                };
+                Self::dwarf_result_or_cfi_gap(result, cfi_gap, &mut cache.unwind_stats)?
+                    .map_evaluation(ModuleEvaluation::Dwarf)
             }
             ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame {
                 index,
                 eh_frame,
                 base_addresses,
+                cie_fixups,
+                text_data,
             } => {
                 let mut dwarf_unwinder = DwarfUnwinder::<_, A, _>::new(
                     EndianSlice::new(eh_frame, LittleEndian),
@@ -506,22 +2089,48 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                     &mut cache.gimli_unwind_context,
                     base_addresses.clone(),
                     module.base_svma,
-                );
-                let fde_offset = index
-                    .fde_offset_for_relative_address(rel_lookup_address)
-                    .ok_or(UnwinderError::DwarfCfiIndexCouldNotFindAddress)?;
-                dwarf_unwinder.unwind_frame_with_fde::<_, P::GimliEvaluationStorage<_>>(
+                )
+                .with_cie_fixups(cie_fixups);
+                let cfi_gap = |gap_start, gap_end| CfiGap {
+                    text_data: text_data.as_ref(),
+                    base_svma: module.base_svma,
+                    rel_lookup_address,
+                    is_first_frame,
+                    gap_start,
+                    gap_end,
+                };
+                let Some(fde_offset) = index.lookup(rel_lookup_address) else {
+                    let gap_end = index.next_fde_start(rel_lookup_address);
+                    return Self::rule_for_cfi_gap(
+                        &cfi_gap(None, gap_end),
+                        &mut cache.unwind_stats,
+                    )
+                    .map(UnwindResult::ExecRule)
+                    .ok_or(UnwinderError::DwarfCfiIndexCouldNotFindAddress);
+                };
+                cache.unwind_stats.dwarf_evaluation_count += 1;
+                let result = dwarf_unwinder.unwind_frame_with_fde::<P::GimliEvaluationStorage<_>>(
                     regs,
                     is_first_frame,
                     rel_lookup_address,
                     fde_offset,
-                    read_stack,
-                )?
+                    tracer,
+                );
+                let cfi_gap = || {
+                    Some(cfi_gap(
+                        dwarf_unwinder.get_fde_end_for_offset(fde_offset),
+                        index.next_fde_start(rel_lookup_address),
+                    ))
+                };
+                Self::dwarf_result_or_cfi_gap(result, cfi_gap, &mut cache.unwind_stats)?
+                    .map_evaluation(ModuleEvaluation::Dwarf)
             }
             ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame {
                 index,
                 debug_frame,
                 base_addresses,
+                cie_fixups,
+                text_data,
             } => {
                 let mut dwarf_unwinder = DwarfUnwinder::<_, A, _>::new(
                     EndianSlice::new(debug_frame, LittleEndian),
@@ -530,17 +2139,41 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                     &mut cache.gimli_unwind_context,
                     base_addresses.clone(),
                     module.base_svma,
-                );
-                let fde_offset = index
-                    .fde_offset_for_relative_address(rel_lookup_address)
-                    .ok_or(UnwinderError::DwarfCfiIndexCouldNotFindAddress)?;
-                dwarf_unwinder.unwind_frame_with_fde::<_, P::GimliEvaluationStorage<_>>(
+                )
+                .with_cie_fixups(cie_fixups);
+                let cfi_gap = |gap_start, gap_end| CfiGap {
+                    text_data: text_data.as_ref(),
+                    base_svma: module.base_svma,
+                    rel_lookup_address,
+                    is_first_frame,
+                    gap_start,
+                    gap_end,
+                };
+                let Some(fde_offset) = index.lookup(rel_lookup_address) else {
+                    let gap_end = index.next_fde_start(rel_lookup_address);
+                    return Self::rule_for_cfi_gap(
+                        &cfi_gap(None, gap_end),
+                        &mut cache.unwind_stats,
+                    )
+                    .map(UnwindResult::ExecRule)
+                    .ok_or(UnwinderError::DwarfCfiIndexCouldNotFindAddress);
+                };
+                cache.unwind_stats.dwarf_evaluation_count += 1;
+                let result = dwarf_unwinder.unwind_frame_with_fde::<P::GimliEvaluationStorage<_>>(
                     regs,
                     is_first_frame,
                     rel_lookup_address,
                     fde_offset,
-                    read_stack,
-                )?
+                    tracer,
+                );
+                let cfi_gap = || {
+                    Some(cfi_gap(
+                        dwarf_unwinder.get_fde_end_for_offset(fde_offset),
+                        index.next_fde_start(rel_lookup_address),
+                    ))
+                };
+                Self::dwarf_result_or_cfi_gap(result, cfi_gap, &mut cache.unwind_stats)?
+                    .map_evaluation(ModuleEvaluation::Dwarf)
             }
             #[cfg(feature = "pe")]
             ModuleUnwindDataInternal::PeUnwindInfo {
@@ -548,22 +2181,858 @@ impl<D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> UnwinderInterna
                 rdata,
                 xdata,
                 text,
-            } => <A as PeUnwinding>::unwind_frame(
-                crate::pe::PeSections {
-                    pdata,
-                    rdata: rdata.as_ref(),
-                    xdata: xdata.as_ref(),
-                    text: text.as_ref(),
-                },
-                rel_lookup_address,
-                regs,
-                is_first_frame,
-                read_stack,
-            )?,
+            } => {
+                let unwind_result = <A as PeUnwinding>::unwind_frame(
+                    crate::pe::PeSections {
+                        pdata,
+                        rdata: rdata.as_ref(),
+                        xdata: xdata.as_ref(),
+                        text: text.as_ref(),
+                    },
+                    rel_lookup_address,
+                    is_first_frame,
+                )?;
+                if let UnwindResult::ExecRule(_) = unwind_result {
+                    // Evaluations are counted once they're done.
+                    cache.unwind_stats.pe_count += 1;
+                }
+                unwind_result.map_evaluation(ModuleEvaluation::Pe)
+            }
+            ModuleUnwindDataInternal::PrologueAnalysis {
+                text_data,
+                function_starts,
+            } => {
+                let rule = Self::rule_from_function_prologue(
+                    text_data,
+                    function_starts.as_deref(),
+                    module.base_svma.wrapping_add(rel_lookup_address as u64),
+                    is_first_frame,
+                )
+                .ok_or(UnwinderError::NoPrologueFound)?;
+                cache.unwind_stats.instruction_analysis_count += 1;
+                UnwindResult::ExecRule(rule)
+            }
             ModuleUnwindDataInternal::None => return Err(UnwinderError::NoModuleUnwindData),
         };
         Ok(unwind_result)
     }
+
+    /// Synthesizes a rule for a module without unwind information, by finding the start
+    /// of the function containing `lookup_svma` and analyzing the function's prologue.
+    fn rule_from_function_prologue(
+        text_data: &TextByteData<D>,
+        function_starts: Option<&[u64]>,
+        lookup_svma: u64,
+        is_first_frame: bool,
+    ) -> Option<A::UnwindRule> {
+        if !text_data.svma_range.contains(&lookup_svma) {
+            return None;
+        }
+        let text_bytes = &text_data.bytes[..];
+        let pc_offset = (lookup_svma - text_data.svma_range.start) as usize;
+        let (function_start, function_end) = match function_starts {
+            Some(function_starts) => {
+                let index = function_starts.partition_point(|start| *start <= lookup_svma);
+                let start = function_starts[..index]
+                    .last()?
+                    .checked_sub(text_data.svma_range.start)?;
+                let end = function_starts.get(index).map_or(text_bytes.len(), |end| {
+                    end.saturating_sub(text_data.svma_range.start) as usize
+                });
+                (start as usize, end.min(text_bytes.len()))
+            }
+            None => (
+                A::guess_function_start(text_bytes, pc_offset)?,
+                text_bytes.len(),
+            ),
+        };
+        let function_bytes = text_bytes.get(function_start..function_end)?;
+        let pc_offset_in_function = pc_offset.checked_sub(function_start)?;
+        if is_first_frame {
+            if pc_offset_in_function == 0 {
+                return Some(A::UnwindRule::rule_for_function_start());
+            }
+            if let Some(rule) =
+                A::rule_from_instruction_analysis(function_bytes, pc_offset_in_function)
+            {
+                return Some(rule);
+            }
+        }
+        let (prologue_len, rule) = A::rule_for_function_body(function_bytes)?;
+        if pc_offset_in_function < prologue_len {
+            return None;
+        }
+        Some(rule)
+    }
+
+    /// Converts the result of DWARF CFI evaluation. If the FDE doesn't cover the lookup
+    /// address, instruction analysis is tried for the gap returned by `cfi_gap`, and the
+    /// architecture's rule for uncovered addresses is used if that doesn't produce a rule.
+    fn dwarf_result_or_cfi_gap<'a, E>(
+        result: Result<UnwindResult<A::UnwindRule, E>, DwarfUnwinderError>,
+        cfi_gap: impl FnOnce() -> Option<CfiGap<'a, D>>,
+        stats: &mut UnwindStats,
+    ) -> Result<UnwindResult<A::UnwindRule, E>, UnwinderError>
+    where
+        D: 'a,
+    {
+        match result {
+            // Other errors, e.g. a full row stack with `MustNotAllocateDuringUnwind`, mean
+            // that the FDE covers the address but couldn't be evaluated.
+            Err(DwarfUnwinderError::UnwindInfoForAddressFailed(
+                gimli::Error::NoUnwindInfoForAddress,
+            )) => {
+                if let Some(rule) =
+                    cfi_gap().and_then(|cfi_gap| Self::rule_for_cfi_gap(&cfi_gap, stats))
+                {
+                    return Ok(UnwindResult::ExecRule(rule));
+                }
+                stats.dwarf_count += 1;
+                Ok(UnwindResult::ExecRule(A::rule_if_uncovered_by_fde()))
+            }
+            Err(err) => Err(err.into()),
+            Ok(UnwindResult::ExecRule(rule)) => {
+                stats.dwarf_count += 1;
+                Ok(UnwindResult::ExecRule(rule))
+            }
+            // Evaluations are counted once they're done.
+            Ok(unwind_result) => Ok(unwind_result),
+        }
+    }
+
+    /// Runs instruction analysis for a first frame whose address isn't covered by the
+    /// module's DWARF CFI. This only does something if the module supplied its text
+    /// bytes, see [`ModuleSectionInfo::instruction_analysis_for_dwarf_cfi_gaps`].
+    fn rule_for_cfi_gap(cfi_gap: &CfiGap<D>, stats: &mut UnwindStats) -> Option<A::UnwindRule> {
+        if !cfi_gap.is_first_frame {
+            return None;
+        }
+        let text_data = cfi_gap.text_data?;
+        let lookup_svma = cfi_gap
+            .base_svma
+            .wrapping_add(cfi_gap.rel_lookup_address as u64);
+        if !text_data.svma_range.contains(&lookup_svma) {
+            return None;
+        }
+        // We don't know exactly where the function starts and ends, so we analyze a
+        // window of bytes around the lookup address. The function can't overlap the
+        // functions which are covered by the neighbouring FDEs, so it is assumed to span
+        // the gap between them, and the window stops at them.
+        let pc_offset = (lookup_svma - text_data.svma_range.start) as usize;
+        let offset_of = |rel_address: u32| {
+            let svma = cfi_gap.base_svma.wrapping_add(rel_address as u64);
+            svma.checked_sub(text_data.svma_range.start)
+                .and_then(|offset| usize::try_from(offset).ok())
+        };
+        let gap_start = cfi_gap
+            .gap_start
+            .filter(|start| *start <= cfi_gap.rel_lookup_address)
+            .and_then(offset_of)
+            .unwrap_or(0);
+        let gap_end = cfi_gap
+            .gap_end
+            .filter(|end| *end > cfi_gap.rel_lookup_address)
+            .and_then(offset_of)
+            .unwrap_or(usize::MAX)
+            .min(text_data.bytes.len());
+        let window_start = pc_offset
+            .saturating_sub(CFI_GAP_ANALYSIS_WINDOW)
+            .max(gap_start);
+        let window_end = pc_offset
+            .saturating_add(CFI_GAP_ANALYSIS_WINDOW)
+            .min(gap_end);
+        let window = text_data.bytes.get(window_start..window_end)?;
+        let function = FunctionBounds {
+            offset: window_start - gap_start,
+            len: gap_end.checked_sub(gap_start)?,
+        };
+        let rule = A::rule_from_instruction_analysis_in_function(
+            window,
+            pc_offset - window_start,
+            function,
+        )?;
+        stats.instruction_analysis_count += 1;
+        Some(rule)
+    }
+}
+
+/// The unwind of a single frame, as a resumable task: [`FrameUnwind::step`] returns the
+/// reads it needs, and its caller provides them. [`UnwinderInternal::unwind_frame`] reads
+/// them right away, and a [`FrameUnwindStepper`] returns them to its own caller.
+pub(crate) struct FrameUnwind<'u, D, A: Unwinding, P: AllocationPolicy> {
+    unwinder: &'u UnwinderInternal<D, A, P>,
+    address: FrameAddress,
+    /// The stack pointer of the frame, for the frame size.
+    sp: u64,
+    /// Whether the frame is a stack link, whose caller is on a different stack.
+    switched_stacks: bool,
+    state: FrameUnwindState<'u, A, P>,
+}
+
+enum FrameUnwindState<'u, A: Unwinding, P: AllocationPolicy> {
+    Start,
+    StackLink(StackLinkExecution),
+    /// The frame is unwound by this foreign unwinder, which hasn't returned yet.
+    Foreign(&'u ForeignUnwindCallback<A::UnwindRegs>),
+    /// The frame is unwound with a rule which isn't cached. `verification` is the
+    /// return address and the stack pointer which the unwind information of the frame's
+    /// module produced, if the rule is checked against them.
+    Rule {
+        rule: A::UnwindRule,
+        verification: Option<(u64, u64)>,
+    },
+    /// The frame is unwound with the unwind information of its module, on a copy of the
+    /// registers, before it's unwound with the frame pointer.
+    Verification {
+        cached: CachedUnwind<'u, A, P>,
+        regs: A::UnwindRegs,
+        info: FrameUnwindInfo,
+    },
+    Cached(CachedUnwind<'u, A, P>),
+    /// The frame is unwound, but the result hasn't been checked yet.
+    Finish(Result<Option<u64>, Error>),
+    /// Like `Finish`, for the result of a foreign unwinder.
+    ForeignFinish(Result<Option<u64>, Error>),
+}
+
+impl<'u, D: Deref<Target = [u8]>, A: Unwinding, P: AllocationPolicy> FrameUnwind<'u, D, A, P> {
+    pub fn new(unwinder: &'u UnwinderInternal<D, A, P>, address: FrameAddress) -> Self {
+        Self {
+            unwinder,
+            address,
+            sp: 0,
+            switched_stacks: false,
+            state: FrameUnwindState::Start,
+        }
+    }
+
+    /// Continue unwinding the frame until it needs a read or a foreign unwinder, or
+    /// until it's done. `rules` executes the unwind rules, and must be the same in every
+    /// step. It must not be stepped again once it's done.
+    pub fn step(
+        &mut self,
+        regs: &mut A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        mut info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+        rules: &mut impl RuleExecutor<A::UnwindRule>,
+    ) -> FrameStep<'u, A::UnwindRegs> {
+        let is_first_frame = !self.address.is_return_address();
+        loop {
+            let result = match &mut self.state {
+                FrameUnwindState::Start => {
+                    if let Some(cached) = self.start(regs, cache, info.as_deref_mut(), tracer) {
+                        self.state = FrameUnwindState::Cached(cached);
+                    }
+                    continue;
+                }
+                FrameUnwindState::StackLink(execution) => match execution.step(regs) {
+                    Step::Read(request) => return FrameStep::Read(request),
+                    Step::Done(result) => result,
+                },
+                FrameUnwindState::Foreign(callback) => return FrameStep::Foreign(*callback),
+                FrameUnwindState::Rule { rule, verification } => {
+                    match rules.exec_rule(*rule, is_first_frame, regs) {
+                        Step::Read(request) => return FrameStep::Read(request),
+                        Step::Done(result) => {
+                            self.unwinder.finish_uncached_rule(
+                                &result,
+                                *verification,
+                                regs,
+                                cache,
+                                info.as_deref_mut(),
+                            );
+                            result
+                        }
+                    }
+                }
+                FrameUnwindState::Verification {
+                    cached,
+                    regs: verification_regs,
+                    info: verification_info,
+                } => {
+                    let result = match cached.step(
+                        self.unwinder,
+                        verification_regs,
+                        cache,
+                        Some(verification_info),
+                        &mut Tracer::disabled(),
+                        rules,
+                    ) {
+                        Step::Read(request) => return FrameStep::Read(request),
+                        Step::Done(result) => result,
+                    };
+                    let verification =
+                        verification_target(result, verification_regs, verification_info);
+                    self.start_frame_pointer_rule(verification, info.as_deref_mut(), tracer);
+                    continue;
+                }
+                FrameUnwindState::Cached(cached) => {
+                    match cached.step(
+                        self.unwinder,
+                        regs,
+                        cache,
+                        info.as_deref_mut(),
+                        tracer,
+                        rules,
+                    ) {
+                        Step::Read(request) => return FrameStep::Read(request),
+                        Step::Done(result) => result,
+                    }
+                }
+                FrameUnwindState::Finish(result) => *result,
+                FrameUnwindState::ForeignFinish(result) => {
+                    // Foreign unwinders are trusted like the fallback rule.
+                    if let Some(info) = info.as_deref_mut() {
+                        info.confidence = self.unwinder.confidence_for_result(true, result);
+                    }
+                    *result
+                }
+            };
+            return FrameStep::Done(self.finish(result, regs, info, tracer));
+        }
+    }
+
+    /// Provide the result of the read which the last step asked for.
+    pub fn provide(
+        &mut self,
+        value: Result<u64, MemoryReadError>,
+        rules: &mut impl RuleExecutor<A::UnwindRule>,
+    ) {
+        match &mut self.state {
+            FrameUnwindState::StackLink(execution) => {
+                Resumable::<A::UnwindRegs>::provide(execution, value);
+            }
+            FrameUnwindState::Rule { .. } => rules.provide(value),
+            FrameUnwindState::Verification { cached, .. } | FrameUnwindState::Cached(cached) => {
+                cached.provide(value, rules);
+            }
+            FrameUnwindState::Start
+            | FrameUnwindState::Foreign(_)
+            | FrameUnwindState::Finish(_)
+            | FrameUnwindState::ForeignFinish(_) => {}
+        }
+    }
+
+    /// Provide the result of the foreign unwinder which the last step returned.
+    pub fn provide_foreign_result(&mut self, result: Result<Option<u64>, Error>) {
+        if let FrameUnwindState::Foreign(_) = self.state {
+            self.state = FrameUnwindState::ForeignFinish(result);
+        }
+    }
+
+    /// Choose how the frame is unwound. Frames in module code are unwound with the
+    /// returned [`CachedUnwind`], and then finished with [`FrameUnwind::finish`]; the
+    /// other frames continue in the state which this sets.
+    #[inline(always)]
+    fn start(
+        &mut self,
+        regs: &A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> Option<CachedUnwind<'u, A, P>> {
+        let unwinder = self.unwinder;
+        let address = self.address;
+        trace_event!(
+            tracer,
+            Begin {
+                address,
+                regs: regs.clone(),
+            }
+        );
+        self.sp = regs.sp();
+        let lookup_address = unwinder.lookup_address(address);
+        if unwinder.is_root_address(lookup_address) {
+            if let Some(info) = info {
+                *info = FrameUnwindInfo::default();
+            }
+            self.state = FrameUnwindState::Finish(Ok(None));
+            return None;
+        }
+        if let Some(rule) = unwinder.stack_link_rule_for_address(lookup_address) {
+            if let Some(info) = info {
+                *info = FrameUnwindInfo::default();
+            }
+            trace_event!(tracer, StackLink { rule });
+            self.switched_stacks = true;
+            self.state = FrameUnwindState::StackLink(rule.start_exec(regs));
+            return None;
+        }
+        if let Some(foreign_unwinder) = unwinder.foreign_unwinder_for_address(lookup_address) {
+            trace_event!(
+                tracer,
+                ForeignUnwinder {
+                    avma_range: foreign_unwinder.avma_range.clone(),
+                }
+            );
+            cache.unwind_stats.foreign_unwinder_count += 1;
+            if let Some(info) = info {
+                *info = FrameUnwindInfo::default();
+            }
+            self.state = FrameUnwindState::Foreign(&*foreign_unwinder.callback);
+            return None;
+        }
+        if let Some(hint) = unwinder.jit_ranges.hint_for_address(lookup_address) {
+            // JIT ranges are replaced often, so their rules aren't cached.
+            let rule = A::UnwindRule::rule_for_hint(hint);
+            trace_event!(tracer, JitRange { hint, rule });
+            cache.unwind_stats.jit_range_count += 1;
+            if let Some(info) = info {
+                *info = FrameUnwindInfo::default();
+            }
+            self.state = FrameUnwindState::Rule {
+                rule,
+                verification: None,
+            };
+            return None;
+        }
+        if !unwinder.frame_pointer_only {
+            return Some(CachedUnwind::new(address));
+        }
+        cache.unwind_stats.frame_pointer_count += 1;
+        cache.frame_pointer_only_frame_count += 1;
+        match unwinder.frame_pointer_verification_interval {
+            Some(interval) if cache.frame_pointer_only_frame_count % interval.get() == 0 => {
+                self.state = FrameUnwindState::Verification {
+                    cached: CachedUnwind::new(address),
+                    regs: regs.clone(),
+                    info: FrameUnwindInfo::default(),
+                };
+            }
+            _ => self.start_frame_pointer_rule(None, info, tracer),
+        }
+        None
+    }
+
+    /// Check the result of the frame, like [`UnwinderInternal::finish_frame`].
+    fn finish(
+        &self,
+        result: Result<Option<u64>, Error>,
+        regs: &A::UnwindRegs,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) -> Result<Option<u64>, Error> {
+        self.unwinder
+            .finish_frame(result, self.sp, self.switched_stacks, regs, info, tracer)
+    }
+
+    fn start_frame_pointer_rule(
+        &mut self,
+        verification: Option<(u64, u64)>,
+        info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+    ) {
+        let rule = A::UnwindRule::fallback_rule();
+        trace_event!(tracer, ExecRule { rule });
+        if let Some(info) = info {
+            *info = FrameUnwindInfo::default();
+        }
+        self.state = FrameUnwindState::Rule { rule, verification };
+    }
+}
+
+/// A [`FrameUnwind`] for a [`FrameUnwindStepper`], whose
+/// cache is the cache type of the architecture's unwinder.
+pub(crate) struct SteppedFrameUnwind<'u, D, A: Unwinding, P: AllocationPolicy> {
+    unwind: FrameUnwind<'u, D, A, P>,
+    rules: SteppedRules<A::UnwindRule>,
+    /// The result of the frame once it's unwound, which the later steps return.
+    result: Option<Result<Option<u64>, Error>>,
+}
+
+impl<'u, 's, D, A, P, C> FrameUnwindTask<'u, A::UnwindRegs, C> for SteppedFrameUnwind<'u, D, A, P>
+where
+    D: Deref<Target = [u8]>,
+    A: Unwinding,
+    P: AllocationPolicy,
+    C: ArchCache<'s, A::UnwindRule, P>,
+{
+    fn step(
+        &mut self,
+        regs: &mut A::UnwindRegs,
+        cache: &mut C,
+        info: &mut FrameUnwindInfo,
+    ) -> FrameStep<'u, A::UnwindRegs> {
+        if let Some(result) = self.result {
+            return FrameStep::Done(result);
+        }
+        let cache = cache.cache_mut();
+        let step = self.unwind.step(
+            regs,
+            cache,
+            Some(info),
+            &mut Tracer::disabled(),
+            &mut self.rules,
+        );
+        if let FrameStep::Done(result) = step {
+            self.result = Some(result);
+        }
+        step
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        self.unwind.provide(value, &mut self.rules);
+    }
+
+    fn provide_foreign_result(&mut self, result: Result<Option<u64>, Error>) {
+        self.unwind.provide_foreign_result(result);
+    }
+}
+
+/// The unwind of a frame in module code, with the rule cache, the unwind information of
+/// the module and the fallback rule.
+struct CachedUnwind<'u, A: Unwinding, P: AllocationPolicy> {
+    address: FrameAddress,
+    state: CachedUnwindState<'u, A, P>,
+}
+
+enum CachedUnwindState<'u, A: Unwinding, P: AllocationPolicy> {
+    Start,
+    Rule {
+        rule: A::UnwindRule,
+        is_fallback: bool,
+    },
+    /// The unwind information of the module couldn't be turned into a rule and is
+    /// evaluated for this frame. The cache slot is used if the evaluation fails, for the
+    /// fallback rule.
+    Evaluation {
+        evaluation: ModuleEvaluation<'u, A, P>,
+        cache_handle: CacheHandle,
+        module_index: usize,
+        relative_lookup_address: u32,
+        #[cfg(feature = "return-address-predictor")]
+        callee_regs: A::UnwindRegs,
+    },
+    /// The frame was unwound by an evaluation, and the return address predictor checks
+    /// whether `rule` reproduces the unwind.
+    #[cfg(feature = "return-address-predictor")]
+    Observation {
+        rule: A::UnwindRule,
+        callee_regs: A::UnwindRegs,
+        return_address: u64,
+    },
+}
+
+impl<'u, A: Unwinding, P: AllocationPolicy> CachedUnwind<'u, A, P> {
+    fn new(address: FrameAddress) -> Self {
+        Self {
+            address,
+            state: CachedUnwindState::Start,
+        }
+    }
+
+    /// Continue with the result of a rule lookup. Returns the result of the frame if the
+    /// lookup already unwound it.
+    fn start_lookup(
+        &mut self,
+        lookup: RuleLookup<'u, A, P>,
+        #[cfg_attr(not(feature = "return-address-predictor"), allow(unused_variables))]
+        regs: &A::UnwindRegs,
+    ) -> Option<Result<Option<u64>, Error>> {
+        // The states are written in place, because moving them copies the whole
+        // evaluation.
+        match lookup {
+            RuleLookup::Done(result) => return Some(result),
+            RuleLookup::Rule { rule, is_fallback } => {
+                self.state = CachedUnwindState::Rule { rule, is_fallback };
+            }
+            RuleLookup::Evaluation {
+                evaluation,
+                cache_handle,
+                module_index,
+                relative_lookup_address,
+            } => {
+                self.state = CachedUnwindState::Evaluation {
+                    evaluation,
+                    cache_handle,
+                    module_index,
+                    relative_lookup_address,
+                    #[cfg(feature = "return-address-predictor")]
+                    callee_regs: regs.clone(),
+                };
+            }
+        }
+        None
+    }
+
+    /// Continue unwinding the frame until it needs a read or until it's done. It must not
+    /// be stepped again once it's done.
+    #[inline(always)]
+    fn step<D: Deref<Target = [u8]>>(
+        &mut self,
+        unwinder: &'u UnwinderInternal<D, A, P>,
+        regs: &mut A::UnwindRegs,
+        cache: &mut Cache<'_, A::UnwindRule, P>,
+        mut info: Option<&mut FrameUnwindInfo>,
+        tracer: &mut Tracer<A::UnwindRule, A::UnwindRegs>,
+        rules: &mut impl RuleExecutor<A::UnwindRule>,
+    ) -> Step<Result<Option<u64>, Error>> {
+        let is_first_frame = !self.address.is_return_address();
+        loop {
+            match &mut self.state {
+                CachedUnwindState::Start => {
+                    match unwinder.lookup_rule(
+                        self.address,
+                        regs,
+                        cache,
+                        info.as_deref_mut(),
+                        tracer,
+                    ) {
+                        // The rule is executed right away, and only stored if it needs a
+                        // read.
+                        RuleLookup::Rule { rule, is_fallback } => {
+                            match rules.exec_rule(rule, is_first_frame, regs) {
+                                Step::Read(request) => {
+                                    self.state = CachedUnwindState::Rule { rule, is_fallback };
+                                    return Step::Read(request);
+                                }
+                                Step::Done(result) => {
+                                    return Step::Done(Self::finish_rule(
+                                        unwinder,
+                                        result,
+                                        is_fallback,
+                                        info,
+                                    ));
+                                }
+                            }
+                        }
+                        lookup => {
+                            if let Some(result) = self.start_lookup(lookup, regs) {
+                                return Step::Done(result);
+                            }
+                        }
+                    }
+                }
+                CachedUnwindState::Rule { rule, is_fallback } => {
+                    let is_fallback = *is_fallback;
+                    let result = match rules.exec_rule(*rule, is_first_frame, regs) {
+                        Step::Read(request) => return Step::Read(request),
+                        Step::Done(result) => result,
+                    };
+                    return Step::Done(Self::finish_rule(unwinder, result, is_fallback, info));
+                }
+                CachedUnwindState::Evaluation {
+                    evaluation,
+                    cache_handle,
+                    module_index,
+                    relative_lookup_address,
+                    #[cfg(feature = "return-address-predictor")]
+                    callee_regs,
+                } => {
+                    let result = match evaluation.step(regs) {
+                        Step::Read(request) => return Step::Read(request),
+                        Step::Done(result) => result,
+                    };
+                    // The evaluation stays in place until the next state replaces it,
+                    // because moving it out copies it.
+                    let (cache_handle, module_index, relative_lookup_address) =
+                        (*cache_handle, *module_index, *relative_lookup_address);
+                    #[cfg(feature = "return-address-predictor")]
+                    let callee_regs = callee_regs.clone();
+                    match result {
+                        Ok(return_address) => {
+                            trace_event!(tracer, Uncacheable { return_address });
+                            cache.unwind_stats.uncacheable_count += 1;
+                            #[cfg(feature = "return-address-predictor")]
+                            match A::UnwindRule::rule_for_observed_frame(&callee_regs, regs) {
+                                Some(rule) => {
+                                    self.state = CachedUnwindState::Observation {
+                                        rule,
+                                        callee_regs,
+                                        return_address,
+                                    };
+                                    continue;
+                                }
+                                None => cache.predictor.observe(
+                                    unwinder.lookup_address(self.address),
+                                    unwinder.modules_generation,
+                                    None,
+                                ),
+                            }
+                            return Step::Done(Ok(Some(return_address)));
+                        }
+                        Err(error) => {
+                            let rule = unwinder.rule_after_error(
+                                &unwinder.modules[module_index],
+                                self.address,
+                                relative_lookup_address,
+                                error,
+                                &mut cache.unwind_stats,
+                                info.as_deref_mut(),
+                                tracer,
+                            );
+                            let lookup = unwinder.start_new_rule(
+                                cache_handle,
+                                rule,
+                                cache,
+                                info.as_deref_mut(),
+                                tracer,
+                            );
+                            if let Some(result) = self.start_lookup(lookup, regs) {
+                                return Step::Done(result);
+                            }
+                        }
+                    }
+                }
+                #[cfg(feature = "return-address-predictor")]
+                CachedUnwindState::Observation {
+                    rule,
+                    callee_regs,
+                    return_address,
+                } => {
+                    let result = match rules.exec_rule(*rule, is_first_frame, callee_regs) {
+                        Step::Read(request) => return Step::Read(request),
+                        Step::Done(result) => result,
+                    };
+                    let rule =
+                        reproduces_observed_frame(result, callee_regs, *return_address, regs)
+                            .then_some(*rule);
+                    let return_address = *return_address;
+                    cache.predictor.observe(
+                        unwinder.lookup_address(self.address),
+                        unwinder.modules_generation,
+                        rule,
+                    );
+                    return Step::Done(Ok(Some(return_address)));
+                }
+            }
+        }
+    }
+
+    /// Set the confidence of a frame which was unwound with a rule from the cache or
+    /// from the unwind information of its module.
+    fn finish_rule<D: Deref<Target = [u8]>>(
+        unwinder: &UnwinderInternal<D, A, P>,
+        result: Result<Option<u64>, Error>,
+        is_fallback: bool,
+        info: Option<&mut FrameUnwindInfo>,
+    ) -> Result<Option<u64>, Error> {
+        if let Some(info) = info {
+            info.confidence = unwinder.confidence_for_result(is_fallback, &result);
+        }
+        result
+    }
+
+    fn provide(
+        &mut self,
+        value: Result<u64, MemoryReadError>,
+        rules: &mut impl RuleExecutor<A::UnwindRule>,
+    ) {
+        match &mut self.state {
+            CachedUnwindState::Rule { .. } => rules.provide(value),
+            CachedUnwindState::Evaluation { evaluation, .. } => evaluation.provide(value),
+            #[cfg(feature = "return-address-predictor")]
+            CachedUnwindState::Observation { .. } => rules.provide(value),
+            CachedUnwindState::Start => {}
+        }
+    }
+}
+
+/// The outcome of looking up the rule for a frame in module code.
+enum RuleLookup<'u, A: Unwinding, P: AllocationPolicy> {
+    /// The frame is unwound with `rule`, which is the fallback rule if `is_fallback`.
+    Rule {
+        rule: A::UnwindRule,
+        is_fallback: bool,
+    },
+    /// The unwind information of the module couldn't be turned into a rule and is
+    /// evaluated for this frame. The cache slot is used if the evaluation fails, for the
+    /// fallback rule.
+    Evaluation {
+        evaluation: ModuleEvaluation<'u, A, P>,
+        cache_handle: CacheHandle,
+        module_index: usize,
+        relative_lookup_address: u32,
+    },
+    /// The frame is unwound without reading anything.
+    Done(Result<Option<u64>, Error>),
+}
+
+/// The return address and the stack pointer of the caller from unwinding a frame with
+/// the unwind information of its module, to check the frame pointer rule against.
+/// Callers which were found with the fallback rule have a lower confidence, so they
+/// aren't used.
+fn verification_target<R: StackLinkRegs>(
+    result: Result<Option<u64>, Error>,
+    regs: &R,
+    info: &FrameUnwindInfo,
+) -> Option<(u64, u64)> {
+    match result {
+        Ok(Some(return_address)) if info.confidence == FrameConfidence::Certain => {
+            Some((return_address, regs.sp()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether executing an observed rule, with the result `result` and the registers
+/// `regs`, found the same caller as the module's unwind information, which found
+/// `return_address` and `caller`.
+#[cfg(feature = "return-address-predictor")]
+fn reproduces_observed_frame<R: StackLinkRegs>(
+    result: Result<Option<u64>, Error>,
+    regs: &R,
+    return_address: u64,
+    caller: &R,
+) -> bool {
+    result == Ok(Some(return_address)) && regs.sp() == caller.sp() && regs.fp() == caller.fp()
+}
+
+/// The evaluation of a module's unwind information for a frame whose unwind
+/// information couldn't be turned into a rule.
+pub(crate) enum ModuleEvaluation<'u, A: Unwinding, P: AllocationPolicy> {
+    Dwarf(
+        A::DwarfEvaluation<
+            EndianSlice<'u, LittleEndian>,
+            P::GimliEvaluationStorage<EndianSlice<'u, LittleEndian>>,
+        >,
+    ),
+    #[cfg(feature = "pe")]
+    Pe(A::PeEvaluation),
+}
+
+impl<A: Unwinding, P: AllocationPolicy> Resumable<A::UnwindRegs> for ModuleEvaluation<'_, A, P> {
+    type Output = Result<u64, UnwinderError>;
+
+    fn step(&mut self, regs: &mut A::UnwindRegs) -> Step<Self::Output> {
+        match self {
+            Self::Dwarf(evaluation) => match evaluation.step(regs) {
+                Step::Read(request) => Step::Read(request),
+                Step::Done(result) => Step::Done(result.map_err(Into::into)),
+            },
+            #[cfg(feature = "pe")]
+            Self::Pe(evaluation) => match evaluation.step(regs) {
+                Step::Read(request) => Step::Read(request),
+                Step::Done(result) => Step::Done(result.map_err(Into::into)),
+            },
+        }
+    }
+
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        match self {
+            Self::Dwarf(evaluation) => evaluation.provide(value),
+            #[cfg(feature = "pe")]
+            Self::Pe(evaluation) => evaluation.provide(value),
+        }
+    }
+}
+
+/// The maximum number of bytes on each side of the lookup address which are considered
+/// when analyzing instructions in a gap in the DWARF CFI.
+const CFI_GAP_ANALYSIS_WINDOW: usize = 256;
+
+/// The information needed to analyze instructions at an address which isn't covered
+/// by DWARF CFI.
+struct CfiGap<'a, D> {
+    text_data: Option<&'a TextByteData<D>>,
+    base_svma: u64,
+    rel_lookup_address: u32,
+    is_first_frame: bool,
+    /// The end of the FDE before the lookup address, relative to the base address.
+    gap_start: Option<u32>,
+    /// The start of the FDE after the lookup address, relative to the base address.
+    gap_end: Option<u32>,
 }
 
 /// The unwind data that should be used when unwinding addresses inside this module.
@@ -589,6 +3058,7 @@ enum ModuleUnwindDataInternal<D> {
         stubs_svma: Option<Range<u64>>,
         stub_helper_svma: Option<Range<u64>>,
         base_addresses: crate::dwarf::BaseAddresses,
+        cie_fixups: CieFixups,
         text_data: Option<TextByteData<D>>,
     },
     /// Used with ELF binaries (Linux and friends), in the `.eh_frame_hdr` and `.eh_frame`
@@ -597,6 +3067,8 @@ enum ModuleUnwindDataInternal<D> {
         eh_frame_hdr: D,
         eh_frame: D,
         base_addresses: crate::dwarf::BaseAddresses,
+        cie_fixups: CieFixups,
+        text_data: Option<TextByteData<D>>,
     },
     /// Used with ELF binaries (Linux and friends), in the `.eh_frame` section. Contains
     /// DWARF CFI. We create a binary index for the FDEs when a module with this unwind
@@ -605,6 +3077,8 @@ enum ModuleUnwindDataInternal<D> {
         index: DwarfCfiIndex,
         eh_frame: D,
         base_addresses: crate::dwarf::BaseAddresses,
+        cie_fixups: CieFixups,
+        text_data: Option<TextByteData<D>>,
     },
     /// Used with ELF binaries (Linux and friends), in the `.debug_frame` section. Contains
     /// DWARF CFI. We create a binary index for the FDEs when a module with this unwind
@@ -613,6 +3087,8 @@ enum ModuleUnwindDataInternal<D> {
         index: DwarfCfiIndex,
         debug_frame: D,
         base_addresses: crate::dwarf::BaseAddresses,
+        cie_fixups: CieFixups,
+        text_data: Option<TextByteData<D>>,
     },
     /// Used with PE binaries (Windows).
     #[cfg(feature = "pe")]
@@ -622,45 +3098,146 @@ enum ModuleUnwindDataInternal<D> {
         xdata: Option<DataAtRvaRange<D>>,
         text: Option<DataAtRvaRange<D>>,
     },
+    /// No unwind information is available, but the module supplied its text section
+    /// and opted in to prologue analysis. Rules are synthesized from the prologue of
+    /// the function containing the address, and then stored in the rule cache.
+    PrologueAnalysis {
+        text_data: TextByteData<D>,
+        /// Sorted function start addresses (SVMAs) from the symbol table, if available.
+        /// Otherwise function starts are guessed from the instructions.
+        function_starts: Option<Vec<u64>>,
+    },
     /// No unwind information is used. Unwinding in this module will use a fallback rule
     /// (usually frame pointer unwinding).
     None,
 }
 
+/// The kind of unwind information that a module has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnwindDataKind {
+    /// mach-O `__unwind_info`, optionally supplemented with `__eh_frame`.
+    CompactUnwindInfoAndEhFrame,
+    /// `.eh_frame` with an `.eh_frame_hdr` index.
+    EhFrameHdrAndEhFrame,
+    /// `.eh_frame` without an index; framehop built its own index.
+    EhFrame,
+    /// `.debug_frame`; framehop built its own index.
+    DebugFrame,
+    /// PE unwind info (`.pdata` and friends).
+    PeUnwindInfo,
+    /// The `.gopclntab` of a Go binary, supplemented by the module's other unwind
+    /// information for non-Go code.
+    GoPclntab,
+    /// No unwind information; rules are synthesized by analyzing function prologues
+    /// in the text section.
+    PrologueAnalysis,
+    /// No unwind information. The fallback rule is used for all addresses.
+    None,
+}
+
+impl<D> ModuleUnwindDataInternal<D> {
+    fn kind(&self) -> UnwindDataKind {
+        match self {
+            #[cfg(feature = "macho")]
+            Self::CompactUnwindInfoAndEhFrame { .. } => UnwindDataKind::CompactUnwindInfoAndEhFrame,
+            Self::EhFrameHdrAndEhFrame { .. } => UnwindDataKind::EhFrameHdrAndEhFrame,
+            Self::DwarfCfiIndexAndEhFrame { .. } => UnwindDataKind::EhFrame,
+            Self::DwarfCfiIndexAndDebugFrame { .. } => UnwindDataKind::DebugFrame,
+            #[cfg(feature = "pe")]
+            Self::PeUnwindInfo { .. } => UnwindDataKind::PeUnwindInfo,
+            Self::PrologueAnalysis { .. } => UnwindDataKind::PrologueAnalysis,
+            Self::None => UnwindDataKind::None,
+        }
+    }
+}
+
 impl<D: Deref<Target = [u8]>> ModuleUnwindDataInternal<D> {
-    fn new(section_info: &mut impl ModuleSectionInfo<D>) -> Self {
+    /// The address ranges which are covered by unwind entries, relative to the base
+    /// address of the module, or `None` if they can't be enumerated.
+    fn covered_relative_ranges(&self, base_svma: u64) -> Option<Vec<Range<u64>>> {
+        let relative_to_base = |ranges: Vec<Range<u64>>| {
+            ranges
+                .into_iter()
+                .map(|range| {
+                    range.start.saturating_sub(base_svma)..range.end.saturating_sub(base_svma)
+                })
+                .collect()
+        };
+        match self {
+            #[cfg(feature = "macho")]
+            Self::CompactUnwindInfoAndEhFrame { unwind_info, .. } => {
+                let unwind_info = macho_unwind_info::UnwindInfo::parse(unwind_info).ok()?;
+                let mut functions = unwind_info.functions();
+                let mut ranges = Vec::new();
+                while let Some(function) = functions.next().ok()? {
+                    // Functions with a null opcode have no unwind information.
+                    if function.opcode != 0 {
+                        ranges.push(function.start_address.into()..function.end_address.into());
+                    }
+                }
+                Some(ranges)
+            }
+            Self::EhFrameHdrAndEhFrame {
+                eh_frame,
+                base_addresses,
+                cie_fixups,
+                ..
+            }
+            | Self::DwarfCfiIndexAndEhFrame {
+                eh_frame,
+                base_addresses,
+                cie_fixups,
+                ..
+            } => fde_svma_ranges(
+                eh_frame,
+                UnwindSectionType::EhFrame,
+                base_addresses,
+                cie_fixups,
+            )
+            .ok()
+            .map(relative_to_base),
+            Self::DwarfCfiIndexAndDebugFrame {
+                debug_frame,
+                base_addresses,
+                cie_fixups,
+                ..
+            } => fde_svma_ranges(
+                debug_frame,
+                UnwindSectionType::DebugFrame,
+                base_addresses,
+                cie_fixups,
+            )
+            .ok()
+            .map(relative_to_base),
+            #[cfg(feature = "pe")]
+            Self::PeUnwindInfo { .. } => None,
+            Self::PrologueAnalysis { .. } | Self::None => Some(Vec::new()),
+        }
+    }
+
+    fn new(section_info: &mut impl ModuleSectionInfo<D>, base_avma: u64) -> Self {
         use crate::dwarf::base_addresses_for_sections;
 
         #[cfg(feature = "macho")]
         if let Some(unwind_info) = section_info.section_data(b"__unwind_info") {
             let eh_frame = section_info.section_data(b"__eh_frame");
-            let stubs = section_info.section_svma_range(b"__stubs");
+            // arm64e binaries have `__auth_stubs` instead of `__stubs`.
+            let stubs = section_info
+                .section_svma_range(b"__stubs")
+                .or_else(|| section_info.section_svma_range(b"__auth_stubs"));
             let stub_helper = section_info.section_svma_range(b"__stub_helper");
-            // Get the bytes of the executable code (instructions).
-            //
-            // In mach-O objects, executable code is stored in the `__TEXT` segment, which contains
-            // multiple executable sections such as `__text`, `__stubs`, and `__stub_helper`. If we
-            // don't have the full `__TEXT` segment contents, we can fall back to the contents of
-            // just the `__text` section.
-            let text_data = if let (Some(bytes), Some(svma_range)) = (
-                section_info.segment_data(b"__TEXT"),
-                section_info.segment_svma_range(b"__TEXT"),
-            ) {
-                Some(TextByteData { bytes, svma_range })
-            } else if let (Some(bytes), Some(svma_range)) = (
-                section_info.section_data(b"__text"),
-                section_info.section_svma_range(b"__text"),
-            ) {
-                Some(TextByteData { bytes, svma_range })
-            } else {
-                None
-            };
+            let text_data = macho_text_data(section_info);
+            let cie_fixups = eh_frame
+                .as_deref()
+                .map(|eh_frame| CieFixups::new(eh_frame, &UnwindSectionType::EhFrame))
+                .unwrap_or_default();
             return ModuleUnwindDataInternal::CompactUnwindInfoAndEhFrame {
                 unwind_info,
                 eh_frame,
                 stubs_svma: stubs,
                 stub_helper_svma: stub_helper,
-                base_addresses: base_addresses_for_sections(section_info),
+                base_addresses: base_addresses_for_sections(section_info, base_avma),
+                cie_fixups,
                 text_data,
             };
         }
@@ -689,6 +3266,7 @@ impl<D: Deref<Target = [u8]>> ModuleUnwindDataInternal<D> {
             .section_data(b".eh_frame")
             .or_else(|| section_info.section_data(b"__eh_frame"))
         {
+            let cie_fixups = CieFixups::new(&eh_frame, &UnwindSectionType::EhFrame);
             if let Some(eh_frame_hdr) = section_info
                 .section_data(b".eh_frame_hdr")
                 .or_else(|| section_info.section_data(b"__eh_frame_hdr"))
@@ -696,29 +3274,54 @@ impl<D: Deref<Target = [u8]>> ModuleUnwindDataInternal<D> {
                 ModuleUnwindDataInternal::EhFrameHdrAndEhFrame {
                     eh_frame_hdr,
                     eh_frame,
-                    base_addresses: base_addresses_for_sections(section_info),
+                    base_addresses: base_addresses_for_sections(section_info, base_avma),
+                    cie_fixups,
+                    text_data: elf_text_data(section_info),
                 }
             } else {
-                match DwarfCfiIndex::try_new_eh_frame(&eh_frame, section_info) {
+                let index = match section_info.dwarf_cfi_index() {
+                    Some(index) => Ok(index),
+                    None => DwarfCfiIndex::try_new_eh_frame(
+                        &eh_frame,
+                        &cie_fixups,
+                        section_info,
+                        base_avma,
+                    ),
+                };
+                match index {
                     Ok(index) => ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame {
                         index,
                         eh_frame,
-                        base_addresses: base_addresses_for_sections(section_info),
+                        base_addresses: base_addresses_for_sections(section_info, base_avma),
+                        cie_fixups,
+                        text_data: elf_text_data(section_info),
                     },
-                    Err(_) => ModuleUnwindDataInternal::None,
+                    Err(_) => prologue_analysis_or_none(section_info),
                 }
             }
         } else if let Some(debug_frame) = section_info.section_data(b".debug_frame") {
-            match DwarfCfiIndex::try_new_debug_frame(&debug_frame, section_info) {
+            let cie_fixups = CieFixups::new(&debug_frame, &UnwindSectionType::DebugFrame);
+            let index = match section_info.dwarf_cfi_index() {
+                Some(index) => Ok(index),
+                None => DwarfCfiIndex::try_new_debug_frame(
+                    &debug_frame,
+                    &cie_fixups,
+                    section_info,
+                    base_avma,
+                ),
+            };
+            match index {
                 Ok(index) => ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame {
                     index,
                     debug_frame,
-                    base_addresses: base_addresses_for_sections(section_info),
+                    base_addresses: base_addresses_for_sections(section_info, base_avma),
+                    cie_fixups,
+                    text_data: elf_text_data(section_info),
                 },
-                Err(_) => ModuleUnwindDataInternal::None,
+                Err(_) => prologue_analysis_or_none(section_info),
             }
         } else {
-            ModuleUnwindDataInternal::None
+            prologue_analysis_or_none(section_info)
         }
     }
 }
@@ -727,12 +3330,15 @@ impl<D: Deref<Target = [u8]>> ModuleUnwindDataInternal<D> {
 /// instructions in order to provide high quality unwinding inside function prologues and
 /// epilogues.
 ///
-/// This is only needed on macOS, because mach-O `__unwind_info` and `__eh_frame` only
+/// This is needed on macOS, because mach-O `__unwind_info` and `__eh_frame` only
 /// cares about accuracy in function bodies, not in function prologues and epilogues.
 ///
-/// On Linux, compilers produce `.eh_frame` and `.debug_frame` which provides correct
-/// unwind information for all instructions including those in function prologues and
-/// epilogues, so instruction analysis is not needed.
+/// On Linux, compilers usually produce `.eh_frame` and `.debug_frame` which provides
+/// correct unwind information for all instructions including those in function prologues
+/// and epilogues. But hand-written assembly and some JIT-adjacent stubs have no CFI or
+/// incomplete CFI, so modules can opt in to instruction analysis for addresses which
+/// the CFI doesn't cover, see
+/// [`ModuleSectionInfo::instruction_analysis_for_dwarf_cfi_gaps`].
 ///
 /// Type arguments:
 ///
@@ -740,17 +3346,82 @@ impl<D: Deref<Target = [u8]>> ModuleUnwindDataInternal<D> {
 ///    module, e.g. `Vec<u8>`. But it could also be a wrapper around mapped memory from
 ///    a file or a different process, for example. It just needs to provide a slice of
 ///    bytes via its `Deref` implementation.
-#[cfg(feature = "macho")]
 struct TextByteData<D> {
     pub bytes: D,
     pub svma_range: Range<u64>,
 }
 
-/// Information about a module that is loaded in a process. You might know this under a
-/// different name, for example: (Shared) library, binary image, DSO ("Dynamic shared object")
+/// Gets the bytes of the executable code (instructions) of a mach-O module.
 ///
-/// The unwinder needs to have an up-to-date list of modules so that it can match an
-/// absolute address to the right module, and so that it can find that module's unwind
+/// In mach-O objects, executable code is usually stored in the `__TEXT` segment, which
+/// contains multiple executable sections such as `__text`, `__stubs`, and `__stub_helper`.
+/// Kernel extensions and some dyld shared cache images put their code in a separate
+/// `__TEXT_EXEC` segment instead, so we use the segment which contains the `__text`
+/// section. If we don't have the full segment contents, we can fall back to the contents
+/// of just the `__text` section.
+#[cfg(feature = "macho")]
+fn macho_text_data<D>(section_info: &mut impl ModuleSectionInfo<D>) -> Option<TextByteData<D>> {
+    let text_svma = section_info.section_svma_range(b"__text");
+    for segment_name in [&b"__TEXT"[..], b"__TEXT_EXEC"] {
+        let Some(svma_range) = section_info.segment_svma_range(segment_name) else {
+            continue;
+        };
+        if let Some(text_svma) = &text_svma {
+            if !svma_range.contains(&text_svma.start) {
+                continue;
+            }
+        }
+        if let Some(bytes) = section_info.segment_data(segment_name) {
+            return Some(TextByteData { bytes, svma_range });
+        }
+    }
+    let bytes = section_info.section_data(b"__text")?;
+    Some(TextByteData {
+        bytes,
+        svma_range: text_svma?,
+    })
+}
+
+/// Gets the `.text` bytes of an ELF module, if the module opted in to instruction analysis
+/// for addresses which aren't covered by DWARF CFI.
+fn elf_text_data<D>(section_info: &mut impl ModuleSectionInfo<D>) -> Option<TextByteData<D>> {
+    if !section_info.instruction_analysis_for_dwarf_cfi_gaps() {
+        return None;
+    }
+    let svma_range = section_info.section_svma_range(b".text")?;
+    let bytes = section_info.section_data(b".text")?;
+    Some(TextByteData { bytes, svma_range })
+}
+
+/// Gets the unwind data for a module without unwind information: prologue analysis if
+/// the module opted in to it and supplied its text section, otherwise nothing.
+fn prologue_analysis_or_none<D>(
+    section_info: &mut impl ModuleSectionInfo<D>,
+) -> ModuleUnwindDataInternal<D> {
+    if !section_info.synthesize_rules_from_prologues() {
+        return ModuleUnwindDataInternal::None;
+    }
+    let (Some(svma_range), Some(bytes)) = (
+        section_info.section_svma_range(b".text"),
+        section_info.section_data(b".text"),
+    ) else {
+        return ModuleUnwindDataInternal::None;
+    };
+    let function_starts = section_info.function_starts().map(|mut function_starts| {
+        function_starts.sort_unstable();
+        function_starts
+    });
+    ModuleUnwindDataInternal::PrologueAnalysis {
+        text_data: TextByteData { bytes, svma_range },
+        function_starts,
+    }
+}
+
+/// Information about a module that is loaded in a process. You might know this under a
+/// different name, for example: (Shared) library, binary image, DSO ("Dynamic shared object")
+///
+/// The unwinder needs to have an up-to-date list of modules so that it can match an
+/// absolute address to the right module, and so that it can find that module's unwind
 /// information.
 ///
 /// Type arguments:
@@ -760,9 +3431,8 @@ struct TextByteData<D> {
 ///    a file or a different process, for example. It just needs to provide a slice of
 ///    bytes via its `Deref` implementation.
 pub struct Module<D> {
-    /// The name or file path of the module. Unused, it's just there for easier debugging.
-    #[allow(unused)]
-    name: String,
+    /// The name or file path of the module. Used in diagnostics.
+    name: Arc<str>,
     /// The address range where this module is mapped into the process.
     avma_range: Range<u64>,
     /// The base address of this module, in the process's address space. On Linux, the base
@@ -770,8 +3440,364 @@ pub struct Module<D> {
     base_avma: u64,
     /// The base address of this module, according to the module.
     base_svma: u64,
+    /// The unwind data and the PLT ranges of this module, which are possibly only loaded
+    /// when an address in this module is unwound for the first time.
+    sections: Arc<ModuleSectionsSource<D>>,
+    /// Whether the module's code signs return addresses with pointer authentication.
+    /// `None` if unknown.
+    signs_return_addresses: Option<bool>,
+    /// The identifier of the binary, e.g. the ELF build ID.
+    code_id: Option<CodeId>,
+    /// The identifier of the module's debug information.
+    debug_id: Option<DebugId>,
+    /// Whether return addresses in this module are unwound with the frame pointer,
+    /// without looking at the unwind information.
+    frame_pointers_guaranteed: bool,
+    /// Address ranges (SVMAs) whose unwind information is replaced with a hint.
+    unwind_hints: Vec<(Range<u64>, UnwindHint)>,
+    /// Address ranges (SVMAs) of well-known trampolines.
+    trampolines: Vec<(Range<u64>, Trampoline)>,
+    /// Whether the module belongs to Rosetta 2, so that unwinding stops at its frames.
+    is_rosetta: bool,
+    /// Whether the module is `wow64cpu.dll`, so that unwinding stops at its frames.
+    is_wow64_transition: bool,
+}
+
+/// The parts of a module which are only needed when an address in the module is unwound.
+struct ModuleSections<D> {
     /// The unwind data that should be used for unwinding addresses from this module.
-    unwind_data: Arc<ModuleUnwindDataInternal<D>>,
+    unwind_data: ModuleUnwindDataInternal<D>,
+    /// The address ranges (SVMAs) of the ELF `.plt`, `.plt.got` and `.plt.sec` sections.
+    /// The stub function rule is used for addresses in these sections which the unwind
+    /// information doesn't cover.
+    plt_svma_ranges: Vec<Range<u64>>,
+    /// The pclntab of a Go binary, which is used for addresses in Go functions.
+    #[cfg(feature = "go")]
+    go_pclntab: Option<GoPclntab<D>>,
+}
+
+impl<D: Deref<Target = [u8]>> ModuleSections<D> {
+    fn new(section_info: &mut impl ModuleSectionInfo<D>, base_avma: u64) -> Self {
+        let unwind_data = ModuleUnwindDataInternal::new(section_info, base_avma);
+        let plt_svma_ranges = [&b".plt"[..], b".plt.got", b".plt.sec"]
+            .into_iter()
+            .filter_map(|name| section_info.section_svma_range(name))
+            .collect();
+        #[cfg(feature = "go")]
+        let go_pclntab = section_info
+            .section_data(b".gopclntab")
+            .or_else(|| section_info.section_data(b"__gopclntab"))
+            .and_then(|data| {
+                let text_svma = section_info
+                    .section_svma_range(b".text")
+                    .or_else(|| section_info.section_svma_range(b"__text"));
+                GoPclntab::parse(data, text_svma.map(|range| range.start)).ok()
+            });
+        Self {
+            unwind_data,
+            plt_svma_ranges,
+            #[cfg(feature = "go")]
+            go_pclntab,
+        }
+    }
+}
+
+enum ModuleSectionsSource<D> {
+    Loaded(ModuleSections<D>),
+    /// The sections are loaded by calling `loader` when they are first needed.
+    #[cfg(feature = "std")]
+    Lazy {
+        #[allow(clippy::type_complexity)]
+        loader: std::sync::Mutex<Option<Box<dyn FnOnce() -> ModuleSections<D> + Send>>>,
+        sections: std::sync::OnceLock<ModuleSections<D>>,
+    },
+}
+
+impl<D: Deref<Target = [u8]>> ModuleSections<D> {
+    fn memory_usage(&self) -> ModuleMemoryUsage {
+        let len = |data: &D| data.len();
+        let text_len = |text: &Option<TextByteData<D>>| text.as_ref().map_or(0, |t| t.bytes.len());
+        let mut usage = ModuleMemoryUsage {
+            indexes: self.plt_svma_ranges.capacity() * core::mem::size_of::<Range<u64>>(),
+            ..Default::default()
+        };
+        match &self.unwind_data {
+            #[cfg(feature = "macho")]
+            ModuleUnwindDataInternal::CompactUnwindInfoAndEhFrame {
+                unwind_info,
+                eh_frame,
+                cie_fixups,
+                text_data,
+                ..
+            } => {
+                usage.unwind_sections = len(unwind_info) + eh_frame.as_ref().map_or(0, len);
+                usage.indexes += cie_fixups.memory_usage();
+                usage.text = text_len(text_data);
+            }
+            ModuleUnwindDataInternal::EhFrameHdrAndEhFrame {
+                eh_frame_hdr,
+                eh_frame,
+                cie_fixups,
+                text_data,
+                ..
+            } => {
+                usage.unwind_sections = len(eh_frame_hdr) + len(eh_frame);
+                usage.indexes += cie_fixups.memory_usage();
+                usage.text = text_len(text_data);
+            }
+            ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame {
+                index,
+                eh_frame: section,
+                cie_fixups,
+                text_data,
+                ..
+            }
+            | ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame {
+                index,
+                debug_frame: section,
+                cie_fixups,
+                text_data,
+                ..
+            } => {
+                usage.unwind_sections = len(section);
+                usage.indexes += index.memory_usage() + cie_fixups.memory_usage();
+                usage.text = text_len(text_data);
+            }
+            #[cfg(feature = "pe")]
+            ModuleUnwindDataInternal::PeUnwindInfo {
+                pdata,
+                rdata,
+                xdata,
+                text,
+            } => {
+                let rva_data_len = |data: &Option<DataAtRvaRange<D>>| {
+                    data.as_ref().map_or(0, |data| data.data.len())
+                };
+                usage.unwind_sections = len(pdata) + rva_data_len(rdata) + rva_data_len(xdata);
+                usage.text = rva_data_len(text);
+            }
+            ModuleUnwindDataInternal::PrologueAnalysis {
+                text_data,
+                function_starts,
+            } => {
+                usage.indexes += function_starts
+                    .as_ref()
+                    .map_or(0, |starts| starts.capacity() * core::mem::size_of::<u64>());
+                usage.text = text_data.bytes.len();
+            }
+            ModuleUnwindDataInternal::None => {}
+        }
+        #[cfg(feature = "go")]
+        if let Some(go_pclntab) = &self.go_pclntab {
+            usage.unwind_sections += go_pclntab.data().len();
+        }
+        usage
+    }
+
+    /// Drop the text bytes if they are optional, and return the number of dropped bytes.
+    /// The text bytes of modules which use prologue analysis are not optional.
+    fn drop_optional_data(&mut self) -> usize {
+        let text_data = match &mut self.unwind_data {
+            #[cfg(feature = "macho")]
+            ModuleUnwindDataInternal::CompactUnwindInfoAndEhFrame { text_data, .. } => text_data,
+            ModuleUnwindDataInternal::EhFrameHdrAndEhFrame { text_data, .. }
+            | ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame { text_data, .. }
+            | ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame { text_data, .. } => text_data,
+            #[cfg(feature = "pe")]
+            ModuleUnwindDataInternal::PeUnwindInfo { text, .. } => {
+                return text.take().map_or(0, |text| text.data.len());
+            }
+            ModuleUnwindDataInternal::PrologueAnalysis { .. } | ModuleUnwindDataInternal::None => {
+                return 0;
+            }
+        };
+        text_data.take().map_or(0, |text| text.bytes.len())
+    }
+
+    #[cfg(feature = "std")]
+    fn content(&self) -> SectionsContent<'_> {
+        let mut content = SectionsContent {
+            kind: self.unwind_data.kind(),
+            bytes: Vec::new(),
+            numbers: Vec::new(),
+            base_addresses: None,
+        };
+        for range in &self.plt_svma_ranges {
+            content.push_range(Some(range));
+        }
+        match &self.unwind_data {
+            #[cfg(feature = "macho")]
+            ModuleUnwindDataInternal::CompactUnwindInfoAndEhFrame {
+                unwind_info,
+                eh_frame,
+                stubs_svma,
+                stub_helper_svma,
+                base_addresses,
+                cie_fixups: _,
+                text_data,
+            } => {
+                content.bytes.push(unwind_info);
+                content.bytes.push(eh_frame.as_deref().unwrap_or_default());
+                content.push_range(stubs_svma.as_ref());
+                content.push_range(stub_helper_svma.as_ref());
+                content.base_addresses = Some(base_addresses);
+                content.push_text(text_data.as_ref());
+            }
+            ModuleUnwindDataInternal::EhFrameHdrAndEhFrame {
+                eh_frame_hdr,
+                eh_frame,
+                base_addresses,
+                cie_fixups: _,
+                text_data,
+            } => {
+                content.bytes.push(eh_frame_hdr);
+                content.bytes.push(eh_frame);
+                content.base_addresses = Some(base_addresses);
+                content.push_text(text_data.as_ref());
+            }
+            ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame {
+                index: _,
+                eh_frame: section,
+                base_addresses,
+                cie_fixups: _,
+                text_data,
+            }
+            | ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame {
+                index: _,
+                debug_frame: section,
+                base_addresses,
+                cie_fixups: _,
+                text_data,
+            } => {
+                // The index and the CIE fixups are derived from the section and the base
+                // addresses.
+                content.bytes.push(section);
+                content.base_addresses = Some(base_addresses);
+                content.push_text(text_data.as_ref());
+            }
+            #[cfg(feature = "pe")]
+            ModuleUnwindDataInternal::PeUnwindInfo {
+                pdata,
+                rdata,
+                xdata,
+                text,
+            } => {
+                content.bytes.push(pdata);
+                for data in [rdata, xdata, text] {
+                    let data = data.as_ref();
+                    content
+                        .bytes
+                        .push(data.map(|data| &data.data[..]).unwrap_or_default());
+                    let rva_range = data.map(|data| data.rva_range.clone());
+                    content.push_range(
+                        rva_range
+                            .map(|range| u64::from(range.start)..u64::from(range.end))
+                            .as_ref(),
+                    );
+                }
+            }
+            ModuleUnwindDataInternal::PrologueAnalysis {
+                text_data,
+                function_starts,
+            } => {
+                content.push_text(Some(text_data));
+                content
+                    .numbers
+                    .extend(function_starts.iter().flatten().copied());
+            }
+            ModuleUnwindDataInternal::None => {}
+        }
+        #[cfg(feature = "go")]
+        if let Some(go_pclntab) = &self.go_pclntab {
+            content.bytes.push(go_pclntab.data());
+            content.numbers.push(go_pclntab.text_start());
+        }
+        content
+    }
+}
+
+/// Everything which determines the unwinding results of a module's sections. Modules
+/// with the same content can share their sections.
+#[cfg(feature = "std")]
+#[derive(PartialEq)]
+struct SectionsContent<'a> {
+    kind: UnwindDataKind,
+    bytes: Vec<&'a [u8]>,
+    numbers: Vec<u64>,
+    base_addresses: Option<&'a crate::dwarf::BaseAddresses>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> SectionsContent<'a> {
+    fn push_range(&mut self, range: Option<&Range<u64>>) {
+        match range {
+            Some(range) => self.numbers.extend([1, range.start, range.end]),
+            None => self.numbers.push(0),
+        }
+    }
+
+    fn push_text<D: Deref<Target = [u8]>>(&mut self, text_data: Option<&'a TextByteData<D>>) {
+        self.bytes
+            .push(text_data.map(|text| &text.bytes[..]).unwrap_or_default());
+        self.push_range(text_data.map(|text| &text.svma_range));
+    }
+
+    /// A hash of everything except the base addresses, which don't implement `Hash`.
+    fn hash(&self) -> u64 {
+        use core::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        self.kind.hash(&mut hasher);
+        self.bytes.hash(&mut hasher);
+        self.numbers.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl<D> ModuleSectionsSource<D> {
+    fn get(&self) -> &ModuleSections<D> {
+        match self {
+            Self::Loaded(sections) => sections,
+            #[cfg(feature = "std")]
+            Self::Lazy { loader, sections } => sections.get_or_init(|| {
+                let loader = loader
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .take();
+                // The loader is only missing if it panicked during an earlier call.
+                loader.map(|loader| loader()).unwrap_or(ModuleSections {
+                    unwind_data: ModuleUnwindDataInternal::None,
+                    plt_svma_ranges: Vec::new(),
+                    #[cfg(feature = "go")]
+                    go_pclntab: None,
+                })
+            }),
+        }
+    }
+
+    /// The sections, if they have been loaded.
+    fn get_if_loaded(&self) -> Option<&ModuleSections<D>> {
+        match self {
+            Self::Loaded(sections) => Some(sections),
+            #[cfg(feature = "std")]
+            Self::Lazy { sections, .. } => sections.get(),
+        }
+    }
+
+    fn get_mut_if_loaded(&mut self) -> Option<&mut ModuleSections<D>> {
+        match self {
+            Self::Loaded(sections) => Some(sections),
+            #[cfg(feature = "std")]
+            Self::Lazy { sections, .. } => sections.get_mut(),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn is_loaded(&self) -> bool {
+        match self {
+            Self::Loaded(_) => true,
+            Self::Lazy { sections, .. } => sections.get().is_some(),
+        }
+    }
 }
 
 impl<D> Clone for Module<D> {
@@ -781,7 +3807,15 @@ impl<D> Clone for Module<D> {
             avma_range: self.avma_range.clone(),
             base_avma: self.base_avma,
             base_svma: self.base_svma,
-            unwind_data: self.unwind_data.clone(),
+            sections: self.sections.clone(),
+            signs_return_addresses: self.signs_return_addresses,
+            code_id: self.code_id.clone(),
+            debug_id: self.debug_id,
+            frame_pointers_guaranteed: self.frame_pointers_guaranteed,
+            unwind_hints: self.unwind_hints.clone(),
+            trampolines: self.trampolines.clone(),
+            is_rosetta: self.is_rosetta,
+            is_wow64_transition: self.is_wow64_transition,
         }
     }
 }
@@ -800,8 +3834,9 @@ impl<D> Clone for Module<D> {
 pub trait ModuleSectionInfo<D> {
     /// Return the base address stated in the module.
     ///
-    /// For mach-O objects, this is the vmaddr of the __TEXT segment. For ELF objects, this is
-    /// zero. For PE objects, this is the image base address.
+    /// For mach-O objects, this is the vmaddr of the __TEXT segment, i.e. the address of the
+    /// mach header, even if the code is in a different segment such as `__TEXT_EXEC`. For ELF
+    /// objects, this is zero. For PE objects, this is the image base address.
     ///
     /// This is used to convert between SVMAs and relative addresses.
     fn base_svma(&self) -> u64;
@@ -812,6 +3847,18 @@ pub trait ModuleSectionInfo<D> {
     /// Get the given section's data. This will only be called once per section.
     fn section_data(&mut self, name: &[u8]) -> Option<D>;
 
+    /// Get the address (AVMA) at which the data returned by
+    /// [`ModuleSectionInfo::section_data`] was mapped in the process, if it differs from
+    /// the address which follows from the section's SVMA and the module's bias.
+    ///
+    /// This is only needed for `.eh_frame` and `.eh_frame_hdr` data which was read from
+    /// a process whose loader moved these sections independently from the rest of the
+    /// module, e.g. because of a packer. Pc-relative pointers in these sections are
+    /// relative to the address of the data, so they're misinterpreted otherwise.
+    fn section_avma(&mut self, _name: &[u8]) -> Option<u64> {
+        None
+    }
+
     /// Get the given segment's memory range, as stated in the module.
     fn segment_svma_range(&mut self, _name: &[u8]) -> Option<Range<u64>> {
         None
@@ -821,6 +3868,65 @@ pub trait ModuleSectionInfo<D> {
     fn segment_data(&mut self, _name: &[u8]) -> Option<D> {
         None
     }
+
+    /// Whether the unwinder should get the `.text` section data of an ELF module, and
+    /// analyze instructions for first frames at addresses which aren't covered by the
+    /// module's DWARF CFI.
+    ///
+    /// This helps with hand-written assembly and stubs whose CFI is missing or
+    /// incomplete. It is off by default because the `.text` section is usually large
+    /// and because compiler-generated CFI is precise.
+    fn instruction_analysis_for_dwarf_cfi_gaps(&self) -> bool {
+        false
+    }
+
+    /// Whether the unwinder should get the `.text` section data of a module which has no
+    /// unwind information at all, and synthesize unwind rules by analyzing function
+    /// prologues. This helps with stripped third-party libraries which would otherwise
+    /// rely on frame pointers.
+    ///
+    /// Function starts are taken from [`ModuleSectionInfo::function_starts`] if
+    /// available, and guessed from the instructions otherwise.
+    fn synthesize_rules_from_prologues(&self) -> bool {
+        false
+    }
+
+    /// Get the start addresses (SVMAs) of the module's functions, e.g. from the symbol
+    /// table. This is only called if [`ModuleSectionInfo::synthesize_rules_from_prologues`]
+    /// returns true.
+    fn function_starts(&mut self) -> Option<Vec<u64>> {
+        None
+    }
+
+    /// Whether the module's code signs return addresses with pointer authentication,
+    /// or `None` if unknown. On macOS, this is the case for arm64e binaries.
+    ///
+    /// This is only used on Aarch64. When unwinding frames in a module which doesn't sign
+    /// return addresses, no pointer authentication bits are stripped.
+    fn signs_return_addresses(&self) -> Option<bool> {
+        None
+    }
+
+    /// Get the identifier of the binary, i.e. the ELF build ID, the mach-O UUID, or the PE
+    /// timestamp and image size.
+    fn code_id(&mut self) -> Option<CodeId> {
+        None
+    }
+
+    /// Get the identifier of the module's debug information. If this returns `None`, the
+    /// debug ID is derived from the code ID for ELF and mach-O binaries. PE binaries need
+    /// to supply the debug ID from their CodeView record here.
+    fn debug_id(&mut self) -> Option<DebugId> {
+        None
+    }
+
+    /// Get an index for the `.eh_frame` or `.debug_frame` section which was built
+    /// earlier, e.g. from an [`UnwinderCheckpoint`](crate::UnwinderCheckpoint). If this
+    /// returns `None`, the index is built from the section, which can take a while for
+    /// large binaries.
+    fn dwarf_cfi_index(&mut self) -> Option<DwarfCfiIndex> {
+        None
+    }
 }
 
 /// Explicit addresses and data of various sections in the module. This implements
@@ -851,9 +3957,10 @@ pub struct ExplicitModuleSectionInfo<D> {
     ///
     /// This is used to handle function prologues and epilogues in some cases.
     pub text: Option<D>,
-    /// The address range of the mach-O `__stubs` section. Contains small pieces of
-    /// executable code for calling imported functions. Code inside this section is not
-    /// covered by the unwind information in `__unwind_info`.
+    /// The address range of the mach-O `__stubs` section, or of the `__auth_stubs` section
+    /// in arm64e binaries. Contains small pieces of executable code for calling imported
+    /// functions. Code inside this section is not covered by the unwind information in
+    /// `__unwind_info`.
     ///
     /// This is used to exclude addresses in this section from incorrectly applying
     /// `__unwind_info` opcodes. It is also used to infer unwind rules for the known
@@ -871,6 +3978,21 @@ pub struct ExplicitModuleSectionInfo<D> {
     /// The address range of the `.got` section (Global Offset Table). This is used
     /// during DWARF CFI processing, to resolve got-relative addresses.
     pub got_svma: Option<Range<u64>>,
+    /// The address range of the `__data` or `.data` section. This is used during DWARF
+    /// CFI processing, to resolve data-relative addresses in binaries without a `.got`
+    /// section.
+    pub data_svma: Option<Range<u64>>,
+    /// The address range of the ELF `.plt` section. Contains small pieces of executable
+    /// code for calling imported functions.
+    ///
+    /// This is used to apply the stub function rule to addresses in this section which
+    /// are not covered by the DWARF CFI, similar to the mach-O `__stubs` section.
+    pub plt_svma: Option<Range<u64>>,
+    /// The address range of the ELF `.plt.got` section. See `plt_svma`.
+    pub plt_got_svma: Option<Range<u64>>,
+    /// The address range of the ELF `.plt.sec` section, which is used instead of `.plt`
+    /// for the PLT entries of binaries with Indirect Branch Tracking. See `plt_svma`.
+    pub plt_sec_svma: Option<Range<u64>>,
     /// The data of the `__unwind_info` section of mach-O binaries.
     pub unwind_info: Option<D>,
     /// The address range of the `__eh_frame` or `.eh_frame` section. This is used during DWARF CFI
@@ -879,18 +4001,54 @@ pub struct ExplicitModuleSectionInfo<D> {
     /// The data of the `__eh_frame` or `.eh_frame` section. This is used during DWARF CFI
     /// processing, to resolve eh_frame-relative addresses.
     pub eh_frame: Option<D>,
+    /// The address at which the `eh_frame` data was mapped in the process, if it differs
+    /// from the address which follows from `eh_frame_svma`. See
+    /// [`ModuleSectionInfo::section_avma`].
+    pub eh_frame_avma: Option<u64>,
     /// The address range of the `.eh_frame_hdr` section. This is used during DWARF CFI processing,
     /// to resolve eh_frame_hdr-relative addresses.
     pub eh_frame_hdr_svma: Option<Range<u64>>,
     /// The data of the `.eh_frame_hdr` section. This is used during DWARF CFI processing, to
     /// resolve eh_frame_hdr-relative addresses.
     pub eh_frame_hdr: Option<D>,
+    /// The address at which the `eh_frame_hdr` data was mapped in the process, if it
+    /// differs from the address which follows from `eh_frame_hdr_svma`. See
+    /// [`ModuleSectionInfo::section_avma`].
+    pub eh_frame_hdr_avma: Option<u64>,
     /// The data of the `.debug_frame` section. The related address range is not needed.
     pub debug_frame: Option<D>,
+    /// The data of the `.gopclntab` or `__gopclntab` section of Go binaries. Only used
+    /// with the `go` feature.
+    pub gopclntab: Option<D>,
     /// The address range of the `__TEXT` segment of mach-O binaries, if available.
     pub text_segment_svma: Option<Range<u64>>,
     /// The data of the `__TEXT` segment of mach-O binaries, if available.
     pub text_segment: Option<D>,
+    /// The address range of the `__TEXT_EXEC` segment of mach-O binaries, if available.
+    /// Kernel extensions and some dyld shared cache images store their code in this
+    /// segment rather than in `__TEXT`.
+    pub text_exec_segment_svma: Option<Range<u64>>,
+    /// The data of the `__TEXT_EXEC` segment of mach-O binaries, if available.
+    pub text_exec_segment: Option<D>,
+    /// Whether `text` should be used for instruction analysis in ELF modules, at
+    /// addresses which aren't covered by DWARF CFI. See
+    /// [`ModuleSectionInfo::instruction_analysis_for_dwarf_cfi_gaps`].
+    pub instruction_analysis_for_dwarf_cfi_gaps: bool,
+    /// Whether `text` should be used to synthesize unwind rules from function prologues
+    /// if the module has no unwind information. See
+    /// [`ModuleSectionInfo::synthesize_rules_from_prologues`].
+    pub synthesize_rules_from_prologues: bool,
+    /// The start addresses of the module's functions, e.g. from the symbol table. Used
+    /// together with `synthesize_rules_from_prologues`.
+    pub function_starts: Option<Vec<u64>>,
+    /// Whether the module's code signs return addresses with pointer authentication.
+    /// See [`ModuleSectionInfo::signs_return_addresses`].
+    pub signs_return_addresses: Option<bool>,
+    /// The identifier of the binary. See [`ModuleSectionInfo::code_id`].
+    pub code_id: Option<CodeId>,
+    /// The identifier of the module's debug information. See
+    /// [`ModuleSectionInfo::debug_id`].
+    pub debug_id: Option<DebugId>,
 }
 
 impl<D> ModuleSectionInfo<D> for ExplicitModuleSectionInfo<D>
@@ -909,6 +4067,10 @@ where
             b"__eh_frame" | b".eh_frame" => self.eh_frame_svma.clone(),
             b"__eh_frame_hdr" | b".eh_frame_hdr" => self.eh_frame_hdr_svma.clone(),
             b"__got" | b".got" => self.got_svma.clone(),
+            b"__data" | b".data" => self.data_svma.clone(),
+            b".plt" => self.plt_svma.clone(),
+            b".plt.got" => self.plt_got_svma.clone(),
+            b".plt.sec" => self.plt_sec_svma.clone(),
             _ => None,
         }
     }
@@ -919,21 +4081,49 @@ where
             b"__eh_frame" | b".eh_frame" => self.eh_frame.take(),
             b"__eh_frame_hdr" | b".eh_frame_hdr" => self.eh_frame_hdr.take(),
             b"__debug_frame" | b".debug_frame" => self.debug_frame.take(),
+            b"__gopclntab" | b".gopclntab" => self.gopclntab.take(),
+            _ => None,
+        }
+    }
+    fn section_avma(&mut self, name: &[u8]) -> Option<u64> {
+        match name {
+            b"__eh_frame" | b".eh_frame" => self.eh_frame_avma,
+            b"__eh_frame_hdr" | b".eh_frame_hdr" => self.eh_frame_hdr_avma,
             _ => None,
         }
     }
     fn segment_svma_range(&mut self, name: &[u8]) -> Option<Range<u64>> {
         match name {
             b"__TEXT" => self.text_segment_svma.clone(),
+            b"__TEXT_EXEC" => self.text_exec_segment_svma.clone(),
             _ => None,
         }
     }
     fn segment_data(&mut self, name: &[u8]) -> Option<D> {
         match name {
             b"__TEXT" => self.text_segment.take(),
+            b"__TEXT_EXEC" => self.text_exec_segment.take(),
             _ => None,
         }
     }
+    fn instruction_analysis_for_dwarf_cfi_gaps(&self) -> bool {
+        self.instruction_analysis_for_dwarf_cfi_gaps
+    }
+    fn synthesize_rules_from_prologues(&self) -> bool {
+        self.synthesize_rules_from_prologues
+    }
+    fn function_starts(&mut self) -> Option<Vec<u64>> {
+        self.function_starts.take()
+    }
+    fn signs_return_addresses(&self) -> Option<bool> {
+        self.signs_return_addresses
+    }
+    fn code_id(&mut self) -> Option<CodeId> {
+        self.code_id.take()
+    }
+    fn debug_id(&mut self) -> Option<DebugId> {
+        self.debug_id
+    }
 }
 
 impl<D: Deref<Target = [u8]>> Module<D> {
@@ -943,26 +4133,1906 @@ impl<D: Deref<Target = [u8]>> Module<D> {
         base_avma: u64,
         mut section_info: impl ModuleSectionInfo<D>,
     ) -> Self {
-        let unwind_data = ModuleUnwindDataInternal::new(&mut section_info);
+        let sections = ModuleSections::new(&mut section_info, base_avma);
+        let code_id = section_info.code_id();
+        let debug_id = section_info
+            .debug_id()
+            .or_else(|| code_id.as_ref()?.debug_id());
 
         Self {
-            name,
+            is_rosetta: is_rosetta_module_name(&name),
+            is_wow64_transition: is_wow64_transition_module_name(&name),
+            name: name.into(),
             avma_range,
             base_avma,
             base_svma: section_info.base_svma(),
-            unwind_data: Arc::new(unwind_data),
+            sections: Arc::new(ModuleSectionsSource::Loaded(sections)),
+            signs_return_addresses: section_info.signs_return_addresses(),
+            code_id,
+            debug_id,
+            frame_pointers_guaranteed: false,
+            unwind_hints: Vec::new(),
+            trampolines: Vec::new(),
+        }
+    }
+
+    /// Create a module whose unwind information is only loaded when an address in the
+    /// module is unwound for the first time. `loader` is called at most once and returns
+    /// the section info.
+    ///
+    /// The loader runs in the middle of unwinding, on the thread which unwinds, while a
+    /// lock is held, and so does everything it does: reading files, parsing the
+    /// sections and allocating the index of the unwind information. This is not
+    /// async-signal-safe, and it breaks the guarantee of
+    /// [`MustNotAllocateDuringUnwind`](crate::MustNotAllocateDuringUnwind). Profilers
+    /// which unwind in a signal handler or while the unwound thread is suspended have to
+    /// load the modules beforehand, with [`Module::load`] or the `load_modules_for`
+    /// method of the unwinder.
+    ///
+    /// This saves memory and startup time if many modules are added up front but only
+    /// few of them show up in stacks. Since the section info isn't available when the
+    /// module is added, the base address which is stated in the module has to be passed
+    /// as `base_svma`. Lazily loaded modules don't have a code ID or debug ID, and don't
+    /// know whether they sign return addresses.
+    #[cfg(feature = "std")]
+    pub fn new_lazy<S, L>(
+        name: String,
+        avma_range: core::ops::Range<u64>,
+        base_avma: u64,
+        base_svma: u64,
+        loader: L,
+    ) -> Self
+    where
+        D: Send + 'static,
+        S: ModuleSectionInfo<D>,
+        L: FnOnce() -> S + Send + 'static,
+    {
+        let loader = move || ModuleSections::new(&mut loader(), base_avma);
+        Self {
+            is_rosetta: is_rosetta_module_name(&name),
+            is_wow64_transition: is_wow64_transition_module_name(&name),
+            name: name.into(),
+            avma_range,
+            base_avma,
+            base_svma,
+            sections: Arc::new(ModuleSectionsSource::Lazy {
+                loader: std::sync::Mutex::new(Some(Box::new(loader))),
+                sections: std::sync::OnceLock::new(),
+            }),
+            signs_return_addresses: None,
+            code_id: None,
+            debug_id: None,
+            frame_pointers_guaranteed: false,
+            unwind_hints: Vec::new(),
+            trampolines: Vec::new(),
         }
     }
 
+    /// The number of bytes held by this module. Modules created with
+    /// [`Module::new_lazy`] hold nothing until they are loaded.
+    ///
+    /// Clones of a module share its data, but each clone reports the full amount. The
+    /// `memory_usage` methods of the unwinders count shared data once.
+    pub fn memory_usage(&self) -> ModuleMemoryUsage {
+        self.sections
+            .get_if_loaded()
+            .map(ModuleSections::memory_usage)
+            .unwrap_or_default()
+    }
+
+    /// How much of the module's address range is covered by entries in its unwind
+    /// information, e.g. FDEs or compact unwind info functions, and the
+    /// `max_gap_count` largest address ranges which aren't. Frames in the gaps are
+    /// unwound with instruction analysis or the fallback rule, so large gaps usually
+    /// mean that some of the code was built without unwind information.
+    ///
+    /// Returns `None` for PE and Go modules, whose entries aren't enumerated. Alignment
+    /// padding between functions is usually not covered either, so a full coverage is
+    /// slightly below 1.
+    ///
+    /// For a module created with [`Module::new_lazy`], this runs the loader on the
+    /// calling thread if the module hasn't been loaded yet.
+    pub fn unwind_coverage(&self, max_gap_count: usize) -> Option<UnwindCoverage> {
+        let sections = self.sections.get();
+        #[cfg(feature = "go")]
+        if sections.go_pclntab.is_some() {
+            return None;
+        }
+        let mut covered: Vec<Range<u64>> = sections
+            .unwind_data
+            .covered_relative_ranges(self.base_svma)?
+            .into_iter()
+            .map(|range| {
+                let start = self.base_avma.saturating_add(range.start);
+                let end = self.base_avma.saturating_add(range.end);
+                start.max(self.avma_range.start)..end.min(self.avma_range.end)
+            })
+            .filter(|range| !range.is_empty())
+            .collect();
+        covered.sort_unstable_by_key(|range| range.start);
+
+        let mut coverage = UnwindCoverage {
+            avma_range: self.avma_range.clone(),
+            covered_bytes: 0,
+            largest_gaps: Vec::new(),
+        };
+        let mut gaps = Vec::new();
+        let mut covered_until = self.avma_range.start;
+        for range in covered {
+            if range.start > covered_until {
+                gaps.push(covered_until..range.start);
+            }
+            if range.end > covered_until {
+                coverage.covered_bytes += range.end - range.start.max(covered_until);
+                covered_until = range.end;
+            }
+        }
+        if covered_until < self.avma_range.end {
+            gaps.push(covered_until..self.avma_range.end);
+        }
+        gaps.sort_by_key(|gap| (core::cmp::Reverse(gap.end - gap.start), gap.start));
+        gaps.truncate(max_gap_count);
+        coverage.largest_gaps = gaps;
+        Some(coverage)
+    }
+
+    /// A hash of the unwind data, for finding modules whose unwind data is identical.
+    /// `None` if the unwind data isn't loaded yet.
+    #[cfg(feature = "std")]
+    pub(crate) fn content_hash(&self) -> Option<u64> {
+        let sections = self.sections.get_if_loaded()?;
+        let mut hash = sections.content().hash();
+        hash ^= self.base_svma.rotate_left(17);
+        Some(hash)
+    }
+
+    /// Use the unwind data of `other` if it is identical to the unwind data of this
+    /// module, and drop this module's copy. Returns whether the data is now shared.
+    #[cfg(feature = "std")]
+    pub(crate) fn share_sections_with(&mut self, other: &Module<D>) -> bool {
+        if Arc::ptr_eq(&self.sections, &other.sections) {
+            return true;
+        }
+        let (Some(sections), Some(other_sections)) = (
+            self.sections.get_if_loaded(),
+            other.sections.get_if_loaded(),
+        ) else {
+            return false;
+        };
+        if self.base_svma != other.base_svma || sections.content() != other_sections.content() {
+            return false;
+        }
+        self.sections = other.sections.clone();
+        true
+    }
+
+    /// A module for the same file, mapped at a different address. The unwind data is
+    /// shared with this module.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_address(
+        &self,
+        name: String,
+        avma_range: Range<u64>,
+        base_avma: u64,
+    ) -> Module<D> {
+        Module {
+            name: name.into(),
+            avma_range,
+            base_avma,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the unwind data is used by other clones of this module.
+    #[cfg(feature = "std")]
+    pub(crate) fn has_shared_sections(&self) -> bool {
+        Arc::strong_count(&self.sections) > 1
+    }
+
+    /// Drop the optional text bytes, unless the data is shared with a clone of this
+    /// module. Returns the number of dropped bytes.
+    fn drop_optional_data(&mut self) -> usize {
+        Arc::get_mut(&mut self.sections)
+            .and_then(ModuleSectionsSource::get_mut_if_loaded)
+            .map_or(0, ModuleSections::drop_optional_data)
+    }
+}
+
+/// How much of a module is covered by its unwind information, see
+/// [`Module::unwind_coverage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnwindCoverage {
+    /// The address range of the module (AVMAs).
+    pub avma_range: Range<u64>,
+    /// The number of bytes in `avma_range` which are covered by unwind entries.
+    pub covered_bytes: u64,
+    /// The largest ranges (AVMAs) which aren't covered by unwind entries, from the
+    /// largest to the smallest.
+    pub largest_gaps: Vec<Range<u64>>,
+}
+
+impl UnwindCoverage {
+    /// The covered fraction of the module's address range, between 0 and 1.
+    pub fn covered_fraction(&self) -> f64 {
+        let len = self.avma_range.end.saturating_sub(self.avma_range.start);
+        if len == 0 {
+            return 0.0;
+        }
+        self.covered_bytes as f64 / len as f64
+    }
+}
+
+/// The number of bytes held by a [`Module`], see [`Module::memory_usage`].
+///
+/// The section data is counted with its length, even if `D` doesn't own it, e.g. if it
+/// refers to a memory-mapped file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct ModuleMemoryUsage {
+    /// The unwind sections, e.g. `.eh_frame` and `.eh_frame_hdr`.
+    pub unwind_sections: usize,
+    /// The indexes which framehop built, e.g. for modules without `.eh_frame_hdr`.
+    pub indexes: usize,
+    /// The bytes of the text section, which are used for instruction analysis.
+    pub text: usize,
+}
+
+impl ModuleMemoryUsage {
+    /// The sum of all parts.
+    pub fn total(&self) -> usize {
+        self.unwind_sections + self.indexes + self.text
+    }
+}
+
+impl<D> Module<D> {
+    /// The name or file path of the module, as passed to [`Module::new`].
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The address range where this module is mapped into the process.
     pub fn avma_range(&self) -> core::ops::Range<u64> {
         self.avma_range.clone()
     }
 
+    /// The base address of this module, in the process's address space.
     pub fn base_avma(&self) -> u64 {
         self.base_avma
     }
 
-    pub fn name(&self) -> &str {
-        &self.name
+    /// The base address of this module, as stated in the module. See
+    /// [`ModuleSectionInfo::base_svma`].
+    pub fn base_svma(&self) -> u64 {
+        self.base_svma
+    }
+
+    /// The kind of unwind information which is used for this module.
+    ///
+    /// For a module created with [`Module::new_lazy`], this runs the loader on the
+    /// calling thread if the module hasn't been loaded yet.
+    pub fn unwind_data_kind(&self) -> UnwindDataKind {
+        let sections = self.sections.get();
+        #[cfg(feature = "go")]
+        if sections.go_pclntab.is_some() {
+            return UnwindDataKind::GoPclntab;
+        }
+        sections.unwind_data.kind()
+    }
+
+    /// The index which was built for the module's `.eh_frame` or `.debug_frame`, if the
+    /// module uses one. This doesn't load lazily loaded modules.
+    pub(crate) fn dwarf_cfi_index(&self) -> Option<&DwarfCfiIndex> {
+        match &self.sections.get_if_loaded()?.unwind_data {
+            ModuleUnwindDataInternal::DwarfCfiIndexAndEhFrame { index, .. }
+            | ModuleUnwindDataInternal::DwarfCfiIndexAndDebugFrame { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+
+    /// Whether the unwind information of this module has been loaded. This is only
+    /// false for modules created with [`Module::new_lazy`] which haven't been used yet.
+    #[cfg(feature = "std")]
+    pub fn is_loaded(&self) -> bool {
+        self.sections.is_loaded()
+    }
+
+    /// Run the loader of a module created with [`Module::new_lazy`] now, on the calling
+    /// thread, unless the module has already been loaded. Does nothing for other
+    /// modules.
+    ///
+    /// Call this outside of the unwinding path, e.g. when a module is added, so that
+    /// the loader doesn't run in the middle of unwinding.
+    #[cfg(feature = "std")]
+    pub fn load(&self) {
+        self.sections.get();
+    }
+
+    /// The identifier of the binary, if the [`ModuleSectionInfo`] provided it.
+    pub fn code_id(&self) -> Option<&CodeId> {
+        self.code_id.as_ref()
+    }
+
+    /// The identifier of the module's debug information, if known.
+    pub fn debug_id(&self) -> Option<DebugId> {
+        self.debug_id
+    }
+
+    /// Whether this module belongs to Rosetta 2, the translator which runs x86_64 code on
+    /// Apple silicon: its runtime, or an ahead-of-time translation of an x86_64 binary.
+    /// This is detected from the module's name.
+    ///
+    /// The frames of these modules follow the conventions of the translated x86_64
+    /// code, so unwinding stops at the first such frame, and
+    /// [`FrameUnwindInfo::reached_translation_boundary`] is set.
+    pub fn is_rosetta(&self) -> bool {
+        self.is_rosetta
+    }
+
+    /// Whether this module is `wow64cpu.dll`, which switches 32-bit threads of WOW64
+    /// processes on x86_64 Windows to the 64-bit code which implements their system
+    /// calls. This is detected from the module's name.
+    ///
+    /// The caller of a frame in this module is 32-bit code, whose frames can't be
+    /// unwound with x86_64 rules, so unwinding stops at the first such frame, and
+    /// [`FrameUnwindInfo::reached_wow64_transition`] is set. The 32-bit part of the stack
+    /// can be unwound separately, starting from the thread's 32-bit context.
+    pub fn is_wow64_transition(&self) -> bool {
+        self.is_wow64_transition
+    }
+
+    /// Whether return addresses in this module are unwound with the frame pointer. See
+    /// [`ModuleBuilder::frame_pointers_guaranteed`](crate::ModuleBuilder::frame_pointers_guaranteed).
+    pub fn frame_pointers_guaranteed(&self) -> bool {
+        self.frame_pointers_guaranteed
+    }
+
+    pub(crate) fn set_frame_pointers_guaranteed(&mut self, guaranteed: bool) {
+        self.frame_pointers_guaranteed = guaranteed;
+    }
+
+    /// The address ranges (SVMAs) whose unwind information is overridden. See
+    /// [`ModuleBuilder::unwind_hint`](crate::ModuleBuilder::unwind_hint).
+    pub fn unwind_hints(&self) -> &[(Range<u64>, UnwindHint)] {
+        &self.unwind_hints
+    }
+
+    pub(crate) fn set_unwind_hints(&mut self, hints: Vec<(Range<u64>, UnwindHint)>) {
+        self.unwind_hints = hints;
+    }
+
+    fn unwind_hint_for_svma(&self, svma: u64) -> Option<UnwindHint> {
+        self.unwind_hints
+            .iter()
+            .find(|(range, _)| range.contains(&svma))
+            .map(|(_, hint)| *hint)
+    }
+
+    /// The address ranges (SVMAs) of well-known trampolines in this module. See
+    /// [`ModuleBuilder::trampoline`](crate::ModuleBuilder::trampoline).
+    pub fn trampolines(&self) -> &[(Range<u64>, Trampoline)] {
+        &self.trampolines
+    }
+
+    pub(crate) fn set_trampolines(&mut self, trampolines: Vec<(Range<u64>, Trampoline)>) {
+        self.trampolines = trampolines;
+    }
+
+    fn trampoline_rule_for_svma<R: UnwindRule>(
+        &self,
+        svma: u64,
+        is_first_frame: bool,
+    ) -> Option<R> {
+        let (range, trampoline) = self
+            .trampolines
+            .iter()
+            .find(|(range, _)| range.contains(&svma))?;
+        R::rule_for_trampoline(*trampoline, is_first_frame, svma == range.start)
+    }
+
+    pub(crate) fn signs_return_addresses(&self) -> Option<bool> {
+        self.signs_return_addresses
+    }
+
+    fn matches_descriptor(&self, descriptor: &ModuleDescriptor) -> bool {
+        self.avma_range == descriptor.avma_range
+            && match (&self.code_id, &descriptor.code_id) {
+                (Some(code_id), Some(descriptor_code_id)) => code_id == descriptor_code_id,
+                _ => true,
+            }
+    }
+
+    fn is_in_plt(&self, svma: u64) -> bool {
+        self.sections
+            .get()
+            .plt_svma_ranges
+            .iter()
+            .any(|range| range.contains(&svma))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::ArchX86_64;
+    use crate::MayAllocateDuringUnwind;
+    use alloc::vec;
+
+    type TestUnwinder = UnwinderInternal<Vec<u8>, ArchX86_64, MayAllocateDuringUnwind>;
+
+    fn module(avma_range: Range<u64>) -> Module<Vec<u8>> {
+        Module::new(
+            String::from("test"),
+            avma_range.clone(),
+            avma_range.start,
+            ExplicitModuleSectionInfo::default(),
+        )
+    }
+
+    fn module_ranges(unwinder: &TestUnwinder) -> Vec<Range<u64>> {
+        unwinder.modules.iter().map(|m| m.avma_range()).collect()
+    }
+
+    #[test]
+    fn test_module_accessors() {
+        let module = Module::<Vec<u8>>::new(
+            String::from("libtest.so"),
+            0x1000..0x3000,
+            0x800,
+            ExplicitModuleSectionInfo {
+                base_svma: 0x100,
+                ..Default::default()
+            },
+        );
+        assert_eq!(module.name(), "libtest.so");
+        assert_eq!(module.avma_range(), 0x1000..0x3000);
+        assert_eq!(module.base_avma(), 0x800);
+        assert_eq!(module.base_svma(), 0x100);
+        assert_eq!(module.unwind_data_kind(), UnwindDataKind::None);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_lazy_module() {
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(Module::new_lazy(
+            String::from("lazy"),
+            0x1000..0x2000,
+            0x1000,
+            0,
+            ExplicitModuleSectionInfo::<Vec<u8>>::default,
+        ));
+        assert!(!unwinder.modules[0].is_loaded());
+
+        let mut cache = Cache::<_, MayAllocateDuringUnwind>::new();
+        let stack = [0x0, 0x0, 0x0, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut regs = crate::x86_64::UnwindRegsX86_64::new(0x1100, 0x10, 0x10);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x1100),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert!(unwinder.modules[0].is_loaded());
+        assert_eq!(unwinder.modules[0].unwind_data_kind(), UnwindDataKind::None);
+
+        unwinder.add_module(Module::new_lazy(
+            String::from("lazy2"),
+            0x2000..0x3000,
+            0x2000,
+            0,
+            ExplicitModuleSectionInfo::<Vec<u8>>::default,
+        ));
+        assert!(!unwinder.modules[1].is_loaded());
+        // Only the second module wasn't loaded yet, and addresses outside of modules are
+        // ignored.
+        assert_eq!(
+            unwinder.load_modules_for([0x1100, 0x2100, 0x2200, 0x5000]),
+            1
+        );
+        assert!(unwinder.modules[1].is_loaded());
+    }
+
+    #[test]
+    fn test_memory_budget() {
+        let module = |avma_range: Range<u64>| {
+            Module::new(
+                String::from("test"),
+                avma_range.clone(),
+                avma_range.start,
+                ExplicitModuleSectionInfo {
+                    eh_frame: Some(vec![0; 100]),
+                    text_svma: Some(0..1000),
+                    text: Some(vec![0; 1000]),
+                    instruction_analysis_for_dwarf_cfi_gaps: true,
+                    ..Default::default()
+                },
+            )
+        };
+        let usage = module(0x1000..0x2000).memory_usage();
+        assert_eq!(usage.unwind_sections, 100);
+        assert_eq!(usage.text, 1000);
+        let index_size = usage.indexes;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(module(0x1000..0x2000));
+        unwinder.add_module(module(0x2000..0x3000));
+        assert_eq!(unwinder.memory_usage(), 2 * usage.total());
+
+        // Dropping the text of the first module is enough.
+        unwinder.set_memory_budget(Some(2000));
+        assert_eq!(unwinder.modules[0].memory_usage().text, 0);
+        assert_eq!(unwinder.modules[1].memory_usage().text, 1000);
+
+        // Unwind sections and indexes are never dropped.
+        unwinder.set_memory_budget(Some(0));
+        assert_eq!(unwinder.memory_usage(), 2 * (100 + index_size));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_memory_budget_shared_sections() {
+        use core::sync::atomic::AtomicUsize;
+
+        let libfoo = Module::new(
+            String::from("libfoo.so"),
+            0x1000..0x2000,
+            0x1000,
+            ExplicitModuleSectionInfo {
+                eh_frame: Some(vec![0; 100]),
+                text_svma: Some(0..1000),
+                text: Some(vec![0; 1000]),
+                instruction_analysis_for_dwarf_cfi_gaps: true,
+                ..Default::default()
+            },
+        );
+        let usage = libfoo.memory_usage().total();
+        let libfoo_mapped_twice =
+            libfoo.with_address(String::from("libfoo.so"), 0x2000..0x3000, 0x2000);
+
+        let exceeded_usage = Arc::new(AtomicUsize::new(0));
+        let exceeded_usage_clone = exceeded_usage.clone();
+        let mut unwinder = TestUnwinder::new();
+        unwinder.set_diagnostics_callback(Some(Arc::new(move |diagnostic: &Diagnostic| {
+            if let Diagnostic::MemoryBudgetExceeded { memory_usage, .. } = diagnostic {
+                exceeded_usage_clone.store(*memory_usage, Ordering::Relaxed);
+            }
+        })));
+        unwinder.add_module(libfoo);
+        unwinder.add_module(libfoo_mapped_twice);
+        // The shared sections are counted once.
+        assert_eq!(unwinder.memory_usage(), usage);
+
+        // The shared text bytes can't be dropped, which is reported.
+        unwinder.set_memory_budget(Some(1000));
+        assert_eq!(unwinder.modules[0].memory_usage().text, 1000);
+        assert_eq!(exceeded_usage.load(Ordering::Relaxed), usage);
+    }
+
+    #[test]
+    fn test_fallback_rule() {
+        use crate::x86_64::{UnwindRegsX86_64, UnwindRuleX86_64};
+
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let address = FrameAddress::from_return_address(0x1800).unwrap();
+        let mut unwind = |unwinder: &TestUnwinder, cache: &mut Cache<'_, _, _>| {
+            let mut regs = UnwindRegsX86_64::new(0x1800, 0x8, 0x10);
+            unwinder.unwind_frame(
+                address,
+                &mut regs,
+                cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        // By default, addresses outside of any module use the frame pointer.
+        let mut unwinder = TestUnwinder::new();
+        let mut cache = Cache::new();
+        assert_eq!(unwind(&unwinder, &mut cache), Ok(Some(0x1234)));
+
+        // The cached rule is not reused after the fallback rule changes.
+        unwinder.set_fallback_rule(UnwindRuleX86_64::EndOfStack);
+        assert_eq!(unwind(&unwinder, &mut cache), Ok(None));
+    }
+
+    #[test]
+    fn test_frame_pointer_only() {
+        use crate::x86_64::UnwindRegsX86_64;
+        use crate::ModuleBuilder;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(
+            ModuleBuilder::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo::default(),
+            )
+            .unwind_hint(0x1000..0x1100, UnwindHint::EndOfStack)
+            .build(),
+        );
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut cache = Cache::new();
+        let mut unwind = |unwinder: &TestUnwinder, cache: &mut Cache<'_, _, _>, address| {
+            let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+            unwinder.unwind_frame(
+                address,
+                &mut regs,
+                cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+        let address = FrameAddress::from_instruction_pointer(0x11008);
+        assert_eq!(unwind(&unwinder, &mut cache, address), Ok(None));
+
+        // The module's unwind information is ignored, even if its rule is cached.
+        unwinder.set_frame_pointer_only(true);
+        assert_eq!(unwind(&unwinder, &mut cache, address), Ok(Some(0x2222)));
+        assert_eq!(cache.unwind_stats.frame_pointer_count, 1);
+
+        // The caller was found with the frame pointer, and isn't in any module.
+        let mut info = FrameUnwindInfo::default();
+        let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+        assert_eq!(
+            unwinder.unwind_frame(
+                FrameAddress::from_return_address(0x11009).unwrap(),
+                &mut regs,
+                &mut cache,
+                &mut |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(()),
+                Some(&mut info),
+                &mut Tracer::disabled(),
+            ),
+            Ok(Some(0x2222))
+        );
+        assert_eq!(info.confidence, FrameConfidence::Guessed);
+
+        // Root address ranges still end the stack.
+        unwinder.add_root_address_range(0x11000..0x11010);
+        assert_eq!(unwind(&unwinder, &mut cache, address), Ok(None));
+    }
+
+    #[test]
+    fn test_frame_pointer_verification() {
+        use crate::x86_64::UnwindRegsX86_64;
+        use crate::ModuleBuilder;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(
+            ModuleBuilder::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo::default(),
+            )
+            .unwind_hint(0x1000..0x1100, UnwindHint::FramePointer)
+            .unwind_hint(0x1100..0x1200, UnwindHint::NoFrame)
+            .unwind_hint(0x1200..0x1300, UnwindHint::EndOfStack)
+            .build(),
+        );
+        unwinder.set_frame_pointer_only(true);
+        unwinder.set_frame_pointer_verification_interval(NonZeroU64::new(1));
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut cache = Cache::new();
+        let mut unwind = |cache: &mut Cache<'_, _, _>, address| {
+            let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+            unwinder.unwind_frame(
+                address,
+                &mut regs,
+                cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+        for address in [0x11008, 0x11108, 0x11208] {
+            let address = FrameAddress::from_return_address(address).unwrap();
+            assert_eq!(unwind(&mut cache, address), Ok(Some(0x2222)));
+        }
+        // The frame in the root function can't be verified, and the frame without a
+        // frame pointer doesn't match.
+        assert_eq!(cache.unwind_stats.frame_pointer_count, 3);
+        assert_eq!(cache.unwind_stats.frame_pointer_verification_count, 2);
+        assert_eq!(cache.unwind_stats.frame_pointer_mismatch_count, 1);
+    }
+
+    #[test]
+    fn test_frame_pointer_verification_interval() {
+        use crate::x86_64::UnwindRegsX86_64;
+        use crate::ModuleBuilder;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(
+            ModuleBuilder::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo::default(),
+            )
+            .frame_pointers_guaranteed(true)
+            .build(),
+        );
+        unwinder.set_frame_pointer_only(true);
+        unwinder.set_frame_pointer_verification_interval(NonZeroU64::new(2));
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut cache = Cache::new();
+        for address in [0x11008, 0x11108, 0x11208, 0x11308] {
+            let address = FrameAddress::from_return_address(address).unwrap();
+            let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+            let result = unwinder.unwind_frame(
+                address,
+                &mut regs,
+                &mut cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            );
+            assert_eq!(result, Ok(Some(0x2222)));
+        }
+        // The verifications use the module's guaranteed frame pointers, which are
+        // counted too, but they don't shift the interval.
+        assert_eq!(cache.unwind_stats.frame_pointer_count, 6);
+        assert_eq!(cache.unwind_stats.frame_pointer_verification_count, 2);
+        assert_eq!(cache.unwind_stats.frame_pointer_mismatch_count, 0);
+    }
+
+    #[test]
+    fn test_instruction_pointer_adjustment() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        #[rustfmt::skip]
+        let debug_frame = vec![
+            // CIE: version 1, code alignment 1, data alignment -8, return address in r16
+            0x10, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x01, 0x78, 0x10,
+            // DW_CFA_def_cfa: rsp + 8; DW_CFA_offset: r16 at cfa - 8; padding
+            0x0c, 0x07, 0x08, 0x90, 0x01, 0x00, 0x00,
+            // FDE for 0x1000..0x1010, which ends with a call to a noreturn function
+            0x18, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // DW_CFA_def_cfa_offset: 16; padding
+            0x0e, 0x10, 0x00, 0x00,
+            // FDE for 0x1010..0x1020
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |adjustment, ip| {
+            let mut unwinder = TestUnwinder::new();
+            unwinder.add_module(Module::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo {
+                    debug_frame: Some(debug_frame.clone()),
+                    ..Default::default()
+                },
+            ));
+            unwinder.set_instruction_pointer_adjustment(adjustment);
+            let mut regs = UnwindRegsX86_64::new(ip, 0x10, 0x0);
+            unwinder.unwind_frame(
+                FrameAddress::from_instruction_pointer(ip),
+                &mut regs,
+                &mut Cache::new(),
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        // The instruction pointer is the return address of the noreturn call, at the
+        // start of the next function.
+        assert_eq!(
+            unwind(InstructionPointerAdjustment::None, 0x11010),
+            Ok(Some(0x1111))
+        );
+        assert_eq!(
+            unwind(InstructionPointerAdjustment::SubtractOne, 0x11010),
+            Ok(Some(0x2222))
+        );
+        // Inside a function, both find the same FDE.
+        assert_eq!(
+            unwind(InstructionPointerAdjustment::None, 0x11008),
+            Ok(Some(0x2222))
+        );
+        assert_eq!(
+            unwind(InstructionPointerAdjustment::SubtractOne, 0x11008),
+            Ok(Some(0x2222))
+        );
+    }
+
+    #[test]
+    fn test_eh_frame_avma() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        #[rustfmt::skip]
+        let eh_frame = vec![
+            // CIE: augmentation "zR", code alignment 1, data alignment -8, return address
+            // in r16, FDE pointer encoding DW_EH_PE_pcrel | DW_EH_PE_sdata4
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, b'z', b'R', 0x00,
+            0x01, 0x78, 0x10, 0x01, 0x1b,
+            // DW_CFA_def_cfa: rsp + 16; DW_CFA_offset: r16 at cfa - 8; padding
+            0x0c, 0x07, 0x10, 0x90, 0x01, 0x00, 0x00,
+            // FDE for 0x1000..0x1010, if the eh_frame data is at SVMA 0x3000
+            0x10, 0x00, 0x00, 0x00, 0x1c, 0x00, 0x00, 0x00,
+            0xe0, 0xdf, 0xff, 0xff, 0x10, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |eh_frame_avma| {
+            let mut unwinder = TestUnwinder::new();
+            // The section headers say that eh_frame is at SVMA 0x2000, but the data was
+            // read from AVMA 0x13000, which corresponds to SVMA 0x3000.
+            unwinder.add_module(Module::new(
+                String::from("test"),
+                0x10000..0x14000,
+                0x10000,
+                ExplicitModuleSectionInfo {
+                    eh_frame_svma: Some(0x2000..0x2000 + eh_frame.len() as u64),
+                    eh_frame: Some(eh_frame.clone()),
+                    eh_frame_avma,
+                    ..Default::default()
+                },
+            ));
+            let mut regs = UnwindRegsX86_64::new(0x11008, 0x10, 0x0);
+            unwinder.unwind_frame(
+                FrameAddress::from_instruction_pointer(0x11008),
+                &mut regs,
+                &mut Cache::new(),
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        assert_eq!(unwind(Some(0x13000)), Ok(Some(0x2222)));
+        assert_ne!(unwind(None), Ok(Some(0x2222)));
+    }
+
+    #[test]
+    fn test_unwind_hints() {
+        use crate::x86_64::UnwindRegsX86_64;
+        use crate::ModuleBuilder;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(
+            ModuleBuilder::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo::default(),
+            )
+            .unwind_hint(0x1000..0x1100, UnwindHint::EndOfStack)
+            .unwind_hint(0x1100..0x1200, UnwindHint::NoFrame)
+            .build(),
+        );
+        let stack = [0x0, 0x0, 0x1111, 0x2222];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |address| {
+            let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+            unwinder.unwind_frame(
+                address,
+                &mut regs,
+                &mut Cache::new(),
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        assert_eq!(
+            unwind(FrameAddress::from_instruction_pointer(0x11008)),
+            Ok(None)
+        );
+        // The hint also applies to return addresses, which would otherwise be unwound
+        // with the frame pointer.
+        assert_eq!(
+            unwind(FrameAddress::from_return_address(0x11108).unwrap()),
+            Ok(Some(0x1111))
+        );
+        assert_eq!(
+            unwind(FrameAddress::from_return_address(0x11208).unwrap()),
+            Ok(Some(0x2222))
+        );
+    }
+
+    #[test]
+    fn test_trampolines() {
+        use crate::x86_64::UnwindRegsX86_64;
+        use crate::ModuleBuilder;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(
+            ModuleBuilder::new(
+                String::from("test"),
+                0x10000..0x12000,
+                0x10000,
+                ExplicitModuleSectionInfo::default(),
+            )
+            .recognize_trampolines([
+                ("_start", 0x1000..0x1100),
+                ("_objc_msgSend", 0x1100..0x1200),
+                ("_dl_runtime_resolve_xsavec", 0x1200..0x1300),
+                ("main", 0x1300..0x1400),
+            ])
+            .build(),
+        );
+        assert_eq!(unwinder.modules[0].trampolines().len(), 3);
+        let stack = [0x0, 0x0, 0x1111, 0x2222, 0x3333];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |address| {
+            let mut regs = UnwindRegsX86_64::new(0x0, 0x10, 0x10);
+            unwinder.unwind_frame(
+                address,
+                &mut regs,
+                &mut Cache::new(),
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        assert_eq!(
+            unwind(FrameAddress::from_return_address(0x11008).unwrap()),
+            Ok(None)
+        );
+        assert_eq!(
+            unwind(FrameAddress::from_instruction_pointer(0x11108)),
+            Ok(Some(0x1111))
+        );
+        assert_eq!(
+            unwind(FrameAddress::from_instruction_pointer(0x11200)),
+            Ok(Some(0x3333))
+        );
+    }
+
+    #[test]
+    fn test_root_detection() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        // A frame pointer chain which continues past the root function at 0x5000.
+        let stack = [0x0, 0x20, 0x5008, 0x0, 0x40, 0x6008, 0x0, 0x0, 0x0];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |unwinder: &TestUnwinder| {
+            let mut regs = UnwindRegsX86_64::new(0x1000, 0x8, 0x8);
+            let mut cache = Cache::new();
+            let mut frames = Vec::new();
+            let mut address = FrameAddress::from_instruction_pointer(0x1000);
+            let result = loop {
+                frames.push(address.address());
+                match unwinder.unwind_frame(
+                    address,
+                    &mut regs,
+                    &mut cache,
+                    &mut read_stack,
+                    None,
+                    &mut Tracer::disabled(),
+                ) {
+                    Ok(Some(return_address)) => {
+                        address = FrameAddress::from_return_address(return_address).unwrap();
+                    }
+                    result => break result,
+                }
+            };
+            (frames, result)
+        };
+
+        let mut unwinder = TestUnwinder::new();
+        let (frames, result) = unwind(&unwinder);
+        assert_eq!(frames, [0x1000, 0x5008, 0x6008]);
+        assert!(result.is_err());
+
+        unwinder.add_root_address_range(0x5000..0x5100);
+        assert_eq!(unwind(&unwinder), (vec![0x1000, 0x5008], Ok(None)));
+
+        unwinder.clear_root_address_ranges();
+        unwinder.set_stack_end_sentinels(vec![0x30]);
+        assert_eq!(unwind(&unwinder), (vec![0x1000, 0x5008], Ok(None)));
+    }
+
+    #[test]
+    fn test_jit_ranges() {
+        use crate::x86_64::{UnwindRegsX86_64, UnwindRuleX86_64};
+
+        // The return address of a leaf function at 0x1000 is on top of the stack.
+        let stack = [0x0, 0x3008, 0x0];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut cache = Cache::new();
+        let mut unwind = |unwinder: &TestUnwinder| {
+            let mut regs = UnwindRegsX86_64::new(0x1000, 0x8, 0x0);
+            unwinder.unwind_frame(
+                FrameAddress::from_instruction_pointer(0x1000),
+                &mut regs,
+                &mut cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.set_fallback_rule(UnwindRuleX86_64::EndOfStack);
+        assert_eq!(unwind(&unwinder), Ok(None));
+
+        unwinder.set_jit_ranges(vec![JitRange {
+            avma_range: 0x1000..0x1100,
+            hint: UnwindHint::NoFrame,
+        }]);
+        assert_eq!(unwind(&unwinder), Ok(Some(0x3008)));
+        unwinder.clear_jit_ranges();
+        assert_eq!(unwind(&unwinder), Ok(None));
+        assert_eq!(cache.unwind_stats.jit_range_count, 1);
+    }
+
+    #[test]
+    fn test_foreign_unwinder() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_foreign_unwinder(ForeignUnwinder {
+            avma_range: 0x7000..0x8000,
+            callback: Arc::new(|address, regs: &mut UnwindRegsX86_64, read_stack| {
+                // The JVM frame stores the caller's sp at bp + 8.
+                assert_eq!(address.address(), 0x7010);
+                let caller_sp = read_stack.read_u64(regs.bp() + 8).unwrap();
+                regs.set_sp(caller_sp);
+                Ok(Some(0x1008))
+            }),
+        });
+        let stack = [0x0, 0x0, 0x0, 0x40];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut regs = UnwindRegsX86_64::new(0x7010, 0x8, 0x10);
+        let mut cache = Cache::new();
+        let result = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x7010),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(result, Ok(Some(0x1008)));
+        assert_eq!(regs.sp(), 0x40);
+        assert_eq!(cache.unwind_stats.foreign_unwinder_count, 1);
+    }
+
+    #[test]
+    fn test_set_modules() {
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(module(0x1000..0x2000));
+        let generation = unwinder.modules_generation;
+
+        unwinder.set_modules(vec![
+            module(0x5000..0x6000),
+            module(0x3000..0x4000),
+            module(0x3800..0x4800),
+        ]);
+        assert_ne!(unwinder.modules_generation, generation);
+        let ranges: Vec<_> = unwinder.modules.iter().map(|m| m.avma_range()).collect();
+        assert_eq!(ranges, vec![0x3800..0x4800, 0x5000..0x6000]);
+    }
+
+    #[test]
+    fn test_sync_modules() {
+        let descriptor = |avma_range: Range<u64>| ModuleDescriptor {
+            name: String::from("test"),
+            avma_range,
+            code_id: None,
+        };
+        let mut unwinder = TestUnwinder::new();
+        let mut loaded = Vec::new();
+        let mut load = |descriptor: &ModuleDescriptor| {
+            loaded.push(descriptor.avma_range.clone());
+            Some(module(descriptor.avma_range.clone()))
+        };
+        let outcome = unwinder.sync_modules(
+            [descriptor(0x3000..0x4000), descriptor(0x1000..0x2000)],
+            &mut load,
+        );
+        assert_eq!(outcome.added_module_count, 2);
+        let generation = unwinder.modules_generation;
+
+        // A re-scan without changes keeps the modules and the generation.
+        let outcome = unwinder.sync_modules(
+            [descriptor(0x3000..0x4000), descriptor(0x1000..0x2000)],
+            &mut load,
+        );
+        assert_eq!(outcome.kept_module_count, 2);
+        assert_eq!(unwinder.modules[0].avma_range(), 0x1000..0x2000);
+        assert_eq!(unwinder.modules_generation, generation);
+
+        // Only the new module is loaded.
+        let outcome = unwinder.sync_modules(
+            [descriptor(0x1000..0x2000), descriptor(0x5000..0x6000)],
+            &mut load,
+        );
+        assert_eq!(
+            outcome,
+            SyncModulesOutcome {
+                added_module_count: 1,
+                removed_module_count: 1,
+                kept_module_count: 1,
+            }
+        );
+        assert_ne!(unwinder.modules_generation, generation);
+        assert_eq!(loaded, vec![0x3000..0x4000, 0x1000..0x2000, 0x5000..0x6000]);
+        let ranges: Vec<_> = unwinder.modules.iter().map(|m| m.avma_range()).collect();
+        assert_eq!(ranges, vec![0x1000..0x2000, 0x5000..0x6000]);
+    }
+
+    #[test]
+    fn test_add_module_overlap() {
+        let mut unwinder = TestUnwinder::new();
+        assert_eq!(
+            unwinder.add_module(module(0x1000..0x2000)),
+            AddModuleOutcome::Added
+        );
+        assert_eq!(
+            unwinder.add_module(module(0x3000..0x4000)),
+            AddModuleOutcome::Added
+        );
+        assert_eq!(
+            unwinder.add_module(module(0x2000..0x3000)),
+            AddModuleOutcome::Added
+        );
+        assert_eq!(
+            module_ranges(&unwinder),
+            vec![0x1000..0x2000, 0x2000..0x3000, 0x3000..0x4000]
+        );
+
+        // Same start address: the old module is replaced.
+        assert_eq!(
+            unwinder.add_module(module(0x1000..0x1800)),
+            AddModuleOutcome::ReplacedOverlapping {
+                removed_module_count: 1
+            }
+        );
+        assert_eq!(
+            module_ranges(&unwinder),
+            vec![0x1000..0x1800, 0x2000..0x3000, 0x3000..0x4000]
+        );
+
+        // Overlapping two modules partially.
+        assert_eq!(
+            unwinder.add_module(module(0x2800..0x3800)),
+            AddModuleOutcome::ReplacedOverlapping {
+                removed_module_count: 2
+            }
+        );
+        assert_eq!(
+            module_ranges(&unwinder),
+            vec![0x1000..0x1800, 0x2800..0x3800]
+        );
+        assert_eq!(unwinder.max_known_code_address(), 0x3800);
+    }
+
+    #[test]
+    fn test_overlap_diagnostic() {
+        use core::sync::atomic::AtomicUsize;
+
+        let removed = Arc::new(AtomicUsize::new(0));
+        let removed_clone = removed.clone();
+        let mut unwinder = TestUnwinder::new();
+        unwinder.set_diagnostics_callback(Some(Arc::new(move |diagnostic: &Diagnostic| {
+            if let Diagnostic::OverlappingModulesReplaced {
+                removed_module_count,
+                ..
+            } = diagnostic
+            {
+                removed_clone.fetch_add(*removed_module_count, Ordering::Relaxed);
+            }
+        })));
+        unwinder.add_module(module(0x1000..0x2000));
+        assert_eq!(removed.load(Ordering::Relaxed), 0);
+        unwinder.add_module(module(0x1000..0x2000));
+        assert_eq!(removed.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_frame_info() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(module(0x1000..0x2000));
+        let mut cache = Cache::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut info = FrameUnwindInfo::default();
+
+        let mut regs = UnwindRegsX86_64::new(0x1800, 0x0, 0x10);
+        let address = FrameAddress::from_instruction_pointer(0x1800);
+        let res = unwinder.unwind_frame(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            Some(&mut info),
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert!(!info.from_cache);
+        let details = info.error_details.as_ref().unwrap();
+        assert_eq!(&*details.module_name, "test");
+        assert_eq!(details.unwind_data_kind, UnwindDataKind::None);
+        assert_eq!(details.lookup_address, 0x1800);
+        assert_eq!(details.relative_lookup_address, 0x800);
+        assert_eq!(details.error, UnwinderError::NoModuleUnwindData);
+        assert_eq!(info.fallback_reason, Some(FallbackReason::NoUnwindData));
+
+        let mut regs = UnwindRegsX86_64::new(0x1800, 0x0, 0x10);
+        let res = unwinder.unwind_frame(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            Some(&mut info),
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert!(info.from_cache);
+        assert_eq!(info.error_details, None);
+        assert_eq!(info.fallback_reason, None);
+
+        // An address outside of all modules.
+        let mut regs = UnwindRegsX86_64::new(0x2800, 0x0, 0x10);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x2800),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            Some(&mut info),
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert_eq!(info.error_details, None);
+        assert_eq!(info.fallback_reason, Some(FallbackReason::NoModule));
+    }
+
+    #[test]
+    fn test_fallback_policy() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(module(0x1000..0x2000));
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let mut unwind = |unwinder: &TestUnwinder, cache: &mut Cache<'_, _, _>, address| {
+            let mut regs = UnwindRegsX86_64::new(address, 0x0, 0x10);
+            unwinder.unwind_frame(
+                FrameAddress::from_instruction_pointer(address),
+                &mut regs,
+                cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            )
+        };
+
+        let mut cache = Cache::new();
+        assert_eq!(unwind(&unwinder, &mut cache, 0x1800), Ok(Some(0x1234)));
+
+        unwinder.set_fallback_policy(FallbackPolicy::StopStack);
+        let mut cache = Cache::new();
+        assert_eq!(unwind(&unwinder, &mut cache, 0x1800), Ok(None));
+        // The policy also applies to fallback rules from the cache.
+        assert_eq!(unwind(&unwinder, &mut cache, 0x1800), Ok(None));
+
+        unwinder.set_fallback_policy(FallbackPolicy::ReturnError);
+        let mut cache = Cache::new();
+        for _ in 0..2 {
+            assert_eq!(
+                unwind(&unwinder, &mut cache, 0x1800),
+                Err(Error::UnreliableFrame(FallbackReason::NoUnwindData))
+            );
+            assert_eq!(
+                unwind(&unwinder, &mut cache, 0x2800),
+                Err(Error::UnreliableFrame(FallbackReason::NoModule))
+            );
+        }
+    }
+
+    #[test]
+    fn test_instruction_analysis_for_dwarf_cfi_gaps() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let elf_module = |instruction_analysis_for_dwarf_cfi_gaps| {
+            Module::new(
+                String::from("test"),
+                0x1000..0x2000,
+                0x1000,
+                ExplicitModuleSectionInfo {
+                    // No FDEs at all, so no address is covered by the CFI.
+                    eh_frame: Some(vec![]),
+                    text_svma: Some(0x800..0x802),
+                    // pop rbp; ret
+                    text: Some(vec![0x5d, 0xc3]),
+                    instruction_analysis_for_dwarf_cfi_gaps,
+                    ..Default::default()
+                },
+            )
+        };
+        let stack = [0x0, 0x0, 0x20, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let address = FrameAddress::from_instruction_pointer(0x1800);
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(elf_module(true));
+        let mut cache = Cache::new();
+        let mut regs = UnwindRegsX86_64::new(0x1800, 0x10, 0x0);
+        let res = unwinder.unwind_frame(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert_eq!(regs.sp(), 0x20);
+        assert_eq!(regs.bp(), 0x20);
+        assert_eq!(cache.unwind_stats.instruction_analysis_count, 1);
+
+        // Without opting in, the frame pointer fallback is used, which stops here
+        // because rbp is zero.
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(elf_module(false));
+        let mut cache = Cache::new();
+        let mut regs = UnwindRegsX86_64::new(0x1800, 0x10, 0x0);
+        let res = unwinder.unwind_frame(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(None));
+        assert_eq!(cache.unwind_stats.instruction_analysis_count, 0);
+    }
+
+    #[test]
+    fn test_synthesize_rules_from_prologues() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let module = |function_starts| {
+            Module::new(
+                String::from("test"),
+                0x1000..0x2000,
+                0x1000,
+                ExplicitModuleSectionInfo {
+                    text_svma: Some(0x800..0x80a),
+                    // push r15; push rbx; sub rsp, 0x10; nop; nop; ret
+                    text: Some(vec![
+                        0x41, 0x57, 0x53, 0x48, 0x83, 0xec, 0x10, 0x90, 0x90, 0xc3,
+                    ]),
+                    synthesize_rules_from_prologues: true,
+                    function_starts,
+                    ..Default::default()
+                },
+            )
+        };
+        let stack = [0x0, 0x0, 0x0, 0x0, 0x1234];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let address = FrameAddress::from_return_address(0x1808).unwrap();
+
+        for function_starts in [Some(vec![0x800]), None] {
+            let mut unwinder = TestUnwinder::new();
+            unwinder.add_module(module(function_starts));
+            let mut cache = Cache::new();
+            let mut regs = UnwindRegsX86_64::new(0x1808, 0x0, 0x0);
+            let res = unwinder.unwind_frame(
+                address,
+                &mut regs,
+                &mut cache,
+                &mut read_stack,
+                None,
+                &mut Tracer::disabled(),
+            );
+            assert_eq!(res, Ok(Some(0x1234)));
+            assert_eq!(regs.sp(), 0x28);
+            assert_eq!(cache.unwind_stats.instruction_analysis_count, 1);
+        }
+    }
+
+    #[test]
+    fn test_plt_stub_rule() {
+        use crate::x86_64::UnwindRegsX86_64;
+
+        let mut unwinder = TestUnwinder::new();
+        unwinder.add_module(Module::new(
+            String::from("test"),
+            0x1000..0x2000,
+            0x1000,
+            ExplicitModuleSectionInfo {
+                plt_sec_svma: Some(0x800..0x840),
+                ..Default::default()
+            },
+        ));
+        let mut cache = Cache::new();
+        let stack = [0x1234, 0x0, 0x0];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+
+        let mut regs = UnwindRegsX86_64::new(0x1810, 0x0, 0x0);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x1810),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(Some(0x1234)));
+        assert_eq!(regs.sp(), 0x8);
+        assert_eq!(cache.unwind_stats.plt_stub_count, 1);
+
+        // Outside of the PLT, the frame pointer fallback is used.
+        let mut regs = UnwindRegsX86_64::new(0x1900, 0x0, 0x0);
+        let res = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(0x1900),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            None,
+            &mut Tracer::disabled(),
+        );
+        assert_eq!(res, Ok(None));
+        assert_eq!(cache.unwind_stats.plt_stub_count, 1);
+    }
+
+    #[cfg(feature = "macho")]
+    #[test]
+    fn test_macho_text_exec_segment() {
+        let section_info = |text_svma| ExplicitModuleSectionInfo {
+            text_svma,
+            text: Some(vec![0xc3]),
+            text_segment_svma: Some(0x0..0x4000),
+            text_segment: Some(vec![0; 0x4000]),
+            text_exec_segment_svma: Some(0x4000..0x8000),
+            text_exec_segment: Some(vec![0; 0x4000]),
+            ..Default::default()
+        };
+
+        // The code is in __TEXT_EXEC, like in kernel extensions.
+        let text_data = macho_text_data(&mut section_info(Some(0x4100..0x4101))).unwrap();
+        assert_eq!(text_data.svma_range, 0x4000..0x8000);
+
+        // The code is in __TEXT.
+        let text_data = macho_text_data(&mut section_info(Some(0x1000..0x1001))).unwrap();
+        assert_eq!(text_data.svma_range, 0x0..0x4000);
+
+        // The __text section lies outside both segments, so only its own bytes are used.
+        let text_data = macho_text_data(&mut section_info(Some(0x9000..0x9001))).unwrap();
+        assert_eq!(text_data.svma_range, 0x9000..0x9001);
+        assert_eq!(&text_data.bytes[..], [0xc3]);
+    }
+
+    #[test]
+    fn test_skip_frames() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+
+        let mut iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .skip_frames(2);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x2345).unwrap()))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x3456).unwrap()))
+        );
+        assert_eq!(iter.next(), Ok(None));
+
+        // Skipping more frames than the stack has.
+        let mut iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .skip_frames(10);
+        assert_eq!(iter.next(), Ok(None));
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+
+        let mut cache = CacheX86_64::<_>::new();
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x1234).unwrap()))
+        );
+        let checkpoint = iter.checkpoint();
+        assert_eq!(checkpoint.regs().bp(), 0x20);
+        assert!(!checkpoint.is_done());
+
+        let mut cache = CacheX86_64::<_>::new();
+        let mut iter = UnwindIterator::resume(&unwinder, checkpoint, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x2345).unwrap()))
+        );
+        assert_eq!(
+            iter.next(),
+            Ok(Some(FrameAddress::from_return_address(0x3456).unwrap()))
+        );
+        assert_eq!(iter.next(), Ok(None));
+        assert!(iter.checkpoint().is_done());
+    }
+
+    #[test]
+    fn test_iterator_fingerprint() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwindRuleX86_64, UnwinderX86_64};
+
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x0, 0x2345];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        let mut expected = StackFingerprint::new(unwinder.modules_generation());
+        while let Ok(Some(frame)) = iter.next() {
+            expected.push(frame);
+        }
+        let fingerprint = iter.fingerprint();
+        assert_eq!(fingerprint, expected);
+        assert_eq!(fingerprint.frame_count(), 3);
+
+        // Changing the settings makes the fingerprints of the same stack differ.
+        unwinder.set_fallback_rule(UnwindRuleX86_64::UseFramePointer);
+        let mut iter = unwinder.iter_frames(0x1000, regs, &mut cache, &mut read_stack);
+        while let Ok(Some(_)) = iter.next() {}
+        assert_ne!(
+            iter.fingerprint().modules_generation(),
+            fingerprint.modules_generation()
+        );
+    }
+
+    #[test]
+    fn test_frame_confidence() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.add_module(Module::new(
+            String::from("libfoo.so"),
+            0x10000..0x20000,
+            0x10000,
+            ExplicitModuleSectionInfo::default(),
+        ));
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x11234, 0x0, 0x2345];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x10100, 0x0, 0x10);
+
+        // The second iteration uses the cached rules.
+        for _ in 0..2 {
+            let mut iter = unwinder.iter_frames(0x10100, regs, &mut cache, &mut read_stack);
+            let mut frames = Vec::new();
+            while let Ok(Some(frame)) = iter.next_with_confidence() {
+                frames.push(frame);
+            }
+            assert_eq!(
+                frames,
+                vec![
+                    (
+                        FrameAddress::from_instruction_pointer(0x10100),
+                        FrameConfidence::Certain
+                    ),
+                    (
+                        FrameAddress::from_return_address(0x11234).unwrap(),
+                        FrameConfidence::Likely
+                    ),
+                    (
+                        FrameAddress::from_return_address(0x2345).unwrap(),
+                        FrameConfidence::Guessed
+                    ),
+                ]
+            );
+        }
+
+        // Rules of JIT ranges and foreign unwinders aren't from unwind information.
+        let mut unwind_confidence = |unwinder: &UnwinderX86_64<Vec<u8>>| {
+            let mut info = FrameUnwindInfo::default();
+            let result = unwinder.unwind_frame_with_info(
+                FrameAddress::from_instruction_pointer(0x10100),
+                &mut regs.clone(),
+                &mut cache,
+                &mut read_stack,
+                &mut info,
+            );
+            (result, info.confidence)
+        };
+        unwinder.add_jit_range(JitRange {
+            avma_range: 0x10000..0x10200,
+            hint: UnwindHint::FramePointer,
+        });
+        assert_eq!(
+            unwind_confidence(&unwinder),
+            (Ok(Some(0x11234)), FrameConfidence::Likely)
+        );
+        unwinder.add_foreign_unwinder(0x10000..0x10200, |_, _, _| Ok(Some(0x2345)));
+        assert_eq!(
+            unwind_confidence(&unwinder),
+            (Ok(Some(0x2345)), FrameConfidence::Guessed)
+        );
+        unwinder.clear_foreign_unwinders();
+        unwinder.add_foreign_unwinder(0x10000..0x10200, |_, _, _| Ok(Some(0x11234)));
+        assert_eq!(
+            unwind_confidence(&unwinder),
+            (Ok(Some(0x11234)), FrameConfidence::Likely)
+        );
+    }
+
+    #[test]
+    fn test_next_relative() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        unwinder.add_module(Module::new(
+            String::from("libfoo.so"),
+            0x10000..0x20000,
+            0x10000,
+            ExplicitModuleSectionInfo {
+                code_id: Some(CodeId::ElfBuildId(vec![1, 2, 3, 4])),
+                ..Default::default()
+            },
+        ));
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x11234, 0x0, 0x2345];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x10100, 0x0, 0x10);
+
+        let code_id = CodeId::ElfBuildId(vec![1, 2, 3, 4]);
+        let mut iter = unwinder.iter_frames(0x10100, regs, &mut cache, &mut read_stack);
+        assert_eq!(
+            iter.next_relative(),
+            Ok(Some(RelativeFrame::Module {
+                code_id: Some(&code_id),
+                relative_address: 0x100,
+                is_return_address: false,
+            }))
+        );
+        assert_eq!(
+            iter.next_relative(),
+            Ok(Some(RelativeFrame::Module {
+                code_id: Some(&code_id),
+                relative_address: 0x1234,
+                is_return_address: true,
+            }))
+        );
+        assert_eq!(
+            iter.next_relative(),
+            Ok(Some(RelativeFrame::Unknown(
+                FrameAddress::from_return_address(0x2345).unwrap()
+            )))
+        );
+        assert_eq!(iter.next_relative(), Ok(None));
+    }
+
+    #[test]
+    fn test_collect_stack() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+        let return_address = |address| FrameAddress::from_return_address(address).unwrap();
+
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 4);
+        assert_eq!(result.end_state, StackEndState::Root);
+
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .collect_stack(2);
+        assert_eq!(
+            result.frames,
+            [
+                FrameAddress::from_instruction_pointer(0x1000),
+                return_address(0x1234)
+            ]
+        );
+        assert_eq!(result.end_state, StackEndState::MaxDepth);
+
+        // The frames before the error are kept.
+        let mut read_truncated_stack =
+            |addr: u64| stack[..6].get((addr / 8) as usize).copied().ok_or(());
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_truncated_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 3);
+        assert!(matches!(result.end_state, StackEndState::Error(_)));
+
+        // Unwinding stops at a frame in the Rosetta runtime.
+        let mut rosetta_runtime = Module::new(
+            String::from("/usr/libexec/rosetta/runtime"),
+            0x2000..0x3000,
+            0x2000,
+            ExplicitModuleSectionInfo::default(),
+        );
+        // Gives the runtime a cacheable rule, which precompute_rules can store.
+        rosetta_runtime.set_frame_pointers_guaranteed(true);
+        unwinder.add_module(rosetta_runtime);
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 3);
+        assert_eq!(result.end_state, StackEndState::TranslationBoundary);
+
+        // No rules are precomputed for the Rosetta runtime, so they don't hide the
+        // boundary.
+        let mut precomputed_cache = CacheX86_64::<_>::new();
+        assert_eq!(
+            unwinder.precompute_rules(&mut precomputed_cache, [return_address(0x2345)]),
+            0
+        );
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut precomputed_cache, &mut read_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 3);
+        assert_eq!(result.end_state, StackEndState::TranslationBoundary);
+
+        // Unwinding stops at the WOW64 transition frame of a 32-bit thread.
+        let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut wow64cpu = Module::new(
+            String::from(r"C:\Windows\System32\wow64cpu.dll"),
+            0x2000..0x3000,
+            0x2000,
+            ExplicitModuleSectionInfo::default(),
+        );
+        wow64cpu.set_frame_pointers_guaranteed(true);
+        unwinder.add_module(wow64cpu);
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 3);
+        assert_eq!(result.end_state, StackEndState::Wow64Transition);
+
+        let mut precomputed_cache = CacheX86_64::<_>::new();
+        assert_eq!(
+            unwinder.precompute_rules(&mut precomputed_cache, [return_address(0x2345)]),
+            0
+        );
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut precomputed_cache, &mut read_stack)
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 3);
+        assert_eq!(result.end_state, StackEndState::Wow64Transition);
+    }
+
+    #[test]
+    fn test_budget() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_budget(UnwindBudget::with_max_steps(100))
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 4);
+        assert_eq!(result.end_state, StackEndState::Root);
+
+        // Every frame takes one step for the frame and one for reading the saved
+        // registers, so the third frame runs out of steps.
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_budget(UnwindBudget::with_max_steps(3))
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 2);
+        assert_eq!(result.end_state, StackEndState::Error(Error::Timeout));
+
+        // The registers may be half-unwound after a timeout, so the iterator stops.
+        let mut iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_budget(UnwindBudget::with_max_steps(3));
+        assert!(iter.next().unwrap().is_some());
+        assert!(iter.next().unwrap().is_some());
+        assert_eq!(iter.next(), Err(Error::Timeout));
+        assert_eq!(iter.next(), Ok(None));
+
+        let result = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_budget(UnwindBudget {
+                max_steps: None,
+                should_stop: Some(|| true),
+            })
+            .collect_stack(10);
+        assert_eq!(result.frames.len(), 1);
+        assert_eq!(result.end_state, StackEndState::Error(Error::Timeout));
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        use crate::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+
+        let unwinder = UnwinderX86_64::<Vec<u8>>::new();
+        let mut cache = CacheX86_64::<_>::new();
+        let stack = [0x0, 0x0, 0x20, 0x1234, 0x30, 0x2345, 0x0, 0x3456];
+        let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+        let regs = UnwindRegsX86_64::new(0x1000, 0x0, 0x10);
+
+        for (max_counted_frames, expected_summary) in [
+            (
+                10,
+                TruncationSummary {
+                    omitted_frame_count: 2,
+                    root_reached: true,
+                },
+            ),
+            (
+                1,
+                TruncationSummary {
+                    omitted_frame_count: 1,
+                    root_reached: false,
+                },
+            ),
+        ] {
+            let mut iter = unwinder
+                .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+                .with_depth_limit(DepthLimit {
+                    max_frames: 2,
+                    max_counted_frames,
+                });
+            assert_eq!(
+                iter.next(),
+                Ok(Some(FrameAddress::from_instruction_pointer(0x1000)))
+            );
+            assert_eq!(
+                iter.next(),
+                Ok(Some(FrameAddress::from_return_address(0x1234).unwrap()))
+            );
+            assert_eq!(iter.truncation_summary(), None);
+            assert_eq!(iter.next(), Ok(None));
+            assert_eq!(iter.truncation_summary(), Some(&expected_summary));
+            assert_eq!(iter.next(), Ok(None));
+        }
+
+        // The stack has exactly four frames, so a limit of four frames leaves nothing out.
+        let mut iter = unwinder
+            .iter_frames(0x1000, regs, &mut cache, &mut read_stack)
+            .with_depth_limit(DepthLimit {
+                max_frames: 4,
+                max_counted_frames: 10,
+            });
+        for _ in 0..4 {
+            assert!(matches!(iter.next(), Ok(Some(_))));
+        }
+        assert_eq!(iter.next(), Ok(None));
+        assert_eq!(iter.truncation_summary(), None);
     }
 }
@@ -0,0 +1,144 @@
+use alloc::vec::Vec;
+
+use crate::error::Error;
+use crate::memory_reader::MemoryReader;
+use crate::unwinder::Unwinder;
+use crate::FrameAddress;
+
+/// Records when modules were added and removed, and provides an unwinder with the
+/// modules which were loaded at a given time.
+///
+/// This is useful when processing a recorded profile offline: a sample has to be
+/// unwound with the modules which were loaded when the sample was taken, not with the
+/// modules at the end of the recording. Timestamps can be in any unit, as long as the
+/// module events and the samples use the same one.
+///
+/// Moving forward in time only applies the events since the previous call, so samples
+/// should be processed in roughly chronological order. Moving backward in time replays
+/// all events from the start.
+pub struct VersionedModuleStore<U: Unwinder> {
+    /// The unwinder without any of the recorded events applied.
+    base: U,
+    /// The unwinder with the first `applied_event_count` events applied.
+    current: U,
+    applied_event_count: usize,
+    /// The recorded events, sorted by timestamp. Events with the same timestamp are in
+    /// the order in which they were recorded.
+    events: Vec<(u64, ModuleEvent<U::Module>)>,
+}
+
+enum ModuleEvent<M> {
+    Add(M),
+    Remove(u64),
+}
+
+impl<U: Unwinder> VersionedModuleStore<U>
+where
+    U::Module: Clone,
+{
+    /// Create a store. `unwinder` is used as the unwinder before the first event, so it
+    /// can carry settings and modules which never change.
+    pub fn new(unwinder: U) -> Self {
+        Self {
+            current: unwinder.clone(),
+            base: unwinder,
+            applied_event_count: 0,
+            events: Vec::new(),
+        }
+    }
+
+    /// Record that `module` was loaded at `timestamp`.
+    pub fn add_module(&mut self, timestamp: u64, module: U::Module) {
+        self.insert_event(timestamp, ModuleEvent::Add(module));
+    }
+
+    /// Record that the module whose address range starts at `module_avma_range_start`
+    /// was unloaded at `timestamp`.
+    pub fn remove_module(&mut self, timestamp: u64, module_avma_range_start: u64) {
+        self.insert_event(timestamp, ModuleEvent::Remove(module_avma_range_start));
+    }
+
+    fn insert_event(&mut self, timestamp: u64, event: ModuleEvent<U::Module>) {
+        let index = self.events.partition_point(|(t, _)| *t <= timestamp);
+        self.events.insert(index, (timestamp, event));
+        if index < self.applied_event_count {
+            // The event happened before the time of the current unwinder, so the current
+            // unwinder has to be rebuilt.
+            self.current = self.base.clone();
+            self.applied_event_count = 0;
+        }
+    }
+
+    /// Returns the unwinder with the modules which were loaded at `timestamp`, including
+    /// the modules which were added at exactly this timestamp.
+    pub fn unwinder_at(&mut self, timestamp: u64) -> &U {
+        let event_count = self.events.partition_point(|(t, _)| *t <= timestamp);
+        if event_count < self.applied_event_count {
+            self.current = self.base.clone();
+            self.applied_event_count = 0;
+        }
+        for (_, event) in &self.events[self.applied_event_count..event_count] {
+            match event {
+                ModuleEvent::Add(module) => {
+                    self.current.add_module(module.clone());
+                }
+                ModuleEvent::Remove(start) => self.current.remove_module(*start),
+            }
+        }
+        self.applied_event_count = event_count;
+        &self.current
+    }
+
+    /// Unwind a single frame of a sample which was taken at `timestamp`. See
+    /// [`Unwinder::unwind_frame`].
+    pub fn unwind_frame<F>(
+        &mut self,
+        timestamp: u64,
+        address: FrameAddress,
+        regs: &mut U::UnwindRegs,
+        cache: &mut U::Cache<'_>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.unwinder_at(timestamp)
+            .unwind_frame(address, regs, cache, read_stack)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::x86_64::UnwinderX86_64;
+    use crate::{ExplicitModuleSectionInfo, Module};
+    use alloc::string::String;
+
+    fn module(avma_range: core::ops::Range<u64>) -> Module<Vec<u8>> {
+        Module::new(
+            String::from("test"),
+            avma_range.clone(),
+            avma_range.start,
+            ExplicitModuleSectionInfo::default(),
+        )
+    }
+
+    #[test]
+    fn test_versioned_module_store() {
+        let mut store = VersionedModuleStore::new(UnwinderX86_64::<Vec<u8>>::new());
+        store.add_module(10, module(0x1000..0x2000));
+        store.add_module(20, module(0x3000..0x4000));
+        store.remove_module(30, 0x3000);
+
+        assert_eq!(store.unwinder_at(5).max_known_code_address(), 0);
+        assert_eq!(store.unwinder_at(10).max_known_code_address(), 0x2000);
+        assert_eq!(store.unwinder_at(25).max_known_code_address(), 0x4000);
+        assert_eq!(store.unwinder_at(35).max_known_code_address(), 0x2000);
+        // Going back in time.
+        assert_eq!(store.unwinder_at(15).max_known_code_address(), 0x2000);
+
+        // An event which is recorded late still applies to earlier samples.
+        store.add_module(12, module(0x5000..0x6000));
+        assert_eq!(store.unwinder_at(15).max_known_code_address(), 0x6000);
+    }
+}
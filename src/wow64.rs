@@ -0,0 +1,34 @@
+/// Whether `name` is the path of `wow64cpu.dll`, the module which switches a WOW64
+/// thread between its 32-bit code and the 64-bit code of the WOW64 layer, on x86_64
+/// Windows.
+///
+/// Every 64-bit stack of a WOW64 thread ends in this module: the caller of its
+/// transition frame is the 32-bit code which made the system call, or which the thread
+/// was simulating when it was interrupted. Windows paths are compared without regard to
+/// case.
+pub(crate) fn is_wow64_transition_module_name(name: &str) -> bool {
+    let file_name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    file_name.eq_ignore_ascii_case("wow64cpu.dll")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_wow64_transition_module_name() {
+        assert!(is_wow64_transition_module_name(
+            r"C:\Windows\System32\wow64cpu.dll"
+        ));
+        assert!(is_wow64_transition_module_name(
+            r"C:\WINDOWS\System32\WOW64CPU.DLL"
+        ));
+        assert!(is_wow64_transition_module_name("wow64cpu.dll"));
+        assert!(!is_wow64_transition_module_name(
+            r"C:\Windows\System32\wow64.dll"
+        ));
+        assert!(!is_wow64_transition_module_name(
+            r"C:\Windows\SysWOW64\ntdll.dll"
+        ));
+    }
+}
@@ -3,12 +3,98 @@ use gimli::{
     UnwindContextStorage, UnwindSection, UnwindTableRow, X86_64,
 };
 
-use super::{arch::ArchX86_64, unwind_rule::UnwindRuleX86_64, unwindregs::UnwindRegsX86_64};
+use super::{
+    arch::ArchX86_64,
+    unwind_rule::UnwindRuleX86_64,
+    unwindregs::{FullUnwindRegsX86_64, Reg, UnwindRegsX86_64},
+};
 use crate::dwarf::{
-    eval_cfa_rule, eval_register_rule, ConversionError, DwarfUnwindRegs, DwarfUnwinderError,
-    DwarfUnwinding,
+    saved_register_prefetch, ConversionError, DwarfCfiSectionAddresses, DwarfUnwindRegs,
+    DwarfUnwinderError, DwarfUnwinding, FullDwarfUnwindRegs, ResolvedRegisterRule, RuleEvaluation,
+    SingleFrameUnwindError,
 };
+use crate::memory_reader::{MemoryReadError, MemoryReader};
+use crate::unwind_regs::UnwindRegs;
 use crate::unwind_result::UnwindResult;
+use crate::unwind_step::{ReadRequest, Resumable, Step};
+use crate::FrameAddress;
+
+/// Unwind a single frame with the CFI in an `.eh_frame` section, without setting up an
+/// [`Unwinder`](crate::Unwinder) and a [`Module`](crate::Module). This is meant for tools
+/// which already know which section covers the frame, e.g. debuggers which step one
+/// frame at a time.
+///
+/// `address` has to be translated into the SVMA space of the binary that `eh_frame_data`
+/// comes from, and `section_addresses` are the SVMAs of its sections. The FDE for the
+/// address is found with a linear search through the section, and nothing is cached, so
+/// use an unwinder to unwind many frames. On success, `regs` contain the caller's
+/// registers, and the return address is returned, or `None` at the end of the stack.
+pub fn unwind_frame_with_eh_frame<F: MemoryReader>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut UnwindRegsX86_64,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError> {
+    crate::dwarf::unwind_frame_with_eh_frame::<ArchX86_64, F>(
+        eh_frame_data,
+        section_addresses,
+        address,
+        regs,
+        read_stack,
+    )
+}
+
+/// Like [`unwind_frame_with_eh_frame`], but recovers every register which has a rule in
+/// the CFI, not just the ones which are needed to keep unwinding. See
+/// [`FullUnwindRegsX86_64`].
+pub fn unwind_full_frame_with_eh_frame<F: MemoryReader>(
+    eh_frame_data: &[u8],
+    section_addresses: &DwarfCfiSectionAddresses,
+    address: FrameAddress,
+    regs: &mut FullUnwindRegsX86_64,
+    read_stack: &mut F,
+) -> Result<Option<u64>, SingleFrameUnwindError> {
+    crate::dwarf::unwind_full_frame_with_eh_frame::<ArchX86_64, _, F>(
+        eh_frame_data,
+        section_addresses,
+        address,
+        regs,
+        read_stack,
+    )
+}
+
+impl DwarfUnwindRegs for FullUnwindRegsX86_64 {
+    fn get(&self, register: Register) -> Option<u64> {
+        self.get_dwarf_reg(register.0)
+    }
+}
+
+impl FullDwarfUnwindRegs for FullUnwindRegsX86_64 {
+    const RA: Register = X86_64::RA;
+
+    fn pc(&self) -> u64 {
+        self.ip()
+    }
+
+    fn set_pc(&mut self, pc: u64) {
+        self.set_ip(pc)
+    }
+
+    fn sp(&self) -> u64 {
+        self.sp()
+    }
+
+    fn set_sp(&mut self, sp: u64) {
+        self.set_sp(sp)
+    }
+
+    fn set_unknown(&mut self, register: Register) {
+        if let Some(reg) = Reg::from_dwarf(register.0) {
+            self.clear(reg);
+        }
+    }
+}
 
 impl DwarfUnwindRegs for UnwindRegsX86_64 {
     fn get(&self, register: Register) -> Option<u64> {
@@ -22,16 +108,16 @@ impl DwarfUnwindRegs for UnwindRegsX86_64 {
 }
 
 impl DwarfUnwinding for ArchX86_64 {
-    fn unwind_frame<F, R, UCS, ES>(
+    type DwarfEvaluation<R: Reader, S: EvaluationStorage<R>> = DwarfEvaluationX86_64<R, S>;
+
+    fn unwind_frame<R, UCS, ES>(
         section: &impl UnwindSection<R>,
         unwind_info: &UnwindTableRow<R::Offset, UCS>,
         encoding: Encoding,
-        regs: &mut Self::UnwindRegs,
+        regs: &Self::UnwindRegs,
         is_first_frame: bool,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<Self::UnwindRule>, DwarfUnwinderError>
+    ) -> Result<UnwindResult<Self::UnwindRule, DwarfEvaluationX86_64<R, ES>>, DwarfUnwinderError>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         R: Reader,
         UCS: UnwindContextStorage<R::Offset>,
         ES: EvaluationStorage<R>,
@@ -44,47 +130,146 @@ impl DwarfUnwinding for ArchX86_64 {
             Ok(unwind_rule) => return Ok(UnwindResult::ExecRule(unwind_rule)),
             Err(_err) => {
                 // Could not translate into a cacheable unwind rule. Fall back to the generic path.
-                // eprintln!("Unwind rule translation failed: {:?}", err);
+                #[cfg(feature = "log")]
+                log::trace!("Unwind rule translation failed: {:?}", _err);
             }
         }
 
-        let cfa = eval_cfa_rule::<R, _, ES>(section, cfa_rule, encoding, regs)
-            .ok_or(DwarfUnwinderError::CouldNotRecoverCfa)?;
+        Ok(UnwindResult::Uncacheable(DwarfEvaluationX86_64 {
+            encoding,
+            is_first_frame,
+            bp_rule: ResolvedRegisterRule::new(section, bp_rule),
+            ra_rule: ResolvedRegisterRule::new(section, ra_rule),
+            cfa: 0,
+            new_bp: 0,
+            state: DwarfEvaluationStateX86_64::Cfa(RuleEvaluation::cfa(
+                section, cfa_rule, encoding, regs,
+            )),
+        }))
+    }
 
-        let ip = regs.ip();
-        let bp = regs.bp();
-        let sp = regs.sp();
+    fn rule_if_uncovered_by_fde() -> Self::UnwindRule {
+        UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp
+    }
+}
 
-        let new_bp = eval_register_rule::<R, F, _, ES>(
-            section, bp_rule, cfa, encoding, bp, regs, read_stack,
-        )
-        .unwrap_or(bp);
+/// The evaluation of a CFI row which couldn't be translated into an
+/// [`UnwindRuleX86_64`]: the CFA, then bp, then the return address.
+pub struct DwarfEvaluationX86_64<R: Reader, S: EvaluationStorage<R>> {
+    encoding: Encoding,
+    is_first_frame: bool,
+    bp_rule: ResolvedRegisterRule<R>,
+    ra_rule: ResolvedRegisterRule<R>,
+    cfa: u64,
+    new_bp: u64,
+    state: DwarfEvaluationStateX86_64<R, S>,
+}
 
-        let return_address = match eval_register_rule::<R, F, _, ES>(
-            section, ra_rule, cfa, encoding, ip, regs, read_stack,
-        ) {
-            Some(ra) => ra,
-            None => {
-                read_stack(cfa - 8).map_err(|_| DwarfUnwinderError::CouldNotRecoverReturnAddress)?
-            }
-        };
+enum DwarfEvaluationStateX86_64<R: Reader, S: EvaluationStorage<R>> {
+    Cfa(RuleEvaluation<R, S>),
+    Bp(RuleEvaluation<R, S>),
+    Ra(RuleEvaluation<R, S>),
+    /// The return address rule couldn't be evaluated, so the return address is read
+    /// from where it usually is.
+    RaSlot(Option<Result<u64, MemoryReadError>>),
+    Done(Result<u64, DwarfUnwinderError>),
+}
 
-        if cfa == sp && return_address == ip {
+impl<R: Reader, S: EvaluationStorage<R>> DwarfEvaluationX86_64<R, S> {
+    fn finish(
+        &self,
+        regs: &mut UnwindRegsX86_64,
+        return_address: u64,
+    ) -> Result<u64, DwarfUnwinderError> {
+        let cfa = self.cfa;
+        if cfa == regs.sp() && return_address == regs.ip() {
             return Err(DwarfUnwinderError::DidNotAdvance);
         }
-        if !is_first_frame && cfa < regs.sp() {
+        if !self.is_first_frame && cfa < regs.sp() {
             return Err(DwarfUnwinderError::StackPointerMovedBackwards);
         }
 
         regs.set_ip(return_address);
-        regs.set_bp(new_bp);
+        regs.set_bp(self.new_bp);
         regs.set_sp(cfa);
+        Ok(return_address)
+    }
+}
 
-        Ok(UnwindResult::Uncacheable(return_address))
+impl<R: Reader, S: EvaluationStorage<R>> Resumable<UnwindRegsX86_64>
+    for DwarfEvaluationX86_64<R, S>
+{
+    type Output = Result<u64, DwarfUnwinderError>;
+
+    fn step(&mut self, regs: &mut UnwindRegsX86_64) -> Step<Self::Output> {
+        use DwarfEvaluationStateX86_64 as State;
+        loop {
+            let next = match &mut self.state {
+                State::Cfa(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(None) => State::Done(Err(DwarfUnwinderError::CouldNotRecoverCfa)),
+                    Step::Done(Some(cfa)) => {
+                        self.cfa = cfa;
+                        let mut evaluation = RuleEvaluation::register(
+                            &self.bp_rule,
+                            cfa,
+                            self.encoding,
+                            regs.bp(),
+                            regs,
+                        );
+                        if self.is_first_frame {
+                            evaluation = evaluation.with_red_zone(regs.sp());
+                        }
+                        if let Some((first, last)) =
+                            saved_register_prefetch(cfa, &self.bp_rule, &self.ra_rule)
+                        {
+                            evaluation = evaluation.with_prefetch(first, last);
+                        }
+                        State::Bp(evaluation)
+                    }
+                },
+                State::Bp(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(new_bp) => {
+                        self.new_bp = new_bp.unwrap_or(regs.bp());
+                        State::Ra(RuleEvaluation::register(
+                            &self.ra_rule,
+                            self.cfa,
+                            self.encoding,
+                            regs.ip(),
+                            regs,
+                        ))
+                    }
+                },
+                State::Ra(evaluation) => match evaluation.step(regs) {
+                    Step::Read(request) => return Step::Read(request),
+                    Step::Done(Some(return_address)) => {
+                        State::Done(self.finish(regs, return_address))
+                    }
+                    Step::Done(None) => State::RaSlot(None),
+                },
+                State::RaSlot(provided) => match provided.take() {
+                    None => return Step::Read(ReadRequest::u64(self.cfa.wrapping_sub(8))),
+                    Some(Ok(return_address)) => State::Done(self.finish(regs, return_address)),
+                    Some(Err(_)) => {
+                        State::Done(Err(DwarfUnwinderError::CouldNotRecoverReturnAddress))
+                    }
+                },
+                State::Done(result) => return Step::Done(*result),
+            };
+            self.state = next;
+        }
     }
 
-    fn rule_if_uncovered_by_fde() -> Self::UnwindRule {
-        UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        use DwarfEvaluationStateX86_64 as State;
+        match &mut self.state {
+            State::Cfa(evaluation) | State::Bp(evaluation) | State::Ra(evaluation) => {
+                evaluation.provide(value)
+            }
+            State::RaSlot(provided) => *provided = Some(value),
+            State::Done(_) => {}
+        }
     }
 }
 
@@ -144,18 +329,28 @@ fn translate_into_unwind_rule<RO: ReaderOffset>(
                 let bp_cfa_offset = register_rule_to_cfa_offset(bp_rule)?
                     .ok_or(ConversionError::FramePointerRuleDoesNotRestoreBp)?;
                 if *offset == 16 && bp_cfa_offset == -16 {
-                    Ok(UnwindRuleX86_64::UseFramePointer)
-                } else {
-                    // TODO: Maybe handle this case. This case has been observed in _ffi_call_unix64,
-                    // which has the following unwind table:
-                    //
-                    // 00000060 00000024 0000001c FDE cie=00000048 pc=000de548...000de6a6
-                    //   0xde548: CFA=reg7+8: reg16=[CFA-8]
-                    //   0xde562: CFA=reg6+32: reg6=[CFA-16], reg16=[CFA-8]
-                    //   0xde5ad: CFA=reg7+8: reg16=[CFA-8]
-                    //   0xde668: CFA=reg7+8: reg6=[CFA-16], reg16=[CFA-8]
-                    Err(ConversionError::FramePointerRuleHasStrangeBpOffset)
+                    return Ok(UnwindRuleX86_64::UseFramePointer);
+                }
+                // This case has been observed in _ffi_call_unix64, which has the
+                // following unwind table:
+                //
+                // 00000060 00000024 0000001c FDE cie=00000048 pc=000de548...000de6a6
+                //   0xde548: CFA=reg7+8: reg16=[CFA-8]
+                //   0xde562: CFA=reg6+32: reg6=[CFA-16], reg16=[CFA-8]
+                //   0xde5ad: CFA=reg7+8: reg16=[CFA-8]
+                //   0xde668: CFA=reg7+8: reg6=[CFA-16], reg16=[CFA-8]
+                let bp_storage_offset = offset + bp_cfa_offset;
+                if offset % 8 != 0 || bp_storage_offset % 8 != 0 {
+                    return Err(ConversionError::FramePointerRuleHasStrangeBpOffset);
                 }
+                let sp_offset_from_bp_by_8 = i16::try_from(offset / 8)
+                    .map_err(|_| ConversionError::FramePointerRuleHasStrangeBpOffset)?;
+                let bp_storage_offset_from_bp_by_8 = i16::try_from(bp_storage_offset / 8)
+                    .map_err(|_| ConversionError::FramePointerRuleHasStrangeBpOffset)?;
+                Ok(UnwindRuleX86_64::UseFramePointerWithOffsets {
+                    sp_offset_from_bp_by_8,
+                    bp_storage_offset_from_bp_by_8,
+                })
             }
             _ => Err(ConversionError::CfaIsOffsetFromUnknownRegister),
         },
@@ -0,0 +1,246 @@
+use alloc::vec::Vec;
+use gimli::{
+    CfaRule, CieOrFde, DebugFrame, EhFrame, EndianSlice, LittleEndian, Reader, RegisterRule,
+    UnwindContext, UnwindSection, X86_64,
+};
+
+use crate::dwarf::{BaseAddresses, DwarfCfiIndexError, DwarfCfiSectionAddresses};
+
+/// How the CFA of an [`EbpfUnwindRowX86_64`] is computed.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EbpfCfaType {
+    /// The CFA is `rbp + cfa_offset`.
+    Rbp = 1,
+    /// The CFA is `rsp + cfa_offset`.
+    Rsp = 2,
+    /// The CFA is computed by a DWARF expression or from another register, which the
+    /// table can't describe.
+    Expression = 3,
+    /// The row marks the end of an FDE. Addresses from this row up to the next row are
+    /// not covered by unwind information.
+    EndOfFde = 4,
+}
+
+/// How the caller's rbp of an [`EbpfUnwindRowX86_64`] is recovered.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EbpfRbpType {
+    /// rbp is not modified by this function.
+    Unchanged = 0,
+    /// rbp is saved at `cfa + rbp_offset`.
+    Offset = 1,
+    /// rbp is saved in another register.
+    Register = 2,
+    /// rbp is recovered with a DWARF expression.
+    Expression = 3,
+    /// The return address is undefined, i.e. this is the outermost frame.
+    UndefinedReturnAddress = 4,
+}
+
+/// A row of an [`EbpfUnwindTableX86_64`]. It applies from `pc` up to the `pc` of the
+/// next row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EbpfUnwindRowX86_64 {
+    /// The start address of the row, as an SVMA.
+    pub pc: u64,
+    /// How the CFA is computed.
+    pub cfa_type: EbpfCfaType,
+    /// How the caller's rbp is recovered.
+    pub rbp_type: EbpfRbpType,
+    /// The offset which is added to the CFA register.
+    pub cfa_offset: i16,
+    /// The offset from the CFA at which rbp is saved, for [`EbpfRbpType::Offset`].
+    pub rbp_offset: i16,
+}
+
+impl EbpfUnwindRowX86_64 {
+    /// The size of a serialized row, see [`EbpfUnwindTableX86_64::to_bytes`].
+    pub const SIZE: usize = 16;
+
+    fn end_of_fde(pc: u64) -> Self {
+        Self {
+            pc,
+            cfa_type: EbpfCfaType::EndOfFde,
+            rbp_type: EbpfRbpType::Unchanged,
+            cfa_offset: 0,
+            rbp_offset: 0,
+        }
+    }
+}
+
+/// A flat unwind table for one module, in the layout used by eBPF-based DWARF unwinders
+/// such as the one in Parca Agent. The table is generated from the module's DWARF CFI on
+/// the host and copied into a BPF map, so that the kernel side can unwind without
+/// parsing DWARF.
+///
+/// Only the CFA and the caller's rbp are described; the return address is always read
+/// from `cfa - 8`. Offsets which don't fit into an `i16` are reported with
+/// [`EbpfCfaType::Expression`] or [`EbpfRbpType::Expression`], like the rules which the
+/// table can't describe at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EbpfUnwindTableX86_64 {
+    rows: Vec<EbpfUnwindRowX86_64>,
+}
+
+impl EbpfUnwindTableX86_64 {
+    /// Generate the table from the raw bytes of an eh_frame section.
+    pub fn try_from_eh_frame_data(
+        eh_frame_data: &[u8],
+        section_addresses: &DwarfCfiSectionAddresses,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let mut eh_frame = EhFrame::from(EndianSlice::new(eh_frame_data, LittleEndian));
+        eh_frame.set_address_size(8);
+
+        Self::try_new(eh_frame, &section_addresses.bases())
+    }
+
+    /// Generate the table from the raw bytes of a debug_frame section.
+    pub fn try_from_debug_frame_data(
+        debug_frame_data: &[u8],
+        section_addresses: &DwarfCfiSectionAddresses,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let mut debug_frame = DebugFrame::from(EndianSlice::new(debug_frame_data, LittleEndian));
+        debug_frame.set_address_size(8);
+
+        Self::try_new(debug_frame, &section_addresses.bases())
+    }
+
+    fn try_new<R: Reader, US: UnwindSection<R>>(
+        unwind_section: US,
+        bases: &BaseAddresses,
+    ) -> Result<Self, DwarfCfiIndexError> {
+        let mut ctx = UnwindContext::new();
+        let mut fdes = Vec::new();
+        let mut entries_iter = unwind_section.entries(bases);
+        while let Some(entry) = entries_iter.next()? {
+            if let CieOrFde::Fde(partial_fde) = entry {
+                fdes.push(partial_fde.parse(US::cie_from_offset)?);
+            }
+        }
+        fdes.sort_by_key(|fde| fde.initial_address());
+
+        let mut rows: Vec<EbpfUnwindRowX86_64> = Vec::new();
+        for fde in &fdes {
+            if let Some(last) = rows.last() {
+                if last.cfa_type == EbpfCfaType::EndOfFde && last.pc == fde.initial_address() {
+                    rows.pop();
+                }
+            }
+            let mut table = fde.rows(&unwind_section, bases, &mut ctx)?;
+            while let Some(row) = table.next_row()? {
+                let (cfa_type, cfa_offset) = match row.cfa() {
+                    CfaRule::RegisterAndOffset { register, offset } => {
+                        match (*register, i16::try_from(*offset)) {
+                            (X86_64::RBP, Ok(offset)) => (EbpfCfaType::Rbp, offset),
+                            (X86_64::RSP, Ok(offset)) => (EbpfCfaType::Rsp, offset),
+                            _ => (EbpfCfaType::Expression, 0),
+                        }
+                    }
+                    CfaRule::Expression(_) => (EbpfCfaType::Expression, 0),
+                };
+                let (rbp_type, rbp_offset) = match row.register(X86_64::RA) {
+                    RegisterRule::Undefined => (EbpfRbpType::UndefinedReturnAddress, 0),
+                    _ => match row.register(X86_64::RBP) {
+                        RegisterRule::Undefined | RegisterRule::SameValue => {
+                            (EbpfRbpType::Unchanged, 0)
+                        }
+                        RegisterRule::Offset(offset) => match i16::try_from(offset) {
+                            Ok(offset) => (EbpfRbpType::Offset, offset),
+                            Err(_) => (EbpfRbpType::Expression, 0),
+                        },
+                        RegisterRule::Register(_) => (EbpfRbpType::Register, 0),
+                        _ => (EbpfRbpType::Expression, 0),
+                    },
+                };
+                rows.push(EbpfUnwindRowX86_64 {
+                    pc: row.start_address(),
+                    cfa_type,
+                    rbp_type,
+                    cfa_offset,
+                    rbp_offset,
+                });
+            }
+            rows.push(EbpfUnwindRowX86_64::end_of_fde(fde.end_address()));
+        }
+        Ok(Self { rows })
+    }
+
+    /// The rows of the table, sorted by `pc`.
+    pub fn rows(&self) -> &[EbpfUnwindRowX86_64] {
+        &self.rows
+    }
+
+    /// Serialize the table. Every row is [`EbpfUnwindRowX86_64::SIZE`] bytes: `pc` as
+    /// a `u64`, two reserved bytes, `cfa_type` and `rbp_type` as `u8`, and `cfa_offset`
+    /// and `rbp_offset` as `i16`, all little-endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.rows.len() * EbpfUnwindRowX86_64::SIZE);
+        for row in &self.rows {
+            bytes.extend_from_slice(&row.pc.to_le_bytes());
+            bytes.extend_from_slice(&[0, 0, row.cfa_type as u8, row.rbp_type as u8]);
+            bytes.extend_from_slice(&row.cfa_offset.to_le_bytes());
+            bytes.extend_from_slice(&row.rbp_offset.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_ebpf_unwind_table() {
+        #[rustfmt::skip]
+        let debug_frame = vec![
+            // CIE: version 1, code alignment 1, data alignment -8, return address in r16
+            0x10, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x01, 0x00, 0x01, 0x78, 0x10,
+            // DW_CFA_def_cfa: rsp + 8; DW_CFA_offset: r16 at cfa - 8; DW_CFA_nop
+            0x0c, 0x07, 0x08, 0x90, 0x01, 0x00, 0x00,
+            // FDE for 0x1000..0x1010
+            0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // DW_CFA_advance_loc 1; DW_CFA_def_cfa_offset 16; DW_CFA_offset: rbp at cfa - 16
+            0x41, 0x0e, 0x10, 0x86, 0x02,
+            // DW_CFA_advance_loc 3; DW_CFA_def_cfa_register: rbp
+            0x43, 0x0d, 0x06,
+            // FDE for 0x1010..0x1020
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        let table = EbpfUnwindTableX86_64::try_from_debug_frame_data(
+            &debug_frame,
+            &DwarfCfiSectionAddresses::default(),
+        )
+        .unwrap();
+        let row = |pc, cfa_type, cfa_offset, rbp_type, rbp_offset| EbpfUnwindRowX86_64 {
+            pc,
+            cfa_type,
+            rbp_type,
+            cfa_offset,
+            rbp_offset,
+        };
+        assert_eq!(
+            table.rows(),
+            [
+                row(0x1000, EbpfCfaType::Rsp, 8, EbpfRbpType::Unchanged, 0),
+                row(0x1001, EbpfCfaType::Rsp, 16, EbpfRbpType::Offset, -16),
+                row(0x1004, EbpfCfaType::Rbp, 16, EbpfRbpType::Offset, -16),
+                // The end of the first FDE is the start of the second one.
+                row(0x1010, EbpfCfaType::Rsp, 8, EbpfRbpType::Unchanged, 0),
+                row(0x1020, EbpfCfaType::EndOfFde, 0, EbpfRbpType::Unchanged, 0),
+            ]
+        );
+
+        let bytes = table.to_bytes();
+        assert_eq!(bytes.len(), 5 * EbpfUnwindRowX86_64::SIZE);
+        assert_eq!(
+            bytes[16..32],
+            [0x01, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 2, 1, 0x10, 0, 0xf0, 0xff]
+        );
+    }
+}
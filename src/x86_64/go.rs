@@ -0,0 +1,65 @@
+use super::arch::ArchX86_64;
+use super::unwind_rule::UnwindRuleX86_64;
+use crate::go::{GoFrame, GoPclntabUnwinderError, GoUnwinding};
+
+impl GoUnwinding for ArchX86_64 {
+    fn rule_for_go_frame(
+        frame: GoFrame,
+        is_first_frame: bool,
+    ) -> Result<UnwindRuleX86_64, GoPclntabUnwinderError> {
+        if frame.is_top_frame {
+            return Ok(UnwindRuleX86_64::EndOfStack);
+        }
+        let sp_delta = frame.sp_delta;
+        if sp_delta < 0 || sp_delta % 8 != 0 {
+            return Err(GoPclntabUnwinderError::UnalignedSpDelta(sp_delta));
+        }
+        let sp_offset_by_8 = u16::try_from(sp_delta / 8 + 1)
+            .map_err(|_| GoPclntabUnwinderError::UnalignedSpDelta(sp_delta))?;
+        // Go functions with a frame push the caller's bp right below the return address.
+        // In the first frame, the prologue may not have saved it yet.
+        if sp_delta == 0 || is_first_frame {
+            return Ok(UnwindRuleX86_64::OffsetSp { sp_offset_by_8 });
+        }
+        let bp_storage_offset_from_sp_by_8 = i16::try_from(sp_offset_by_8 - 2)
+            .map_err(|_| GoPclntabUnwinderError::UnalignedSpDelta(sp_delta))?;
+        Ok(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+            sp_offset_by_8,
+            bp_storage_offset_from_sp_by_8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rule_for_go_frame() {
+        let rule = |sp_delta| {
+            let frame = GoFrame {
+                sp_delta,
+                is_top_frame: false,
+            };
+            ArchX86_64::rule_for_go_frame(frame, false)
+        };
+        assert_eq!(
+            rule(0x10),
+            Ok(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8: 3,
+                bp_storage_offset_from_sp_by_8: 1,
+            })
+        );
+        // The largest aligned delta doesn't overflow, and frames which are too large
+        // for the rule are errors.
+        let max_sp_delta = i32::MAX & !7;
+        assert_eq!(
+            rule(max_sp_delta),
+            Err(GoPclntabUnwinderError::UnalignedSpDelta(max_sp_delta))
+        );
+        assert_eq!(
+            rule(0x8_0000),
+            Err(GoPclntabUnwinderError::UnalignedSpDelta(0x8_0000))
+        );
+    }
+}
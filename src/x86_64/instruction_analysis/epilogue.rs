@@ -1,33 +1,48 @@
 use super::super::unwind_rule::UnwindRuleX86_64;
+use crate::instruction_analysis::FunctionBounds;
 
-pub fn unwind_rule_from_detected_epilogue(
+/// `text_bytes` are the part of the function described by `function`, which is used to
+/// tell tail calls from jumps inside the function.
+pub fn unwind_rule_from_detected_epilogue_in_function(
     text_bytes: &[u8],
     pc_offset: usize,
+    function: FunctionBounds,
 ) -> Option<UnwindRuleX86_64> {
-    let (slice_from_start, slice_to_end) = text_bytes.split_at(pc_offset);
+    let (slice_from_start, slice_to_end) = text_bytes.split_at_checked(pc_offset)?;
 
-    let mut sp_offset_by_8 = 0;
+    let mut sp_offset_by_8: u16 = 0;
     let mut bp_offset_by_8 = None;
+    // Set if the stack pointer was restored from the frame pointer, with `leave` or
+    // `mov rsp, rbp`. In that case the frame pointer is still valid at the initial pc.
+    let mut restores_sp_from_bp = false;
     let mut bytes = slice_to_end;
     loop {
         if bytes.is_empty() {
             return None;
         }
 
-        // Detect ret
-        if bytes[0] == 0xc3 {
+        // Detect ret and rep ret
+        if bytes[0] == 0xc3 || bytes.starts_with(&[0xf3, 0xc3]) {
             break;
         }
         // Detect jmp
-        if bytes[0] == 0xeb || bytes[0] == 0xe9 || bytes[0] == 0xff {
-            // This could be a tail call, or just a regular jump inside the current function.
-            // Ideally, we would check whether the jump target is inside this function.
-            // But this would require having an accurate idea of where the current function
-            // starts and ends.
-            // For now, we instead use the following heuristic: Any jmp that directly follows
-            // a `pop` instruction is treated as a tail call.
-            if sp_offset_by_8 != 0 {
-                // We have detected a pop in the previous loop iteration.
+        if bytes[0] == 0xeb
+            || bytes[0] == 0xe9
+            || bytes[0] == 0xff
+            || bytes.starts_with(&[0x41, 0xff])
+        {
+            // This could be a tail call, or just a regular jump inside the current function,
+            // e.g. in a loop or a switch. Only jumps which leave the function are tail calls.
+            let jmp_offset = function.offset + (text_bytes.len() - bytes.len());
+            if !is_tail_call_jmp(bytes, jmp_offset, function.len) {
+                return None;
+            }
+            // Jumps to other functions can still be regular jumps, e.g. to a cold part of
+            // this function which the compiler outlined. So we also check that the jmp
+            // follows a `pop` or a stack pointer adjustment.
+            if sp_offset_by_8 != 0 || restores_sp_from_bp {
+                // We have detected a pop or a stack pointer adjustment in a previous loop
+                // iteration.
                 break;
             }
             // This must be the first iteration. Look backwards.
@@ -43,22 +58,59 @@ pub fn unwind_rule_from_detected_epilogue(
             }
             return None;
         }
+        // Detect leave, which is `mov rsp, rbp; pop rbp`
+        if bytes[0] == 0xc9 {
+            if sp_offset_by_8 != 0 || bp_offset_by_8.is_some() || restores_sp_from_bp {
+                return None;
+            }
+            restores_sp_from_bp = true;
+            bp_offset_by_8 = Some(0);
+            bytes = &bytes[1..];
+            continue;
+        }
+        // Detect mov rsp, rbp
+        if bytes.starts_with(&[0x48, 0x89, 0xec]) || bytes.starts_with(&[0x48, 0x8b, 0xe5]) {
+            if sp_offset_by_8 != 0 || bp_offset_by_8.is_some() || restores_sp_from_bp {
+                return None;
+            }
+            restores_sp_from_bp = true;
+            bytes = &bytes[3..];
+            continue;
+        }
+        if restores_sp_from_bp {
+            // After restoring rsp from rbp, we only expect rbp to be popped before the return.
+            if bytes[0] == 0x5d && bp_offset_by_8.is_none() {
+                bp_offset_by_8 = Some(0);
+                bytes = &bytes[1..];
+                continue;
+            }
+            return None;
+        }
+        // Detect add rsp, imm8 and add rsp, imm32
+        if let Some((stack_adjust, instruction_len)) = decode_rsp_adjustment(bytes) {
+            if stack_adjust % 8 != 0 {
+                return None;
+            }
+            sp_offset_by_8 = sp_offset_by_8.checked_add(u16::try_from(stack_adjust / 8).ok()?)?;
+            bytes = &bytes[instruction_len..];
+            continue;
+        }
         // Detect pop rbp
         if bytes[0] == 0x5d {
-            bp_offset_by_8 = Some(sp_offset_by_8 as i16);
-            sp_offset_by_8 += 1;
+            bp_offset_by_8 = Some(i16::try_from(sp_offset_by_8).ok()?);
+            sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
             bytes = &bytes[1..];
             continue;
         }
         // Detect pop rXX
         if (0x58..=0x5f).contains(&bytes[0]) {
-            sp_offset_by_8 += 1;
+            sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
             bytes = &bytes[1..];
             continue;
         }
         // Detect pop rXX with prefix
         if bytes.len() >= 2 && bytes[0] & 0xfe == 0x40 && bytes[1] & 0xf8 == 0x58 {
-            sp_offset_by_8 += 1;
+            sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
             bytes = &bytes[2..];
             continue;
         }
@@ -68,10 +120,14 @@ pub fn unwind_rule_from_detected_epilogue(
     }
 
     // We've found the return or the tail call.
+    if restores_sp_from_bp {
+        return Some(UnwindRuleX86_64::UseFramePointer);
+    }
     let rule = if sp_offset_by_8 == 0 {
         UnwindRuleX86_64::JustReturn
     } else {
-        sp_offset_by_8 += 1; // Add one for popping the return address.
+        // Add one for popping the return address.
+        sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
         if let Some(bp_storage_offset_from_sp_by_8) = bp_offset_by_8 {
             UnwindRuleX86_64::OffsetSpAndRestoreBp {
                 sp_offset_by_8,
@@ -83,3 +139,239 @@ pub fn unwind_rule_from_detected_epilogue(
     };
     Some(rule)
 }
+
+/// Detects a `jmp` which probably leaves the function, i.e. a tail call. `bytes` starts
+/// with the instruction, which is at `jmp_offset` in the function's bytes.
+///
+/// A `jmp rel32` is a tail call if its target is outside the function. Indirect jumps
+/// through a register or through a RIP-relative pointer, e.g. a GOT entry, can't be
+/// followed, so they're assumed to be tail calls. Short jumps and jumps through a table
+/// of addresses, which is how switches are compiled, stay inside the function.
+fn is_tail_call_jmp(bytes: &[u8], jmp_offset: usize, function_len: usize) -> bool {
+    match bytes {
+        // jmp rel32
+        [0xe9, a, b, c, d, ..] => {
+            let target = (jmp_offset as i64) + 5 + i64::from(i32::from_le_bytes([*a, *b, *c, *d]));
+            target < 0 || target >= function_len as i64
+        }
+        // jmp [rip + disp32]
+        [0xff, 0x25, ..] => true,
+        // jmp reg, jmp r8-r15
+        [0xff, modrm, ..] | [0x41, 0xff, modrm, ..] => modrm & 0xf8 == 0xe0,
+        _ => false,
+    }
+}
+
+/// Detects `add rsp, imm` and `lea rsp, [rsp + disp]` with a positive immediate.
+/// Returns the immediate and the instruction length.
+fn decode_rsp_adjustment(bytes: &[u8]) -> Option<(u32, usize)> {
+    let (imm, len) = match bytes {
+        // add rsp, imm8
+        [0x48, 0x83, 0xc4, imm, ..] => (i32::from(*imm as i8), 4),
+        // add rsp, imm32
+        [0x48, 0x81, 0xc4, a, b, c, d, ..] => (i32::from_le_bytes([*a, *b, *c, *d]), 7),
+        // lea rsp, [rsp + disp8]
+        [0x48, 0x8d, 0x64, 0x24, disp, ..] => (i32::from(*disp as i8), 5),
+        // lea rsp, [rsp + disp32]
+        [0x48, 0x8d, 0xa4, 0x24, a, b, c, d, ..] => (i32::from_le_bytes([*a, *b, *c, *d]), 8),
+        _ => return None,
+    };
+    Some((u32::try_from(imm).ok().filter(|imm| *imm > 0)?, len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unwind_rule_from_detected_epilogue(
+        function_bytes: &[u8],
+        pc_offset: usize,
+    ) -> Option<UnwindRuleX86_64> {
+        unwind_rule_from_detected_epilogue_in_function(
+            function_bytes,
+            pc_offset,
+            FunctionBounds::of(function_bytes),
+        )
+    }
+
+    #[test]
+    fn test_epilogue_leave() {
+        // c9     leave
+        // c3     ret
+        let bytes = &[0xc9, 0xc3];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::UseFramePointer)
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 1),
+            Some(UnwindRuleX86_64::JustReturn)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_mov_rsp_rbp() {
+        // 48 89 ec     mov    rsp, rbp
+        // 5d           pop    rbp
+        // c3           ret
+        let bytes = &[0x48, 0x89, 0xec, 0x5d, 0xc3];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::UseFramePointer)
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 3),
+            Some(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8: 2,
+                bp_storage_offset_from_sp_by_8: 0
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleX86_64::JustReturn)
+        );
+    }
+
+    #[test]
+    fn test_epilogue_add_rsp_and_pops() {
+        // 48 83 c4 18     add    rsp, 0x18
+        // 5b              pop    rbx
+        // 5d              pop    rbp
+        // 41 5e           pop    r14
+        // c3              ret
+        let bytes = &[0x48, 0x83, 0xc4, 0x18, 0x5b, 0x5d, 0x41, 0x5e, 0xc3];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8: 7,
+                bp_storage_offset_from_sp_by_8: 4
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 4),
+            Some(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8: 4,
+                bp_storage_offset_from_sp_by_8: 1
+            })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 8),
+            Some(UnwindRuleX86_64::JustReturn)
+        );
+
+        // 48 81 c4 08 01 00 00     add    rsp, 0x108
+        // 5b                       pop    rbx
+        // f3 c3                    rep ret
+        let bytes = &[0x48, 0x81, 0xc4, 0x08, 0x01, 0x00, 0x00, 0x5b, 0xf3, 0xc3];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 35 })
+        );
+    }
+
+    #[test]
+    fn test_epilogue_tail_call() {
+        // 48 83 c4 08        add    rsp, 0x8
+        // 5b                 pop    rbx
+        // e9 00 00 00 00     jmp    some_function
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0x5b, 0xe9, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 3 })
+        );
+    }
+
+    #[test]
+    fn test_not_an_epilogue() {
+        // 48 83 c4 08        add    rsp, 0x8
+        // e8 00 00 00 00     call   some_function
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0xe8, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+
+        // c9     leave
+        // 5d     pop    rbp
+        // c3     ret
+        let bytes = &[0xc9, 0x5d, 0xc3];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+    }
+
+    #[test]
+    fn test_jmp_inside_function() {
+        // 48 83 c4 08        add    rsp, 0x8
+        // e9 f7 ff ff ff     jmp    0x0 (loop back to the start)
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0xe9, 0xf7, 0xff, 0xff, 0xff];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+
+        // 48 83 c4 08        add    rsp, 0x8
+        // eb fa              jmp    0x0
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0xeb, 0xfa];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+
+        // 48 83 c4 08             add    rsp, 0x8
+        // ff 24 c5 00 00 00 00    jmp    qword ptr [rax*8 + jump_table]
+        let bytes = &[
+            0x48, 0x83, 0xc4, 0x08, 0xff, 0x24, 0xc5, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(unwind_rule_from_detected_epilogue(bytes, 0), None);
+    }
+
+    #[test]
+    fn test_jmp_in_part_of_function() {
+        // 48 83 c4 08        add    rsp, 0x8
+        // e9 00 01 00 00     jmp    +0x100
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0xe9, 0x00, 0x01, 0x00, 0x00];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 })
+        );
+        // The bytes are the start of a longer function, which the jmp stays in.
+        let function = FunctionBounds {
+            offset: 0,
+            len: 0x200,
+        };
+        assert_eq!(
+            unwind_rule_from_detected_epilogue_in_function(bytes, 0, function),
+            None
+        );
+
+        // 48 83 c4 08        add    rsp, 0x8
+        // e9 f7 fe ff ff     jmp    -0x109
+        let bytes = &[0x48, 0x83, 0xc4, 0x08, 0xe9, 0xf7, 0xfe, 0xff, 0xff];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 })
+        );
+        // The bytes are 0x100 bytes into the function, and the jmp goes to its start.
+        let function = FunctionBounds {
+            offset: 0x100,
+            len: 0x200,
+        };
+        assert_eq!(
+            unwind_rule_from_detected_epilogue_in_function(bytes, 0, function),
+            None
+        );
+    }
+
+    #[test]
+    fn test_epilogue_indirect_tail_call() {
+        // 5b                 pop    rbx
+        // ff 25 00 00 00 00  jmp    qword ptr [rip + some_function@GOTPCREL]
+        let bytes = &[0x5b, 0xff, 0x25, 0x00, 0x00, 0x00, 0x00];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 1),
+            Some(UnwindRuleX86_64::JustReturn)
+        );
+
+        // 41 5e              pop    r14
+        // 41 ff e3           jmp    r11
+        let bytes = &[0x41, 0x5e, 0x41, 0xff, 0xe3];
+        assert_eq!(
+            unwind_rule_from_detected_epilogue(bytes, 0),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 })
+        );
+    }
+}
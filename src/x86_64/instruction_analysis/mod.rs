@@ -1,11 +1,13 @@
 use super::arch::ArchX86_64;
-use crate::instruction_analysis::InstructionAnalysis;
+use crate::instruction_analysis::{FunctionBounds, InstructionAnalysis};
 
 mod epilogue;
 mod prologue;
 
-use epilogue::unwind_rule_from_detected_epilogue;
-use prologue::unwind_rule_from_detected_prologue;
+use epilogue::unwind_rule_from_detected_epilogue_in_function;
+use prologue::{
+    guess_function_start, unwind_rule_for_function_body, unwind_rule_from_detected_prologue,
+};
 
 impl InstructionAnalysis for ArchX86_64 {
     fn rule_from_prologue_analysis(
@@ -18,7 +20,16 @@ impl InstructionAnalysis for ArchX86_64 {
     fn rule_from_epilogue_analysis(
         text_bytes: &[u8],
         pc_offset: usize,
+        function: FunctionBounds,
     ) -> Option<Self::UnwindRule> {
-        unwind_rule_from_detected_epilogue(text_bytes, pc_offset)
+        unwind_rule_from_detected_epilogue_in_function(text_bytes, pc_offset, function)
+    }
+
+    fn rule_for_function_body(function_bytes: &[u8]) -> Option<(usize, Self::UnwindRule)> {
+        unwind_rule_for_function_body(function_bytes)
+    }
+
+    fn guess_function_start(text_bytes: &[u8], pc_offset: usize) -> Option<usize> {
+        guess_function_start(text_bytes, pc_offset)
     }
 }
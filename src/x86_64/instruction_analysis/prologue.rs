@@ -1,10 +1,13 @@
 use super::super::unwind_rule::UnwindRuleX86_64;
 
+/// The encoding of the `endbr64` instruction.
+const ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+
 pub fn unwind_rule_from_detected_prologue(
     text_bytes: &[u8],
     pc_offset: usize,
 ) -> Option<UnwindRuleX86_64> {
-    let (slice_from_start, slice_to_end) = text_bytes.split_at(pc_offset);
+    let (slice_from_start, slice_to_end) = text_bytes.split_at_checked(pc_offset)?;
     if !is_next_instruction_expected_in_prologue(slice_to_end) {
         return None;
     }
@@ -15,19 +18,23 @@ pub fn unwind_rule_from_detected_prologue(
     // Let's do it anyway and hope our heuristics are good enough so that
     // they work in more cases than they fail in.
     let mut cursor = slice_from_start.len();
-    let mut sp_offset_by_8 = 0;
+    let mut sp_offset_by_8: u16 = 0;
     loop {
         if cursor >= 4 {
             // Detect push rbp; mov rbp, rsp [0x55, 0x48 0x89 0xe5]
             if slice_from_start[cursor - 4..cursor] == [0x55, 0x48, 0x89, 0xe5] {
                 return Some(UnwindRuleX86_64::UseFramePointer);
             }
+            // Detect endbr64, which marks the start of the function.
+            if slice_from_start[cursor - 4..cursor] == ENDBR64 {
+                break;
+            }
         }
         if cursor >= 1 {
             // Detect push rXX with optional prefix
             let byte = slice_from_start[cursor - 1];
             if byte & 0xf8 == 0x50 {
-                sp_offset_by_8 += 1;
+                sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
                 cursor -= 1;
 
                 // Consume prefix, if present
@@ -40,7 +47,8 @@ pub fn unwind_rule_from_detected_prologue(
         }
         break;
     }
-    sp_offset_by_8 += 1; // Add one for popping the return address.
+    // Add one for popping the return address.
+    let sp_offset_by_8 = sp_offset_by_8.checked_add(1)?;
     Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8 })
 }
 
@@ -49,6 +57,12 @@ fn is_next_instruction_expected_in_prologue(bytes: &[u8]) -> bool {
         return false;
     }
 
+    // Detect endbr64, which is the first instruction of functions compiled with
+    // Indirect Branch Tracking.
+    if bytes[0..4] == ENDBR64 {
+        return true;
+    }
+
     // Detect push rXX
     if bytes[0] & 0xf8 == 0x50 {
         return true;
@@ -81,6 +95,99 @@ fn is_next_instruction_expected_in_prologue(bytes: &[u8]) -> bool {
     false
 }
 
+/// Scans the prologue at the start of a function and returns the length of the
+/// prologue in bytes and the rule which applies to the rest of the function.
+///
+/// Recognizes `push rbp; mov rbp, rsp`, and a sequence of register pushes followed
+/// by an optional `sub rsp, imm`.
+pub fn unwind_rule_for_function_body(function_bytes: &[u8]) -> Option<(usize, UnwindRuleX86_64)> {
+    let mut cursor = 0;
+    // Skip endbr64.
+    if function_bytes.starts_with(&ENDBR64) {
+        cursor += 4;
+    }
+    let mut push_count = 0u32;
+    // The number of registers pushed before rbp, if rbp was pushed.
+    let mut rbp_push_index = None;
+    loop {
+        match function_bytes[cursor..] {
+            // push rbp; mov rbp, rsp
+            [0x55, 0x48, 0x89, 0xe5, ..] | [0x55, 0x48, 0x8b, 0xec, ..] => {
+                return Some((cursor + 4, UnwindRuleX86_64::UseFramePointer));
+            }
+            // push rXX with prefix
+            [prefix, reg, ..] if prefix & 0xfe == 0x40 && reg & 0xf8 == 0x50 => {
+                if prefix == 0x40 && reg == 0x55 {
+                    rbp_push_index = Some(push_count);
+                }
+                push_count += 1;
+                cursor += 2;
+            }
+            // push rXX
+            [reg, ..] if reg & 0xf8 == 0x50 => {
+                if reg == 0x55 {
+                    rbp_push_index = Some(push_count);
+                }
+                push_count += 1;
+                cursor += 1;
+            }
+            _ => break,
+        }
+    }
+    let sub_amount = match function_bytes[cursor..] {
+        // sub rsp, 0xXX (8-bit immediate operand)
+        [0x48, 0x83, 0xec, imm, ..] if imm < 0x80 => {
+            cursor += 4;
+            u32::from(imm)
+        }
+        // sub rsp, 0xXX (32-bit immediate operand)
+        [0x48, 0x81, 0xec, a, b, c, d, ..] => {
+            cursor += 7;
+            u32::from_le_bytes([a, b, c, d])
+        }
+        _ => 0,
+    };
+    if push_count == 0 && sub_amount == 0 {
+        return None;
+    }
+    if sub_amount % 8 != 0 {
+        return None;
+    }
+    // Add one for popping the return address.
+    let sp_offset_by_8 = u16::try_from(push_count + sub_amount / 8 + 1).ok()?;
+    let rule = match rbp_push_index {
+        // rbp was saved without being set up as a frame pointer, so the function
+        // body may use it as a general purpose register.
+        Some(index) => UnwindRuleX86_64::OffsetSpAndRestoreBp {
+            sp_offset_by_8,
+            bp_storage_offset_from_sp_by_8: i16::try_from(sub_amount / 8 + push_count - 1 - index)
+                .ok()?,
+        },
+        None => UnwindRuleX86_64::OffsetSp { sp_offset_by_8 },
+    };
+    Some((cursor, rule))
+}
+
+/// The maximum distance, in bytes, that [`guess_function_start`] looks backwards.
+const MAX_FUNCTION_START_DISTANCE: usize = 0x4000;
+
+/// Guesses the start of the function containing `pc_offset`, for code without a
+/// symbol table. This looks backwards for a recognizable prologue which follows a
+/// `ret` or padding.
+pub fn guess_function_start(text_bytes: &[u8], pc_offset: usize) -> Option<usize> {
+    if pc_offset > text_bytes.len() {
+        return None;
+    }
+    let lowest_start = pc_offset.saturating_sub(MAX_FUNCTION_START_DISTANCE);
+    (lowest_start..=pc_offset).rev().find(|&start| {
+        let follows_ret_or_padding =
+            start == 0 || matches!(text_bytes[start - 1], 0xc3 | 0xcc | 0x90);
+        follows_ret_or_padding
+            && unwind_rule_for_function_body(&text_bytes[start..])
+                .is_some_and(|(prologue_len, _)| start + prologue_len <= pc_offset)
+    })
+}
+
 // TODO: Write tests for different "sub" types
 // 4e88e40  41 57                 push  r15
 // 4e88e42  41 56                 push  r14
@@ -98,3 +205,98 @@ fn is_next_instruction_expected_in_prologue(bytes: &[u8]) -> bool {
 // 442405  53           push  rbx
 // 442406  48 83 EC 18  sub  rsp, 0x18
 // 44240a  48 8B 07     mov  rax, qword [rdi]
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_prologue_with_endbr64() {
+        // 5f           pop  rdi
+        // c3           ret
+        // f3 0f 1e fa  endbr64
+        // 41 57        push  r15
+        // 53           push  rbx
+        // 48 89 fb     mov  rbx, rdi
+        let bytes = &[
+            0x5f, 0xc3, 0xf3, 0x0f, 0x1e, 0xfa, 0x41, 0x57, 0x53, 0x48, 0x89, 0xfb,
+        ];
+        assert_eq!(
+            unwind_rule_from_detected_prologue(bytes, 2),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 1 })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_prologue(bytes, 6),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 1 })
+        );
+        assert_eq!(
+            unwind_rule_from_detected_prologue(bytes, 8),
+            Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 2 })
+        );
+        assert_eq!(unwind_rule_from_detected_prologue(bytes, 9), None);
+    }
+
+    #[test]
+    fn test_function_body_with_frame_pointer() {
+        // f3 0f 1e fa  endbr64
+        // 55           push  rbp
+        // 48 89 e5     mov  rbp, rsp
+        // 41 57        push  r15
+        let bytes = &[0xf3, 0x0f, 0x1e, 0xfa, 0x55, 0x48, 0x89, 0xe5, 0x41, 0x57];
+        assert_eq!(
+            unwind_rule_for_function_body(bytes),
+            Some((8, UnwindRuleX86_64::UseFramePointer))
+        );
+    }
+
+    #[test]
+    fn test_function_body_with_pushes_and_sub() {
+        // 41 57                 push  r15
+        // 41 56                 push  r14
+        // 53                    push  rbx
+        // 48 81 ec 80 00 00 00  sub  rsp, 0x80
+        // 48 89 f3              mov  rbx, rsi
+        let bytes = &[
+            0x41, 0x57, 0x41, 0x56, 0x53, 0x48, 0x81, 0xec, 0x80, 0x00, 0x00, 0x00, 0x48, 0x89,
+            0xf3,
+        ];
+        assert_eq!(
+            unwind_rule_for_function_body(bytes),
+            Some((12, UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 20 }))
+        );
+        assert_eq!(unwind_rule_for_function_body(&bytes[12..]), None);
+    }
+
+    #[test]
+    fn test_function_body_with_rbp_push_without_frame_pointer() {
+        // 55           push  rbp
+        // 53           push  rbx
+        // 48 83 ec 10  sub  rsp, 0x10
+        // 48 89 fd     mov  rbp, rdi
+        let bytes = &[0x55, 0x53, 0x48, 0x83, 0xec, 0x10, 0x48, 0x89, 0xfd];
+        assert_eq!(
+            unwind_rule_for_function_body(bytes),
+            Some((
+                6,
+                UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                    sp_offset_by_8: 5,
+                    bp_storage_offset_from_sp_by_8: 3,
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn test_guess_function_start() {
+        // c3           ret
+        // cc           int3
+        // 55           push  rbp
+        // 48 89 e5     mov  rbp, rsp
+        // 48 8b 07     mov  rax, qword [rdi]
+        let bytes = &[0xc3, 0xcc, 0x55, 0x48, 0x89, 0xe5, 0x48, 0x8b, 0x07];
+        assert_eq!(guess_function_start(bytes, 6), Some(2));
+        assert_eq!(guess_function_start(bytes, 8), Some(2));
+        // Inside the prologue, the function start is not found.
+        assert_eq!(guess_function_start(bytes, 3), None);
+    }
+}
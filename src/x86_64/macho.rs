@@ -1,10 +1,50 @@
 use super::arch::ArchX86_64;
-use super::unwind_rule::UnwindRuleX86_64;
+use super::unwind_rule::{OffsetOrPop, UnwindRuleX86_64};
+use super::unwindregs::Reg;
 use crate::instruction_analysis::InstructionAnalysis;
 use crate::macho::{CompactUnwindInfoUnwinderError, CompactUnwindInfoUnwinding, CuiUnwindResult};
+use arrayvec::ArrayVec;
 use macho_unwind_info::opcodes::{OpcodeX86_64, RegisterNameX86_64};
 use macho_unwind_info::Function;
 
+/// The rule for a frameless function whose stack frame is `stack_size_in_bytes` large,
+/// including the return address and the saved registers. `saved_regs` lists the saved
+/// registers in the order in which the epilogue pops them, so the first one is at the
+/// lowest address, above the function's local variables.
+fn rule_for_frameless_function(
+    stack_size_in_bytes: u32,
+    saved_regs: &[Option<RegisterNameX86_64>],
+) -> Result<UnwindRuleX86_64, CompactUnwindInfoUnwinderError> {
+    let saved_regs: ArrayVec<Reg, 6> = saved_regs
+        .iter()
+        .flatten()
+        .map(|reg| match reg {
+            RegisterNameX86_64::Rbx => Reg::RBX,
+            RegisterNameX86_64::R12 => Reg::R12,
+            RegisterNameX86_64::R13 => Reg::R13,
+            RegisterNameX86_64::R14 => Reg::R14,
+            RegisterNameX86_64::R15 => Reg::R15,
+            RegisterNameX86_64::Rbp => Reg::RBP,
+        })
+        .collect();
+    if saved_regs.is_empty() {
+        let sp_offset_by_8 = u16::try_from(stack_size_in_bytes / 8)
+            .map_err(|_| CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)?;
+        return Ok(UnwindRuleX86_64::OffsetSp { sp_offset_by_8 });
+    }
+    // The locals are below the saved registers, which are below the return address.
+    let saved_size_in_bytes = (saved_regs.len() as u32 + 1) * 8;
+    let locals_size_in_bytes = stack_size_in_bytes
+        .checked_sub(saved_size_in_bytes)
+        .ok_or(CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)?;
+    let sp_offset_by_8 = u16::try_from(locals_size_in_bytes / 8)
+        .map_err(|_| CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)?;
+    let operations = core::iter::once(OffsetOrPop::OffsetBy8(sp_offset_by_8))
+        .chain(saved_regs.into_iter().map(OffsetOrPop::Pop));
+    UnwindRuleX86_64::for_sequence_of_offset_or_pop(operations)
+        .ok_or(CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)
+}
+
 impl CompactUnwindInfoUnwinding for ArchX86_64 {
     fn unwind_frame(
         function: Function,
@@ -24,7 +64,7 @@ impl CompactUnwindInfoUnwinding for ArchX86_64 {
                 ) {
                     // We are inside a prologue / epilogue. Ignore the opcode and use the rule from
                     // instruction analysis.
-                    return Ok(CuiUnwindResult::ExecRule(rule));
+                    return Ok(CuiUnwindResult::ExecRuleFromInstructionAnalysis(rule));
                 }
                 if opcode == OpcodeX86_64::Null
                     && function_bytes.starts_with(&[0x55, 0x48, 0x89, 0xe5])
@@ -50,28 +90,10 @@ impl CompactUnwindInfoUnwinding for ArchX86_64 {
                 if stack_size_in_bytes == 8 {
                     CuiUnwindResult::ExecRule(UnwindRuleX86_64::JustReturn)
                 } else {
-                    let bp_positon_from_outside = saved_regs
-                        .iter()
-                        .rev()
-                        .flatten()
-                        .position(|r| *r == RegisterNameX86_64::Rbp);
-                    match bp_positon_from_outside {
-                        Some(pos) => {
-                            let bp_offset_from_sp =
-                                stack_size_in_bytes as i32 - 2 * 8 - pos as i32 * 8;
-                            let bp_storage_offset_from_sp_by_8 =
-                                i16::try_from(bp_offset_from_sp / 8).map_err(|_| {
-                                    CompactUnwindInfoUnwinderError::BpOffsetDoesNotFit
-                                })?;
-                            CuiUnwindResult::ExecRule(UnwindRuleX86_64::OffsetSpAndRestoreBp {
-                                sp_offset_by_8: stack_size_in_bytes / 8,
-                                bp_storage_offset_from_sp_by_8,
-                            })
-                        }
-                        None => CuiUnwindResult::ExecRule(UnwindRuleX86_64::OffsetSp {
-                            sp_offset_by_8: stack_size_in_bytes / 8,
-                        }),
-                    }
+                    CuiUnwindResult::ExecRule(rule_for_frameless_function(
+                        stack_size_in_bytes.into(),
+                        &saved_regs,
+                    )?)
                 }
             }
             OpcodeX86_64::FramelessIndirect {
@@ -98,28 +120,10 @@ impl CompactUnwindInfoUnwinding for ArchX86_64 {
                     sub_immediate
                         .checked_add(stack_adjust_in_bytes.into())
                         .ok_or(CompactUnwindInfoUnwinderError::StackAdjustOverflow)?;
-                let sp_offset_by_8 = u16::try_from(stack_size_in_bytes / 8)
-                    .map_err(|_| CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)?;
-                let bp_positon_from_outside = saved_regs
-                    .iter()
-                    .rev()
-                    .flatten()
-                    .position(|r| *r == RegisterNameX86_64::Rbp);
-                match bp_positon_from_outside {
-                    Some(pos) => {
-                        let bp_offset_from_sp = stack_size_in_bytes as i32 - 2 * 8 - pos as i32 * 8;
-                        let bp_storage_offset_from_sp_by_8 =
-                            i16::try_from(bp_offset_from_sp / 8)
-                                .map_err(|_| CompactUnwindInfoUnwinderError::BpOffsetDoesNotFit)?;
-                        CuiUnwindResult::ExecRule(UnwindRuleX86_64::OffsetSpAndRestoreBp {
-                            sp_offset_by_8,
-                            bp_storage_offset_from_sp_by_8,
-                        })
-                    }
-                    None => {
-                        CuiUnwindResult::ExecRule(UnwindRuleX86_64::OffsetSp { sp_offset_by_8 })
-                    }
-                }
+                CuiUnwindResult::ExecRule(rule_for_frameless_function(
+                    stack_size_in_bytes,
+                    &saved_regs,
+                )?)
             }
             OpcodeX86_64::Dwarf { eh_frame_fde } => CuiUnwindResult::NeedDwarf(eh_frame_fde),
             OpcodeX86_64::FrameBased { .. } => {
@@ -169,3 +173,41 @@ impl CompactUnwindInfoUnwinding for ArchX86_64 {
         Ok(CuiUnwindResult::ExecRule(rule))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::unwind_rule::UnwindRule;
+    use crate::x86_64::UnwindRegsX86_64;
+
+    #[test]
+    fn test_frameless_saved_registers() {
+        // Three words of locals, then rbp and rbx, then the return address.
+        let saved_regs = [
+            Some(RegisterNameX86_64::Rbp),
+            Some(RegisterNameX86_64::Rbx),
+            None,
+            None,
+            None,
+            None,
+        ];
+        let rule = rule_for_frameless_function(0x30, &saved_regs).unwrap();
+        let stack = [1, 2, 3, 0x90, 0x80, 0x100300];
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x0, 0x20);
+        let res = rule.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Ok(Some(0x100300)));
+        assert_eq!(regs.sp(), 0x30);
+        assert_eq!(regs.bp(), 0x90);
+        assert_eq!(regs.get(Reg::RBX), 0x80);
+
+        assert_eq!(
+            rule_for_frameless_function(0x10, &saved_regs),
+            Err(CompactUnwindInfoUnwinderError::StackSizeDoesNotFit)
+        );
+        assert_eq!(
+            rule_for_frameless_function(0x30, &[None; 6]),
+            Ok(UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 6 })
+        );
+    }
+}
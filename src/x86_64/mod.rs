@@ -1,6 +1,9 @@
 mod arch;
 mod cache;
 mod dwarf;
+mod ebpf_table;
+#[cfg(feature = "go")]
+mod go;
 mod instruction_analysis;
 #[cfg(feature = "macho")]
 mod macho;
@@ -13,6 +16,8 @@ mod unwindregs;
 
 pub use arch::*;
 pub use cache::*;
+pub use dwarf::{unwind_frame_with_eh_frame, unwind_full_frame_with_eh_frame};
+pub use ebpf_table::*;
 pub use unwind_rule::*;
 pub use unwinder::*;
 pub use unwindregs::*;
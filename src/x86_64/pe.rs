@@ -1,44 +1,21 @@
 use super::{
     arch::ArchX86_64,
     unwind_rule::{OffsetOrPop, UnwindRuleX86_64},
-    unwindregs::Reg,
+    unwindregs::{Reg, UnwindRegsX86_64},
 };
-use crate::arch::Arch;
+use crate::memory_reader::MemoryReadError;
 use crate::pe::{PeSections, PeUnwinderError, PeUnwinding};
 use crate::unwind_result::UnwindResult;
-use core::ops::ControlFlow;
+use crate::unwind_step::{ReadRequest, Resumable, Step};
 
 use alloc::vec::Vec;
 use pe_unwind_info::x86_64::{
     FunctionEpilogInstruction, FunctionTableEntries, Register, UnwindInfo, UnwindInfoTrailer,
-    UnwindOperation, UnwindState,
+    UnwindOperation,
 };
 
-struct State<'a, F> {
-    regs: &'a mut <ArchX86_64 as Arch>::UnwindRegs,
-    read_stack: &'a mut F,
-}
-
-impl<F> UnwindState for State<'_, F>
-where
-    F: FnMut(u64) -> Result<u64, ()>,
-{
-    fn read_register(&mut self, register: Register) -> u64 {
-        self.regs.get(convert_pe_register(register))
-    }
-
-    fn read_stack(&mut self, addr: u64) -> Option<u64> {
-        (self.read_stack)(addr).ok()
-    }
-
-    fn write_register(&mut self, register: Register, value: u64) {
-        self.regs.set(convert_pe_register(register), value)
-    }
-
-    fn write_xmm_register(&mut self, _register: pe_unwind_info::x86_64::XmmRegister, _value: u128) {
-        // Ignore
-    }
-}
+/// The maximum length of a chain of unwind infos which is followed.
+const MAX_CHAINED_UNWIND_INFOS: usize = 32;
 
 fn convert_pe_register(r: Register) -> Reg {
     match r {
@@ -94,15 +71,14 @@ impl From<&'_ UnwindOperation> for OffsetOrPop {
 }
 
 impl PeUnwinding for ArchX86_64 {
-    fn unwind_frame<F, D>(
+    type PeEvaluation = PeEvaluationX86_64;
+
+    fn unwind_frame<D>(
         sections: PeSections<D>,
         address: u32,
-        regs: &mut Self::UnwindRegs,
         is_first_frame: bool,
-        read_stack: &mut F,
-    ) -> Result<UnwindResult<Self::UnwindRule>, PeUnwinderError>
+    ) -> Result<UnwindResult<Self::UnwindRule, PeEvaluationX86_64>, PeUnwinderError>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
         D: core::ops::Deref<Target = [u8]>,
     {
         let entries = FunctionTableEntries::parse(sections.pdata);
@@ -110,10 +86,6 @@ impl PeUnwinding for ArchX86_64 {
             return Ok(UnwindResult::ExecRule(UnwindRuleX86_64::JustReturn));
         };
 
-        let read_stack_err = |read_stack: &mut F, addr| {
-            read_stack(addr).map_err(|()| PeUnwinderError::MissingStackData(Some(addr)))
-        };
-
         let unwind_info_address = function.unwind_info_address.get();
         let unwind_info =
             UnwindInfo::parse(sections.unwind_info_memory_at_rva(unwind_info_address)?)
@@ -124,8 +96,11 @@ impl PeUnwinding for ArchX86_64 {
             // simulate the remaining epilog instructions (unwind codes don't account for
             // unwinding from the epilog). We only need to check this for the first unwind info (if
             // there are chained infos).
-            let bytes = (function.end_address.get() - address) as usize;
-            let instruction = &sections.text_memory_at_rva(address)?[..bytes];
+            let bytes = function.end_address.get().saturating_sub(address) as usize;
+            let instruction = sections
+                .text_memory_at_rva(address)?
+                .get(..bytes)
+                .ok_or(PeUnwinderError::MissingInstructionData(address))?;
             if let Ok(epilog_instructions) =
                 FunctionEpilogInstruction::parse_sequence(instruction, unwind_info.frame_register())
             {
@@ -137,34 +112,26 @@ impl PeUnwinding for ArchX86_64 {
                     return Ok(UnwindResult::ExecRule(rule));
                 }
 
+                let mut ops = Vec::new();
                 for instruction in epilog_instructions.iter() {
-                    match instruction {
-                        FunctionEpilogInstruction::AddSP(offset) => {
-                            let rsp = regs.get(Reg::RSP);
-                            regs.set(Reg::RSP, rsp + *offset as u64);
-                        }
+                    ops.push(match instruction {
+                        FunctionEpilogInstruction::AddSP(offset) => PeOp::AddSp(u64::from(*offset)),
                         FunctionEpilogInstruction::AddSPFromFP(offset) => {
                             let fp = unwind_info
                                 .frame_register()
-                                .expect("invalid fp register offset");
-                            let fp = convert_pe_register(fp);
-                            let fp = regs.get(fp);
-                            regs.set(Reg::RSP, fp + *offset as u64);
+                                .ok_or(PeUnwinderError::UnwindInfoParseError)?;
+                            PeOp::SetSp {
+                                base: convert_pe_register(fp),
+                                offset: u64::from(*offset),
+                            }
                         }
-                        FunctionEpilogInstruction::Pop(reg) => {
-                            let rsp = regs.get(Reg::RSP);
-                            let val = read_stack_err(read_stack, rsp)?;
-                            regs.set(convert_pe_register(*reg), val);
-                            regs.set(Reg::RSP, rsp + 8);
-                        }
-                    }
+                        FunctionEpilogInstruction::Pop(reg) => PeOp::Pop(convert_pe_register(*reg)),
+                    });
                 }
-
-                let rsp = regs.get(Reg::RSP);
-                let ra = read_stack_err(read_stack, rsp)?;
-                regs.set(Reg::RSP, rsp + 8);
-
-                return Ok(UnwindResult::Uncacheable(ra));
+                ops.push(PeOp::Return);
+                return Ok(UnwindResult::Uncacheable(PeEvaluationX86_64::new(
+                    ops, true,
+                )));
             }
         }
 
@@ -186,11 +153,13 @@ impl PeUnwinding for ArchX86_64 {
                 None
             }
         })
+        // A corrupt module could chain an unwind info to itself.
+        .take(MAX_CHAINED_UNWIND_INFOS)
         .collect::<Result<Vec<_>, _>>()?;
 
         // Get all operations across chained UnwindInfo. The first should be filtered to only those
         // operations which are before the offset in the function.
-        let offset = address - function.begin_address.get();
+        let offset = address.saturating_sub(function.begin_address.get());
         let operations = chained_info.into_iter().enumerate().flat_map(|(i, info)| {
             info.unwind_operations()
                 .skip_while(move |(o, _)| i == 0 && *o as u32 > offset)
@@ -204,21 +173,185 @@ impl PeUnwinding for ArchX86_64 {
             return Ok(UnwindResult::ExecRule(rule));
         }
 
-        // Resolve operations to get the return address.
-        let mut state = State { regs, read_stack };
+        // Translate the operations into the steps which get the return address. Offsets are
+        // relative to the frame register of the first unwind info.
+        let frame_register = unwind_info.frame_register().map(convert_pe_register);
+        let frame_register_offset = u64::from(unwind_info.frame_register_offset());
+        let resolve_offset = |offset| match frame_register {
+            Some(reg) => {
+                // With the frame register at its offset, this is the raw offset.
+                let offset = unwind_info.resolve_offset(|_| frame_register_offset, offset);
+                (reg, offset.wrapping_sub(frame_register_offset))
+            }
+            None => (Reg::RSP, unwind_info.resolve_offset(|_| 0, offset)),
+        };
+        let mut ops = Vec::with_capacity(operations.len() + 1);
         for op in operations {
-            if let ControlFlow::Break(ra) = unwind_info
-                .resolve_operation(&mut state, &op)
-                .ok_or(PeUnwinderError::MissingStackData(None))?
-            {
-                return Ok(UnwindResult::Uncacheable(ra));
+            match op {
+                UnwindOperation::PopNonVolatile(reg) => {
+                    ops.push(PeOp::Pop(convert_pe_register(reg)))
+                }
+                UnwindOperation::UnStackAlloc(bytes) => ops.push(PeOp::AddSp(u64::from(bytes))),
+                UnwindOperation::RestoreSPFromFP => {
+                    if let Some(reg) = frame_register {
+                        ops.push(PeOp::SetSp {
+                            base: reg,
+                            offset: frame_register_offset.wrapping_neg(),
+                        });
+                    }
+                }
+                UnwindOperation::ReadNonVolatile(reg, offset) => {
+                    let (base, offset) = resolve_offset(offset);
+                    ops.push(PeOp::Read {
+                        reg: Some(convert_pe_register(reg)),
+                        base,
+                        offset,
+                    });
+                }
+                UnwindOperation::ReadXMM(_, offset) => {
+                    // The xmm registers aren't unwound, but the reads have to succeed.
+                    let (base, offset) = resolve_offset(offset);
+                    for offset in [offset, offset.wrapping_add(8)] {
+                        ops.push(PeOp::Read {
+                            reg: None,
+                            base,
+                            offset,
+                        });
+                    }
+                }
+                UnwindOperation::PopMachineFrame { error_code } => {
+                    let offset = if error_code { 8 } else { 0 };
+                    ops.push(PeOp::MachineFrameReturnAddress { offset });
+                    ops.push(PeOp::Read {
+                        reg: Some(Reg::RSP),
+                        base: Reg::RSP,
+                        offset: offset + 24,
+                    });
+                    return Ok(UnwindResult::Uncacheable(PeEvaluationX86_64::new(
+                        ops, false,
+                    )));
+                }
             }
         }
+        ops.push(PeOp::Return);
+        Ok(UnwindResult::Uncacheable(PeEvaluationX86_64::new(
+            ops, false,
+        )))
+    }
+}
+
+/// A step of a [`PeEvaluationX86_64`].
+#[derive(Debug, Clone, Copy)]
+enum PeOp {
+    /// rsp += offset
+    AddSp(u64),
+    /// rsp = base + offset
+    SetSp { base: Reg, offset: u64 },
+    /// reg = [rsp], rsp += 8
+    Pop(Reg),
+    /// reg = [base + offset], or just check that the value can be read if `reg` is
+    /// `None`.
+    Read {
+        reg: Option<Reg>,
+        base: Reg,
+        offset: u64,
+    },
+    /// The return address is [rsp + offset], which is pushed by an interrupt or an
+    /// exception.
+    MachineFrameReturnAddress { offset: u64 },
+    /// The return address is [rsp], rsp += 8
+    Return,
+}
+
+/// The evaluation of the unwind codes or of the epilog of a function which couldn't be
+/// translated into an [`UnwindRuleX86_64`]. It runs the steps in order, on the
+/// registers which it's stepped with, and results in the return address.
+pub struct PeEvaluationX86_64 {
+    ops: Vec<PeOp>,
+    next: usize,
+    /// Whether the steps come from an epilog. These report the address of failed reads.
+    epilog: bool,
+    return_address: Option<u64>,
+    provided: Option<Result<u64, MemoryReadError>>,
+    result: Option<Result<u64, PeUnwinderError>>,
+}
 
-        let rsp = regs.get(Reg::RSP);
-        let ra = read_stack_err(read_stack, rsp)?;
-        regs.set(Reg::RSP, rsp + 8);
+impl PeEvaluationX86_64 {
+    fn new(ops: Vec<PeOp>, epilog: bool) -> Self {
+        Self {
+            ops,
+            next: 0,
+            epilog,
+            return_address: None,
+            provided: None,
+            result: None,
+        }
+    }
+}
+
+impl Resumable<UnwindRegsX86_64> for PeEvaluationX86_64 {
+    type Output = Result<u64, PeUnwinderError>;
+
+    fn step(&mut self, regs: &mut UnwindRegsX86_64) -> Step<Self::Output> {
+        loop {
+            if let Some(result) = self.result {
+                return Step::Done(result);
+            }
+            let Some(&op) = self.ops.get(self.next) else {
+                self.result = Some(
+                    self.return_address
+                        .ok_or(PeUnwinderError::MissingStackData(None)),
+                );
+                continue;
+            };
+            let rsp = regs.get(Reg::RSP);
+            let (address, reports_address) = match op {
+                PeOp::AddSp(offset) => {
+                    regs.set(Reg::RSP, rsp.wrapping_add(offset));
+                    self.next += 1;
+                    continue;
+                }
+                PeOp::SetSp { base, offset } => {
+                    regs.set(Reg::RSP, regs.get(base).wrapping_add(offset));
+                    self.next += 1;
+                    continue;
+                }
+                PeOp::Pop(_) => (rsp, self.epilog),
+                PeOp::Read { base, offset, .. } => (regs.get(base).wrapping_add(offset), false),
+                PeOp::MachineFrameReturnAddress { offset } => (rsp.wrapping_add(offset), false),
+                PeOp::Return => (rsp, true),
+            };
+            let value = match self.provided.take() {
+                None => return Step::Read(ReadRequest::u64(address)),
+                Some(Ok(value)) => value,
+                Some(Err(_)) => {
+                    let address = reports_address.then_some(address);
+                    self.result = Some(Err(PeUnwinderError::MissingStackData(address)));
+                    continue;
+                }
+            };
+            match op {
+                PeOp::Pop(reg) => {
+                    regs.set(reg, value);
+                    regs.set(Reg::RSP, rsp.wrapping_add(8));
+                }
+                PeOp::Read { reg, .. } => {
+                    if let Some(reg) = reg {
+                        regs.set(reg, value);
+                    }
+                }
+                PeOp::MachineFrameReturnAddress { .. } => self.return_address = Some(value),
+                PeOp::Return => {
+                    self.return_address = Some(value);
+                    regs.set(Reg::RSP, rsp.wrapping_add(8));
+                }
+                PeOp::AddSp(_) | PeOp::SetSp { .. } => {}
+            }
+            self.next += 1;
+        }
+    }
 
-        Ok(UnwindResult::Uncacheable(ra))
+    fn provide(&mut self, value: Result<u64, MemoryReadError>) {
+        self.provided = Some(value);
     }
 }
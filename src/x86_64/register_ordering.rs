@@ -28,6 +28,11 @@ pub fn decode(count: u8, encoded_ordering: u16) -> ArrayVec<Reg, 8> {
     regs
 }
 
+/// Whether `decode` accepts these values. There are 8! orderings of the registers.
+pub fn is_valid(count: u8, encoded_ordering: u16) -> bool {
+    count <= 8 && encoded_ordering < 40320
+}
+
 pub fn encode(registers: &[Reg]) -> Option<(u8, u16)> {
     if registers.len() > ENCODE_REGISTERS.len() {
         return None;
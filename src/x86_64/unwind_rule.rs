@@ -2,7 +2,8 @@ use super::register_ordering;
 use super::unwindregs::{Reg, UnwindRegsX86_64};
 use crate::add_signed::checked_add_signed;
 use crate::error::Error;
-use crate::unwind_rule::UnwindRule;
+use crate::unwind_rule::{decode_rule, encode_rule, RulePlan, RuleReads, StackRead, UnwindRule};
+use crate::unwind_step::ReadRequest;
 use arrayvec::ArrayVec;
 
 /// For all of these: return address is *(new_sp - 8)
@@ -24,6 +25,14 @@ pub enum UnwindRuleX86_64 {
     },
     /// (sp, bp) = (bp + 16, *bp)
     UseFramePointer,
+    /// (sp, bp) = (bp + 8x, *(bp + 8y))
+    /// This is for functions which address their frame relative to bp but didn't set bp
+    /// up with the usual `push rbp; mov rbp, rsp`, e.g. because they saved more
+    /// registers before setting bp, or because they realign the stack.
+    UseFramePointerWithOffsets {
+        sp_offset_from_bp_by_8: i16,
+        bp_storage_offset_from_bp_by_8: i16,
+    },
     /// (sp, ...) = (sp + 8 * (offset + register count), ... popped according to encoded ordering)
     /// This supports the common case of pushed callee-saved registers followed by a stack
     /// allocation. Up to 8 registers can be stored, which covers all callee-saved registers (aside
@@ -84,10 +93,89 @@ impl UnwindRuleX86_64 {
             })
         }
     }
+
+    /// Serialize the rule into a stable binary form, e.g. to persist a rule cache or to
+    /// share rules with another process. The first byte is the kind of rule, followed by
+    /// the fields of the rule as little-endian 16-bit values. Unused bytes are zero.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        match *self {
+            UnwindRuleX86_64::EndOfStack => encode_rule(0, [0, 0, 0]),
+            UnwindRuleX86_64::JustReturn => encode_rule(1, [0, 0, 0]),
+            UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp => encode_rule(2, [0, 0, 0]),
+            UnwindRuleX86_64::OffsetSp { sp_offset_by_8 } => encode_rule(3, [sp_offset_by_8, 0, 0]),
+            UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8,
+                bp_storage_offset_from_sp_by_8,
+            } => encode_rule(
+                4,
+                [sp_offset_by_8, bp_storage_offset_from_sp_by_8 as u16, 0],
+            ),
+            UnwindRuleX86_64::UseFramePointer => encode_rule(5, [0, 0, 0]),
+            UnwindRuleX86_64::UseFramePointerWithOffsets {
+                sp_offset_from_bp_by_8,
+                bp_storage_offset_from_bp_by_8,
+            } => encode_rule(
+                7,
+                [
+                    sp_offset_from_bp_by_8 as u16,
+                    bp_storage_offset_from_bp_by_8 as u16,
+                    0,
+                ],
+            ),
+            UnwindRuleX86_64::OffsetSpAndPopRegisters {
+                sp_offset_by_8,
+                register_count,
+                encoded_registers_to_pop,
+            } => encode_rule(
+                6,
+                [
+                    sp_offset_by_8,
+                    u16::from(register_count),
+                    encoded_registers_to_pop,
+                ],
+            ),
+        }
+    }
+
+    /// Deserialize a rule which was serialized with [`UnwindRuleX86_64::to_bytes`].
+    /// Returns `None` if the bytes don't describe a valid rule.
+    pub fn from_bytes(bytes: [u8; 8]) -> Option<Self> {
+        let rule = match decode_rule(bytes)? {
+            (0, [0, 0, 0]) => UnwindRuleX86_64::EndOfStack,
+            (1, [0, 0, 0]) => UnwindRuleX86_64::JustReturn,
+            (2, [0, 0, 0]) => UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp,
+            (3, [sp_offset_by_8, 0, 0]) => UnwindRuleX86_64::OffsetSp { sp_offset_by_8 },
+            (4, [sp_offset_by_8, bp_storage_offset, 0]) => UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8,
+                bp_storage_offset_from_sp_by_8: bp_storage_offset as i16,
+            },
+            (5, [0, 0, 0]) => UnwindRuleX86_64::UseFramePointer,
+            (7, [sp_offset, bp_storage_offset, 0]) => {
+                UnwindRuleX86_64::UseFramePointerWithOffsets {
+                    sp_offset_from_bp_by_8: sp_offset as i16,
+                    bp_storage_offset_from_bp_by_8: bp_storage_offset as i16,
+                }
+            }
+            (6, [sp_offset_by_8, register_count, encoded_registers_to_pop]) => {
+                let register_count = u8::try_from(register_count).ok()?;
+                if !register_ordering::is_valid(register_count, encoded_registers_to_pop) {
+                    return None;
+                }
+                UnwindRuleX86_64::OffsetSpAndPopRegisters {
+                    sp_offset_by_8,
+                    register_count,
+                    encoded_registers_to_pop,
+                }
+            }
+            _ => return None,
+        };
+        Some(rule)
+    }
 }
 
 impl UnwindRule for UnwindRuleX86_64 {
     type UnwindRegs = UnwindRegsX86_64;
+    type Plan = RulePlanX86_64;
 
     fn rule_for_stub_functions() -> Self {
         UnwindRuleX86_64::JustReturn
@@ -95,45 +183,80 @@ impl UnwindRule for UnwindRuleX86_64 {
     fn rule_for_function_start() -> Self {
         UnwindRuleX86_64::JustReturn
     }
+    fn rule_for_end_of_stack() -> Self {
+        UnwindRuleX86_64::EndOfStack
+    }
+    fn rule_for_plt_lazy_resolver_entry() -> Self {
+        // PLT0 pushed the link map on top of the relocation index, which the PLT entry
+        // pushed on top of the return address.
+        UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 3 }
+    }
+    fn rule_for_dyld_stub_binder_entry() -> Self {
+        // The stub helper pushed the lazy binding info offset and a pointer to the
+        // image's dyld private data on top of the return address.
+        UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 3 }
+    }
     fn fallback_rule() -> Self {
         UnwindRuleX86_64::UseFramePointer
     }
 
-    fn exec<F>(
+    fn serialize(&self) -> [u8; 8] {
+        self.to_bytes()
+    }
+    fn deserialize(bytes: [u8; 8]) -> Option<Self> {
+        UnwindRuleX86_64::from_bytes(bytes)
+    }
+
+    #[cfg(feature = "return-address-predictor")]
+    fn rule_for_observed_frame(
+        callee: &UnwindRegsX86_64,
+        caller: &UnwindRegsX86_64,
+    ) -> Option<Self> {
+        let sp_offset = caller.sp().checked_sub(callee.sp())?;
+        if sp_offset % 8 != 0 {
+            return None;
+        }
+        let sp_offset_by_8 = u16::try_from(sp_offset / 8).ok()?;
+        if caller.bp() == callee.bp() {
+            return Some(UnwindRuleX86_64::OffsetSp { sp_offset_by_8 });
+        }
+        // bp is usually pushed right after the return address.
+        Some(UnwindRuleX86_64::OffsetSpAndRestoreBp {
+            sp_offset_by_8,
+            bp_storage_offset_from_sp_by_8: i16::try_from(sp_offset_by_8).ok()? - 2,
+        })
+    }
+
+    #[inline(always)]
+    fn plan_exec<S: RuleReads>(
         self,
         is_first_frame: bool,
-        regs: &mut UnwindRegsX86_64,
-        read_stack: &mut F,
-    ) -> Result<Option<u64>, Error>
-    where
-        F: FnMut(u64) -> Result<u64, ()>,
-    {
+        regs: &UnwindRegsX86_64,
+        reads: &mut S,
+    ) -> Result<Option<RulePlanX86_64>, Error> {
         let sp = regs.sp();
-        let (new_sp, new_bp) = match self {
+        let mut registers = ArrayVec::new();
+        let new_sp = match self {
             UnwindRuleX86_64::EndOfStack => return Ok(None),
-            UnwindRuleX86_64::JustReturn => {
-                let new_sp = sp.checked_add(8).ok_or(Error::IntegerOverflow)?;
-                (new_sp, regs.bp())
-            }
+            UnwindRuleX86_64::JustReturn => sp.checked_add(8).ok_or(Error::IntegerOverflow)?,
             UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp => {
                 if is_first_frame {
-                    let new_sp = sp.checked_add(8).ok_or(Error::IntegerOverflow)?;
-                    (new_sp, regs.bp())
+                    sp.checked_add(8).ok_or(Error::IntegerOverflow)?
                 } else {
-                    let sp = regs.sp();
                     let bp = regs.bp();
                     let new_sp = bp.checked_add(16).ok_or(Error::IntegerOverflow)?;
                     if new_sp <= sp {
                         return Err(Error::FramepointerUnwindingMovedBackwards);
                     }
-                    let new_bp = read_stack(bp).map_err(|_| Error::CouldNotReadStack(bp))?;
-                    (new_sp, new_bp)
+                    let request = ReadRequest::u64(bp).with_prefetch(bp, bp + 8);
+                    reads.read(StackRead::new(request))?;
+                    registers.push(Reg::RBP);
+                    new_sp
                 }
             }
             UnwindRuleX86_64::OffsetSp { sp_offset_by_8 } => {
                 let sp_offset = u64::from(sp_offset_by_8) * 8;
-                let new_sp = sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?;
-                (new_sp, regs.bp())
+                sp.checked_add(sp_offset).ok_or(Error::IntegerOverflow)?
             }
             UnwindRuleX86_64::OffsetSpAndRestoreBp {
                 sp_offset_by_8,
@@ -144,9 +267,12 @@ impl UnwindRule for UnwindRuleX86_64 {
                 let bp_storage_offset_from_sp = i64::from(bp_storage_offset_from_sp_by_8) * 8;
                 let bp_location = checked_add_signed(sp, bp_storage_offset_from_sp)
                     .ok_or(Error::IntegerOverflow)?;
-                let new_bp = match read_stack(bp_location) {
-                    Ok(new_bp) => new_bp,
-                    Err(()) if is_first_frame && bp_location < sp => {
+                let mut read = StackRead::new(ReadRequest::u64(bp_location));
+                if is_first_frame {
+                    // If the snapshot doesn't have this location, whatever the reader
+                    // would return for it isn't the saved bp.
+                    read.request = read.request.with_red_zone(sp);
+                    if bp_location < sp {
                         // Ignore errors when reading beyond the stack pointer in the first frame.
                         // These negative offsets are sometimes seen in x86_64 epilogues, where
                         // a bunch of registers are popped one after the other, and the compiler
@@ -155,11 +281,12 @@ impl UnwindRule for UnwindRuleX86_64 {
                         // read_stack may legitimately refuse to read beyond the stack pointer,
                         // for example when the stack bytes are coming from a linux perf event
                         // sample record, where the ustack bytes are copied starting from sp.
-                        regs.bp()
+                        read.fallback = Some(regs.bp());
                     }
-                    Err(()) => return Err(Error::CouldNotReadStack(bp_location)),
-                };
-                (new_sp, new_bp)
+                }
+                reads.read(read)?;
+                registers.push(Reg::RBP);
+                new_sp
             }
             UnwindRuleX86_64::UseFramePointer => {
                 // Do a frame pointer stack walk. Code that is compiled with frame pointers
@@ -201,7 +328,6 @@ impl UnwindRule for UnwindRuleX86_64 {
                 //     return_address: *const c_void,
                 // }
                 // and rbp is a *const CallFrameInfo.
-                let sp = regs.sp();
                 let bp = regs.bp();
                 if bp == 0 {
                     return Ok(None);
@@ -210,43 +336,104 @@ impl UnwindRule for UnwindRuleX86_64 {
                 if new_sp <= sp {
                     return Err(Error::FramepointerUnwindingMovedBackwards);
                 }
-                let new_bp = read_stack(bp).map_err(|_| Error::CouldNotReadStack(bp))?;
-                // new_bp is the caller's bp. If the caller uses frame pointers, then bp should be
-                // a valid frame pointer and we could do a coherency check on new_bp to make sure
-                // it's moving in the right direction. But if the caller is using bp as a general
-                // purpose register, then any value (including zero) would be a valid value.
-                // At this point we don't know how the caller uses bp, so we leave new_bp unchecked.
-
-                (new_sp, new_bp)
+                // Fetch the caller's bp and the return address together.
+                let request = ReadRequest::u64(bp).with_prefetch(bp, bp + 8);
+                reads.read(StackRead::new(request))?;
+                registers.push(Reg::RBP);
+                // The read bp is the caller's bp. If the caller uses frame pointers, then bp
+                // should be a valid frame pointer and we could do a coherency check on it to
+                // make sure it's moving in the right direction. But if the caller is using bp
+                // as a general purpose register, then any value (including zero) would be a
+                // valid value. At this point we don't know how the caller uses bp, so we
+                // leave it unchecked.
+                new_sp
+            }
+            UnwindRuleX86_64::UseFramePointerWithOffsets {
+                sp_offset_from_bp_by_8,
+                bp_storage_offset_from_bp_by_8,
+            } => {
+                let bp = regs.bp();
+                let new_sp = checked_add_signed(bp, i64::from(sp_offset_from_bp_by_8) * 8)
+                    .ok_or(Error::IntegerOverflow)?;
+                if new_sp <= sp {
+                    return Err(Error::FramepointerUnwindingMovedBackwards);
+                }
+                let bp_location =
+                    checked_add_signed(bp, i64::from(bp_storage_offset_from_bp_by_8) * 8)
+                        .ok_or(Error::IntegerOverflow)?;
+                let mut request = ReadRequest::u64(bp_location);
+                if let Some(ra_location) = new_sp.checked_sub(8) {
+                    request = request.with_prefetch(bp_location, ra_location);
+                }
+                reads.read(StackRead::new(request))?;
+                registers.push(Reg::RBP);
+                new_sp
             }
             UnwindRuleX86_64::OffsetSpAndPopRegisters {
                 sp_offset_by_8,
                 register_count,
                 encoded_registers_to_pop,
             } => {
-                let sp = regs.sp();
                 let mut sp = sp
                     .checked_add(sp_offset_by_8 as u64 * 8)
                     .ok_or(Error::IntegerOverflow)?;
+                // The popped registers are followed by the return address. The first read
+                // fetches them all.
+                let mut prefetch = sp
+                    .checked_add(u64::from(register_count) * 8)
+                    .map(|ra_location| (sp, ra_location));
+                let mut request = |location| {
+                    let request = ReadRequest::u64(location);
+                    match prefetch.take() {
+                        Some((first, last)) => request.with_prefetch(first, last),
+                        None => request,
+                    }
+                };
                 for reg in register_ordering::decode(register_count, encoded_registers_to_pop) {
-                    let value = read_stack(sp).map_err(|_| Error::CouldNotReadStack(sp))?;
+                    reads.read(StackRead::new(request(sp)))?;
+                    registers.push(reg);
                     sp = sp.checked_add(8).ok_or(Error::IntegerOverflow)?;
-                    regs.set(reg, value);
                 }
-                (sp.checked_add(8).ok_or(Error::IntegerOverflow)?, regs.bp())
+                let new_sp = sp.checked_add(8).ok_or(Error::IntegerOverflow)?;
+                let ra_location = new_sp - 8;
+                reads.read(StackRead::new(request(ra_location)))?;
+                return Ok(Some(RulePlanX86_64 { new_sp, registers }));
             }
         };
-        let return_address =
-            read_stack(new_sp - 8).map_err(|_| Error::CouldNotReadStack(new_sp - 8))?;
+        let ra_location = new_sp.checked_sub(8).ok_or(Error::IntegerOverflow)?;
+        reads.read(StackRead::new(ReadRequest::u64(ra_location)))?;
+        Ok(Some(RulePlanX86_64 { new_sp, registers }))
+    }
+}
+
+/// What an x86_64 unwind rule does with the values it reads: the registers in
+/// `registers` are restored from the first values, and the last value is the return
+/// address.
+#[derive(Debug, Clone)]
+pub struct RulePlanX86_64 {
+    new_sp: u64,
+    registers: ArrayVec<Reg, 8>,
+}
+
+impl RulePlan for RulePlanX86_64 {
+    type UnwindRegs = UnwindRegsX86_64;
+
+    #[inline(always)]
+    fn finish(self, regs: &mut UnwindRegsX86_64, values: &[u64]) -> Result<Option<u64>, Error> {
+        let Some((&return_address, restored)) = values.split_last() else {
+            return Ok(None);
+        };
         if return_address == 0 {
             return Ok(None);
         }
-        if new_sp == sp && return_address == regs.ip() {
+        if self.new_sp == regs.sp() && return_address == regs.ip() {
             return Err(Error::DidNotAdvance);
         }
+        for (&reg, &value) in self.registers.iter().zip(restored) {
+            regs.set(reg, value);
+        }
         regs.set_ip(return_address);
-        regs.set_sp(new_sp);
-        regs.set_bp(new_bp);
+        regs.set_sp(self.new_sp);
         Ok(Some(return_address))
     }
 }
@@ -254,13 +441,15 @@ impl UnwindRule for UnwindRuleX86_64 {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::memory_reader::{MemoryReadError, MemoryReader};
+    use core::ops::Range;
 
     #[test]
     fn test_basic() {
         let stack = [
             1, 2, 0x100300, 4, 0x40, 0x100200, 5, 6, 0x70, 0x100100, 7, 8, 9, 10, 0x0, 0x0,
         ];
-        let mut read_stack = |addr| Ok(stack[(addr / 8) as usize]);
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
         let mut regs = UnwindRegsX86_64::new(0x100400, 0x10, 0x20);
         let res =
             UnwindRuleX86_64::OffsetSp { sp_offset_by_8: 1 }.exec(true, &mut regs, &mut read_stack);
@@ -282,6 +471,89 @@ mod test {
         assert_eq!(res, Ok(None));
     }
 
+    #[test]
+    fn test_frame_pointer_with_offsets() {
+        // CFA=rbp+32: rbp=[CFA-16], ra=[CFA-8], as in _ffi_call_unix64.
+        let rule = UnwindRuleX86_64::UseFramePointerWithOffsets {
+            sp_offset_from_bp_by_8: 4,
+            bp_storage_offset_from_bp_by_8: 2,
+        };
+        let stack = [1, 2, 3, 4, 5, 6, 0x90, 0x100300, 7, 8];
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x10, 0x20);
+        let res = rule.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Ok(Some(0x100300)));
+        assert_eq!(regs.sp(), 0x40);
+        assert_eq!(regs.bp(), 0x90);
+
+        // The caller's sp must be above the callee's sp.
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x40, 0x20);
+        let res = rule.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Err(Error::FramepointerUnwindingMovedBackwards));
+
+        // The caller's sp is too small to hold the return address.
+        let rule = UnwindRuleX86_64::UseFramePointerWithOffsets {
+            sp_offset_from_bp_by_8: 0,
+            bp_storage_offset_from_bp_by_8: 0,
+        };
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x0, 0x4);
+        let res = rule.exec(false, &mut regs, &mut read_stack);
+        assert_eq!(res, Err(Error::IntegerOverflow));
+    }
+
+    /// A copy of the stack starting at `start`, which returns zero for the addresses
+    /// below it instead of an error.
+    struct SnapshotReader<'a> {
+        start: u64,
+        stack: &'a [u64],
+    }
+
+    impl MemoryReader for SnapshotReader<'_> {
+        fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+            match address.checked_sub(self.start) {
+                Some(offset) => self
+                    .stack
+                    .get((offset / 8) as usize)
+                    .copied()
+                    .ok_or(MemoryReadError::OutsideSnapshot),
+                None => Ok(0),
+            }
+        }
+
+        fn snapshot_range(&mut self) -> Option<Range<u64>> {
+            Some(self.start..self.start + self.stack.len() as u64 * 8)
+        }
+    }
+
+    #[test]
+    fn test_red_zone_outside_snapshot() {
+        // bp was saved in the red zone, 16 bytes below sp.
+        let rule = UnwindRuleX86_64::OffsetSpAndRestoreBp {
+            sp_offset_by_8: 1,
+            bp_storage_offset_from_sp_by_8: -2,
+        };
+
+        // The snapshot starts at sp, so bp keeps its value.
+        let mut reader = SnapshotReader {
+            start: 0x100,
+            stack: &[0x100300, 0x1234],
+        };
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x100, 0x200);
+        let res = rule.exec(true, &mut regs, &mut reader);
+        assert_eq!(res, Ok(Some(0x100300)));
+        assert_eq!(regs.bp(), 0x200);
+
+        // The snapshot includes the red zone, so bp is restored from it.
+        let mut reader = SnapshotReader {
+            start: 0xf0,
+            stack: &[0x300, 0x0, 0x100300, 0x1234],
+        };
+        let mut regs = UnwindRegsX86_64::new(0x100400, 0x100, 0x200);
+        let res = rule.exec(true, &mut regs, &mut reader);
+        assert_eq!(res, Ok(Some(0x100300)));
+        assert_eq!(regs.bp(), 0x300);
+    }
+
     #[test]
     fn test_overflow() {
         // This test makes sure that debug builds don't panic when trying to use frame pointer
@@ -290,7 +562,7 @@ mod test {
         let stack = [
             1, 2, 0x100300, 4, 0x40, 0x100200, 5, 6, 0x70, 0x100100, 7, 8, 9, 10, 0x0, 0x0,
         ];
-        let mut read_stack = |addr| Ok(stack[(addr / 8) as usize]);
+        let mut read_stack = |addr| Ok::<_, ()>(stack[(addr / 8) as usize]);
         let mut regs = UnwindRegsX86_64::new(0x100400, u64::MAX / 8 * 8, u64::MAX);
         let res = UnwindRuleX86_64::JustReturn.exec(true, &mut regs, &mut read_stack);
         assert_eq!(res, Err(Error::IntegerOverflow));
@@ -306,4 +578,37 @@ mod test {
         let res = UnwindRuleX86_64::UseFramePointer.exec(true, &mut regs, &mut read_stack);
         assert_eq!(res, Err(Error::IntegerOverflow));
     }
+
+    #[test]
+    fn test_serialization() {
+        let rules = [
+            UnwindRuleX86_64::EndOfStack,
+            UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp,
+            UnwindRuleX86_64::OffsetSpAndRestoreBp {
+                sp_offset_by_8: 3,
+                bp_storage_offset_from_sp_by_8: -2,
+            },
+            UnwindRuleX86_64::UseFramePointerWithOffsets {
+                sp_offset_from_bp_by_8: 4,
+                bp_storage_offset_from_bp_by_8: -2,
+            },
+            UnwindRuleX86_64::for_sequence_of_offset_or_pop(
+                [OffsetOrPop::OffsetBy8(4), OffsetOrPop::Pop(Reg::R15)].into_iter(),
+            )
+            .unwrap(),
+        ];
+        for rule in rules {
+            assert_eq!(UnwindRuleX86_64::from_bytes(rule.to_bytes()), Some(rule));
+        }
+        assert_eq!(
+            UnwindRuleX86_64::OffsetSp {
+                sp_offset_by_8: 0x102
+            }
+            .to_bytes(),
+            [3, 2, 1, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(UnwindRuleX86_64::from_bytes([3, 2, 1, 1, 0, 0, 0, 0]), None);
+        assert_eq!(UnwindRuleX86_64::from_bytes([6, 0, 0, 9, 0, 0, 0, 0]), None);
+        assert_eq!(UnwindRuleX86_64::from_bytes([8, 0, 0, 0, 0, 0, 0, 0]), None);
+    }
 }
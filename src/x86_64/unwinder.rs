@@ -1,13 +1,32 @@
-use core::ops::Deref;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::num::NonZeroU64;
+use core::ops::{Deref, Range};
 
 use super::arch::ArchX86_64;
 use super::cache::CacheX86_64;
+use super::unwind_rule::UnwindRuleX86_64;
 use super::unwindregs::UnwindRegsX86_64;
 use crate::cache::{AllocationPolicy, MayAllocateDuringUnwind};
+use crate::diagnostics::Diagnostic;
 use crate::error::Error;
+use crate::foreign_unwinder::ForeignUnwinder;
+use crate::frame_info::{FallbackPolicy, FrameUnwindInfo};
+use crate::jit_range::JitRange;
+use crate::memory_reader::MemoryReader;
+use crate::stack_link::StackLink;
+use crate::trace::Tracer;
+#[cfg(feature = "trace")]
+use crate::trace::UnwindTrace;
+use crate::unwind_step::FrameUnwindStepper;
 use crate::unwinder::UnwinderInternal;
-use crate::unwinder::{Module, Unwinder};
-use crate::FrameAddress;
+use crate::unwinder::{AddModuleOutcome, Module, ModuleDescriptor, SyncModulesOutcome, Unwinder};
+use crate::{CacheStats, CodeId, FrameAddress, InstructionPointerAdjustment};
+
+/// The [`UnwindTrace`] type for the x86_64 CPU architecture.
+#[cfg(feature = "trace")]
+pub type UnwindTraceX86_64 = UnwindTrace<UnwindRuleX86_64, UnwindRegsX86_64>;
 
 /// The unwinder for the x86_64 CPU architecture. Use the [`Unwinder`] trait for unwinding.
 ///
@@ -34,35 +53,341 @@ impl<D, P> UnwinderX86_64<D, P> {
     pub fn new() -> Self {
         Self(UnwinderInternal::new())
     }
+
+    /// Set a callback which receives [`Diagnostic`]s about unusual situations
+    /// encountered by this unwinder, for example unwind information which could
+    /// not be used. Replaces any previously-set callback.
+    pub fn set_diagnostics_callback<C>(&mut self, callback: C)
+    where
+        C: Fn(&Diagnostic) + Send + Sync + 'static,
+    {
+        self.0.set_diagnostics_callback(Some(Arc::new(callback)));
+    }
+
+    /// Remove the diagnostics callback.
+    pub fn clear_diagnostics_callback(&mut self) {
+        self.0.set_diagnostics_callback(None);
+    }
+
+    /// Register a stack-switch trampoline, so that unwinding continues on the parent
+    /// stack when it reaches a frame inside the trampoline. See [`StackLink`].
+    ///
+    /// Stack links are checked before the unwind information of the modules. If the
+    /// address ranges of several stack links overlap, the one which was added first is
+    /// used.
+    pub fn add_stack_link(&mut self, stack_link: StackLink) {
+        self.0.add_stack_link(stack_link);
+    }
+
+    /// Remove all stack links which were added with `add_stack_link`.
+    pub fn clear_stack_links(&mut self) {
+        self.0.clear_stack_links();
+    }
+
+    /// Set the rule which is used for addresses outside of any module, and for addresses
+    /// whose unwind information couldn't be used. The default is
+    /// [`UnwindRuleX86_64::UseFramePointer`]. For example, use
+    /// [`UnwindRuleX86_64::EndOfStack`] to stop unwinding instead of guessing, or
+    /// [`UnwindRuleX86_64::JustReturnIfFirstFrameOtherwiseFp`] if the first frame is
+    /// often in a leaf function which doesn't set up a frame pointer.
+    ///
+    /// Modules with [`ModuleBuilder::frame_pointers_guaranteed`](crate::ModuleBuilder::frame_pointers_guaranteed)
+    /// still use the frame pointer.
+    pub fn set_fallback_rule(&mut self, rule: UnwindRuleX86_64) {
+        self.0.set_fallback_rule(rule);
+    }
+
+    /// Set what happens to frames which would be unwound with the fallback rule. The
+    /// default is [`FallbackPolicy::UseFallback`]. Crash reporters which need reliable
+    /// stacks can use [`FallbackPolicy::StopStack`] to end the stack at the first such
+    /// frame, or [`FallbackPolicy::ReturnError`] to also tell this apart from the root of
+    /// the stack.
+    pub fn set_fallback_policy(&mut self, policy: FallbackPolicy) {
+        self.0.set_fallback_policy(policy);
+    }
+
+    /// Unwind every frame with the frame pointer, i.e. with
+    /// [`UnwindRuleX86_64::UseFramePointer`], without looking up the unwind information of
+    /// the modules. This is the cheapest way to unwind if all code is compiled with frame
+    /// pointers. Stack links, foreign unwinders, JIT ranges, root address ranges and
+    /// stack end sentinels still apply, and the rule's checks still end the stack when
+    /// the frame pointer doesn't move up the stack. Rules are not cached in this mode.
+    ///
+    /// The first frame is unwound with the frame pointer as well, so its caller is
+    /// skipped if the instruction pointer is in a function which hasn't set up its
+    /// frame yet.
+    pub fn set_frame_pointer_only(&mut self, frame_pointer_only: bool) {
+        self.0.set_frame_pointer_only(frame_pointer_only);
+    }
+
+    /// In frame-pointer-only mode, also unwind every `interval`-th frame with the unwind
+    /// information of its module, and count in the [`UnwindStats`](crate::UnwindStats)
+    /// of the cache how often the frame pointer produced a different caller. This
+    /// measures how reliable the frame pointers of a workload are, at a fraction of the
+    /// cost of always using the unwind information. Frames which the unwind information
+    /// doesn't cover aren't verified. `None`, the default, disables the verification.
+    pub fn set_frame_pointer_verification_interval(&mut self, interval: Option<NonZeroU64>) {
+        self.0.set_frame_pointer_verification_interval(interval);
+    }
+
+    /// Set how instruction pointers are adjusted before their unwind information is
+    /// looked up. See [`InstructionPointerAdjustment`].
+    pub fn set_instruction_pointer_adjustment(&mut self, adjustment: InstructionPointerAdjustment) {
+        self.0.set_instruction_pointer_adjustment(adjustment);
+    }
+
+    /// Declare that the function at `avma_range` is the root of the stack, e.g. the
+    /// entry point of the process or of its threads. Unwinding a frame in this range
+    /// ends the stack with `Ok(None)`, even if the function's unwind information doesn't
+    /// say so and the frame pointer isn't zero.
+    pub fn add_root_address_range(&mut self, avma_range: Range<u64>) {
+        self.0.add_root_address_range(avma_range);
+    }
+
+    /// Remove all ranges which were added with `add_root_address_range`.
+    pub fn clear_root_address_ranges(&mut self) {
+        self.0.clear_root_address_ranges();
+    }
+
+    /// Tag the code at `avma_range`, e.g. a signal trampoline, a JIT entry stub or a
+    /// syscall wrapper, so that iterators created with
+    /// [`UnwindIterator::with_frame_markers`](crate::UnwindIterator::with_frame_markers)
+    /// or [`UnwindIterator::with_frame_hook`](crate::UnwindIterator::with_frame_hook)
+    /// yield a [`MixedFrame::Marker`](crate::MixedFrame::Marker) with `tag` before
+    /// every frame in this range. The meaning of the tag is up to the caller. Marker
+    /// ranges don't change how frames are unwound.
+    pub fn add_marker_range(&mut self, avma_range: Range<u64>, tag: u32) {
+        self.0.add_marker_range(avma_range, tag);
+    }
+
+    /// Remove all ranges which were added with `add_marker_range`.
+    pub fn clear_marker_ranges(&mut self) {
+        self.0.clear_marker_ranges();
+    }
+
+    /// Set the stack pointer values at which the stack ends, e.g. the known tops of the
+    /// threads' stacks. If unwinding a frame restores one of these values as the stack
+    /// pointer, unwinding ends with `Ok(None)` instead of reading the caller's frame
+    /// from outside the stack.
+    pub fn set_stack_end_sentinels(&mut self, sentinels: Vec<u64>) {
+        self.0.set_stack_end_sentinels(sentinels);
+    }
+
+    /// Replace the ranges of JIT code outside of any module, e.g. after reading the
+    /// JIT's perf map file with [`JitRange::from_perf_map`]. Frames in these ranges are
+    /// unwound according to the hint of their range instead of with the fallback rule.
+    /// Replacing the ranges is cheap and doesn't invalidate the rule cache.
+    pub fn set_jit_ranges(&mut self, ranges: Vec<JitRange>) {
+        self.0.set_jit_ranges(ranges);
+    }
+
+    /// Add a range of JIT code, e.g. for a function which the JIT just compiled. Ranges
+    /// which overlap the new range are removed.
+    pub fn add_jit_range(&mut self, range: JitRange) {
+        self.0.add_jit_range(range);
+    }
+
+    /// Remove all JIT ranges.
+    pub fn clear_jit_ranges(&mut self) {
+        self.0.clear_jit_ranges();
+    }
+
+    /// Register a callback which unwinds the frames in `avma_range`, for code that
+    /// framehop can't unwind on its own, e.g. the interpreter and the compiled code of
+    /// a JVM, whose frames a mixed-mode profiler walks with the JVM's own stack walker.
+    ///
+    /// The callback sets the registers to the values of the caller frame and returns
+    /// the return address, or `None` if the stack ends. Unwinding then continues
+    /// natively in the caller frame. The callback's results are not cached.
+    ///
+    /// Foreign unwinders are checked after stack links and before JIT ranges and the
+    /// modules. If the address ranges of several foreign unwinders overlap, the one
+    /// which was added first is used.
+    pub fn add_foreign_unwinder<C>(&mut self, avma_range: Range<u64>, callback: C)
+    where
+        C: Fn(
+                FrameAddress,
+                &mut UnwindRegsX86_64,
+                &mut dyn MemoryReader,
+            ) -> Result<Option<u64>, Error>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.0.add_foreign_unwinder(ForeignUnwinder {
+            avma_range,
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// Remove all callbacks which were added with `add_foreign_unwinder`.
+    pub fn clear_foreign_unwinders(&mut self) {
+        self.0.clear_foreign_unwinders();
+    }
+}
+
+impl<D: Deref<Target = [u8]>, P: AllocationPolicy> UnwinderX86_64<D, P> {
+    /// Returns the number of bytes held by all modules. See [`Module::memory_usage`].
+    /// Data which modules share with their clones is counted once.
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+
+    /// Limit the number of bytes held by the modules. If the modules hold more than
+    /// `budget`, the text bytes of some modules are dropped, which makes unwinding in
+    /// function prologues and epilogues less reliable. Unwind sections and indexes are
+    /// never dropped, and neither is data which a module shares with its clones, so the
+    /// usage can stay above the budget. This is reported with
+    /// [`Diagnostic::MemoryBudgetExceeded`](crate::Diagnostic::MemoryBudgetExceeded).
+    ///
+    /// The budget is checked now and whenever a module is added. Modules created with
+    /// [`Module::new_lazy`] are only counted once they are loaded.
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        self.0.set_memory_budget(budget);
+    }
+
+    /// Load the unwind information of the modules created with
+    /// [`Module::new_lazy`] which contain any of `addresses`, so that their loaders
+    /// don't run during unwinding, e.g. in a signal handler. See [`Module::load`].
+    /// Returns the number of modules which weren't loaded before.
+    #[cfg(feature = "std")]
+    pub fn load_modules_for(&mut self, addresses: impl IntoIterator<Item = u64>) -> usize {
+        self.0.load_modules_for(addresses)
+    }
 }
 
 impl<D: Deref<Target = [u8]>, P: AllocationPolicy> Unwinder for UnwinderX86_64<D, P> {
     type UnwindRegs = UnwindRegsX86_64;
-    type Cache = CacheX86_64<P>;
+    type Cache<'s> = CacheX86_64<'s, P>;
     type Module = Module<D>;
+    #[cfg(feature = "trace")]
+    type UnwindTrace = UnwindTraceX86_64;
 
-    fn add_module(&mut self, module: Module<D>) {
-        self.0.add_module(module);
+    fn add_module(&mut self, module: Module<D>) -> AddModuleOutcome {
+        self.0.add_module(module)
     }
 
     fn remove_module(&mut self, module_address_range_start: u64) {
         self.0.remove_module(module_address_range_start);
     }
 
+    fn set_modules(&mut self, modules: Vec<Self::Module>) {
+        self.0.set_modules(modules);
+    }
+
+    fn sync_modules<I, L>(&mut self, current: I, load: L) -> SyncModulesOutcome
+    where
+        I: IntoIterator<Item = ModuleDescriptor>,
+        L: FnMut(&ModuleDescriptor) -> Option<Self::Module>,
+    {
+        self.0.sync_modules(current, load)
+    }
+
     fn max_known_code_address(&self) -> u64 {
         self.0.max_known_code_address()
     }
 
+    fn modules_generation(&self) -> u16 {
+        self.0.modules_generation()
+    }
+
+    fn module_relative_address(&self, address: FrameAddress) -> Option<(Option<&CodeId>, u32)> {
+        self.0.module_relative_address(address)
+    }
+
+    fn module_for_address(&self, address: u64) -> Option<&Self::Module> {
+        self.0.module_for_address(address)
+    }
+
+    fn modules(&self) -> &[Self::Module] {
+        self.0.modules()
+    }
+
+    fn marker_for_address(&self, address: u64) -> Option<u32> {
+        self.0.marker_for_address(address)
+    }
+
+    fn cache_stats(&self, cache: &Self::Cache<'_>) -> CacheStats {
+        cache.stats()
+    }
+
     fn unwind_frame<F>(
         &self,
         address: FrameAddress,
         regs: &mut UnwindRegsX86_64,
-        cache: &mut CacheX86_64<P>,
+        cache: &mut CacheX86_64<'_, P>,
+        read_stack: &mut F,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.0.unwind_frame(
+            address,
+            regs,
+            &mut cache.0,
+            read_stack,
+            None,
+            &mut Tracer::disabled(),
+        )
+    }
+
+    fn unwind_frame_with_info<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsX86_64,
+        cache: &mut CacheX86_64<'_, P>,
+        read_stack: &mut F,
+        info: &mut FrameUnwindInfo,
+    ) -> Result<Option<u64>, Error>
+    where
+        F: MemoryReader,
+    {
+        self.0.unwind_frame(
+            address,
+            regs,
+            &mut cache.0,
+            read_stack,
+            Some(info),
+            &mut Tracer::disabled(),
+        )
+    }
+
+    fn frame_unwind_stepper<'s>(
+        &self,
+        address: FrameAddress,
+        regs: UnwindRegsX86_64,
+    ) -> FrameUnwindStepper<'_, 's, Self> {
+        let task = self.0.frame_unwind_task(address);
+        FrameUnwindStepper::new(address, regs, Box::new(task))
+    }
+
+    fn precompute_rules<I>(&self, cache: &mut CacheX86_64<'_, P>, addresses: I) -> usize
+    where
+        I: IntoIterator<Item = FrameAddress>,
+    {
+        self.0
+            .precompute_rules(&mut cache.0, addresses, &UnwindRegsX86_64::new(0, 0, 0))
+    }
+
+    #[cfg(feature = "trace")]
+    fn unwind_frame_traced<F>(
+        &self,
+        address: FrameAddress,
+        regs: &mut UnwindRegsX86_64,
+        cache: &mut CacheX86_64<'_, P>,
         read_stack: &mut F,
+        trace: &mut UnwindTraceX86_64,
     ) -> Result<Option<u64>, Error>
     where
-        F: FnMut(u64) -> Result<u64, ()>,
+        F: MemoryReader,
     {
-        self.0.unwind_frame(address, regs, &mut cache.0, read_stack)
+        self.0.unwind_frame(
+            address,
+            regs,
+            &mut cache.0,
+            read_stack,
+            None,
+            &mut Tracer::new(trace),
+        )
     }
 }
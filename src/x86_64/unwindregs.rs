@@ -1,6 +1,9 @@
 use core::fmt::Debug;
 
 use crate::display_utils::HexNum;
+use crate::partial_regs::PartialUnwindRegs;
+use crate::stack_link::StackLinkRegs;
+use crate::unwind_regs::UnwindRegs;
 
 #[derive(Clone, Copy, PartialEq, Eq)]
 pub struct UnwindRegsX86_64 {
@@ -29,6 +32,14 @@ pub enum Reg {
     R15,
 }
 
+impl Reg {
+    /// The register with the DWARF register number `register`, if it is one of the
+    /// general-purpose registers.
+    pub fn from_dwarf(register: u16) -> Option<Self> {
+        REGS.get(usize::from(register)).copied()
+    }
+}
+
 impl UnwindRegsX86_64 {
     pub fn new(ip: u64, sp: u64, bp: u64) -> Self {
         let mut r = Self {
@@ -77,6 +88,210 @@ impl UnwindRegsX86_64 {
     }
 }
 
+/// The DWARF register numbers of rax to r15 are the indexes of [`Reg`], and the return
+/// address register, 16, is the instruction pointer.
+impl UnwindRegs for UnwindRegsX86_64 {
+    fn get_dwarf_reg(&self, register: u16) -> Option<u64> {
+        match register {
+            0..=15 => Some(self.regs[usize::from(register)]),
+            16 => Some(self.ip()),
+            _ => None,
+        }
+    }
+
+    fn set_dwarf_reg(&mut self, register: u16, value: u64) -> bool {
+        match register {
+            0..=15 => self.regs[usize::from(register)] = value,
+            16 => self.set_ip(value),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl StackLinkRegs for UnwindRegsX86_64 {
+    fn sp(&self) -> u64 {
+        self.sp()
+    }
+
+    fn fp(&self) -> u64 {
+        self.bp()
+    }
+
+    fn switch_to_parent_stack(&mut self, sp: u64, fp: u64, return_address: u64) -> u64 {
+        self.set_sp(sp);
+        self.set_bp(fp);
+        self.set_ip(return_address);
+        return_address
+    }
+}
+
+/// The registers of x86_64 which are needed for unwinding, when the frame pointer
+/// might be unknown. See
+/// [`Unwinder::unwind_frame_with_partial_regs`](crate::Unwinder::unwind_frame_with_partial_regs).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PartialUnwindRegsX86_64 {
+    pub ip: u64,
+    pub sp: u64,
+    pub bp: Option<u64>,
+}
+
+impl PartialUnwindRegsX86_64 {
+    pub fn new(ip: u64, sp: u64, bp: Option<u64>) -> Self {
+        Self { ip, sp, bp }
+    }
+}
+
+impl PartialUnwindRegs for PartialUnwindRegsX86_64 {
+    type Regs = UnwindRegsX86_64;
+
+    fn is_complete(&self) -> bool {
+        self.bp.is_some()
+    }
+
+    fn with_placeholder(&self, placeholder: u64) -> UnwindRegsX86_64 {
+        UnwindRegsX86_64::new(self.ip, self.sp, self.bp.unwrap_or(placeholder))
+    }
+
+    fn set_from_unwound(&mut self, first: &UnwindRegsX86_64, second: &UnwindRegsX86_64) {
+        self.ip = first.ip();
+        self.sp = first.sp();
+        self.bp = Some(first.bp()).filter(|bp| *bp == second.bp());
+    }
+}
+
+/// All general-purpose registers of x86_64, for consumers which need the complete
+/// register file of the caller, e.g. debuggers which evaluate variable locations.
+///
+/// [`UnwindRegsX86_64`] only recovers the registers which are needed to keep unwinding.
+/// When a frame is unwound with
+/// [`unwind_full_frame_with_eh_frame`](crate::x86_64::unwind_full_frame_with_eh_frame),
+/// every register which has a rule in the CFI is recovered. Registers without a rule
+/// keep their values, and registers whose rule could not be evaluated become unknown.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct FullUnwindRegsX86_64 {
+    ip: u64,
+    regs: [u64; 16],
+    /// Bit `n` is set if the register with index `n` is known.
+    known: u16,
+}
+
+impl FullUnwindRegsX86_64 {
+    /// Create a register file in which all registers are known. `regs` is indexed by
+    /// [`Reg`].
+    pub fn new(ip: u64, regs: [u64; 16]) -> Self {
+        Self {
+            ip,
+            regs,
+            known: u16::MAX,
+        }
+    }
+
+    /// The value of `reg`, or `None` if it could not be recovered.
+    #[inline(always)]
+    pub fn get(&self, reg: Reg) -> Option<u64> {
+        if self.known & (1 << reg as u16) != 0 {
+            Some(self.regs[reg as usize])
+        } else {
+            None
+        }
+    }
+    #[inline(always)]
+    pub fn set(&mut self, reg: Reg, value: u64) {
+        self.regs[reg as usize] = value;
+        self.known |= 1 << reg as u16;
+    }
+    /// Mark `reg` as unknown.
+    #[inline(always)]
+    pub fn clear(&mut self, reg: Reg) {
+        self.known &= !(1 << reg as u16);
+    }
+
+    #[inline(always)]
+    pub fn ip(&self) -> u64 {
+        self.ip
+    }
+    #[inline(always)]
+    pub fn set_ip(&mut self, ip: u64) {
+        self.ip = ip
+    }
+
+    /// The stack pointer, which is always known, because unwinding sets it to the CFA.
+    #[inline(always)]
+    pub fn sp(&self) -> u64 {
+        self.regs[Reg::RSP as usize]
+    }
+    #[inline(always)]
+    pub fn set_sp(&mut self, sp: u64) {
+        self.set(Reg::RSP, sp)
+    }
+
+    /// The registers which are needed to continue unwinding with an
+    /// [`UnwinderX86_64`](crate::x86_64::UnwinderX86_64). An unknown rbp becomes zero.
+    pub fn to_unwind_regs(&self) -> UnwindRegsX86_64 {
+        UnwindRegsX86_64::new(self.ip, self.sp(), self.get(Reg::RBP).unwrap_or(0))
+    }
+}
+
+/// Uses the same register numbers as [`UnwindRegsX86_64`]. Unknown registers are `None`.
+impl UnwindRegs for FullUnwindRegsX86_64 {
+    fn get_dwarf_reg(&self, register: u16) -> Option<u64> {
+        match Reg::from_dwarf(register) {
+            Some(reg) => self.get(reg),
+            None if register == 16 => Some(self.ip()),
+            None => None,
+        }
+    }
+
+    fn set_dwarf_reg(&mut self, register: u16, value: u64) -> bool {
+        match Reg::from_dwarf(register) {
+            Some(reg) => self.set(reg, value),
+            None if register == 16 => self.set_ip(value),
+            None => return false,
+        }
+        true
+    }
+}
+
+/// The registers in the order of their DWARF register numbers.
+const REGS: [Reg; 16] = [
+    Reg::RAX,
+    Reg::RDX,
+    Reg::RCX,
+    Reg::RBX,
+    Reg::RSI,
+    Reg::RDI,
+    Reg::RBP,
+    Reg::RSP,
+    Reg::R8,
+    Reg::R9,
+    Reg::R10,
+    Reg::R11,
+    Reg::R12,
+    Reg::R13,
+    Reg::R14,
+    Reg::R15,
+];
+
+impl Debug for FullUnwindRegsX86_64 {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut s = f.debug_struct("FullUnwindRegsX86_64");
+        s.field("ip", &HexNum(self.ip()));
+        for (reg, name) in REGS.iter().zip(REG_NAMES) {
+            match self.get(*reg) {
+                Some(value) => s.field(name, &HexNum(value)),
+                None => s.field(name, &format_args!("<unknown>")),
+            };
+        }
+        s.finish()
+    }
+}
+
+const REG_NAMES: [&str; 16] = [
+    "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12", "r13",
+    "r14", "r15",
+];
+
 impl Debug for UnwindRegsX86_64 {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("UnwindRegsX86_64")
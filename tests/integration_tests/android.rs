@@ -0,0 +1,91 @@
+use std::path::Path;
+
+use framehop::aarch64::*;
+use framehop::android::{apk_entry_name, MappedModule};
+use framehop::*;
+
+use super::common;
+
+const BASE_AVMA: u64 = 0x7a_1000_0000;
+const ENTRY_NAME: &str = "lib/arm64-v8a/libmozglue.so";
+
+/// A zip file with a single uncompressed entry, whose data is page-aligned like the
+/// libraries in an APK. Returns the zip data and the offset of the entry's data.
+fn stored_zip(name: &str, data: &[u8]) -> (Vec<u8>, u64) {
+    let mut zip = Vec::new();
+    zip.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0; 26]);
+    zip.extend_from_slice(name.as_bytes());
+    let padding = 0x1000 - zip.len();
+    zip[26..28].copy_from_slice(&(name.len() as u16).to_le_bytes());
+    zip[28..30].copy_from_slice(&(padding as u16).to_le_bytes());
+    zip.resize(0x1000, 0);
+    zip.extend_from_slice(data);
+
+    let central_directory_offset = zip.len() as u32;
+    zip.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0; 24]);
+    zip.extend_from_slice(&(name.len() as u16).to_le_bytes());
+    zip.extend_from_slice(&[0; 16]);
+    zip.extend_from_slice(name.as_bytes());
+    let central_directory_len = zip.len() as u32 - central_directory_offset;
+    zip.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    zip.extend_from_slice(&[0, 0, 0, 0, 1, 0, 1, 0]);
+    zip.extend_from_slice(&central_directory_len.to_le_bytes());
+    zip.extend_from_slice(&central_directory_offset.to_le_bytes());
+    zip.extend_from_slice(&[0; 2]);
+    (zip, 0x1000)
+}
+
+#[test]
+fn test_library_in_apk() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures/android/aarch64/release-libmozglue.so");
+    let library = std::fs::read(&path).unwrap();
+    let (apk, file_offset) = stored_zip(ENTRY_NAME, &library);
+    assert_eq!(apk_entry_name(&apk, file_offset), Some(ENTRY_NAME));
+
+    let avma_range = BASE_AVMA..BASE_AVMA + 0x13e000;
+    let mapped = MappedModule {
+        descriptor: ModuleDescriptor {
+            name: "/data/app/com.example/base.apk".to_string(),
+            avma_range: avma_range.clone(),
+            code_id: None,
+        },
+        file_offset,
+    };
+    let module = mapped.module_from_file_data::<Vec<u8>>(&apk).unwrap();
+    assert_eq!(
+        module.name(),
+        "/data/app/com.example/base.apk!/lib/arm64-v8a/libmozglue.so"
+    );
+    assert_eq!(module.base_avma(), BASE_AVMA);
+    assert!(module.code_id().is_some());
+    assert_eq!(
+        module.unwind_data_kind(),
+        UnwindDataKind::EhFrameHdrAndEhFrame
+    );
+
+    // The module unwinds like the same library loaded from its own file.
+    let mut apk_unwinder = UnwinderAarch64::new();
+    apk_unwinder.add_module(module);
+    let mut file_unwinder = UnwinderAarch64::new();
+    file_unwinder.add_module(common::object_module(&path, Some(avma_range), BASE_AVMA));
+    let stack: Vec<u64> = (0..0x40).map(|i| 0x1000 + 0x10 * i).collect();
+    let mut read_stack = |addr: u64| stack.get((addr / 8) as usize).copied().ok_or(());
+    let mut unwound_frame_count = 0;
+    for relative_address in (0x455f0..0x10fd00).step_by(0x1004) {
+        let address = FrameAddress::from_return_address(BASE_AVMA + relative_address).unwrap();
+        let results = [&apk_unwinder, &file_unwinder].map(|unwinder| {
+            let mut regs = UnwindRegsAarch64::new(0x1234, 0x80, 0x100);
+            let mut cache = CacheAarch64::<_>::new();
+            let result = unwinder.unwind_frame(address, &mut regs, &mut cache, &mut read_stack);
+            (result, regs.sp(), regs.fp())
+        });
+        assert_eq!(results[0], results[1], "{address:?}");
+        if let (Ok(Some(_)), ..) = results[0] {
+            unwound_frame_count += 1;
+        }
+    }
+    assert!(unwound_frame_count > 0);
+}
@@ -0,0 +1,394 @@
+//! Builds `.eh_frame` and `.debug_frame` sections from a high-level description, so that
+//! the DWARF unwinder can be tested against CFI which no fixture binary contains.
+
+/// The section format to emit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfiFormat {
+    EhFrame,
+    DebugFrame,
+}
+
+/// How the FDE addresses are encoded in `.eh_frame`. `.debug_frame` always uses absolute
+/// 8-byte addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerEncoding {
+    /// `DW_EH_PE_absptr`
+    Absolute,
+    /// `DW_EH_PE_pcrel | DW_EH_PE_sdata4`
+    PcRelSdata4,
+    /// `DW_EH_PE_textrel | DW_EH_PE_udata4`
+    TextRelUdata4,
+    /// `DW_EH_PE_datarel | DW_EH_PE_sdata4`
+    DataRelSdata4,
+}
+
+impl PointerEncoding {
+    fn byte(self) -> u8 {
+        match self {
+            PointerEncoding::Absolute => 0x00,
+            PointerEncoding::PcRelSdata4 => 0x1b,
+            PointerEncoding::TextRelUdata4 => 0x23,
+            PointerEncoding::DataRelSdata4 => 0x3b,
+        }
+    }
+}
+
+/// A call frame instruction. Offsets are in the units which the instruction encodes,
+/// i.e. `factored_*` offsets are multiplied by the CIE's alignment factors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CfaOp {
+    AdvanceLoc(u32),
+    DefCfa { register: u16, offset: u64 },
+    DefCfaSf { register: u16, factored_offset: i64 },
+    DefCfaRegister(u16),
+    DefCfaOffset(u64),
+    DefCfaOffsetSf(i64),
+    DefCfaExpression(Vec<u8>),
+    Undefined(u16),
+    SameValue(u16),
+    Offset { register: u16, factored_offset: u64 },
+    OffsetExtendedSf { register: u16, factored_offset: i64 },
+    ValOffset { register: u16, factored_offset: u64 },
+    Register { register: u16, from: u16 },
+    Expression { register: u16, expr: Vec<u8> },
+    ValExpression { register: u16, expr: Vec<u8> },
+    Restore(u16),
+    RememberState,
+    RestoreState,
+    GnuArgsSize(u64),
+    Nop,
+}
+
+/// The fields of a CIE.
+#[derive(Debug, Clone)]
+pub struct Cie {
+    pub code_alignment: u64,
+    pub data_alignment: i64,
+    pub return_address_register: u16,
+    pub initial_instructions: Vec<CfaOp>,
+}
+
+impl Cie {
+    /// The usual x86_64 CIE: the CFA is rsp + 8 and the return address is at CFA - 8.
+    pub fn x86_64() -> Self {
+        Cie {
+            code_alignment: 1,
+            data_alignment: -8,
+            return_address_register: 16,
+            initial_instructions: vec![
+                CfaOp::DefCfa {
+                    register: 7,
+                    offset: 8,
+                },
+                CfaOp::Offset {
+                    register: 16,
+                    factored_offset: 1,
+                },
+            ],
+        }
+    }
+}
+
+/// An FDE which covers `start..start + len` and uses the CIE of the builder.
+#[derive(Debug, Clone)]
+pub struct Fde {
+    pub start: u64,
+    pub len: u64,
+    pub instructions: Vec<CfaOp>,
+}
+
+/// The addresses which relative pointers are resolved against.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SectionBases {
+    /// The address of the emitted section itself.
+    pub section: u64,
+    pub text: u64,
+    pub data: u64,
+}
+
+/// Builds an unwind section with a single CIE and any number of FDEs.
+pub struct CfiBuilder {
+    format: CfiFormat,
+    pointer_encoding: PointerEncoding,
+    cie: Cie,
+    cie_version: u8,
+    augmentation_suffix: Vec<u8>,
+    fdes: Vec<Fde>,
+}
+
+impl CfiBuilder {
+    pub fn new(format: CfiFormat, cie: Cie) -> Self {
+        CfiBuilder {
+            format,
+            pointer_encoding: PointerEncoding::Absolute,
+            cie,
+            cie_version: 1,
+            augmentation_suffix: Vec::new(),
+            fdes: Vec::new(),
+        }
+    }
+
+    /// The version of the CIE: 1, 3 or 4. Version 4 is only valid in debug_frame.
+    pub fn cie_version(mut self, version: u8) -> Self {
+        self.cie_version = version;
+        self
+    }
+
+    /// Augmentation characters which are appended to the `zR` augmentation of an
+    /// eh_frame CIE. They have no augmentation data.
+    pub fn augmentation_suffix(mut self, suffix: &[u8]) -> Self {
+        self.augmentation_suffix = suffix.to_vec();
+        self
+    }
+
+    pub fn pointer_encoding(mut self, encoding: PointerEncoding) -> Self {
+        self.pointer_encoding = encoding;
+        self
+    }
+
+    pub fn fde(mut self, fde: Fde) -> Self {
+        self.fdes.push(fde);
+        self
+    }
+
+    /// Emit the section bytes.
+    pub fn build(&self, bases: SectionBases) -> Vec<u8> {
+        let mut out = Vec::new();
+        let cie_offset = out.len();
+        let mut cie = Vec::new();
+        match self.format {
+            CfiFormat::EhFrame => {
+                cie.extend_from_slice(&0u32.to_le_bytes());
+                cie.push(self.cie_version);
+                cie.extend_from_slice(b"zR");
+                cie.extend_from_slice(&self.augmentation_suffix);
+                cie.push(0);
+            }
+            CfiFormat::DebugFrame => {
+                cie.extend_from_slice(&0xffff_ffffu32.to_le_bytes());
+                cie.push(self.cie_version);
+                cie.push(0);
+                if self.cie_version == 4 {
+                    // The address size and the segment selector size.
+                    cie.extend_from_slice(&[8, 0]);
+                }
+            }
+        }
+        uleb(&mut cie, self.cie.code_alignment);
+        sleb(&mut cie, self.cie.data_alignment);
+        if self.cie_version == 1 {
+            cie.push(u8::try_from(self.cie.return_address_register).unwrap());
+        } else {
+            uleb(&mut cie, u64::from(self.cie.return_address_register));
+        }
+        if self.format == CfiFormat::EhFrame {
+            uleb(&mut cie, 1);
+            cie.push(self.pointer_encoding.byte());
+        }
+        for op in &self.cie.initial_instructions {
+            encode_op(&mut cie, op);
+        }
+        push_entry(&mut out, cie);
+
+        for fde in &self.fdes {
+            let entry_offset = out.len();
+            let mut entry = Vec::new();
+            match self.format {
+                CfiFormat::EhFrame => {
+                    // The CIE pointer is relative to the CIE pointer field itself.
+                    let cie_pointer = entry_offset + 4 - cie_offset;
+                    entry.extend_from_slice(&(cie_pointer as u32).to_le_bytes());
+                    // The initial location follows the length and CIE pointer fields.
+                    let field_address = bases.section + entry_offset as u64 + 8;
+                    self.encode_pointer(&mut entry, fde.start, field_address, bases);
+                    match self.pointer_encoding {
+                        PointerEncoding::Absolute => {
+                            entry.extend_from_slice(&fde.len.to_le_bytes())
+                        }
+                        _ => entry.extend_from_slice(&(fde.len as u32).to_le_bytes()),
+                    }
+                    uleb(&mut entry, 0);
+                }
+                CfiFormat::DebugFrame => {
+                    entry.extend_from_slice(&(cie_offset as u32).to_le_bytes());
+                    entry.extend_from_slice(&fde.start.to_le_bytes());
+                    entry.extend_from_slice(&fde.len.to_le_bytes());
+                }
+            }
+            for op in &fde.instructions {
+                encode_op(&mut entry, op);
+            }
+            push_entry(&mut out, entry);
+        }
+        if self.format == CfiFormat::EhFrame {
+            // The zero terminator.
+            out.extend_from_slice(&0u32.to_le_bytes());
+        }
+        out
+    }
+
+    fn encode_pointer(&self, out: &mut Vec<u8>, address: u64, field: u64, bases: SectionBases) {
+        let relative = match self.pointer_encoding {
+            PointerEncoding::Absolute => {
+                out.extend_from_slice(&address.to_le_bytes());
+                return;
+            }
+            PointerEncoding::PcRelSdata4 => address.wrapping_sub(field),
+            PointerEncoding::TextRelUdata4 => address - bases.text,
+            PointerEncoding::DataRelSdata4 => address.wrapping_sub(bases.data),
+        };
+        out.extend_from_slice(&(relative as u32).to_le_bytes());
+    }
+}
+
+/// Append a CIE or FDE with its length field, padded to 8 bytes with `DW_CFA_nop`.
+fn push_entry(out: &mut Vec<u8>, mut entry: Vec<u8>) {
+    while !(entry.len() + 4).is_multiple_of(8) {
+        entry.push(0);
+    }
+    out.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+    out.extend_from_slice(&entry);
+}
+
+fn encode_op(out: &mut Vec<u8>, op: &CfaOp) {
+    let reg = |out: &mut Vec<u8>, register: u16| uleb(out, u64::from(register));
+    match op {
+        CfaOp::AdvanceLoc(delta) => match *delta {
+            0..=0x3f => out.push(0x40 | *delta as u8),
+            0x40..=0xff => out.extend_from_slice(&[0x02, *delta as u8]),
+            0x100..=0xffff => {
+                out.push(0x03);
+                out.extend_from_slice(&(*delta as u16).to_le_bytes());
+            }
+            _ => {
+                out.push(0x04);
+                out.extend_from_slice(&delta.to_le_bytes());
+            }
+        },
+        CfaOp::DefCfa { register, offset } => {
+            out.push(0x0c);
+            reg(out, *register);
+            uleb(out, *offset);
+        }
+        CfaOp::DefCfaSf {
+            register,
+            factored_offset,
+        } => {
+            out.push(0x12);
+            reg(out, *register);
+            sleb(out, *factored_offset);
+        }
+        CfaOp::DefCfaRegister(register) => {
+            out.push(0x0d);
+            reg(out, *register);
+        }
+        CfaOp::DefCfaOffset(offset) => {
+            out.push(0x0e);
+            uleb(out, *offset);
+        }
+        CfaOp::DefCfaOffsetSf(factored_offset) => {
+            out.push(0x13);
+            sleb(out, *factored_offset);
+        }
+        CfaOp::DefCfaExpression(expr) => {
+            out.push(0x0f);
+            block(out, expr);
+        }
+        CfaOp::Undefined(register) => {
+            out.push(0x07);
+            reg(out, *register);
+        }
+        CfaOp::SameValue(register) => {
+            out.push(0x08);
+            reg(out, *register);
+        }
+        CfaOp::Offset {
+            register,
+            factored_offset,
+        } => {
+            if *register < 0x40 {
+                out.push(0x80 | *register as u8);
+            } else {
+                out.push(0x05);
+                reg(out, *register);
+            }
+            uleb(out, *factored_offset);
+        }
+        CfaOp::OffsetExtendedSf {
+            register,
+            factored_offset,
+        } => {
+            out.push(0x11);
+            reg(out, *register);
+            sleb(out, *factored_offset);
+        }
+        CfaOp::ValOffset {
+            register,
+            factored_offset,
+        } => {
+            out.push(0x14);
+            reg(out, *register);
+            uleb(out, *factored_offset);
+        }
+        CfaOp::Register { register, from } => {
+            out.push(0x09);
+            reg(out, *register);
+            reg(out, *from);
+        }
+        CfaOp::Expression { register, expr } => {
+            out.push(0x10);
+            reg(out, *register);
+            block(out, expr);
+        }
+        CfaOp::ValExpression { register, expr } => {
+            out.push(0x16);
+            reg(out, *register);
+            block(out, expr);
+        }
+        CfaOp::Restore(register) => {
+            if *register < 0x40 {
+                out.push(0xc0 | *register as u8);
+            } else {
+                out.push(0x06);
+                reg(out, *register);
+            }
+        }
+        CfaOp::RememberState => out.push(0x0a),
+        CfaOp::RestoreState => out.push(0x0b),
+        CfaOp::GnuArgsSize(size) => {
+            out.push(0x2e);
+            uleb(out, *size);
+        }
+        CfaOp::Nop => out.push(0x00),
+    }
+}
+
+fn block(out: &mut Vec<u8>, bytes: &[u8]) {
+    uleb(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn uleb(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        let done = (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
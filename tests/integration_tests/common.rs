@@ -8,6 +8,16 @@ pub fn add_object<U>(unwinder: &mut U, objpath: &Path, base_avma: u64)
 where
     U: Unwinder<Module = Module<Vec<u8>>>,
 {
+    unwinder.add_module(object_module(objpath, None, base_avma));
+}
+
+/// Create a module for the object file at `objpath`. If `avma_range` is `None`, the
+/// module covers the size of the file, starting at `base_avma`.
+pub fn object_module(
+    objpath: &Path,
+    avma_range: Option<Range<u64>>,
+    base_avma: u64,
+) -> framehop::Module<Vec<u8>> {
     let mut buf = Vec::new();
     let mut file = std::fs::File::open(objpath).unwrap();
     file.read_to_end(&mut buf).unwrap();
@@ -54,13 +64,13 @@ where
         }
     }
 
-    let module = framehop::Module::new(
+    let avma_range = avma_range.unwrap_or(base_avma..(base_avma + buf.len() as u64));
+    framehop::Module::new(
         objpath.to_string_lossy().to_string(),
-        base_avma..(base_avma + buf.len() as u64),
+        avma_range,
         base_avma,
         Module(file),
-    );
-    unwinder.add_module(module);
+    )
 }
 
 fn get_uncompressed_section_data<'a>(
@@ -103,7 +113,8 @@ fn get_uncompressed_section_data<'a>(
 /// This function computes that base address. It is defined as follows:
 ///
 ///  - For Windows binaries, the base address is the "image base address".
-///  - For mach-O binaries, the base address is the vmaddr of the __TEXT segment.
+///  - For mach-O binaries, the base address is the vmaddr of the __TEXT segment, or
+///    of the segment which contains the mach header if there is no __TEXT segment.
 ///  - For ELF binaries, the base address is zero.
 ///
 /// Stand-alone mach-O dylibs usually have a base address of zero because their
@@ -126,6 +137,18 @@ pub fn relative_address_base<'data>(object_file: &impl object::Object<'data>) ->
         return text_segment.address();
     }
 
+    if object_file
+        .segments()
+        .any(|s| s.name() == Ok(Some("__TEXT_EXEC")))
+    {
+        // This mach-O image has no __TEXT segment, which can happen for images in
+        // kernel collections. Relative addresses are relative to the mach header,
+        // which is at the start of the segment with file offset zero.
+        if let Some(header_segment) = object_file.segments().find(|s| s.file_range().0 == 0) {
+            return header_segment.address();
+        }
+    }
+
     // For PE binaries, relative_address_base() returns the image base address.
     // Otherwise it returns zero. This gives regular ELF images a base address of zero,
     // which is what we want.
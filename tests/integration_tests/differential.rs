@@ -0,0 +1,175 @@
+//! Differential tests: unwind the same stack with framehop and with a reference unwinder,
+//! e.g. `_Unwind_Backtrace` from the system's libgcc / libunwind, and report where the
+//! two disagree.
+
+use std::fmt;
+
+use framehop::FrameAddress;
+
+/// The first position at which framehop's frames differ from the reference frames.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// The index into the framehop frames.
+    pub index: usize,
+    pub reference: Vec<u64>,
+    pub framehop: Vec<u64>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "stacks diverge at framehop frame {}:", self.index)?;
+        writeln!(f, "  {:>3}  {:>18}  {:>18}", "#", "framehop", "reference")?;
+        for i in 0..self.framehop.len().max(self.reference.len()) {
+            let cell = |frames: &[u64]| match frames.get(i) {
+                Some(address) => format!("{address:#18x}"),
+                None => format!("{:>18}", "-"),
+            };
+            let marker = if i == self.index { " <--" } else { "" };
+            writeln!(
+                f,
+                "  {i:>3}  {}  {}{marker}",
+                cell(&self.framehop),
+                cell(&self.reference)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the return addresses which framehop found with those of a reference unwinder.
+///
+/// The reference stack usually starts with frames of the unwinder itself, so it's
+/// aligned at the first return address in `frames`. From there, the frames are compared
+/// until one of the stacks ends. Returns the number of matching frames.
+pub fn compare_with_reference(
+    reference: &[u64],
+    frames: &[FrameAddress],
+) -> Result<usize, Divergence> {
+    let framehop: Vec<u64> = frames.iter().map(|frame| frame.address()).collect();
+    let divergence = |index| Divergence {
+        index,
+        reference: reference.to_vec(),
+        framehop: framehop.clone(),
+    };
+    let first = frames
+        .iter()
+        .position(|frame| frame.is_return_address())
+        .ok_or_else(|| divergence(0))?;
+    let start = reference
+        .iter()
+        .position(|&address| address == framehop[first])
+        .ok_or_else(|| divergence(first))?;
+    let mut matched = 0;
+    for (i, (&expected, &actual)) in reference[start..]
+        .iter()
+        .zip(&framehop[first..])
+        .enumerate()
+    {
+        if expected != actual {
+            return Err(divergence(first + i));
+        }
+        matched += 1;
+    }
+    Ok(matched)
+}
+
+#[test]
+fn test_compare_with_reference() {
+    let frames = [
+        FrameAddress::from_instruction_pointer(0x1000),
+        FrameAddress::from_return_address(0x2000).unwrap(),
+        FrameAddress::from_return_address(0x3000).unwrap(),
+    ];
+    assert_eq!(
+        compare_with_reference(&[0x9, 0x2000, 0x3000, 0x4000], &frames),
+        Ok(2)
+    );
+    let divergence = compare_with_reference(&[0x9, 0x2000, 0x3001], &frames).unwrap_err();
+    assert_eq!(divergence.index, 2);
+    assert!(divergence.to_string().contains("<--"));
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64", feature = "std"))]
+mod libgcc {
+    use std::ffi::{c_int, c_void};
+    use std::path::Path;
+
+    use framehop::x86_64::{CacheX86_64, UnwindRegsX86_64, UnwinderX86_64};
+    use framehop::{ModuleDescriptor, ProcessAddressSpace, Unwinder};
+    use object::{Object, ObjectSegment};
+
+    use super::compare_with_reference;
+    use crate::common::object_module;
+
+    #[repr(C)]
+    struct UnwindContext {
+        _private: [u8; 0],
+    }
+
+    type UnwindTraceFn = extern "C" fn(*mut UnwindContext, *mut c_void) -> c_int;
+
+    extern "C" {
+        fn _Unwind_Backtrace(trace: UnwindTraceFn, arg: *mut c_void) -> c_int;
+        fn _Unwind_GetIP(context: *mut UnwindContext) -> usize;
+    }
+
+    extern "C" fn push_frame(context: *mut UnwindContext, arg: *mut c_void) -> c_int {
+        let frames = unsafe { &mut *(arg as *mut Vec<u64>) };
+        frames.push(unsafe { _Unwind_GetIP(context) } as u64);
+        0
+    }
+
+    fn load_module(descriptor: &ModuleDescriptor) -> Option<framehop::Module<Vec<u8>>> {
+        let path = Path::new(&descriptor.name);
+        let data = std::fs::read(path).ok()?;
+        let file = object::File::parse(&data[..]).ok()?;
+        // The first mapping of the file is its lowest segment.
+        let lowest_svma = file.segments().map(|s| s.address()).min()? & !0xfff;
+        let base_avma = descriptor.avma_range.start - lowest_svma;
+        Some(object_module(
+            path,
+            Some(descriptor.avma_range.clone()),
+            base_avma,
+        ))
+    }
+
+    /// Unwind the current thread with framehop and with `_Unwind_Backtrace`.
+    #[inline(never)]
+    fn capture_both() -> (Vec<u64>, Vec<framehop::FrameAddress>) {
+        let mut address_space = ProcessAddressSpace::current(load_module).unwrap();
+        let mut unwinder = UnwinderX86_64::new();
+        unwinder.sync_address_space(&mut address_space);
+
+        let (ip, sp, bp): (u64, u64, u64);
+        unsafe {
+            std::arch::asm!(
+                "lea {ip}, [rip]",
+                "mov {sp}, rsp",
+                "mov {bp}, rbp",
+                ip = out(reg) ip,
+                sp = out(reg) sp,
+                bp = out(reg) bp,
+            );
+        }
+        let mut reference = Vec::new();
+        unsafe {
+            _Unwind_Backtrace(push_frame, &mut reference as *mut Vec<u64> as *mut c_void);
+        }
+
+        let mut cache = CacheX86_64::<_>::new();
+        let regs = UnwindRegsX86_64::new(ip, sp, bp);
+        let stack = unwinder
+            .iter_frames(ip, regs, &mut cache, &mut address_space)
+            .collect_stack(256);
+        (reference, stack.frames)
+    }
+
+    #[test]
+    fn test_differential_current_thread() {
+        let (reference, frames) = capture_both();
+        match compare_with_reference(&reference, &frames) {
+            Ok(matched) => assert!(matched >= 3, "only {matched} frames were compared"),
+            Err(divergence) => panic!("{divergence}"),
+        }
+    }
+}
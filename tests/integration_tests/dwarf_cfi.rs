@@ -0,0 +1,1093 @@
+use std::ops::Range;
+
+use framehop::x86_64::*;
+use framehop::{
+    AllocationPolicy, CheckpointError, CodeId, DwarfCfiIndex, DwarfCfiSectionAddresses,
+    DwarfUnwinderError, ExplicitModuleSectionInfo, FrameAddress, FrameUnwindInfo,
+    MayAllocateDuringUnwind, MemoryReadError, MemoryReader, MemoryRequest, Module,
+    MustNotAllocateDuringUnwind, SingleFrameUnwindError, UnwindStep, Unwinder, UnwinderCheckpoint,
+};
+
+use super::cfi_builder::{CfaOp, CfiBuilder, CfiFormat, Cie, Fde, PointerEncoding, SectionBases};
+
+const TEXT: u64 = 0x1000;
+const SECTION: u64 = 0x20_0000;
+const DATA: u64 = 0x30_0000;
+const STACK: u64 = 0x80_0000;
+const FUNCTION: u64 = 0x1100;
+
+const RBP: u16 = 6;
+const RSP: u16 = 7;
+const RA: u16 = 16;
+
+/// Every combination of section format and pointer encoding.
+const VARIANTS: [(CfiFormat, PointerEncoding); 5] = [
+    (CfiFormat::EhFrame, PointerEncoding::Absolute),
+    (CfiFormat::EhFrame, PointerEncoding::PcRelSdata4),
+    (CfiFormat::EhFrame, PointerEncoding::TextRelUdata4),
+    (CfiFormat::EhFrame, PointerEncoding::DataRelSdata4),
+    (CfiFormat::DebugFrame, PointerEncoding::Absolute),
+];
+
+fn unwinder_for<P: AllocationPolicy>(
+    format: CfiFormat,
+    encoding: PointerEncoding,
+    instructions: &[CfaOp],
+) -> UnwinderX86_64<Vec<u8>, P> {
+    let fde = Fde {
+        start: FUNCTION,
+        len: 0x2_0000,
+        instructions: instructions.to_vec(),
+    };
+    unwinder_with_fdes(format, encoding, &[fde])
+}
+
+/// An unwinder with a single module whose CFI has `fdes`, in this order.
+fn unwinder_with_fdes<P: AllocationPolicy>(
+    format: CfiFormat,
+    encoding: PointerEncoding,
+    fdes: &[Fde],
+) -> UnwinderX86_64<Vec<u8>, P> {
+    let mut builder = CfiBuilder::new(format, Cie::x86_64()).pointer_encoding(encoding);
+    for fde in fdes {
+        builder = builder.fde(fde.clone());
+    }
+    let section = builder.build(SectionBases {
+        section: SECTION,
+        text: TEXT,
+        data: DATA,
+    });
+    let mut section_info = ExplicitModuleSectionInfo {
+        base_svma: 0,
+        text_svma: Some(TEXT..SECTION),
+        got_svma: Some(DATA..DATA + 0x1000),
+        ..Default::default()
+    };
+    match format {
+        CfiFormat::EhFrame => {
+            section_info.eh_frame_svma = Some(SECTION..SECTION + section.len() as u64);
+            section_info.eh_frame = Some(section);
+        }
+        CfiFormat::DebugFrame => section_info.debug_frame = Some(section),
+    }
+    let mut unwinder = UnwinderX86_64::new();
+    unwinder.add_module(Module::new(
+        "cfi-test".to_string(),
+        0..0x40_0000,
+        0,
+        section_info,
+    ));
+    unwinder
+}
+
+/// Unwind the first frame at `FUNCTION + pc_offset` with every format and pointer
+/// encoding, and check that the CFI was used and produced `expected`, which is
+/// `(return address, sp, bp)`, or `None` for the end of the stack. `stack[i]` is at
+/// `STACK + 8 * i`, and the registers are relative to `STACK`.
+fn check(
+    instructions: &[CfaOp],
+    pc_offset: u64,
+    sp_offset: u64,
+    bp: u64,
+    stack: &[u64],
+    expected: Option<(u64, u64, u64)>,
+) {
+    for (format, encoding) in VARIANTS {
+        let unwinder = unwinder_for::<MayAllocateDuringUnwind>(format, encoding, instructions);
+        let mut cache = CacheX86_64::<_>::new();
+        let mut read_stack = |addr: u64| {
+            let index = addr.checked_sub(STACK).ok_or(())? / 8;
+            stack.get(index as usize).copied().ok_or(())
+        };
+        let ip = FUNCTION + pc_offset;
+        let mut regs = UnwindRegsX86_64::new(ip, STACK + sp_offset, bp);
+        let mut info = FrameUnwindInfo::default();
+        let result = unwinder.unwind_frame_with_info(
+            FrameAddress::from_instruction_pointer(ip),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            &mut info,
+        );
+        let context = format!("{format:?} with {encoding:?} at {ip:#x}");
+        assert_eq!(info.error_details, None, "{context}");
+        let actual = result
+            .unwrap_or_else(|e| panic!("{context}: {e}"))
+            .map(|ra| (ra, regs.sp(), regs.bp()));
+        assert_eq!(actual, expected, "{context}");
+        let expected_frame_size = expected.and_then(|(_, sp, _)| sp.checked_sub(STACK + sp_offset));
+        assert_eq!(info.frame_size, expected_frame_size, "{context}");
+    }
+}
+
+fn prologue() -> Vec<CfaOp> {
+    vec![
+        CfaOp::AdvanceLoc(1),
+        CfaOp::DefCfaOffset(16),
+        CfaOp::Offset {
+            register: RBP,
+            factored_offset: 2,
+        },
+        CfaOp::Nop,
+        CfaOp::AdvanceLoc(3),
+        CfaOp::DefCfaRegister(RBP),
+        CfaOp::GnuArgsSize(0),
+    ]
+}
+
+#[test]
+fn test_cfi_prologue() {
+    let stack = [0x9000, 0x1555];
+    check(
+        &prologue(),
+        0,
+        8,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x7)),
+    );
+    check(
+        &prologue(),
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x9000, STACK + 8, 0x7)),
+    );
+    check(
+        &prologue(),
+        1,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+    check(
+        &prologue(),
+        4,
+        0,
+        STACK,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+}
+
+#[test]
+fn test_cfi_frame_pointer_with_offsets() {
+    // CFA=rbp+32: rbp=[CFA-16], ra=[CFA-8], as in _ffi_call_unix64.
+    let instructions = [
+        CfaOp::AdvanceLoc(1),
+        CfaOp::DefCfa {
+            register: RBP,
+            offset: 32,
+        },
+        CfaOp::Offset {
+            register: RBP,
+            factored_offset: 2,
+        },
+    ];
+    let stack = [0x1, 0x2, 0x3, 0x4, 0x9000, 0x1555];
+    check(
+        &instructions,
+        1,
+        0,
+        STACK + 0x10,
+        &stack,
+        Some((0x1555, STACK + 0x30, 0x9000)),
+    );
+}
+
+#[test]
+fn test_cfi_signed_factored_offsets() {
+    let instructions = [
+        CfaOp::DefCfaSf {
+            register: RSP,
+            factored_offset: -2,
+        },
+        CfaOp::OffsetExtendedSf {
+            register: RBP,
+            factored_offset: 2,
+        },
+        CfaOp::AdvanceLoc(1),
+        CfaOp::DefCfaOffsetSf(-3),
+    ];
+    let stack = [0x9000, 0x1555, 0x2555];
+    check(
+        &instructions,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+    check(
+        &instructions,
+        1,
+        0,
+        0x7,
+        &stack,
+        Some((0x2555, STACK + 0x18, 0x1555)),
+    );
+}
+
+#[test]
+fn test_cfi_cfa_expression() {
+    // DW_OP_breg7 (rsp) 16
+    let instructions = [CfaOp::DefCfaExpression(vec![0x77, 0x10])];
+    check(
+        &instructions,
+        0,
+        0,
+        0x7,
+        &[0x0, 0x1555],
+        Some((0x1555, STACK + 0x10, 0x7)),
+    );
+}
+
+/// A copy of the stack which starts at sp. The addresses below it are still mapped in
+/// the process, but they no longer hold the values which were there when the sample was
+/// taken.
+struct StackCopy<'a> {
+    stack: &'a [u64],
+}
+
+impl MemoryReader for StackCopy<'_> {
+    fn read_u64(&mut self, address: u64) -> Result<u64, MemoryReadError> {
+        match address.checked_sub(STACK) {
+            Some(offset) => self
+                .stack
+                .get((offset / 8) as usize)
+                .copied()
+                .ok_or(MemoryReadError::OutsideSnapshot),
+            None => Ok(0xbad),
+        }
+    }
+
+    fn snapshot_range(&mut self) -> Option<Range<u64>> {
+        Some(STACK..STACK + self.stack.len() as u64 * 8)
+    }
+}
+
+#[test]
+fn test_red_zone_with_frame_unwind_stepper() {
+    // rbp was saved in the red zone, 16 bytes below rsp. The CFA is a rule in the first
+    // case, and an expression which is evaluated for every frame in the second one.
+    let saved_rbp = CfaOp::Offset {
+        register: RBP,
+        factored_offset: 3,
+    };
+    // DW_OP_breg7 (rsp) 8
+    let cfa_expression = CfaOp::DefCfaExpression(vec![0x77, 0x08]);
+    for instructions in [vec![saved_rbp.clone()], vec![cfa_expression, saved_rbp]] {
+        let unwinder = unwinder_for::<MayAllocateDuringUnwind>(
+            CfiFormat::EhFrame,
+            PointerEncoding::Absolute,
+            &instructions,
+        );
+        let mut stack_copy = StackCopy { stack: &[0x1555] };
+        let address = FrameAddress::from_instruction_pointer(FUNCTION + 4);
+        let mut cache = CacheX86_64::<_>::new();
+        let mut regs = UnwindRegsX86_64::new(FUNCTION + 4, STACK, 0x7);
+        let result = unwinder.unwind_frame(address, &mut regs, &mut cache, &mut stack_copy);
+        assert_eq!(result, Ok(Some(0x1555)));
+        assert_eq!((regs.sp(), regs.bp()), (STACK + 8, 0x7));
+
+        let mut cache = CacheX86_64::<_>::new();
+        let snapshot_range = stack_copy.snapshot_range().unwrap();
+        let mut stepper = unwinder
+            .frame_unwind_stepper(address, UnwindRegsX86_64::new(FUNCTION + 4, STACK, 0x7))
+            .with_snapshot_range(snapshot_range);
+        let stepper_result = loop {
+            match stepper.step(&mut cache) {
+                UnwindStep::NeedsMemory(MemoryRequest::ReadU64(address)) => {
+                    assert!(address >= STACK, "{address:#x} is outside of the copy");
+                    stepper.provide(stack_copy.read_u64(address));
+                }
+                UnwindStep::NeedsMemory(request) => panic!("unexpected {request:?}"),
+                UnwindStep::Done(result) => break result,
+            }
+        };
+        assert_eq!(stepper_result, result);
+        assert_eq!(stepper.regs(), &regs);
+    }
+}
+
+#[test]
+fn test_cfi_register_rules() {
+    let stack = [0x9000, 0x1555];
+    let with_bp_rule = |rule| [CfaOp::DefCfaOffset(16), rule];
+
+    // rbp = CFA - 32
+    let val_offset = with_bp_rule(CfaOp::ValOffset {
+        register: RBP,
+        factored_offset: 4,
+    });
+    check(
+        &val_offset,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, STACK - 0x10)),
+    );
+
+    // rbp = rsp
+    let register = with_bp_rule(CfaOp::Register {
+        register: RBP,
+        from: RSP,
+    });
+    check(
+        &register,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, STACK)),
+    );
+
+    // rbp = [rsp], with DW_OP_breg7 (rsp) 0
+    let expression = with_bp_rule(CfaOp::Expression {
+        register: RBP,
+        expr: vec![0x77, 0x00],
+    });
+    check(
+        &expression,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+
+    // rbp = rsp + 0x20, with DW_OP_breg7 (rsp) 0x20
+    let val_expression = with_bp_rule(CfaOp::ValExpression {
+        register: RBP,
+        expr: vec![0x77, 0x20],
+    });
+    check(
+        &val_expression,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, STACK + 0x20)),
+    );
+
+    let same_value = [
+        CfaOp::DefCfaOffset(16),
+        CfaOp::Offset {
+            register: RBP,
+            factored_offset: 2,
+        },
+        CfaOp::SameValue(RBP),
+    ];
+    check(
+        &same_value,
+        0,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x7)),
+    );
+}
+
+#[test]
+fn test_cfi_undefined_return_address() {
+    check(&[CfaOp::Undefined(RA)], 0, 0, 0x7, &[0x0], None);
+}
+
+#[test]
+fn test_cfi_remember_restore_state() {
+    let instructions = [
+        CfaOp::AdvanceLoc(1),
+        CfaOp::DefCfaOffset(16),
+        CfaOp::Offset {
+            register: RBP,
+            factored_offset: 2,
+        },
+        CfaOp::AdvanceLoc(1),
+        CfaOp::RememberState,
+        CfaOp::DefCfaOffset(8),
+        CfaOp::Restore(RBP),
+        CfaOp::AdvanceLoc(1),
+        CfaOp::RestoreState,
+    ];
+    let stack = [0x9000, 0x1555];
+    check(
+        &instructions,
+        1,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+    // The epilogue has popped rbp.
+    check(
+        &instructions,
+        2,
+        8,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x7)),
+    );
+    check(
+        &instructions,
+        3,
+        0,
+        0x7,
+        &stack,
+        Some((0x1555, STACK + 0x10, 0x9000)),
+    );
+}
+
+/// Unwind the first frame at `FUNCTION + pc_offset` with the allocation policy `P`.
+/// Returns `None` if the CFI couldn't be used.
+fn unwind_without_allocation<P: AllocationPolicy>(
+    instructions: &[CfaOp],
+    pc_offset: u64,
+    stack: &[u64],
+) -> Option<(u64, u64)> {
+    let unwinder = unwinder_for::<P>(CfiFormat::EhFrame, PointerEncoding::Absolute, instructions);
+    let mut cache = CacheX86_64::<P>::new_in();
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+    let ip = FUNCTION + pc_offset;
+    let mut regs = UnwindRegsX86_64::new(ip, STACK, 0x7);
+    let mut info = FrameUnwindInfo::default();
+    let result = unwinder.unwind_frame_with_info(
+        FrameAddress::from_instruction_pointer(ip),
+        &mut regs,
+        &mut cache,
+        &mut read_stack,
+        &mut info,
+    );
+    if info.error_details.is_some() {
+        return None;
+    }
+    Some((result.ok()??, regs.sp()))
+}
+
+#[test]
+fn test_cfi_nested_remember_state_without_allocation() {
+    let mut instructions = Vec::new();
+    for depth in 1..=4 {
+        instructions.push(CfaOp::RememberState);
+        instructions.push(CfaOp::AdvanceLoc(1));
+        instructions.push(CfaOp::DefCfaOffset(8 + 8 * depth));
+    }
+    for _ in 0..4 {
+        instructions.push(CfaOp::AdvanceLoc(1));
+        instructions.push(CfaOp::RestoreState);
+    }
+    let stack = [0x1, 0x2, 0x3, 0x4, 0x5];
+
+    // After all restores, the CFA is rsp + 8 again. The row stack grows as needed if
+    // the unwinder may allocate.
+    assert_eq!(
+        unwind_without_allocation::<MayAllocateDuringUnwind>(&instructions, 8, &stack),
+        Some((0x1, STACK + 8))
+    );
+    // The default row stack is too small for four remembered rows.
+    assert_eq!(
+        unwind_without_allocation::<MustNotAllocateDuringUnwind>(&instructions, 8, &stack),
+        None
+    );
+    assert_eq!(
+        unwind_without_allocation::<MustNotAllocateDuringUnwind<192, 8>>(&instructions, 8, &stack),
+        Some((0x1, STACK + 8))
+    );
+    assert_eq!(
+        unwind_without_allocation::<MustNotAllocateDuringUnwind<192, 8>>(&instructions, 4, &stack),
+        Some((0x5, STACK + 0x28))
+    );
+}
+
+#[test]
+fn test_cfi_advance_loc_sizes() {
+    let instructions = [
+        CfaOp::AdvanceLoc(0x40),
+        CfaOp::DefCfaOffset(16),
+        CfaOp::AdvanceLoc(0x100),
+        CfaOp::DefCfaOffset(24),
+        CfaOp::AdvanceLoc(0x1_0000),
+        CfaOp::DefCfaOffset(32),
+    ];
+    let stack = [0x1, 0x2, 0x3, 0x4];
+    check(
+        &instructions,
+        0x3f,
+        0,
+        0x7,
+        &stack,
+        Some((0x1, STACK + 8, 0x7)),
+    );
+    check(
+        &instructions,
+        0x40,
+        0,
+        0x7,
+        &stack,
+        Some((0x2, STACK + 0x10, 0x7)),
+    );
+    check(
+        &instructions,
+        0x140,
+        0,
+        0x7,
+        &stack,
+        Some((0x3, STACK + 0x18, 0x7)),
+    );
+    check(
+        &instructions,
+        0x1_0140,
+        0,
+        0x7,
+        &stack,
+        Some((0x4, STACK + 0x20, 0x7)),
+    );
+}
+
+#[test]
+fn test_unwind_frame_with_eh_frame() {
+    let eh_frame = CfiBuilder::new(CfiFormat::EhFrame, Cie::x86_64())
+        .pointer_encoding(PointerEncoding::PcRelSdata4)
+        .fde(Fde {
+            start: FUNCTION,
+            len: 0x10,
+            instructions: prologue(),
+        })
+        .build(SectionBases {
+            section: SECTION,
+            text: TEXT,
+            data: DATA,
+        });
+    let section_addresses = DwarfCfiSectionAddresses {
+        eh_frame: SECTION,
+        text: TEXT,
+        ..Default::default()
+    };
+    let stack = [0x9000, 0x1555];
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+
+    let mut regs = UnwindRegsX86_64::new(FUNCTION + 1, STACK, 0x7);
+    let result = unwind_frame_with_eh_frame(
+        &eh_frame,
+        &section_addresses,
+        FrameAddress::from_instruction_pointer(FUNCTION + 1),
+        &mut regs,
+        &mut read_stack,
+    );
+    assert_eq!(result, Ok(Some(0x1555)));
+    assert_eq!((regs.sp(), regs.bp()), (STACK + 0x10, 0x9000));
+
+    // The lookup address of a return address is in the preceding call instruction,
+    // which is the last instruction of the FDE.
+    let mut regs = UnwindRegsX86_64::new(FUNCTION + 0x10, STACK, STACK);
+    let result = unwind_frame_with_eh_frame(
+        &eh_frame,
+        &section_addresses,
+        FrameAddress::from_return_address(FUNCTION + 0x10).unwrap(),
+        &mut regs,
+        &mut read_stack,
+    );
+    assert_eq!(result, Ok(Some(0x1555)));
+
+    let mut regs = UnwindRegsX86_64::new(FUNCTION + 0x10, STACK, 0x7);
+    let result = unwind_frame_with_eh_frame(
+        &eh_frame,
+        &section_addresses,
+        FrameAddress::from_instruction_pointer(FUNCTION + 0x10),
+        &mut regs,
+        &mut read_stack,
+    );
+    assert!(matches!(
+        result,
+        Err(SingleFrameUnwindError::Dwarf(
+            DwarfUnwinderError::UnwindInfoForAddressFailed(_)
+        ))
+    ));
+}
+
+#[test]
+fn test_unsorted_and_overlapping_fdes() {
+    let fde = |start, len, instructions| Fde {
+        start,
+        len,
+        instructions,
+    };
+    // A frameless FDE nested in a function with a frame pointer, followed by an FDE
+    // which is out of order.
+    let fdes = [
+        fde(FUNCTION + 0x40, 0x10, vec![]),
+        fde(FUNCTION, 0x100, prologue()),
+        fde(FUNCTION - 0x80, 0x80, vec![]),
+    ];
+    let stack = [0x9000, 0x1555];
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+    for (format, encoding) in VARIANTS {
+        let unwinder = unwinder_with_fdes::<MayAllocateDuringUnwind>(format, encoding, &fdes);
+        let mut cache = CacheX86_64::<_>::new();
+        for (ip, expected) in [
+            (FUNCTION - 0x40, (0x9000, STACK + 8, STACK)),
+            (FUNCTION + 0x44, (0x9000, STACK + 8, STACK)),
+            (FUNCTION + 0x80, (0x1555, STACK + 0x10, 0x9000)),
+        ] {
+            let mut regs = UnwindRegsX86_64::new(ip, STACK, STACK);
+            let mut info = FrameUnwindInfo::default();
+            let result = unwinder.unwind_frame_with_info(
+                FrameAddress::from_instruction_pointer(ip),
+                &mut regs,
+                &mut cache,
+                &mut read_stack,
+                &mut info,
+            );
+            let context = format!("{format:?} with {encoding:?} at {ip:#x}");
+            assert_eq!(info.error_details, None, "{context}");
+            let actual = (result.unwrap(), regs.sp(), regs.bp());
+            assert_eq!(
+                actual,
+                (Some(expected.0), expected.1, expected.2),
+                "{context}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_instruction_analysis_in_short_cfi_gap() {
+    let fde = |start, len| Fde {
+        start,
+        len,
+        instructions: vec![],
+    };
+    // The function at FUNCTION has no FDE and is followed by another function which has
+    // one. It tail-calls the next function: pop rbx; jmp FUNCTION + 0x10
+    let fdes = [fde(FUNCTION - 0x80, 0x80), fde(FUNCTION + 0x10, 0x10)];
+    let mut text = vec![0xcc; 0x1000];
+    let function_offset = (FUNCTION - TEXT) as usize;
+    text[function_offset..][..6].copy_from_slice(&[0x5b, 0xe9, 0x0a, 0x00, 0x00, 0x00]);
+    let stack = [0x9000, 0x1555];
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+    for (format, with_eh_frame_hdr) in [
+        (CfiFormat::EhFrame, true),
+        (CfiFormat::EhFrame, false),
+        (CfiFormat::DebugFrame, false),
+    ] {
+        let mut builder = CfiBuilder::new(format, Cie::x86_64());
+        for fde in &fdes {
+            builder = builder.fde(fde.clone());
+        }
+        let section = builder.build(SectionBases {
+            section: SECTION,
+            text: TEXT,
+            data: DATA,
+        });
+        let mut section_info = ExplicitModuleSectionInfo {
+            base_svma: 0,
+            text_svma: Some(TEXT..TEXT + text.len() as u64),
+            text: Some(text.clone()),
+            instruction_analysis_for_dwarf_cfi_gaps: true,
+            ..Default::default()
+        };
+        match format {
+            CfiFormat::EhFrame => {
+                if with_eh_frame_hdr {
+                    let section_addresses = DwarfCfiSectionAddresses {
+                        eh_frame: SECTION,
+                        text: TEXT,
+                        ..Default::default()
+                    };
+                    let index =
+                        DwarfCfiIndex::try_from_eh_frame_data(&section, &section_addresses, 0)
+                            .unwrap();
+                    // Version 1, with absolute udata4 pointers.
+                    let mut eh_frame_hdr = vec![1, 0x03, 0x03, 0x03];
+                    eh_frame_hdr.extend_from_slice(&(SECTION as u32).to_le_bytes());
+                    eh_frame_hdr.extend_from_slice(&(index.len() as u32).to_le_bytes());
+                    for (start, fde_offset) in index.iter() {
+                        eh_frame_hdr.extend_from_slice(&start.to_le_bytes());
+                        let fde_address = SECTION as u32 + fde_offset;
+                        eh_frame_hdr.extend_from_slice(&fde_address.to_le_bytes());
+                    }
+                    section_info.eh_frame_hdr_svma = Some(DATA..DATA + eh_frame_hdr.len() as u64);
+                    section_info.eh_frame_hdr = Some(eh_frame_hdr);
+                }
+                section_info.eh_frame_svma = Some(SECTION..SECTION + section.len() as u64);
+                section_info.eh_frame = Some(section);
+            }
+            CfiFormat::DebugFrame => section_info.debug_frame = Some(section),
+        }
+        let mut unwinder = UnwinderX86_64::new();
+        unwinder.add_module(Module::new(
+            "cfi-gap-test".to_string(),
+            0..0x40_0000,
+            0,
+            section_info,
+        ));
+        let mut cache = CacheX86_64::<_>::new();
+        let mut regs = UnwindRegsX86_64::new(FUNCTION, STACK, STACK);
+        let result = unwinder.unwind_frame(
+            FrameAddress::from_instruction_pointer(FUNCTION),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+        );
+        // The jmp leaves the function, so it's a tail call after popping rbx.
+        let context = format!("{format:?} with eh_frame_hdr: {with_eh_frame_hdr}");
+        assert_eq!(result, Ok(Some(0x1555)), "{context}");
+        assert_eq!(regs.sp(), STACK + 0x10, "{context}");
+        assert_eq!(
+            cache.unwind_stats().instruction_analysis_count,
+            1,
+            "{context}"
+        );
+    }
+}
+
+#[test]
+fn test_cie_versions_and_augmentations() {
+    // A frameless function with the return address at sp + 16, which the frame
+    // pointer fallback wouldn't find.
+    let fde = Fde {
+        start: FUNCTION,
+        len: 0x100,
+        instructions: vec![CfaOp::DefCfaOffset(24)],
+    };
+    let stack = [0x0, 0x0, 0x1555];
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+    for (format, version, augmentation_suffix) in [
+        (CfiFormat::EhFrame, 3, &b""[..]),
+        (CfiFormat::EhFrame, 1, b"B"),
+        (CfiFormat::EhFrame, 1, b"BG"),
+        (CfiFormat::EhFrame, 3, b"X"),
+        (CfiFormat::DebugFrame, 3, b""),
+        (CfiFormat::DebugFrame, 4, b""),
+    ] {
+        let section = CfiBuilder::new(format, Cie::x86_64())
+            .pointer_encoding(PointerEncoding::PcRelSdata4)
+            .cie_version(version)
+            .augmentation_suffix(augmentation_suffix)
+            .fde(fde.clone())
+            .build(SectionBases {
+                section: SECTION,
+                text: TEXT,
+                data: DATA,
+            });
+        let mut section_info = ExplicitModuleSectionInfo {
+            base_svma: 0,
+            text_svma: Some(TEXT..SECTION),
+            ..Default::default()
+        };
+        match format {
+            CfiFormat::EhFrame => {
+                section_info.eh_frame_svma = Some(SECTION..SECTION + section.len() as u64);
+                section_info.eh_frame = Some(section);
+            }
+            CfiFormat::DebugFrame => section_info.debug_frame = Some(section),
+        }
+        let mut unwinder = UnwinderX86_64::new();
+        unwinder.add_module(Module::new(
+            "cie-test".to_string(),
+            0..0x40_0000,
+            0,
+            section_info,
+        ));
+        let mut cache = CacheX86_64::<_>::new();
+        let ip = FUNCTION + 0x10;
+        let mut regs = UnwindRegsX86_64::new(ip, STACK, 0);
+        let mut info = FrameUnwindInfo::default();
+        let result = unwinder.unwind_frame_with_info(
+            FrameAddress::from_instruction_pointer(ip),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            &mut info,
+        );
+        let context = format!(
+            "{format:?} version {version} with augmentation suffix {:?}",
+            String::from_utf8_lossy(augmentation_suffix)
+        );
+        assert_eq!(info.error_details, None, "{context}");
+        assert_eq!(info.fallback_reason, None, "{context}");
+        assert_eq!(result, Ok(Some(0x1555)), "{context}");
+        assert_eq!(regs.sp(), STACK + 24, "{context}");
+    }
+}
+
+#[test]
+fn test_unwind_coverage() {
+    for (format, encoding) in [
+        (CfiFormat::EhFrame, PointerEncoding::PcRelSdata4),
+        (CfiFormat::DebugFrame, PointerEncoding::Absolute),
+    ] {
+        let fde = |start, len| Fde {
+            start,
+            len,
+            instructions: prologue(),
+        };
+        let section = CfiBuilder::new(format, Cie::x86_64())
+            .pointer_encoding(encoding)
+            .fde(fde(0x1300, 0x200))
+            .fde(fde(FUNCTION, 0x100))
+            .build(SectionBases {
+                section: SECTION,
+                text: TEXT,
+                data: DATA,
+            });
+        let mut section_info = ExplicitModuleSectionInfo {
+            base_svma: 0,
+            text_svma: Some(TEXT..0x2000),
+            ..Default::default()
+        };
+        match format {
+            CfiFormat::EhFrame => {
+                section_info.eh_frame_svma = Some(SECTION..SECTION + section.len() as u64);
+                section_info.eh_frame = Some(section);
+            }
+            CfiFormat::DebugFrame => section_info.debug_frame = Some(section),
+        }
+        let module = Module::new("coverage-test".to_string(), TEXT..0x2000, 0, section_info);
+        let coverage = module.unwind_coverage(2).unwrap();
+        assert_eq!(coverage.covered_bytes, 0x300, "{format:?}");
+        assert_eq!(coverage.covered_fraction(), 0.1875, "{format:?}");
+        assert_eq!(
+            coverage.largest_gaps,
+            [0x1500..0x2000, TEXT..FUNCTION],
+            "{format:?}"
+        );
+    }
+}
+
+#[test]
+fn test_unwind_full_frame_with_eh_frame() {
+    const RBX: u16 = 3;
+    const R12: u16 = 12;
+    const R13: u16 = 13;
+    const R14: u16 = 14;
+    let eh_frame = CfiBuilder::new(CfiFormat::EhFrame, Cie::x86_64())
+        .pointer_encoding(PointerEncoding::PcRelSdata4)
+        .fde(Fde {
+            start: FUNCTION,
+            len: 0x10,
+            instructions: vec![
+                CfaOp::AdvanceLoc(1),
+                CfaOp::DefCfaOffset(32),
+                CfaOp::Offset {
+                    register: RBP,
+                    factored_offset: 2,
+                },
+                CfaOp::Offset {
+                    register: RBX,
+                    factored_offset: 3,
+                },
+                CfaOp::Register {
+                    register: R12,
+                    from: R13,
+                },
+                // Outside of the stack, so r14 can't be recovered.
+                CfaOp::Offset {
+                    register: R14,
+                    factored_offset: 10,
+                },
+            ],
+        })
+        .build(SectionBases {
+            section: SECTION,
+            text: TEXT,
+            data: DATA,
+        });
+    let section_addresses = DwarfCfiSectionAddresses {
+        eh_frame: SECTION,
+        text: TEXT,
+        ..Default::default()
+    };
+    let stack = [0, 0xb0b0, 0x9000, 0x1555];
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = 0x100 + i as u64;
+    }
+    values[usize::from(RSP)] = STACK;
+    let mut regs = FullUnwindRegsX86_64::new(FUNCTION + 1, values);
+    let result = unwind_full_frame_with_eh_frame(
+        &eh_frame,
+        &section_addresses,
+        FrameAddress::from_instruction_pointer(FUNCTION + 1),
+        &mut regs,
+        &mut read_stack,
+    );
+    assert_eq!(result, Ok(Some(0x1555)));
+    assert_eq!(regs.ip(), 0x1555);
+    assert_eq!(regs.sp(), STACK + 32);
+    assert_eq!(regs.get(Reg::RBP), Some(0x9000));
+    assert_eq!(regs.get(Reg::RBX), Some(0xb0b0));
+    assert_eq!(regs.get(Reg::R12), Some(0x10d));
+    assert_eq!(regs.get(Reg::R13), Some(0x10d));
+    assert_eq!(regs.get(Reg::R14), None);
+    assert_eq!(regs.get(Reg::RAX), Some(0x100));
+
+    let unwind_regs = regs.to_unwind_regs();
+    assert_eq!(
+        (unwind_regs.ip(), unwind_regs.sp(), unwind_regs.bp()),
+        (0x1555, STACK + 32, 0x9000)
+    );
+}
+
+#[cfg(feature = "return-address-predictor")]
+#[test]
+fn test_return_address_predictor() {
+    // DW_OP_breg7 (rsp) 16, which can't be translated into a cacheable rule.
+    let instructions = [
+        CfaOp::DefCfaExpression(vec![0x77, 0x10]),
+        CfaOp::Offset {
+            register: RBP,
+            factored_offset: 2,
+        },
+    ];
+    let unwinder = unwinder_for::<MayAllocateDuringUnwind>(
+        CfiFormat::EhFrame,
+        PointerEncoding::Absolute,
+        &instructions,
+    );
+    let mut cache = CacheX86_64::<_>::new();
+    let stack = [0x9000, 0x1555].repeat(4);
+    let mut read_stack = |addr: u64| {
+        let index = addr.checked_sub(STACK).ok_or(())? / 8;
+        stack.get(index as usize).copied().ok_or(())
+    };
+    // The same frame at different stack pointers, like an interpreter loop.
+    for i in 0..4 {
+        let sp = STACK + 16 * i;
+        let mut regs = UnwindRegsX86_64::new(FUNCTION + 0x11, sp, 0x7);
+        let mut info = FrameUnwindInfo::default();
+        let result = unwinder.unwind_frame_with_info(
+            FrameAddress::from_return_address(FUNCTION + 0x11).unwrap(),
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            &mut info,
+        );
+        assert_eq!(result, Ok(Some(0x1555)));
+        assert_eq!((regs.sp(), regs.bp()), (sp + 16, 0x9000));
+        // Predicted frames aren't checked against the CFI.
+        let confidence = if i < 2 {
+            framehop::FrameConfidence::Certain
+        } else {
+            framehop::FrameConfidence::Likely
+        };
+        assert_eq!(info.confidence, confidence);
+    }
+    // The first two unwinds evaluate the CFI, then the frame is hot.
+    assert_eq!(cache.unwind_stats().uncacheable_count, 2);
+    assert_eq!(cache.unwind_stats().dwarf_count, 0);
+    let stats = cache.predictor_stats();
+    assert_eq!((stats.hit_count, stats.miss_count), (2, 2));
+    assert_eq!(stats.layout_change_count, 0);
+}
+
+#[test]
+fn test_restore_checkpoint() {
+    let eh_frame = CfiBuilder::new(CfiFormat::EhFrame, Cie::x86_64())
+        .pointer_encoding(PointerEncoding::PcRelSdata4)
+        .fde(Fde {
+            start: FUNCTION,
+            len: 0x100,
+            instructions: prologue(),
+        })
+        .build(SectionBases {
+            section: SECTION,
+            text: TEXT,
+            data: DATA,
+        });
+    let section_info = |code_id: Option<&[u8]>| ExplicitModuleSectionInfo {
+        base_svma: 0,
+        text_svma: Some(TEXT..SECTION),
+        eh_frame_svma: Some(SECTION..SECTION + eh_frame.len() as u64),
+        eh_frame: Some(eh_frame.clone()),
+        code_id: code_id.map(|code_id| CodeId::ElfBuildId(code_id.to_vec())),
+        ..Default::default()
+    };
+    let mut unwinder = UnwinderX86_64::<Vec<u8>>::new();
+    unwinder.add_module(Module::new(
+        "checkpoint-test".to_string(),
+        0..0x40_0000,
+        0,
+        section_info(Some(b"abcd")),
+    ));
+
+    let checkpoint = UnwinderCheckpoint::new(&unwinder);
+    let bytes = checkpoint.to_bytes();
+    assert_eq!(
+        UnwinderCheckpoint::from_bytes(&bytes),
+        Ok(checkpoint.clone())
+    );
+    assert_eq!(
+        UnwinderCheckpoint::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(CheckpointError::InvalidData)
+    );
+    assert_eq!(
+        checkpoint.modules[0]
+            .dwarf_cfi_index
+            .as_ref()
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // Replace the saved index with an empty one, to see whether it is used.
+    let mut checkpoint = checkpoint;
+    checkpoint.modules[0].dwarf_cfi_index = Some(DwarfCfiIndex::from_bytes(&[0; 4]).unwrap());
+    let unwind = |unwinder: &UnwinderX86_64<Vec<u8>>| {
+        let mut cache = CacheX86_64::<_>::new();
+        let mut regs = UnwindRegsX86_64::new(FUNCTION + 1, STACK, 0);
+        let mut read_stack = |addr| Ok::<_, ()>(addr);
+        let mut info = FrameUnwindInfo::default();
+        let address = FrameAddress::from_instruction_pointer(FUNCTION + 1);
+        let _ = unwinder.unwind_frame_with_info(
+            address,
+            &mut regs,
+            &mut cache,
+            &mut read_stack,
+            &mut info,
+        );
+        info.fallback_reason
+    };
+
+    let mut restored = UnwinderX86_64::<Vec<u8>>::new();
+    checkpoint.restore(&mut restored, |module| {
+        assert_eq!(module.name, "checkpoint-test");
+        Some(section_info(Some(b"abcd")))
+    });
+    assert_eq!(restored.modules().len(), 1);
+    assert_eq!(restored.modules()[0].avma_range(), 0..0x40_0000);
+    assert!(unwind(&restored).is_some());
+
+    // If the binary has changed, the index is built again.
+    let mut restored = UnwinderX86_64::<Vec<u8>>::new();
+    checkpoint.restore(&mut restored, |_| Some(section_info(Some(b"efgh"))));
+    assert_eq!(unwind(&restored), None);
+
+    // If the binary has no code ID, it can't be matched with the checkpoint, so the
+    // index is built again as well.
+    let mut restored = UnwinderX86_64::<Vec<u8>>::new();
+    checkpoint.restore(&mut restored, |_| Some(section_info(None)));
+    assert_eq!(unwind(&restored), None);
+}
@@ -0,0 +1,368 @@
+//! Validates x86_64 instruction analysis against the DWARF CFI of real binaries.
+//!
+//! The prologue and the epilogues of every function in a fixture are disassembled, and
+//! the machine state at each of their instruction boundaries is simulated, so that the
+//! caller's state is known. At every boundary where the module's `.eh_frame` unwinds to
+//! that caller state, a rule which was synthesized from the instructions has to do the
+//! same.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use framehop::x86_64::*;
+use framehop::{ExplicitModuleSectionInfo, FrameAddress, FrameUnwindInfo, Module, Unwinder};
+use gimli::{BaseAddresses, CieOrFde, EhFrame, LittleEndian, UnwindSection};
+use iced_x86::{Decoder, DecoderOptions, Instruction, Mnemonic, OpKind, Register};
+use object::{Object, ObjectSection};
+
+/// The stack pointer at the start of the simulation.
+const INITIAL_SP: u64 = 0x7f00_0000_0800;
+/// The frame pointer at the start of an epilogue. It points above the stack pointer,
+/// like in a function with a frame.
+const EPILOGUE_INITIAL_BP: u64 = INITIAL_SP + 0x40;
+/// The return address and the frame pointer of the caller of a prologue.
+const CALLER_RETURN_ADDRESS: u64 = 0x1234_5678;
+const CALLER_BP: u64 = 0x7f00_0000_0f00;
+/// Stack slots which are pushed, or read in an epilogue, get distinct values from this
+/// base.
+const SLOT_VALUE: u64 = 0x5500_0000;
+
+/// The state of a frame at some pc, together with the caller state which unwinding it
+/// has to produce.
+struct Scenario {
+    pc: u64,
+    sp: u64,
+    bp: u64,
+    stack: HashMap<u64, u64>,
+    /// The return address, stack pointer and frame pointer of the caller.
+    caller: (u64, u64, u64),
+}
+
+/// The caller's return address, stack pointer and frame pointer, or an error message.
+type Unwound = Result<(u64, u64, u64), String>;
+
+struct Fixture {
+    text: Vec<u8>,
+    text_svma: Range<u64>,
+    eh_frame: Vec<u8>,
+    eh_frame_svma: Range<u64>,
+    got_svma: Option<Range<u64>>,
+    /// The address ranges of the FDEs in `.eh_frame`, sorted by start address.
+    functions: Vec<Range<u64>>,
+}
+
+impl Fixture {
+    fn load(path: &str) -> Self {
+        let data = std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join(path)).unwrap();
+        let file = object::File::parse(&data[..]).unwrap();
+        let section = |name: &str| {
+            let section = file.section_by_name(name)?;
+            let svma = section.address()..section.address() + section.size();
+            Some((section.data().unwrap().to_vec(), svma))
+        };
+        let (text, text_svma) = section(".text").unwrap();
+        let (eh_frame, eh_frame_svma) = section(".eh_frame").unwrap();
+        let got_svma = section(".got").map(|(_, svma)| svma);
+
+        let mut bases = BaseAddresses::default()
+            .set_eh_frame(eh_frame_svma.start)
+            .set_text(text_svma.start);
+        if let Some(got_svma) = &got_svma {
+            bases = bases.set_got(got_svma.start);
+        }
+        let section = EhFrame::new(&eh_frame, LittleEndian);
+        let mut entries = section.entries(&bases);
+        let mut functions = Vec::new();
+        while let Some(entry) = entries.next().unwrap() {
+            if let CieOrFde::Fde(partial) = entry {
+                let fde = partial.parse(EhFrame::cie_from_offset).unwrap();
+                let start = fde.initial_address();
+                let range = start..start + fde.len();
+                if text_svma.start <= range.start && range.end <= text_svma.end {
+                    functions.push(range);
+                }
+            }
+        }
+        functions.sort_by_key(|range| range.start);
+        functions.dedup_by_key(|range| range.start);
+        Fixture {
+            text,
+            text_svma,
+            eh_frame,
+            eh_frame_svma,
+            got_svma,
+            functions,
+        }
+    }
+
+    /// A module which is unwound with `.eh_frame`, the ground truth.
+    fn dwarf_module(&self) -> Module<Vec<u8>> {
+        self.module(ExplicitModuleSectionInfo {
+            eh_frame_svma: Some(self.eh_frame_svma.clone()),
+            eh_frame: Some(self.eh_frame.clone()),
+            got_svma: self.got_svma.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// A module without unwind information, which is unwound with rules that are
+    /// synthesized from the instructions.
+    fn instruction_analysis_module(&self) -> Module<Vec<u8>> {
+        self.module(ExplicitModuleSectionInfo {
+            text: Some(self.text.clone()),
+            synthesize_rules_from_prologues: true,
+            function_starts: Some(self.functions.iter().map(|range| range.start).collect()),
+            ..Default::default()
+        })
+    }
+
+    fn module(&self, section_info: ExplicitModuleSectionInfo<Vec<u8>>) -> Module<Vec<u8>> {
+        let section_info = ExplicitModuleSectionInfo {
+            base_svma: 0,
+            text_svma: Some(self.text_svma.clone()),
+            ..section_info
+        };
+        Module::new("fixture".to_string(), 0..0x1_0000_0000, 0, section_info)
+    }
+
+    fn instructions(&self, function: &Range<u64>) -> Vec<Instruction> {
+        let start = (function.start - self.text_svma.start) as usize;
+        let end = (function.end - self.text_svma.start) as usize;
+        Decoder::with_ip(
+            64,
+            &self.text[start..end],
+            function.start,
+            DecoderOptions::NONE,
+        )
+        .into_iter()
+        .collect()
+    }
+}
+
+/// Unwind the frame of `scenario`. Returns `None` if the module's unwind information
+/// didn't produce a rule and the unwinder used its fallback rule.
+fn unwind(
+    unwinder: &UnwinderX86_64<Vec<u8>>,
+    cache: &mut CacheX86_64,
+    scenario: &Scenario,
+) -> Option<Unwound> {
+    let mut regs = UnwindRegsX86_64::new(scenario.pc, scenario.sp, scenario.bp);
+    let mut read_stack = |address| scenario.stack.get(&address).copied().ok_or(());
+    let mut info = FrameUnwindInfo::default();
+    let result = unwinder.unwind_frame_with_info(
+        FrameAddress::from_instruction_pointer(scenario.pc),
+        &mut regs,
+        cache,
+        &mut read_stack,
+        &mut info,
+    );
+    if info.error_details.is_some() {
+        return None;
+    }
+    Some(match result {
+        Ok(Some(return_address)) => Ok((return_address, regs.sp(), regs.bp())),
+        Ok(None) => Err("end of stack".to_string()),
+        Err(err) => Err(err.to_string()),
+    })
+}
+
+/// The amount which `instruction` adds to rsp, if it's an `add`, `sub` or `lea` which
+/// only adjusts rsp by a constant.
+fn stack_pointer_adjustment(instruction: &Instruction) -> Option<u64> {
+    if instruction.op0_register() != Register::RSP {
+        return None;
+    }
+    match instruction.mnemonic() {
+        Mnemonic::Add if instruction.op1_kind() != OpKind::Register => {
+            Some(instruction.immediate(1))
+        }
+        Mnemonic::Sub if instruction.op1_kind() != OpKind::Register => {
+            Some(instruction.immediate(1).wrapping_neg())
+        }
+        Mnemonic::Lea
+            if instruction.memory_base() == Register::RSP
+                && instruction.memory_index() == Register::None =>
+        {
+            Some(instruction.memory_displacement64())
+        }
+        _ => None,
+    }
+}
+
+fn is_prologue_instruction(instruction: &Instruction) -> bool {
+    match instruction.mnemonic() {
+        Mnemonic::Endbr64 => true,
+        Mnemonic::Push => instruction.op0_kind() == OpKind::Register,
+        Mnemonic::Sub => stack_pointer_adjustment(instruction).is_some(),
+        Mnemonic::Mov => {
+            instruction.op0_register() == Register::RBP
+                && instruction.op1_register() == Register::RSP
+        }
+        _ => false,
+    }
+}
+
+fn is_epilogue_instruction(instruction: &Instruction) -> bool {
+    match instruction.mnemonic() {
+        Mnemonic::Pop => instruction.op0_kind() == OpKind::Register,
+        Mnemonic::Leave => true,
+        Mnemonic::Add | Mnemonic::Lea => stack_pointer_adjustment(instruction).is_some(),
+        Mnemonic::Mov => {
+            instruction.op0_register() == Register::RSP
+                && instruction.op1_register() == Register::RBP
+        }
+        _ => false,
+    }
+}
+
+/// The scenarios at the boundaries of the leading prologue instructions of a function,
+/// and at the first instruction after them. The state is simulated from the function
+/// entry, where the return address is at the stack pointer.
+fn prologue_scenarios(instructions: &[Instruction]) -> Vec<Scenario> {
+    let mut sp = INITIAL_SP;
+    let mut bp = CALLER_BP;
+    let mut stack = HashMap::from([(INITIAL_SP, CALLER_RETURN_ADDRESS)]);
+    let caller = (CALLER_RETURN_ADDRESS, INITIAL_SP + 8, CALLER_BP);
+    let mut scenarios = Vec::new();
+    for instruction in instructions {
+        scenarios.push(Scenario {
+            pc: instruction.ip(),
+            sp,
+            bp,
+            stack: stack.clone(),
+            caller,
+        });
+        if !is_prologue_instruction(instruction) {
+            break;
+        }
+        match instruction.mnemonic() {
+            Mnemonic::Push => {
+                sp -= 8;
+                let value = match instruction.op0_register() {
+                    Register::RBP => bp,
+                    register => SLOT_VALUE + register as u64,
+                };
+                stack.insert(sp, value);
+            }
+            Mnemonic::Mov => bp = sp,
+            Mnemonic::Sub => {
+                sp = sp.wrapping_add(stack_pointer_adjustment(instruction).unwrap());
+            }
+            _ => {}
+        }
+    }
+    scenarios
+}
+
+/// The scenarios at the boundaries of the epilogue instructions which end with the
+/// `ret` at `instructions[ret_index]`, including the `ret`. The state is simulated
+/// forwards from the first epilogue instruction, and every stack slot which the
+/// epilogue reads gets a distinct value.
+fn epilogue_scenarios(instructions: &[Instruction], ret_index: usize) -> Vec<Scenario> {
+    let start = instructions[..ret_index]
+        .iter()
+        .rposition(|instruction| !is_epilogue_instruction(instruction))
+        .map_or(0, |body_end| body_end + 1);
+
+    let mut stack = HashMap::new();
+    let read = |stack: &mut HashMap<u64, u64>, address: u64| {
+        let value = SLOT_VALUE + stack.len() as u64 * 0x10;
+        *stack.entry(address).or_insert(value)
+    };
+    let mut states = Vec::new();
+    let mut sp = INITIAL_SP;
+    let mut bp = EPILOGUE_INITIAL_BP;
+    for instruction in &instructions[start..=ret_index] {
+        states.push((instruction.ip(), sp, bp));
+        match instruction.mnemonic() {
+            Mnemonic::Pop => {
+                let value = read(&mut stack, sp);
+                if instruction.op0_register() == Register::RBP {
+                    bp = value;
+                }
+                sp += 8;
+            }
+            Mnemonic::Leave => {
+                sp = bp;
+                bp = read(&mut stack, sp);
+                sp += 8;
+            }
+            Mnemonic::Mov => sp = bp,
+            Mnemonic::Add | Mnemonic::Lea => {
+                sp = sp.wrapping_add(stack_pointer_adjustment(instruction).unwrap());
+            }
+            _ => {}
+        }
+    }
+    let return_address = read(&mut stack, sp);
+    let caller = (return_address, sp + 8, bp);
+    states
+        .into_iter()
+        .map(|(pc, sp, bp)| Scenario {
+            pc,
+            sp,
+            bp,
+            stack: stack.clone(),
+            caller,
+        })
+        .collect()
+}
+
+fn check(path: &str) {
+    let fixture = Fixture::load(path);
+    let mut dwarf = UnwinderX86_64::new();
+    dwarf.add_module(fixture.dwarf_module());
+    let mut analysis = UnwinderX86_64::new();
+    analysis.add_module(fixture.instruction_analysis_module());
+    let mut dwarf_cache = CacheX86_64::new();
+    let mut analysis_cache = CacheX86_64::new();
+
+    let mut checked = 0;
+    let mut mismatches = Vec::new();
+    for function in &fixture.functions {
+        let instructions = fixture.instructions(function);
+        let mut scenarios = prologue_scenarios(&instructions);
+        for (i, instruction) in instructions.iter().enumerate() {
+            if instruction.mnemonic() == Mnemonic::Ret {
+                scenarios.extend(epilogue_scenarios(&instructions, i));
+            }
+        }
+        for scenario in scenarios {
+            // The simulation only knows the instructions in a straight line, so it's
+            // wrong at pcs which are also reached from elsewhere with a different state.
+            // It's only ground truth where the CFI agrees with it.
+            let expected = Ok(scenario.caller);
+            if unwind(&dwarf, &mut dwarf_cache, &scenario).as_ref() != Some(&expected) {
+                continue;
+            }
+            let Some(actual) = unwind(&analysis, &mut analysis_cache, &scenario) else {
+                continue;
+            };
+            checked += 1;
+            if actual != expected {
+                mismatches.push(format!(
+                    "at {:#x}: instruction analysis {actual:x?}, DWARF {expected:x?}",
+                    scenario.pc
+                ));
+            }
+        }
+    }
+    assert!(checked > 1000, "only {checked} pcs were checked in {path}");
+    assert!(
+        mismatches.is_empty(),
+        "{} of {checked} pcs in {path} mismatch:\n{}",
+        mismatches.len(),
+        mismatches[..mismatches.len().min(20)].join("\n")
+    );
+}
+
+#[test]
+fn test_instruction_analysis_matches_dwarf_libc() {
+    check("fixtures/linux/x86_64/nofp/libc.so.6");
+}
+
+#[test]
+fn test_instruction_analysis_matches_dwarf_with_frame_pointers() {
+    check("fixtures/linux/x86_64/fp/nightly-libsoftokn3.so");
+}
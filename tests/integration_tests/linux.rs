@@ -258,6 +258,11 @@ fn test_no_eh_frame_hdr() {
     assert_eq!(regs.sp(), 0x50);
     assert_eq!(regs.fp(), 0x70);
     assert_eq!(regs.lr(), 0x1234);
+
+    let stats = cache.unwind_stats();
+    assert_eq!(stats.dwarf_count, 7);
+    assert_eq!(stats.dwarf_evaluation_count, 7);
+    assert_eq!(stats.fallbacks(), 0);
 }
 
 #[test]
@@ -534,3 +539,40 @@ fn test_root_func_aarch64_old_glibc() {
     );
     assert_eq!(res, Ok(None));
 }
+
+#[cfg(feature = "object")]
+#[test]
+fn test_object_file_as_section_info() {
+    let path =
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/linux/x86_64/fp/nightly-firefox-bin");
+    let buf = std::fs::read(&path).unwrap();
+    let file = object::File::parse(&buf[..]).unwrap();
+    let module = framehop::Module::<Vec<u8>>::new(
+        path.to_string_lossy().to_string(),
+        0x1000000..0x1000000 + buf.len() as u64,
+        0x1000000,
+        file,
+    );
+    assert_eq!(
+        module.unwind_data_kind(),
+        framehop::UnwindDataKind::EhFrameHdrAndEhFrame
+    );
+    assert!(matches!(
+        module.code_id(),
+        Some(framehop::CodeId::ElfBuildId(_))
+    ));
+
+    let mut cache = CacheX86_64::new();
+    let mut unwinder = UnwinderX86_64::new();
+    unwinder.add_module(module);
+    let stack = [1, 2, 3, 4, 5, 0xa, 0x123456, 6, 7, 8, 9];
+    let mut read_stack = |addr| stack.get((addr / 8) as usize).cloned().ok_or(());
+    let mut regs = UnwindRegsX86_64::new(0x1000000 + 0xc0e0, 0x30, 0x345);
+    let res = unwinder.unwind_frame(
+        FrameAddress::from_instruction_pointer(0x1000000 + 0xc0e0),
+        &mut regs,
+        &mut cache,
+        &mut read_stack,
+    );
+    assert_eq!(res, Ok(Some(0x123456)));
+}
@@ -71,6 +71,38 @@ fn test_basic() {
         &mut read_stack,
     );
     assert_eq!(res, Ok(None));
+    let stats = cache.unwind_stats();
+    assert_eq!(stats.compact_unwind_info_count, 3);
+    assert_eq!(stats.total(), cache.stats().misses());
+}
+
+#[test]
+fn test_precompute_rules() {
+    let mut cache = CacheAarch64::<_>::new();
+    let mut unwinder = UnwinderAarch64::new();
+    common::add_object(
+        &mut unwinder,
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/macos/arm64/fp/query-api"),
+        0x1003fc000,
+    );
+    let addresses = [
+        FrameAddress::from_instruction_pointer(0x1003fc000 + 0x1292c0),
+        FrameAddress::from_return_address(0x1003fc000 + 0xe4830).unwrap(),
+        FrameAddress::from_return_address(0x1003fc000 + 0x100dc4).unwrap(),
+    ];
+    assert_eq!(unwinder.precompute_rules(&mut cache, addresses), 3);
+    // Rules which are already cached are not computed again.
+    assert_eq!(unwinder.precompute_rules(&mut cache, addresses), 0);
+
+    let hits_before = cache.stats().hits();
+    let stack = [1, 2, 3, 4, 0x40, 0x1003fc000 + 0x100dc4, 5, 6, 0x0, 0x0];
+    let mut read_stack = |addr| stack.get((addr / 8) as usize).cloned().ok_or(());
+    let mut regs = UnwindRegsAarch64::new(0x1003fc000 + 0xe4830, 0x10, 0x20);
+    let res = unwinder.unwind_frame(addresses[0], &mut regs, &mut cache, &mut read_stack);
+    assert_eq!(res, Ok(Some(0x1003fc000 + 0xe4830)));
+    let res = unwinder.unwind_frame(addresses[1], &mut regs, &mut cache, &mut read_stack);
+    assert_eq!(res, Ok(Some(0x1003fc000 + 0x100dc4)));
+    assert_eq!(cache.stats().hits() - hits_before, 2);
 }
 
 #[test]
@@ -1144,3 +1176,104 @@ fn test_go_debug_frame() {
     );
     assert_eq!(res, Ok(None));
 }
+
+#[cfg(feature = "trace")]
+#[test]
+fn test_trace() {
+    use framehop::UnwindTraceEvent;
+
+    let mut cache = CacheAarch64::<_>::new();
+    let mut unwinder = UnwinderAarch64::new();
+    common::add_object(
+        &mut unwinder,
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/macos/arm64/fp/query-api"),
+        0x1003fc000,
+    );
+    let stack = [1, 2, 3, 4, 0x40, 0x1003fc000 + 0x100dc4];
+    let mut read_stack = |addr| stack.get((addr / 8) as usize).cloned().ok_or(());
+    let mut trace = UnwindTraceAarch64::new();
+    let mut regs = UnwindRegsAarch64::new(0x1003fc000 + 0xe4830, 0x10, 0x20);
+    let res = unwinder.unwind_frame_traced(
+        FrameAddress::from_instruction_pointer(0x1003fc000 + 0x1292c0),
+        &mut regs,
+        &mut cache,
+        &mut read_stack,
+        &mut trace,
+    );
+    assert_eq!(res, Ok(Some(0x1003fc000 + 0xe4830)));
+    let events = trace.events();
+    assert!(matches!(events[0], UnwindTraceEvent::Begin { .. }));
+    assert!(matches!(events[1], UnwindTraceEvent::CacheMiss));
+    assert!(matches!(events[2], UnwindTraceEvent::Module { .. }));
+    assert!(matches!(
+        events[3],
+        UnwindTraceEvent::CompactUnwindInfoFunction { .. }
+    ));
+    assert!(matches!(events[4], UnwindTraceEvent::ExecRule { .. }));
+    assert!(matches!(
+        events[5],
+        UnwindTraceEvent::End {
+            result: Ok(Some(_)),
+            ..
+        }
+    ));
+    assert_eq!(events.len(), 6);
+
+    // The second time around, the rule comes from the cache.
+    trace.clear();
+    let mut regs = UnwindRegsAarch64::new(0x1003fc000 + 0xe4830, 0x10, 0x20);
+    let res = unwinder.unwind_frame_traced(
+        FrameAddress::from_instruction_pointer(0x1003fc000 + 0x1292c0),
+        &mut regs,
+        &mut cache,
+        &mut read_stack,
+        &mut trace,
+    );
+    assert_eq!(res, Ok(Some(0x1003fc000 + 0xe4830)));
+    let events = trace.events();
+    assert!(matches!(events[1], UnwindTraceEvent::CacheHit { .. }));
+    assert_eq!(events.len(), 3);
+}
+
+#[cfg(feature = "macho")]
+#[test]
+fn test_compact_unwind_info_contents() {
+    use framehop::{CompactUnwindInfoContents, CompactUnwindInfoPageKind};
+
+    let data = std::fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/macos/arm64/fp/query-api.__unwind_info"),
+    )
+    .unwrap();
+    let contents = CompactUnwindInfoContents::parse(&data).unwrap();
+    let page_starts: Vec<u32> = contents
+        .pages
+        .iter()
+        .map(|page| page.address_range.start)
+        .collect();
+    assert_eq!(page_starts, [0xb64, 0x5e160, 0x1127f4]);
+    assert!(contents
+        .pages
+        .iter()
+        .all(|page| page.kind == CompactUnwindInfoPageKind::Compressed));
+    assert_eq!(contents.address_range.start, 0xb64);
+    assert_eq!(
+        contents.pages.last().unwrap().address_range.end,
+        contents.address_range.end
+    );
+    assert_eq!(contents.entries.len(), 2562);
+    assert_eq!(
+        contents
+            .pages
+            .iter()
+            .map(|page| page.entry_count)
+            .sum::<usize>(),
+        contents.entries.len()
+    );
+    let counts = contents.opcode_kind_counts();
+    // arm64 opcode kinds: 2 is frameless, 3 is DWARF, 4 is frame-based.
+    assert_eq!(
+        counts.into_iter().collect::<Vec<_>>(),
+        [(2, 461), (3, 3), (4, 2098)]
+    );
+}
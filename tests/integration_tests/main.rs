@@ -1,3 +1,9 @@
+#[cfg(feature = "android")]
+mod android;
+mod cfi_builder;
 mod common;
+mod differential;
+mod dwarf_cfi;
+mod instruction_analysis;
 mod linux;
 mod macos;